@@ -0,0 +1,140 @@
+// Operational CLI for the posts and users API: user roles, post purging and
+// API key management, going through the same repositories as the server
+// instead of hand-written SQL or curl calls.
+
+use std::error::Error;
+use std::path::Path;
+
+use clap::{Parser, Subcommand};
+use rust_axum_rest_api::models::Role;
+use rust_axum_rest_api::profile::Profile;
+use rust_axum_rest_api::repo;
+use rust_axum_rest_api::startup::{self, Started};
+use sqlx::{Pool, Postgres};
+
+#[derive(Parser)]
+#[command(
+    name = "admin",
+    about = "Administration tasks for the posts and users API"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage users
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Manage posts
+    #[command(subcommand)]
+    Posts(PostsCommand),
+    /// Manage API keys
+    #[command(subcommand)]
+    Apikeys(ApiKeysCommand),
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    /// Give a user the admin role
+    Promote { username: String },
+    /// Take the admin role away from a user
+    Demote { username: String },
+}
+
+#[derive(Subcommand)]
+enum PostsCommand {
+    /// Permanently delete soft-deleted posts
+    PurgeDeleted {
+        /// Only purge posts deleted at least this many days ago
+        #[arg(long, default_value_t = 0)]
+        older_than_days: i32,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApiKeysCommand {
+    /// Create an API key for a user and print it (it is not shown again)
+    Create {
+        username: String,
+        /// Label to tell the key apart from others of the same user
+        #[arg(long, default_value = "default")]
+        name: String,
+    },
+    /// List the API keys of a user
+    List { username: String },
+    /// Revoke an API key by id
+    Revoke { id: i32 },
+}
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    let cli = Cli::parse();
+
+    // the server's .env files, secrets, configuration and email key, so the
+    // CLI works on the same database the same way (see `startup`)
+    Profile::load(Path::new("."))?;
+    let Started { pool, .. } = startup::connect().await?;
+
+    match cli.command {
+        Command::Users(UsersCommand::Promote { username }) => {
+            set_role(&pool, &username, Role::Admin).await
+        }
+        Command::Users(UsersCommand::Demote { username }) => {
+            set_role(&pool, &username, Role::User).await
+        }
+        Command::Posts(PostsCommand::PurgeDeleted { older_than_days }) => {
+            let purged = repo::posts::purge_deleted(&pool, older_than_days).await?;
+            println!("purged {purged} posts");
+            Ok(())
+        }
+        Command::Apikeys(ApiKeysCommand::Create { username, name }) => {
+            let user = find_user(&pool, &username).await?;
+            let (api_key, secret) = repo::api_keys::create(&pool, user.id, &name).await?;
+            println!(
+                "created key {} ({}) for {}",
+                api_key.id, api_key.name, user.username
+            );
+            println!("{secret}");
+            Ok(())
+        }
+        Command::Apikeys(ApiKeysCommand::List { username }) => {
+            let user = find_user(&pool, &username).await?;
+            for api_key in repo::api_keys::list_for_user(&pool, user.id).await? {
+                let status = if api_key.revoked { "revoked" } else { "active" };
+                println!(
+                    "{}\t{}...\t{}\t{status}",
+                    api_key.id, api_key.prefix, api_key.name
+                );
+            }
+            Ok(())
+        }
+        Command::Apikeys(ApiKeysCommand::Revoke { id }) => {
+            if !repo::api_keys::revoke(&pool, id).await? {
+                return Err(format!("no active API key with id {id}").into());
+            }
+            println!("revoked key {id}");
+            Ok(())
+        }
+    }
+}
+
+async fn find_user(
+    pool: &Pool<Postgres>,
+    username: &str,
+) -> Result<rust_axum_rest_api::models::User, BoxError> {
+    repo::users::find_by_username(pool, username)
+        .await?
+        .ok_or_else(|| format!("no user named {username}").into())
+}
+
+async fn set_role(pool: &Pool<Postgres>, username: &str, role: Role) -> Result<(), BoxError> {
+    let user = repo::users::set_role(pool, username, role)
+        .await?
+        .ok_or_else(|| format!("no user named {username}"))?;
+    println!("{} is now {}", user.username, role.as_str());
+    Ok(())
+}
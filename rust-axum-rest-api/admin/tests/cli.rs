@@ -0,0 +1,171 @@
+// The admin CLI run the way operators do, against a disposable database.
+
+mod common;
+
+use std::process::Output;
+
+use common::db::TestDb;
+use common::unique;
+use rust_axum_rest_api::repo::emails::{self, EmailKey};
+use tokio::process::Command;
+
+// run `admin <args>` on `db` in the test profile with the variables of `env`
+// set, away from any .env files
+async fn admin(db: &TestDb, env: &[(&str, &str)], args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_admin"))
+        .args(args)
+        .current_dir(std::env::temp_dir())
+        .env("DATABASE_URL", &db.url)
+        .env("APP_ENV", "test")
+        .env_remove("EMAIL_ENCRYPTION_KEY")
+        .envs(env.iter().copied())
+        .output()
+        .await
+        .expect("run the admin CLI")
+}
+
+fn stdout(out: &Output) -> String {
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+async fn create_user(db: &TestDb) -> (i32, String) {
+    let username = unique("user");
+    let id = sqlx::query_scalar(
+        "INSERT INTO users (username, email) VALUES ($1, $1 || '@example.com') RETURNING id",
+    )
+    .bind(&username)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    (id, username)
+}
+
+async fn role(db: &TestDb, id: i32) -> String {
+    sqlx::query_scalar("SELECT role FROM users WHERE id = $1")
+        .bind(id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn users_are_promoted_and_demoted() {
+    let db = TestDb::new().await;
+    let (id, username) = create_user(&db).await;
+
+    let out = admin(&db, &[], &["users", "promote", &username]).await;
+    assert_eq!(stdout(&out), format!("{username} is now admin\n"));
+    assert_eq!(role(&db, id).await, "admin");
+    // usernames are matched case-insensitively
+    let shouting = username.to_uppercase();
+    stdout(&admin(&db, &[], &["users", "demote", &shouting]).await);
+    assert_eq!(role(&db, id).await, "user");
+
+    let out = admin(&db, &[], &["users", "promote", "nobody"]).await;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no user named nobody"));
+}
+
+#[tokio::test]
+async fn only_posts_deleted_long_enough_ago_are_purged() {
+    let db = TestDb::new().await;
+    sqlx::query(
+        "INSERT INTO posts (title, body, deleted_at) VALUES
+             ('old', 'b', NOW() - INTERVAL '10 days'), ('recent', 'b', NOW() - INTERVAL '1 day'),
+             ('live', 'b', NULL)",
+    )
+    .execute(&db.pool)
+    .await
+    .unwrap();
+    let titles = || async {
+        sqlx::query_scalar::<_, String>("SELECT title FROM posts ORDER BY id")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap()
+    };
+
+    let out = admin(
+        &db,
+        &[],
+        &["posts", "purge-deleted", "--older-than-days", "5"],
+    )
+    .await;
+    assert_eq!(stdout(&out), "purged 1 posts\n");
+    assert_eq!(titles().await, ["recent", "live"]);
+    let out = admin(&db, &[], &["posts", "purge-deleted"]).await;
+    assert_eq!(stdout(&out), "purged 1 posts\n");
+    assert_eq!(titles().await, ["live"]);
+}
+
+#[tokio::test]
+async fn api_keys_are_created_listed_and_revoked() {
+    let db = TestDb::new().await;
+    let (user_id, username) = create_user(&db).await;
+
+    let out = stdout(&admin(&db, &[], &["apikeys", "create", &username, "--name", "ci"]).await);
+    let (created, secret) = out.trim_end().split_once('\n').unwrap();
+    let key_id: i32 = sqlx::query_scalar("SELECT id FROM api_keys WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(created, format!("created key {key_id} (ci) for {username}"));
+    let prefix: String = sqlx::query_scalar("SELECT prefix FROM api_keys WHERE id = $1")
+        .bind(key_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert!(secret.starts_with(&prefix), "{secret}");
+
+    let list_args = ["apikeys", "list", username.as_str()];
+    let list = || admin(&db, &[], &list_args);
+    assert_eq!(
+        stdout(&list().await),
+        format!("{key_id}\t{prefix}...\tci\tactive\n")
+    );
+    let out = admin(&db, &[], &["apikeys", "revoke", &key_id.to_string()]).await;
+    assert_eq!(stdout(&out), format!("revoked key {key_id}\n"));
+    assert_eq!(
+        stdout(&list().await),
+        format!("{key_id}\t{prefix}...\tci\trevoked\n")
+    );
+    // only active keys are revoked
+    let out = admin(&db, &[], &["apikeys", "revoke", &key_id.to_string()]).await;
+    assert!(!out.status.success());
+}
+
+#[tokio::test]
+async fn sealed_emails_are_opened_with_the_servers_key() {
+    const KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+    let db = TestDb::new().await;
+    emails::configure(EmailKey::from_base64(KEY).unwrap());
+    let username = unique("user");
+    let email = emails::seal(&format!("{username}@example.com"));
+    let id: i32 = sqlx::query_scalar(
+        "INSERT INTO users (username, email, email_hash) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(&username)
+    .bind(&email.stored)
+    .bind(&email.hash)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    let sealed = [("EMAIL_ENCRYPTION_KEY", KEY)];
+
+    stdout(&admin(&db, &sealed, &["users", "promote", &username]).await);
+    assert_eq!(role(&db, id).await, "admin");
+    stdout(&admin(&db, &sealed, &["apikeys", "create", &username]).await);
+    let keys = stdout(&admin(&db, &sealed, &["apikeys", "list", &username]).await);
+    assert_eq!(keys.lines().count(), 1);
+
+    // without the key the user can't be read, and their role stays as it was
+    let out = admin(&db, &[], &["users", "demote", &username]).await;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("EMAIL_ENCRYPTION_KEY"));
+    assert_eq!(role(&db, id).await, "admin");
+}
@@ -0,0 +1,14 @@
+// The server's disposable databases (see its tests/common/db.rs), for
+// running the CLI against.
+
+#![allow(dead_code)]
+
+#[path = "../../../tests/common/db.rs"]
+pub mod db;
+
+use sqlx::migrate::Migrator;
+
+pub use db::unique;
+
+// what `db` migrates its databases with, the server's migrations
+pub static MIGRATOR: Migrator = sqlx::migrate!("../migrations");
@@ -0,0 +1,164 @@
+// Typed client for the posts and users API.
+//
+//     let client = api_client::Client::new("http://localhost:5000");
+//     let posts = client.list_posts().await?;
+//
+// Requests and responses use the same `api-types` models as the server.
+// Receivers of webhooks can check deliveries with `webhooks::verify`.
+
+pub mod webhooks;
+
+use std::fmt;
+
+pub use api_types::{
+    Availability, Count, CreatePost, CreateReport, CreateUser, FieldChange, Message, Post,
+    PostRevision, PostWithAuthor, PublicUser, Report, UpdatePost, User,
+};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Debug)]
+pub enum Error {
+    // the request could not be sent or the response could not be decoded
+    Http(reqwest::Error),
+    // the server answered with a non-success status
+    Status { status: StatusCode, body: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request failed: {e}"),
+            Error::Status { status, body } if body.is_empty() => {
+                write!(f, "server returned {status}")
+            }
+            Error::Status { status, body } => write!(f, "server returned {status}: {body}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Status { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Client::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    // use a preconfigured reqwest client, e.g. with timeouts or a proxy
+    pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Client {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            token: None,
+        }
+    }
+
+    // send `Authorization: Bearer <token>` with every request
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub async fn list_posts(&self) -> Result<Vec<Post>> {
+        self.send(self.request(Method::GET, "/posts")).await
+    }
+
+    pub async fn count_posts(&self) -> Result<i64> {
+        let count: Count = self.send(self.request(Method::GET, "/posts/count")).await?;
+        Ok(count.count)
+    }
+
+    // `id` is the post's public id, or its serial id or uuid depending on the
+    // server's ID_SCHEME; the same goes for `update_post` and `delete_post`
+
+    pub async fn get_post(&self, id: impl fmt::Display) -> Result<Post> {
+        self.send(self.request(Method::GET, &format!("/posts/{id}")))
+            .await
+    }
+
+    pub async fn create_post(&self, post: &CreatePost) -> Result<Post> {
+        self.send_json(Method::POST, "/posts", post).await
+    }
+
+    pub async fn update_post(&self, id: impl fmt::Display, post: &UpdatePost) -> Result<Post> {
+        self.send_json(Method::PUT, &format!("/posts/{id}"), post)
+            .await
+    }
+
+    pub async fn delete_post(&self, id: impl fmt::Display) -> Result<Message> {
+        self.send(self.request(Method::DELETE, &format!("/posts/{id}")))
+            .await
+    }
+
+    // the post's changes, newest first
+    pub async fn post_history(&self, id: impl fmt::Display) -> Result<Vec<PostRevision>> {
+        self.send(self.request(Method::GET, &format!("/posts/{id}/history")))
+            .await
+    }
+
+    pub async fn report_post(
+        &self,
+        id: impl fmt::Display,
+        report: &CreateReport,
+    ) -> Result<Report> {
+        self.send_json(Method::POST, &format!("/posts/{id}/report"), report)
+            .await
+    }
+
+    pub async fn create_user(&self, user: &CreateUser) -> Result<User> {
+        self.send_json(Method::POST, "/users", user).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{path}", self.base_url))
+            .header(reqwest::header::ACCEPT, "application/json");
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: &B,
+    ) -> Result<T> {
+        self.send(self.request(method, path).json(body)).await
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(Error::Status { status, body });
+        }
+        Ok(response.json().await?)
+    }
+}
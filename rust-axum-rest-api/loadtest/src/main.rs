@@ -0,0 +1,225 @@
+// Load generator for the posts and users API.
+//
+//     loadtest --url http://localhost:5000 -e "GET /posts" -e "GET /posts/1" \
+//         --concurrency 32 --rate 500 --duration 30
+//
+// Workers cycle through the endpoints until the duration is over, then the
+// latency percentiles, throughput and status code counts are printed.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use hdrhistogram::Histogram;
+use reqwest::Method;
+use tokio::sync::Mutex;
+use tokio::time::{interval, MissedTickBehavior};
+
+#[derive(Parser)]
+#[command(name = "loadtest", about = "Hammer API endpoints and report latencies")]
+struct Args {
+    /// Base URL of the server
+    #[arg(long, default_value = "http://localhost:5000")]
+    url: String,
+    /// Endpoint to request as "METHOD /path", can be repeated
+    #[arg(short, long = "endpoint", default_value = "GET /posts")]
+    endpoints: Vec<String>,
+    /// Request body sent with POST and PUT requests
+    #[arg(long)]
+    body: Option<String>,
+    /// Number of requests in flight at the same time
+    #[arg(short, long, default_value_t = 16)]
+    concurrency: usize,
+    /// Maximum requests per second over all workers, unlimited when 0
+    #[arg(short, long, default_value_t = 0)]
+    rate: u64,
+    /// How long to run, in seconds
+    #[arg(short, long, default_value_t = 10)]
+    duration: u64,
+}
+
+struct Endpoint {
+    method: Method,
+    path: String,
+}
+
+// what a worker collected, merged once all workers are done
+struct Report {
+    latencies: Histogram<u64>,
+    statuses: BTreeMap<u16, u64>,
+    errors: u64,
+}
+
+impl Report {
+    fn new() -> Self {
+        Report {
+            // microseconds, up to a minute with 3 significant digits
+            latencies: Histogram::new_with_bounds(1, 60_000_000, 3)
+                .expect("valid histogram bounds"),
+            statuses: BTreeMap::new(),
+            errors: 0,
+        }
+    }
+
+    fn merge(&mut self, other: Report) {
+        self.latencies
+            .add(other.latencies)
+            .expect("histograms with the same bounds");
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        self.errors += other.errors;
+    }
+}
+
+fn parse_endpoint(spec: &str) -> Result<Endpoint, String> {
+    let (method, path) = spec
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| format!("expected \"METHOD /path\", got {spec:?}"))?;
+    let method = method
+        .to_ascii_uppercase()
+        .parse::<Method>()
+        .map_err(|e| format!("{spec:?}: {e}"))?;
+    Ok(Endpoint {
+        method,
+        path: path.trim().to_owned(),
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let endpoints = args
+        .endpoints
+        .iter()
+        .map(|spec| parse_endpoint(spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    let endpoints = Arc::new(endpoints);
+    let base_url = Arc::new(args.url.trim_end_matches('/').to_owned());
+    let body = Arc::new(args.body);
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(args.concurrency)
+        .build()?;
+
+    // a shared ticker spaces requests out evenly when a rate is set
+    let ticker = (args.rate > 0).then(|| {
+        let mut ticker = interval(Duration::from_secs_f64(1.0 / args.rate as f64));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Arc::new(Mutex::new(ticker))
+    });
+    let next = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.duration);
+
+    let workers: Vec<_> = (0..args.concurrency.max(1))
+        .map(|_| {
+            let (client, endpoints, base_url, body, ticker, next) = (
+                client.clone(),
+                endpoints.clone(),
+                base_url.clone(),
+                body.clone(),
+                ticker.clone(),
+                next.clone(),
+            );
+            tokio::spawn(async move {
+                let mut report = Report::new();
+                while Instant::now() < deadline {
+                    if let Some(ticker) = &ticker {
+                        ticker.lock().await.tick().await;
+                    }
+                    let endpoint =
+                        &endpoints[next.fetch_add(1, Ordering::Relaxed) % endpoints.len()];
+                    let mut request = client.request(
+                        endpoint.method.clone(),
+                        format!("{base_url}{}", endpoint.path),
+                    );
+                    let sends_body =
+                        endpoint.method == Method::POST || endpoint.method == Method::PUT;
+                    if let Some(body) = body.as_ref().as_ref().filter(|_| sends_body) {
+                        request = request
+                            .header(reqwest::header::CONTENT_TYPE, "application/json")
+                            .body(body.clone());
+                    }
+
+                    let sent = Instant::now();
+                    // the body is read so the latency covers the whole response
+                    let result = match request.send().await {
+                        Ok(response) => {
+                            let status = response.status().as_u16();
+                            response.bytes().await.map(|_| status)
+                        }
+                        Err(e) => Err(e),
+                    };
+                    let elapsed = sent.elapsed().as_micros() as u64;
+                    match result {
+                        Ok(status) => {
+                            report.latencies.saturating_record(elapsed.max(1));
+                            *report.statuses.entry(status).or_default() += 1;
+                        }
+                        Err(_) => report.errors += 1,
+                    }
+                }
+                report
+            })
+        })
+        .collect();
+
+    let mut total = Report::new();
+    for worker in workers {
+        total.merge(worker.await?);
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let completed = total.latencies.len();
+
+    println!("requests:   {completed} completed, {} failed", total.errors);
+    println!("throughput: {:.1} req/s", completed as f64 / elapsed);
+    if completed > 0 {
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        println!(
+            "latency:    p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  p99.9 {:.2}ms  max {:.2}ms",
+            ms(total.latencies.value_at_quantile(0.5)),
+            ms(total.latencies.value_at_quantile(0.9)),
+            ms(total.latencies.value_at_quantile(0.99)),
+            ms(total.latencies.value_at_quantile(0.999)),
+            ms(total.latencies.max()),
+        );
+    }
+    for (status, count) in &total.statuses {
+        println!("status {status}: {count}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_are_a_method_and_a_path() {
+        let endpoint = parse_endpoint(" post /posts ").unwrap();
+        assert_eq!(endpoint.method, Method::POST);
+        assert_eq!(endpoint.path, "/posts");
+        assert!(parse_endpoint("/posts").is_err());
+        assert!(parse_endpoint("G(T /posts").is_err());
+    }
+
+    #[test]
+    fn reports_add_up() {
+        let (mut total, mut other) = (Report::new(), Report::new());
+        total.latencies.record(100).unwrap();
+        total.statuses.insert(200, 1);
+        other.latencies.record(300).unwrap();
+        other.statuses.insert(200, 1);
+        other.statuses.insert(503, 1);
+        other.errors = 2;
+        total.merge(other);
+        assert_eq!(total.latencies.len(), 2);
+        assert_eq!(total.latencies.max(), 300);
+        assert_eq!(total.statuses, BTreeMap::from([(200, 2), (503, 1)]));
+        assert_eq!(total.errors, 2);
+    }
+}
@@ -0,0 +1,79 @@
+// The load generator run against a minimal HTTP server counting the requests
+// to each path.
+
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+type Hits = Arc<Mutex<HashMap<String, u64>>>;
+
+// answer every request with a 200, or a 503 for /busy, keeping connections
+// alive
+async fn serve(listener: TcpListener, hits: Hits) {
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            return;
+        };
+        let hits = hits.clone();
+        tokio::spawn(async move {
+            let mut buffer = Vec::new();
+            let mut chunk = [0; 1024];
+            loop {
+                while let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&buffer[..end]).into_owned();
+                    buffer.drain(..end + 4);
+                    let path = head.split(' ').nth(1).unwrap_or_default().to_owned();
+                    let status = match path.as_str() {
+                        "/busy" => "503 Service Unavailable",
+                        _ => "200 OK",
+                    };
+                    *hits.lock().unwrap().entry(path).or_default() += 1;
+                    let response = format!("HTTP/1.1 {status}\r\ncontent-length: 2\r\n\r\nok");
+                    if socket.write_all(response.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+                match socket.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn endpoints_are_requested_in_turn_and_reported() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let hits = Hits::default();
+    tokio::spawn(serve(listener, hits.clone()));
+
+    let out = tokio::task::spawn_blocking(move || {
+        Command::new(env!("CARGO_BIN_EXE_loadtest"))
+            .args(["--url", &url, "-e", "GET /posts", "-e", "get /busy"])
+            .args(["--concurrency", "2", "--rate", "50", "--duration", "1"])
+            .output()
+            .unwrap()
+    })
+    .await
+    .unwrap();
+    assert!(out.status.success());
+    let report = String::from_utf8_lossy(&out.stdout);
+
+    let hits = hits.lock().unwrap().clone();
+    let (posts, busy) = (hits["/posts"], hits["/busy"]);
+    assert!(posts.abs_diff(busy) <= 1, "{hits:?}");
+    // the rate holds, give or take the first tick
+    assert!(posts + busy <= 52, "{hits:?}");
+    assert!(
+        report.contains(&format!("{} completed, 0 failed", posts + busy)),
+        "{report}"
+    );
+    assert!(report.contains(&format!("status 200: {posts}")), "{report}");
+    assert!(report.contains(&format!("status 503: {busy}")), "{report}");
+    assert!(report.contains("p99"), "{report}");
+}
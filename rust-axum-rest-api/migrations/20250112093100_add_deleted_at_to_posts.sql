@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE posts ADD COLUMN deleted_at TIMESTAMP;
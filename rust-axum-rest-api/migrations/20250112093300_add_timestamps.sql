@@ -0,0 +1,16 @@
+-- Add migration script here
+UPDATE users SET created_at = NOW() WHERE created_at IS NULL;
+ALTER TABLE users
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at SET NOT NULL,
+    ADD COLUMN updated_at TIMESTAMPTZ;
+UPDATE users SET updated_at = created_at;
+ALTER TABLE users ALTER COLUMN updated_at SET NOT NULL, ALTER COLUMN updated_at SET DEFAULT NOW();
+
+UPDATE posts SET created_at = NOW() WHERE created_at IS NULL;
+ALTER TABLE posts
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at SET NOT NULL,
+    ADD COLUMN updated_at TIMESTAMPTZ;
+UPDATE posts SET updated_at = created_at;
+ALTER TABLE posts ALTER COLUMN updated_at SET NOT NULL, ALTER COLUMN updated_at SET DEFAULT NOW();
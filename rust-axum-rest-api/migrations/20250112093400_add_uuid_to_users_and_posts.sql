@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN uuid UUID NOT NULL UNIQUE DEFAULT gen_random_uuid();
+ALTER TABLE posts ADD COLUMN uuid UUID NOT NULL UNIQUE DEFAULT gen_random_uuid();
@@ -0,0 +1,23 @@
+-- Add migration script here
+-- ULID: 48 bits of milliseconds since the epoch followed by 80 random bits,
+-- as 26 Crockford base32 characters
+CREATE FUNCTION gen_ulid() RETURNS TEXT LANGUAGE plpgsql VOLATILE AS $$
+DECLARE
+    alphabet CONSTANT TEXT := '0123456789ABCDEFGHJKMNPQRSTVWXYZ';
+    random BYTEA := substring(uuid_send(gen_random_uuid()) FROM 1 FOR 6)
+        || substring(uuid_send(gen_random_uuid()) FROM 1 FOR 4);
+    value NUMERIC := floor(extract(epoch FROM clock_timestamp()) * 1000);
+    output TEXT := '';
+BEGIN
+    FOR i IN 0..9 LOOP
+        value := value * 256 + get_byte(random, i);
+    END LOOP;
+    FOR i IN 1..26 LOOP
+        output := substr(alphabet, mod(value, 32)::INT + 1, 1) || output;
+        value := div(value, 32);
+    END LOOP;
+    RETURN output;
+END
+$$;
+
+ALTER TABLE posts ADD COLUMN public_id TEXT NOT NULL UNIQUE DEFAULT gen_ulid();
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- usernames are unique regardless of case; the index keeps the constraint's
+-- name so violations still report the username field
+ALTER TABLE users DROP CONSTRAINT users_username_key;
+CREATE UNIQUE INDEX users_username_key ON users (LOWER(username));
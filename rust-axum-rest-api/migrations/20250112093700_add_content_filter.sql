@@ -0,0 +1,13 @@
+-- Add migration script here
+-- posts caught by the content filter wait for a moderator instead of being
+-- listed
+ALTER TABLE posts ADD COLUMN status TEXT NOT NULL DEFAULT 'published'
+    CONSTRAINT posts_status_check CHECK (status IN ('published', 'pending'));
+
+-- words the content filter rejects or flags, managed through /admin/banned-words
+CREATE TABLE banned_words (
+    id SERIAL PRIMARY KEY,
+    word TEXT NOT NULL UNIQUE,
+    action TEXT NOT NULL CHECK (action IN ('reject', 'flag')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE posts DROP CONSTRAINT posts_status_check;
+ALTER TABLE posts ADD CONSTRAINT posts_status_check
+    CHECK (status IN ('published', 'pending', 'spam'));
@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE posts DROP CONSTRAINT posts_status_check;
+ALTER TABLE posts ADD CONSTRAINT posts_status_check
+    CHECK (status IN ('published', 'pending', 'spam', 'rejected'));
+
+-- the last moderator decision, the reason is given for rejections
+ALTER TABLE posts ADD COLUMN moderation_reason TEXT;
+ALTER TABLE posts ADD COLUMN moderated_at TIMESTAMPTZ;
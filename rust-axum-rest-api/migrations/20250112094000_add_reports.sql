@@ -0,0 +1,14 @@
+-- Add migration script here
+-- reports of posts by readers, open until an admin resolves them
+CREATE TABLE reports (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    reporter_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ,
+    outcome TEXT CHECK (outcome IN ('dismissed', 'upheld')),
+    note TEXT
+);
+
+CREATE INDEX reports_open_idx ON reports (post_id) WHERE resolved_at IS NULL;
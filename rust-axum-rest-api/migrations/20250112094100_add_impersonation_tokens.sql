@@ -0,0 +1,11 @@
+-- Add migration script here
+-- short-lived tokens that let an admin act as a user, only the hash of the
+-- token is stored
+CREATE TABLE impersonation_tokens (
+    id SERIAL PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    impersonator TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- the fields that differ between before and after, as [{field, old, new}]
+ALTER TABLE audit_log ADD COLUMN changes JSONB;
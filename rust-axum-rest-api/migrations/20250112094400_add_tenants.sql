@@ -0,0 +1,41 @@
+-- Add migration script here
+-- customers sharing the deployment; everything that existed before belongs
+-- to the default tenant
+CREATE TABLE tenants (
+    id SERIAL PRIMARY KEY,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO tenants (id, slug, name) VALUES (1, 'default', 'Default');
+SELECT setval(pg_get_serial_sequence('tenants', 'id'), 1);
+
+ALTER TABLE users ADD COLUMN tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id);
+ALTER TABLE posts ADD COLUMN tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id);
+ALTER TABLE reports ADD COLUMN tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id);
+ALTER TABLE banned_words ADD COLUMN tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id);
+ALTER TABLE audit_log ADD COLUMN tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id);
+
+-- usernames, emails and banned words are unique per tenant; the names stay
+-- so conflicts still report the column
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (tenant_id, email);
+DROP INDEX users_username_key;
+CREATE UNIQUE INDEX users_username_key ON users (tenant_id, LOWER(username));
+ALTER TABLE banned_words DROP CONSTRAINT banned_words_word_key;
+ALTER TABLE banned_words ADD CONSTRAINT banned_words_word_key UNIQUE (tenant_id, word);
+
+-- authors and reporters from another tenant don't exist as far as a post is
+-- concerned
+ALTER TABLE users ADD CONSTRAINT users_tenant_id_id_key UNIQUE (tenant_id, id);
+ALTER TABLE posts DROP CONSTRAINT posts_user_id_fkey;
+ALTER TABLE posts ADD CONSTRAINT posts_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+    REFERENCES users (tenant_id, id) ON DELETE CASCADE;
+ALTER TABLE reports DROP CONSTRAINT reports_reporter_id_fkey;
+ALTER TABLE reports ADD CONSTRAINT reports_reporter_id_fkey FOREIGN KEY (tenant_id, reporter_id)
+    REFERENCES users (tenant_id, id) ON DELETE SET NULL (reporter_id);
+
+CREATE INDEX posts_tenant_id_idx ON posts (tenant_id);
+DROP INDEX audit_log_entity_idx;
+CREATE INDEX audit_log_entity_idx ON audit_log (tenant_id, entity_type, entity_id);
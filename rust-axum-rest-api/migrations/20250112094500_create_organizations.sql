@@ -0,0 +1,29 @@
+-- Add migration script here
+-- teams of users sharing ownership of posts, within a tenant
+CREATE TABLE organizations (
+    id SERIAL PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id),
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (tenant_id, id)
+);
+
+CREATE TABLE org_memberships (
+    org_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (org_id, user_id),
+    CONSTRAINT org_memberships_org_id_fkey FOREIGN KEY (tenant_id, org_id)
+        REFERENCES organizations (tenant_id, id) ON DELETE CASCADE,
+    CONSTRAINT org_memberships_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
+
+CREATE INDEX org_memberships_user_id_idx ON org_memberships (user_id);
+
+ALTER TABLE posts ADD COLUMN org_id INTEGER;
+ALTER TABLE posts ADD CONSTRAINT posts_org_id_fkey FOREIGN KEY (tenant_id, org_id)
+    REFERENCES organizations (tenant_id, id) ON DELETE SET NULL (org_id);
+CREATE INDEX posts_org_id_idx ON posts (org_id) WHERE org_id IS NOT NULL;
@@ -0,0 +1,22 @@
+-- Add migration script here
+-- invitations to join an organization, mailed as a link carrying a secret
+-- token of which only the hash is kept
+CREATE TABLE org_invitations (
+    id SERIAL PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    org_id INTEGER NOT NULL,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+    token_hash TEXT NOT NULL UNIQUE,
+    invited_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    accepted_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    revoked_at TIMESTAMPTZ,
+    CONSTRAINT org_invitations_org_id_fkey FOREIGN KEY (tenant_id, org_id)
+        REFERENCES organizations (tenant_id, id) ON DELETE CASCADE
+);
+
+CREATE INDEX org_invitations_pending_idx ON org_invitations (org_id)
+    WHERE accepted_at IS NULL AND revoked_at IS NULL;
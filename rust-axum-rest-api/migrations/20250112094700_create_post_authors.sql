@@ -0,0 +1,13 @@
+-- Add migration script here
+-- co-authors of posts, next to the primary author in posts.user_id
+CREATE TABLE post_authors (
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    added_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (post_id, user_id),
+    CONSTRAINT post_authors_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
+
+CREATE INDEX post_authors_user_id_idx ON post_authors (user_id);
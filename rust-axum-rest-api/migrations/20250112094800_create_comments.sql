@@ -0,0 +1,18 @@
+-- Add migration script here
+-- comments on posts, threaded by replying to another comment of the post;
+-- `path` holds the ids of the comment's ancestors, root first, so a post's
+-- whole thread comes back in tree order from one indexed query
+CREATE TABLE comments (
+    id SERIAL PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    parent_comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE,
+    path INTEGER[] NOT NULL DEFAULT '{}',
+    user_id INTEGER,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT comments_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE SET NULL (user_id)
+);
+
+CREATE INDEX comments_post_id_path_idx ON comments (post_id, (path || id));
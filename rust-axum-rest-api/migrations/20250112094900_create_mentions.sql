@@ -0,0 +1,16 @@
+-- Add migration script here
+-- users @mentioned in a post's body (comment_id NULL) or in one of its
+-- comments
+CREATE TABLE mentions (
+    id SERIAL PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    user_id INTEGER NOT NULL,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT mentions_user_id_key UNIQUE NULLS NOT DISTINCT (user_id, post_id, comment_id),
+    CONSTRAINT mentions_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
+
+CREATE INDEX mentions_post_id_idx ON mentions (post_id);
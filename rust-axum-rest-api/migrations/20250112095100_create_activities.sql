@@ -0,0 +1,22 @@
+-- Add migration script here
+-- what users did, for their activity feeds; filled by the handlers as
+-- things happen
+CREATE TABLE activities (
+    id BIGSERIAL PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    user_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT activities_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
+
+CREATE INDEX activities_user_id_id_idx ON activities (user_id, id DESC);
+
+-- what happened before there was a feed
+INSERT INTO activities (tenant_id, user_id, kind, post_id, created_at)
+SELECT tenant_id, user_id, 'post', id, created_at FROM posts WHERE user_id IS NOT NULL ORDER BY id;
+INSERT INTO activities (tenant_id, user_id, kind, post_id, comment_id, created_at)
+SELECT tenant_id, user_id, 'comment', post_id, id, created_at FROM comments WHERE user_id IS NOT NULL ORDER BY id;
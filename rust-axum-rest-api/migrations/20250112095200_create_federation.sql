@@ -0,0 +1,39 @@
+-- Add migration script here
+-- ActivityPub federation: the signing keys of users as actors, who follows
+-- them from other servers and the activities waiting to be delivered there
+CREATE TABLE actor_keys (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    public_key_pem TEXT NOT NULL,
+    private_key_pem TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE remote_followers (
+    id SERIAL PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    user_id INTEGER NOT NULL,
+    -- the follower's actor id and inbox, both URLs
+    actor TEXT NOT NULL,
+    inbox TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT remote_followers_actor_key UNIQUE (user_id, actor),
+    CONSTRAINT remote_followers_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
+
+CREATE TABLE deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    sender_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    inbox TEXT NOT NULL,
+    activity JSONB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_error TEXT,
+    delivered_at TIMESTAMPTZ,
+    -- given up on
+    failed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX deliveries_due_idx ON deliveries (next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
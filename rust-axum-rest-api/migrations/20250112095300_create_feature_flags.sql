@@ -0,0 +1,25 @@
+-- Add migration script here
+-- features that can be turned on for a share of the users, see `flags`
+CREATE TABLE feature_flags (
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    key TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    -- the percentage of users the feature is on for
+    rollout_percent SMALLINT NOT NULL DEFAULT 0 CHECK (rollout_percent BETWEEN 0 AND 100),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT feature_flags_key_key PRIMARY KEY (tenant_id, key)
+);
+
+-- users a flag is on or off for whatever the rollout says
+CREATE TABLE feature_flag_overrides (
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    flag_key TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    PRIMARY KEY (tenant_id, flag_key, user_id),
+    CONSTRAINT feature_flag_overrides_flag_key_fkey FOREIGN KEY (tenant_id, flag_key)
+        REFERENCES feature_flags (tenant_id, key) ON DELETE CASCADE,
+    CONSTRAINT feature_flag_overrides_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
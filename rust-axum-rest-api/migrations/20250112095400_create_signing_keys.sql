@@ -0,0 +1,12 @@
+-- Add migration script here
+-- keys signing the API's JWTs, see `jwt`. The newest unretired one signs,
+-- retired ones stay published until the tokens they signed have expired.
+CREATE TABLE signing_keys (
+    kid TEXT PRIMARY KEY,
+    public_key_pem TEXT NOT NULL,
+    private_key_pem TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    retired_at TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX signing_keys_current_idx ON signing_keys ((retired_at IS NULL)) WHERE retired_at IS NULL;
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Which local user the subject of an external identity provider's tokens
+-- acts as
+CREATE TABLE external_identities (
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT external_identities_subject_key PRIMARY KEY (tenant_id, issuer, subject),
+    CONSTRAINT external_identities_user_id_fkey FOREIGN KEY (tenant_id, user_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
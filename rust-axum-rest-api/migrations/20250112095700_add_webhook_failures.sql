@@ -0,0 +1,9 @@
+-- Add migration script here
+-- endpoints that keep failing are disabled until an admin enables them
+-- again; their queued deliveries wait meanwhile
+ALTER TABLE webhook_endpoints ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE webhook_endpoints ADD COLUMN disabled_at TIMESTAMPTZ;
+
+-- the dead letters: deliveries given up on, newest first
+CREATE INDEX webhook_deliveries_failed_idx ON webhook_deliveries (failed_at DESC)
+    WHERE failed_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- a user's own avatar; users without one are shown Gravatar's image for
+-- their email (see `avatars`)
+ALTER TABLE users ADD COLUMN avatar_url TEXT;
@@ -0,0 +1,26 @@
+-- Add migration script here
+-- the Open Graph metadata of pages posts link to, fetched in the background
+-- and shared by every post, in any tenant, linking the same URL
+CREATE TABLE link_previews (
+    url TEXT PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    image_url TEXT,
+    site_name TEXT,
+    -- why fetching failed, in which case there is no preview
+    error TEXT,
+    fetched_at TIMESTAMPTZ,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX link_previews_due_idx ON link_previews (next_attempt_at)
+    WHERE fetched_at IS NULL;
+
+-- the links of a post, in the order they appear in its body
+CREATE TABLE post_links (
+    post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    url TEXT NOT NULL REFERENCES link_previews(url),
+    position INTEGER NOT NULL,
+    PRIMARY KEY (post_id, url)
+);
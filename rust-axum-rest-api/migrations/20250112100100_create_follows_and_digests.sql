@@ -0,0 +1,26 @@
+-- Add migration script here
+-- users following other users of their tenant, whose posts they get in
+-- their digest
+CREATE TABLE follows (
+    tenant_id INTEGER NOT NULL DEFAULT 1,
+    follower_id INTEGER NOT NULL,
+    followee_id INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (follower_id, followee_id),
+    CHECK (follower_id <> followee_id),
+    CONSTRAINT follows_follower_id_fkey FOREIGN KEY (tenant_id, follower_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE,
+    CONSTRAINT follows_followee_id_fkey FOREIGN KEY (tenant_id, followee_id)
+        REFERENCES users (tenant_id, id) ON DELETE CASCADE
+);
+
+CREATE INDEX follows_followee_id_idx ON follows (followee_id);
+
+-- the email users opted into; users without a row get none
+CREATE TABLE notification_preferences (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    weekly_digest BOOLEAN NOT NULL DEFAULT FALSE,
+    -- when the last digest was sent, or skipped for lack of posts
+    digest_sent_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
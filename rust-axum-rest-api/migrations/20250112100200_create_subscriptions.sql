@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Stripe subscriptions of users and organizations to paid plans, kept in
+-- sync by the billing webhook
+CREATE TABLE subscriptions (
+    stripe_subscription_id TEXT PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id),
+    stripe_customer_id TEXT NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    org_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE,
+    plan TEXT NOT NULL CHECK (plan IN ('free', 'pro', 'business')),
+    status TEXT NOT NULL,
+    current_period_end TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((user_id IS NULL) <> (org_id IS NULL))
+);
+
+CREATE INDEX subscriptions_user_id_idx ON subscriptions (user_id) WHERE user_id IS NOT NULL;
+CREATE INDEX subscriptions_org_id_idx ON subscriptions (org_id) WHERE org_id IS NOT NULL;
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- what users and organizations used in each billing period, counted in
+-- batches by `usage`
+CREATE TABLE usage (
+    tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id),
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    org_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE,
+    period_start DATE NOT NULL,
+    metric TEXT NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((user_id IS NULL) <> (org_id IS NULL)),
+    UNIQUE NULLS NOT DISTINCT (user_id, org_id, period_start, metric)
+);
+
+CREATE INDEX usage_org_id_idx ON usage (org_id) WHERE org_id IS NOT NULL;
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- old posts moved out of the way, their row left as a stub whose body is
+-- kept here until they are unarchived
+ALTER TABLE posts DROP CONSTRAINT posts_status_check;
+ALTER TABLE posts ADD CONSTRAINT posts_status_check
+    CHECK (status IN ('published', 'pending', 'spam', 'rejected', 'archived'));
+
+CREATE TABLE archived_posts (
+    post_id INTEGER PRIMARY KEY REFERENCES posts(id) ON DELETE CASCADE,
+    tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id),
+    body TEXT NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
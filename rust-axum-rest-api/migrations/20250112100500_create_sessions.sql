@@ -0,0 +1,12 @@
+-- Add migration script here
+-- browser sessions, traded for an API key or access token; only the hash of
+-- the session id is stored
+CREATE TABLE sessions (
+    id_hash TEXT PRIMARY KEY,
+    tenant_id INTEGER NOT NULL DEFAULT 1 REFERENCES tenants(id),
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- where requests came from, see `geoip`
+ALTER TABLE audit_log ADD COLUMN country TEXT, ADD COLUMN region TEXT;
+
+-- clicks on share links by the clicking client's country
+CREATE TABLE short_link_countries (
+    code TEXT NOT NULL REFERENCES short_links(code) ON DELETE CASCADE,
+    country TEXT NOT NULL,
+    clicks BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (code, country)
+);
@@ -0,0 +1,46 @@
+-- Add migration script here
+-- counts kept up to date by triggers, so reads don't COUNT(*): the comments
+-- of each post, and the posts of each user that are out there, neither
+-- deleted nor held back by moderation (archived ones still count)
+ALTER TABLE posts ADD COLUMN comments_count BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN posts_count BIGINT NOT NULL DEFAULT 0;
+
+UPDATE posts p SET comments_count = (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id);
+UPDATE users u SET posts_count = (
+    SELECT COUNT(*) FROM posts p
+    WHERE p.user_id = u.id AND p.tenant_id = u.tenant_id
+      AND p.deleted_at IS NULL AND p.status IN ('published', 'archived')
+);
+
+CREATE FUNCTION count_comments() RETURNS TRIGGER LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE posts SET comments_count = comments_count + 1 WHERE id = NEW.post_id;
+    ELSE
+        UPDATE posts SET comments_count = comments_count - 1 WHERE id = OLD.post_id;
+    END IF;
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER comments_count AFTER INSERT OR DELETE ON comments
+    FOR EACH ROW EXECUTE FUNCTION count_comments();
+
+CREATE FUNCTION count_posts() RETURNS TRIGGER LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' AND OLD.user_id IS NOT NULL AND OLD.deleted_at IS NULL
+            AND OLD.status IN ('published', 'archived') THEN
+        UPDATE users SET posts_count = posts_count - 1
+        WHERE id = OLD.user_id AND tenant_id = OLD.tenant_id;
+    END IF;
+    IF TG_OP <> 'DELETE' AND NEW.user_id IS NOT NULL AND NEW.deleted_at IS NULL
+            AND NEW.status IN ('published', 'archived') THEN
+        UPDATE users SET posts_count = posts_count + 1
+        WHERE id = NEW.user_id AND tenant_id = NEW.tenant_id;
+    END IF;
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER posts_count AFTER INSERT OR DELETE OR UPDATE OF user_id, deleted_at, status ON posts
+    FOR EACH ROW EXECUTE FUNCTION count_posts();
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- emails sealed by the application (see `repo::emails`): `email` holds the
+-- ciphertext and `email_hash` a keyed hash of the address, which lookups and
+-- uniqueness go by. Rows from before keep the address in the clear, and no
+-- hash, until `app encrypt-emails` seals them; the old constraint still
+-- keeps those unique.
+ALTER TABLE users ADD COLUMN email_hash TEXT;
+ALTER TABLE users ADD CONSTRAINT users_email_hash_key UNIQUE (tenant_id, email_hash);
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- one report of a post per reporter, so a single reader can't push a post
+-- back into the moderation queue; earlier duplicates keep their first report
+DELETE FROM reports a USING reports b
+WHERE a.post_id = b.post_id AND a.reporter_id = b.reporter_id AND a.id > b.id;
+
+ALTER TABLE reports ADD CONSTRAINT reports_post_id_reporter_id_key UNIQUE (post_id, reporter_id);
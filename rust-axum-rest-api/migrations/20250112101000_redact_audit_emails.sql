@@ -0,0 +1,18 @@
+-- Add migration script here
+-- audit snapshots no longer keep email addresses (see `audit`); scrub those
+-- written before
+UPDATE audit_log SET before = jsonb_set(before, '{email}', '"[redacted]"')
+WHERE jsonb_typeof(before->'email') = 'string';
+UPDATE audit_log SET after = jsonb_set(after, '{email}', '"[redacted]"')
+WHERE jsonb_typeof(after->'email') = 'string';
+UPDATE audit_log SET changes = (
+    SELECT jsonb_agg(
+        CASE WHEN change->>'field' = 'email' THEN change
+            || jsonb_build_object(
+                'old', CASE WHEN change->'old' = 'null' THEN 'null'::jsonb ELSE '"[redacted]"' END,
+                'new', CASE WHEN change->'new' = 'null' THEN 'null'::jsonb ELSE '"[redacted]"' END)
+        ELSE change END
+        ORDER BY position)
+    FROM jsonb_array_elements(changes) WITH ORDINALITY AS c(change, position)
+)
+WHERE changes @> '[{"field": "email"}]';
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- when the user mentioned was told, NULL until the post is published; the
+-- mentions so far were notified as they were written
+ALTER TABLE mentions ADD COLUMN notified_at TIMESTAMPTZ;
+
+UPDATE mentions SET notified_at = created_at;
@@ -0,0 +1,24 @@
+-- Add migration script here
+-- comments are moderated like posts: held back ones wait for a moderator,
+-- and only published ones are counted
+ALTER TABLE comments ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE comments ADD COLUMN moderation_reason TEXT;
+ALTER TABLE comments ADD COLUMN moderated_at TIMESTAMPTZ;
+
+CREATE INDEX comments_status_idx ON comments (status, created_at) WHERE status <> 'published';
+
+CREATE OR REPLACE FUNCTION count_comments() RETURNS TRIGGER LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' AND OLD.status = 'published' THEN
+        UPDATE posts SET comments_count = comments_count - 1 WHERE id = OLD.post_id;
+    END IF;
+    IF TG_OP <> 'DELETE' AND NEW.status = 'published' THEN
+        UPDATE posts SET comments_count = comments_count + 1 WHERE id = NEW.post_id;
+    END IF;
+    RETURN NULL;
+END
+$$;
+
+DROP TRIGGER comments_count ON comments;
+CREATE TRIGGER comments_count AFTER INSERT OR DELETE OR UPDATE OF status ON comments
+    FOR EACH ROW EXECUTE FUNCTION count_comments();
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- reports of comments, which name the comment's post too; a reader reports
+-- a post and each of its comments once
+ALTER TABLE reports ADD COLUMN comment_id INTEGER REFERENCES comments(id) ON DELETE CASCADE;
+
+ALTER TABLE reports DROP CONSTRAINT reports_post_id_reporter_id_key;
+CREATE UNIQUE INDEX reports_post_id_reporter_id_key ON reports (post_id, reporter_id)
+    WHERE comment_id IS NULL;
+CREATE UNIQUE INDEX reports_comment_id_reporter_id_key ON reports (comment_id, reporter_id)
+    WHERE comment_id IS NOT NULL;
@@ -0,0 +1,21 @@
+-- Add migration script here
+-- when a post was first published, so approving it again after it was held
+-- back (by readers' reports, a flagged edit) doesn't announce it twice.
+-- Posts inserted as published get it straight away, those approved by a
+-- moderator when they are (see `PostRepository::mark_published`).
+ALTER TABLE posts ADD COLUMN published_at TIMESTAMPTZ;
+
+UPDATE posts SET published_at = COALESCE(moderated_at, created_at)
+WHERE status IN ('published', 'archived') OR moderation_reason LIKE 'reported % times';
+
+CREATE FUNCTION set_published_at() RETURNS TRIGGER LANGUAGE plpgsql AS $$
+BEGIN
+    IF NEW.status = 'published' AND NEW.published_at IS NULL THEN
+        NEW.published_at = NOW();
+    END IF;
+    RETURN NEW;
+END
+$$;
+
+CREATE TRIGGER posts_published_at BEFORE INSERT ON posts
+    FOR EACH ROW EXECUTE FUNCTION set_published_at();
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- everyone who wrote a post, `user_id` first, then the co-authors in the
+-- order they were added: `Post::authors` for every query returning posts,
+-- as `post_authors_of(posts) AS "authors!"`
+CREATE FUNCTION post_authors_of(post posts) RETURNS INTEGER[] LANGUAGE sql STABLE AS $$
+    SELECT ARRAY_REMOVE(ARRAY[post.user_id] || ARRAY(
+        SELECT a.user_id FROM post_authors a
+        WHERE a.post_id = post.id AND a.user_id IS DISTINCT FROM post.user_id
+        ORDER BY a.added_at, a.user_id
+    ), NULL)
+$$;
@@ -0,0 +1,59 @@
+// Activity feeds: what a user posted and commented, newest first.
+//
+// The handlers record an activity as they create things (see `record`), into
+// the activities table rather than the feed being pieced together from the
+// posts and comments tables on every read. There are no likes yet to show.
+
+use axum::extract::{Query, State};
+use axum::http::Uri;
+use axum::response::{IntoResponse, Response};
+
+use crate::error::ApiError;
+use crate::handlers;
+use crate::ids::PathKey;
+use crate::models::{ActivityKind, NewActivity};
+use crate::negotiate::Accept;
+use crate::pagination::{self, PageParams};
+use crate::state::AppState;
+
+// add to the feed of `user_id`, if the thing has an author at all
+pub async fn record(
+    state: &AppState,
+    user_id: Option<i32>,
+    kind: ActivityKind,
+    post_id: i32,
+    comment_id: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    let Some(user_id) = user_id else {
+        return Ok(());
+    };
+    state
+        .activities
+        .append(&NewActivity {
+            user_id,
+            kind,
+            post_id,
+            comment_id,
+        })
+        .await
+}
+
+// handler for "GET /users/:id/activity" rest API endpoint
+pub async fn get_user_activity(
+    State(state): State<AppState>,
+    uri: Uri,
+    PathKey(key): PathKey,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let page = page.page()?;
+    let user = handlers::user(&state, key).await?;
+    let total = state.activities.count(user.id).await?;
+    let activities = state.activities.list(user.id, page).await?;
+
+    Ok((
+        pagination::headers(&uri, page, total),
+        format.respond(activities),
+    )
+        .into_response())
+}
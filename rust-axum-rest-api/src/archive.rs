@@ -0,0 +1,117 @@
+// Post archiving: with ARCHIVE_AFTER_YEARS set, a background job moves the
+// bodies of published posts older than that into the archived_posts table,
+// leaving a stub with the status `archived` in their place. Archived posts
+// are left out of lists like any unpublished post, and fetching or editing
+// one is a 410 Gone saying how to get it back:
+//
+//     {"error": "archived", "message": "...", "archived_at": "...",
+//      "unarchive": "/posts/01J.../unarchive"}
+//
+// `POST /posts/:id/unarchive` puts a post back, published, for its authors
+// or an admin. The job only looks at `created_at`, so an unarchived post is
+// archived again with the next run unless ARCHIVE_AFTER_YEARS was raised.
+
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use chrono::{DateTime, Months, Utc};
+use sqlx::{Pool, Postgres};
+use tracing::{error, info};
+
+use crate::audit::{self, Change};
+use crate::auth::{Actor, RequireAdmin};
+use crate::authors::require_author;
+use crate::error::{ApiError, OrNotFound};
+use crate::ids::PathKey;
+use crate::jobs;
+use crate::links::Linked;
+use crate::locks::Locks;
+use crate::models::{Post, PostStatus};
+use crate::negotiate::{Accept, Negotiated};
+use crate::repo;
+use crate::routes::{expand, POST_ROUTE};
+use crate::state::AppState;
+
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// posts archived in one statement
+const BATCH: i64 = 500;
+
+// Archive every published post created before `cutoff`, `BATCH` at a time,
+// returning how many were.
+pub async fn run_once(pool: &Pool<Postgres>, cutoff: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    let mut total = 0;
+    loop {
+        let archived = repo::archive::archive_before(pool, cutoff, BATCH).await?;
+        total += archived;
+        if archived < BATCH as u64 {
+            return Ok(total);
+        }
+    }
+}
+
+// Archive the posts older than `years` every `INTERVAL` until the process
+// exits.
+pub fn spawn(pool: Pool<Postgres>, locks: Locks, years: u32) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(cutoff) = Utc::now().checked_sub_months(Months::new(years * 12)) else {
+                continue;
+            };
+            match jobs::exclusive(&locks, "archive", run_once(&pool, cutoff)).await {
+                Ok(None | Some(0)) => {}
+                Ok(Some(archived)) => info!(archived, "archived posts created before {cutoff}"),
+                Err(e) => error!("archiving posts failed: {e}"),
+            }
+        }
+    });
+}
+
+// The 410 for an archived post, Ok for any other.
+pub async fn ensure_live(state: &AppState, post: &Post) -> Result<(), ApiError> {
+    if post.status != PostStatus::Archived.as_str() {
+        return Ok(());
+    }
+    let path = expand(POST_ROUTE, &post.public_id);
+    let archived_at = repo::archive::archived_at(&state.pool, post.id).await?;
+    Err(ApiError::new(
+        StatusCode::GONE,
+        "archived",
+        format!(
+            "post {} was archived, unarchive it to see it",
+            post.public_id
+        ),
+    )
+    .with("archived_at", archived_at)
+    .with("unarchive", format!("{path}/unarchive")))
+}
+
+// handler for "POST /posts/:id/unarchive" rest API endpoint
+pub async fn unarchive_post(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Linked<Post>>, ApiError> {
+    let before = state.posts.get(key).await.or_not_found("post")?;
+    require_author(&before, &actor, admin, "unarchive it")?;
+    if !repo::archive::unarchive(&state.pool, before.id).await? {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "not_archived",
+            format!("post {} isn't archived", before.public_id),
+        ));
+    }
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    audit::record(
+        Change::new("post.unarchive", "post", post.id)
+            .before(&before)
+            .after(&post),
+    );
+
+    Ok(format.respond(Linked::new(post, state.config.id_scheme)))
+}
@@ -0,0 +1,230 @@
+// Audit log: who did what to which entity, from where and when.
+//
+// `log` wraps every mutating request (anything but GET, HEAD and OPTIONS).
+// Handlers describe what they changed with `record`, before and after
+// snapshots of the entity included, and once the request succeeded each
+// change is written to the audit_log table along with the actor, the
+// impersonating admin if any, the client's address and which fields differ
+// between the snapshots (what `GET /posts/:id/history` shows). A successful request
+// that recorded nothing still gets an entry naming its route, so no mutating
+// endpoint goes unaudited. Failed requests change nothing and are not logged.
+//
+// Snapshots keep no email addresses, which are sealed in their own tables
+// (see `repo::emails`): `email` fields read "[redacted]", at any depth.
+
+use std::cell::RefCell;
+
+use axum::extract::{MatchedPath, Query, Request, State};
+use axum::http::{Method, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::error;
+
+use crate::auth::{Actor, Admin, RequireAdmin};
+use crate::client_ip::ClientIp;
+use crate::error::ApiError;
+use crate::geoip::Location;
+use crate::models::{AuditEntry, FieldChange, NewAuditEntry};
+use crate::negotiate::Accept;
+use crate::pagination::{self, PageParams};
+use crate::redact::REDACTED;
+use crate::repo::AuditFilter;
+use crate::state::AppState;
+
+// A change to one entity, see `record`.
+pub struct Change {
+    action: &'static str,
+    entity_type: &'static str,
+    entity_id: String,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl Change {
+    // e.g. `Change::new("post.update", "post", post.id)`
+    pub fn new(action: &'static str, entity_type: &'static str, entity_id: impl ToString) -> Self {
+        Change {
+            action,
+            entity_type,
+            entity_id: entity_id.to_string(),
+            before: None,
+            after: None,
+        }
+    }
+
+    // the entity as it was
+    pub fn before(mut self, entity: &impl Serialize) -> Self {
+        self.before = snapshot(entity);
+        self
+    }
+
+    // the entity as it is now
+    pub fn after(mut self, entity: &impl Serialize) -> Self {
+        self.after = snapshot(entity);
+        self
+    }
+}
+
+// fields left out of snapshots
+const PRIVATE_FIELDS: &[&str] = &["email"];
+
+fn snapshot(entity: &impl Serialize) -> Option<Value> {
+    let mut value = serde_json::to_value(entity).ok()?;
+    redact(&mut value);
+    Some(value)
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if PRIVATE_FIELDS.contains(&name.as_str()) && !field.is_null() {
+                    *field = Value::from(REDACTED);
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[derive(Default)]
+struct Trail {
+    admin: Option<Admin>,
+    changes: Vec<Change>,
+}
+
+tokio::task_local! {
+    static TRAIL: RefCell<Trail>;
+}
+
+// note a change made by the request being handled, a no-op outside of `log`
+pub fn record(change: Change) {
+    let _ = TRAIL.try_with(|trail| trail.borrow_mut().changes.push(change));
+}
+
+// note that the request was made by an admin, see `RequireAdmin`
+pub(crate) fn admin(admin: Admin) {
+    let _ = TRAIL.try_with(|trail| trail.borrow_mut().admin = Some(admin));
+}
+
+// write the changes of successful mutating requests to the audit log
+pub async fn log(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    location: Location,
+    actor: Actor,
+    request: Request,
+    next: Next,
+) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_owned(),
+        None => request.uri().path().to_owned(),
+    };
+    let route = format!("{} {route}", request.method());
+
+    let (response, trail) = TRAIL
+        .scope(RefCell::new(Trail::default()), async {
+            let response = next.run(request).await;
+            (response, TRAIL.with(|trail| trail.take()))
+        })
+        .await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let who = match (trail.admin, actor.user_id) {
+        (Some(admin), _) => admin.to_string(),
+        (None, Some(user_id)) => format!("user {user_id}"),
+        (None, None) => String::from("anonymous"),
+    };
+    let entry = |action: String| NewAuditEntry {
+        actor: who.clone(),
+        impersonator: actor.impersonator.clone(),
+        action,
+        entity_type: None,
+        entity_id: None,
+        before: None,
+        after: None,
+        changes: None,
+        ip: client.map(|ip| ip.to_string()),
+        country: location.country.clone(),
+        region: location.region.clone(),
+    };
+    let entries: Vec<NewAuditEntry> = if trail.changes.is_empty() {
+        vec![entry(route)]
+    } else {
+        trail
+            .changes
+            .into_iter()
+            .map(|change| NewAuditEntry {
+                entity_type: Some(change.entity_type.to_owned()),
+                entity_id: Some(change.entity_id),
+                changes: (change.before.is_some() || change.after.is_some())
+                    .then(|| diff(change.before.as_ref(), change.after.as_ref())),
+                before: change.before,
+                after: change.after,
+                ..entry(change.action.to_owned())
+            })
+            .collect()
+    };
+    // the change is made, an audit failure can't undo it
+    if let Err(e) = state.audit.append(&entries).await {
+        error!("writing {} audit log entries failed: {e}", entries.len());
+    }
+    response
+}
+
+// the top-level fields that differ between two snapshots, missing ones as
+// null; `updated_at` always changes and is left out
+fn diff(before: Option<&Value>, after: Option<&Value>) -> Vec<FieldChange> {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+    let fields = after
+        .keys()
+        .chain(before.keys().filter(|field| !after.contains_key(*field)));
+    fields
+        .filter(|field| *field != "updated_at")
+        .filter_map(|field| {
+            let old = before.get(field).unwrap_or(&Value::Null);
+            let new = after.get(field).unwrap_or(&Value::Null);
+            (old != new).then(|| FieldChange {
+                field: field.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            })
+        })
+        .collect()
+}
+
+// handler for "GET /admin/audit" rest API endpoint, newest first, see
+// `AuditFilter` for the parameters and `pagination` for paging
+pub async fn list_audit_log(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(filter): Query<AuditFilter>,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let page = page.page()?;
+    let total = state.audit.count(&filter).await?;
+    let entries: Vec<AuditEntry> = state.audit.list(&filter, page).await?;
+
+    Ok((
+        pagination::headers(&uri, page, total),
+        format.respond(entries),
+    )
+        .into_response())
+}
@@ -0,0 +1,256 @@
+// Authorization for admin-only endpoints, and who is acting on a request.
+//
+// Admin requests must carry `Authorization: Bearer <token>` where the token is
+// either the configured ADMIN_TOKEN or an API key of a user with the admin
+// role (see `admin apikeys create`). An unset ADMIN_TOKEN simply disables the
+// first option. Internal services may present a client certificate instead,
+// if its identity is one of MTLS_ADMIN_IDENTITIES (see `tls`).
+//
+// Users act through their API keys: requests carrying one are made by the
+// key's owner, who organizations (see `orgs`) check roles against.
+//
+// API keys can be traded for short-lived access tokens (JWTs, see `jwt`)
+// acting for the same user, and the tokens of an external identity provider
+// act for the user linked to them (see `oidc`); invalid or expired ones are
+// rejected. Browsers can hold a session cookie instead (see `sessions`),
+// which requests without an Authorization header act through.
+//
+// Admins can also get a short-lived impersonation token acting as another
+// user (`POST /admin/users/:id/impersonate`). Requests made with one are
+// attributed to that user, carry the admin as impersonator and are logged;
+// they never pass as admin requests.
+
+use std::fmt;
+
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::{error, info};
+
+use crate::audit;
+use crate::error::ApiError;
+use crate::jwt;
+use crate::repo;
+use crate::repo::api_keys::KEY_PREFIX;
+use crate::repo::impersonation::TOKEN_PREFIX;
+use crate::sessions;
+use crate::state::AppState;
+use crate::tls::ClientIdentity;
+
+// Which admin credential a request was made with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Admin {
+    // the configured ADMIN_TOKEN
+    Token,
+    // an API key of the admin with this id
+    User(i32),
+    // a client certificate of this identity
+    Service(String),
+}
+
+impl fmt::Display for Admin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Admin::Token => f.write_str("admin token"),
+            Admin::User(id) => write!(f, "admin user {id}"),
+            Admin::Service(identity) => write!(f, "admin service {identity}"),
+        }
+    }
+}
+
+// Extractor that rejects the request unless it is made by an admin.
+pub struct RequireAdmin(pub Admin);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequireAdmin
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+        if let Some(ClientIdentity(identity)) = parts.extensions.get::<ClientIdentity>() {
+            if state.config.admin_identities.contains(identity) {
+                let admin = Admin::Service(identity.clone());
+                audit::admin(admin.clone());
+                return Ok(RequireAdmin(admin));
+            }
+        }
+        let presented = bearer(&parts.headers).ok_or(StatusCode::UNAUTHORIZED)?;
+
+        if let Some(expected) = &state.config.admin_token {
+            if constant_time_eq(presented.as_bytes(), expected.as_bytes()) {
+                audit::admin(Admin::Token);
+                return Ok(RequireAdmin(Admin::Token));
+            }
+        }
+
+        match repo::api_keys::admin_key_owner(&state.pool, presented).await {
+            Ok(Some(id)) => {
+                audit::admin(Admin::User(id));
+                Ok(RequireAdmin(Admin::User(id)))
+            }
+            Ok(None) => Err(StatusCode::FORBIDDEN),
+            Err(e) => {
+                error!("admin key lookup failed: {e}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+// Who a request acts for, see `actor`. Anonymous unless it carries a user's
+// API key or an impersonation token.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Actor {
+    pub user_id: Option<i32>,
+    // the admin acting as `user_id`
+    pub impersonator: Option<String>,
+}
+
+impl Actor {
+    // the acting user, a 401 for anonymous requests
+    pub fn require_user(&self) -> Result<i32, ApiError> {
+        self.user_id.ok_or_else(|| {
+            ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "unauthenticated",
+                "this takes a user's API key",
+            )
+        })
+    }
+
+    // Who something created with `user_id` in its body is by: the acting
+    // user, whom the body may only name again. Admins may name anyone.
+    pub fn author(&self, user_id: Option<i32>, admin: bool) -> Result<Option<i32>, ApiError> {
+        match user_id {
+            Some(id) if !admin && self.user_id != Some(id) => Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "only admins act for another user",
+            )
+            .with("field", "user_id")),
+            Some(id) => Ok(Some(id)),
+            None => Ok(self.user_id),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Actor>().cloned().unwrap_or_default())
+    }
+}
+
+// Resolve API keys, access tokens, impersonation tokens and session cookies
+// into an `Actor` for the handlers. Unknown or expired impersonation tokens are rejected and every
+// impersonated request is logged; unknown API keys leave the request
+// anonymous, admin endpoints judge them on their own.
+pub async fn actor(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let Some(token) = bearer(request.headers()) else {
+        match sessions::resolve(&state, request.headers()).await {
+            Ok(Some(user_id)) => {
+                request.extensions_mut().insert(Actor {
+                    user_id: Some(user_id),
+                    impersonator: None,
+                });
+            }
+            Ok(None) => {}
+            Err(e) => return e.into_response(),
+        }
+        return next.run(request).await;
+    };
+    if token.starts_with(KEY_PREFIX) {
+        match repo::api_keys::key_owner(&state.pool, token).await {
+            Ok(Some(user_id)) => {
+                request.extensions_mut().insert(Actor {
+                    user_id: Some(user_id),
+                    impersonator: None,
+                });
+            }
+            Ok(None) => {}
+            Err(e) => return ApiError::from(e).into_response(),
+        }
+        return next.run(request).await;
+    }
+    if let Some(oidc) = state.oidc.as_ref().filter(|oidc| oidc.issued(token)) {
+        match oidc.authenticate(&state, token).await {
+            Ok(user_id) => {
+                request.extensions_mut().insert(Actor {
+                    user_id: Some(user_id),
+                    impersonator: None,
+                });
+            }
+            Err(e) => return e.into_response(),
+        }
+        return next.run(request).await;
+    }
+    if jwt::looks_like_jwt(token) {
+        let claims = match jwt::verify(&state, token).await {
+            Ok(claims) => claims,
+            Err(e) => return e.into_response(),
+        };
+        let Ok(user_id) = claims.sub.parse() else {
+            return ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "the token's subject isn't a user",
+            )
+            .into_response();
+        };
+        request.extensions_mut().insert(Actor {
+            user_id: Some(user_id),
+            impersonator: None,
+        });
+        return next.run(request).await;
+    }
+    if !token.starts_with(TOKEN_PREFIX) {
+        return next.run(request).await;
+    }
+    let (user_id, impersonator) = match repo::impersonation::resolve(&state.pool, token).await {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            return ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "the impersonation token is unknown or expired",
+            )
+            .into_response()
+        }
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+
+    info!(
+        user_id,
+        impersonator,
+        "impersonated request: {} {}",
+        request.method(),
+        request.uri().path()
+    );
+    request.extensions_mut().insert(Actor {
+        user_id: Some(user_id),
+        impersonator: Some(impersonator),
+    });
+    next.run(request).await
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+// compare without short-circuiting so the token can't be guessed byte by byte
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,144 @@
+// Co-authors of posts.
+//
+// A post's `user_id` is its primary author, changed with `PUT /posts/:id`
+// by an author taking the post over or by admins; others are added and
+// removed as co-authors by the post's authors or by admins. Only authors and
+// admins edit or delete a post, organizations' posts go by the members' roles
+// instead (see `orgs`). Responses list everyone in `authors`.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+
+use crate::audit::{self, Change};
+use crate::auth::{Actor, RequireAdmin};
+use crate::error::{ApiError, OrNotFound};
+use crate::ids::{Key, PathKey};
+use crate::links::Linked;
+use crate::models::{AddAuthor, Post, PostStatus};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::repo;
+use crate::state::AppState;
+
+// only the post's authors and admins may `what`, e.g. change who wrote it
+pub fn require_author(
+    post: &Post,
+    actor: &Actor,
+    admin: Option<RequireAdmin>,
+    what: &str,
+) -> Result<(), ApiError> {
+    if admin.is_some() {
+        return Ok(());
+    }
+    match actor.user_id {
+        Some(id) if post.authors.contains(&id) => Ok(()),
+        Some(_) => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            format!("only the post's authors and admins {what}"),
+        )),
+        None => Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthenticated",
+            "this takes an author's API key or an admin token",
+        )),
+    }
+}
+
+// whether `actor` may read `post`: anyone once it's published (archived
+// posts get their 410, see `archive`), before that (pending, spam or
+// rejected) only its authors and admins
+pub fn can_read(post: &Post, actor: &Actor, admin: bool) -> bool {
+    post.status == PostStatus::Published.as_str()
+        || post.status == PostStatus::Archived.as_str()
+        || admin
+        || actor.user_id.is_some_and(|id| post.authors.contains(&id))
+}
+
+// a 404 for those who may not read `post`, as if it didn't exist
+pub fn require_visible(
+    post: &Post,
+    actor: &Actor,
+    admin: &Option<RequireAdmin>,
+) -> Result<(), ApiError> {
+    if can_read(post, actor, admin.is_some()) {
+        Ok(())
+    } else {
+        Err(ApiError::not_found("post not found"))
+    }
+}
+
+// handler for "POST /posts/:id/authors" rest API endpoint, adding a co-author
+pub async fn add_author(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+    Payload(author): Payload<AddAuthor>,
+) -> Result<Negotiated<Linked<Post>>, ApiError> {
+    let before = state.posts.get(key).await.or_not_found("post")?;
+    require_author(&before, &actor, admin, "change its authors")?;
+    if before.authors.contains(&author.user_id)
+        || !repo::posts::add_author(&state.pool, before.id, author.user_id).await?
+    {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "already_author",
+            format!("user {} already is an author of the post", author.user_id),
+        )
+        .with("field", "user_id"));
+    }
+    let post = state
+        .posts
+        .get(Key::Serial(before.id))
+        .await
+        .or_not_found("post")?;
+    audit::record(
+        Change::new("post.add_author", "post", post.id)
+            .before(&before)
+            .after(&post),
+    );
+
+    Ok(format.respond(Linked::new(post, state.config.id_scheme)))
+}
+
+// handler for "DELETE /posts/:id/authors/:user_id" rest API endpoint,
+// removing a co-author
+pub async fn remove_author(
+    State(state): State<AppState>,
+    Path((segment, user_id)): Path<(String, i32)>,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Linked<Post>>, ApiError> {
+    let key = state
+        .config
+        .id_scheme
+        .parse_key(&segment)
+        .ok_or_else(|| ApiError::not_found("post not found"))?;
+    let before = state.posts.get(key).await.or_not_found("post")?;
+    require_author(&before, &actor, admin, "change its authors")?;
+    if before.user_id == Some(user_id) {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "primary_author",
+            "the primary author is changed with PUT /posts/:id, not removed",
+        )
+        .with("field", "user_id"));
+    }
+    if !repo::posts::remove_author(&state.pool, before.id, user_id).await? {
+        return Err(ApiError::not_found("co-author not found"));
+    }
+    let post = state
+        .posts
+        .get(Key::Serial(before.id))
+        .await
+        .or_not_found("post")?;
+    audit::record(
+        Change::new("post.remove_author", "post", post.id)
+            .before(&before)
+            .after(&post),
+    );
+
+    Ok(format.respond(Linked::new(post, state.config.id_scheme)))
+}
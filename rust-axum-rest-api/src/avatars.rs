@@ -0,0 +1,128 @@
+// Avatars: users without one of their own are shown Gravatar's image for
+// their email. Gravatar is addressed by the SHA-256 of the trimmed,
+// lowercased address, so the address itself isn't in the URL.
+//
+// GRAVATAR_DEFAULT is what Gravatar shows for emails it doesn't know: one of
+// its own images (`identicon` by default, `mp`, `retro`, `robohash`, ...),
+// `404` for none, or the URL of an image. GRAVATAR_RATING is the most
+// explicit rating shown, g, pg, r or x, g by default. GRAVATAR=false leaves
+// users without an avatar.
+
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::models::{PublicUser, User};
+use crate::repo::api_keys::hex;
+use crate::state::AppState;
+
+const BASE_URL: &str = "https://gravatar.com/avatar/";
+
+const DEFAULT_IMAGES: &[&str] = &[
+    "404",
+    "mp",
+    "identicon",
+    "monsterid",
+    "wavatar",
+    "retro",
+    "robohash",
+    "blank",
+];
+
+const RATINGS: &[&str] = &["g", "pg", "r", "x"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gravatar {
+    pub default_image: String,
+    pub rating: String,
+}
+
+impl Default for Gravatar {
+    fn default() -> Self {
+        Gravatar {
+            default_image: String::from("identicon"),
+            rating: String::from("g"),
+        }
+    }
+}
+
+impl Gravatar {
+    // GRAVATAR and friends, see above
+    pub fn from_env() -> Result<Option<Gravatar>, String> {
+        let enabled = match std::env::var("GRAVATAR") {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| format!("invalid GRAVATAR {value:?}, expected true or false"))?,
+            Err(_) => true,
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let mut gravatar = Gravatar::default();
+        if let Ok(image) = std::env::var("GRAVATAR_DEFAULT") {
+            let url = reqwest::Url::parse(&image).ok();
+            let is_url = url.is_some_and(|url| matches!(url.scheme(), "http" | "https"));
+            if !is_url && !DEFAULT_IMAGES.contains(&image.as_str()) {
+                return Err(format!(
+                    "invalid GRAVATAR_DEFAULT {image:?}, expected an image URL or one of {}",
+                    DEFAULT_IMAGES.join(", ")
+                ));
+            }
+            gravatar.default_image = image;
+        }
+        if let Ok(rating) = std::env::var("GRAVATAR_RATING") {
+            let rating = rating.to_ascii_lowercase();
+            if !RATINGS.contains(&rating.as_str()) {
+                return Err(format!(
+                    "invalid GRAVATAR_RATING {rating:?}, expected g, pg, r or x"
+                ));
+            }
+            gravatar.rating = rating;
+        }
+        Ok(Some(gravatar))
+    }
+
+    // the image Gravatar has for `email`
+    pub fn url(&self, email: &str) -> String {
+        let hash = hex(&Sha256::digest(email.trim().to_lowercase().as_bytes()));
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("d", &self.default_image)
+            .append_pair("r", &self.rating)
+            .finish();
+        format!("{BASE_URL}{hash}?{query}")
+    }
+}
+
+// Give a user without an avatar Gravatar's, unless that is turned off.
+pub fn fill(config: &Config, user: &mut User) {
+    if let (None, Some(gravatar)) = (&user.avatar_url, &config.gravatar) {
+        user.avatar_url = Some(gravatar.url(&user.email));
+    }
+}
+
+// The same for the authors inlined into posts, whose emails the posts'
+// queries leave out: those without an avatar are looked up at once.
+pub async fn fill_authors<'a>(
+    state: &AppState,
+    authors: impl IntoIterator<Item = &'a mut PublicUser>,
+) -> Result<(), sqlx::Error> {
+    let Some(gravatar) = &state.config.gravatar else {
+        return Ok(());
+    };
+    let mut missing: Vec<_> = authors
+        .into_iter()
+        .filter(|author| author.avatar_url.is_none())
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i32> = missing.iter().map(|author| author.id).collect();
+    let emails: HashMap<i32, String> = state.users.emails(&ids).await?.into_iter().collect();
+    for author in &mut missing {
+        if let Some(email) = emails.get(&author.id) {
+            author.avatar_url = Some(gravatar.url(email));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,462 @@
+// Billing: users and organizations subscribe to paid plans through Stripe,
+// which tells us about their subscriptions at `POST /billing/webhook`. Each
+// plan has its limits (see `limits`), enforced on the posts a user or an
+// organization owns, on the rate of a user's requests and on what they use
+// in a billing period (see `usage`); without a paid subscription one is on
+// the free plan.
+//
+// Checkout happens on Stripe: its subscriptions carry the user or the
+// organization they are for in their metadata (`user_id` or `org_id`), and
+// their price tells the plan, STRIPE_PRICES mapping price ids to plans:
+//
+//     STRIPE_PRICES=price_1Pro...=pro,price_1Biz...=business
+//
+// Webhook requests are signed with STRIPE_WEBHOOK_SECRET in their
+// Stripe-Signature header and rejected unless the signature is valid and
+// recent. Without the secret billing is off: the webhook is a 404 and
+// nothing is limited.
+
+use std::collections::HashMap;
+
+use axum::body::Bytes;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::{info, warn};
+
+use crate::audit::{self, Change};
+use crate::auth::{constant_time_eq, Actor};
+use crate::error::ApiError;
+use crate::models::Plan;
+use crate::repo;
+use crate::repo::api_keys::hex;
+use crate::repo::subscriptions::SubscriptionSync;
+use crate::state::AppState;
+use crate::usage::{self, Metric, Owner};
+
+pub const SIGNATURE_HEADER: &str = "stripe-signature";
+
+// how old a signed webhook request may be, in seconds, against replays
+pub const TOLERANCE: i64 = 300;
+
+// Stripe settings, see `billing`
+#[derive(Clone, Debug)]
+pub struct StripeConfig {
+    // STRIPE_WEBHOOK_SECRET, the `whsec_...` signing secret of the endpoint
+    pub webhook_secret: String,
+    // STRIPE_PRICES, the plan of each price id
+    pub prices: HashMap<String, Plan>,
+}
+
+impl StripeConfig {
+    pub fn from_env() -> Result<Option<StripeConfig>, String> {
+        let Some(webhook_secret) = std::env::var("STRIPE_WEBHOOK_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty())
+        else {
+            return Ok(None);
+        };
+        let mut prices = HashMap::new();
+        for entry in std::env::var("STRIPE_PRICES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (price, plan) = entry.split_once('=').ok_or_else(|| {
+                format!("invalid STRIPE_PRICES entry {entry:?}, expected price=plan")
+            })?;
+            prices.insert(price.trim().to_owned(), plan.trim().parse()?);
+        }
+        Ok(Some(StripeConfig {
+            webhook_secret,
+            prices,
+        }))
+    }
+}
+
+// What a plan allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    // posts a user, or an organization, may have; None for no limit
+    pub max_posts: Option<i64>,
+    // requests a user may make a minute
+    pub requests_per_minute: u32,
+    // quotas of a billing period, None for no limit (see `usage`)
+    pub api_calls_per_period: Option<i64>,
+    pub posts_per_period: Option<i64>,
+    pub comments_per_period: Option<i64>,
+}
+
+impl Limits {
+    pub fn quota(&self, metric: Metric) -> Option<i64> {
+        match metric {
+            Metric::ApiCalls => self.api_calls_per_period,
+            Metric::Posts => self.posts_per_period,
+            Metric::Comments => self.comments_per_period,
+        }
+    }
+}
+
+pub fn limits(plan: Plan) -> Limits {
+    match plan {
+        Plan::Free => Limits {
+            max_posts: Some(100),
+            requests_per_minute: 60,
+            api_calls_per_period: Some(10_000),
+            posts_per_period: Some(50),
+            comments_per_period: Some(500),
+        },
+        Plan::Pro => Limits {
+            max_posts: Some(10_000),
+            requests_per_minute: 600,
+            api_calls_per_period: Some(1_000_000),
+            posts_per_period: Some(5_000),
+            comments_per_period: Some(50_000),
+        },
+        Plan::Business => Limits {
+            max_posts: None,
+            requests_per_minute: 3000,
+            api_calls_per_period: None,
+            posts_per_period: None,
+            comments_per_period: None,
+        },
+    }
+}
+
+// The plan of a user, the best of their subscriptions in good standing.
+pub async fn user_plan(state: &AppState, user_id: i32) -> Result<Plan, sqlx::Error> {
+    let plans = repo::subscriptions::user_plans(&state.pool, user_id).await?;
+    Ok(best(&plans))
+}
+
+// The plan of an organization, like `user_plan`.
+pub async fn org_plan(state: &AppState, org_id: i32) -> Result<Plan, sqlx::Error> {
+    let plans = repo::subscriptions::org_plans(&state.pool, org_id).await?;
+    Ok(best(&plans))
+}
+
+fn best(plans: &[String]) -> Plan {
+    plans
+        .iter()
+        .map(|plan| Plan::from_column(plan))
+        .max()
+        .unwrap_or_default()
+}
+
+// Refuse a new post of a user, or of an organization, already having the
+// posts their plan allows.
+pub async fn check_post_limit(
+    state: &AppState,
+    user_id: Option<i32>,
+    org_id: Option<i32>,
+) -> Result<(), ApiError> {
+    if state.config.stripe.is_none() {
+        return Ok(());
+    }
+    let (plan, posts) = match (org_id, user_id) {
+        (Some(org_id), _) => (
+            org_plan(state, org_id).await?,
+            repo::subscriptions::org_post_count(&state.pool, org_id).await?,
+        ),
+        (None, Some(user_id)) => (
+            user_plan(state, user_id).await?,
+            repo::subscriptions::user_post_count(&state.pool, user_id).await?,
+        ),
+        (None, None) => return Ok(()),
+    };
+    match limits(plan).max_posts {
+        Some(max) if posts >= max => Err(ApiError::new(
+            StatusCode::PAYMENT_REQUIRED,
+            "plan_limit",
+            format!(
+                "the {} plan allows {max} posts, upgrade for more",
+                plan.as_str()
+            ),
+        )
+        .with("plan", plan)
+        .with("limit", max)),
+        _ => Ok(()),
+    }
+}
+
+// Middleware metering the API calls of users (see `usage`) and turning away
+// the ones over their plan's rate or quota, with a 429 telling when to
+// retry. Anonymous requests pass.
+pub async fn enforce_limits(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let user_id = request
+        .extensions()
+        .get::<Actor>()
+        .and_then(|actor| actor.user_id);
+    let Some(user_id) = user_id else {
+        return next.run(request).await;
+    };
+    let owner = Owner::User(user_id);
+    if state.config.stripe.is_none() {
+        state.usage.record(owner, Metric::ApiCalls, 1);
+        return next.run(request).await;
+    }
+    let plan = match user_plan(&state, user_id).await {
+        Ok(plan) => plan,
+        Err(e) => return ApiError::from(e).into_response(),
+    };
+    if let Err(limited) = state
+        .plan_limiter
+        .check_against(user_id, limits(plan).requests_per_minute)
+    {
+        return limited.into_response();
+    }
+    let warning = match usage::check(&state, owner, plan, Metric::ApiCalls).await {
+        Ok(warning) => warning,
+        Err(e) => return usage::reject(e),
+    };
+    state.usage.record(owner, Metric::ApiCalls, 1);
+
+    let mut response = next.run(request).await;
+    if let Some(value) = warning.and_then(|warning| HeaderValue::try_from(warning).ok()) {
+        response.headers_mut().insert(usage::WARNING_HEADER, value);
+    }
+    response
+}
+
+// Whether `header`, a Stripe-Signature, signs `body` with `secret` within
+// `TOLERANCE` of `now`, in Unix seconds. Any of its v1 signatures may match,
+// Stripe sends several while a secret is rolled.
+pub fn verify(secret: &str, header: &str, body: &[u8], now: i64) -> bool {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for (key, value) in header.split(',').filter_map(|part| part.split_once('=')) {
+        match key.trim() {
+            "t" => timestamp = value.parse::<i64>().ok(),
+            "v1" => signatures.push(value),
+            _ => {}
+        }
+    }
+    let Some(timestamp) = timestamp.filter(|t| now.abs_diff(*t) <= TOLERANCE.unsigned_abs()) else {
+        return false;
+    };
+    let expected = signature(secret, timestamp, body);
+    signatures
+        .iter()
+        .any(|signature| constant_time_eq(signature.as_bytes(), expected.as_bytes()))
+}
+
+// The v1 signature of `body` sent at `timestamp`.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex(&mac.finalize().into_bytes())
+}
+
+// The parts of a Stripe event we look at.
+#[derive(Deserialize)]
+struct StripeEvent {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    data: StripeEventData,
+}
+
+#[derive(Deserialize)]
+struct StripeEventData {
+    object: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct StripeSubscription {
+    id: String,
+    customer: String,
+    status: String,
+    current_period_end: Option<i64>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    items: StripeList<StripeItem>,
+}
+
+#[derive(Deserialize)]
+struct StripeList<T> {
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct StripeItem {
+    price: StripePrice,
+}
+
+#[derive(Deserialize)]
+struct StripePrice {
+    id: String,
+}
+
+// handler for "POST /billing/webhook" rest API endpoint, called by Stripe
+pub async fn stripe_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let stripe = state
+        .config
+        .stripe
+        .as_ref()
+        .ok_or_else(|| ApiError::not_found("billing is off, it takes STRIPE_WEBHOOK_SECRET"))?;
+    let signed = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|header| {
+            verify(
+                &stripe.webhook_secret,
+                header,
+                &body,
+                Utc::now().timestamp(),
+            )
+        });
+    if !signed {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_signature",
+            "the Stripe-Signature is missing, invalid or too old",
+        ));
+    }
+    let event: StripeEvent = serde_json::from_slice(&body).map_err(|e| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_event",
+            format!("not a Stripe event: {e}"),
+        )
+    })?;
+    if !matches!(
+        event.kind.as_str(),
+        "customer.subscription.created"
+            | "customer.subscription.updated"
+            | "customer.subscription.deleted"
+    ) {
+        // acknowledged, or Stripe would keep sending them
+        return Ok(StatusCode::OK);
+    }
+    let subscription: StripeSubscription =
+        serde_json::from_value(event.data.object).map_err(|e| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_event",
+                format!("not a Stripe subscription: {e}"),
+            )
+        })?;
+
+    let owner_id = |key: &str| {
+        subscription
+            .metadata
+            .get(key)
+            .and_then(|id| id.parse::<i32>().ok())
+    };
+    let (user_id, org_id) = (owner_id("user_id"), owner_id("org_id"));
+    if user_id.is_some() == org_id.is_some() {
+        warn!(
+            event = event.id,
+            "Stripe subscription {} is for neither a user nor an organization", subscription.id
+        );
+        return Ok(StatusCode::OK);
+    }
+    let Some(plan) = subscription
+        .items
+        .data
+        .iter()
+        .find_map(|item| stripe.prices.get(&item.price.id))
+    else {
+        warn!(
+            event = event.id,
+            "Stripe subscription {} has none of the STRIPE_PRICES", subscription.id
+        );
+        return Ok(StatusCode::OK);
+    };
+    let sync = SubscriptionSync {
+        stripe_subscription_id: subscription.id,
+        stripe_customer_id: subscription.customer,
+        user_id,
+        org_id,
+        plan: *plan,
+        status: subscription.status,
+        current_period_end: subscription
+            .current_period_end
+            .and_then(|end| DateTime::from_timestamp(end, 0)),
+    };
+    match repo::subscriptions::sync(&state.pool, &sync).await? {
+        Some(subscription) => {
+            info!(
+                event = event.id,
+                "Stripe subscription {} is {} on the {} plan",
+                subscription.stripe_subscription_id,
+                subscription.status,
+                subscription.plan
+            );
+            audit::record(
+                Change::new(
+                    "subscription.sync",
+                    "subscription",
+                    &subscription.stripe_subscription_id,
+                )
+                .after(&subscription),
+            );
+        }
+        None => warn!(
+            event = event.id,
+            "the owner of Stripe subscription {} doesn't exist", sync.stripe_subscription_id
+        ),
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn signatures_verify_within_the_tolerance() {
+        let body = br#"{"type":"customer.subscription.updated"}"#;
+        let header = format!("t={NOW},v1={}", signature("whsec", NOW, body));
+        assert!(verify("whsec", &header, body, NOW));
+        assert!(verify("whsec", &header, body, NOW + TOLERANCE));
+        assert!(!verify("whsec", &header, body, NOW + TOLERANCE + 1));
+        assert!(!verify("other", &header, body, NOW));
+        assert!(!verify("whsec", &header, b"{}", NOW));
+    }
+
+    #[test]
+    fn any_v1_signature_may_match() {
+        let body = b"{}";
+        let rolled = format!(
+            "t={NOW},v1={},v0=ignored,v1={}",
+            signature("old", NOW, body),
+            signature("new", NOW, body)
+        );
+        assert!(verify("new", &rolled, body, NOW));
+        assert!(verify("old", &rolled, body, NOW));
+        let untimed = format!("v1={}", signature("new", NOW, body));
+        assert!(!verify("new", &untimed, body, NOW));
+        assert!(!verify("new", "", body, NOW));
+    }
+
+    #[test]
+    fn extreme_timestamps_are_refused() {
+        let body = b"{}";
+        for t in [i64::MIN, i64::MAX] {
+            let header = format!("t={t},v1={}", signature("whsec", t, body));
+            assert!(!verify("whsec", &header, body, NOW));
+            assert!(!verify("whsec", &header, body, -NOW));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// camelCase JSON for JS-centric consumers.
+//
+// The models are declared, and deserialized, with snake_case fields. With
+// JSON_CASE=camel, or per request with an `X-Json-Case: camel|snake` header,
+// the keys of JSON request bodies and query parameters are converted to
+// snake_case on the way in and those of JSON responses to camelCase on the
+// way out, so every endpoint speaks the same case without each model
+// needing a second serde representation. Keys with a leading underscore
+// (`_links`) keep it, values are never touched.
+//
+// Like the envelope, successful responses are rewritten by `rewrite` right
+// around the handlers and errors by `ApiError` itself.
+
+use std::str::FromStr;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::uri::PathAndQuery;
+use axum::http::{HeaderMap, HeaderName, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::{Map, Value};
+
+use crate::error::ApiError;
+use crate::state::AppState;
+
+pub const X_JSON_CASE: HeaderName = HeaderName::from_static("x-json-case");
+
+// the largest JSON body rewritten, the same as axum's default body limit
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyCase {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl FromStr for KeyCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "snake" => Ok(KeyCase::Snake),
+            "camel" => Ok(KeyCase::Camel),
+            _ => Err(format!("invalid JSON_CASE {s:?}, expected snake or camel")),
+        }
+    }
+}
+
+tokio::task_local! {
+    static CASE: KeyCase;
+}
+
+// the key case of the response to the request being handled
+pub fn current() -> KeyCase {
+    CASE.try_with(|case| *case).unwrap_or_default()
+}
+
+// decide on the key case for the whole request and bring camelCase request
+// keys back to snake_case
+pub async fn scope(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let requested = request
+        .headers()
+        .get(X_JSON_CASE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let case = requested.unwrap_or(state.config.json_case);
+    CASE.scope(case, async move {
+        if case == KeyCase::Snake {
+            return next.run(request).await;
+        }
+        match snake_case_request(request).await {
+            Ok(request) => next.run(request).await,
+            Err(error) => error.into_response(),
+        }
+    })
+    .await
+}
+
+// camelCase successful JSON responses
+pub async fn rewrite(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if current() == KeyCase::Snake
+        || !response.status().is_success()
+        || !is_json(response.headers())
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(apply(value).to_string()))
+}
+
+// convert the keys of a response body to the current case
+pub fn apply(value: Value) -> Value {
+    match current() {
+        KeyCase::Snake => value,
+        KeyCase::Camel => rename_keys(value, &to_camel),
+    }
+}
+
+async fn snake_case_request(request: Request) -> Result<Request, ApiError> {
+    let (mut parts, body) = request.into_parts();
+    if let Some(query) = parts.uri.query() {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(
+                form_urlencoded::parse(query.as_bytes())
+                    .map(|(key, value)| (to_snake(&key), value)),
+            )
+            .finish();
+        let path_and_query = format!("{}?{query}", parts.uri.path());
+        let mut uri = parts.uri.clone().into_parts();
+        uri.path_and_query = PathAndQuery::from_str(&path_and_query).ok();
+        parts.uri = Uri::from_parts(uri).unwrap_or(parts.uri);
+    }
+    if !is_json(&parts.headers) {
+        return Ok(Request::from_parts(parts, body));
+    }
+
+    let bytes = to_bytes(body, BODY_LIMIT).await.map_err(|_| {
+        ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            "the request body is too large",
+        )
+    })?;
+    // malformed bodies are left for the extractors to report
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok(Request::from_parts(parts, Body::from(bytes)));
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    let body = rename_keys(value, &to_snake).to_string();
+    Ok(Request::from_parts(parts, Body::from(body)))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+fn rename_keys(value: Value, rename: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (rename(&key), rename_keys(value, rename)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| rename_keys(item, rename))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+// created_at -> createdAt, _links stays _links
+fn to_camel(key: &str) -> String {
+    let rest = key.trim_start_matches('_');
+    let mut camel = key[..key.len() - rest.len()].to_owned();
+    let mut upper = false;
+    for c in rest.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+// createdAt -> created_at
+fn to_snake(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_uppercase() {
+            snake.push('_');
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
@@ -0,0 +1,140 @@
+// The client's address, as seen through reverse proxies.
+//
+// Requests from the proxies listed in TRUSTED_PROXIES (comma separated CIDRs
+// or addresses) are taken to be on behalf of the client their Forwarded
+// header names, or else their X-Forwarded-For header: the rightmost address
+// that isn't itself a trusted proxy, since every proxy appends the address it
+// got the request from and only what ours appended can be believed. Anyone
+// else is their own client, whatever their headers claim.
+//
+// `resolve` works the client out once per request for everything after it:
+// the IP rules (see `ip_filter`), rate limiting, spam checks, the audit log,
+// and the access log it writes, a line per request under the `access` target.
+// It locates the client as well, see `geoip`.
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts, Request, State};
+use axum::http::header::FORWARDED;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName};
+use axum::middleware::Next;
+use axum::response::Response;
+use ipnet::IpNet;
+use tracing::info;
+
+use crate::request_id;
+use crate::state::AppState;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+// The address of the client a request is from, if known.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    // the client of a request from `peer` with `headers`, trusting the
+    // proxies in `trusted`
+    pub fn resolve(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNet]) -> ClientIp {
+        let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+        let Some(mut client) = peer.map(|ip| ip.to_canonical()) else {
+            return ClientIp(None);
+        };
+        if !is_trusted(&client) {
+            return ClientIp(Some(client));
+        }
+        for hop in forwarded_for(headers).into_iter().rev() {
+            // an obfuscated or garbled hop, the last proxy is all we know
+            let Some(hop) = hop else {
+                break;
+            };
+            client = hop.to_canonical();
+            if !is_trusted(&client) {
+                break;
+            }
+        }
+        ClientIp(Some(client))
+    }
+}
+
+// The addresses a request was forwarded for, the original client first, from
+// the Forwarded header or else X-Forwarded-For; None for those that aren't
+// addresses.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+    };
+    let forwarded: Vec<_> = values(FORWARDED)
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for").then(|| parse(value))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    values(X_FORWARDED_FOR).map(parse).collect()
+}
+
+// an address as proxies write it: maybe quoted, with a port, IPv6 in brackets
+fn parse(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| value.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(client) = parts.extensions.get::<ClientIp>() {
+            return Ok(*client);
+        }
+        // not through `resolve`, only the peer is known
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>();
+        Ok(ClientIp(peer.map(|ConnectInfo(addr)| addr.ip())))
+    }
+}
+
+// resolve the client of a request, and log the request once answered
+pub async fn resolve(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = ClientIp::resolve(peer, request.headers(), &state.config.trusted_proxies);
+    let location = state.geoip.locate(client.0);
+    request.extensions_mut().insert(client);
+    request.extensions_mut().insert(location.clone());
+
+    let started = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let response = next.run(request).await;
+    info!(
+        target: "access",
+        client = ?client.0,
+        country = location.country,
+        region = location.region,
+        request_id = request_id::current().as_deref(),
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "{method} {path} {}",
+        response.status().as_u16()
+    );
+    response
+}
@@ -0,0 +1,278 @@
+// `app backup` and `app restore`: a logical dump of the core tables for
+// environments without pg_dump.
+//
+// The archive is gzip compressed JSON lines, one `{"table": ..., "row": ...}`
+// record per row, users before posts so a restore never sees a post whose
+// author doesn't exist yet. Both directions stream, nothing is held in memory
+// beyond one batch of rows. Tenants and organizations aren't part of the
+// archive and co-authors aren't restored, restored rows belong to the
+// default tenant and posts to no organization, with only their primary
+// author. Emails are dumped as addresses, opened, and sealed again on restore
+// with whatever key is set then (see `repo::emails`).
+
+use std::error::Error;
+use std::path::Path;
+
+use async_compression::tokio::bufread::GzipDecoder;
+use async_compression::tokio::write::GzipEncoder;
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use rust_axum_rest_api::models::{Post, User};
+use rust_axum_rest_api::repo::emails;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// rows inserted per statement on restore
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "table", content = "row", rename_all = "lowercase")]
+enum Record {
+    Users(User),
+    Posts(Post),
+}
+
+pub async fn backup(pool: &Pool<Postgres>, out: &Path) -> Result<(), BoxError> {
+    let mut archive = GzipEncoder::new(BufWriter::new(File::create(out).await?));
+
+    let mut users = sqlx::query_as!(
+        User,
+        "SELECT id, uuid, username, email, avatar_url, posts_count, created_at, updated_at FROM users ORDER BY id"
+    )
+    .fetch(pool);
+    let mut user_count = 0;
+    while let Some(user) = users.try_next().await? {
+        let user = emails::open_user(user)?;
+        write_record(&mut archive, &Record::Users(user)).await?;
+        user_count += 1;
+    }
+
+    let mut posts = sqlx::query_as!(
+        Post,
+        r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at FROM posts WHERE deleted_at IS NULL ORDER BY id"#
+    )
+    .fetch(pool);
+    let mut post_count = 0;
+    while let Some(post) = posts.try_next().await? {
+        write_record(&mut archive, &Record::Posts(post)).await?;
+        post_count += 1;
+    }
+
+    archive.shutdown().await?;
+    info!(
+        "backed up {user_count} users and {post_count} posts to {}",
+        out.display()
+    );
+    Ok(())
+}
+
+async fn write_record<W: AsyncWrite + Unpin>(
+    archive: &mut W,
+    record: &Record,
+) -> Result<(), BoxError> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    archive.write_all(&line).await?;
+    Ok(())
+}
+
+// Load an archive written by `backup`. The tables must be empty unless
+// `truncate` is set, in which case they are wiped first. Truncating users and
+// posts empties every table referring to them too (API keys, comments,
+// follows and so on, see `dependents`), so when any of those has rows the
+// restore is refused, listing them, unless `force` is set as well.
+// Everything happens in a single transaction, a failed restore leaves the
+// database untouched.
+pub async fn restore(
+    pool: &Pool<Postgres>,
+    file: &Path,
+    truncate: bool,
+    force: bool,
+) -> Result<(), BoxError> {
+    let mut lines =
+        BufReader::new(GzipDecoder::new(BufReader::new(File::open(file).await?))).lines();
+    let mut tx = pool.begin().await?;
+
+    if truncate {
+        let mut lost = Vec::new();
+        for table in dependents(&mut tx).await? {
+            let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+                .fetch_one(&mut *tx)
+                .await?;
+            if rows > 0 {
+                lost.push(format!("{table} ({rows} rows)"));
+            }
+        }
+        if !lost.is_empty() && !force {
+            return Err(format!(
+                "truncating users and posts would also empty {}, pass --force to go ahead",
+                lost.join(", ")
+            )
+            .into());
+        }
+        if !lost.is_empty() {
+            warn!(
+                "truncating users and posts, and with them {}",
+                lost.join(", ")
+            );
+        }
+        sqlx::query!("TRUNCATE posts, users RESTART IDENTITY CASCADE")
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        let existing = sqlx::query_scalar!(
+            r#"SELECT (SELECT COUNT(*) FROM users) + (SELECT COUNT(*) FROM posts) AS "count!""#
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if existing > 0 {
+            return Err("users and posts must be empty, pass --truncate to replace them".into());
+        }
+    }
+
+    let mut users = Vec::with_capacity(BATCH_SIZE);
+    let mut posts = Vec::with_capacity(BATCH_SIZE);
+    let (mut user_count, mut post_count) = (0, 0);
+    let mut line_number = 0;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        let record: Record =
+            serde_json::from_str(&line).map_err(|e| format!("line {line_number}: {e}"))?;
+        match record {
+            Record::Users(user) => {
+                users.push(user);
+                user_count += 1;
+            }
+            Record::Posts(post) => {
+                // authors must be in place before their posts
+                insert_users(&mut tx, &mut users).await?;
+                posts.push(post);
+                post_count += 1;
+            }
+        }
+        if users.len() == BATCH_SIZE {
+            insert_users(&mut tx, &mut users).await?;
+        }
+        if posts.len() == BATCH_SIZE {
+            insert_posts(&mut tx, &mut posts).await?;
+        }
+    }
+    insert_users(&mut tx, &mut users).await?;
+    insert_posts(&mut tx, &mut posts).await?;
+
+    // ids were restored verbatim, move the sequences past them
+    sqlx::query!(
+        "SELECT setval(pg_get_serial_sequence('users', 'id'), GREATEST((SELECT MAX(id) FROM users), 1))"
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "SELECT setval(pg_get_serial_sequence('posts', 'id'), GREATEST((SELECT MAX(id) FROM posts), 1))"
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    info!(
+        "restored {user_count} users and {post_count} posts from {}",
+        file.display()
+    );
+    Ok(())
+}
+
+// The tables `TRUNCATE ... CASCADE` empties along with users and posts: those
+// with a foreign key to them, or to one of the others, quoted as needed.
+async fn dependents(tx: &mut Transaction<'_, Postgres>) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"WITH RECURSIVE dependents (oid) AS (
+               SELECT conrelid FROM pg_constraint
+               WHERE contype = 'f' AND confrelid IN ('users'::regclass, 'posts'::regclass)
+               UNION
+               SELECT c.conrelid FROM pg_constraint c JOIN dependents d ON c.confrelid = d.oid
+               WHERE c.contype = 'f'
+           )
+           SELECT oid::regclass::text AS "table!" FROM dependents
+           WHERE oid NOT IN ('users'::regclass, 'posts'::regclass)
+           ORDER BY 1"#
+    )
+    .fetch_all(&mut **tx)
+    .await
+}
+
+async fn insert_users(
+    tx: &mut Transaction<'_, Postgres>,
+    users: &mut Vec<User>,
+) -> Result<(), sqlx::Error> {
+    if users.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i32> = users.iter().map(|user| user.id).collect();
+    let uuids: Vec<Uuid> = users.iter().map(|user| user.uuid).collect();
+    let usernames: Vec<String> = users.iter().map(|user| user.username.clone()).collect();
+    let (stored, hashes): (Vec<String>, Vec<Option<String>>) = users
+        .iter()
+        .map(|user| {
+            let sealed = emails::seal(&user.email);
+            (sealed.stored, sealed.hash)
+        })
+        .unzip();
+    let avatars: Vec<Option<String>> = users.iter().map(|user| user.avatar_url.clone()).collect();
+    let created: Vec<DateTime<Utc>> = users.iter().map(|user| user.created_at).collect();
+    let updated: Vec<DateTime<Utc>> = users.iter().map(|user| user.updated_at).collect();
+    sqlx::query!(
+        "INSERT INTO users (id, uuid, username, email, email_hash, avatar_url, created_at, updated_at) SELECT * FROM UNNEST($1::int4[], $2::uuid[], $3::text[], $4::text[], $5::text[], $6::text[], $7::timestamptz[], $8::timestamptz[])",
+        &ids,
+        &uuids,
+        &usernames,
+        &stored,
+        &hashes as &[Option<String>],
+        &avatars as &[Option<String>],
+        &created,
+        &updated
+    )
+    .execute(&mut **tx)
+    .await?;
+    users.clear();
+    Ok(())
+}
+
+async fn insert_posts(
+    tx: &mut Transaction<'_, Postgres>,
+    posts: &mut Vec<Post>,
+) -> Result<(), sqlx::Error> {
+    if posts.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+    let uuids: Vec<Uuid> = posts.iter().map(|post| post.uuid).collect();
+    let public_ids: Vec<String> = posts.iter().map(|post| post.public_id.clone()).collect();
+    let user_ids: Vec<Option<i32>> = posts.iter().map(|post| post.user_id).collect();
+    let titles: Vec<String> = posts.iter().map(|post| post.title.clone()).collect();
+    let bodies: Vec<String> = posts.iter().map(|post| post.body.clone()).collect();
+    let statuses: Vec<String> = posts.iter().map(|post| post.status.clone()).collect();
+    let created: Vec<DateTime<Utc>> = posts.iter().map(|post| post.created_at).collect();
+    let updated: Vec<DateTime<Utc>> = posts.iter().map(|post| post.updated_at).collect();
+    sqlx::query!(
+        "INSERT INTO posts (id, uuid, public_id, user_id, title, body, status, created_at, updated_at) SELECT * FROM UNNEST($1::int4[], $2::uuid[], $3::text[], $4::int4[], $5::text[], $6::text[], $7::text[], $8::timestamptz[], $9::timestamptz[])",
+        &ids,
+        &uuids,
+        &public_ids,
+        &user_ids as &[Option<i32>],
+        &titles,
+        &bodies,
+        &statuses,
+        &created,
+        &updated
+    )
+    .execute(&mut **tx)
+    .await?;
+    posts.clear();
+    Ok(())
+}
@@ -0,0 +1,30 @@
+// `app encrypt-emails`: seal the users' and invitations' emails written
+// before EMAIL_ENCRYPTION_KEY was set (see `repo::emails`), a batch per
+// transaction, so it can run next to the server and pick up where it left
+// off after a failure.
+
+use std::error::Error;
+
+use sqlx::{Pool, Postgres};
+use tracing::info;
+
+use rust_axum_rest_api::repo::emails;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+pub async fn run(pool: &Pool<Postgres>, batch_size: usize) -> Result<(), BoxError> {
+    if !emails::enabled() {
+        return Err("EMAIL_ENCRYPTION_KEY isn't set, there is nothing to seal with".into());
+    }
+    let mut sealed = 0;
+    loop {
+        let batch = emails::seal_clear(pool, batch_size as i64).await?;
+        sealed += batch;
+        if batch < batch_size as u64 {
+            break;
+        }
+        info!("sealed {sealed} emails so far");
+    }
+    info!("sealed {sealed} emails, none are left in the clear");
+    Ok(())
+}
@@ -0,0 +1,202 @@
+// `app import`: load posts from an NDJSON file such as the one served by
+// GET /posts/export.ndjson. The file is read line by line and written in
+// batches, each batch in its own transaction, so arbitrarily large files can
+// be imported and a failure only loses the batch it happened in.
+
+use std::error::Error;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use sqlx::{Acquire, Pool, Postgres, Transaction};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{info, warn};
+
+use rust_axum_rest_api::models::CreatePost;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// What to do with a record whose id already exists.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum OnDuplicate {
+    /// Keep the existing post
+    Skip,
+    /// Overwrite the existing post with the imported one
+    Update,
+    /// Count the record as failed
+    Fail,
+}
+
+// A line of the import file: the API's create payload plus the optional id
+// the post had in the database it was exported from.
+#[derive(Deserialize)]
+struct ImportedPost {
+    id: Option<i32>,
+    #[serde(flatten)]
+    post: CreatePost,
+}
+
+#[derive(Default)]
+struct Summary {
+    inserted: u64,
+    updated: u64,
+    skipped: u64,
+    invalid: u64,
+    failed: u64,
+}
+
+enum Outcome {
+    Inserted,
+    Updated,
+    Skipped,
+    Duplicate,
+}
+
+pub async fn run(
+    pool: &Pool<Postgres>,
+    file: &Path,
+    batch_size: usize,
+    on_duplicate: OnDuplicate,
+) -> Result<(), BoxError> {
+    let mut lines = BufReader::new(File::open(file).await?).lines();
+    let mut summary = Summary::default();
+    let mut batch: Vec<(usize, ImportedPost)> = Vec::with_capacity(batch_size);
+    let mut line_number = 0;
+
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse(&line) {
+            Ok(record) => batch.push((line_number, record)),
+            Err(reason) => {
+                warn!("line {line_number}: {reason}");
+                summary.invalid += 1;
+            }
+        }
+        if batch.len() >= batch_size {
+            import_batch(pool, &mut batch, on_duplicate, &mut summary).await?;
+            info!("processed {line_number} lines");
+        }
+    }
+    import_batch(pool, &mut batch, on_duplicate, &mut summary).await?;
+
+    // explicit ids bypass the serial sequence, move it past the largest id
+    sqlx::query!(
+        "SELECT setval(pg_get_serial_sequence('posts', 'id'), GREATEST((SELECT MAX(id) FROM posts), 1))"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    info!(
+        "import of {} finished: {line_number} lines, {} inserted, {} updated, {} skipped, {} invalid, {} failed",
+        file.display(),
+        summary.inserted,
+        summary.updated,
+        summary.skipped,
+        summary.invalid,
+        summary.failed
+    );
+    Ok(())
+}
+
+fn parse(line: &str) -> Result<ImportedPost, String> {
+    let record: ImportedPost = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    if record.post.title.trim().is_empty() {
+        return Err(String::from("title must not be empty"));
+    }
+    if record.post.body.trim().is_empty() {
+        return Err(String::from("body must not be empty"));
+    }
+    Ok(record)
+}
+
+async fn import_batch(
+    pool: &Pool<Postgres>,
+    batch: &mut Vec<(usize, ImportedPost)>,
+    on_duplicate: OnDuplicate,
+    summary: &mut Summary,
+) -> Result<(), BoxError> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let mut tx = pool.begin().await?;
+    for (line_number, record) in batch.drain(..) {
+        // every record gets a savepoint so a rejected row (e.g. an unknown
+        // user_id) doesn't abort the rest of the batch
+        let mut savepoint = Acquire::begin(&mut *tx).await?;
+        match insert(&mut savepoint, &record, on_duplicate).await {
+            Ok(outcome) => {
+                savepoint.commit().await?;
+                match outcome {
+                    Outcome::Inserted => summary.inserted += 1,
+                    Outcome::Updated => summary.updated += 1,
+                    Outcome::Skipped => summary.skipped += 1,
+                    Outcome::Duplicate => {
+                        warn!("line {line_number}: post {:?} already exists", record.id);
+                        summary.failed += 1;
+                    }
+                }
+            }
+            Err(e) => {
+                savepoint.rollback().await?;
+                warn!("line {line_number}: {e}");
+                summary.failed += 1;
+            }
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn insert(
+    tx: &mut Transaction<'_, Postgres>,
+    record: &ImportedPost,
+    on_duplicate: OnDuplicate,
+) -> Result<Outcome, sqlx::Error> {
+    let post = &record.post;
+    let Some(id) = record.id else {
+        sqlx::query!(
+            "INSERT INTO posts (user_id, title, body) VALUES ($1, $2, $3)",
+            post.user_id,
+            post.title,
+            post.body
+        )
+        .execute(&mut **tx)
+        .await?;
+        return Ok(Outcome::Inserted);
+    };
+
+    let inserted = sqlx::query!(
+        "INSERT INTO posts (id, user_id, title, body) VALUES ($1, $2, $3, $4) ON CONFLICT (id) DO NOTHING",
+        id,
+        post.user_id,
+        post.title,
+        post.body
+    )
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+    if inserted == 1 {
+        return Ok(Outcome::Inserted);
+    }
+
+    match on_duplicate {
+        OnDuplicate::Skip => Ok(Outcome::Skipped),
+        OnDuplicate::Fail => Ok(Outcome::Duplicate),
+        OnDuplicate::Update => {
+            sqlx::query!(
+                "UPDATE posts SET user_id = $1, title = $2, body = $3, updated_at = NOW() WHERE id = $4",
+                post.user_id,
+                post.title,
+                post.body,
+                id
+            )
+            .execute(&mut **tx)
+            .await?;
+            Ok(Outcome::Updated)
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Command line interface. Without a subcommand the binary runs the HTTP
+// server, the other subcommands are one-off maintenance tasks that share the
+// database connection and models with the API.
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+pub mod backup;
+pub mod encrypt_emails;
+pub mod import;
+pub mod parquet;
+pub mod seed;
+
+#[derive(Parser)]
+#[command(name = "app", about = "Posts and users REST API")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (the default)
+    Serve,
+    /// Write posts and users to Parquet files partitioned by creation date
+    ExportParquet {
+        /// Directory the `posts/` and `users/` datasets are written to
+        #[arg(long, default_value = "export")]
+        out: PathBuf,
+    },
+    /// Import posts from an NDJSON file, one JSON post per line
+    Import {
+        /// NDJSON file to read, e.g. the output of GET /posts/export.ndjson
+        #[arg(long)]
+        file: PathBuf,
+        /// Number of records written per transaction
+        #[arg(long, default_value_t = 500)]
+        batch_size: usize,
+        /// What to do when a post with the same id already exists
+        #[arg(long, value_enum, default_value_t = import::OnDuplicate::Skip)]
+        on_duplicate: import::OnDuplicate,
+    },
+    /// Fill the database with fake users and posts (APP_ENV=development or test only)
+    Seed {
+        /// Number of seeded users
+        #[arg(long, default_value_t = 100)]
+        users: usize,
+        /// Number of seeded posts, spread over the seeded users
+        #[arg(long, default_value_t = 5000)]
+        posts: usize,
+        /// Seed whatever APP_ENV says, production included
+        #[arg(long)]
+        force: bool,
+    },
+    /// Start signing access tokens with a new key, keeping the old one valid for an hour
+    RotateSigningKey,
+    /// Write users and posts to a gzip compressed JSON lines archive
+    Backup {
+        /// Archive to create
+        #[arg(long, default_value = "dump.jsonl.gz")]
+        out: PathBuf,
+    },
+    /// Load users and posts from an archive written by `backup`
+    Restore {
+        /// Archive to read
+        #[arg(long, default_value = "dump.jsonl.gz")]
+        file: PathBuf,
+        /// Replace existing users and posts instead of requiring empty tables
+        #[arg(long)]
+        truncate: bool,
+        /// With --truncate, also empty the tables that refer to users and posts
+        #[arg(long, requires = "truncate")]
+        force: bool,
+    },
+    /// Seal the emails still stored in the clear with EMAIL_ENCRYPTION_KEY
+    EncryptEmails {
+        /// Number of users sealed per transaction
+        #[arg(long, default_value_t = 500)]
+        batch_size: usize,
+    },
+}
@@ -0,0 +1,241 @@
+// `app export-parquet`: dump posts and users as Parquet datasets laid out in
+// hive-style date partitions (`posts/created_on=2024-12-01/part-0.parquet`)
+// so DuckDB and warehouse loaders pick up the partition column on their own.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::builder::{Int32Builder, StringBuilder, TimestampMicrosecondBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use futures::{Stream, StreamExt, TryStreamExt};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use sqlx::{Pool, Postgres};
+use tracing::info;
+
+use rust_axum_rest_api::repo::emails;
+
+// rows buffered before they are flushed as one record batch
+const BATCH_SIZE: usize = 8192;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+struct PostRow {
+    id: i32,
+    user_id: Option<i32>,
+    title: String,
+    body: String,
+    created_at_us: i64,
+    created_on: String,
+}
+
+struct UserRow {
+    id: i32,
+    username: String,
+    email: String,
+    created_at_us: i64,
+    created_on: String,
+}
+
+// A row that can be appended to a Parquet dataset partitioned by date.
+trait ParquetRow: Sized {
+    const DATASET: &'static str;
+
+    fn schema() -> SchemaRef;
+    fn partition(&self) -> &str;
+    fn batch(rows: &[Self]) -> Result<RecordBatch, BoxError>;
+}
+
+impl ParquetRow for PostRow {
+    const DATASET: &'static str = "posts";
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("user_id", DataType::Int32, true),
+            Field::new("title", DataType::Utf8, false),
+            Field::new("body", DataType::Utf8, false),
+            Field::new(
+                "created_at",
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                false,
+            ),
+        ]))
+    }
+
+    fn partition(&self) -> &str {
+        &self.created_on
+    }
+
+    fn batch(rows: &[Self]) -> Result<RecordBatch, BoxError> {
+        let mut id = Int32Builder::with_capacity(rows.len());
+        let mut user_id = Int32Builder::with_capacity(rows.len());
+        let mut title = StringBuilder::new();
+        let mut body = StringBuilder::new();
+        let mut created_at =
+            TimestampMicrosecondBuilder::with_capacity(rows.len()).with_timezone("UTC");
+        for row in rows {
+            id.append_value(row.id);
+            user_id.append_option(row.user_id);
+            title.append_value(&row.title);
+            body.append_value(&row.body);
+            created_at.append_value(row.created_at_us);
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(id.finish()),
+            Arc::new(user_id.finish()),
+            Arc::new(title.finish()),
+            Arc::new(body.finish()),
+            Arc::new(created_at.finish()),
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+}
+
+impl ParquetRow for UserRow {
+    const DATASET: &'static str = "users";
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("username", DataType::Utf8, false),
+            Field::new("email", DataType::Utf8, false),
+            Field::new(
+                "created_at",
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                false,
+            ),
+        ]))
+    }
+
+    fn partition(&self) -> &str {
+        &self.created_on
+    }
+
+    fn batch(rows: &[Self]) -> Result<RecordBatch, BoxError> {
+        let mut id = Int32Builder::with_capacity(rows.len());
+        let mut username = StringBuilder::new();
+        let mut email = StringBuilder::new();
+        let mut created_at =
+            TimestampMicrosecondBuilder::with_capacity(rows.len()).with_timezone("UTC");
+        for row in rows {
+            id.append_value(row.id);
+            username.append_value(&row.username);
+            email.append_value(&row.email);
+            created_at.append_value(row.created_at_us);
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(id.finish()),
+            Arc::new(username.finish()),
+            Arc::new(email.finish()),
+            Arc::new(created_at.finish()),
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+}
+
+pub async fn run(pool: &Pool<Postgres>, out: &Path) -> Result<(), BoxError> {
+    // rows come back ordered by day so each partition is written in one go;
+    // rows without a timestamp are filed under the epoch
+    let posts = sqlx::query_as!(
+        PostRow,
+        r#"SELECT id, user_id, title, body,
+               (EXTRACT(EPOCH FROM COALESCE(created_at, 'epoch')) * 1000000)::BIGINT AS "created_at_us!",
+               to_char(COALESCE(created_at, 'epoch'), 'YYYY-MM-DD') AS "created_on!"
+           FROM posts WHERE deleted_at IS NULL ORDER BY 6, id"#
+    )
+    .fetch(pool);
+    let written = write_partitioned(posts, out).await?;
+    info!(
+        "exported {written} posts to {}",
+        out.join(PostRow::DATASET).display()
+    );
+
+    let users = sqlx::query_as!(
+        UserRow,
+        r#"SELECT id, username, email,
+               (EXTRACT(EPOCH FROM COALESCE(created_at, 'epoch')) * 1000000)::BIGINT AS "created_at_us!",
+               to_char(COALESCE(created_at, 'epoch'), 'YYYY-MM-DD') AS "created_on!"
+           FROM users ORDER BY 5, id"#
+    )
+    .fetch(pool)
+    .map(|row| {
+        let mut row = row?;
+        row.email = emails::open(&row.email)?;
+        Ok(row)
+    });
+    let written = write_partitioned(users, out).await?;
+    info!(
+        "exported {written} users to {}",
+        out.join(UserRow::DATASET).display()
+    );
+
+    Ok(())
+}
+
+// Write rows, which must arrive grouped by partition, into one file per
+// partition. Returns the number of rows written.
+async fn write_partitioned<T, S>(mut rows: S, out: &Path) -> Result<usize, BoxError>
+where
+    T: ParquetRow,
+    S: Stream<Item = Result<T, sqlx::Error>> + Unpin,
+{
+    let dataset = out.join(T::DATASET);
+    let mut writer: Option<(String, ArrowWriter<File>)> = None;
+    let mut buffer: Vec<T> = Vec::with_capacity(BATCH_SIZE);
+    let mut written = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        let same_partition = writer
+            .as_ref()
+            .is_some_and(|(day, _)| day == row.partition());
+        if !same_partition {
+            if let Some((_, mut current)) = writer.take() {
+                flush(&mut current, &mut buffer)?;
+                current.close()?;
+            }
+            writer = Some((
+                row.partition().to_owned(),
+                open_partition::<T>(&dataset, row.partition())?,
+            ));
+        }
+        buffer.push(row);
+        written += 1;
+        if buffer.len() == BATCH_SIZE {
+            if let Some((_, current)) = writer.as_mut() {
+                flush(current, &mut buffer)?;
+            }
+        }
+    }
+    if let Some((_, mut current)) = writer {
+        flush(&mut current, &mut buffer)?;
+        current.close()?;
+    }
+
+    Ok(written)
+}
+
+fn open_partition<T: ParquetRow>(dataset: &Path, day: &str) -> Result<ArrowWriter<File>, BoxError> {
+    let dir: PathBuf = dataset.join(format!("created_on={day}"));
+    fs::create_dir_all(&dir)?;
+    let file = File::create(dir.join("part-0.parquet"))?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    Ok(ArrowWriter::try_new(file, T::schema(), Some(props))?)
+}
+
+fn flush<T: ParquetRow>(
+    writer: &mut ArrowWriter<File>,
+    buffer: &mut Vec<T>,
+) -> Result<(), BoxError> {
+    if !buffer.is_empty() {
+        writer.write(&T::batch(buffer)?)?;
+        buffer.clear();
+    }
+    Ok(())
+}
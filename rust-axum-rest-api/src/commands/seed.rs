@@ -0,0 +1,158 @@
+// `app seed`: fill the database with fake users and posts for development
+// and load testing.
+//
+// Seeding is idempotent: seeded users are derived from their index (and live
+// on the reserved seed.example.com domain), and posts are only topped up to the
+// requested count, so running the command twice leaves the database as it was.
+// It only runs in the development and test profiles, which APP_ENV has to
+// name (see `profile`), or with `force`.
+
+use std::error::Error;
+
+use fake::faker::internet::en::Username;
+use fake::faker::lorem::en::{Paragraphs, Sentence};
+use fake::Fake;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sqlx::{Pool, Postgres};
+use tracing::info;
+
+use rust_axum_rest_api::profile::Profile;
+use rust_axum_rest_api::repo::emails;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+// whether fake data may go into the profile's database; an unset APP_ENV is
+// production, so it takes naming development or test
+fn allowed(profile: Profile, force: bool) -> Result<(), String> {
+    match profile {
+        Profile::Development | Profile::Test => Ok(()),
+        _ if force => Ok(()),
+        Profile::Production => Err(String::from(
+            "refusing to seed fake data outside APP_ENV=development or test, pass --force to go ahead",
+        )),
+    }
+}
+
+const SEED_DOMAIN: &str = "seed.example.com";
+
+// rows sent to the database per INSERT
+const CHUNK_SIZE: usize = 1000;
+
+pub async fn run(
+    pool: &Pool<Postgres>,
+    users: usize,
+    posts: usize,
+    force: bool,
+) -> Result<(), BoxError> {
+    allowed(Profile::from_env()?, force)?;
+
+    let (usernames, addresses): (Vec<String>, Vec<String>) = (1..=users)
+        .map(|n| {
+            let mut rng = StdRng::seed_from_u64(n as u64);
+            let name: String = Username().fake_with_rng(&mut rng);
+            let username = format!("{}{n}", name.to_lowercase());
+            let email = format!("{username}@{SEED_DOMAIN}");
+            (username, email)
+        })
+        .unzip();
+    let sealed: Vec<emails::Sealed> = addresses.iter().map(|email| emails::seal(email)).collect();
+    let hashes: Vec<String> = sealed
+        .iter()
+        .filter_map(|email| email.hash.clone())
+        .collect();
+    for (usernames, sealed) in usernames.chunks(CHUNK_SIZE).zip(sealed.chunks(CHUNK_SIZE)) {
+        let (stored, hashes): (Vec<String>, Vec<Option<String>>) = sealed
+            .iter()
+            .map(|email| (email.stored.clone(), email.hash.clone()))
+            .unzip();
+        sqlx::query!(
+            "INSERT INTO users (username, email, email_hash) SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[]) ON CONFLICT DO NOTHING",
+            usernames,
+            &stored,
+            &hashes as &[Option<String>]
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    // sealed emails are found by their hashes
+    let user_ids: Vec<i32> = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE email LIKE '%@' || $1 OR email_hash = ANY($2) ORDER BY id",
+        SEED_DOMAIN,
+        &hashes
+    )
+    .fetch_all(pool)
+    .await?;
+    info!("{} seeded users present", user_ids.len());
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    let existing = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM posts WHERE user_id = ANY($1) AND deleted_at IS NULL"#,
+        &user_ids
+    )
+    .fetch_one(pool)
+    .await? as usize;
+    let missing = posts.saturating_sub(existing);
+
+    let mut authors = Vec::with_capacity(CHUNK_SIZE);
+    let mut titles = Vec::with_capacity(CHUNK_SIZE);
+    let mut bodies = Vec::with_capacity(CHUNK_SIZE);
+    for n in existing..existing + missing {
+        let mut rng = StdRng::seed_from_u64(n as u64);
+        authors.push(user_ids[rng.random_range(0..user_ids.len())]);
+        let title: String = Sentence(3..8).fake_with_rng(&mut rng);
+        titles.push(title.trim_end_matches('.').to_owned());
+        let paragraphs: Vec<String> = Paragraphs(1..4).fake_with_rng(&mut rng);
+        bodies.push(paragraphs.join("\n\n"));
+
+        if authors.len() == CHUNK_SIZE {
+            insert_posts(pool, &mut authors, &mut titles, &mut bodies).await?;
+        }
+    }
+    insert_posts(pool, &mut authors, &mut titles, &mut bodies).await?;
+    info!(
+        "inserted {missing} posts, {} seeded posts present",
+        existing + missing
+    );
+
+    Ok(())
+}
+
+async fn insert_posts(
+    pool: &Pool<Postgres>,
+    authors: &mut Vec<i32>,
+    titles: &mut Vec<String>,
+    bodies: &mut Vec<String>,
+) -> Result<(), sqlx::Error> {
+    if authors.is_empty() {
+        return Ok(());
+    }
+    sqlx::query!(
+        "INSERT INTO posts (user_id, title, body) SELECT * FROM UNNEST($1::int4[], $2::text[], $3::text[])",
+        &authors[..],
+        &titles[..],
+        &bodies[..]
+    )
+    .execute(pool)
+    .await?;
+    authors.clear();
+    titles.clear();
+    bodies.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_development_profiles_are_seeded_unforced() {
+        assert!(allowed(Profile::Development, false).is_ok());
+        assert!(allowed(Profile::Test, false).is_ok());
+        assert!(allowed(Profile::Production, false).is_err());
+        assert!(allowed(Profile::Production, true).is_ok());
+    }
+}
@@ -0,0 +1,279 @@
+// Threaded comments on posts.
+//
+// A comment replies to a published post or, with `parent_comment_id`, to
+// another published comment of the same post, at most `MAX_DEPTH` levels
+// deep. New comments go through the content filter and the spam checks like
+// posts; held back ones wait in the moderation queue (see `moderation`) and
+// are left out of threads until approved. Readers report comments with
+// `POST /comments/:id/report`.
+// `GET /posts/:id/comments` returns the thread as a tree of comments with
+// their `replies`, fetched with a single query in tree order (see
+// `repo::comments::thread`) and assembled here; `?depth=` cuts it off after
+// that many levels.
+
+use std::net::IpAddr;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::activity;
+use crate::archive;
+use crate::audit::{self, Change};
+use crate::auth::{Actor, RequireAdmin};
+use crate::authors;
+use crate::client_ip::ClientIp;
+use crate::content_filter::{self, Verdict};
+use crate::error::{ApiError, OrNotFound};
+use crate::events::Event;
+use crate::handlers;
+use crate::ids::{Key, PathKey};
+use crate::mentions;
+use crate::models::{
+    ActivityKind, Comment, CommentThread, CreateComment, CreateReport, PostStatus, Report,
+};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::repo;
+use crate::spam::{SpamVerdict, Submission};
+use crate::state::AppState;
+use crate::usage::{self, Metric, Owner};
+
+// levels of a thread, top-level comments being the first
+pub const MAX_DEPTH: i32 = 8;
+
+// `GET /posts/:id/comments` parameters
+#[derive(Deserialize)]
+pub struct ThreadParams {
+    depth: Option<i32>,
+}
+
+// handler for "GET /posts/:id/comments" rest API endpoint
+pub async fn list_comments(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Query(params): Query<ThreadParams>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<CommentThread>>, ApiError> {
+    let levels = params.depth.unwrap_or(MAX_DEPTH);
+    if !(1..=MAX_DEPTH).contains(&levels) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_depth",
+            format!("depth must be between 1 and {MAX_DEPTH}"),
+        ));
+    }
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    authors::require_visible(&post, &actor, &admin)?;
+    let comments = repo::comments::thread(&state.pool, post.id, levels - 1).await?;
+
+    Ok(format.respond(tree(comments)))
+}
+
+// nest comments given in tree order under their parents
+fn tree(comments: Vec<Comment>) -> Vec<CommentThread> {
+    // the open threads, from a top-level comment down to the last one seen
+    let mut open: Vec<CommentThread> = Vec::new();
+    let mut roots = Vec::new();
+    for comment in comments {
+        while open.len() > comment.depth as usize {
+            close(&mut open, &mut roots);
+        }
+        open.push(CommentThread {
+            comment,
+            replies: Vec::new(),
+        });
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut roots);
+    }
+    roots
+}
+
+// attach the innermost open thread to its parent, or to the roots
+fn close(open: &mut Vec<CommentThread>, roots: &mut Vec<CommentThread>) {
+    let done = open.pop().expect("an open thread");
+    match open.last_mut() {
+        Some(parent) => parent.replies.push(done),
+        None => roots.push(done),
+    }
+}
+
+// handler for "POST /posts/:id/comments" rest API endpoint, commenting on a
+// post or replying to one of its comments
+pub async fn create_comment(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    ClientIp(client): ClientIp,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+    Payload(mut comment): Payload<CreateComment>,
+) -> Result<Negotiated<Comment>, ApiError> {
+    comment.body = comment.body.trim().to_owned();
+    if comment.body.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "missing_body",
+            "comments need a body",
+        )
+        .with("field", "body"));
+    }
+    comment.user_id = actor.author(comment.user_id, admin.is_some())?;
+    let owner = Owner::of(comment.user_id, None);
+    usage::check_resource(&state, owner, Metric::Comments).await?;
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    authors::require_visible(&post, &actor, &admin)?;
+    archive::ensure_live(&state, &post).await?;
+    if post.status != PostStatus::Published.as_str() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "not_published",
+            "only published posts take comments",
+        ));
+    }
+    if let Some(parent_id) = comment.parent_comment_id {
+        let parent = match repo::comments::get(&state.pool, parent_id).await {
+            Ok(parent)
+                if parent.post_id == post.id && parent.status == PostStatus::Published.as_str() =>
+            {
+                parent
+            }
+            Ok(_) | Err(sqlx::Error::RowNotFound) => {
+                return Err(ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "invalid_parent",
+                    format!("the post has no comment {parent_id}"),
+                )
+                .with("field", "parent_comment_id"))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if parent.depth + 1 >= MAX_DEPTH {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "too_deep",
+                format!("threads are at most {MAX_DEPTH} comments deep"),
+            )
+            .with("field", "parent_comment_id"));
+        }
+    }
+    let status = match admin {
+        // moderators' comments aren't held back for moderators
+        Some(_) => PostStatus::Published,
+        None => screen(&state, client, &comment).await?,
+    };
+    let comment = repo::comments::create(&state.pool, post.id, &comment, status).await?;
+    usage::record_resource(&state, owner, Metric::Comments);
+    audit::record(Change::new("comment.create", "comment", comment.id).after(&comment));
+    mentions::record(
+        &state,
+        post.id,
+        Some(comment.id),
+        None,
+        &comment.body,
+        comment.user_id,
+    )
+    .await?;
+    // held back ones go into the feed once they are approved, see
+    // `moderation`
+    if status == PostStatus::Published {
+        activity::record(
+            &state,
+            comment.user_id,
+            ActivityKind::Comment,
+            post.id,
+            Some(comment.id),
+        )
+        .await?;
+        state.events.publish(Event::CommentAdded(comment.clone()));
+    }
+
+    Ok(format.respond(comment))
+}
+
+// handler for "POST /comments/:id/report" rest API endpoint, a user
+// reporting a comment to the moderators, once. Enough readers' reports hold
+// it back like a post, see `handlers::report_post`.
+pub async fn report_comment(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    actor: Actor,
+    Accept(format): Accept,
+    Payload(report): Payload<CreateReport>,
+) -> Result<Negotiated<Report>, ApiError> {
+    let reporter_id = actor.require_user()?;
+    let reason = handlers::report_reason(&report)?;
+    let comment = match repo::comments::get(&state.pool, id).await {
+        Ok(comment) if comment.status == PostStatus::Published.as_str() => comment,
+        Ok(_) | Err(sqlx::Error::RowNotFound) => {
+            return Err(ApiError::not_found("comment not found"))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let post = state
+        .posts
+        .get(Key::Serial(comment.post_id))
+        .await
+        .or_not_found("comment")?;
+    authors::require_visible(&post, &actor, &None)?;
+    let Some(report) = state
+        .reports
+        .create(post.id, Some(comment.id), reporter_id, reason)
+        .await?
+    else {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "already_reported",
+            "you already reported this comment",
+        ));
+    };
+    audit::record(Change::new("report.create", "report", report.id).after(&report));
+
+    let open = state.reports.count_open(post.id, Some(comment.id)).await?;
+    if open >= handlers::REPORT_THRESHOLD {
+        let reason = format!("reported {open} times");
+        let held =
+            repo::comments::moderate(&state.pool, comment.id, PostStatus::Pending, Some(&reason))
+                .await?;
+        audit::record(
+            Change::new("comment.hold", "comment", comment.id)
+                .before(&comment)
+                .after(&held),
+        );
+    }
+
+    Ok(format.respond(report))
+}
+
+// Run a comment through the content filter and the spam checks like a post
+// (see `handlers::create_post`): flagged or suspicious comments wait for a
+// moderator, spam is kept as such.
+async fn screen(
+    state: &AppState,
+    client: Option<IpAddr>,
+    comment: &CreateComment,
+) -> Result<PostStatus, ApiError> {
+    let filtered =
+        content_filter::check(state.banned_words.as_ref(), &[("body", &comment.body)]).await?;
+    let submission = Submission {
+        user_id: comment.user_id,
+        client,
+        title: "",
+        body: &comment.body,
+    };
+    Ok(match (state.spam.check(&submission).await, filtered) {
+        (SpamVerdict::Spam(reason), _) => {
+            info!("holding back a comment as spam: {reason}");
+            PostStatus::Spam
+        }
+        (SpamVerdict::Suspicious(reason), _) => {
+            info!("holding back a suspicious comment: {reason}");
+            PostStatus::Pending
+        }
+        (SpamVerdict::Ham, Verdict::Flag) => PostStatus::Pending,
+        (SpamVerdict::Ham, Verdict::Allow) => PostStatus::Published,
+    })
+}
@@ -0,0 +1,393 @@
+// Runtime configuration, read from the environment (and the profile's .env
+// files loaded by main, see `profile`) once at startup.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use chrono::Duration;
+use ipnet::IpNet;
+use rand::RngCore;
+
+use crate::avatars::Gravatar;
+use crate::billing::StripeConfig;
+use crate::case::KeyCase;
+use crate::db::PoolConfig;
+use crate::ids::IdScheme;
+use crate::ip_filter::{self, IpRules};
+use crate::maintenance::MaintenanceMode;
+use crate::metrics::SloTargets;
+use crate::oidc::OidcConfig;
+use crate::profile::Profile;
+use crate::repo::api_keys::hex;
+use crate::repo::emails::EmailKey;
+use crate::retention::Retention;
+use crate::server::{Http2, Listener, UnixSocket};
+use crate::shutdown::ShutdownConfig;
+use crate::tls::TlsConfig;
+use crate::tunables::Tunables;
+use crate::usernames;
+
+pub struct Config {
+    pub database_url: String,
+    // the connection pool's size and timeouts, DB_MAX_CONNECTIONS and friends
+    // (see `db`)
+    pub pool: PoolConfig,
+    // address the HTTP server listens on, BIND_ADDR; none by default with
+    // `unix_socket`
+    pub bind_addr: Option<SocketAddr>,
+    // unix domain socket the HTTP server listens on too, UNIX_SOCKET and
+    // UNIX_SOCKET_MODE (see `server`)
+    pub unix_socket: Option<UnixSocket>,
+    // more addresses serving the API or /admin, LISTENERS (see `server`)
+    pub listeners: Vec<Listener>,
+    // bearer token accepted on admin endpoints in addition to admin API keys,
+    // ADMIN_TOKEN; unset or empty disables it
+    pub admin_token: Option<String>,
+    // which id `/posts/:id` takes, ID_SCHEME=serial|uuid, serial by default
+    pub id_scheme: IdScheme,
+    // wrap responses as {data, meta, errors}, RESPONSE_ENVELOPE=true|false,
+    // off by default (see `envelope`)
+    pub envelope: bool,
+    // the case of JSON keys, JSON_CASE=snake|camel, snake by default (see
+    // `case`)
+    pub json_case: KeyCase,
+    // usernames nobody can sign up with, comma separated RESERVED_USERNAMES
+    // (see `usernames` for the defaults)
+    pub reserved_usernames: Vec<String>,
+    // check that new users' email domains accept mail, EMAIL_MX_CHECK=true|false,
+    // off by default (see `email`)
+    pub email_mx_check: bool,
+    // Akismet-style service asked about new posts, SPAM_CHECK_URL; unset
+    // leaves the built-in heuristics (see `spam`)
+    pub spam_check_url: Option<String>,
+    // requests to `<slug>.<TENANT_DOMAIN>` belong to that tenant; unset, only
+    // the X-Tenant header picks one (see `tenant`)
+    pub tenant_domain: Option<String>,
+    // where the API is reachable from outside, PUBLIC_URL, for links sent by
+    // mail; unset, they are relative. Also turns on federation (see
+    // `federation`)
+    pub public_url: Option<String>,
+    // let federation fetch keys from and deliver to private addresses,
+    // FEDERATION_PRIVATE_HOSTS=true|false, off by default (see `federation`)
+    pub federation_private_hosts: bool,
+    // signs the links in emails that act without logging in, e.g. the
+    // digests' unsubscribe links, EMAIL_LINK_SECRET; required in production,
+    // elsewhere unset is a random one and the links stop working with a
+    // restart (see `unsubscribe`)
+    pub email_link_secret: String,
+    // seals users' emails in the database, EMAIL_ENCRYPTION_KEY; unset, they
+    // are stored in the clear (see `repo::emails`)
+    pub email_key: Option<EmailKey>,
+    // the maintenance mode the server starts in, MAINTENANCE=off|read_only|full,
+    // off by default (see `maintenance`)
+    pub maintenance: MaintenanceMode,
+    // TOML file of the settings that can be reloaded at runtime, CONFIG_FILE
+    // (see `tunables`)
+    pub config_file: Option<PathBuf>,
+    // those settings as read at startup
+    pub tunables: Tunables,
+    // rotate the access token signing key once it is this old,
+    // SIGNING_KEY_ROTATION_DAYS; unset, only `rotate-signing-key` does (see
+    // `jwt`)
+    pub signing_key_rotation: Option<Duration>,
+    // trust the bearer tokens of an external identity provider, OIDC_ISSUER
+    // and friends; unset, only ours (see `oidc`)
+    pub oidc: Option<OidcConfig>,
+    // serve HTTPS on BIND_ADDR, TLS_CERT_FILE and TLS_KEY_FILE or ACME_DOMAINS;
+    // unset, plain HTTP (see `tls`)
+    pub tls: Option<TlsConfig>,
+    // where plain HTTP is redirected to HTTPS, HTTP_REDIRECT_ADDR; only with
+    // `tls`
+    pub http_redirect_addr: Option<SocketAddr>,
+    // HTTP/2 without TLS and the streams of a connection, H2C and
+    // HTTP2_MAX_CONCURRENT_STREAMS (see `server`)
+    pub http2: Http2,
+    // client certificate identities that count as admins, comma separated
+    // MTLS_ADMIN_IDENTITIES (see `tls`)
+    pub admin_identities: Vec<String>,
+    // who may reach the API outside /admin, IP_ALLOW and IP_DENY (see
+    // `ip_filter`)
+    pub ip_rules: IpRules,
+    // who may reach /admin, ADMIN_IP_ALLOW and ADMIN_IP_DENY
+    pub admin_ip_rules: IpRules,
+    // the reverse proxies whose forwarding headers name the client, comma
+    // separated TRUSTED_PROXIES; none by default (see `client_ip`)
+    pub trusted_proxies: Vec<IpNet>,
+    // the service level objectives whose burn rates are exported,
+    // SLO_AVAILABILITY, SLO_LATENCY_MS and SLO_LATENCY_PERCENT; none by
+    // default (see `metrics`)
+    pub slo: SloTargets,
+    // the avatar of users without their own, GRAVATAR and friends; on by
+    // default (see `avatars`)
+    pub gravatar: Option<Gravatar>,
+    // subscriptions to paid plans, STRIPE_WEBHOOK_SECRET and STRIPE_PRICES;
+    // unset, billing is off and nothing is limited (see `billing`)
+    pub stripe: Option<StripeConfig>,
+    // purge soft-deleted and expired data, RETENTION_DAYS and
+    // RETENTION_DRY_RUN; unset, it is kept (see `retention`)
+    pub retention: Option<Retention>,
+    // ARCHIVE_AFTER_YEARS, how old published posts are archived; unset, none
+    // are (see `archive`)
+    pub archive_after_years: Option<u32>,
+    // what instances share through Redis rather than Postgres, REDIS_URL,
+    // which takes a build with the redis feature (see `redis_store`)
+    pub redis_url: Option<String>,
+    // pass events on to the other instances, EVENT_RELAY=true|false, off by
+    // default (see `relay`)
+    pub event_relay: bool,
+    // APP_ENV, production by default (see `profile`)
+    pub profile: Profile,
+    // say what went wrong in 500s, ERROR_DETAILS=true|false, the profile's
+    // default (see `error`)
+    pub error_details: bool,
+    // how the instance drains on SIGTERM, SHUTDOWN_DRAIN_SECS and
+    // SHUTDOWN_TIMEOUT_SECS (see `shutdown`)
+    pub shutdown: ShutdownConfig,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config, String> {
+        let database_url = std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set")?;
+        let pool = PoolConfig::from_env()?;
+        let unix_socket = UnixSocket::from_env()?;
+        let listeners = Listener::from_env()?;
+        let bind_addr = match std::env::var("BIND_ADDR") {
+            Ok(addr) => Some(
+                addr.parse()
+                    .map_err(|e| format!("invalid BIND_ADDR {addr:?}: {e}"))?,
+            ),
+            // only the unix socket then
+            Err(_) if unix_socket.is_some() => None,
+            Err(_) => Some(SocketAddr::from(([0, 0, 0, 0], 5000))),
+        };
+        let admin_token = std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        let id_scheme = match std::env::var("ID_SCHEME") {
+            Ok(scheme) => scheme.parse()?,
+            Err(_) => IdScheme::default(),
+        };
+        let envelope = match std::env::var("RESPONSE_ENVELOPE") {
+            Ok(value) => value.parse().map_err(|_| {
+                format!("invalid RESPONSE_ENVELOPE {value:?}, expected true or false")
+            })?,
+            Err(_) => false,
+        };
+        let json_case = match std::env::var("JSON_CASE") {
+            Ok(case) => case.parse()?,
+            Err(_) => KeyCase::default(),
+        };
+        let reserved_usernames = match std::env::var("RESERVED_USERNAMES") {
+            Ok(names) => names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            Err(_) => usernames::DEFAULT_RESERVED
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        };
+        let email_mx_check = match std::env::var("EMAIL_MX_CHECK") {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| format!("invalid EMAIL_MX_CHECK {value:?}, expected true or false"))?,
+            Err(_) => false,
+        };
+        let spam_check_url = std::env::var("SPAM_CHECK_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+        let tenant_domain = std::env::var("TENANT_DOMAIN")
+            .ok()
+            .map(|domain| domain.trim_start_matches('.').to_ascii_lowercase())
+            .filter(|domain| !domain.is_empty());
+        let public_url = std::env::var("PUBLIC_URL")
+            .ok()
+            .map(|url| url.trim_end_matches('/').to_owned())
+            .filter(|url| !url.is_empty());
+        let federation_private_hosts = match std::env::var("FEDERATION_PRIVATE_HOSTS") {
+            Ok(value) => value.parse().map_err(|_| {
+                format!("invalid FEDERATION_PRIVATE_HOSTS {value:?}, expected true or false")
+            })?,
+            Err(_) => false,
+        };
+        let profile = Profile::from_env()?;
+        let email_link_secret = email_link_secret(
+            std::env::var("EMAIL_LINK_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            profile,
+        )?;
+        let email_key = EmailKey::from_env()?;
+        let maintenance = match std::env::var("MAINTENANCE") {
+            Ok(mode) => mode.parse()?,
+            Err(_) => MaintenanceMode::default(),
+        };
+        let config_file = std::env::var_os("CONFIG_FILE")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let tunables = Tunables::load(config_file.as_deref())?;
+        let signing_key_rotation = match std::env::var("SIGNING_KEY_ROTATION_DAYS") {
+            Ok(days) => match days.parse() {
+                Ok(days) if days > 0 => Some(Duration::days(days)),
+                _ => {
+                    return Err(format!(
+                        "invalid SIGNING_KEY_ROTATION_DAYS {days:?}, expected a positive number"
+                    ))
+                }
+            },
+            Err(_) => None,
+        };
+        let archive_after_years = match std::env::var("ARCHIVE_AFTER_YEARS") {
+            Ok(years) => match years.parse() {
+                Ok(years) if years > 0 => Some(years),
+                _ => {
+                    return Err(format!(
+                        "invalid ARCHIVE_AFTER_YEARS {years:?}, expected a positive number"
+                    ))
+                }
+            },
+            Err(_) => None,
+        };
+        let redis_url = std::env::var("REDIS_URL").ok();
+        #[cfg(feature = "redis")]
+        if let Some(url) = &redis_url {
+            redis::Client::open(url.as_str())
+                .map_err(|e| format!("invalid REDIS_URL {url:?}: {e}"))?;
+        }
+        #[cfg(not(feature = "redis"))]
+        if redis_url.is_some() {
+            return Err(String::from(
+                "REDIS_URL needs a build with the redis feature",
+            ));
+        }
+        let event_relay = match std::env::var("EVENT_RELAY") {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| format!("invalid EVENT_RELAY {value:?}, expected true or false"))?,
+            Err(_) => false,
+        };
+        let error_details = match std::env::var("ERROR_DETAILS") {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| format!("invalid ERROR_DETAILS {value:?}, expected true or false"))?,
+            Err(_) => profile.error_details(),
+        };
+        let shutdown = ShutdownConfig::from_env()?;
+        let oidc = OidcConfig::from_env()?;
+        let tls = TlsConfig::from_env()?;
+        if tls.is_some() && bind_addr.is_none() {
+            return Err(String::from(
+                "HTTPS is served on BIND_ADDR, set it alongside UNIX_SOCKET",
+            ));
+        }
+        let http_redirect_addr = match std::env::var("HTTP_REDIRECT_ADDR") {
+            Ok(addr) if tls.is_none() => {
+                return Err(format!(
+                    "HTTP_REDIRECT_ADDR {addr:?} takes TLS_CERT_FILE or ACME_DOMAINS"
+                ))
+            }
+            Ok(addr) => Some(
+                addr.parse()
+                    .map_err(|e| format!("invalid HTTP_REDIRECT_ADDR {addr:?}: {e}"))?,
+            ),
+            Err(_) => None,
+        };
+
+        let http2 = Http2::from_env()?;
+
+        let admin_identities = std::env::var("MTLS_ADMIN_IDENTITIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|identity| !identity.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let ip_rules = IpRules::from_env("")?;
+        let admin_ip_rules = IpRules::from_env("ADMIN_")?;
+        let trusted_proxies = ip_filter::list("TRUSTED_PROXIES")?;
+        let slo = SloTargets::from_env()?;
+        let gravatar = Gravatar::from_env()?;
+        let stripe = StripeConfig::from_env()?;
+        let retention = Retention::from_env()?;
+
+        Ok(Config {
+            database_url,
+            pool,
+            bind_addr,
+            unix_socket,
+            listeners,
+            admin_token,
+            id_scheme,
+            envelope,
+            json_case,
+            reserved_usernames,
+            email_mx_check,
+            spam_check_url,
+            tenant_domain,
+            public_url,
+            federation_private_hosts,
+            email_link_secret,
+            email_key,
+            maintenance,
+            config_file,
+            tunables,
+            signing_key_rotation,
+            oidc,
+            tls,
+            http_redirect_addr,
+            http2,
+            admin_identities,
+            ip_rules,
+            admin_ip_rules,
+            trusted_proxies,
+            slo,
+            gravatar,
+            stripe,
+            retention,
+            archive_after_years,
+            redis_url,
+            event_relay,
+            profile,
+            error_details,
+            shutdown,
+        })
+    }
+}
+
+// EMAIL_LINK_SECRET, if set. Digests go out in every profile, and in
+// production their unsubscribe links have to outlive restarts and work on
+// every instance, so it can't be left out there.
+fn email_link_secret(secret: Option<String>, profile: Profile) -> Result<String, String> {
+    match secret {
+        Some(secret) => Ok(secret),
+        None if profile == Profile::Production => Err(String::from(
+            "EMAIL_LINK_SECRET must be set in the production profile, it signs the unsubscribe links of digests",
+        )),
+        None => {
+            let mut secret = [0u8; 32];
+            rand::rng().fill_bytes(&mut secret);
+            Ok(hex(&secret))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_link_secret_is_required_in_production() {
+        assert!(email_link_secret(None, Profile::Production)
+            .unwrap_err()
+            .contains("EMAIL_LINK_SECRET"));
+        assert_eq!(
+            email_link_secret(Some(String::from("links")), Profile::Production).unwrap(),
+            "links"
+        );
+        let random = email_link_secret(None, Profile::Development).unwrap();
+        assert_eq!(random.len(), 64);
+        assert_ne!(random, email_link_secret(None, Profile::Test).unwrap());
+    }
+}
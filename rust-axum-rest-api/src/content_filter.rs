@@ -0,0 +1,82 @@
+// Banned word filter for titles, bodies and usernames.
+//
+// Admins keep a list of words, each with an action: `reject` refuses the
+// submission with a 422 "banned_content" naming the field and the words,
+// `flag` accepts it but holds posts back as pending for a moderator (flagged
+// usernames are only logged, users have no moderation status). Matching is
+// by whole words, ignoring case and Unicode compatibility forms, so "Spam",
+// "ｓｐａｍ" and "spam!" all match "spam" but "spammer" doesn't. Listed
+// entries may be phrases of several words.
+
+use axum::http::StatusCode;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::ApiError;
+use crate::models::{BannedWord, FilterAction};
+use crate::repo::banned_words::BannedWordRepository;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Flag,
+}
+
+// the form words are stored and compared in
+pub fn normalize(text: &str) -> String {
+    words(text).join(" ")
+}
+
+// check the named texts of one submission against the list
+pub async fn check(
+    banned: &dyn BannedWordRepository,
+    texts: &[(&str, &str)],
+) -> Result<Verdict, ApiError> {
+    let list = banned.list().await?;
+    if list.is_empty() {
+        return Ok(Verdict::Allow);
+    }
+
+    let mut verdict = Verdict::Allow;
+    for (field, text) in texts {
+        let text = words(text);
+        let found: Vec<&BannedWord> = list
+            .iter()
+            .filter(|banned| contains_phrase(&text, &banned.word))
+            .collect();
+        let rejected: Vec<&str> = found
+            .iter()
+            .filter(|banned| banned.action == FilterAction::Reject)
+            .map(|banned| banned.word.as_str())
+            .collect();
+        if !rejected.is_empty() {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "banned_content",
+                format!("the {field} contains words that aren't allowed"),
+            )
+            .with("field", field)
+            .with("words", rejected));
+        }
+        if !found.is_empty() {
+            verdict = Verdict::Flag;
+        }
+    }
+    Ok(verdict)
+}
+
+// the lower case words of a text, in order
+fn words(text: &str) -> Vec<String> {
+    let text: String = text.nfkc().collect::<String>().to_lowercase();
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn contains_phrase(words: &[String], phrase: &str) -> bool {
+    let phrase: Vec<&str> = phrase.split(' ').collect();
+    !phrase.is_empty()
+        && words
+            .windows(phrase.len())
+            .any(|window| window.iter().zip(&phrase).all(|(a, b)| a == b))
+}
@@ -0,0 +1,143 @@
+// The database connection pool, sized and tuned from the environment:
+//
+//     DB_MAX_CONNECTIONS          connections at most, 10 by default
+//     DB_MIN_CONNECTIONS          kept open even when idle, 0 by default
+//     DB_ACQUIRE_TIMEOUT_SECS     how long a query waits for a connection
+//                                 before failing, 30 by default
+//     DB_IDLE_TIMEOUT_SECS        idle connections are closed after, 600 by
+//                                 default, 0 for never
+//     DB_MAX_LIFETIME_SECS        connections are replaced after, 1800 by
+//                                 default, 0 for never
+//     DB_TEST_BEFORE_ACQUIRE      ping connections before handing them out,
+//                                 true or false, true by default
+//
+// and DB_BREAKER_FAILURES and friends for the circuit breaker queries go
+// through (see `repo::breaker`) and DB_READ_RETRIES and DB_RETRY_BACKOFF_MS
+// for retrying reads (see `repo::retry`), set up on connecting. How the pool is doing
+// is exported with the other metrics (see `metrics`).
+
+use std::time::{Duration, Instant};
+
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+
+use crate::repo::breaker::{self, BreakerConfig};
+use crate::repo::retry::{self, RetryConfig};
+
+// how long `acquire_wait` waits for a connection at most
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    // None to keep idle connections open
+    pub idle_timeout: Option<Duration>,
+    // None to keep connections for good
+    pub max_lifetime: Option<Duration>,
+    pub test_before_acquire: bool,
+    pub breaker: BreakerConfig,
+    pub retry: RetryConfig,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            max_lifetime: Some(Duration::from_secs(30 * 60)),
+            test_before_acquire: true,
+            breaker: BreakerConfig::DEFAULT,
+            retry: RetryConfig::DEFAULT,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn from_env() -> Result<PoolConfig, String> {
+        let number = |name: &str| match std::env::var(name) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map(Some)
+                .map_err(|_| format!("invalid {name} {value:?}, expected a number")),
+            Err(_) => Ok(None),
+        };
+        // zero for never
+        let timeout = |name: &str, default: Option<Duration>| {
+            Ok::<_, String>(match number(name)? {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => default,
+            })
+        };
+        let count = |name: &str, default: u32| match number(name)? {
+            Some(count) => u32::try_from(count).map_err(|_| format!("{name} {count} is too large")),
+            None => Ok(default),
+        };
+
+        let defaults = PoolConfig::default();
+        let max_connections = count("DB_MAX_CONNECTIONS", defaults.max_connections)?;
+        if max_connections == 0 {
+            return Err(String::from("DB_MAX_CONNECTIONS must be at least 1"));
+        }
+        let min_connections = count("DB_MIN_CONNECTIONS", defaults.min_connections)?;
+        if min_connections > max_connections {
+            return Err(format!(
+                "DB_MIN_CONNECTIONS {min_connections} is more than DB_MAX_CONNECTIONS {max_connections}"
+            ));
+        }
+        let acquire_timeout = match number("DB_ACQUIRE_TIMEOUT_SECS")? {
+            Some(0) => return Err(String::from("DB_ACQUIRE_TIMEOUT_SECS must be at least 1")),
+            Some(secs) => Duration::from_secs(secs),
+            None => defaults.acquire_timeout,
+        };
+        let test_before_acquire = match std::env::var("DB_TEST_BEFORE_ACQUIRE") {
+            Ok(value) => value.parse().map_err(|_| {
+                format!("invalid DB_TEST_BEFORE_ACQUIRE {value:?}, expected true or false")
+            })?,
+            Err(_) => defaults.test_before_acquire,
+        };
+        Ok(PoolConfig {
+            max_connections,
+            min_connections,
+            acquire_timeout,
+            idle_timeout: timeout("DB_IDLE_TIMEOUT_SECS", defaults.idle_timeout)?,
+            max_lifetime: timeout("DB_MAX_LIFETIME_SECS", defaults.max_lifetime)?,
+            test_before_acquire,
+            breaker: BreakerConfig::from_env()?,
+            retry: RetryConfig::from_env()?,
+        })
+    }
+
+    pub fn options(&self) -> PgPoolOptions {
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+            .max_lifetime(self.max_lifetime)
+            .test_before_acquire(self.test_before_acquire)
+    }
+
+    // Connect to the database at `url`, with the breaker and retries set up.
+    pub async fn connect(&self, url: &str) -> Result<Pool<Postgres>, sqlx::Error> {
+        breaker::global().configure(self.breaker);
+        retry::configure(self.retry);
+        self.options().connect(url).await
+    }
+}
+
+// How long it takes to get a connection right now, up to `PROBE_TIMEOUT`;
+// None while the pool has none open, so that it never connects just to
+// answer.
+pub async fn acquire_wait(pool: &Pool<Postgres>) -> Option<Duration> {
+    if pool.size() == 0 {
+        return None;
+    }
+    let started = Instant::now();
+    let _connection = tokio::time::timeout(PROBE_TIMEOUT, pool.acquire()).await;
+    Some(started.elapsed())
+}
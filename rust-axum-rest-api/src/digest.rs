@@ -0,0 +1,117 @@
+// The weekly digest: users who opted in (see `preferences`) are emailed the
+// posts published by the users they follow (see `follows`) since their last
+// digest, through the notifier.
+//
+// A background job checks every `POLL_INTERVAL` for users whose last digest
+// is at least `PERIOD` old, marking each one sent as it is taken so that
+// several instances don't mail the same user twice. Users whose followees
+// posted nothing get no email, their next digest is a period later all the
+// same. The first digest covers the last period. Each one links to its
+// one-click unsubscribe, see `unsubscribe`.
+
+use std::time::Duration;
+
+use tracing::error;
+
+use crate::locks;
+use crate::meta;
+use crate::notify::Notification;
+use crate::repo;
+use crate::repo::follows::DigestPost;
+use crate::repo::preferences::DigestRecipient;
+use crate::routes::{expand, POST_ROUTE};
+use crate::state::AppState;
+use crate::unsubscribe;
+
+pub const PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// users handled at once
+const BATCH: i64 = 50;
+
+// posts listed in one digest, the newest ones
+const MAX_POSTS: i64 = 20;
+
+// Send due digests until the process exits, from the one instance leading
+// (see `locks`).
+pub fn spawn(state: AppState) {
+    locks::spawn_singleton(state.locks.clone(), "digest", move || {
+        let state = state.clone();
+        async move {
+            loop {
+                match run_once(&state).await {
+                    // there may be more
+                    Ok(count) if count as i64 == BATCH => continue,
+                    Ok(_) => {}
+                    Err(e) => error!("sending digests failed: {e}"),
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    });
+}
+
+// Send one batch of due digests, returning how many users were due.
+pub async fn run_once(state: &AppState) -> Result<usize, sqlx::Error> {
+    let due =
+        repo::preferences::claim_due_digests(&state.pool, PERIOD.as_secs_f64(), BATCH).await?;
+    for recipient in &due {
+        let posts =
+            repo::follows::posts_since(&state.pool, recipient.user_id, recipient.since, MAX_POSTS)
+                .await?;
+        if posts.is_empty() {
+            continue;
+        }
+        let base = state.config.public_url.as_deref().unwrap_or_default();
+        let unsubscribe = unsubscribe::link(state, recipient.user_id);
+        let (subject, body) = render(recipient, &posts, base, &unsubscribe);
+        state
+            .notifier
+            .notify(Notification::Digest {
+                user_id: recipient.user_id,
+                email: recipient.email.clone(),
+                subject,
+                body,
+                headers: unsubscribe::headers(state, recipient.user_id),
+            })
+            .await;
+    }
+    Ok(due.len())
+}
+
+// The digest's subject and text, its posts linked from `base`.
+fn render(
+    recipient: &DigestRecipient,
+    posts: &[DigestPost],
+    base: &str,
+    unsubscribe: &str,
+) -> (String, String) {
+    let total = posts.first().map_or(0, |post| post.total);
+    let subject = match total {
+        1 => String::from("Your weekly digest: 1 new post"),
+        total => format!("Your weekly digest: {total} new posts"),
+    };
+    let mut body = format!(
+        "Hi {},\n\nhere is what the people you follow posted this week:\n",
+        recipient.username
+    );
+    for post in posts {
+        body += &format!(
+            "\n* {} by {}, {}\n  {}\n  {base}{}\n",
+            post.title,
+            post.author,
+            post.created_at.format("%A %B %-d"),
+            meta::description(&post.body),
+            expand(POST_ROUTE, &post.public_id)
+        );
+    }
+    if total > posts.len() as i64 {
+        body += &format!("\n...and {} more.\n", total - posts.len() as i64);
+    }
+    body += &format!(
+        "\nYou get this digest because you turned it on, \
+         turn it off at {unsubscribe} or with `PUT /me/preferences`.\n"
+    );
+    (subject, body)
+}
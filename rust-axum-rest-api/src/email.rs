@@ -0,0 +1,218 @@
+// Validation of email addresses on signup.
+//
+// Every address has to look like one: a local part and a domain of dot
+// separated labels, within the lengths SMTP allows. With EMAIL_MX_CHECK=true
+// the domain must also be able to receive mail, which is asked of the
+// first nameserver in /etc/resolv.conf: a domain that doesn't exist, has a
+// null MX record ("."), or has neither MX nor address records is rejected
+// as undeliverable. The lookup fails open, an unreachable resolver or a
+// timeout lets the address through, it is there to catch typos rather than
+// to enforce anything.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+use crate::error::ApiError;
+
+const MAX_LENGTH: usize = 254;
+const MAX_LOCAL_LENGTH: usize = 64;
+
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+const TYPE_MX: u16 = 15;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+
+// the trimmed address, or a 422 "invalid_email" when it isn't one
+pub fn validate(raw: &str) -> Result<String, ApiError> {
+    let email = raw.trim();
+    let valid = email.len() <= MAX_LENGTH
+        && email.split_once('@').is_some_and(|(local, domain)| {
+            valid_local_part(local) && parse_domain(domain).is_some()
+        });
+    if !valid {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_email",
+            format!("{email:?} is not an email address"),
+        )
+        .with("field", "email"));
+    }
+    Ok(email.to_owned())
+}
+
+// fail with a 422 "undeliverable_email" when the address's domain is known
+// not to accept mail
+pub async fn check_deliverable(email: &str) -> Result<(), ApiError> {
+    let Some(domain) = email.rsplit_once('@').map(|(_, domain)| domain) else {
+        return Ok(());
+    };
+    match accepts_mail(domain).await {
+        Ok(false) => Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "undeliverable_email",
+            format!("{domain} does not accept email"),
+        )
+        .with("field", "email")),
+        Ok(true) => Ok(()),
+        Err(e) => {
+            warn!("MX lookup for {domain} failed, accepting it: {e}");
+            Ok(())
+        }
+    }
+}
+
+// printable ASCII without the characters that need quoting
+fn valid_local_part(local: &str) -> bool {
+    !local.is_empty()
+        && local.len() <= MAX_LOCAL_LENGTH
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-/=?^_`{|}~.".contains(&b))
+}
+
+// the labels of a domain with at least two of them
+fn parse_domain(domain: &str) -> Option<Vec<&str>> {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        });
+    valid.then_some(labels)
+}
+
+async fn accepts_mail(domain: &str) -> Result<bool, String> {
+    let resolver = nameserver().ok_or("no nameserver in /etc/resolv.conf")?;
+    let labels = parse_domain(domain).ok_or("invalid domain")?;
+    let id = rand::random::<u16>();
+    let query = mx_query(id, &labels);
+
+    let bind: SocketAddr = if resolver.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+    socket
+        .send_to(&query, resolver)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut response = [0; 1500];
+    let len = tokio::time::timeout(DNS_TIMEOUT, socket.recv(&mut response))
+        .await
+        .map_err(|_| "timed out")?
+        .map_err(|e| e.to_string())?;
+
+    match parse_mx_response(id, &response[..len])? {
+        MxAnswer::NoSuchDomain => Ok(false),
+        MxAnswer::NullMx => Ok(false),
+        MxAnswer::Exchanges => Ok(true),
+        // without MX records mail goes to the domain's own address
+        MxAnswer::NoRecords => Ok(tokio::net::lookup_host((domain, 25))
+            .await
+            .map(|mut addrs| addrs.next().is_some())
+            .unwrap_or(false)),
+    }
+}
+
+fn nameserver() -> Option<SocketAddr> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf").ok()?;
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|addr| addr.trim().parse().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+}
+
+fn mx_query(id: u16, labels: &[&str]) -> Vec<u8> {
+    let mut query = Vec::with_capacity(32);
+    query.extend(id.to_be_bytes());
+    // recursion desired, one question
+    query.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in labels {
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(TYPE_MX.to_be_bytes());
+    query.extend(CLASS_IN.to_be_bytes());
+    query
+}
+
+enum MxAnswer {
+    NoSuchDomain,
+    NoRecords,
+    NullMx,
+    Exchanges,
+}
+
+fn parse_mx_response(id: u16, response: &[u8]) -> Result<MxAnswer, String> {
+    let u16_at = |at: usize| {
+        response
+            .get(at..at + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or("truncated response")
+    };
+    if u16_at(0)? != id {
+        return Err("response to another query".into());
+    }
+    let flags = u16_at(2)?;
+    if flags & 0x000f == RCODE_NXDOMAIN {
+        return Ok(MxAnswer::NoSuchDomain);
+    }
+    if flags & 0x000f != 0 {
+        return Err(format!("resolver answered with rcode {}", flags & 0x000f));
+    }
+
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(response, at)? + 4;
+    }
+    let mut exchanges = 0;
+    let mut null_mx = false;
+    for _ in 0..answers {
+        at = skip_name(response, at)?;
+        let kind = u16_at(at)?;
+        let rdata_len = usize::from(u16_at(at + 8)?);
+        let rdata = at + 10;
+        if kind == TYPE_MX {
+            // preference, then the exchange, the root name for a null MX
+            if response.get(rdata + 2) == Some(&0) {
+                null_mx = true;
+            } else {
+                exchanges += 1;
+            }
+        }
+        at = rdata + rdata_len;
+    }
+    Ok(match (exchanges, null_mx) {
+        (0, true) => MxAnswer::NullMx,
+        (0, false) => MxAnswer::NoRecords,
+        _ => MxAnswer::Exchanges,
+    })
+}
+
+// the offset just past the (possibly compressed) name at `at`
+fn skip_name(response: &[u8], mut at: usize) -> Result<usize, String> {
+    loop {
+        let len = *response.get(at).ok_or("truncated name")?;
+        match len {
+            0 => return Ok(at + 1),
+            len if len & 0xc0 == 0xc0 => return Ok(at + 2),
+            len => at += 1 + usize::from(len),
+        }
+    }
+}
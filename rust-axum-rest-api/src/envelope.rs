@@ -0,0 +1,103 @@
+// Optional response envelope. Some client frameworks want every response
+// wrapped as
+//
+//     {"data": …, "meta": {"request_id": "…", "total_count": 5}, "errors": []}
+//
+// (with `"total_exact": false` as well when the total is estimated, and
+// the pages of a paginated list as `_links`, e.g.
+// `"_links": {"next": {"href": "/posts?page=3&per_page=10"}, …}`)
+//
+// while others want the bare bodies, so it is off unless RESPONSE_ENVELOPE is
+// set, and `?envelope=true|false` overrides the setting per request.
+//
+// Successful JSON responses are wrapped by `wrap`, right around the handlers
+// so that ETag and Content-Length describe the wrapped body. Errors can come
+// from further out (405s, panics), `ApiError` wraps itself instead, with
+// `data` null and the error in `errors`. Other formats pass through as is.
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{Request, State};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Map, Value};
+
+use crate::pagination::{self, X_TOTAL_COUNT, X_TOTAL_COUNT_EXACT};
+use crate::request_id;
+use crate::state::AppState;
+
+tokio::task_local! {
+    static ENVELOPE: bool;
+}
+
+// whether the response to the request being handled is enveloped
+pub fn enabled() -> bool {
+    ENVELOPE.try_with(|enabled| *enabled).unwrap_or(false)
+}
+
+// decide on the envelope for the whole request
+pub async fn scope(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let requested = form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+        .find(|(key, _)| key == "envelope")
+        .and_then(|(_, value)| value.parse().ok());
+    let enabled = requested.unwrap_or(state.config.envelope);
+    ENVELOPE.scope(enabled, next.run(request)).await
+}
+
+// envelope successful JSON responses
+pub async fn wrap(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !enabled() || !response.status().is_success() || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Ok(data) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let mut meta = meta();
+    let total = parts
+        .headers
+        .get(X_TOTAL_COUNT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<i64>().ok());
+    if let Some(total) = total {
+        meta.insert("total_count".to_owned(), Value::from(total));
+    }
+    if parts.headers.contains_key(X_TOTAL_COUNT_EXACT) {
+        meta.insert("total_exact".to_owned(), Value::from(false));
+    }
+    let mut body = json!({ "data": data, "meta": meta, "errors": [] });
+    let pages = pagination::links(&parts.headers);
+    if !pages.is_empty() {
+        let links: Map<String, Value> = pages
+            .into_iter()
+            .map(|(rel, uri)| (rel.to_owned(), json!({ "href": uri })))
+            .collect();
+        body["_links"] = Value::Object(links);
+    }
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body.to_string()))
+}
+
+// an error body in the envelope
+pub fn error(error: Map<String, Value>) -> Value {
+    json!({ "data": null, "meta": meta(), "errors": [error] })
+}
+
+fn meta() -> Map<String, Value> {
+    let mut meta = Map::new();
+    if let Some(id) = request_id::current() {
+        meta.insert("request_id".to_owned(), Value::from(id));
+    }
+    meta
+}
@@ -0,0 +1,229 @@
+// The JSON error format shared by all endpoints:
+//
+//     {"error": "not_found", "message": "no route for GET /nope", "request_id": "…"}
+//
+// `error` is a stable machine readable code, `message` is meant for humans and
+// any further fields depend on the error.
+// With the response envelope on, the same object is the single entry of
+// `errors` and the request id moves to `meta` (see `envelope`). Keys follow
+// the requested case (see `case`).
+//
+// Database errors convert into `ApiError` so handlers can use `?`: a missing
+// row is a 404 (`or_not_found` says what was missing), unique violations
+// become a 409 naming the conflicting field, foreign key violations a 422
+// naming the field whose referenced row doesn't exist,
+// queries the circuit breaker failed fast a 503 with Retry-After (see
+// `repo::breaker`), anything else is logged and reported as a bare 500.
+//
+// With ERROR_DETAILS on, as it is in the development profile (see
+// `profile`), a 500 for a database error or a panic also says what went
+// wrong in `detail`.
+
+use std::fmt;
+
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::error::DatabaseError;
+use tracing::error;
+
+use crate::repo::breaker;
+use crate::state::AppState;
+use crate::{case, envelope, request_id};
+
+tokio::task_local! {
+    static DETAILS: bool;
+}
+
+// decide whether errors say what went wrong for the whole request
+pub async fn scope(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    DETAILS
+        .scope(state.config.error_details, next.run(request))
+        .await
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    pub details: Map<String, Value>,
+    // seconds for the Retry-After header, if any
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            details: Map::new(),
+            retry_after: None,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    // the details stay in the logs
+    pub fn internal() -> Self {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal",
+            "the server hit an unexpected error",
+        )
+    }
+
+    // add an extra field to the body
+    pub fn with(mut self, key: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self.details.insert(key.to_owned(), value);
+        self
+    }
+
+    // say what went wrong, when ERROR_DETAILS is on
+    pub fn detail(self, detail: impl fmt::Display) -> Self {
+        if DETAILS.try_with(|details| *details).unwrap_or(false) {
+            return self.with("detail", detail.to_string());
+        }
+        self
+    }
+
+    // tell clients to come back in so many seconds
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let retry_after = self
+            .retry_after
+            .map(|seconds| [(RETRY_AFTER, seconds.to_string())]);
+        let mut body = Map::new();
+        body.insert("error".to_owned(), Value::from(self.code));
+        body.insert("message".to_owned(), Value::from(self.message));
+        body.extend(self.details);
+        if envelope::enabled() {
+            let body = case::apply(envelope::error(body));
+            return (self.status, retry_after, Json(body)).into_response();
+        }
+        if let Some(id) = request_id::current() {
+            body.insert("request_id".to_owned(), Value::from(id));
+        }
+        (
+            self.status,
+            retry_after,
+            Json(case::apply(Value::Object(body))),
+        )
+            .into_response()
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        if let Some(open) = breaker::open(&err) {
+            return ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "database_unavailable",
+                "the database is unavailable, try again later",
+            )
+            .retry_after(open.retry_after_secs());
+        }
+        match &err {
+            sqlx::Error::RowNotFound => ApiError::not_found("not found"),
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                let field = constrained_field(db.as_ref());
+                ApiError::new(
+                    StatusCode::CONFLICT,
+                    "conflict",
+                    format!("{field} is already taken"),
+                )
+                .with("field", field)
+            }
+            sqlx::Error::Database(db) if db.is_foreign_key_violation() => {
+                let field = constrained_field(db.as_ref());
+                // `user_id` refers to a user, `parent_id` to a parent, ...
+                let entity = field
+                    .strip_suffix("_id")
+                    .unwrap_or(&field)
+                    .replace('_', " ");
+                ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "invalid_reference",
+                    format!("the referenced {entity} does not exist"),
+                )
+                .with("field", field)
+            }
+            _ => {
+                error!("database error: {err}");
+                ApiError::internal().detail(&err)
+            }
+        }
+    }
+}
+
+pub trait OrNotFound<T> {
+    // like `?` on the sqlx error, but a missing row reads "<what> not found"
+    fn or_not_found(self, what: &str) -> Result<T, ApiError>;
+}
+
+impl<T> OrNotFound<T> for Result<T, sqlx::Error> {
+    fn or_not_found(self, what: &str) -> Result<T, ApiError> {
+        self.map_err(|err| match err {
+            sqlx::Error::RowNotFound => ApiError::not_found(format!("{what} not found")),
+            err => err.into(),
+        })
+    }
+}
+
+// The column behind a constraint, going by Postgres' default naming of
+// `<table>_<column>_key` and `<table>_<column>_fkey`. A keyed hash stands for
+// the column it hashes, `email_hash` for `email` (see `repo::emails`).
+fn constrained_field(db: &dyn DatabaseError) -> String {
+    let constraint = db.constraint().unwrap_or_default();
+    let column = db
+        .table()
+        .and_then(|table| constraint.strip_prefix(table))
+        .and_then(|rest| rest.strip_prefix('_'))
+        .unwrap_or(constraint);
+    let column = column
+        .strip_suffix("_fkey")
+        .or_else(|| column.strip_suffix("_key"))
+        .unwrap_or(column);
+    column.strip_suffix("_hash").unwrap_or(column).to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_missing_rows_are_not_found() {
+        let missing = ApiError::from(sqlx::Error::RowNotFound);
+        assert_eq!(
+            (missing.status, missing.code),
+            (StatusCode::NOT_FOUND, "not_found")
+        );
+        let missing = Err::<(), _>(sqlx::Error::RowNotFound)
+            .or_not_found("post")
+            .unwrap_err();
+        assert_eq!(missing.message, "post not found");
+
+        for err in [sqlx::Error::PoolClosed, sqlx::Error::PoolTimedOut] {
+            let failed = Err::<(), _>(err).or_not_found("post").unwrap_err();
+            assert_eq!(
+                (failed.status, failed.code),
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal")
+            );
+        }
+    }
+}
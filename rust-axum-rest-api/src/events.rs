@@ -0,0 +1,75 @@
+// In-process events about what was just written, for whoever follows along
+// live: the GraphQL subscriptions, and webhooks which are queued from here.
+// Only what readers may see is published, so posts once they are published
+// and comments on those.
+//
+// Events are kept per tenant, listeners only see the ones of the tenant
+// they subscribed in. A listener too slow to keep up skips what it missed.
+//
+// With several instances, listeners also see the events published by the
+// others when `relay` passes them on; background work only sees this
+// instance's, so that each event is handled once.
+
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::models::{Comment, Post};
+use crate::tenant;
+
+// events held for slow listeners before they start missing some
+const CAPACITY: usize = 256;
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    PostCreated(Post),
+    CommentAdded(Comment),
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    // published by this instance
+    local: broadcast::Sender<(i32, Event)>,
+    // those and the ones relayed from other instances
+    live: broadcast::Sender<(i32, Event)>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus {
+            local: broadcast::channel(CAPACITY).0,
+            live: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    // tell the current tenant's listeners, if there are any
+    pub fn publish(&self, event: Event) {
+        let tenant_id = tenant::current();
+        let _ = self.local.send((tenant_id, event.clone()));
+        let _ = self.live.send((tenant_id, event));
+    }
+
+    // tell the listeners of `tenant_id` about an event another instance
+    // published, see `relay`
+    pub fn relay(&self, tenant_id: i32, event: Event) {
+        let _ = self.live.send((tenant_id, event));
+    }
+
+    // every tenant's events published by this instance from now on along
+    // with their tenant, for background work rather than a listener of one
+    // request, see `webhooks`
+    pub fn subscribe_local(&self) -> broadcast::Receiver<(i32, Event)> {
+        self.local.subscribe()
+    }
+
+    // the current tenant's events from now on, wherever they were published
+    pub fn subscribe(&self) -> impl Stream<Item = Event> {
+        let tenant_id = tenant::current();
+        BroadcastStream::new(self.live.subscribe()).filter_map(move |received| match received {
+            Ok((tenant, event)) if tenant == tenant_id => Some(event),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,308 @@
+// Bulk export endpoints. Rows are streamed straight from the database cursor to
+// the client, so the response body only ever holds a handful of rows in memory
+// and a slow client slows down the fetch instead of growing a buffer.
+//
+// `GET /me/export.zip` is built the same way, a post at a time: the archive is
+// written into a small pipe as the response body drains it. Posts have no
+// attachments in this tree, so each comes as markdown and as JSON.
+
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipDateTime, ZipEntryBuilder};
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{BoxError, Extension};
+use futures::{Stream, TryStreamExt};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use tokio::io::{AsyncWrite, DuplexStream};
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+use crate::auth::{Actor, RequireAdmin};
+use crate::error::ApiError;
+use crate::methods::{self, Head};
+use crate::models::{Post, User};
+use crate::repo::emails;
+use crate::state::AppState;
+use crate::tenant;
+
+// handler for "GET /posts/export.ndjson" rest API endpoint; a HEAD doesn't
+// run the export
+pub async fn posts_ndjson(
+    State(AppState { pool, .. }): State<AppState>,
+    head: Option<Extension<Head>>,
+) -> impl IntoResponse {
+    let body = match head {
+        Some(_) => methods::unsized_body(),
+        None => {
+            let lines = ndjson_lines(pool, tenant::current());
+            Body::from_stream(lines.inspect_err(|e| error!("posts export aborted: {e}")))
+        }
+    };
+    ([(CONTENT_TYPE, "application/x-ndjson")], body)
+}
+
+// one serialized post of the tenant per line, in id order; the tenant is
+// passed in as the body is streamed after the request's scope has ended
+fn ndjson_lines(
+    pool: Pool<Postgres>,
+    tenant_id: i32,
+) -> impl Stream<Item = Result<Vec<u8>, BoxError>> {
+    async_stream::try_stream! {
+        let mut posts = sqlx::query_as!(Post, r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at FROM posts WHERE deleted_at IS NULL AND status = 'published' AND tenant_id = $1 ORDER BY id"#, tenant_id)
+            .fetch(&pool);
+        while let Some(post) = posts.try_next().await? {
+            let mut line = serde_json::to_vec(&post)?;
+            line.push(b'\n');
+            yield line;
+        }
+    }
+}
+
+// Models that can be written as CSV rows. `COLUMNS` lists every exportable
+// column in default order, `field` renders a single one of them.
+trait CsvRecord {
+    const COLUMNS: &'static [&'static str];
+
+    fn field(&self, column: &str) -> String;
+}
+
+impl CsvRecord for Post {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "uuid",
+        "public_id",
+        "user_id",
+        "org_id",
+        "authors",
+        "title",
+        "body",
+        "status",
+        "created_at",
+        "updated_at",
+    ];
+
+    fn field(&self, column: &str) -> String {
+        match column {
+            "id" => self.id.to_string(),
+            "uuid" => self.uuid.to_string(),
+            "public_id" => self.public_id.clone(),
+            "user_id" => self.user_id.map(|id| id.to_string()).unwrap_or_default(),
+            "org_id" => self.org_id.map(|id| id.to_string()).unwrap_or_default(),
+            // the ids separated by spaces
+            "authors" => self
+                .authors
+                .iter()
+                .map(i32::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+            "title" => self.title.clone(),
+            "body" => self.body.clone(),
+            "status" => self.status.clone(),
+            "created_at" => self.created_at.to_rfc3339(),
+            "updated_at" => self.updated_at.to_rfc3339(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl CsvRecord for User {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "uuid",
+        "username",
+        "email",
+        "created_at",
+        "updated_at",
+    ];
+
+    fn field(&self, column: &str) -> String {
+        match column {
+            "id" => self.id.to_string(),
+            "uuid" => self.uuid.to_string(),
+            "username" => self.username.clone(),
+            "email" => self.email.clone(),
+            "created_at" => self.created_at.to_rfc3339(),
+            "updated_at" => self.updated_at.to_rfc3339(),
+            _ => String::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CsvParams {
+    // comma separated list of columns, all columns when absent
+    columns: Option<String>,
+}
+
+impl CsvParams {
+    fn columns<T: CsvRecord>(&self) -> Result<Vec<&'static str>, ApiError> {
+        let Some(columns) = self.columns.as_deref() else {
+            return Ok(T::COLUMNS.to_vec());
+        };
+        columns
+            .split(',')
+            .map(|name| {
+                let name = name.trim();
+                T::COLUMNS
+                    .iter()
+                    .find(|column| **column == name)
+                    .copied()
+                    .ok_or_else(|| {
+                        ApiError::new(
+                            StatusCode::BAD_REQUEST,
+                            "invalid_columns",
+                            format!(
+                                "unknown column `{name}`, expected some of {}",
+                                T::COLUMNS.join(",")
+                            ),
+                        )
+                        .with("field", "columns")
+                    })
+            })
+            .collect()
+    }
+}
+
+// handler for "GET /posts.csv" rest API endpoint
+pub async fn posts_csv(
+    State(AppState { pool, .. }): State<AppState>,
+    Query(params): Query<CsvParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let columns = params.columns::<Post>()?;
+    let tenant_id = tenant::current();
+    let rows = async_stream::try_stream! {
+        yield csv_record(columns.iter().copied())?;
+        let mut posts = sqlx::query_as!(Post, r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at FROM posts WHERE deleted_at IS NULL AND status = 'published' AND tenant_id = $1 ORDER BY id"#, tenant_id)
+            .fetch(&pool);
+        while let Some(post) = posts.try_next().await? {
+            yield csv_record(columns.iter().map(|column| post.field(column)))?;
+        }
+    };
+    Ok(csv_response("posts.csv", rows))
+}
+
+// handler for "GET /users.csv" rest API endpoint, admins only
+pub async fn users_csv(
+    _admin: RequireAdmin,
+    State(AppState { pool, .. }): State<AppState>,
+    Query(params): Query<CsvParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let columns = params.columns::<User>()?;
+    let tenant_id = tenant::current();
+    let rows = async_stream::try_stream! {
+        yield csv_record(columns.iter().copied())?;
+        let mut users = sqlx::query_as!(User, "SELECT id, uuid, username, email, avatar_url, posts_count, created_at, updated_at FROM users WHERE tenant_id = $1 ORDER BY id", tenant_id)
+            .fetch(&pool);
+        while let Some(user) = users.try_next().await? {
+            let user = emails::open_user(user)?;
+            yield csv_record(columns.iter().map(|column| user.field(column)))?;
+        }
+    };
+    Ok(csv_response("users.csv", rows))
+}
+
+fn csv_response(
+    filename: &str,
+    rows: impl Stream<Item = Result<Vec<u8>, BoxError>> + Send + 'static,
+) -> impl IntoResponse {
+    let disposition = format!("attachment; filename=\"{filename}\"");
+    let body = Body::from_stream(rows.inspect_err(|e| error!("csv export aborted: {e}")));
+    (
+        [
+            (CONTENT_TYPE, String::from("text/csv; charset=utf-8")),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+}
+
+// a single CSV line, quoted by the csv crate where needed
+fn csv_record<I>(fields: I) -> Result<Vec<u8>, BoxError>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
+// how much of the archive may be written ahead of the client
+const ZIP_PIPE_BYTES: usize = 64 * 1024;
+
+// handler for "GET /me/export.zip" rest API endpoint, the acting user's posts,
+// drafts and all, as `posts/<public id>.md` and `posts/<public id>.json`
+pub async fn my_posts_zip(
+    State(AppState { pool, .. }): State<AppState>,
+    actor: Actor,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_id = actor.require_user()?;
+    let (writer, reader) = tokio::io::duplex(ZIP_PIPE_BYTES);
+    let writing = tokio::spawn(write_posts_zip(pool, tenant::current(), user_id, writer));
+    let body = Body::from_stream(
+        zip_body(reader, writing).inspect_err(|e| error!("zip export aborted: {e}")),
+    );
+    Ok((
+        [
+            (CONTENT_TYPE, "application/zip"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"posts.zip\""),
+        ],
+        body,
+    ))
+}
+
+// the archive as it is written, failing at the end if writing it did, so
+// that the client sees the download break rather than a truncated zip
+fn zip_body(
+    reader: DuplexStream,
+    writing: tokio::task::JoinHandle<Result<(), BoxError>>,
+) -> impl Stream<Item = Result<axum::body::Bytes, BoxError>> {
+    async_stream::try_stream! {
+        let mut chunks = ReaderStream::new(reader);
+        while let Some(chunk) = chunks.try_next().await? {
+            yield chunk;
+        }
+        writing.await??;
+    }
+}
+
+async fn write_posts_zip(
+    pool: Pool<Postgres>,
+    tenant_id: i32,
+    user_id: i32,
+    writer: impl AsyncWrite + Unpin,
+) -> Result<(), BoxError> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let mut posts = sqlx::query_as!(Post, r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at FROM posts WHERE deleted_at IS NULL AND user_id = $1 AND tenant_id = $2 ORDER BY id"#, user_id, tenant_id)
+        .fetch(&pool);
+    while let Some(post) = posts.try_next().await? {
+        let modified = ZipDateTime::from_chrono(&post.updated_at);
+        let entry = |extension: &str| {
+            let name = format!("posts/{}.{extension}", post.public_id);
+            ZipEntryBuilder::new(name.into(), Compression::Deflate).last_modification_date(modified)
+        };
+        zip.write_entry_whole(entry("md"), markdown(&post).as_bytes())
+            .await?;
+        zip.write_entry_whole(entry("json"), &serde_json::to_vec_pretty(&post)?)
+            .await?;
+    }
+    zip.close().await?;
+    Ok(())
+}
+
+// the post's title as a heading over its body, the rest as front matter
+fn markdown(post: &Post) -> String {
+    format!(
+        "---\nid: {}\nstatus: {}\ncreated_at: {}\nupdated_at: {}\n---\n\n# {}\n\n{}\n",
+        post.public_id,
+        post.status,
+        post.created_at.to_rfc3339(),
+        post.updated_at.to_rfc3339(),
+        post.title,
+        post.body
+    )
+}
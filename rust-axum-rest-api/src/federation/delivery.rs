@@ -0,0 +1,108 @@
+// The delivery worker: sends queued activities to remote inboxes, signed
+// with the sender's key. Failed deliveries are retried with exponential
+// backoff, starting at `FIRST_RETRY`, and given up on after `MAX_ATTEMPTS`.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{Pool, Postgres};
+use tracing::{error, info, warn};
+
+use crate::federation::{http, key_id, keys, signatures, ACTIVITY_JSON};
+use crate::link_previews::fetch::{check_url, reason};
+use crate::repo;
+use crate::repo::federation::Delivery;
+
+// deliveries taken at once
+const BATCH: i64 = 20;
+
+// how long a taken delivery is held before another worker may retry it,
+// longer than a request can take
+const LEASE: Duration = Duration::from_secs(60);
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const FIRST_RETRY: chrono::Duration = chrono::Duration::minutes(1);
+
+const MAX_ATTEMPTS: i32 = 8;
+
+// Poll for due deliveries until the process exits.
+pub fn spawn(pool: Pool<Postgres>, base: String, private_hosts: bool) {
+    tokio::spawn(async move {
+        loop {
+            match run_once(&pool, &base, private_hosts).await {
+                // there may be more
+                Ok(count) if count as i64 == BATCH => continue,
+                Ok(_) => {}
+                Err(e) => error!("federation delivery failed: {e}"),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+// Deliver one batch of due activities, returning how many were attempted.
+// `private_hosts` lets them go to private addresses.
+pub async fn run_once(
+    pool: &Pool<Postgres>,
+    base: &str,
+    private_hosts: bool,
+) -> Result<usize, sqlx::Error> {
+    let due = repo::federation::claim_due(pool, BATCH, LEASE.as_secs_f64()).await?;
+    for delivery in &due {
+        match send(pool, base, delivery, private_hosts).await {
+            Ok(()) => repo::federation::delivered(pool, delivery.id).await?,
+            Err(reason) => {
+                let attempts = delivery.attempts + 1;
+                let retry_at = (attempts < MAX_ATTEMPTS)
+                    .then(|| Utc::now() + FIRST_RETRY * 2i32.pow(attempts as u32 - 1));
+                match retry_at {
+                    Some(at) => info!(
+                        "delivery {} to {} failed, retrying at {at}: {reason}",
+                        delivery.id, delivery.inbox
+                    ),
+                    None => warn!(
+                        "giving up on delivery {} to {}: {reason}",
+                        delivery.id, delivery.inbox
+                    ),
+                }
+                repo::federation::failed(pool, delivery.id, &reason, retry_at).await?;
+            }
+        }
+    }
+    Ok(due.len())
+}
+
+async fn send(
+    pool: &Pool<Postgres>,
+    base: &str,
+    delivery: &Delivery,
+    private_hosts: bool,
+) -> Result<(), String> {
+    let keys = keys(pool, delivery.sender_id)
+        .await
+        .map_err(|e| e.message)?;
+    let url = reqwest::Url::parse(&delivery.inbox).map_err(|e| e.to_string())?;
+    check_url(&url, private_hosts)?;
+    let body = serde_json::to_vec(&delivery.activity).map_err(|e| e.to_string())?;
+    let headers = signatures::sign(
+        &keys.private_key_pem,
+        &key_id(base, delivery.sender_id),
+        &url,
+        &body,
+    )?;
+    let mut request = http(private_hosts)
+        .post(url)
+        .header("content-type", ACTIVITY_JSON)
+        .body(body);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    // a redirect isn't followed: the signature covers the inbox's host and
+    // path, and the next one's address would need checking
+    let response = request.send().await.map_err(|e| reason(&e))?;
+    if !response.status().is_success() {
+        return Err(format!("the inbox answered {}", response.status()));
+    }
+    Ok(())
+}
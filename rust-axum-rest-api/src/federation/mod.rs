@@ -0,0 +1,397 @@
+// ActivityPub federation: fediverse servers (Mastodon and the like) can find
+// our users through WebFinger, follow them and receive their published posts.
+//
+// Every user is an actor at `/ap/users/:id` with an inbox, an outbox and a
+// followers collection, and an RSA key generated on first use. Follow and
+// Undo requests arrive at the inbox, signed by the remote actor (see
+// `signatures`), and followers are answered with an Accept. Posts are
+// published as Create activities of Notes, queued for each follower's inbox
+// and delivered signed by a background worker that retries with backoff
+// (see `delivery`).
+//
+// Federation takes PUBLIC_URL, which actor and object ids are built from;
+// unset, the endpoints are 404s and nothing is published.
+//
+// Keys are only fetched from, and activities only delivered to, public
+// addresses, through the same resolver as link previews (see
+// `link_previews::fetch`), so that a remote actor can't make the server
+// request its own network.
+// Redirects of key fetches are followed by hand to check theirs too, and
+// deliveries aren't redirected. FEDERATION_PRIVATE_HOSTS=true lifts this,
+// for servers federating on a private network.
+
+pub mod delivery;
+pub mod signatures;
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use reqwest::header::LOCATION;
+use reqwest::{redirect, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::error::{ApiError, OrNotFound};
+use crate::link_previews::fetch::{check_url, guarded, reason};
+use crate::models::Post;
+use crate::repo;
+use crate::repo::federation::{ActorKeys, Note};
+use crate::state::AppState;
+
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+
+const JRD_JSON: &str = "application/jrd+json";
+
+const CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+
+const SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+
+// the audience of everything we publish
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+// how many of the latest posts the outbox shows
+const OUTBOX_SIZE: i64 = 20;
+
+const MAX_REDIRECTS: usize = 3;
+
+// the client fetching remote keys and delivering activities, to public
+// addresses only unless `private_hosts`
+pub(crate) fn http(private_hosts: bool) -> &'static reqwest::Client {
+    static PUBLIC: OnceLock<reqwest::Client> = OnceLock::new();
+    static ANY: OnceLock<reqwest::Client> = OnceLock::new();
+    let client = if private_hosts { &ANY } else { &PUBLIC };
+    client.get_or_init(|| {
+        let builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .redirect(redirect::Policy::none())
+            .user_agent(concat!("rust-axum-rest-api/", env!("CARGO_PKG_VERSION")));
+        guarded(builder, private_hosts)
+            .build()
+            .expect("the federation HTTP client builds")
+    })
+}
+
+// GET the ActivityPub document at `url`, from public addresses only unless
+// `private_hosts`
+pub(crate) async fn fetch(url: &str, private_hosts: bool) -> Result<Value, String> {
+    let mut url = Url::parse(url).map_err(|e| e.to_string())?;
+    for _ in 0..=MAX_REDIRECTS {
+        check_url(&url, private_hosts)?;
+        let response = http(private_hosts)
+            .get(url.clone())
+            .header("accept", ACTIVITY_JSON)
+            .send()
+            .await
+            .map_err(|e| reason(&e))?;
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or("a redirect without a location")?;
+            url = url.join(location).map_err(|e| e.to_string())?;
+            continue;
+        }
+        return response
+            .error_for_status()
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string());
+    }
+    Err(String::from("too many redirects"))
+}
+
+pub fn actor_url(base: &str, user_id: i32) -> String {
+    format!("{base}/ap/users/{user_id}")
+}
+
+pub fn key_id(base: &str, user_id: i32) -> String {
+    format!("{}#main-key", actor_url(base, user_id))
+}
+
+// PUBLIC_URL, without which there is no federation
+fn base(state: &AppState) -> Result<&str, ApiError> {
+    state
+        .config
+        .public_url
+        .as_deref()
+        .ok_or_else(|| ApiError::not_found("federation is off, it takes PUBLIC_URL"))
+}
+
+// A user's key pair, generated the first time they need one.
+pub async fn keys(pool: &Pool<Postgres>, user_id: i32) -> Result<ActorKeys, ApiError> {
+    if let Some(keys) = repo::federation::keys(pool, user_id).await? {
+        return Ok(keys);
+    }
+    let generated = tokio::task::spawn_blocking(signatures::generate_keys)
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|generated| generated)
+        .map_err(|e| {
+            error!("generating the keys of user {user_id} failed: {e}");
+            ApiError::internal()
+        })?;
+    Ok(repo::federation::store_keys(pool, user_id, &generated).await?)
+}
+
+fn activity_json(document: Value) -> Response {
+    ([(CONTENT_TYPE, ACTIVITY_JSON)], Json(document)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct WebfingerQuery {
+    pub resource: String,
+}
+
+// handler for "GET /.well-known/webfinger" rest API endpoint, finds the
+// actor of `acct:<username>@<host>`
+pub async fn webfinger(
+    State(state): State<AppState>,
+    Query(query): Query<WebfingerQuery>,
+) -> Result<Response, ApiError> {
+    let base = base(&state)?;
+    let account = query
+        .resource
+        .strip_prefix("acct:")
+        .unwrap_or(&query.resource);
+    let unknown = || ApiError::not_found(format!("no account {}", query.resource));
+    let (username, host) = account.split_once('@').ok_or_else(unknown)?;
+    if Some(host) != authority(base).as_deref() {
+        return Err(unknown());
+    }
+    let user = repo::users::find_by_username(&state.pool, username)
+        .await?
+        .ok_or_else(unknown)?;
+    let actor = actor_url(base, user.id);
+    let document = json!({
+        "subject": format!("acct:{}@{host}", user.username),
+        "aliases": [actor],
+        "links": [{"rel": "self", "type": ACTIVITY_JSON, "href": actor}],
+    });
+
+    Ok(([(CONTENT_TYPE, JRD_JSON)], Json(document)).into_response())
+}
+
+// host and port of a URL
+fn authority(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    match url.port() {
+        Some(port) => Some(format!("{}:{port}", url.host_str()?)),
+        None => url.host_str().map(str::to_owned),
+    }
+}
+
+// handler for "GET /ap/users/:id" rest API endpoint, the user as an actor
+pub async fn actor(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Response, ApiError> {
+    let base = base(&state)?;
+    let user = state.users.get(id).await.or_not_found("user")?;
+    let keys = keys(&state.pool, user.id).await?;
+    let actor = actor_url(base, user.id);
+
+    Ok(activity_json(json!({
+        "@context": [CONTEXT, SECURITY_CONTEXT],
+        "id": actor,
+        "type": "Person",
+        "preferredUsername": user.username,
+        "name": user.username,
+        "url": format!("{base}/users/{}", user.id),
+        "inbox": format!("{actor}/inbox"),
+        "outbox": format!("{actor}/outbox"),
+        "followers": format!("{actor}/followers"),
+        "publicKey": {
+            "id": key_id(base, user.id),
+            "owner": actor,
+            "publicKeyPem": keys.public_key_pem,
+        },
+    })))
+}
+
+// handler for "GET /ap/users/:id/outbox" rest API endpoint, the user's
+// latest published posts as Create activities
+pub async fn outbox(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Response, ApiError> {
+    let base = base(&state)?;
+    let user = state.users.get(id).await.or_not_found("user")?;
+    let total = repo::federation::count_notes(&state.pool, user.id).await?;
+    let notes = repo::federation::notes(&state.pool, user.id, OUTBOX_SIZE).await?;
+    let items: Vec<Value> = notes
+        .iter()
+        .map(|note| create_activity(base, user.id, note))
+        .collect();
+
+    Ok(activity_json(json!({
+        "@context": CONTEXT,
+        "id": format!("{}/outbox", actor_url(base, user.id)),
+        "type": "OrderedCollection",
+        "totalItems": total,
+        "orderedItems": items,
+    })))
+}
+
+// handler for "GET /ap/users/:id/followers" rest API endpoint, only how many
+// there are, the followers themselves aren't shown
+pub async fn followers(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<Response, ApiError> {
+    let base = base(&state)?;
+    let user = state.users.get(id).await.or_not_found("user")?;
+    let total = repo::federation::count_followers(&state.pool, user.id).await?;
+
+    Ok(activity_json(json!({
+        "@context": CONTEXT,
+        "id": format!("{}/followers", actor_url(base, user.id)),
+        "type": "OrderedCollection",
+        "totalItems": total,
+    })))
+}
+
+// handler for "POST /ap/users/:id/inbox" rest API endpoint, takes signed
+// Follow and Undo Follow activities and accepts the rest without acting on
+// them
+pub async fn inbox(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let base = base(&state)?;
+    let user = state.users.get(id).await.or_not_found("user")?;
+    let target = uri
+        .path_and_query()
+        .map_or(uri.path(), |target| target.as_str());
+    let signer = signatures::verify(
+        &method,
+        target,
+        &headers,
+        &body,
+        state.config.federation_private_hosts,
+    )
+    .await?;
+    let activity: Value = serde_json::from_slice(&body).map_err(|e| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_activity",
+            format!("the activity isn't JSON: {e}"),
+        )
+    })?;
+    if activity["actor"].as_str() != Some(signer.id.as_str()) {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid_signature",
+            "the activity isn't the signer's",
+        ));
+    }
+
+    let actor = actor_url(base, user.id);
+    match activity["type"].as_str() {
+        Some("Follow") if object_id(&activity["object"]) == Some(actor.as_str()) => {
+            repo::federation::add_follower(&state.pool, user.id, &signer.id, &signer.inbox).await?;
+            let accept = json!({
+                "@context": CONTEXT,
+                "id": format!("{actor}#accepts/{}", Uuid::new_v4()),
+                "type": "Accept",
+                "actor": actor,
+                "object": activity,
+            });
+            repo::federation::enqueue(&state.pool, user.id, &[signer.inbox], &accept).await?;
+        }
+        Some("Undo") if activity["object"]["type"].as_str() == Some("Follow") => {
+            repo::federation::remove_follower(&state.pool, user.id, &signer.id).await?;
+        }
+        _ => {}
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+// the id of an object given inline or by reference
+fn object_id(object: &Value) -> Option<&str> {
+    object.as_str().or_else(|| object["id"].as_str())
+}
+
+// Queue a newly published post for its author's followers. Posts without an
+// author, and every post while federation is off, stay local.
+pub async fn publish(state: &AppState, post: &Post) -> Result<(), sqlx::Error> {
+    let (Some(base), Some(user_id)) = (state.config.public_url.as_deref(), post.user_id) else {
+        return Ok(());
+    };
+    let inboxes = repo::federation::follower_inboxes(&state.pool, user_id).await?;
+    if inboxes.is_empty() {
+        return Ok(());
+    }
+    let note = Note {
+        public_id: post.public_id.clone(),
+        title: post.title.clone(),
+        body: post.body.clone(),
+        created_at: post.created_at,
+    };
+    let activity = create_activity(base, user_id, &note);
+    repo::federation::enqueue(&state.pool, user_id, &inboxes, &activity).await
+}
+
+// a post as the Create activity of a Note addressed to everyone
+fn create_activity(base: &str, user_id: i32, note: &Note) -> Value {
+    let actor = actor_url(base, user_id);
+    let id = format!("{base}/posts/{}", note.public_id);
+    let published = note.created_at.to_rfc3339();
+    let to = [PUBLIC];
+    let cc = [format!("{actor}/followers")];
+    json!({
+        "@context": CONTEXT,
+        "id": format!("{id}/activity"),
+        "type": "Create",
+        "actor": actor,
+        "published": published,
+        "to": to,
+        "cc": cc,
+        "object": {
+            "id": id,
+            "type": "Note",
+            "attributedTo": actor,
+            "url": id,
+            "content": format!(
+                "<p><strong>{}</strong></p><p>{}</p>",
+                escape(&note.title),
+                escape(&note.body)
+            ),
+            "published": published,
+            "to": to,
+            "cc": cc,
+        },
+    })
+}
+
+// post text as HTML, which Note content and oEmbed snippets are
+pub(crate) fn escape(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\n' => html.push_str("<br>"),
+            c => html.push(c),
+        }
+    }
+    html
+}
@@ -0,0 +1,201 @@
+// HTTP signatures (draft-cavage-http-signatures, as the fediverse uses
+// them): requests between servers are signed with the sending actor's RSA
+// key over `(request-target) host date digest`, and the receiver verifies
+// them with the public key published in the actor document that `keyId`
+// points at.
+
+use axum::http::{HeaderMap, Method, StatusCode};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
+use rsa::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+};
+use rsa::sha2::{Digest, Sha256};
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde_json::Value;
+
+use crate::error::ApiError;
+use crate::federation::fetch;
+use crate::repo::federation::ActorKeys;
+
+const KEY_BITS: usize = 2048;
+
+// how far a request's Date may be from now
+const MAX_SKEW: Duration = Duration::hours(12);
+
+const SIGNED_HEADERS: &str = "(request-target) host date digest";
+
+// a fresh key pair, slow enough to be kept off the async threads
+pub fn generate_keys() -> Result<ActorKeys, String> {
+    let private =
+        RsaPrivateKey::new(&mut rsa::rand_core::OsRng, KEY_BITS).map_err(|e| e.to_string())?;
+    let public_key_pem = RsaPublicKey::from(&private)
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| e.to_string())?;
+    let private_key_pem = private
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| e.to_string())?
+        .to_string();
+    Ok(ActorKeys {
+        public_key_pem,
+        private_key_pem,
+    })
+}
+
+// the Digest header of a body
+pub fn digest(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64.encode(Sha256::digest(body)))
+}
+
+// The headers signing a POST of `body` to `url` with the key `key_id`
+// names: Host, Date, Digest and Signature.
+pub fn sign(
+    private_key_pem: &str,
+    key_id: &str,
+    url: &reqwest::Url,
+    body: &[u8],
+) -> Result<Vec<(&'static str, String)>, String> {
+    let key = RsaPrivateKey::from_pkcs8_pem(private_key_pem).map_err(|e| e.to_string())?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_owned(),
+        (None, _) => return Err(format!("{url} has no host")),
+    };
+    let date = httpdate::fmt_http_date(std::time::SystemTime::now());
+    let digest = digest(body);
+    let target = match url.query() {
+        Some(query) => format!("post {}?{query}", url.path()),
+        None => format!("post {}", url.path()),
+    };
+    let signing_string =
+        format!("(request-target): {target}\nhost: {host}\ndate: {date}\ndigest: {digest}");
+    let signature = SigningKey::<Sha256>::new(key).sign(signing_string.as_bytes());
+    let header = format!(
+        r#"keyId="{key_id}",algorithm="rsa-sha256",headers="{SIGNED_HEADERS}",signature="{}""#,
+        BASE64.encode(signature.to_bytes())
+    );
+    Ok(vec![
+        ("host", host),
+        ("date", date),
+        ("digest", digest),
+        ("signature", header),
+    ])
+}
+
+// The remote actor that signed a request, as its actor document describes
+// it.
+#[derive(Debug, Clone)]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+}
+
+// check the signature of a request to `target` (path and query) and return
+// who made it; its key is fetched from a private address only if
+// `private_hosts`
+pub async fn verify(
+    method: &Method,
+    target: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    private_hosts: bool,
+) -> Result<RemoteActor, ApiError> {
+    let header = headers
+        .get("signature")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| invalid("the request isn't signed"))?;
+    let params = parse_params(header);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let key_id = param("keyId").ok_or_else(|| invalid("the signature has no keyId"))?;
+    let signature = param("signature")
+        .and_then(|signature| BASE64.decode(signature).ok())
+        .ok_or_else(|| invalid("the signature isn't base64"))?;
+    let signed: Vec<&str> = param("headers").unwrap_or("date").split(' ').collect();
+    for required in ["(request-target)", "host", "date", "digest"] {
+        if !signed.contains(&required) {
+            return Err(invalid(format!("the signature must cover {required}")));
+        }
+    }
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| invalid(format!("the signed {name} header is missing")))
+    };
+    if header("digest")? != digest(body) {
+        return Err(invalid("the digest doesn't match the body"));
+    }
+    let date: DateTime<Utc> = httpdate::parse_http_date(header("date")?)
+        .map_err(|_| invalid("the date isn't an HTTP date"))?
+        .into();
+    if (Utc::now() - date).abs() > MAX_SKEW {
+        return Err(invalid("the request is too old or from the future"));
+    }
+    let mut lines = Vec::with_capacity(signed.len());
+    for name in &signed {
+        let value = match *name {
+            "(request-target)" => format!("{} {target}", method.as_str().to_lowercase()),
+            name => header(name)?.to_owned(),
+        };
+        lines.push(format!("{name}: {value}"));
+    }
+    let signing_string = lines.join("\n");
+
+    let (actor, public_key) = fetch_key(key_id, private_hosts).await?;
+    let signature = Signature::try_from(signature.as_slice())
+        .map_err(|_| invalid("the signature is malformed"))?;
+    VerifyingKey::<Sha256>::new(public_key)
+        .verify(signing_string.as_bytes(), &signature)
+        .map_err(|_| invalid("the signature doesn't verify"))?;
+    Ok(actor)
+}
+
+// `key="value"` pairs of a Signature header
+fn parse_params(header: &str) -> Vec<(String, String)> {
+    header
+        .split(',')
+        .filter_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            Some((key.to_owned(), value.trim_matches('"').to_owned()))
+        })
+        .collect()
+}
+
+// the actor owning `key_id` and its public key
+async fn fetch_key(
+    key_id: &str,
+    private_hosts: bool,
+) -> Result<(RemoteActor, RsaPublicKey), ApiError> {
+    let url = key_id.split('#').next().unwrap_or(key_id);
+    let document = fetch(url, private_hosts)
+        .await
+        .map_err(|e| invalid(format!("can't fetch the key {key_id}: {e}")))?;
+
+    let field = |value: &Value, name: &str| value[name].as_str().map(str::to_owned);
+    let key = &document["publicKey"];
+    let pem = field(key, "publicKeyPem").ok_or_else(|| invalid("the actor has no publicKey"))?;
+    let public_key = RsaPublicKey::from_public_key_pem(&pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem))
+        .map_err(|_| invalid("the actor's public key isn't an RSA key"))?;
+    let (Some(id), Some(inbox)) = (field(&document, "id"), field(&document, "inbox")) else {
+        return Err(invalid("the key's owner isn't an actor"));
+    };
+    if field(key, "owner").is_some_and(|owner| owner != id) {
+        return Err(invalid("the key belongs to another actor"));
+    }
+    Ok((RemoteActor { id, inbox }, public_key))
+}
+
+fn invalid(message: impl Into<String>) -> ApiError {
+    ApiError::new(StatusCode::UNAUTHORIZED, "invalid_signature", message)
+}
@@ -0,0 +1,165 @@
+// Sparse fieldsets: `?fields=id,title` on list endpoints returns only the
+// named fields of each item.
+//
+// The projection happens on the typed models while they are serialized, every
+// projectable model lists its fields and knows how to write each of them, so
+// the queries stay the same and no field can be requested that the model
+// doesn't have.
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+use crate::models::{Post, PostWithAuthor};
+use crate::negotiate::XmlElement;
+
+pub trait Fields {
+    // every field, in serialization order
+    const FIELDS: &'static [&'static str];
+
+    fn serialize_field<M: SerializeMap>(&self, field: &str, map: &mut M) -> Result<(), M::Error>;
+}
+
+impl Fields for Post {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "uuid",
+        "public_id",
+        "user_id",
+        "org_id",
+        "authors",
+        "title",
+        "body",
+        "status",
+        "comments_count",
+        "created_at",
+        "updated_at",
+    ];
+
+    fn serialize_field<M: SerializeMap>(&self, field: &str, map: &mut M) -> Result<(), M::Error> {
+        match field {
+            "id" => map.serialize_entry(field, &self.id),
+            "uuid" => map.serialize_entry(field, &self.uuid),
+            "public_id" => map.serialize_entry(field, &self.public_id),
+            "user_id" => map.serialize_entry(field, &self.user_id),
+            "org_id" => map.serialize_entry(field, &self.org_id),
+            "authors" => map.serialize_entry(field, &self.authors),
+            "title" => map.serialize_entry(field, &self.title),
+            "body" => map.serialize_entry(field, &self.body),
+            "status" => map.serialize_entry(field, &self.status),
+            "comments_count" => map.serialize_entry(field, &self.comments_count),
+            "created_at" => map.serialize_entry(field, &self.created_at),
+            "updated_at" => map.serialize_entry(field, &self.updated_at),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Fields for PostWithAuthor {
+    const FIELDS: &'static [&'static str] = &[
+        "id",
+        "uuid",
+        "public_id",
+        "user_id",
+        "org_id",
+        "authors",
+        "title",
+        "body",
+        "status",
+        "comments_count",
+        "created_at",
+        "updated_at",
+        "author",
+    ];
+
+    fn serialize_field<M: SerializeMap>(&self, field: &str, map: &mut M) -> Result<(), M::Error> {
+        match field {
+            "author" => map.serialize_entry(field, &self.author),
+            _ => self.post.serialize_field(field, map),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FieldsParams {
+    // comma separated, all fields when absent
+    fields: Option<String>,
+}
+
+impl FieldsParams {
+    // the requested fields of `T` in `T::FIELDS` order, `None` for all of them
+    pub fn fields<T: Fields>(&self) -> Result<Option<FieldSet>, ApiError> {
+        let Some(fields) = self.fields.as_deref() else {
+            return Ok(None);
+        };
+        let mut requested = Vec::new();
+        for name in fields
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            if !T::FIELDS.contains(&name) {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_fields",
+                    format!(
+                        "unknown field `{name}`, expected some of {}",
+                        T::FIELDS.join(",")
+                    ),
+                ));
+            }
+            requested.push(name);
+        }
+        let set = T::FIELDS
+            .iter()
+            .copied()
+            .filter(|field| requested.contains(field))
+            .collect();
+        Ok(Some(FieldSet(set)))
+    }
+}
+
+#[derive(Clone)]
+pub struct FieldSet(Arc<[&'static str]>);
+
+impl FieldSet {
+    pub fn apply<T: Fields>(&self, items: Vec<T>) -> Vec<Sparse<T>> {
+        items
+            .into_iter()
+            .map(|value| Sparse {
+                value,
+                fields: self.clone(),
+            })
+            .collect()
+    }
+}
+
+// A model serialized with only the fields of a `FieldSet`.
+pub struct Sparse<T> {
+    value: T,
+    fields: FieldSet,
+}
+
+impl<T> Sparse<T> {
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Fields> Serialize for Sparse<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.0.len()))?;
+        for field in self.fields.0.iter() {
+            self.value.serialize_field(field, &mut map)?;
+        }
+        map.end()
+    }
+}
+
+impl<T: XmlElement> XmlElement for Sparse<T> {
+    const ELEMENT: &'static str = T::ELEMENT;
+    const LIST: &'static str = T::LIST;
+}
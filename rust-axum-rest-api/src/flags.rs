@@ -0,0 +1,307 @@
+// Feature flags, for dark-launching features.
+//
+// A flag is on for `rollout_percent` of the users: each user falls into one
+// of 100 buckets by a hash of the flag key and their id, so raising the
+// percentage only ever adds users and each flag picks a different share.
+// Anonymous requests only see fully rolled out flags. Overrides turn a flag
+// on or off for single users whatever the rollout, and unknown flags are
+// off.
+//
+// Handlers ask through the `Flags` extractor, which knows the acting user;
+// admins manage flags and overrides under `/admin/flags`.
+
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts, Path, State};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use sha2::{Digest, Sha256};
+
+use crate::audit::{self, Change};
+use crate::auth::{Actor, RequireAdmin};
+use crate::error::{ApiError, OrNotFound};
+use crate::models::{
+    CreateFeatureFlag, FeatureFlag, FlagOverride, FlagRule, FlagState, Message, SetFlagOverride,
+    UpdateFeatureFlag,
+};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::repo::FlagRepository;
+use crate::state::AppState;
+
+const MAX_KEY_LENGTH: usize = 64;
+
+// Evaluates flags against their storage.
+#[derive(Clone)]
+pub struct FlagService {
+    repo: Arc<dyn FlagRepository>,
+}
+
+impl FlagService {
+    pub fn new(repo: Arc<dyn FlagRepository>) -> Self {
+        FlagService { repo }
+    }
+
+    pub fn repo(&self) -> &dyn FlagRepository {
+        self.repo.as_ref()
+    }
+
+    // whether `key` is on for `user_id`
+    pub async fn enabled(&self, key: &str, user_id: Option<i32>) -> Result<bool, sqlx::Error> {
+        let rule = self.repo.rule(key, user_id).await?;
+        Ok(rule.is_some_and(|rule| decide(&rule, user_id)))
+    }
+
+    // every flag, on or off for `user_id`
+    pub async fn all(&self, user_id: Option<i32>) -> Result<Vec<FlagState>, sqlx::Error> {
+        let rules = self.repo.rules(user_id).await?;
+        Ok(rules
+            .into_iter()
+            .map(|rule| FlagState {
+                enabled: decide(&rule, user_id),
+                key: rule.key,
+            })
+            .collect())
+    }
+}
+
+fn decide(rule: &FlagRule, user_id: Option<i32>) -> bool {
+    if let Some(enabled) = rule.user_override {
+        return enabled;
+    }
+    match user_id {
+        Some(user_id) => bucket(&rule.key, user_id) < rule.rollout_percent,
+        None => rule.rollout_percent >= 100,
+    }
+}
+
+// 0 to 99, the same for a user and flag across restarts and instances
+fn bucket(key: &str, user_id: i32) -> i16 {
+    let hash = Sha256::digest(format!("{key}:{user_id}"));
+    let value = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]);
+    (value % 100) as i16
+}
+
+// Extractor checking flags for the acting user.
+pub struct Flags {
+    service: FlagService,
+    user_id: Option<i32>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Flags
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Ok(actor) = Actor::from_request_parts(parts, state).await;
+        Ok(Flags {
+            service: AppState::from_ref(state).flags,
+            user_id: actor.user_id,
+        })
+    }
+}
+
+impl Flags {
+    pub async fn enabled(&self, key: &str) -> Result<bool, ApiError> {
+        Ok(self.service.enabled(key, self.user_id).await?)
+    }
+
+    // a 404 unless `key` is on, so dark-launched endpoints look like they
+    // don't exist
+    pub async fn require(&self, key: &str) -> Result<(), ApiError> {
+        if self.enabled(key).await? {
+            Ok(())
+        } else {
+            Err(ApiError::not_found("not found"))
+        }
+    }
+}
+
+// handler for "GET /me/flags" rest API endpoint, the flags as they are for
+// the acting user
+pub async fn my_flags(
+    flags: Flags,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<FlagState>>, ApiError> {
+    let states = flags.service.all(flags.user_id).await?;
+
+    Ok(format.respond(states))
+}
+
+// handler for "GET /admin/flags" rest API endpoint
+pub async fn list_flags(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<FeatureFlag>>, ApiError> {
+    let flags = state.flags.repo().list().await?;
+
+    Ok(format.respond(flags))
+}
+
+// handler for "POST /admin/flags" rest API endpoint
+pub async fn create_flag(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Payload(flag): Payload<CreateFeatureFlag>,
+) -> Result<Negotiated<FeatureFlag>, ApiError> {
+    validate_key(&flag.key)?;
+    validate_rollout(flag.rollout_percent)?;
+    let flag = state.flags.repo().create(&flag).await?;
+    audit::record(Change::new("flag.create", "flag", &flag.key).after(&flag));
+
+    Ok(format.respond(flag))
+}
+
+// handler for "GET /admin/flags/:key" rest API endpoint
+pub async fn get_flag(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Accept(format): Accept,
+) -> Result<Negotiated<FeatureFlag>, ApiError> {
+    let flag = state.flags.repo().get(&key).await.or_not_found("flag")?;
+
+    Ok(format.respond(flag))
+}
+
+// handler for "PUT /admin/flags/:key" rest API endpoint
+pub async fn update_flag(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Accept(format): Accept,
+    Payload(update): Payload<UpdateFeatureFlag>,
+) -> Result<Negotiated<FeatureFlag>, ApiError> {
+    validate_rollout(update.rollout_percent)?;
+    let before = state.flags.repo().get(&key).await.or_not_found("flag")?;
+    let flag = state
+        .flags
+        .repo()
+        .update(&key, &update)
+        .await
+        .or_not_found("flag")?;
+    audit::record(
+        Change::new("flag.update", "flag", &flag.key)
+            .before(&before)
+            .after(&flag),
+    );
+
+    Ok(format.respond(flag))
+}
+
+// handler for "DELETE /admin/flags/:key" rest API endpoint, the flag's
+// overrides go with it
+pub async fn delete_flag(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    if !state.flags.repo().delete(&key).await? {
+        return Err(ApiError::not_found("flag not found"));
+    }
+    audit::record(Change::new("flag.delete", "flag", &key));
+
+    Ok(format.respond(Message {
+        message: String::from("Flag deleted"),
+    }))
+}
+
+// handler for "GET /admin/flags/:key/overrides" rest API endpoint
+pub async fn list_overrides(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<FlagOverride>>, ApiError> {
+    let flag = state.flags.repo().get(&key).await.or_not_found("flag")?;
+    let overrides = state.flags.repo().overrides(&flag.key).await?;
+
+    Ok(format.respond(overrides))
+}
+
+// handler for "PUT /admin/flags/:key/overrides/:user_id" rest API endpoint
+pub async fn set_override(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path((key, user_id)): Path<(String, i32)>,
+    Accept(format): Accept,
+    Payload(set): Payload<SetFlagOverride>,
+) -> Result<Negotiated<FlagOverride>, ApiError> {
+    let flag = state.flags.repo().get(&key).await.or_not_found("flag")?;
+    let flag_override = state
+        .flags
+        .repo()
+        .set_override(&flag.key, user_id, set.enabled)
+        .await?;
+    audit::record(
+        Change::new(
+            "flag.override",
+            "flag_override",
+            format!("{}:{user_id}", flag.key),
+        )
+        .after(&flag_override),
+    );
+
+    Ok(format.respond(flag_override))
+}
+
+// handler for "DELETE /admin/flags/:key/overrides/:user_id" rest API endpoint
+pub async fn remove_override(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path((key, user_id)): Path<(String, i32)>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    if !state.flags.repo().remove_override(&key, user_id).await? {
+        return Err(ApiError::not_found("override not found"));
+    }
+    audit::record(Change::new(
+        "flag.override_remove",
+        "flag_override",
+        format!("{key}:{user_id}"),
+    ));
+
+    Ok(format.respond(Message {
+        message: String::from("Override removed"),
+    }))
+}
+
+// lower case letters, digits, `_`, `-` and `.`, starting with a letter or
+// digit
+fn validate_key(key: &str) -> Result<(), ApiError> {
+    let valid = key.len() <= MAX_KEY_LENGTH
+        && key.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c));
+    if valid {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_key",
+        format!(
+            "flag keys are up to {MAX_KEY_LENGTH} lower case letters, digits, '_', '-' and '.'"
+        ),
+    )
+    .with("field", "key"))
+}
+
+fn validate_rollout(percent: i16) -> Result<(), ApiError> {
+    if (0..=100).contains(&percent) {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_rollout",
+        "rollout_percent must be between 0 and 100",
+    )
+    .with("field", "rollout_percent"))
+}
@@ -0,0 +1,71 @@
+// Following: users follow other users of their tenant to get their new
+// posts in the weekly digest (see `digest`). `GET /me/following` lists
+// whom the acting user follows, `PUT` and `DELETE /me/following/:user_id`
+// start and stop following someone; both are idempotent.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+
+use crate::auth::Actor;
+use crate::avatars;
+use crate::error::{ApiError, OrNotFound};
+use crate::models::{Message, PublicUser};
+use crate::negotiate::{Accept, Negotiated};
+use crate::repo;
+use crate::state::AppState;
+
+// handler for "GET /me/following" rest API endpoint
+pub async fn my_following(
+    State(state): State<AppState>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<PublicUser>>, ApiError> {
+    let user_id = actor.require_user()?;
+    let mut users = repo::follows::following(&state.pool, user_id).await?;
+    for user in &mut users {
+        avatars::fill(&state.config, user);
+    }
+    // whom someone follows doesn't get them their addresses
+    let users = users.into_iter().map(PublicUser::from).collect();
+
+    Ok(format.respond(users))
+}
+
+// handler for "PUT /me/following/:user_id" rest API endpoint
+pub async fn follow_user(
+    State(state): State<AppState>,
+    Path(followee_id): Path<i32>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    let user_id = actor.require_user()?;
+    if followee_id == user_id {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_follow",
+            "users can't follow themselves",
+        ));
+    }
+    let followee = state.users.get(followee_id).await.or_not_found("user")?;
+    repo::follows::follow(&state.pool, user_id, followee.id).await?;
+
+    Ok(format.respond(Message {
+        message: format!("following {}", followee.username),
+    }))
+}
+
+// handler for "DELETE /me/following/:user_id" rest API endpoint
+pub async fn unfollow_user(
+    State(state): State<AppState>,
+    Path(followee_id): Path<i32>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    let user_id = actor.require_user()?;
+    let followee = state.users.get(followee_id).await.or_not_found("user")?;
+    repo::follows::unfollow(&state.pool, user_id, followee.id).await?;
+
+    Ok(format.respond(Message {
+        message: format!("not following {}", followee.username),
+    }))
+}
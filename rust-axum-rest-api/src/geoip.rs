@@ -0,0 +1,103 @@
+// Where clients are: built with the geoip feature, the `geoip_db` tunable
+// (see `tunables`) names a MaxMind GeoIP2 or GeoLite2 City or Country
+// database, in which the client addresses of `client_ip` are looked up. The
+// country and region (ISO 3166 codes, the region only with a City database)
+// go to the access log, the audit log and the share link analytics (see
+// `short_links`).
+//
+// The file is read again on every reload, so after `geoipupdate` replaced it
+// a SIGHUP or `POST /admin/config/reload` puts the new one in use. A file
+// that can't be read is rejected and the database in use is kept.
+
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[cfg(feature = "geoip")]
+use arc_swap::ArcSwapOption;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use serde::Serialize;
+
+// Where a client is, as far as the database knows.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub country: Option<String>,
+    pub region: Option<String>,
+}
+
+#[derive(Default)]
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    reader: ArcSwapOption<maxminddb::Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    // Look clients up in the database at `path` from now on, or nowhere.
+    #[cfg(feature = "geoip")]
+    pub fn load(&self, path: Option<&Path>) -> Result<(), String> {
+        let reader = match path {
+            Some(path) => Some(
+                maxminddb::Reader::open_readfile(path)
+                    .map_err(|e| format!("can't read {}: {e}", path.display()))?,
+            ),
+            None => None,
+        };
+        self.reader.store(reader.map(Into::into));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn load(&self, path: Option<&Path>) -> Result<(), String> {
+        match path {
+            Some(_) => Err(String::from(
+                "geoip_db needs a build with the geoip feature",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Where `ip` is, if it is known and found.
+    #[cfg(feature = "geoip")]
+    pub fn locate(&self, ip: Option<IpAddr>) -> Location {
+        use maxminddb::geoip2::City;
+
+        let (Some(ip), Some(reader)) = (ip, self.reader.load_full()) else {
+            return Location::default();
+        };
+        let Ok(city) = reader.lookup::<City>(ip) else {
+            return Location::default();
+        };
+        Location {
+            country: city
+                .country
+                .and_then(|country| country.iso_code)
+                .map(str::to_owned),
+            region: city
+                .subdivisions
+                .and_then(|subdivisions| subdivisions.into_iter().next())
+                .and_then(|subdivision| subdivision.iso_code)
+                .map(str::to_owned),
+        }
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn locate(&self, _ip: Option<IpAddr>) -> Location {
+        Location::default()
+    }
+}
+
+// The location `client_ip::resolve` found for the request, or nowhere.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Location {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Location>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
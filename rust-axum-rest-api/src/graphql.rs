@@ -0,0 +1,238 @@
+// GraphQL at `/graphql`: queries are POSTed as JSON, subscriptions run over
+// a WebSocket opened with a GET, speaking either graphql-ws (the
+// `graphql-transport-ws` subprotocol) or the older subscriptions-transport-ws
+// (`graphql-ws`).
+//
+//     subscription { postCreated { id title } }
+//     subscription { commentAdded(postId: 3) { id body } }
+//
+// Subscriptions are fed by the event bus (see `events`), so they see posts
+// as they are published and comments on published posts, of the tenant the
+// socket was opened in.
+
+use std::future::ready;
+use std::sync::OnceLock;
+
+use async_graphql::http::{WebSocket, WebSocketProtocols, WsMessage, ALL_WEBSOCKET_PROTOCOLS};
+use async_graphql::{Context, Data, EmptyMutation, Object, Schema, SimpleObject, Subscription, ID};
+use axum::extract::ws::{CloseFrame, Message, WebSocketUpgrade};
+use axum::extract::State;
+use axum::http::header::{CONTENT_TYPE, SEC_WEBSOCKET_PROTOCOL};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, Stream, StreamExt};
+
+use crate::auth::{Actor, RequireAdmin};
+use crate::authors;
+use crate::events::Event;
+use crate::models::{Comment, Post};
+use crate::state::AppState;
+use crate::tenant;
+
+// the media type of GraphQL over HTTP responses, which also keeps the
+// envelope and key case rewriting off them
+const GRAPHQL_RESPONSE: &str = "application/graphql-response+json";
+
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+fn schema() -> &'static ApiSchema {
+    static SCHEMA: OnceLock<ApiSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::new(QueryRoot, EmptyMutation, SubscriptionRoot))
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Post")]
+pub struct PostObject {
+    pub id: i32,
+    pub public_id: String,
+    pub user_id: Option<i32>,
+    pub title: String,
+    pub body: String,
+    pub status: String,
+    pub comments_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Post> for PostObject {
+    fn from(post: Post) -> Self {
+        PostObject {
+            id: post.id,
+            public_id: post.public_id,
+            user_id: post.user_id,
+            title: post.title,
+            body: post.body,
+            status: post.status,
+            comments_count: post.comments_count,
+            created_at: post.created_at,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Comment")]
+pub struct CommentObject {
+    pub id: i32,
+    pub post_id: i32,
+    pub parent_comment_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub body: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Comment> for CommentObject {
+    fn from(comment: Comment) -> Self {
+        CommentObject {
+            id: comment.id,
+            post_id: comment.post_id,
+            parent_comment_id: comment.parent_comment_id,
+            user_id: comment.user_id,
+            body: comment.body,
+            status: comment.status,
+            created_at: comment.created_at,
+        }
+    }
+}
+
+// who a query is made by, for what it may read; anonymous when missing
+struct Viewer {
+    actor: Actor,
+    admin: bool,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // a post by any of the ids `/posts/:id` takes, null for those held back
+    // unless the viewer wrote it or is an admin
+    async fn post(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<Option<PostObject>> {
+        let state = ctx.data::<AppState>()?;
+        let Some(key) = state.config.id_scheme.parse_key(&id) else {
+            return Ok(None);
+        };
+        let anonymous = Viewer {
+            actor: Actor::default(),
+            admin: false,
+        };
+        let viewer = ctx.data_opt::<Viewer>().unwrap_or(&anonymous);
+        match state.posts.get(key).await {
+            Ok(post) if authors::can_read(&post, &viewer.actor, viewer.admin) => {
+                Ok(Some(post.into()))
+            }
+            Ok(_) => Ok(None),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    // posts as they are published
+    async fn post_created(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<impl Stream<Item = PostObject>> {
+        let events = ctx.data::<AppState>()?.events.subscribe();
+        Ok(events.filter_map(|event| {
+            ready(match event {
+                Event::PostCreated(post) => Some(post.into()),
+                _ => None,
+            })
+        }))
+    }
+
+    // new comments, only those on `post_id` when given
+    async fn comment_added(
+        &self,
+        ctx: &Context<'_>,
+        post_id: Option<i32>,
+    ) -> async_graphql::Result<impl Stream<Item = CommentObject>> {
+        let events = ctx.data::<AppState>()?.events.subscribe();
+        Ok(events.filter_map(move |event| {
+            ready(match event {
+                Event::CommentAdded(comment)
+                    if post_id.is_none_or(|post_id| post_id == comment.post_id) =>
+                {
+                    Some(comment.into())
+                }
+                _ => None,
+            })
+        }))
+    }
+}
+
+// handler for "POST /graphql" rest API endpoint
+pub async fn execute(
+    State(state): State<AppState>,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Json(request): Json<async_graphql::Request>,
+) -> Response {
+    let viewer = Viewer {
+        actor,
+        admin: admin.is_some(),
+    };
+    let response = schema().execute(request.data(state).data(viewer)).await;
+
+    ([(CONTENT_TYPE, GRAPHQL_RESPONSE)], Json(response)).into_response()
+}
+
+// handler for "GET /graphql" rest API endpoint, upgrades to a WebSocket
+// carrying subscriptions
+pub async fn subscribe(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    // the first subprotocol offered that we speak, graphql-ws without one
+    let protocol = headers
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|offered| {
+            offered
+                .split(',')
+                .find_map(|protocol| protocol.trim().parse().ok())
+        })
+        .unwrap_or(WebSocketProtocols::GraphQLWS);
+    // the socket outlives the request and with it its tenant scope
+    let tenant_id = tenant::current();
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            tenant::within(tenant_id, async move {
+                let (mut sink, stream) = socket.split();
+                let input = stream
+                    .take_while(|message| ready(message.is_ok()))
+                    .filter_map(|message| {
+                        ready(match message {
+                            Ok(Message::Text(text)) => Some(text.into_bytes()),
+                            Ok(Message::Binary(bytes)) => Some(bytes),
+                            _ => None,
+                        })
+                    });
+                let mut data = Data::default();
+                data.insert(state);
+                let mut output =
+                    WebSocket::new(schema().clone(), input, protocol).connection_data(data);
+                while let Some(message) = output.next().await {
+                    let message = match message {
+                        WsMessage::Text(text) => Message::Text(text),
+                        WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                            code,
+                            reason: reason.into(),
+                        })),
+                    };
+                    if sink.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+}
@@ -0,0 +1,550 @@
+// Request handlers for the posts and users endpoints.
+
+use std::time::{Duration, Instant};
+
+use axum::extract::{Query, State};
+use axum::http::{Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::activity;
+use crate::archive;
+use crate::audit::{self, Change};
+use crate::auth::{Actor, RequireAdmin};
+use crate::authors;
+use crate::avatars;
+use crate::billing;
+use crate::client_ip::ClientIp;
+use crate::content_filter::{self, Verdict};
+use crate::email;
+use crate::error::{ApiError, OrNotFound};
+use crate::events::Event;
+use crate::federation;
+use crate::fields::{FieldSet, Fields, FieldsParams};
+use crate::ids::{IdScheme, Key, PathKey};
+use crate::link_previews::{self, WithPreviews};
+use crate::links::{HasLinks, Linked};
+use crate::models::{
+    ActivityKind, Availability, Count, CreatePost, CreateReport, CreateUser, Message, OrgRole,
+    Post, PostRevision, PostStatus, PostWithAuthor, PublicUser, Report, UpdatePost, User,
+};
+use crate::negotiate::{Accept, Format, Negotiated, Payload, XmlElement};
+use crate::pagination::{self, PageParams, Total};
+use crate::repo::{AuditFilter, PostFilter};
+use crate::routes::TOP_LEVEL_ROUTES;
+use crate::spam::{SpamVerdict, Submission};
+use crate::state::AppState;
+use crate::usage::{self, Metric, Owner};
+use crate::{hashtags, mentions, orgs, usernames};
+
+// handler for "GET /" rest API endpoint
+pub async fn root() -> &'static str {
+    "Hello, world!"
+}
+
+// `?include=author` on the post endpoints, inlining the author of each post
+// (see `PostWithAuthor`)
+#[derive(Deserialize)]
+pub struct Include {
+    include: Option<String>,
+}
+
+impl Include {
+    fn author(&self) -> Result<bool, ApiError> {
+        let mut author = false;
+        for name in self.include.iter().flat_map(|list| list.split(',')) {
+            match name.trim() {
+                "author" => author = true,
+                "" => {}
+                other => {
+                    return Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_include",
+                        format!("cannot include `{other}`, expected author"),
+                    ))
+                }
+            }
+        }
+        Ok(author)
+    }
+}
+
+// handler for "GET /posts" rest API endpoint, see `PostFilter` for the
+// sorting and filtering parameters and `pagination` for paging
+pub async fn get_posts(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(filter): Query<PostFilter>,
+    Query(page): Query<PageParams>,
+    Query(include): Query<Include>,
+    Query(fields): Query<FieldsParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let exact = page.exact();
+    let page = page.page()?;
+    let total = if exact {
+        Total::from(state.posts.count(&filter).await?)
+    } else {
+        Total::estimated(state.posts.estimate(&filter).await?)
+    };
+    let headers = pagination::headers(&uri, page, total);
+    if include.author()? {
+        let fields = fields.fields::<PostWithAuthor>()?;
+        let mut posts = state.posts.list_with_authors(&filter, page).await?;
+        avatars::fill_authors(
+            &state,
+            posts.iter_mut().filter_map(|post| post.author.as_mut()),
+        )
+        .await?;
+        return Ok((
+            headers,
+            respond_list(format, posts, &fields, state.config.id_scheme),
+        )
+            .into_response());
+    }
+    let fields = fields.fields::<Post>()?;
+    let posts = state.posts.list(&filter, page).await?;
+    Ok((
+        headers,
+        respond_list(format, posts, &fields, state.config.id_scheme),
+    )
+        .into_response())
+}
+
+// a list in full, or projected onto the requested fields
+fn respond_list<T>(
+    format: Format,
+    items: Vec<T>,
+    fields: &Option<FieldSet>,
+    ids: IdScheme,
+) -> Response
+where
+    T: Fields + HasLinks + Serialize + XmlElement,
+{
+    match fields {
+        Some(fields) => format
+            .respond(Linked::all(fields.apply(items), ids))
+            .into_response(),
+        None => format.respond(Linked::all(items, ids)).into_response(),
+    }
+}
+
+// handler for "GET /posts/count" rest API endpoint, the number of posts
+// "GET /posts" would list with the same filters
+pub async fn count_posts(
+    State(state): State<AppState>,
+    Query(filter): Query<PostFilter>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Count>, ApiError> {
+    let count = state.posts.count(&filter).await?;
+
+    Ok(format.respond(Count { count }))
+}
+
+// handler for "GET /posts/:id" rest API endpoint, also answering HEAD to
+// check whether a post exists (see `methods`)
+pub async fn get_post(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Query(include): Query<Include>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    if include.author()? {
+        let mut post = state
+            .posts
+            .get_with_author(key)
+            .await
+            .or_not_found("post")?;
+        authors::require_visible(&post.post, &actor, &admin)?;
+        archive::ensure_live(&state, &post.post).await?;
+        avatars::fill_authors(&state, post.author.as_mut()).await?;
+        let previews = link_previews::for_post(&state, &post.post).await?;
+        let post = WithPreviews::new(post, previews);
+        return Ok(format
+            .respond(Linked::new(post, state.config.id_scheme))
+            .into_response());
+    }
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    authors::require_visible(&post, &actor, &admin)?;
+    archive::ensure_live(&state, &post).await?;
+    let previews = link_previews::for_post(&state, &post).await?;
+
+    Ok(format
+        .respond(Linked::new(
+            WithPreviews::new(post, previews),
+            state.config.id_scheme,
+        ))
+        .into_response())
+}
+
+// handler for Create a new post and return the created data
+pub async fn create_post(
+    State(state): State<AppState>,
+    ClientIp(client): ClientIp,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+    Payload(mut new_post): Payload<CreatePost>,
+) -> Result<Negotiated<Linked<Post>>, ApiError> {
+    // posts made while impersonating are the impersonated user's, and so
+    // are their plan limits and quotas
+    new_post.user_id = actor.author(new_post.user_id, admin.is_some())?;
+    if let Some(org_id) = new_post.org_id {
+        orgs::require_role(&state, &actor, org_id, OrgRole::Member).await?;
+    }
+    billing::check_post_limit(&state, new_post.user_id, new_post.org_id).await?;
+    let owner = Owner::of(new_post.user_id, new_post.org_id);
+    usage::check_resource(&state, owner, Metric::Posts).await?;
+    let filtered = screen(&state, &new_post.title, &new_post.body).await?;
+    let submission = Submission {
+        user_id: new_post.user_id,
+        client,
+        title: &new_post.title,
+        body: &new_post.body,
+    };
+    let status = match (state.spam.check(&submission).await, filtered) {
+        (SpamVerdict::Spam(reason), _) => {
+            info!("holding back a post as spam: {reason}");
+            PostStatus::Spam
+        }
+        (SpamVerdict::Suspicious(reason), _) => {
+            info!("holding back a suspicious post: {reason}");
+            PostStatus::Pending
+        }
+        (SpamVerdict::Ham, Verdict::Flag) => PostStatus::Pending,
+        (SpamVerdict::Ham, Verdict::Allow) => PostStatus::Published,
+    };
+    let post = state.posts.create(&new_post, status).await?;
+    usage::record_resource(&state, owner, Metric::Posts);
+    audit::record(Change::new("post.create", "post", post.id).after(&post));
+    mentions::record(&state, post.id, None, None, &post.body, post.user_id).await?;
+    hashtags::record(&state, post.id, None, &post.body).await?;
+    link_previews::record(&state, &post, None).await?;
+    activity::record(&state, post.user_id, ActivityKind::Post, post.id, None).await?;
+    if status == PostStatus::Published {
+        federation::publish(&state, &post).await?;
+        state.events.publish(Event::PostCreated(post.clone()));
+    }
+
+    Ok(format.respond(Linked::new(post, state.config.id_scheme)))
+}
+
+// handler for Update a post and return the updated data
+pub async fn update_post(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    ClientIp(client): ClientIp,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+    Payload(mut updated_post): Payload<UpdatePost>,
+) -> Result<Negotiated<Linked<Post>>, ApiError> {
+    let before = state.posts.get(key).await.or_not_found("post")?;
+    authors::require_visible(&before, &actor, &admin)?;
+    let is_admin = admin.is_some();
+    // an organization's posts are edited by its members, see `orgs`
+    match before.org_id {
+        Some(org_id) if !is_admin => {
+            orgs::require_role(&state, &actor, org_id, OrgRole::Member).await?;
+        }
+        _ => authors::require_author(&before, &actor, admin, "edit it")?,
+    }
+    // leaving `user_id` out keeps the primary author; editors may take the
+    // post over, only admins hand it to someone else
+    updated_post.user_id = match updated_post.user_id {
+        None => before.user_id,
+        named => actor.author(named, is_admin)?,
+    };
+    archive::ensure_live(&state, &before).await?;
+    // an edit is held back like a new post, a clean one keeps the post's
+    // status
+    let filtered = screen(&state, &updated_post.title, &updated_post.body).await?;
+    let submission = Submission {
+        user_id: before.user_id,
+        client,
+        title: &updated_post.title,
+        // an unchanged body was checked when it was written, and would pass
+        // for a duplicate of itself
+        body: if updated_post.body == before.body {
+            ""
+        } else {
+            &updated_post.body
+        },
+    };
+    let status = match (state.spam.check(&submission).await, filtered) {
+        (SpamVerdict::Spam(reason), _) => {
+            info!("holding back an edit as spam: {reason}");
+            Some(PostStatus::Spam)
+        }
+        (SpamVerdict::Suspicious(reason), _) => {
+            info!("holding back a suspicious edit: {reason}");
+            Some(PostStatus::Pending)
+        }
+        (SpamVerdict::Ham, Verdict::Flag) => Some(PostStatus::Pending),
+        (SpamVerdict::Ham, Verdict::Allow) => None,
+    };
+    let post = state
+        .posts
+        .update(key, &updated_post, status)
+        .await
+        .or_not_found("post")?;
+    audit::record(
+        Change::new("post.update", "post", post.id)
+            .before(&before)
+            .after(&post),
+    );
+    mentions::record(
+        &state,
+        post.id,
+        None,
+        Some(&before.body),
+        &post.body,
+        actor.user_id,
+    )
+    .await?;
+    hashtags::record(&state, post.id, Some(&before.body), &post.body).await?;
+    link_previews::record(&state, &post, Some(&before.body)).await?;
+
+    Ok(format.respond(Linked::new(post, state.config.id_scheme)))
+}
+
+// run a post's title and body through the content filter
+async fn screen(state: &AppState, title: &str, body: &str) -> Result<Verdict, ApiError> {
+    content_filter::check(
+        state.banned_words.as_ref(),
+        &[("title", title), ("body", body)],
+    )
+    .await
+}
+
+// handler for "GET /posts/:id/history" rest API endpoint, who changed which
+// fields of a post and when, newest first, see `audit`
+pub async fn get_post_history(
+    State(state): State<AppState>,
+    uri: Uri,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let page = page.page()?;
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    authors::require_visible(&post, &actor, &admin)?;
+    let filter = AuditFilter {
+        entity_type: Some(String::from("post")),
+        entity_id: Some(post.id.to_string()),
+        actor: None,
+    };
+    let total = state.audit.count(&filter).await?;
+    let history: Vec<PostRevision> = state
+        .audit
+        .list(&filter, page)
+        .await?
+        .into_iter()
+        .map(PostRevision::from)
+        .collect();
+
+    Ok((
+        pagination::headers(&uri, page, total),
+        format.respond(history),
+    )
+        .into_response())
+}
+
+// readers with open reports that take a published post or comment back to
+// the moderation queue
+pub(crate) const REPORT_THRESHOLD: i64 = 3;
+
+// handler for "POST /posts/:id/report" rest API endpoint, a user reporting a
+// post to the moderators, once
+pub async fn report_post(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    Accept(format): Accept,
+    Payload(report): Payload<CreateReport>,
+) -> Result<Negotiated<Report>, ApiError> {
+    let reporter_id = actor.require_user()?;
+    let reason = report_reason(&report)?;
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    authors::require_visible(&post, &actor, &None)?;
+    let Some(report) = state
+        .reports
+        .create(post.id, None, reporter_id, reason)
+        .await?
+    else {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "already_reported",
+            "you already reported this post",
+        ));
+    };
+    audit::record(Change::new("report.create", "report", report.id).after(&report));
+
+    let open = state.reports.count_open(post.id, None).await?;
+    if open >= REPORT_THRESHOLD && post.status == PostStatus::Published.as_str() {
+        let reason = format!("reported {open} times");
+        let held = state
+            .posts
+            .moderate(Key::Serial(post.id), PostStatus::Pending, Some(&reason))
+            .await?;
+        audit::record(
+            Change::new("post.hold", "post", post.id)
+                .before(&post)
+                .after(&held),
+        );
+    }
+
+    Ok(format.respond(report))
+}
+
+// the reason a report gives, trimmed
+pub(crate) fn report_reason(report: &CreateReport) -> Result<&str, ApiError> {
+    let reason = report.reason.trim();
+    if reason.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "missing_reason",
+            "reports need a reason",
+        )
+        .with("field", "reason"));
+    }
+    Ok(reason)
+}
+
+// This handler is a bit different as we delete a post we cannot return any data but we will return a custom message in the negotiated format
+pub async fn delete_post(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    // posts are soft-deleted, the admin CLI purges them for good
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    authors::require_visible(&post, &actor, &admin)?;
+    // an organization's posts are deleted by its admins, see `orgs`
+    match post.org_id {
+        Some(org_id) if admin.is_none() => {
+            orgs::require_role(&state, &actor, org_id, OrgRole::Admin).await?;
+        }
+        _ => authors::require_author(&post, &actor, admin, "delete it")?,
+    }
+    if !state.posts.delete(key).await? {
+        return Err(ApiError::not_found("post not found"));
+    }
+    audit::record(Change::new("post.delete", "post", post.id).before(&post));
+
+    Ok(format.respond(Message {
+        message: String::from("Post deleted successfully"),
+    }))
+}
+
+pub async fn create_user(
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Payload(mut new_user): Payload<CreateUser>,
+) -> Result<Negotiated<User>, ApiError> {
+    new_user.username = usernames::check(&state, &new_user.username).await?;
+    new_user.email = email::validate(&new_user.email)?;
+    if state.config.email_mx_check {
+        email::check_deliverable(&new_user.email).await?;
+    }
+    let mut user = state.users.create(&new_user).await?;
+    audit::record(Change::new("user.create", "user", user.id).after(&user));
+    avatars::fill(&state.config, &mut user);
+
+    Ok(format.respond(user))
+}
+
+// `GET /users/check` parameters, at least one is required
+#[derive(Deserialize)]
+pub struct CheckParams {
+    username: Option<String>,
+    email: Option<String>,
+}
+
+// the least time `check_user` takes, so that how long it took doesn't tell
+// whether anything was found
+const CHECK_FLOOR: Duration = Duration::from_millis(50);
+
+// handler for "GET /users/check" rest API endpoint, whether a username and
+// an email are still available for signing up
+pub async fn check_user(
+    State(state): State<AppState>,
+    client: ClientIp,
+    Query(params): Query<CheckParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let started = Instant::now();
+    if let Err(limited) = state.check_limiter.check(client) {
+        return Ok(limited.into_response());
+    }
+    if params.username.is_none() && params.email.is_none() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "missing_parameter",
+            "expected a username or an email to check",
+        ));
+    }
+
+    // invalid and reserved names are as unavailable as taken ones; both are
+    // looked up either way, for the same reason as the floor
+    let username = params
+        .username
+        .as_deref()
+        .and_then(|name| usernames::normalize(name, &state.config.reserved_usernames).ok());
+    let usable = username.is_some();
+    let email = params.email.as_deref().unwrap_or_default();
+    let (username_taken, email_taken) = state
+        .users
+        .taken(username.as_deref().unwrap_or_default(), email)
+        .await?;
+    tokio::time::sleep(CHECK_FLOOR.saturating_sub(started.elapsed())).await;
+
+    Ok(format
+        .respond(Availability {
+            username: params.username.as_ref().map(|_| usable && !username_taken),
+            email: params.email.as_ref().map(|_| !email_taken),
+        })
+        .into_response())
+}
+
+// the user a `/users/:id` key names, by the id scheme; posts' public ids
+// name no user
+pub(crate) async fn user(state: &AppState, key: Key) -> Result<User, ApiError> {
+    match key {
+        Key::Serial(id) => state.users.get(id).await,
+        Key::Uuid(uuid) => state.users.get_by_uuid(uuid).await,
+        Key::Public(_) => Err(sqlx::Error::RowNotFound),
+    }
+    .or_not_found("user")
+}
+
+// handler for "GET /users/:id" rest API endpoint, the email only for the
+// user themselves and admins
+pub async fn get_user(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let mut user = user(&state, key).await?;
+    avatars::fill(&state.config, &mut user);
+    if admin.is_some() || actor.user_id == Some(user.id) {
+        return Ok(format.respond(user).into_response());
+    }
+
+    Ok(format.respond(PublicUser::from(user)).into_response())
+}
+
+// fallback for paths no route matches
+pub async fn not_found(method: Method, uri: Uri) -> ApiError {
+    ApiError::not_found(format!("no route for {method} {}", uri.path()))
+        .with("routes", TOP_LEVEL_ROUTES)
+}
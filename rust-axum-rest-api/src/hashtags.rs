@@ -0,0 +1,132 @@
+// Hashtags: the #tags in a post's body become its tags, so the content
+// itself makes posts discoverable without a separate tag input.
+//
+// Tags are synced whenever a post is written, edits dropping the tags the
+// body no longer has. `GET /hashtags/trending` ranks the tags put on the
+// most published posts recently, `GET /hashtags/:tag/posts` lists a tag's
+// posts.
+//
+// A hashtag is `#` at the start of a word followed by letters, digits and
+// `_`, with at least one letter (`#1` is no tag), stored normalized to NFKC
+// and lowercased.
+
+use axum::extract::{Path, Query, State};
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::ApiError;
+use crate::links::Linked;
+use crate::models::TrendingTag;
+use crate::negotiate::{Accept, Negotiated};
+use crate::pagination::{self, PageParams};
+use crate::repo;
+use crate::state::AppState;
+
+pub const MAX_LENGTH: usize = 64;
+
+// the tags `body` names, each once
+pub fn parse(body: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    let mut previous = None;
+    let mut chars = body.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let starts_word = previous.is_none_or(|p: char| !p.is_alphanumeric() && p != '_');
+        previous = Some(c);
+        if c != '#' || !starts_word {
+            continue;
+        }
+        let mut end = at + 1;
+        while let Some(&(i, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
+            }
+            end = i + c.len_utf8();
+            previous = Some(c);
+            chars.next();
+        }
+        let tag: String = body[at + 1..end].nfkc().collect::<String>().to_lowercase();
+        if tag.chars().any(char::is_alphabetic)
+            && tag.chars().count() <= MAX_LENGTH
+            && !tags.contains(&tag)
+        {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+// make a post's tags the hashtags of its body. `previous` is the body being
+// replaced; when neither has hashtags there is nothing to do.
+pub async fn record(
+    state: &AppState,
+    post_id: i32,
+    previous: Option<&str>,
+    body: &str,
+) -> Result<(), ApiError> {
+    let tags = parse(body);
+    if tags.is_empty() && previous.is_none_or(|body| parse(body).is_empty()) {
+        return Ok(());
+    }
+    repo::hashtags::sync(&state.pool, post_id, &tags).await?;
+    Ok(())
+}
+
+// `GET /hashtags/trending` parameters
+#[derive(Deserialize)]
+pub struct TrendingParams {
+    // how far back to look
+    hours: Option<i32>,
+    limit: Option<i64>,
+}
+
+const DEFAULT_HOURS: i32 = 24;
+const MAX_HOURS: i32 = 24 * 30;
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+
+// handler for "GET /hashtags/trending" rest API endpoint
+pub async fn trending_hashtags(
+    State(state): State<AppState>,
+    Query(params): Query<TrendingParams>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<TrendingTag>>, ApiError> {
+    let hours = params.hours.unwrap_or(DEFAULT_HOURS);
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_HOURS).contains(&hours) || !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_parameter",
+            format!("hours must be between 1 and {MAX_HOURS}, limit between 1 and {MAX_LIMIT}"),
+        ));
+    }
+    let tags = repo::hashtags::trending(&state.pool, hours, limit).await?;
+
+    Ok(format.respond(tags))
+}
+
+// handler for "GET /hashtags/:tag/posts" rest API endpoint, the published
+// posts with the tag, most recent first
+pub async fn hashtag_posts(
+    State(state): State<AppState>,
+    uri: Uri,
+    Path(tag): Path<String>,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let tag: String = tag
+        .trim_start_matches('#')
+        .nfkc()
+        .collect::<String>()
+        .to_lowercase();
+    let page = page.page()?;
+    let total = repo::hashtags::count_tagged(&state.pool, &tag).await?;
+    let posts = repo::hashtags::posts_tagged(&state.pool, &tag, page).await?;
+
+    Ok((
+        pagination::headers(&uri, page, total),
+        format.respond(Linked::all(posts, state.config.id_scheme)),
+    )
+        .into_response())
+}
@@ -0,0 +1,216 @@
+// How posts are addressed in URLs.
+//
+// Every post and user has both a serial `id` and a random `uuid`. With
+// ID_SCHEME=serial (the default) `/posts/:id` takes the serial id, with
+// ID_SCHEME=uuid it only accepts the uuid, so ids handed out by the API can't
+// be guessed by counting and stay unique across environments. `/users/:id`
+// follows the same scheme, and links to users are written in it.
+//
+// Posts additionally carry a `public_id`, a ULID: 26 Crockford base32
+// characters that sort by creation time without revealing how many rows
+// there are. It is accepted by `/posts/:id` under either scheme.
+
+use std::fmt;
+use std::str::FromStr;
+
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts, Path};
+use axum::http::request::Parts;
+use uuid::Uuid;
+
+use crate::error::ApiError;
+use crate::state::AppState;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdScheme {
+    #[default]
+    Serial,
+    Uuid,
+}
+
+impl FromStr for IdScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "serial" => Ok(IdScheme::Serial),
+            "uuid" => Ok(IdScheme::Uuid),
+            _ => Err(format!("invalid ID_SCHEME {s:?}, expected serial or uuid")),
+        }
+    }
+}
+
+impl IdScheme {
+    // a post key written the way URLs take it: a ULID, or an id of the
+    // scheme's kind
+    pub fn parse_key(self, text: &str) -> Option<Key> {
+        if let Ok(public_id) = text.parse() {
+            return Some(Key::Public(public_id));
+        }
+        match self {
+            IdScheme::Serial => text.parse().ok().map(Key::Serial),
+            IdScheme::Uuid => text.parse().ok().map(Key::Uuid),
+        }
+    }
+
+    // the path segment addressing a row with these ids, e.g. a post's
+    // author; none under the uuid scheme when the uuid isn't known
+    pub fn segment(self, id: i32, uuid: Option<Uuid>) -> Option<String> {
+        match self {
+            IdScheme::Serial => Some(id.to_string()),
+            IdScheme::Uuid => uuid.map(|uuid| uuid.to_string()),
+        }
+    }
+}
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+// A ULID in its canonical upper case text form.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PublicId([u8; 26]);
+
+impl PublicId {
+    // encode a millisecond timestamp and 80 random bits, the way the
+    // database's gen_ulid() does
+    pub fn new(unix_ms: u64, random: u128) -> PublicId {
+        let mut value = (u128::from(unix_ms) << 80) | (random & ((1 << 80) - 1));
+        let mut text = [0; 26];
+        for byte in text.iter_mut().rev() {
+            *byte = CROCKFORD[(value & 31) as usize];
+            value >>= 5;
+        }
+        PublicId(text)
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ever holds ASCII from CROCKFORD
+        std::str::from_utf8(&self.0).expect("ascii")
+    }
+}
+
+impl FromStr for PublicId {
+    type Err = ();
+
+    // case-insensitive, the first character can only be 0-7 as a ULID is
+    // 128 bits
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s.as_bytes();
+        if bytes.len() != 26 || bytes[0] > b'7' {
+            return Err(());
+        }
+        let mut text = [0; 26];
+        for (out, byte) in text.iter_mut().zip(bytes) {
+            let upper = byte.to_ascii_uppercase();
+            if !CROCKFORD.contains(&upper) {
+                return Err(());
+            }
+            *out = upper;
+        }
+        Ok(PublicId(text))
+    }
+}
+
+impl fmt::Display for PublicId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for PublicId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicId({})", self.as_str())
+    }
+}
+
+// A reference to a single row, by any of its ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Serial(i32),
+    Uuid(Uuid),
+    Public(PublicId),
+}
+
+impl Key {
+    // the key as (id, uuid, public_id) bind parameters, exactly one of them
+    // set
+    pub fn binds(&self) -> (Option<i32>, Option<Uuid>, Option<&str>) {
+        match self {
+            Key::Serial(id) => (Some(*id), None, None),
+            Key::Uuid(uuid) => (None, Some(*uuid), None),
+            Key::Public(public_id) => (None, None, Some(public_id.as_str())),
+        }
+    }
+}
+
+// Extractor for the `:id` path segment, parsed according to the configured
+// scheme. A segment of the wrong shape is a 404, the same as an unknown id.
+pub struct PathKey(pub Key);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for PathKey
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(segment) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| ApiError::not_found("no id in the path"))?;
+        AppState::from_ref(state)
+            .config
+            .id_scheme
+            .parse_key(&segment)
+            .map(PathKey)
+            .ok_or_else(|| ApiError::not_found(format!("nothing has the id {segment:?}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_ids_encode_their_time_first() {
+        // the example of the ULID spec
+        let id = PublicId::new(1_469_918_176_385, 0);
+        assert_eq!(id.as_str(), "01ARYZ6S410000000000000000");
+        let later = PublicId::new(1_469_918_176_386, 0);
+        let random = PublicId::new(1_469_918_176_385, u128::MAX);
+        assert!(id.as_str() < random.as_str() && random.as_str() < later.as_str());
+    }
+
+    #[test]
+    fn public_ids_parse_in_any_case_and_nothing_else() {
+        let id: PublicId = "01aryz6s41tsv4rrffq69g5fav".parse().unwrap();
+        assert_eq!(id.as_str(), "01ARYZ6S41TSV4RRFFQ69G5FAV");
+        for text in [
+            "01ARYZ6S41TSV4RRFFQ69G5FA",
+            "01ARYZ6S41TSV4RRFFQ69G5FAVV",
+            "81ARYZ6S41TSV4RRFFQ69G5FAV",
+            "01ARYZ6S41TSV4RRFFQ69G5FAU",
+        ] {
+            assert!(text.parse::<PublicId>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn keys_follow_the_scheme_but_always_take_public_ids() {
+        let uuid = Uuid::nil();
+        let public_id = "01ARYZ6S41TSV4RRFFQ69G5FAV";
+        assert_eq!(IdScheme::Serial.parse_key("42"), Some(Key::Serial(42)));
+        assert_eq!(IdScheme::Serial.parse_key(&uuid.to_string()), None);
+        assert_eq!(
+            IdScheme::Uuid.parse_key(&uuid.to_string()),
+            Some(Key::Uuid(uuid))
+        );
+        assert_eq!(IdScheme::Uuid.parse_key("42"), None);
+        for scheme in [IdScheme::Serial, IdScheme::Uuid] {
+            assert_eq!(
+                scheme.parse_key(public_id),
+                Some(Key::Public(public_id.parse().unwrap()))
+            );
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// Admin endpoint handing out impersonation tokens, see `auth`.
+
+use axum::extract::{Path, State};
+use chrono::Duration;
+
+use crate::audit::{self, Change};
+use crate::auth::RequireAdmin;
+use crate::error::{ApiError, OrNotFound};
+use crate::models::ImpersonationToken;
+use crate::negotiate::{Accept, Negotiated};
+use crate::repo;
+use crate::state::AppState;
+
+// how long an impersonation token stays valid
+pub const TOKEN_TTL: Duration = Duration::minutes(15);
+
+// handler for "POST /admin/users/:id/impersonate" rest API endpoint
+pub async fn impersonate_user(
+    RequireAdmin(admin): RequireAdmin,
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    Accept(format): Accept,
+) -> Result<Negotiated<ImpersonationToken>, ApiError> {
+    let user = state.users.get(user_id).await.or_not_found("user")?;
+    let impersonator = admin.to_string();
+    let (token, expires_at) =
+        repo::impersonation::create(&state.pool, user.id, &impersonator, TOKEN_TTL).await?;
+    // never the token itself
+    audit::record(Change::new("user.impersonate", "user", user.id));
+
+    Ok(format.respond(ImpersonationToken {
+        token,
+        user_id: user.id,
+        impersonator,
+        expires_at,
+    }))
+}
@@ -0,0 +1,155 @@
+// Invitations to organizations by email.
+//
+// Owners and admins invite an address with a role (inviting admins or owners
+// takes an owner, as with `orgs::add_member`); the invitation is mailed as a
+// link holding a one-time token, valid for `INVITATION_TTL`. Accepting it
+// makes the user with the invited email, in the tenant, a member, signing
+// one up with the given username when there is none yet. Pending invitations
+// are listed and revoked by the organization's admins.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use chrono::Duration;
+
+use crate::audit::{self, Change};
+use crate::auth::Actor;
+use crate::email;
+use crate::error::{ApiError, OrNotFound};
+use crate::models::{
+    AcceptInvitation, CreateInvitation, CreateUser, Invitation, Membership, Message, OrgRole,
+};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::notify::Notification;
+use crate::orgs::require_role;
+use crate::repo;
+use crate::state::AppState;
+use crate::usernames;
+
+pub const INVITATION_TTL: Duration = Duration::days(7);
+
+// handler for "POST /orgs/:id/invitations" rest API endpoint
+pub async fn create_invitation(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    actor: Actor,
+    Accept(format): Accept,
+    Payload(invitation): Payload<CreateInvitation>,
+) -> Result<Negotiated<Invitation>, ApiError> {
+    let org = repo::orgs::get(&state.pool, id)
+        .await
+        .or_not_found("organization")?;
+    let required = match invitation.role {
+        OrgRole::Member => OrgRole::Admin,
+        OrgRole::Admin | OrgRole::Owner => OrgRole::Owner,
+    };
+    require_role(&state, &actor, org.id, required).await?;
+    let address = email::validate(&invitation.email)?;
+    let (invitation, token) = repo::invitations::create(
+        &state.pool,
+        org.id,
+        &address,
+        invitation.role,
+        actor.user_id,
+        INVITATION_TTL,
+    )
+    .await?;
+    let base = state.config.public_url.as_deref().unwrap_or_default();
+    state
+        .notifier
+        .notify(Notification::Invitation {
+            email: invitation.email.clone(),
+            org_name: org.name,
+            link: format!("{base}/invitations/{token}"),
+            expires_at: invitation.expires_at,
+        })
+        .await;
+    audit::record(Change::new("org.invite", "org", org.id).after(&invitation));
+
+    Ok(format.respond(invitation))
+}
+
+// handler for "GET /orgs/:id/invitations" rest API endpoint, the pending ones
+pub async fn list_invitations(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<Invitation>>, ApiError> {
+    let org = repo::orgs::get(&state.pool, id)
+        .await
+        .or_not_found("organization")?;
+    require_role(&state, &actor, org.id, OrgRole::Admin).await?;
+    let invitations = repo::invitations::pending(&state.pool, org.id).await?;
+
+    Ok(format.respond(invitations))
+}
+
+// handler for "DELETE /orgs/:id/invitations/:invitation_id" rest API endpoint
+pub async fn revoke_invitation(
+    State(state): State<AppState>,
+    Path((id, invitation_id)): Path<(i32, i32)>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    let org = repo::orgs::get(&state.pool, id)
+        .await
+        .or_not_found("organization")?;
+    require_role(&state, &actor, org.id, OrgRole::Admin).await?;
+    if !repo::invitations::revoke(&state.pool, org.id, invitation_id).await? {
+        return Err(ApiError::not_found("pending invitation not found"));
+    }
+    audit::record(Change::new("org.revoke_invitation", "org", org.id));
+
+    Ok(format.respond(Message {
+        message: String::from("Invitation revoked"),
+    }))
+}
+
+// handler for "POST /invitations/:token/accept" rest API endpoint
+pub async fn accept_invitation(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Accept(format): Accept,
+    Payload(accept): Payload<AcceptInvitation>,
+) -> Result<Negotiated<Membership>, ApiError> {
+    let invitation = repo::invitations::find_pending(&state.pool, &token)
+        .await?
+        .ok_or_else(gone)?;
+    let user = match repo::users::find_by_email(&state.pool, &invitation.email).await? {
+        Some(user) => user,
+        None => {
+            let Some(username) = accept.username else {
+                return Err(ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "missing_username",
+                    "no user has the invited email, a username is needed to sign up",
+                )
+                .with("field", "username"));
+            };
+            let new_user = CreateUser {
+                username: usernames::check(&state, &username).await?,
+                email: invitation.email.clone(),
+            };
+            let user = state.users.create(&new_user).await?;
+            audit::record(Change::new("user.create", "user", user.id).after(&user));
+            user
+        }
+    };
+    let membership = repo::invitations::accept(&state.pool, invitation.id, user.id)
+        .await?
+        .ok_or_else(gone)?;
+    audit::record(
+        Change::new("org.accept_invitation", "org", membership.org_id).after(&membership),
+    );
+
+    Ok(format.respond(membership))
+}
+
+// the same answer for unknown, used, revoked and expired tokens
+fn gone() -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        "invalid_invitation",
+        "the invitation doesn't exist, was used or revoked, or has expired",
+    )
+}
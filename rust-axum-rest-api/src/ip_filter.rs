@@ -0,0 +1,140 @@
+// IP allow and deny lists, checked before routing, for locking down the
+// management endpoints.
+//
+// Requests to /admin are judged by ADMIN_IP_ALLOW and ADMIN_IP_DENY, all
+// others by IP_ALLOW and IP_DENY, each a comma separated list of CIDRs or
+// single addresses. Clients on the deny list are turned away with a 403, and
+// so are clients missing from a non-empty allow list. Empty lists let
+// everyone through.
+
+use std::net::IpAddr;
+
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use tracing::info;
+
+use crate::client_ip::ClientIp;
+use crate::error::ApiError;
+use crate::state::AppState;
+
+#[derive(Clone, Debug, Default)]
+pub struct IpRules {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    // the rules of `<prefix>IP_ALLOW` and `<prefix>IP_DENY`
+    pub fn from_env(prefix: &str) -> Result<IpRules, String> {
+        Ok(IpRules {
+            allow: list(&format!("{prefix}IP_ALLOW"))?,
+            deny: list(&format!("{prefix}IP_DENY"))?,
+        })
+    }
+
+    // Whether a client may pass. Clients whose address isn't known only pass
+    // when there is no allow list.
+    pub fn permits(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip.map(|ip| ip.to_canonical()) else {
+            return self.allow.is_empty();
+        };
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+pub(crate) fn list(name: &str) -> Result<Vec<IpNet>, String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| {
+                    format!("invalid {name} entry {entry:?}, expected a CIDR or an address")
+                })
+        })
+        .collect()
+}
+
+fn is_admin(path: &str) -> bool {
+    path == "/admin" || path.starts_with("/admin/")
+}
+
+// turn away the clients the rules for the requested path don't permit
+pub async fn guard(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let rules = match is_admin(request.uri().path()) {
+        true => &state.config.admin_ip_rules,
+        false => &state.config.ip_rules,
+    };
+    if !rules.permits(ip) {
+        info!(
+            ?ip,
+            "turned away {} {}",
+            request.method(),
+            request.uri().path()
+        );
+        return ApiError::new(
+            StatusCode::FORBIDDEN,
+            "ip_forbidden",
+            "requests from this address aren't allowed",
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str]) -> IpRules {
+        let nets = |list: &[&str]| list.iter().map(|net| net.parse().unwrap()).collect();
+        IpRules {
+            allow: nets(allow),
+            deny: nets(deny),
+        }
+    }
+
+    fn ip(text: &str) -> Option<IpAddr> {
+        Some(text.parse().unwrap())
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let rules = rules(&["10.0.0.0/8"], &["10.0.0.13/32"]);
+        assert!(rules.permits(ip("10.1.2.3")));
+        assert!(!rules.permits(ip("10.0.0.13")));
+        assert!(!rules.permits(ip("192.0.2.1")));
+        // IPv4 clients of dual stack sockets
+        assert!(rules.permits(ip("::ffff:10.1.2.3")));
+    }
+
+    #[test]
+    fn unknown_clients_pass_only_without_an_allow_list() {
+        assert!(IpRules::default().permits(None));
+        assert!(rules(&[], &["192.0.2.0/24"]).permits(None));
+        assert!(!rules(&["192.0.2.0/24"], &[]).permits(None));
+    }
+
+    #[test]
+    fn admin_rules_cover_everything_under_admin() {
+        for path in ["/admin", "/admin/jobs", "/admin/debug/pprof/profile"] {
+            assert!(is_admin(path), "{path}");
+        }
+        for path in ["/", "/administrators", "/posts/admin"] {
+            assert!(!is_admin(path), "{path}");
+        }
+    }
+}
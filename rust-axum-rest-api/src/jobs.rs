@@ -0,0 +1,100 @@
+// Scheduled jobs run on every instance, but only one instance at a time does
+// their work: each run first takes a lock named after the job (see `locks`),
+// and an instance finding it taken skips the run.
+//
+// The queue workers (webhook and federation deliveries, link previews) aren't
+// wrapped, their claims already spread the work over instances, nor is the
+// usage flush, each instance has its own counts.
+//
+// `GET /admin/jobs` counts, per job and since the process started, the runs
+// this instance did and the ones it skipped because another held the lock.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::auth::RequireAdmin;
+use crate::error::ApiError;
+use crate::locks::Locks;
+use crate::negotiate::{Accept, Negotiated, XmlElement};
+
+// What this instance did of one job.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct JobStats {
+    pub job: &'static str,
+    pub runs: u64,
+    // runs skipped, another instance holding the lock
+    pub contended: u64,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_contended_at: Option<DateTime<Utc>>,
+}
+
+impl XmlElement for JobStats {
+    const ELEMENT: &'static str = "job";
+    const LIST: &'static str = "jobs";
+}
+
+static STATS: Mutex<BTreeMap<&'static str, JobStats>> = Mutex::new(BTreeMap::new());
+
+fn count(job: &'static str, update: impl FnOnce(&mut JobStats)) {
+    let mut stats = STATS.lock().expect("job stats lock poisoned");
+    let stats = stats.entry(job).or_insert_with(|| JobStats {
+        job,
+        ..Default::default()
+    });
+    update(stats)
+}
+
+// The counts of every job run or skipped so far.
+pub fn stats() -> Vec<JobStats> {
+    let stats = STATS.lock().expect("job stats lock poisoned");
+    stats.values().cloned().collect()
+}
+
+// Run `task` unless another instance is running `job`, returning None when
+// it is. The errors are the lock's or the task's.
+pub async fn exclusive<T, E>(
+    locks: &Locks,
+    job: &'static str,
+    task: impl Future<Output = Result<T, E>>,
+) -> Result<Option<T>, Box<dyn Error + Send + Sync>>
+where
+    E: Into<Box<dyn Error + Send + Sync>>,
+{
+    let Some(mut lease) = locks.try_acquire(job).await? else {
+        count(job, |stats| {
+            stats.contended += 1;
+            stats.last_contended_at = Some(Utc::now());
+        });
+        debug!(job, "skipping the job, another instance is running it");
+        return Ok(None);
+    };
+    count(job, |stats| {
+        stats.runs += 1;
+        stats.last_run_at = Some(Utc::now());
+    });
+    let result = lease.run(task).await;
+    let released = lease.release().await;
+    let value = match result {
+        Some(result) => result.map_err(Into::into)?,
+        None => {
+            warn!(job, "lost the job's lock, stopped it midway");
+            return Ok(None);
+        }
+    };
+    released?;
+    Ok(Some(value))
+}
+
+// handler for "GET /admin/jobs" rest API endpoint
+pub async fn list_jobs(
+    _admin: RequireAdmin,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<JobStats>>, ApiError> {
+    Ok(format.respond(stats()))
+}
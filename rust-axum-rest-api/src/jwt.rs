@@ -0,0 +1,387 @@
+// Short-lived RS256 access tokens (JWTs) for users, traded for an API key at
+// `POST /auth/token`, so that other services can check who a request is made
+// by without asking us: they validate the tokens against the public keys
+// published at `GET /.well-known/jwks.json`.
+//
+// Tokens name the key that signed them in their `kid` header. New tokens are
+// signed with the current key only; rotating it (`rotate-signing-key`, or
+// every SIGNING_KEY_ROTATION_DAYS) retires the old key but keeps it published
+// for GRACE, longer than any token it signed lives, so a rotation never
+// invalidates the sessions in flight.
+
+use std::error::Error;
+
+use axum::extract::State;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use rsa::pkcs1v15::{Signature, SigningKey as RsaSigningKey, VerifyingKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::sha2::Sha256;
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use tracing::{error, info};
+
+use crate::auth::Actor;
+use crate::error::ApiError;
+use crate::federation::signatures::generate_keys;
+use crate::jobs;
+use crate::locks::Locks;
+use crate::models::AccessToken;
+use crate::repo::api_keys::hex;
+use crate::repo::signing_keys::{self, SigningKey};
+use crate::state::AppState;
+use crate::tenant;
+
+// how long an access token stays valid
+pub const TOKEN_TTL: Duration = Duration::minutes(15);
+
+// how long a retired key stays published, longer than TOKEN_TTL
+pub const GRACE: Duration = Duration::hours(1);
+
+// the issuer of our tokens when PUBLIC_URL is unset
+const DEFAULT_ISSUER: &str = "rust-axum-rest-api";
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    typ: Option<String>,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+// What a token says.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub iss: String,
+    // the user id, as a string like JWTs want it
+    pub sub: String,
+    // the tenant the user belongs to, see `tenant`
+    pub tid: i32,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+fn issuer(state: &AppState) -> &str {
+    state.config.public_url.as_deref().unwrap_or(DEFAULT_ISSUER)
+}
+
+pub(crate) fn invalid(message: &str) -> ApiError {
+    ApiError::new(StatusCode::UNAUTHORIZED, "invalid_token", message)
+}
+
+// a fresh key pair under a random kid, generated off the async threads
+async fn generate() -> Result<SigningKey, String> {
+    let keys = tokio::task::spawn_blocking(generate_keys)
+        .await
+        .map_err(|e| e.to_string())??;
+    let mut kid = [0u8; 8];
+    rand::rng().fill_bytes(&mut kid);
+    Ok(SigningKey {
+        kid: hex(&kid),
+        public_key_pem: keys.public_key_pem,
+        private_key_pem: keys.private_key_pem,
+        created_at: Utc::now(),
+    })
+}
+
+// Start signing with a new key, returning its kid. The previous key stays
+// published for GRACE.
+pub async fn rotate(pool: &Pool<Postgres>) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let key = generate().await?;
+    signing_keys::rotate(pool, &key, GRACE, false).await?;
+    info!(kid = key.kid, "rotated the token signing key");
+    Ok(key.kid)
+}
+
+// Rotate the key once it is older than `max_age`, checking hourly.
+pub fn spawn_rotation(pool: Pool<Postgres>, locks: Locks, max_age: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            let due = match signing_keys::current(&pool).await {
+                Ok(Some(key)) => key.created_at + max_age <= Utc::now(),
+                // the first token creates the first key
+                Ok(None) => false,
+                Err(e) => {
+                    error!("looking up the token signing key failed: {e}");
+                    false
+                }
+            };
+            if due {
+                let rotation = jobs::exclusive(&locks, "signing-key-rotation", rotate(&pool));
+                if let Err(e) = rotation.await {
+                    error!("rotating the token signing key failed: {e}");
+                }
+            }
+        }
+    });
+}
+
+// the key to sign with, created on first use
+async fn signing_key(pool: &Pool<Postgres>) -> Result<SigningKey, ApiError> {
+    if let Some(key) = signing_keys::current(pool).await? {
+        return Ok(key);
+    }
+    let key = generate().await.map_err(|e| {
+        error!("generating the token signing key failed: {e}");
+        ApiError::internal()
+    })?;
+    // whoever got there first wins
+    signing_keys::rotate(pool, &key, GRACE, true).await?;
+    signing_keys::current(pool)
+        .await?
+        .ok_or_else(ApiError::internal)
+}
+
+// a token for `user_id` of the current tenant, and when it expires
+pub async fn issue(state: &AppState, user_id: i32) -> Result<(String, DateTime<Utc>), ApiError> {
+    let key = signing_key(&state.pool).await?;
+    let now = Utc::now();
+    let expires_at = now + TOKEN_TTL;
+    let header = Header {
+        alg: String::from("RS256"),
+        typ: Some(String::from("JWT")),
+        kid: Some(key.kid.clone()),
+    };
+    let claims = Claims {
+        iss: issuer(state).to_owned(),
+        sub: user_id.to_string(),
+        tid: tenant::current(),
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+    };
+    let signing_input = format!(
+        "{}.{}",
+        BASE64URL.encode(serde_json::to_vec(&header).expect("serializable header")),
+        BASE64URL.encode(serde_json::to_vec(&claims).expect("serializable claims"))
+    );
+    let private = RsaPrivateKey::from_pkcs8_pem(&key.private_key_pem).map_err(|e| {
+        error!("the token signing key {} is unreadable: {e}", key.kid);
+        ApiError::internal()
+    })?;
+    let signature = RsaSigningKey::<Sha256>::new(private).sign(signing_input.as_bytes());
+    let token = format!("{signing_input}.{}", BASE64URL.encode(signature.to_bytes()));
+    Ok((token, expires_at))
+}
+
+// whether a bearer token is a JWT, ours or the identity provider's (see
+// `oidc`), rather than an API key or an impersonation token
+pub fn looks_like_jwt(token: &str) -> bool {
+    token.starts_with("eyJ") && token.split('.').count() == 3
+}
+
+// A token taken apart, nothing but its shape and algorithm checked yet.
+pub(crate) struct Unverified<'a> {
+    // the key it claims to be signed with
+    pub kid: Option<String>,
+    claims: Vec<u8>,
+    signing_input: &'a str,
+    signature: Signature,
+}
+
+pub(crate) fn decode(token: &str) -> Result<Unverified<'_>, ApiError> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(claims), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid("the token isn't a JWT"));
+    };
+    let header: Header = BASE64URL
+        .decode(header)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| invalid("the token header is malformed"))?;
+    if header.alg != "RS256" {
+        return Err(invalid("the token isn't signed with RS256"));
+    }
+    let claims = BASE64URL
+        .decode(claims)
+        .map_err(|_| invalid("the token claims are malformed"))?;
+    let signature = BASE64URL
+        .decode(signature)
+        .ok()
+        .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok())
+        .ok_or_else(|| invalid("the token signature is malformed"))?;
+    Ok(Unverified {
+        kid: header.kid,
+        claims,
+        signing_input: &token[..token.rfind('.').expect("three parts")],
+        signature,
+    })
+}
+
+impl Unverified<'_> {
+    // the claims, whether or not the signature is valid
+    pub(crate) fn claims<T: DeserializeOwned>(&self) -> Result<T, ApiError> {
+        serde_json::from_slice(&self.claims).map_err(|_| invalid("the token claims are malformed"))
+    }
+
+    // the claims, if `key` signed them
+    pub(crate) fn verify<T: DeserializeOwned>(&self, key: RsaPublicKey) -> Result<T, ApiError> {
+        VerifyingKey::<Sha256>::new(key)
+            .verify(self.signing_input.as_bytes(), &self.signature)
+            .map_err(|_| invalid("the token signature doesn't match"))?;
+        self.claims()
+    }
+}
+
+// The claims of a valid token: signed by a published key, issued by us for
+// the current tenant and not expired.
+pub async fn verify(state: &AppState, token: &str) -> Result<Claims, ApiError> {
+    let token = decode(token)?;
+    let key = signing_keys::published(&state.pool, GRACE)
+        .await?
+        .into_iter()
+        .find(|key| Some(&key.kid) == token.kid.as_ref())
+        .ok_or_else(|| invalid("the token is signed with an unknown key"))?;
+    let public = RsaPublicKey::from_public_key_pem(&key.public_key_pem).map_err(|e| {
+        error!("the token signing key {} is unreadable: {e}", key.kid);
+        ApiError::internal()
+    })?;
+    let claims: Claims = token.verify(public)?;
+    if claims.iss != issuer(state) || claims.tid != tenant::current() {
+        return Err(invalid("the token was issued for someone else"));
+    }
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(invalid("the token has expired"));
+    }
+    Ok(claims)
+}
+
+// handler for "POST /auth/token" rest API endpoint, trades the API key the
+// request is made with for an access token
+pub async fn create_token(
+    State(state): State<AppState>,
+    actor: Actor,
+) -> Result<Json<AccessToken>, ApiError> {
+    let user_id = actor.user_id.ok_or_else(|| {
+        ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "an API key is required",
+        )
+    })?;
+    if actor.impersonator.is_some() {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "impersonation tokens can't be traded for access tokens",
+        ));
+    }
+    let (token, expires_at) = issue(&state, user_id).await?;
+    Ok(Json(AccessToken {
+        token,
+        token_type: String::from("Bearer"),
+        expires_at,
+    }))
+}
+
+// handler for "GET /.well-known/jwks.json" rest API endpoint, the public keys
+// tokens may be signed with
+pub async fn jwks(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let keys = signing_keys::published(&state.pool, GRACE).await?;
+    let mut jwks = Vec::with_capacity(keys.len());
+    for key in keys {
+        let public = RsaPublicKey::from_public_key_pem(&key.public_key_pem).map_err(|e| {
+            error!("the token signing key {} is unreadable: {e}", key.kid);
+            ApiError::internal()
+        })?;
+        jwks.push(json!({
+            "kty": "RSA",
+            "use": "sig",
+            "alg": "RS256",
+            "kid": key.kid,
+            "n": BASE64URL.encode(public.n().to_bytes_be()),
+            "e": BASE64URL.encode(public.e().to_bytes_be()),
+        }));
+    }
+    Ok((
+        [
+            (CONTENT_TYPE, "application/jwk-set+json"),
+            // verifiers refetch on an unknown kid anyway
+            (CACHE_CONTROL, "public, max-age=300"),
+        ],
+        Json(json!({ "keys": jwks })),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(header: serde_json::Value, claims: serde_json::Value, key: &RsaPrivateKey) -> String {
+        let signing_input = format!(
+            "{}.{}",
+            BASE64URL.encode(header.to_string()),
+            BASE64URL.encode(claims.to_string())
+        );
+        let signature = RsaSigningKey::<Sha256>::new(key.clone()).sign(signing_input.as_bytes());
+        format!("{signing_input}.{}", BASE64URL.encode(signature.to_bytes()))
+    }
+
+    fn key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap()
+    }
+
+    #[test]
+    fn tokens_verify_only_against_their_key() {
+        let (key, other) = (key(), key());
+        let token = encode(
+            json!({ "alg": "RS256", "kid": "k1" }),
+            json!({ "sub": "42" }),
+            &key,
+        );
+        assert!(looks_like_jwt(&token));
+        let unverified = decode(&token).unwrap();
+        assert_eq!(unverified.kid.as_deref(), Some("k1"));
+        let claims: serde_json::Value = unverified.verify(RsaPublicKey::from(&key)).unwrap();
+        assert_eq!(claims["sub"], "42");
+        let err = unverified
+            .verify::<serde_json::Value>(RsaPublicKey::from(&other))
+            .unwrap_err();
+        assert_eq!(err.code, "invalid_token");
+
+        // claims changed after signing
+        let (signed, signature) = token.rsplit_once('.').unwrap();
+        let (header, _) = signed.split_once('.').unwrap();
+        let forged = format!(
+            "{header}.{}.{signature}",
+            BASE64URL.encode(json!({ "sub": "1" }).to_string())
+        );
+        assert!(decode(&forged)
+            .unwrap()
+            .verify::<serde_json::Value>(RsaPublicKey::from(&key))
+            .is_err());
+    }
+
+    #[test]
+    fn only_well_formed_rs256_tokens_decode() {
+        let key = key();
+        let hs256 = encode(json!({ "alg": "HS256" }), json!({}), &key);
+        let none = encode(json!({ "alg": "none" }), json!({}), &key);
+        for token in [
+            hs256.as_str(),
+            none.as_str(),
+            "a.b",
+            "a.b.c.d",
+            "not a token",
+        ] {
+            assert!(decode(token).is_err(), "{token}");
+        }
+        assert!(!looks_like_jwt("ak_0123456789"));
+    }
+}
@@ -0,0 +1,87 @@
+// Shared library of the posts and users API: the router and its handlers,
+// the models, content negotiation and the repositories used by the server
+// binary, the admin CLI and the integration tests.
+
+pub mod activity;
+pub mod allocator;
+pub mod archive;
+pub mod audit;
+pub mod auth;
+pub mod authors;
+pub mod avatars;
+pub mod billing;
+pub mod case;
+pub mod client_ip;
+pub mod comments;
+pub mod config;
+pub mod content_filter;
+pub mod db;
+pub mod digest;
+pub mod email;
+pub mod envelope;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod federation;
+pub mod fields;
+pub mod flags;
+pub mod follows;
+pub mod geoip;
+pub mod graphql;
+pub mod handlers;
+pub mod hashtags;
+pub mod ids;
+pub mod impersonation;
+pub mod invitations;
+pub mod ip_filter;
+pub mod jobs;
+pub mod jwt;
+pub mod link_previews;
+pub mod links;
+pub mod locks;
+pub mod maintenance;
+pub mod mentions;
+pub mod meta;
+pub mod methods;
+pub mod metrics;
+pub mod models;
+pub mod moderation;
+pub mod negotiate;
+pub mod notify;
+pub mod oembed;
+pub mod oidc;
+pub mod orgs;
+pub mod pagination;
+pub mod panic;
+pub mod preferences;
+pub mod profile;
+pub mod profiling;
+pub mod qr;
+pub mod rate_limit;
+pub mod redact;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+pub mod relay;
+pub mod repo;
+pub mod request_id;
+pub mod retention;
+pub mod routes;
+pub mod runtime;
+pub mod secrets;
+pub mod server;
+pub mod sessions;
+pub mod short_links;
+pub mod shutdown;
+pub mod spam;
+pub mod startup;
+pub mod state;
+pub mod tenant;
+pub mod tls;
+pub mod tunables;
+pub mod unsubscribe;
+pub mod usage;
+pub mod usernames;
+pub mod webhooks;
+
+pub use routes::build_router;
+pub use state::AppState;
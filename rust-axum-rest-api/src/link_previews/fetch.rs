@@ -0,0 +1,449 @@
+// The fetch worker: loads the queued pages and keeps what their Open Graph
+// tags say about them, falling back to the Twitter card tags and then to the
+// page's <title> and description.
+//
+// Only public addresses are fetched, so that a post can't make the server
+// request its own network: names are resolved by `PublicResolver`, which
+// leaves the client nothing else to connect to, addresses in URLs are
+// checked before every request, and redirects are followed by hand to check
+// theirs too.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{ACCEPT, CONTENT_TYPE, LOCATION};
+use reqwest::{redirect, ClientBuilder, Url};
+use sqlx::{Pool, Postgres};
+use tracing::{debug, error};
+
+use crate::models::LinkPreview;
+use crate::repo;
+
+// pages fetched at once
+const BATCH: i64 = 10;
+
+// how long a taken page is held before another worker may fetch it, longer
+// than fetching can take
+const LEASE: Duration = Duration::from_secs(60);
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const MAX_REDIRECTS: usize = 3;
+
+// how much of a page is read; the tags are in its head
+const MAX_BYTES: usize = 512 * 1024;
+
+// characters kept of a title or description
+const MAX_TEXT: usize = 300;
+
+fn http(allow_private: bool) -> &'static reqwest::Client {
+    static PUBLIC: OnceLock<reqwest::Client> = OnceLock::new();
+    static ANY: OnceLock<reqwest::Client> = OnceLock::new();
+    let client = if allow_private { &ANY } else { &PUBLIC };
+    client.get_or_init(|| {
+        let builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .redirect(redirect::Policy::none())
+            .user_agent(concat!("rust-axum-rest-api/", env!("CARGO_PKG_VERSION")));
+        guarded(builder, allow_private)
+            .build()
+            .expect("the link preview HTTP client builds")
+    })
+}
+
+// Fetch queued pages until the process exits.
+pub fn spawn(pool: Pool<Postgres>) {
+    tokio::spawn(async move {
+        loop {
+            match run_once(&pool, false).await {
+                // there may be more
+                Ok(count) if count as i64 == BATCH => continue,
+                Ok(_) => {}
+                Err(e) => error!("fetching link previews failed: {e}"),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+// Fetch one batch of queued pages, returning how many were attempted.
+// `allow_private` lifts the restriction to public addresses, for tests.
+pub async fn run_once(pool: &Pool<Postgres>, allow_private: bool) -> Result<usize, sqlx::Error> {
+    let due = repo::link_previews::claim_due(pool, BATCH, LEASE.as_secs_f64()).await?;
+    for url in &due {
+        match fetch(url, allow_private).await {
+            Ok(preview) => repo::link_previews::store(pool, &preview).await?,
+            Err(reason) => {
+                debug!("no preview for {url}: {reason}");
+                repo::link_previews::store_error(pool, url, &reason).await?;
+            }
+        }
+    }
+    Ok(due.len())
+}
+
+async fn fetch(queued: &str, allow_private: bool) -> Result<LinkPreview, String> {
+    let mut url = Url::parse(queued).map_err(|e| e.to_string())?;
+    for _ in 0..=MAX_REDIRECTS {
+        check_url(&url, allow_private)?;
+        let mut response = http(allow_private)
+            .get(url.clone())
+            .header(ACCEPT, "text/html")
+            .send()
+            .await
+            .map_err(|e| reason(&e))?;
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or("a redirect without a location")?;
+            url = url.join(location).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if !response.status().is_success() {
+            return Err(format!("the page answered {}", response.status()));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if !content_type.starts_with("text/html") {
+            return Err(format!("not an HTML page but {content_type:?}"));
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            body.extend_from_slice(&chunk);
+            if body.len() >= MAX_BYTES {
+                break;
+            }
+        }
+        return Ok(parse_html(queued, &url, &String::from_utf8_lossy(&body)));
+    }
+    Err(String::from("too many redirects"))
+}
+
+// Fail for URLs that aren't http(s) or whose host is an address that isn't
+// public, also for federation (see `federation::fetch`). Names are left to the client's `PublicResolver`: resolving them
+// here would let the connection resolve them again, to whatever a rebinding
+// DNS server answers the second time.
+pub(crate) fn check_url(url: &Url, allow_private: bool) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("can't fetch {} URLs", url.scheme()));
+    }
+    if allow_private {
+        return Ok(());
+    }
+    let host = url.host_str().ok_or("a URL without a host")?;
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) if !is_public(ip) => Err(format!("{host} isn't a public address")),
+        _ => Ok(()),
+    }
+}
+
+// `builder` connecting to public addresses only, unless `allow_private`
+pub(crate) fn guarded(builder: ClientBuilder, allow_private: bool) -> ClientBuilder {
+    if allow_private {
+        builder
+    } else {
+        builder.dns_resolver(Arc::new(PublicResolver))
+    }
+}
+
+// Resolves names for the clients, failing for those with an address that
+// isn't public; the addresses it checked are the ones connected to.
+pub(crate) struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+            if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+                return Err(format!("{host} isn't a public address").into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+// what went wrong with a request, with the causes that reqwest's message
+// leaves out, such as the resolver's
+pub(crate) fn reason(e: &reqwest::Error) -> String {
+    let mut reason = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        reason = format!("{reason}: {cause}");
+        source = cause.source();
+    }
+    reason
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || first == 0
+                // shared address space, carrier-grade NAT
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local and link-local
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+// The preview of the page at `url`, queued as `queued`, from its head.
+fn parse_html(queued: &str, url: &Url, html: &str) -> LinkPreview {
+    let lower = html.to_ascii_lowercase();
+    let end = lower
+        .find("</head")
+        .or_else(|| lower.find("<body"))
+        .unwrap_or(html.len());
+    let mut meta: HashMap<String, String> = HashMap::new();
+    let mut title = None;
+    let mut at = 0;
+    while let Some(offset) = html[at..end].find('<') {
+        let start = at + offset + 1;
+        let name_length = html[start..end]
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(end - start);
+        let (attributes, length) = attributes(&html[start + name_length..end]);
+        at = start + name_length + length;
+        match lower[start..start + name_length].as_ref() {
+            "meta" => {
+                let key = attributes
+                    .iter()
+                    .find(|(name, _)| name == "property" || name == "name")
+                    .map(|(_, key)| key.to_ascii_lowercase());
+                let content = attributes
+                    .into_iter()
+                    .find(|(name, _)| name == "content")
+                    .map(|(_, content)| content);
+                if let (Some(key), Some(content)) = (key, content) {
+                    meta.entry(key).or_insert(content);
+                }
+            }
+            "title" if title.is_none() => {
+                let close = lower[at..end].find("</title").map_or(end, |i| at + i);
+                title = Some(decode(&html[at..close]));
+                at = close;
+            }
+            _ => {}
+        }
+    }
+    let mut first = |keys: &[&str]| keys.iter().find_map(|key| meta.remove(*key));
+    LinkPreview {
+        url: queued.to_owned(),
+        title: first(&["og:title", "twitter:title"])
+            .and_then(tidy)
+            .or_else(|| title.and_then(tidy)),
+        description: first(&["og:description", "twitter:description", "description"])
+            .and_then(tidy),
+        image_url: first(&[
+            "og:image:secure_url",
+            "og:image",
+            "og:image:url",
+            "twitter:image",
+        ])
+        .and_then(|image| url.join(&image).ok())
+        .filter(|image| matches!(image.scheme(), "http" | "https"))
+        .map(String::from),
+        site_name: first(&["og:site_name"]).and_then(tidy),
+    }
+}
+
+// `text` on one line and at most `MAX_TEXT` characters long, None if blank
+fn tidy(text: String) -> Option<String> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then(|| text.chars().take(MAX_TEXT).collect())
+}
+
+// The attributes of the tag that `tag` is the rest of, names lowercased and
+// values decoded, and the length of the tag up to and including its `>`.
+fn attributes(tag: &str) -> (Vec<(String, String)>, usize) {
+    let bytes = tag.as_bytes();
+    let skip_whitespace = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+    let mut attributes = Vec::new();
+    let mut i = 0;
+    loop {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        if i == bytes.len() {
+            return (attributes, i);
+        }
+        if bytes[i] == b'>' {
+            return (attributes, i + 1);
+        }
+        let name_start = i;
+        while i < bytes.len()
+            && !bytes[i].is_ascii_whitespace()
+            && !matches!(bytes[i], b'=' | b'>' | b'/')
+        {
+            i += 1;
+        }
+        let name = tag[name_start..i].to_ascii_lowercase();
+        i = skip_whitespace(i);
+        let mut value = String::new();
+        if i < bytes.len() && bytes[i] == b'=' {
+            i = skip_whitespace(i + 1);
+            if i < bytes.len() && matches!(bytes[i], b'"' | b'\'') {
+                let quote = bytes[i];
+                let start = i + 1;
+                i = start;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += 1;
+                }
+                value = decode(&tag[start..i]);
+                i = (i + 1).min(bytes.len());
+            } else {
+                let start = i;
+                while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                    i += 1;
+                }
+                value = decode(&tag[start..i]);
+            }
+        }
+        if !name.is_empty() {
+            attributes.push((name, value));
+        }
+    }
+}
+
+// `text` with its character references replaced, the named ones common in
+// titles and the numeric ones
+fn decode(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        decoded.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let reference = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let character = reference.and_then(|reference| match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let number = reference.strip_prefix('#')?;
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (reference, character) {
+            (Some(reference), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in [
+            "10.1.2.3",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.216.34", "100.128.0.1", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn urls_with_private_addresses_are_refused() {
+        let check = |url: &str, allow_private| check_url(&Url::parse(url).unwrap(), allow_private);
+        assert!(check("http://127.0.0.1:8080/", false)
+            .unwrap_err()
+            .contains("isn't a public address"));
+        assert!(check("http://[::1]/", false).is_err());
+        assert!(check("http://[::ffff:10.0.0.1]/", false).is_err());
+        assert!(check("ftp://example.com/", false).is_err());
+        assert!(check("file:///etc/passwd", true).is_err());
+        // names are the resolver's to check
+        assert!(check("https://example.com/", false).is_ok());
+        assert!(check("http://127.0.0.1/", true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn names_resolving_to_private_addresses_are_refused() {
+        let resolved = PublicResolver.resolve("localhost".parse().unwrap()).await;
+        let error = resolved.err().expect("localhost is refused");
+        assert!(error.to_string().contains("isn't a public address"));
+    }
+
+    // whatever a name resolves to when the client connects is checked, not
+    // just what it resolved to before
+    #[tokio::test]
+    async fn clients_connect_to_public_addresses_only() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = axum::Router::new().route("/", axum::routing::get(|| async { "internal" }));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let url = format!("http://localhost:{port}/");
+
+        let client = guarded(reqwest::Client::builder(), false).build().unwrap();
+        let error = client.get(&url).send().await.unwrap_err();
+        assert!(reason(&error).contains("isn't a public address"), "{error}");
+
+        let client = guarded(reqwest::Client::builder(), true).build().unwrap();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "internal");
+    }
+}
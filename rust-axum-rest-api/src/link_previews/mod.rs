@@ -0,0 +1,111 @@
+// Link previews: the pages a post links to are fetched in the background
+// and what their Open Graph tags say about them kept, so that
+// `GET /posts/:id` lists them as `link_previews` for clients to render cards
+// with. Lists of posts leave them out.
+//
+// Links are synced whenever a post is written, like hashtags. Previews are
+// shared by every post linking the same URL, and fetched again when a post
+// links a page whose preview is older than `MAX_AGE_DAYS`. A preview shows
+// up once the worker in `fetch` got to it; pages that couldn't be fetched
+// have none. The links of posts held back for moderation aren't fetched
+// until the post is approved, so spam can't send the server anywhere.
+
+pub mod fetch;
+
+use serde::Serialize;
+
+use crate::error::ApiError;
+use crate::ids::IdScheme;
+use crate::links::{HasLinks, Links};
+use crate::models::{LinkPreview, Post, PostStatus};
+use crate::negotiate::XmlElement;
+use crate::repo;
+use crate::state::AppState;
+
+// links of a post that get a preview, the first ones
+pub const MAX_LINKS: usize = 5;
+
+const MAX_AGE_DAYS: i32 = 7;
+
+const MAX_URL_LENGTH: usize = 2048;
+
+// the http and https URLs in `body`, each once
+pub fn parse(body: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for word in body.split_whitespace() {
+        let Some(start) = word.find("https://").or_else(|| word.find("http://")) else {
+            continue;
+        };
+        // sentence punctuation and the closing brackets of `(https://…)`
+        let candidate = word[start..].trim_end_matches(|c: char| {
+            matches!(
+                c,
+                '.' | ',' | ';' | ':' | '!' | '?' | ')' | ']' | '}' | '>' | '"' | '\''
+            )
+        });
+        let Ok(url) = reqwest::Url::parse(candidate) else {
+            continue;
+        };
+        let url = url.to_string();
+        if url.len() <= MAX_URL_LENGTH && !urls.contains(&url) {
+            urls.push(url);
+        }
+        if urls.len() == MAX_LINKS {
+            break;
+        }
+    }
+    urls
+}
+
+// make a published post's links the URLs in its body. `previous` is the
+// body being replaced; when neither has links there is nothing to do.
+pub async fn record(
+    state: &AppState,
+    post: &Post,
+    previous: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    if post.status != PostStatus::Published.as_str() {
+        return Ok(());
+    }
+    let urls = parse(&post.body);
+    if urls.is_empty() && previous.is_none_or(|body| parse(body).is_empty()) {
+        return Ok(());
+    }
+    repo::link_previews::sync(&state.pool, post.id, &urls, MAX_AGE_DAYS).await
+}
+
+// the previews of a post's links fetched so far
+pub async fn for_post(state: &AppState, post: &Post) -> Result<Vec<LinkPreview>, ApiError> {
+    if parse(&post.body).is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(repo::link_previews::for_post(&state.pool, post.id).await?)
+}
+
+// A post serialized with the previews of its links next to its own fields.
+#[derive(Serialize)]
+pub struct WithPreviews<T> {
+    #[serde(flatten)]
+    post: T,
+    link_previews: Vec<LinkPreview>,
+}
+
+impl<T> WithPreviews<T> {
+    pub fn new(post: T, link_previews: Vec<LinkPreview>) -> Self {
+        WithPreviews {
+            post,
+            link_previews,
+        }
+    }
+}
+
+impl<T: HasLinks> HasLinks for WithPreviews<T> {
+    fn links(&self, ids: IdScheme) -> Links {
+        self.post.links(ids)
+    }
+}
+
+impl<T: XmlElement> XmlElement for WithPreviews<T> {
+    const ELEMENT: &'static str = T::ELEMENT;
+    const LIST: &'static str = T::LIST;
+}
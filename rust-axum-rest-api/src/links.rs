@@ -0,0 +1,98 @@
+// HATEOAS links: post responses carry a `_links` object pointing at related
+// resources, e.g.
+//
+//     "_links": {
+//         "self": {"href": "/posts/01J..."},
+//         "author": {"href": "/users/3"},
+//         "comments": {"href": "/posts/01J.../comments"}
+//     }
+//
+// Lists are paged through their Link header, which the envelope repeats as
+// the list's own `_links` (see `envelope`).
+//
+// The hrefs are filled in from the route templates in `routes`, so they
+// can't drift from the paths the router actually serves, and users are
+// addressed the way ID_SCHEME says.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::fields::Sparse;
+use crate::ids::IdScheme;
+use crate::models::{Post, PostWithAuthor};
+use crate::negotiate::XmlElement;
+use crate::routes::{expand, COMMENTS_ROUTE, POST_ROUTE, USER_ROUTE};
+
+#[derive(Serialize)]
+pub struct Link {
+    pub href: String,
+}
+
+impl Link {
+    fn to(template: &str, id: &str) -> Link {
+        Link {
+            href: expand(template, id),
+        }
+    }
+}
+
+pub type Links = BTreeMap<&'static str, Link>;
+
+pub trait HasLinks {
+    fn links(&self, ids: IdScheme) -> Links;
+}
+
+impl HasLinks for Post {
+    fn links(&self, ids: IdScheme) -> Links {
+        // the public id addresses the post whatever ID_SCHEME is
+        let mut links = Links::from([
+            ("self", Link::to(POST_ROUTE, &self.public_id)),
+            ("comments", Link::to(COMMENTS_ROUTE, &self.public_id)),
+        ]);
+        if let Some(user) = self.user_id.and_then(|id| ids.segment(id, self.user_uuid)) {
+            links.insert("author", Link::to(USER_ROUTE, &user));
+        }
+        links
+    }
+}
+
+impl HasLinks for PostWithAuthor {
+    fn links(&self, ids: IdScheme) -> Links {
+        self.post.links(ids)
+    }
+}
+
+impl<T: HasLinks> HasLinks for Sparse<T> {
+    fn links(&self, ids: IdScheme) -> Links {
+        self.value().links(ids)
+    }
+}
+
+// A value serialized with its `_links` next to its own fields.
+#[derive(Serialize)]
+pub struct Linked<T> {
+    #[serde(flatten)]
+    value: T,
+    #[serde(rename = "_links")]
+    links: Links,
+}
+
+impl<T: HasLinks> Linked<T> {
+    pub fn new(value: T, ids: IdScheme) -> Self {
+        let links = value.links(ids);
+        Linked { value, links }
+    }
+
+    pub fn all(values: Vec<T>, ids: IdScheme) -> Vec<Self> {
+        values
+            .into_iter()
+            .map(|value| Linked::new(value, ids))
+            .collect()
+    }
+}
+
+impl<T: XmlElement> XmlElement for Linked<T> {
+    const ELEMENT: &'static str = T::ELEMENT;
+    const LIST: &'static str = T::LIST;
+}
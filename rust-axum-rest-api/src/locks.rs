@@ -0,0 +1,231 @@
+// Locks shared by the instances of a deployment, for work only one of them
+// should do at a time: scheduled jobs (see `jobs`), rebuilding what is
+// shared, and singleton services.
+//
+// Without Redis they are Postgres advisory locks, held on a connection of
+// their own for as long as the lock is. With Redis (see `redis_store`) they
+// are keys set with `SET NX PX` to a random token, expiring after `TTL`
+// unless renewed, and deleted on release only while they still hold that
+// token, so an instance never releases a lock another one took over.
+//
+// A lease is renewed every `RENEW_INTERVAL` while its work runs; the work
+// is dropped if the lease is lost (its connection broken, its key expired),
+// another instance may be doing it already.
+//
+// `spawn_singleton` is leader election on top: every instance campaigns for
+// a lock named after a service, and the one holding it runs the service
+// until it loses it.
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use sqlx::{Connection, PgConnection, Pool, Postgres};
+use tracing::{error, info, warn};
+
+#[cfg(feature = "redis")]
+use crate::redis_store::RedisStore;
+
+// how long a Redis lock lives without being renewed
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+const TTL: Duration = Duration::from_secs(30);
+
+const RENEW_INTERVAL: Duration = Duration::from_secs(10);
+
+// how often instances not leading try to
+const CAMPAIGN_INTERVAL: Duration = Duration::from_secs(15);
+
+// the first key of the advisory locks, keeping them apart from any other
+// locks taken on the database
+const LOCK_NAMESPACE: i32 = 0x6a6f6273;
+
+#[derive(Debug)]
+pub enum LockError {
+    Postgres(sqlx::Error),
+    #[cfg(feature = "redis")]
+    Redis(redis::RedisError),
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Postgres(e) => write!(f, "Postgres lock: {e}"),
+            #[cfg(feature = "redis")]
+            LockError::Redis(e) => write!(f, "Redis lock: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+impl From<sqlx::Error> for LockError {
+    fn from(e: sqlx::Error) -> Self {
+        LockError::Postgres(e)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl From<redis::RedisError> for LockError {
+    fn from(e: redis::RedisError) -> Self {
+        LockError::Redis(e)
+    }
+}
+
+// Where locks are taken, see the top of the module.
+#[derive(Clone)]
+pub enum Locks {
+    Postgres(Pool<Postgres>),
+    #[cfg(feature = "redis")]
+    Redis(RedisStore),
+}
+
+impl Locks {
+    // Take the lock `name` unless another instance holds it.
+    pub async fn try_acquire(&self, name: &str) -> Result<Option<Lease>, LockError> {
+        match self {
+            Locks::Postgres(pool) => {
+                let mut conn = pool.acquire().await?.detach();
+                let locked = sqlx::query_scalar!(
+                    r#"SELECT pg_try_advisory_lock($1, hashtext($2)) AS "locked!""#,
+                    LOCK_NAMESPACE,
+                    name
+                )
+                .fetch_one(&mut conn)
+                .await?;
+                if !locked {
+                    conn.close().await?;
+                    return Ok(None);
+                }
+                Ok(Some(Lease::Postgres(conn)))
+            }
+            #[cfg(feature = "redis")]
+            Locks::Redis(store) => {
+                let key = format!("lock:{name}");
+                let mut token = [0u8; 16];
+                rand::RngCore::fill_bytes(&mut rand::rng(), &mut token);
+                let token = crate::repo::api_keys::hex(&token);
+                let set: Option<String> = redis::cmd("SET")
+                    .arg(&key)
+                    .arg(&token)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(TTL.as_millis() as u64)
+                    .query_async(&mut store.conn().await?)
+                    .await?;
+                Ok(set.map(|_| Lease::Redis {
+                    store: store.clone(),
+                    key,
+                    token,
+                }))
+            }
+        }
+    }
+}
+
+// A lock held, until released or lost.
+pub enum Lease {
+    // the lock goes with the session
+    Postgres(PgConnection),
+    #[cfg(feature = "redis")]
+    Redis {
+        store: RedisStore,
+        key: String,
+        token: String,
+    },
+}
+
+#[cfg(feature = "redis")]
+const RENEW_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then \
+     return redis.call('pexpire', KEYS[1], ARGV[2]) else return 0 end";
+
+#[cfg(feature = "redis")]
+const RELEASE_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then \
+     return redis.call('del', KEYS[1]) else return 0 end";
+
+impl Lease {
+    // Whether the lock is still held, extending it if it expires.
+    pub async fn renew(&mut self) -> Result<bool, LockError> {
+        match self {
+            Lease::Postgres(conn) => {
+                conn.ping().await?;
+                Ok(true)
+            }
+            #[cfg(feature = "redis")]
+            Lease::Redis { store, key, token } => {
+                let renewed: i32 = redis::Script::new(RENEW_SCRIPT)
+                    .key(&*key)
+                    .arg(&*token)
+                    .arg(TTL.as_millis() as u64)
+                    .invoke_async(&mut store.conn().await?)
+                    .await?;
+                Ok(renewed == 1)
+            }
+        }
+    }
+
+    // Give the lock up.
+    pub async fn release(self) -> Result<(), LockError> {
+        match self {
+            Lease::Postgres(conn) => conn.close().await?,
+            #[cfg(feature = "redis")]
+            Lease::Redis { store, key, token } => {
+                let _: i32 = redis::Script::new(RELEASE_SCRIPT)
+                    .key(key)
+                    .arg(token)
+                    .invoke_async(&mut store.conn().await?)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Run `work` while renewing the lease, None if it was lost first.
+    pub async fn run<T>(&mut self, work: impl Future<Output = T>) -> Option<T> {
+        tokio::pin!(work);
+        let mut renewal = tokio::time::interval(RENEW_INTERVAL);
+        // the first tick is immediate, the lease was just taken
+        renewal.tick().await;
+        loop {
+            tokio::select! {
+                value = &mut work => return Some(value),
+                _ = renewal.tick() => match self.renew().await {
+                    Ok(true) => {}
+                    Ok(false) => return None,
+                    Err(e) => {
+                        warn!("renewing a lock failed, giving it up: {e}");
+                        return None;
+                    }
+                },
+            }
+        }
+    }
+}
+
+// Run `service` on the one instance holding the lock `name`, starting it
+// again on another when that one loses the lock.
+pub fn spawn_singleton<F, Fut>(locks: Locks, name: &'static str, mut service: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tokio::spawn(async move {
+        loop {
+            match locks.try_acquire(name).await {
+                Ok(Some(mut lease)) => {
+                    info!(service = name, "leading, running the service here");
+                    let done = lease.run(service()).await;
+                    if let Err(e) = lease.release().await {
+                        warn!(service = name, "releasing the lock failed: {e}");
+                    }
+                    if done.is_some() {
+                        return;
+                    }
+                    warn!(service = name, "lost the lead, stopped the service");
+                }
+                Ok(None) => {}
+                Err(e) => error!(service = name, "campaigning for the lead failed: {e}"),
+            }
+            tokio::time::sleep(CAMPAIGN_INTERVAL).await;
+        }
+    });
+}
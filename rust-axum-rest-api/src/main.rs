@@ -15,49 +15,18 @@ Users: To manage the users who can create and interact with posts.
 
 */
 
-use dotenvy::dotenv;
-use sqlx::postgres::PgPoolOptions;
-use sqlx::Postgres;
-use sqlx::Pool;
-use axum::{extract::Extension, routing::get, Json, Router};
-use axum::routing::post;
-use axum::extract::Path;
-use tracing::{info, Level};
-use tracing_subscriber;
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize)]
-struct Post {
-    id: i32,
-    user_id: Option<i32>,
-    title: String,
-    body: String,
-}
+mod commands;
 
-#[derive(Serialize, Deserialize)]
-struct UpdatePost {
-    title: String,
-    body: String,
-    user_id: Option<i32>,
-}
+use std::path::Path;
 
-#[derive(Serialize)]
-struct Message {
-    message: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct CreateUser {
-    username: String,
-    email: String,
-}
- 
-#[derive(Serialize, Deserialize)]
-struct User {
-    id: i32,
-    username: String,
-    email: String,
-}
+use clap::Parser;
+use commands::{Cli, Command};
+use rust_axum_rest_api::profile::{LogFormat, Profile};
+use rust_axum_rest_api::routes::{build_router_for, Surface};
+use rust_axum_rest_api::startup::{self, Started};
+use rust_axum_rest_api::AppState;
+use rust_axum_rest_api::{server, tls, tunables};
+use tracing::info;
 
 /* Initial test for database connection
 
@@ -72,144 +41,177 @@ async fn main() -> Result<(), sqlx::Error> {
 }
 */
 
-
-// handler for "GET /" rest API endpoint
-async fn root() -> &'static str {
-    "Hello, world!"
-}
-
-// handler for "GET /posts" rest API endpoint
-async fn get_posts(
-    Extension(pool): Extension<Pool<Postgres>>
-) -> Result<Json<Vec<Post>>, StatusCode> {
-    let posts = sqlx::query_as!(Post, "SELECT id, title, body FROM posts")
-        .fetch_all(&pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(Json(posts))
-}
-
-// handler for "GET /posts/:id" rest API endpoint
-async fn get_post(
-    Extension(pool): Extension<Pool<Postgres>>,
-    Path(id): Path<i32>,
-) -> Result<Json<Post>, StatusCode> {
-    let post = sqlx::query_as!(
-        Post,
-        "SELECT id, user_id, title, body FROM posts WHERE id = $1",
-        id
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|_| StatusCode::NOT_FOUND)?;
- 
-    Ok(Json(post))
-}
-
-// handler for Create a new post and return the created data
-async fn create_post(
-    Extension(pool): Extension<Pool<Postgres>>,
-    Json(new_post): Json<CreatePost>,
-) -> Result<Json<Post>, StatusCode> {
-    let post = sqlx::query_as!(
-        Post,
-        "INSERT INTO posts (user_id, title, body) VALUES ($1, $2, $3) RETURNING id, title, body, user_id",
-        new_post.user_id,
-        new_post.title,
-        new_post.body
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
- 
-    Ok(Json(post))
-}
-
-// handler for Update a post and return the updated data
-async fn update_post(
-    Extension(pool): Extension<Pool<Postgres>>,
-    Path(id): Path<i32>,
-    Json(updated_post): Json<UpdatePost>,
-) -> Result<Json<Post>, StatusCode> {
-    let post = sqlx::query_as!(
-        Post,
-        "UPDATE posts SET title = $1, body = $2, user_id = $3 WHERE id = $4 RETURNING id, user_id, title, body",
-        updated_post.title,
-        updated_post.body,
-        updated_post.user_id,
-        id
-    )
-    .fetch_one(&pool)
-    .await;
- 
-    match post {
-        Ok(post) => Ok(Json(post)),
-        Err(_) => Err(StatusCode::NOT_FOUND),
+// build the router and serve it until SIGTERM, then shut down without
+// dropping requests (see `shutdown`)
+async fn serve(state: AppState) {
+    rust_axum_rest_api::panic::install_hook();
+    let addr = state.config.bind_addr;
+    // the config file's log level and friends, and again on every SIGHUP
+    tunables::apply(&state, state.config.tunables.clone()).unwrap();
+    #[cfg(unix)]
+    tunables::reload_on_sighup(state.clone());
+    // deliver federated posts, see `federation`
+    if let Some(base) = &state.config.public_url {
+        rust_axum_rest_api::federation::delivery::spawn(
+            state.pool.clone(),
+            base.clone(),
+            state.config.federation_private_hosts,
+        );
+    }
+    if let Some(max_age) = state.config.signing_key_rotation {
+        rust_axum_rest_api::jwt::spawn_rotation(state.pool.clone(), state.locks.clone(), max_age);
+    }
+    // delete what is past keeping, see `retention`
+    if let Some(retention) = state.config.retention {
+        rust_axum_rest_api::retention::spawn(state.pool.clone(), state.locks.clone(), retention);
+    }
+    // move old posts out of the way, see `archive`
+    if let Some(years) = state.config.archive_after_years {
+        rust_axum_rest_api::archive::spawn(state.pool.clone(), state.locks.clone(), years);
+    }
+    // mail the weekly digests, see `digest`
+    rust_axum_rest_api::digest::spawn(state.clone());
+    // fetch the pages posts link to, see `link_previews`
+    rust_axum_rest_api::link_previews::fetch::spawn(state.pool.clone());
+    // write the usage counts, see `usage`
+    rust_axum_rest_api::usage::spawn_flusher(state.clone());
+    // pass events on to the other instances, see `relay`
+    if state.config.event_relay {
+        rust_axum_rest_api::relay::start(state.clone())
+            .await
+            .unwrap();
+    }
+    // forget expired sessions, see `sessions`
+    rust_axum_rest_api::sessions::spawn_cleanup(state.clone());
+    // send webhooks, see `webhooks`
+    rust_axum_rest_api::webhooks::spawn_dispatcher(&state);
+    rust_axum_rest_api::webhooks::delivery::spawn(state.pool.clone());
+    let shutdown = state.shutdown.clone();
+    let shutdown_config = state.config.shutdown;
+    let tls = state.config.tls.clone();
+    let http_redirect_addr = state.config.http_redirect_addr;
+    let http2 = state.config.http2;
+    #[cfg(unix)]
+    let unix_socket = state.config.unix_socket.clone();
+    // /admin on a listener of its own, or everything on all of them
+    let admin_apart = state
+        .config
+        .listeners
+        .iter()
+        .any(|l| l.surface == Surface::Admin);
+    let surface = if admin_apart {
+        Surface::Api
+    } else {
+        Surface::All
+    };
+    // more addresses, see `server`
+    for listener in state.config.listeners.clone() {
+        let tcp = tokio::net::TcpListener::bind(listener.addr).await.unwrap();
+        info!("Serving {:?} on http://{}", listener.surface, listener.addr);
+        let app = build_router_for(state.clone(), listener.surface);
+        tokio::spawn(server::serve(tcp, http2, app, shutdown.clone()));
+    }
+    // for the last usage counts, once the requests are done
+    let (usage, pool) = (state.usage.clone(), state.pool.clone());
+    let app = build_router_for(state, surface);
+
+    // on a unix socket for a proxy on the same host, see `server`
+    #[cfg(unix)]
+    if let Some(socket) = unix_socket {
+        let listener = socket.bind().unwrap();
+        info!("Server is running on unix:{}", socket.path.display());
+        tokio::spawn(server::serve_unix(
+            listener,
+            http2,
+            app.clone(),
+            shutdown.clone(),
+        ));
     }
-}
 
-// This handler is a bit different as we delete a post we cannot return any data but we will return custom JSON response using the serde_json crate
-async fn delete_post(
-    Extension(pool): Extension<Pool<Postgres>>,
-    Path(id): Path<i32>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    let result = sqlx::query!("DELETE FROM posts WHERE id = $1", id)
-        .execute(&pool)
-        .await;
- 
-    match result {
-        Ok(_) => Ok(Json(serde_json::json! ({
-            "message": "Post deleted successfully"
-        }))),
-        Err(_) => Err(StatusCode::NOT_FOUND),
+    // run our app with hyper, listening on BIND_ADDR (all interfaces on port 5000 by default)
+    if let Some(addr) = addr {
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        if let Some(tls) = tls {
+            if let Some(redirect_addr) = http_redirect_addr {
+                let redirect = tokio::net::TcpListener::bind(redirect_addr).await.unwrap();
+                info!("Redirecting http://{redirect_addr} to HTTPS");
+                tokio::spawn(tls::redirect_to_https(redirect, addr.port()));
+            }
+            info!("Server is running on https://{addr}");
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                if let Err(e) = tls::serve(listener, &tls, http2, app, shutdown).await {
+                    tracing::error!("can't serve HTTPS: {e}");
+                    std::process::exit(1);
+                }
+            });
+        } else {
+            info!("Server is running on http://{addr}");
+            tokio::spawn(server::serve(listener, http2, app, shutdown.clone()));
+        }
     }
-}
 
-async fn create_user(
-    Extension(pool): Extension<Pool<Postgres>>,
-    Json(new_user): Json<CreateUser>,
-) -> Result<Json<User>, StatusCode> {
-    let user = sqlx::query_as!(
-        User,
-        "INSERT INTO users (username, email) VALUES ($1, $2) RETURNING id, username, email",
-        new_user.username,
-        new_user.email
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
- 
-    Ok(Json(user))
+    // serve until SIGTERM, then drain
+    rust_axum_rest_api::shutdown::signal().await;
+    shutdown.run(shutdown_config).await;
+    // what was counted since the last flush, see `usage`
+    if let Err(e) = usage.flush(&pool).await {
+        tracing::error!("flushing usage failed: {e}");
+    }
 }
 
-
 #[tokio::main]
-async fn main() -> Result<(), sqlx::Error> {
-    // initialize tracing for logging with maximum level of tracing INFO
-    tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
-        .init();
-
-    // looading your environment variables from a .env file and connect to the database
-    dotenv().ok();
-    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPoolOptions::new().connect(&url).await?;
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let cli = Cli::parse();
+
+    // looading your environment variables from the .env files of the APP_ENV
+    // profile, LOG_REDACT_FIELDS and LOG_FORMAT included (see `profile`)
+    let profile = Profile::load(Path::new("."))?;
+
+    // initialize tracing for logging with maximum level of tracing INFO, until
+    // the config's log level is applied
+    tunables::init_logging(LogFormat::from_env(profile)?);
+    info!("Running the {profile} profile");
+
+    // the variables kept in a secrets backend over the .env ones, the
+    // configuration and the database (see `startup`)
+    let Started {
+        config,
+        pool,
+        secrets,
+    } = startup::connect().await?;
     info!("Connected to the database!");
- 
-    // build anew router for our application with a route
-    let app = Router::new()
-        // `GET /` goes to `root`
-        .route("/", get(root))
-        .route("/posts", get(get_posts).post(create_post))
-        .route("/posts/:id", get(get_post).put(update_post).delete(delete_post))
-        .route("/users", post(create_user))
-        // extension layer
-        .layer(Extension(pool));
- 
-    // run our app with hyper, listening globally on port 5000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:5000").await.unwrap();
-    info!("Server is running on http://0.0.0.0:5000");
-    axum::serve(listener, app).await.unwrap();
- 
+    if let Some(secrets) = secrets {
+        secrets.spawn_refresh(pool.clone());
+    }
+
+    match cli.command {
+        None | Some(Command::Serve) => serve(AppState::new(pool, config)).await,
+        Some(Command::ExportParquet { out }) => commands::parquet::run(&pool, &out).await?,
+        Some(Command::Import {
+            file,
+            batch_size,
+            on_duplicate,
+        }) => commands::import::run(&pool, &file, batch_size.max(1), on_duplicate).await?,
+        Some(Command::Seed {
+            users,
+            posts,
+            force,
+        }) => commands::seed::run(&pool, users, posts, force).await?,
+        Some(Command::RotateSigningKey) => {
+            let kid = rust_axum_rest_api::jwt::rotate(&pool).await?;
+            println!("now signing access tokens with key {kid}");
+        }
+        Some(Command::Backup { out }) => commands::backup::backup(&pool, &out).await?,
+        Some(Command::Restore {
+            file,
+            truncate,
+            force,
+        }) => commands::backup::restore(&pool, &file, truncate, force).await?,
+        Some(Command::EncryptEmails { batch_size }) => {
+            commands::encrypt_emails::run(&pool, batch_size.max(1)).await?
+        }
+    }
+
     Ok(())
 }
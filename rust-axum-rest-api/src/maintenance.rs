@@ -0,0 +1,169 @@
+// Maintenance mode, switched at runtime by admins through
+// `PUT /admin/maintenance` or set at startup with MAINTENANCE.
+//
+// In `read_only` mode reads are still served and writes (anything but GET,
+// HEAD and OPTIONS) are answered with a 503 carrying Retry-After; in `full`
+// mode every request is. `/readyz` reports not-ready in either, so load
+// balancers drain the instance meanwhile. The maintenance endpoint itself
+// keeps working so that the mode can be switched off again.
+
+use std::str::FromStr;
+
+use axum::extract::{Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::audit::{self, Change};
+use crate::auth::RequireAdmin;
+use crate::error::ApiError;
+use crate::negotiate::{Accept, Negotiated, Payload, XmlElement};
+use crate::state::AppState;
+
+// seconds clients are told to wait unless the admin says otherwise
+pub const DEFAULT_RETRY_AFTER: u64 = 300;
+
+// served whatever the mode
+const EXEMPT: &[&str] = &["/admin/maintenance", "/readyz"];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceMode {
+    #[default]
+    Off,
+    ReadOnly,
+    Full,
+}
+
+impl FromStr for MaintenanceMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "off" => Ok(MaintenanceMode::Off),
+            "read_only" => Ok(MaintenanceMode::ReadOnly),
+            "full" => Ok(MaintenanceMode::Full),
+            _ => Err(format!(
+                "invalid MAINTENANCE {s:?}, expected off, read_only or full"
+            )),
+        }
+    }
+}
+
+// The current maintenance state, and the body of `PUT /admin/maintenance`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Maintenance {
+    pub mode: MaintenanceMode,
+    // the Retry-After of the 503s, in seconds
+    #[serde(default = "default_retry_after")]
+    pub retry_after: u64,
+}
+
+fn default_retry_after() -> u64 {
+    DEFAULT_RETRY_AFTER
+}
+
+impl Maintenance {
+    pub fn new(mode: MaintenanceMode) -> Self {
+        Maintenance {
+            mode,
+            retry_after: DEFAULT_RETRY_AFTER,
+        }
+    }
+
+    fn unavailable(&self) -> Response {
+        let error = ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "maintenance",
+            "the API is down for maintenance, try again later",
+        )
+        .with("mode", self.mode);
+        ([(RETRY_AFTER, self.retry_after.to_string())], error).into_response()
+    }
+}
+
+impl XmlElement for Maintenance {
+    const ELEMENT: &'static str = "maintenance";
+    const LIST: &'static str = "maintenances";
+}
+
+fn current(state: &AppState) -> Maintenance {
+    state.maintenance.read().expect("maintenance lock").clone()
+}
+
+// turn requests away while in maintenance
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let maintenance = current(&state);
+    let served = match maintenance.mode {
+        MaintenanceMode::Off => true,
+        MaintenanceMode::ReadOnly => request.method().is_safe(),
+        MaintenanceMode::Full => false,
+    };
+    if served || EXEMPT.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    maintenance.unavailable()
+}
+
+// handler for "GET /admin/maintenance" rest API endpoint
+pub async fn get_maintenance(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+) -> Negotiated<Maintenance> {
+    format.respond(current(&state))
+}
+
+// handler for "PUT /admin/maintenance" rest API endpoint, switches the mode
+// of this instance
+pub async fn set_maintenance(
+    RequireAdmin(admin): RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Payload(maintenance): Payload<Maintenance>,
+) -> Negotiated<Maintenance> {
+    let before = std::mem::replace(
+        &mut *state.maintenance.write().expect("maintenance lock"),
+        maintenance.clone(),
+    );
+    info!("{admin} set maintenance mode {:?}", maintenance.mode);
+    audit::record(
+        Change::new("maintenance.set", "maintenance", "api")
+            .before(&before)
+            .after(&maintenance),
+    );
+
+    format.respond(maintenance)
+}
+
+// handler for "GET /readyz" rest API endpoint, whether this instance should
+// get traffic: not shutting down (see `shutdown`), not in maintenance and
+// with a working database
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    if state.shutdown.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "shutting_down" })),
+        )
+            .into_response();
+    }
+    let maintenance = current(&state);
+    if maintenance.mode != MaintenanceMode::Off {
+        return maintenance.unavailable();
+    }
+    if let Err(e) = sqlx::query("SELECT 1").execute(&state.pool).await {
+        error!("readiness check failed: {e}");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "unavailable" })),
+        )
+            .into_response();
+    }
+
+    Json(json!({ "status": "ready" })).into_response()
+}
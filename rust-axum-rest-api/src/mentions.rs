@@ -0,0 +1,131 @@
+// @mentions of users in post bodies and comments.
+//
+// Writing a post or a comment records the users it @mentions and notifies
+// those mentioned for the first time, the writer excepted; editing a post
+// drops the mentions it no longer makes. Mentions in posts and comments
+// held back for moderation are notified once a moderator approves them. `GET /me/mentions` lists the posts
+// mentioning the acting user, in their body or a comment.
+//
+// A mention is `@` at the start of a word followed by letters, digits, `_`,
+// `-` and inner dots, matched against usernames ignoring case; usernames
+// with other characters can't be mentioned. Email addresses are no
+// mentions, their `@` follows a letter.
+
+use axum::extract::{Query, State};
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::auth::Actor;
+use crate::error::ApiError;
+use crate::links::Linked;
+use crate::negotiate::Accept;
+use crate::notify::Notification;
+use crate::pagination::{self, PageParams};
+use crate::repo;
+use crate::state::AppState;
+
+// the usernames `body` mentions, normalized like usernames and lowercased,
+// each once
+pub fn parse(body: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut previous = None;
+    let mut chars = body.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let starts_word = previous.is_none_or(|p: char| !p.is_alphanumeric() && p != '_');
+        previous = Some(c);
+        if c != '@' || !starts_word {
+            continue;
+        }
+        let mut end = at + 1;
+        while let Some(&(i, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+                break;
+            }
+            end = i + c.len_utf8();
+            previous = Some(c);
+            chars.next();
+        }
+        let name: String = body[at + 1..end]
+            .trim_end_matches('.')
+            .nfkc()
+            .collect::<String>()
+            .to_lowercase();
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+// record the mentions of a post's body (`comment_id` None) or of one of its
+// comments, and notify the newly mentioned users unless the post or comment
+// is held back. `previous` is the body being replaced; when neither
+// mentions anyone there is nothing to do.
+pub async fn record(
+    state: &AppState,
+    post_id: i32,
+    comment_id: Option<i32>,
+    previous: Option<&str>,
+    body: &str,
+    writer: Option<i32>,
+) -> Result<(), ApiError> {
+    let names = parse(body);
+    if names.is_empty() && previous.is_none_or(|body| parse(body).is_empty()) {
+        return Ok(());
+    }
+    repo::mentions::sync(&state.pool, post_id, comment_id, &names, writer).await?;
+    notify(state, post_id).await?;
+    Ok(())
+}
+
+// notify the users a post or comment held back for moderation mentions in
+// `body`, once it's approved
+pub async fn approved(state: &AppState, post_id: i32, body: &str) -> Result<(), sqlx::Error> {
+    if parse(body).is_empty() {
+        return Ok(());
+    }
+    notify(state, post_id).await
+}
+
+// tell the users mentioned in a post or its comments who weren't told yet
+async fn notify(state: &AppState, post_id: i32) -> Result<(), sqlx::Error> {
+    for (user_id, comment_id) in repo::mentions::take_unnotified(&state.pool, post_id).await? {
+        state
+            .notifier
+            .notify(Notification::Mentioned {
+                user_id,
+                post_id,
+                comment_id,
+            })
+            .await;
+    }
+    Ok(())
+}
+
+// handler for "GET /me/mentions" rest API endpoint, the posts mentioning the
+// acting user, most recent first
+pub async fn my_mentions(
+    State(state): State<AppState>,
+    uri: Uri,
+    actor: Actor,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let user_id = actor.user_id.ok_or_else(|| {
+        ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthenticated",
+            "this takes a user's API key",
+        )
+    })?;
+    let page = page.page()?;
+    let total = repo::mentions::count_mentioning(&state.pool, user_id).await?;
+    let posts = repo::mentions::posts_mentioning(&state.pool, user_id, page).await?;
+
+    Ok((
+        pagination::headers(&uri, page, total),
+        format.respond(Linked::all(posts, state.config.id_scheme)),
+    )
+        .into_response())
+}
@@ -0,0 +1,126 @@
+// Link unfurling: `GET /posts/:id/meta` is a small HTML page whose head
+// carries the Open Graph and Twitter Card tags of a post, for the crawlers
+// of social platforms to turn a shared link into a card, e.g.
+//
+//     <meta property="og:title" content="Hello">
+//     <meta name="twitter:card" content="summary">
+//
+// The card's image is the first one among the previews of the post's links
+// (see `link_previews`); without one the card is a plain summary. URLs are
+// absolute with PUBLIC_URL, which also adds the canonical link and the
+// oEmbed discovery link (see `oembed`); unset, the tags needing absolute
+// URLs are left out. Like oEmbed, only published posts have a page.
+
+use axum::extract::State;
+use axum::response::Html;
+
+use crate::error::{ApiError, OrNotFound};
+use crate::federation::escape;
+use crate::ids::PathKey;
+use crate::link_previews;
+use crate::models::PostStatus;
+use crate::oembed;
+use crate::routes::{expand, POST_ROUTE, USER_ROUTE};
+use crate::state::AppState;
+
+// characters of the body kept as the description
+const DESCRIPTION_LENGTH: usize = 200;
+
+// handler for "GET /posts/:id/meta" rest API endpoint
+pub async fn post_meta(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+) -> Result<Html<String>, ApiError> {
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    if post.status != PostStatus::Published.as_str() {
+        return Err(ApiError::not_found("post not found"));
+    }
+    let image = link_previews::for_post(&state, &post)
+        .await?
+        .into_iter()
+        .find_map(|preview| preview.image_url);
+    let base = state.config.public_url.as_deref();
+    let absolute = |path: String| base.map(|base| base.to_owned() + &path);
+    let url = absolute(expand(POST_ROUTE, &post.public_id));
+    let description = description(&post.body);
+
+    let mut head = format!(
+        "<meta charset=\"utf-8\">\n<title>{}</title>\n<meta name=\"description\" content=\"{}\">\n",
+        escape(&post.title),
+        escape(&description)
+    );
+    let mut tag = |attribute: &str, key: &str, content: &str| {
+        head += &format!(
+            "<meta {attribute}=\"{key}\" content=\"{}\">\n",
+            escape(content)
+        );
+    };
+    tag("property", "og:type", "article");
+    tag("property", "og:site_name", oembed::PROVIDER_NAME);
+    tag("property", "og:title", &post.title);
+    tag("property", "og:description", &description);
+    if let Some(url) = &url {
+        tag("property", "og:url", url);
+    }
+    if let Some(image) = &image {
+        tag("property", "og:image", image);
+    }
+    tag(
+        "property",
+        "article:published_time",
+        &post.created_at.to_rfc3339(),
+    );
+    tag(
+        "property",
+        "article:modified_time",
+        &post.updated_at.to_rfc3339(),
+    );
+    if let Some(author) = post
+        .user_id
+        .and_then(|id| state.config.id_scheme.segment(id, post.user_uuid))
+        .and_then(|user| absolute(expand(USER_ROUTE, &user)))
+    {
+        tag("property", "article:author", &author);
+    }
+    let card = match image {
+        Some(_) => "summary_large_image",
+        None => "summary",
+    };
+    tag("name", "twitter:card", card);
+    tag("name", "twitter:title", &post.title);
+    tag("name", "twitter:description", &description);
+    if let Some(image) = &image {
+        tag("name", "twitter:image", image);
+    }
+    if let (Some(base), Some(url)) = (base, &url) {
+        head += &format!("<link rel=\"canonical\" href=\"{}\">\n", escape(url));
+        head += &format!(
+            "<link rel=\"alternate\" type=\"application/json+oembed\" href=\"{}\">\n",
+            escape(&oembed::discovery_url(base, url))
+        );
+    }
+    let link = match &url {
+        Some(url) => format!("<p><a href=\"{}\">{}</a></p>", escape(url), escape(url)),
+        None => String::new(),
+    };
+
+    Ok(Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n{head}</head>\n<body>\n<h1>{}</h1>\n<p>{}</p>\n{link}\n</body>\n</html>\n",
+        escape(&post.title),
+        escape(&post.body)
+    )))
+}
+
+// the start of `body` on one line, cut at a word
+pub fn description(body: &str) -> String {
+    let text = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= DESCRIPTION_LENGTH {
+        return text;
+    }
+    let cut: String = text.chars().take(DESCRIPTION_LENGTH).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) => &cut[..space],
+        None => &cut,
+    };
+    format!("{cut}…")
+}
@@ -0,0 +1,129 @@
+// HEAD, OPTIONS and 405 handling for every route.
+//
+// axum answers HEAD by running the GET handler and dropping the body, which
+// leaves the headers derived from the body to chance, and answers OPTIONS
+// and unsupported methods with an empty 405. This middleware runs HEAD
+// requests as GET so they get exactly the headers a GET would (including
+// Content-Length and ETag), turns the 405 for OPTIONS into a 204 and gives
+// other 405s a JSON error body, both carrying the route's Allow header.
+//
+// Handlers whose GET does more than read, counting a short link's click or
+// running an export, look for the `Head` extension to leave that out.
+
+use std::convert::Infallible;
+
+use axum::body::{to_bytes, Body, Bytes, HttpBody};
+use axum::extract::Request;
+use axum::http::header::{ALLOW, CONTENT_LENGTH, ETAG};
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures::stream;
+use sha2::{Digest, Sha256};
+
+use crate::error::ApiError;
+
+// Set on HEAD requests, which handlers see as GET. Extract it with
+// `Option<Extension<Head>>`.
+#[derive(Clone, Copy, Debug)]
+pub struct Head;
+
+pub async fn method_semantics(mut request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    if method == Method::HEAD {
+        *request.method_mut() = Method::GET;
+        request.extensions_mut().insert(Head);
+    }
+    let response = next.run(request).await;
+
+    let response = if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+        let allowed = allowed_methods(response.headers().get(ALLOW));
+        if method == Method::OPTIONS {
+            return options_response(allowed);
+        }
+        method_not_allowed(&method, &path, allowed)
+    } else if method == Method::GET || method == Method::HEAD {
+        with_entity_headers(response).await
+    } else {
+        response
+    };
+
+    if method == Method::HEAD {
+        let (parts, _) = response.into_parts();
+        let body = if parts.headers.contains_key(CONTENT_LENGTH) {
+            Body::empty()
+        } else {
+            unsized_body()
+        };
+        Response::from_parts(parts, body)
+    } else {
+        response
+    }
+}
+
+// An empty body of unknown length: a streamed GET has no Content-Length and
+// its HEAD mustn't get a `Content-Length: 0` either.
+pub fn unsized_body() -> Body {
+    Body::from_stream(stream::empty::<Result<Bytes, Infallible>>())
+}
+
+// Add Content-Length and a strong ETag (a hash of the body) to successful
+// responses whose body is already fully known. Streamed bodies pass
+// through untouched, their length isn't known up front.
+async fn with_entity_headers(response: Response) -> Response {
+    if !response.status().is_success() || response.body().size_hint().exact().is_none() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let digest = Sha256::digest(&bytes);
+    let tag: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+    if let Ok(etag) = HeaderValue::from_str(&format!("\"{tag}\"")) {
+        parts.headers.entry(ETAG).or_insert(etag);
+    }
+    parts
+        .headers
+        .insert(CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+// the methods of an Allow header plus OPTIONS, which every route answers
+fn allowed_methods(allow: Option<&HeaderValue>) -> Vec<String> {
+    let mut methods: Vec<String> = allow
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|method| !method.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    if !methods.iter().any(|method| method == "OPTIONS") {
+        methods.push(String::from("OPTIONS"));
+    }
+    methods
+}
+
+fn allow_header(methods: &[String]) -> HeaderValue {
+    HeaderValue::from_str(&methods.join(", ")).expect("method names are valid")
+}
+
+fn options_response(allowed: Vec<String>) -> Response {
+    (StatusCode::NO_CONTENT, [(ALLOW, allow_header(&allowed))]).into_response()
+}
+
+fn method_not_allowed(method: &Method, path: &str, allowed: Vec<String>) -> Response {
+    let allow = allow_header(&allowed);
+    let error = ApiError::new(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "method_not_allowed",
+        format!("{method} is not supported on {path}"),
+    )
+    .with("allowed", allowed);
+    ([(ALLOW, allow)], error).into_response()
+}
@@ -0,0 +1,406 @@
+// Request metrics in the Prometheus text format, at `GET /admin/metrics`:
+// a latency histogram and counts by status class for every route and
+// method, the burn rates of the service level objectives, the slow queries,
+// retries and the circuit breaker (see `repo::slow`, `repo::retry` and
+// `repo::breaker`), the database connection pool (see `db`), the tokio
+// runtime (see `runtime`) and the allocator (see `allocator`).
+//
+// SLO_AVAILABILITY sets the percentage of requests that should succeed
+// (anything but a 5xx), 99.9 say; SLO_LATENCY_MS and SLO_LATENCY_PERCENT
+// (99 by default) the percentage that should be answered within that many
+// milliseconds. A burn rate is how fast the error budget a target leaves is
+// spent over the last `WINDOWS`: 1 spends it exactly over the SLO period,
+// 14.4 over an hour spends 2% of a 30 day budget, the usual paging
+// threshold. Alerts can then be written as, for instance,
+//
+//     slo_burn_rate{slo="availability",window="1h"} > 14.4
+//       and slo_burn_rate{slo="availability",window="5m"} > 14.4
+//
+// Counts are kept in memory per process since it started.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::allocator;
+use crate::auth::RequireAdmin;
+use crate::db;
+use crate::repo::{breaker, retry, slow};
+use crate::runtime;
+use crate::state::AppState;
+
+// upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// the windows burn rates are measured over, in minutes
+const WINDOWS: [(&str, u64); 3] = [("5m", 5), ("30m", 30), ("1h", 60)];
+
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// What the service aims for.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SloTargets {
+    // the share of requests that should succeed, SLO_AVAILABILITY
+    pub availability: Option<f64>,
+    // the share that should be answered within `latency_threshold`,
+    // SLO_LATENCY_PERCENT
+    pub latency: Option<f64>,
+    // SLO_LATENCY_MS
+    pub latency_threshold: Duration,
+}
+
+impl SloTargets {
+    pub fn from_env() -> Result<SloTargets, String> {
+        let percent = |name: &str| match std::env::var(name) {
+            Ok(value) => match value.parse::<f64>() {
+                Ok(percent) if percent > 0.0 && percent < 100.0 => Ok(Some(percent / 100.0)),
+                _ => Err(format!(
+                    "invalid {name} {value:?}, expected a percentage like 99.9"
+                )),
+            },
+            Err(_) => Ok(None),
+        };
+        let availability = percent("SLO_AVAILABILITY")?;
+        let (latency, latency_threshold) = match std::env::var("SLO_LATENCY_MS") {
+            Ok(ms) => {
+                let ms = ms
+                    .parse()
+                    .map_err(|_| format!("invalid SLO_LATENCY_MS {ms:?}, expected milliseconds"))?;
+                let target = percent("SLO_LATENCY_PERCENT")?.unwrap_or(0.99);
+                (Some(target), Duration::from_millis(ms))
+            }
+            Err(_) => (None, Duration::ZERO),
+        };
+        Ok(SloTargets {
+            availability,
+            latency,
+            latency_threshold,
+        })
+    }
+}
+
+#[derive(Default)]
+struct RouteStats {
+    // requests in each of `BUCKETS`, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    seconds: f64,
+    // by status class, 1 to 5
+    statuses: [u64; 5],
+}
+
+// the requests of one minute
+#[derive(Clone, Copy, Default)]
+struct Minute {
+    // since the epoch
+    at: u64,
+    requests: u64,
+    errors: u64,
+    slow: u64,
+}
+
+// the requests of a window that missed an objective
+type Misses = fn(&Minute) -> u64;
+
+pub struct Metrics {
+    // by method and route
+    routes: Mutex<BTreeMap<(String, String), RouteStats>>,
+    // the last hour for the burn rates, a minute a slot
+    minutes: Mutex<[Minute; 60]>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            routes: Mutex::default(),
+            minutes: Mutex::new([Minute::default(); 60]),
+        }
+    }
+}
+
+fn now_minute() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_secs() / 60
+}
+
+impl Metrics {
+    // Count a request to `route` answered with `status` after `elapsed`.
+    pub fn observe(
+        &self,
+        slo: &SloTargets,
+        method: &str,
+        route: &str,
+        status: u16,
+        elapsed: Duration,
+    ) {
+        let seconds = elapsed.as_secs_f64();
+        {
+            let mut routes = self.routes.lock().expect("metrics lock");
+            let stats = routes
+                .entry((method.to_owned(), route.to_owned()))
+                .or_default();
+            if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+                stats.buckets[bucket] += 1;
+            }
+            stats.count += 1;
+            stats.seconds += seconds;
+            if let Some(class) = stats
+                .statuses
+                .get_mut(usize::from(status / 100).wrapping_sub(1))
+            {
+                *class += 1;
+            }
+        }
+
+        let at = now_minute();
+        let mut minutes = self.minutes.lock().expect("metrics lock");
+        let minute = &mut minutes[(at % 60) as usize];
+        if minute.at != at {
+            *minute = Minute {
+                at,
+                ..Minute::default()
+            };
+        }
+        minute.requests += 1;
+        minute.errors += u64::from(status >= 500);
+        minute.slow += u64::from(slo.latency.is_some() && elapsed > slo.latency_threshold);
+    }
+
+    // the requests, errors and slow requests of the last `minutes`
+    fn window(&self, minutes: u64) -> Minute {
+        let now = now_minute();
+        let slots = self.minutes.lock().expect("metrics lock");
+        slots
+            .iter()
+            .filter(|slot| slot.requests > 0 && now - slot.at < minutes)
+            .fold(Minute::default(), |sum, slot| Minute {
+                at: now,
+                requests: sum.requests + slot.requests,
+                errors: sum.errors + slot.errors,
+                slow: sum.slow + slot.slow,
+            })
+    }
+
+    // Everything in the Prometheus text format.
+    pub fn render(&self, slo: &SloTargets) -> String {
+        let mut out = String::new();
+        let routes = self.routes.lock().expect("metrics lock");
+
+        out.push_str("# HELP http_request_duration_seconds How long requests took to answer.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), stats) in routes.iter() {
+            let labels = format!("method=\"{method}\",route=\"{}\"", escape(route));
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                stats.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{labels}}} {}",
+                stats.seconds
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{labels}}} {}",
+                stats.count
+            );
+        }
+
+        out.push_str("# HELP http_requests_total Requests answered, by status class.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route), stats) in routes.iter() {
+            for (class, count) in stats.statuses.iter().enumerate() {
+                if *count > 0 {
+                    let _ = writeln!(
+                        out,
+                        "http_requests_total{{method=\"{method}\",route=\"{}\",status=\"{}xx\"}} {count}",
+                        escape(route),
+                        class + 1
+                    );
+                }
+            }
+        }
+
+        out.push_str("# HELP http_request_errors_total Requests answered with a 5xx.\n");
+        out.push_str("# TYPE http_request_errors_total counter\n");
+        for ((method, route), stats) in routes.iter() {
+            let _ = writeln!(
+                out,
+                "http_request_errors_total{{method=\"{method}\",route=\"{}\"}} {}",
+                escape(route),
+                stats.statuses[4]
+            );
+        }
+        drop(routes);
+
+        out.push_str(
+            "# HELP db_slow_queries_total Queries slower than the slow query threshold.\n",
+        );
+        out.push_str("# TYPE db_slow_queries_total counter\n");
+        for (query, count) in slow::counts() {
+            let _ = writeln!(out, "db_slow_queries_total{{query=\"{query}\"}} {count}");
+        }
+        out.push_str("# TYPE db_slow_query_threshold_seconds gauge\n");
+        let _ = writeln!(
+            out,
+            "db_slow_query_threshold_seconds {}",
+            slow::threshold().as_secs_f64()
+        );
+
+        out.push_str("# HELP db_query_retries_total Reads tried again after a transient error.\n");
+        out.push_str("# TYPE db_query_retries_total counter\n");
+        for (query, count) in retry::counts() {
+            let _ = writeln!(out, "db_query_retries_total{{query=\"{query}\"}} {count}");
+        }
+
+        let breaker = breaker::global();
+        out.push_str("# HELP db_breaker_state The database circuit breaker's state, 1 for the current one.\n");
+        out.push_str("# TYPE db_breaker_state gauge\n");
+        let current = breaker.state();
+        for state in ["closed", "open", "half_open"] {
+            let _ = writeln!(
+                out,
+                "db_breaker_state{{state=\"{state}\"}} {}",
+                u8::from(state == current)
+            );
+        }
+        out.push_str(
+            "# HELP db_breaker_rejected_total Queries failed fast while the breaker was open.\n",
+        );
+        out.push_str("# TYPE db_breaker_rejected_total counter\n");
+        let _ = writeln!(out, "db_breaker_rejected_total {}", breaker.rejected());
+
+        let objectives: [(&str, Option<f64>, Misses); 2] = [
+            ("availability", slo.availability, |window: &Minute| {
+                window.errors
+            }),
+            ("latency", slo.latency, |window: &Minute| window.slow),
+        ];
+        if objectives.iter().any(|(_, target, _)| target.is_some()) {
+            out.push_str("# HELP slo_target The share of requests an objective aims at.\n");
+            out.push_str("# TYPE slo_target gauge\n");
+            for (name, target, _) in &objectives {
+                if let Some(target) = target {
+                    let _ = writeln!(out, "slo_target{{slo=\"{name}\"}} {target}");
+                }
+            }
+            if slo.latency.is_some() {
+                out.push_str("# TYPE slo_latency_threshold_seconds gauge\n");
+                let _ = writeln!(
+                    out,
+                    "slo_latency_threshold_seconds {}",
+                    slo.latency_threshold.as_secs_f64()
+                );
+            }
+            out.push_str("# HELP slo_burn_rate How fast the error budget is spent, 1 over exactly the SLO period.\n");
+            out.push_str("# TYPE slo_burn_rate gauge\n");
+            for (window_name, minutes) in WINDOWS {
+                let window = self.window(minutes);
+                for (name, target, bad) in &objectives {
+                    let Some(target) = target else {
+                        continue;
+                    };
+                    let rate = match window.requests {
+                        0 => 0.0,
+                        requests => bad(&window) as f64 / requests as f64 / (1.0 - target),
+                    };
+                    let _ = writeln!(
+                        out,
+                        "slo_burn_rate{{slo=\"{name}\",window=\"{window_name}\"}} {rate}"
+                    );
+                }
+            }
+        }
+        out
+    }
+}
+
+// a label value, with its backslashes, quotes and newlines escaped
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Count every request once answered, under the route `matched` found.
+pub async fn record(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().clone();
+    let response = next.run(request).await;
+    let route = match response.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str(),
+        // unrouted, each path would be a series of its own
+        None => "unmatched",
+    };
+    state.metrics.observe(
+        &state.config.slo,
+        method.as_str(),
+        route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+
+// Hand the matched route out to `record` with the response, as the router
+// only tells the requests inside it.
+pub async fn matched(path: MatchedPath, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(path);
+    response
+}
+
+// The connection pool's gauges, see `db`.
+async fn render_pool(state: &AppState) -> String {
+    let mut out = String::new();
+    let (size, idle) = (state.pool.size(), state.pool.num_idle() as u32);
+    out.push_str("# HELP db_pool_connections Open database connections, by state.\n");
+    out.push_str("# TYPE db_pool_connections gauge\n");
+    let _ = writeln!(out, "db_pool_connections{{state=\"idle\"}} {idle}");
+    let _ = writeln!(
+        out,
+        "db_pool_connections{{state=\"in_use\"}} {}",
+        size.saturating_sub(idle)
+    );
+    out.push_str("# TYPE db_pool_max_connections gauge\n");
+    let _ = writeln!(
+        out,
+        "db_pool_max_connections {}",
+        state.pool.options().get_max_connections()
+    );
+    if let Some(wait) = db::acquire_wait(&state.pool).await {
+        out.push_str("# HELP db_pool_acquire_wait_seconds How long getting a connection took when scraped.\n");
+        out.push_str("# TYPE db_pool_acquire_wait_seconds gauge\n");
+        let _ = writeln!(out, "db_pool_acquire_wait_seconds {}", wait.as_secs_f64());
+    }
+    out
+}
+
+// handler for "GET /admin/metrics" rest API endpoint
+pub async fn export(_admin: RequireAdmin, State(state): State<AppState>) -> impl IntoResponse {
+    let mut metrics = state.metrics.render(&state.config.slo);
+    metrics.push_str(&render_pool(&state).await);
+    metrics.push_str(&runtime::render());
+    metrics.push_str(&allocator::render());
+    ([(CONTENT_TYPE, CONTENT_TYPE_TEXT)], metrics)
+}
@@ -0,0 +1,803 @@
+// Request and response models shared by the API, the subcommands and the
+// admin CLI. The wire types live in the `api-types` crate so the client SDK
+// can use them without pulling in the server.
+
+pub use api_types::{
+    Availability, Count, CreatePost, CreateReport, CreateUser, FieldChange, Message, Post,
+    PostRevision, PostWithAuthor, PublicUser, Report, UpdatePost, User,
+};
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use uuid::Uuid;
+
+use crate::negotiate::XmlElement;
+
+impl XmlElement for Post {
+    const ELEMENT: &'static str = "post";
+    const LIST: &'static str = "posts";
+}
+
+impl XmlElement for PostWithAuthor {
+    const ELEMENT: &'static str = "post";
+    const LIST: &'static str = "posts";
+}
+
+impl XmlElement for Message {
+    const ELEMENT: &'static str = "message";
+    const LIST: &'static str = "messages";
+}
+
+impl XmlElement for Availability {
+    const ELEMENT: &'static str = "availability";
+    const LIST: &'static str = "availabilities";
+}
+
+impl XmlElement for Count {
+    const ELEMENT: &'static str = "count";
+    const LIST: &'static str = "counts";
+}
+
+impl XmlElement for Report {
+    const ELEMENT: &'static str = "report";
+    const LIST: &'static str = "reports";
+}
+
+impl XmlElement for User {
+    const ELEMENT: &'static str = "user";
+    const LIST: &'static str = "users";
+}
+
+impl XmlElement for PublicUser {
+    const ELEMENT: &'static str = "user";
+    const LIST: &'static str = "users";
+}
+
+// Role of a user, stored as text in users.role.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+// Moderation status of a post, stored as text in posts.status and sent as
+// `Post::status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    Published,
+    // held back for a moderator, e.g. by the content filter
+    Pending,
+    // caught by the spam checks, kept for a moderator to recover
+    Spam,
+    // turned down by a moderator
+    Rejected,
+    // old and moved out of the way, see `archive`
+    Archived,
+}
+
+impl PostStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PostStatus::Published => "published",
+            PostStatus::Pending => "pending",
+            PostStatus::Spam => "spam",
+            PostStatus::Rejected => "rejected",
+            PostStatus::Archived => "archived",
+        }
+    }
+}
+
+// What the content filter does with a submission containing a banned word,
+// stored as text in banned_words.action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    Reject,
+    Flag,
+}
+
+impl FilterAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FilterAction::Reject => "reject",
+            FilterAction::Flag => "flag",
+        }
+    }
+}
+
+// An entry of the content filter's list, managed by admins.
+#[derive(Debug, Clone, Serialize)]
+pub struct BannedWord {
+    pub word: String,
+    pub action: FilterAction,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateBannedWord {
+    pub word: String,
+    pub action: FilterAction,
+}
+
+impl XmlElement for BannedWord {
+    const ELEMENT: &'static str = "banned_word";
+    const LIST: &'static str = "banned_words";
+}
+
+// A moderator's decision on a post.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Approve,
+    Reject,
+}
+
+impl Decision {
+    pub fn status(self) -> PostStatus {
+        match self {
+            Decision::Approve => PostStatus::Published,
+            Decision::Reject => PostStatus::Rejected,
+        }
+    }
+}
+
+// Body of a rejection, the reason is passed on to the author.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rejection {
+    pub reason: String,
+}
+
+// Body of a bulk decision on posts given by any of their ids.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BulkModeration {
+    pub ids: Vec<String>,
+    pub decision: Decision,
+    // required to reject
+    pub reason: Option<String>,
+}
+
+// Outcome of a bulk decision: the ids acted on and those that matched no
+// post.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BulkModerationResult {
+    pub moderated: Vec<String>,
+    pub not_found: Vec<String>,
+}
+
+impl XmlElement for BulkModerationResult {
+    const ELEMENT: &'static str = "result";
+    const LIST: &'static str = "results";
+}
+
+// How an admin resolved a report, stored as text in reports.outcome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportOutcome {
+    // nothing wrong with the post
+    Dismissed,
+    // the post is rejected, with the note as the reason
+    Upheld,
+}
+
+impl ReportOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportOutcome::Dismissed => "dismissed",
+            ReportOutcome::Upheld => "upheld",
+        }
+    }
+}
+
+// Body of `POST /admin/reports/:id/resolve`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResolveReport {
+    pub outcome: ReportOutcome,
+    pub note: Option<String>,
+}
+
+// Role of a member of an organization, stored as text in
+// org_memberships.role. Ordered by what the role may do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrgRole {
+    // posts and edits the organization's posts
+    Member,
+    // also adds members and deletes posts
+    Admin,
+    // also makes others admins and owners
+    Owner,
+}
+
+impl OrgRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrgRole::Member => "member",
+            OrgRole::Admin => "admin",
+            OrgRole::Owner => "owner",
+        }
+    }
+
+    // the column's check constraint only allows the three roles
+    pub fn from_column(role: &str) -> Self {
+        match role {
+            "owner" => OrgRole::Owner,
+            "admin" => OrgRole::Admin,
+            _ => OrgRole::Member,
+        }
+    }
+}
+
+// A team of users sharing ownership of posts, see `orgs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Organization {
+    pub id: i32,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateOrganization {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Membership {
+    pub org_id: i32,
+    pub user_id: i32,
+    pub role: OrgRole,
+    pub created_at: DateTime<Utc>,
+}
+
+// Body of `POST /orgs/:id/members`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddMember {
+    pub user_id: i32,
+    #[serde(default = "member")]
+    pub role: OrgRole,
+}
+
+// Body of `POST /posts/:id/authors`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddAuthor {
+    pub user_id: i32,
+}
+
+fn member() -> OrgRole {
+    OrgRole::Member
+}
+
+// A pending invitation to an organization. The token is only ever mailed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Invitation {
+    pub id: i32,
+    pub org_id: i32,
+    pub email: String,
+    pub role: OrgRole,
+    pub invited_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Body of `POST /orgs/:id/invitations`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateInvitation {
+    pub email: String,
+    #[serde(default = "member")]
+    pub role: OrgRole,
+}
+
+// Body of `POST /invitations/:token/accept`; the username is needed when no
+// user has the invited email yet.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AcceptInvitation {
+    pub username: Option<String>,
+}
+
+// A tag and how many recent posts have it, see `hashtags`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrendingTag {
+    pub tag: String,
+    pub posts: i64,
+}
+
+impl XmlElement for TrendingTag {
+    const ELEMENT: &'static str = "hashtag";
+    const LIST: &'static str = "hashtags";
+}
+
+// A comment on a post, a reply when it has a parent, see `comments`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Comment {
+    pub id: i32,
+    pub post_id: i32,
+    pub parent_comment_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub body: String,
+    // published, or held back like posts, see `PostStatus`
+    pub status: String,
+    // how many comments up the thread it replies to, 0 for top-level ones
+    pub depth: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+// A comment with its replies, in `GET /posts/:id/comments` trees.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentThread {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub replies: Vec<CommentThread>,
+}
+
+// Body of `POST /posts/:id/comments`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateComment {
+    pub body: String,
+    // the comment this one replies to
+    #[serde(default)]
+    pub parent_comment_id: Option<i32>,
+    // defaults to the acting user
+    #[serde(default)]
+    pub user_id: Option<i32>,
+}
+
+impl XmlElement for Comment {
+    const ELEMENT: &'static str = "comment";
+    const LIST: &'static str = "comments";
+}
+
+impl XmlElement for CommentThread {
+    const ELEMENT: &'static str = "comment";
+    const LIST: &'static str = "comments";
+}
+
+impl XmlElement for Invitation {
+    const ELEMENT: &'static str = "invitation";
+    const LIST: &'static str = "invitations";
+}
+
+impl XmlElement for Organization {
+    const ELEMENT: &'static str = "organization";
+    const LIST: &'static str = "organizations";
+}
+
+impl XmlElement for Membership {
+    const ELEMENT: &'static str = "membership";
+    const LIST: &'static str = "memberships";
+}
+
+// A customer of the deployment, see `tenant`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tenant {
+    pub id: i32,
+    // picks the tenant in the X-Tenant header and the subdomain
+    pub slug: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateTenant {
+    pub slug: String,
+    pub name: String,
+}
+
+impl XmlElement for Tenant {
+    const ELEMENT: &'static str = "tenant";
+    const LIST: &'static str = "tenants";
+}
+
+// What a user did, an entry of their feed, see `activity`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Activity {
+    pub id: i64,
+    pub user_id: i32,
+    // "post" or "comment"
+    pub kind: String,
+    pub post_id: i32,
+    pub comment_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivityKind {
+    Post,
+    Comment,
+}
+
+impl ActivityKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActivityKind::Post => "post",
+            ActivityKind::Comment => "comment",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewActivity {
+    pub user_id: i32,
+    pub kind: ActivityKind,
+    pub post_id: i32,
+    pub comment_id: Option<i32>,
+}
+
+impl XmlElement for Activity {
+    const ELEMENT: &'static str = "activity";
+    const LIST: &'static str = "activities";
+}
+
+// A row of the audit log, see `audit`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    // "anonymous", "user 3", "admin token" or "admin user 1"
+    pub actor: String,
+    // the admin acting as `actor`, see `auth::Actor`
+    pub impersonator: Option<String>,
+    pub action: String,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    // where `before` and `after` differ
+    pub changes: Option<Json<Vec<FieldChange>>>,
+    pub ip: Option<String>,
+    // where `ip` is, see `geoip`
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: String,
+    pub impersonator: Option<String>,
+    pub action: String,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub changes: Option<Vec<FieldChange>>,
+    pub ip: Option<String>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+}
+
+impl XmlElement for AuditEntry {
+    const ELEMENT: &'static str = "audit_entry";
+    const LIST: &'static str = "audit_log";
+}
+
+impl From<AuditEntry> for PostRevision {
+    fn from(entry: AuditEntry) -> Self {
+        PostRevision {
+            action: entry.action,
+            actor: entry.actor,
+            changes: entry
+                .changes
+                .map(|Json(changes)| changes)
+                .unwrap_or_default(),
+            changed_at: entry.created_at,
+        }
+    }
+}
+
+impl XmlElement for PostRevision {
+    const ELEMENT: &'static str = "revision";
+    const LIST: &'static str = "history";
+}
+
+// A token acting as `user_id`, returned once by
+// `POST /admin/users/:id/impersonate`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImpersonationToken {
+    pub token: String,
+    pub user_id: i32,
+    pub impersonator: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl XmlElement for ImpersonationToken {
+    const ELEMENT: &'static str = "impersonation_token";
+    const LIST: &'static str = "impersonation_tokens";
+}
+
+// An access token for the user making the request, returned by
+// `POST /auth/token`, see `jwt`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessToken {
+    pub token: String,
+    pub token_type: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// A browser session, see `sessions`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub tenant_id: i32,
+    pub user_id: i32,
+    pub expires_at: DateTime<Utc>,
+}
+
+// A subject of the external identity provider and the user it acts as, see
+// `oidc`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExternalIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub user_id: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl XmlElement for ExternalIdentity {
+    const ELEMENT: &'static str = "external_identity";
+    const LIST: &'static str = "external_identities";
+}
+
+// `POST /admin/users/:id/identities` body, a subject of the configured
+// identity provider
+#[derive(Debug, Deserialize)]
+pub struct LinkIdentity {
+    pub subject: String,
+}
+
+// An API key as stored, the secret itself is only known when it is created.
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub revoked: bool,
+}
+
+// A feature flag, see `flags`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeatureFlag {
+    pub key: String,
+    pub description: String,
+    // the percentage of users the feature is on for
+    pub rollout_percent: i16,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateFeatureFlag {
+    pub key: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub rollout_percent: i16,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateFeatureFlag {
+    #[serde(default)]
+    pub description: String,
+    pub rollout_percent: i16,
+}
+
+impl XmlElement for FeatureFlag {
+    const ELEMENT: &'static str = "feature_flag";
+    const LIST: &'static str = "feature_flags";
+}
+
+// A user a flag is on or off for regardless of the rollout.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagOverride {
+    pub flag_key: String,
+    pub user_id: i32,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetFlagOverride {
+    pub enabled: bool,
+}
+
+impl XmlElement for FlagOverride {
+    const ELEMENT: &'static str = "override";
+    const LIST: &'static str = "overrides";
+}
+
+// What decides a flag for one user: the rollout, unless they have an
+// override.
+#[derive(Debug, Clone)]
+pub struct FlagRule {
+    pub key: String,
+    pub rollout_percent: i16,
+    pub user_override: Option<bool>,
+}
+
+// Whether a flag is on for the acting user, `GET /me/flags`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagState {
+    pub key: String,
+    pub enabled: bool,
+}
+
+impl XmlElement for FlagState {
+    const ELEMENT: &'static str = "flag";
+    const LIST: &'static str = "flags";
+}
+
+// An endpoint events are delivered to, see `webhooks`. The secret signing
+// the deliveries is only returned when the endpoint is created.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEndpoint {
+    pub id: i32,
+    pub url: String,
+    // the event types delivered, all of them when empty
+    pub events: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    // failed attempts since the last successful one
+    pub consecutive_failures: i32,
+    // when it was disabled for failing too often, see `webhooks::delivery`
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl XmlElement for WebhookEndpoint {
+    const ELEMENT: &'static str = "webhook";
+    const LIST: &'static str = "webhooks";
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateWebhookEndpoint {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+// An event queued for a webhook endpoint, listed among the dead letters once
+// given up on.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub endpoint_id: i32,
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub failed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl XmlElement for WebhookDelivery {
+    const ELEMENT: &'static str = "delivery";
+    const LIST: &'static str = "deliveries";
+}
+
+// What a page linked from a post says about itself, for rendering a card,
+// see `previews`.
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub site_name: Option<String>,
+}
+
+impl XmlElement for LinkPreview {
+    const ELEMENT: &'static str = "link_preview";
+    const LIST: &'static str = "link_previews";
+}
+
+// A share link of a post, see `short_links`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShortLink {
+    pub code: String,
+    pub post_id: i32,
+    // who minted it, None for anonymous callers
+    pub created_by: Option<i32>,
+    pub clicks: i64,
+    pub last_clicked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl XmlElement for ShortLink {
+    const ELEMENT: &'static str = "short_link";
+    const LIST: &'static str = "short_links";
+}
+
+// Clicks on share links from one country.
+#[derive(Debug, Clone, Serialize)]
+pub struct CountryClicks {
+    pub country: String,
+    pub clicks: i64,
+}
+
+// What a user wants to be emailed, see `preferences`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NotificationPreferences {
+    pub weekly_digest: bool,
+}
+
+impl XmlElement for NotificationPreferences {
+    const ELEMENT: &'static str = "preferences";
+    const LIST: &'static str = "preferences";
+}
+
+// `PUT /me/preferences` body, the preferences left out stay as they are
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateNotificationPreferences {
+    pub weekly_digest: Option<bool>,
+}
+
+// A billing plan, stored as text in subscriptions.plan. Ordered by what the
+// plan allows, see `billing`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Plan {
+    // everyone without a paid subscription
+    #[default]
+    Free,
+    Pro,
+    Business,
+}
+
+impl Plan {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Plan::Free => "free",
+            Plan::Pro => "pro",
+            Plan::Business => "business",
+        }
+    }
+
+    // the column's check constraint only allows the three plans
+    pub fn from_column(plan: &str) -> Self {
+        match plan {
+            "business" => Plan::Business,
+            "pro" => Plan::Pro,
+            _ => Plan::Free,
+        }
+    }
+}
+
+impl FromStr for Plan {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "free" => Ok(Plan::Free),
+            "pro" => Ok(Plan::Pro),
+            "business" => Ok(Plan::Business),
+            _ => Err(format!(
+                "unknown plan {s:?}, expected free, pro or business"
+            )),
+        }
+    }
+}
+
+// A Stripe subscription of a user or an organization, see `billing`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Subscription {
+    pub stripe_subscription_id: String,
+    pub stripe_customer_id: String,
+    pub user_id: Option<i32>,
+    pub org_id: Option<i32>,
+    pub plan: String,
+    // Stripe's, e.g. active, past_due or canceled
+    pub status: String,
+    pub current_period_end: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
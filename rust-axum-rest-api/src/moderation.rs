@@ -0,0 +1,376 @@
+// Admin endpoints for managing what content is allowed: the content
+// filter's word list and the moderation queue.
+//
+// Posts held back by the content filter or the spam checks, or reported by
+// enough readers, wait in the queue until a moderator approves (publishes) or rejects them, one at a
+// time or in bulk. Authors are notified of either decision, rejections with
+// the moderator's reason. Held back comments wait in a queue of their own,
+// `/admin/moderation/comments`, and their authors are told the same way.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+use crate::activity;
+use crate::audit::{self, Change};
+use crate::auth::RequireAdmin;
+use crate::content_filter;
+use crate::error::{ApiError, OrNotFound};
+use crate::events::Event;
+use crate::federation;
+use crate::ids::{Key, PathKey};
+use crate::link_previews;
+use crate::mentions;
+use crate::models::{
+    ActivityKind, BannedWord, BulkModeration, BulkModerationResult, Comment, CreateBannedWord,
+    Decision, Message, Post, PostStatus, Rejection, Report, ReportOutcome, ResolveReport,
+};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::notify::Notification;
+use crate::pagination::PageParams;
+use crate::repo;
+use crate::state::AppState;
+
+// handler for "GET /admin/banned-words" rest API endpoint
+pub async fn list_banned_words(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<BannedWord>>, ApiError> {
+    let words = state.banned_words.list().await?;
+
+    Ok(format.respond(words))
+}
+
+// handler for "POST /admin/banned-words" rest API endpoint, adding a word or
+// changing the action of a listed one
+pub async fn add_banned_word(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Payload(mut word): Payload<CreateBannedWord>,
+) -> Result<Negotiated<BannedWord>, ApiError> {
+    word.word = content_filter::normalize(&word.word);
+    if word.word.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_word",
+            "expected a word or phrase of letters and digits",
+        )
+        .with("field", "word"));
+    }
+    let word = state.banned_words.add(&word).await?;
+    audit::record(Change::new("banned_word.add", "banned_word", &word.word).after(&word));
+
+    Ok(format.respond(word))
+}
+
+// handler for "DELETE /admin/banned-words/:word" rest API endpoint
+pub async fn remove_banned_word(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(word): Path<String>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    let word = content_filter::normalize(&word);
+    if !state.banned_words.remove(&word).await? {
+        return Err(ApiError::not_found("banned word not found"));
+    }
+    audit::record(Change::new("banned_word.remove", "banned_word", word));
+
+    Ok(format.respond(Message {
+        message: String::from("Banned word removed"),
+    }))
+}
+
+// `GET /admin/moderation/posts` parameters
+#[derive(Deserialize)]
+pub struct QueueParams {
+    status: Option<PostStatus>,
+}
+
+// handler for "GET /admin/moderation/posts" rest API endpoint, the posts
+// waiting for a decision (or `?status=spam` and so on), oldest first
+pub async fn moderation_queue(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Query(params): Query<QueueParams>,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<Post>>, ApiError> {
+    let status = params.status.unwrap_or(PostStatus::Pending);
+    let posts = state.posts.queue(status, page.page()?).await?;
+
+    Ok(format.respond(posts))
+}
+
+// handler for "POST /admin/moderation/posts/:id/approve" rest API endpoint
+pub async fn approve_post(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    Accept(format): Accept,
+) -> Result<Negotiated<Post>, ApiError> {
+    let post = decide(&state, key, Decision::Approve, None)
+        .await
+        .or_not_found("post")?;
+
+    Ok(format.respond(post))
+}
+
+// handler for "POST /admin/moderation/posts/:id/reject" rest API endpoint
+pub async fn reject_post(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    Accept(format): Accept,
+    Payload(rejection): Payload<Rejection>,
+) -> Result<Negotiated<Post>, ApiError> {
+    let reason = required_reason(Some(&rejection.reason))?;
+    let post = decide(&state, key, Decision::Reject, Some(reason))
+        .await
+        .or_not_found("post")?;
+
+    Ok(format.respond(post))
+}
+
+// handler for "GET /admin/moderation/comments" rest API endpoint, the
+// comments waiting for a decision (or `?status=spam` and so on), oldest first
+pub async fn comment_queue(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Query(params): Query<QueueParams>,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<Comment>>, ApiError> {
+    let status = params.status.unwrap_or(PostStatus::Pending);
+    let comments = repo::comments::queue(&state.pool, status, page.page()?).await?;
+
+    Ok(format.respond(comments))
+}
+
+// handler for "POST /admin/moderation/comments/:id/approve" rest API endpoint
+pub async fn approve_comment(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Comment>, ApiError> {
+    let comment = decide_comment(&state, id, Decision::Approve, None)
+        .await
+        .or_not_found("comment")?;
+
+    Ok(format.respond(comment))
+}
+
+// handler for "POST /admin/moderation/comments/:id/reject" rest API endpoint
+pub async fn reject_comment(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Accept(format): Accept,
+    Payload(rejection): Payload<Rejection>,
+) -> Result<Negotiated<Comment>, ApiError> {
+    let reason = required_reason(Some(&rejection.reason))?;
+    let comment = decide_comment(&state, id, Decision::Reject, Some(reason))
+        .await
+        .or_not_found("comment")?;
+
+    Ok(format.respond(comment))
+}
+
+// handler for "POST /admin/moderation/posts" rest API endpoint, the same
+// decision on many posts
+pub async fn moderate_posts(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Payload(bulk): Payload<BulkModeration>,
+) -> Result<Negotiated<BulkModerationResult>, ApiError> {
+    let reason = match bulk.decision {
+        Decision::Approve => None,
+        Decision::Reject => Some(required_reason(bulk.reason.as_deref())?),
+    };
+    let mut result = BulkModerationResult::default();
+    for id in bulk.ids {
+        let Some(key) = state.config.id_scheme.parse_key(&id) else {
+            result.not_found.push(id);
+            continue;
+        };
+        match decide(&state, key, bulk.decision, reason).await {
+            Ok(_) => result.moderated.push(id),
+            Err(sqlx::Error::RowNotFound) => result.not_found.push(id),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(format.respond(result))
+}
+
+fn required_reason(reason: Option<&str>) -> Result<&str, ApiError> {
+    reason
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "missing_reason",
+                "rejections need a reason for the author",
+            )
+            .with("field", "reason")
+        })
+}
+
+// `GET /admin/reports` parameters
+#[derive(Deserialize)]
+pub struct ReportParams {
+    #[serde(default)]
+    resolved: bool,
+}
+
+// handler for "GET /admin/reports" rest API endpoint, open reports oldest
+// first, or the resolved ones with `?resolved=true`
+pub async fn list_reports(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Query(params): Query<ReportParams>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<Report>>, ApiError> {
+    let reports = state.reports.list(params.resolved).await?;
+
+    Ok(format.respond(reports))
+}
+
+// handler for "POST /admin/reports/:id/resolve" rest API endpoint. Upholding
+// a report rejects the post or comment reported; its author is told, with
+// the note (or else the report's reason).
+pub async fn resolve_report(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Accept(format): Accept,
+    Payload(resolution): Payload<ResolveReport>,
+) -> Result<Negotiated<Report>, ApiError> {
+    let note = resolution
+        .note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+    let report = state
+        .reports
+        .resolve(id, resolution.outcome, note)
+        .await
+        .or_not_found("open report")?;
+    audit::record(Change::new("report.resolve", "report", report.id).after(&report));
+    if resolution.outcome == ReportOutcome::Upheld {
+        let reason = note.unwrap_or(&report.reason);
+        match report.comment_id {
+            Some(comment_id) => {
+                decide_comment(&state, comment_id, Decision::Reject, Some(reason))
+                    .await
+                    .or_not_found("comment")?;
+            }
+            None => {
+                decide(
+                    &state,
+                    Key::Serial(report.post_id),
+                    Decision::Reject,
+                    Some(reason),
+                )
+                .await
+                .or_not_found("post")?;
+            }
+        }
+    }
+
+    Ok(format.respond(report))
+}
+
+// apply a decision and tell the author about it
+async fn decide(
+    state: &AppState,
+    key: Key,
+    decision: Decision,
+    reason: Option<&str>,
+) -> Result<Post, sqlx::Error> {
+    let status = decision.status();
+    let before = state.posts.get(key).await?;
+    let post = state.posts.moderate(key, status, reason).await?;
+    if decision == Decision::Approve && before.status != PostStatus::Published.as_str() {
+        // a post held back after it was out (reported, edited) isn't new
+        // again, only the mentions it was held with are
+        if state.posts.mark_published(post.id).await? {
+            federation::publish(state, &post).await?;
+            link_previews::record(state, &post, None).await?;
+            state.events.publish(Event::PostCreated(post.clone()));
+        }
+        mentions::approved(state, post.id, &post.body).await?;
+    }
+    let action = match decision {
+        Decision::Approve => "post.approve",
+        Decision::Reject => "post.reject",
+    };
+    audit::record(
+        Change::new(action, "post", post.id)
+            .before(&before)
+            .after(&post),
+    );
+    if let Some(user_id) = post.user_id {
+        state
+            .notifier
+            .notify(Notification::PostModerated {
+                user_id,
+                post_id: post.id,
+                status,
+                reason: reason.map(str::to_owned),
+            })
+            .await;
+    }
+    Ok(post)
+}
+
+// apply a decision to a comment, publishing it when it's approved for the
+// first time, and tell its author about it
+async fn decide_comment(
+    state: &AppState,
+    id: i32,
+    decision: Decision,
+    reason: Option<&str>,
+) -> Result<Comment, sqlx::Error> {
+    let before = repo::comments::get(&state.pool, id).await?;
+    let comment = repo::comments::moderate(&state.pool, id, decision.status(), reason).await?;
+    if decision == Decision::Approve && before.status != PostStatus::Published.as_str() {
+        mentions::approved(state, comment.post_id, &comment.body).await?;
+        activity::record(
+            state,
+            comment.user_id,
+            ActivityKind::Comment,
+            comment.post_id,
+            Some(comment.id),
+        )
+        .await?;
+        state.events.publish(Event::CommentAdded(comment.clone()));
+    }
+    let action = match decision {
+        Decision::Approve => "comment.approve",
+        Decision::Reject => "comment.reject",
+    };
+    audit::record(
+        Change::new(action, "comment", comment.id)
+            .before(&before)
+            .after(&comment),
+    );
+    if let Some(user_id) = comment.user_id {
+        state
+            .notifier
+            .notify(Notification::CommentModerated {
+                user_id,
+                post_id: comment.post_id,
+                comment_id: comment.id,
+                status: decision.status(),
+                reason: reason.map(str::to_owned),
+            })
+            .await;
+    }
+    Ok(comment)
+}
@@ -0,0 +1,282 @@
+// Content negotiation between JSON, MessagePack, CBOR and XML.
+//
+// `Accept` is an extractor that picks the response format from the request's
+// Accept header, `Negotiated` is the responder that serializes a value in that
+// format and `Payload` is the request body extractor that decodes according to
+// the Content-Type header. XML is only offered for responses.
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::ApiError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MsgPack,
+    Cbor,
+    Xml,
+}
+
+impl Format {
+    // map a media type (without parameters) to a supported format
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        match media_type.trim().to_ascii_lowercase().as_str() {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MsgPack)
+            }
+            "application/cbor" => Some(Format::Cbor),
+            "application/xml" | "text/xml" => Some(Format::Xml),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MsgPack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+            Format::Xml => "application/xml; charset=utf-8",
+        }
+    }
+
+    // pick the supported format with the highest quality value, the first one
+    // listed wins on ties; a missing or empty header means JSON
+    fn from_accept(headers: &HeaderMap) -> Option<Format> {
+        let Some(accept) = headers.get(ACCEPT).and_then(|value| value.to_str().ok()) else {
+            return Some(Format::Json);
+        };
+        if accept.trim().is_empty() {
+            return Some(Format::Json);
+        }
+
+        let mut best: Option<(Format, f32)> = None;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let media_type = parts.next().unwrap_or_default();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                continue;
+            }
+            if let Some(format) = Format::from_media_type(media_type) {
+                if best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                    best = Some((format, quality));
+                }
+            }
+        }
+        best.map(|(format, _)| format)
+    }
+
+    // the format of a request body, a missing Content-Type is treated as JSON
+    fn from_content_type(headers: &HeaderMap) -> Option<Format> {
+        match headers.get(CONTENT_TYPE) {
+            None => Some(Format::Json),
+            Some(value) => {
+                let media_type = value.to_str().ok()?.split(';').next()?;
+                match Format::from_media_type(media_type)? {
+                    // wildcards are only meaningful in Accept
+                    Format::Json if media_type.contains('*') => None,
+                    Format::Xml => None,
+                    format => Some(format),
+                }
+            }
+        }
+    }
+
+    pub fn serialize<T: Serialize + ToXml>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Format::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).map_err(|e| e.to_string())?;
+                Ok(buf)
+            }
+            Format::Xml => value.to_xml().map(String::into_bytes),
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+            Format::Xml => Err(String::from("XML request bodies are not supported")),
+        }
+    }
+
+    // wrap a value so it is serialized in this format when returned from a handler
+    pub fn respond<T>(self, value: T) -> Negotiated<T> {
+        Negotiated(self, value)
+    }
+}
+
+// Element names used when a model is rendered as XML, e.g. a `Vec<Post>`
+// becomes `<posts><post>...</post></posts>`.
+pub trait XmlElement {
+    const ELEMENT: &'static str;
+    const LIST: &'static str;
+}
+
+// Values that can be rendered as an XML document. Implemented for every
+// `XmlElement` and for lists of them; quick-xml takes care of escaping.
+pub trait ToXml {
+    fn to_xml(&self) -> Result<String, String>;
+}
+
+impl<T: XmlElement + Serialize> ToXml for T {
+    fn to_xml(&self) -> Result<String, String> {
+        quick_xml::se::to_string_with_root(T::ELEMENT, self).map_err(|e| e.to_string())
+    }
+}
+
+impl<T: XmlElement + Serialize> ToXml for Vec<T> {
+    fn to_xml(&self) -> Result<String, String> {
+        let mut xml = format!("<{}>", T::LIST);
+        for item in self {
+            quick_xml::se::to_writer_with_root(&mut xml, T::ELEMENT, item)
+                .map_err(|e| e.to_string())?;
+        }
+        xml.push_str(&format!("</{}>", T::LIST));
+        Ok(xml)
+    }
+}
+
+// Extractor for the response format requested through the Accept header.
+// Requests that only accept unsupported media types are rejected with a 406
+// in the usual error format (see `error`).
+pub struct Accept(pub Format);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Accept
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Format::from_accept(&parts.headers).map(Accept).ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_ACCEPTABLE,
+                "not_acceptable",
+                "expected to respond with application/json, application/msgpack, application/cbor or application/xml",
+            )
+        })
+    }
+}
+
+// Responder serializing the value in the negotiated format.
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T: Serialize + ToXml> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let Negotiated(format, value) = self;
+        match format.serialize(&value) {
+            Ok(body) => (
+                [(
+                    CONTENT_TYPE,
+                    HeaderValue::from_static(format.content_type()),
+                )],
+                body,
+            )
+                .into_response(),
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+// Request body extractor decoding JSON, MessagePack or CBOR based on the
+// Content-Type header, the counterpart of `axum::Json` for negotiated bodies.
+pub struct Payload<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Payload<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = Format::from_content_type(req.headers()).ok_or_else(|| {
+            ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "expected application/json, application/msgpack or application/cbor",
+            )
+        })?;
+        let bytes = Bytes::from_request(req, state).await.map_err(|e| {
+            let code = match e.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+                _ => "invalid_body",
+            };
+            ApiError::new(e.status(), code, e.body_text())
+        })?;
+        format
+            .deserialize(&bytes)
+            .map(Payload)
+            .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_body", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: axum::http::HeaderName, value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(name, HeaderValue::from_static(value))])
+    }
+
+    fn accept(value: &'static str) -> Option<Format> {
+        Format::from_accept(&headers(ACCEPT, value))
+    }
+
+    #[test]
+    fn accept_picks_the_best_quality_then_the_first_listed() {
+        assert_eq!(Format::from_accept(&HeaderMap::new()), Some(Format::Json));
+        assert_eq!(accept(" "), Some(Format::Json));
+        assert_eq!(accept("*/*"), Some(Format::Json));
+        assert_eq!(
+            accept("application/cbor, application/xml"),
+            Some(Format::Cbor)
+        );
+        assert_eq!(
+            accept("application/json;q=0.5, application/msgpack"),
+            Some(Format::MsgPack)
+        );
+        assert_eq!(
+            accept("text/html, application/xml;q=0.9"),
+            Some(Format::Xml)
+        );
+        assert_eq!(accept("application/json;q=0"), None);
+        assert_eq!(accept("text/html"), None);
+    }
+
+    #[test]
+    fn bodies_are_never_xml_or_wildcards() {
+        let content_type = |value| Format::from_content_type(&headers(CONTENT_TYPE, value));
+        assert_eq!(
+            Format::from_content_type(&HeaderMap::new()),
+            Some(Format::Json)
+        );
+        assert_eq!(
+            content_type("application/json; charset=utf-8"),
+            Some(Format::Json)
+        );
+        assert_eq!(content_type("application/x-msgpack"), Some(Format::MsgPack));
+        assert_eq!(content_type("application/xml"), None);
+        assert_eq!(content_type("*/*"), None);
+        assert_eq!(content_type("text/plain"), None);
+    }
+}
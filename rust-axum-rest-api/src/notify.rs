@@ -0,0 +1,168 @@
+// Notifications to users about decisions concerning their content, their
+// digests, and invitations to people who may not be users yet.
+//
+// Delivery is behind the `Notifier` trait; the default `LogNotifier` only
+// writes them to the log, to be replaced by mail or push delivery.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+use crate::models::PostStatus;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Notification {
+    // a moderator published or rejected one of the user's posts
+    PostModerated {
+        user_id: i32,
+        post_id: i32,
+        status: PostStatus,
+        reason: Option<String>,
+    },
+    // a moderator published or rejected one of the user's comments
+    CommentModerated {
+        user_id: i32,
+        post_id: i32,
+        comment_id: i32,
+        status: PostStatus,
+        reason: Option<String>,
+    },
+    // the user was @mentioned in a post, or in a comment on it
+    Mentioned {
+        user_id: i32,
+        post_id: i32,
+        comment_id: Option<i32>,
+    },
+    // someone was invited to an organization, `link` accepts
+    Invitation {
+        email: String,
+        org_name: String,
+        link: String,
+        expires_at: DateTime<Utc>,
+    },
+    // the weekly digest of the posts of the users they follow, see `digest`
+    Digest {
+        user_id: i32,
+        email: String,
+        subject: String,
+        body: String,
+        // extra email headers, the unsubscribe ones (see `unsubscribe`)
+        headers: Vec<(&'static str, String)>,
+    },
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notification::PostModerated {
+                post_id,
+                status,
+                reason,
+                ..
+            } => {
+                write!(f, "post {post_id} is now {}", status.as_str())?;
+                if let Some(reason) = reason {
+                    write!(f, ": {reason}")?;
+                }
+                Ok(())
+            }
+            Notification::CommentModerated {
+                post_id,
+                comment_id,
+                status,
+                reason,
+                ..
+            } => {
+                write!(
+                    f,
+                    "comment {comment_id} on post {post_id} is now {}",
+                    status.as_str()
+                )?;
+                if let Some(reason) = reason {
+                    write!(f, ": {reason}")?;
+                }
+                Ok(())
+            }
+            Notification::Mentioned {
+                post_id,
+                comment_id: None,
+                ..
+            } => write!(f, "you were mentioned in post {post_id}"),
+            Notification::Mentioned {
+                post_id,
+                comment_id: Some(comment_id),
+                ..
+            } => write!(
+                f,
+                "you were mentioned in comment {comment_id} on post {post_id}"
+            ),
+            Notification::Invitation {
+                org_name,
+                link,
+                expires_at,
+                ..
+            } => write!(
+                f,
+                "you are invited to join {org_name}, accept at {link} before {expires_at}"
+            ),
+            Notification::Digest { subject, .. } => write!(f, "{subject}"),
+        }
+    }
+}
+
+impl Notification {
+    // who it goes to, a user id or an email address
+    pub fn recipient(&self) -> String {
+        match self {
+            Notification::PostModerated { user_id, .. }
+            | Notification::CommentModerated { user_id, .. }
+            | Notification::Mentioned { user_id, .. }
+            | Notification::Digest { user_id, .. } => {
+                format!("user {user_id}")
+            }
+            Notification::Invitation { email, .. } => email.clone(),
+        }
+    }
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, notification: Notification);
+}
+
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, notification: Notification) {
+        info!(
+            recipient = notification.recipient(),
+            "notification: {notification}"
+        );
+    }
+}
+
+// Keeps what was sent, for tests. Only compiled with the `test-support`
+// feature.
+#[cfg(feature = "test-support")]
+#[derive(Default)]
+pub struct RecordingNotifier {
+    sent: std::sync::Mutex<Vec<Notification>>,
+}
+
+#[cfg(feature = "test-support")]
+impl RecordingNotifier {
+    pub fn sent(&self) -> Vec<Notification> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(feature = "test-support")]
+#[async_trait]
+impl Notifier for RecordingNotifier {
+    async fn notify(&self, notification: Notification) {
+        self.sent.lock().unwrap().push(notification);
+    }
+}
@@ -0,0 +1,149 @@
+// oEmbed provider: `GET /oembed?url=<post URL>` describes one of our posts
+// as a "rich" oEmbed response, whose HTML snippet third-party platforms put
+// on their pages in place of a bare link, see https://oembed.com.
+//
+// Post URLs are `<PUBLIC_URL>/posts/:id`, so like federation this takes
+// PUBLIC_URL; unset, the endpoint is a 404. Only published posts are
+// embedded, other URLs are 404s. Only the JSON format is provided, asking
+// for XML is a 501 as the spec wants.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+use crate::federation::escape;
+use crate::models::PostStatus;
+use crate::routes::{expand, POST_ROUTE, USER_ROUTE};
+use crate::state::AppState;
+
+pub const PROVIDER_NAME: &str = env!("CARGO_PKG_NAME");
+
+// the width of the snippet unless the consumer wants it narrower
+const WIDTH: u32 = 550;
+
+// how long consumers may cache a response, in seconds
+const CACHE_AGE: u32 = 3600;
+
+// `GET /oembed` parameters
+#[derive(Deserialize)]
+pub struct OembedParams {
+    url: String,
+    maxwidth: Option<u32>,
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Oembed {
+    version: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_url: Option<String>,
+    provider_name: &'static str,
+    provider_url: String,
+    cache_age: u32,
+    html: String,
+    width: u32,
+    // the snippet is as tall as the post is long
+    height: Option<u32>,
+}
+
+// where consumers find the oEmbed response for `url`, from `base`
+pub fn discovery_url(base: &str, url: &str) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("url", url)
+        .finish();
+    format!("{base}/oembed?{query}")
+}
+
+// handler for "GET /oembed" rest API endpoint
+pub async fn oembed(
+    State(state): State<AppState>,
+    Query(params): Query<OembedParams>,
+) -> Result<Json<Oembed>, ApiError> {
+    let base = state
+        .config
+        .public_url
+        .as_deref()
+        .ok_or_else(|| ApiError::not_found("oEmbed is off, it takes PUBLIC_URL"))?;
+    match params.format.as_deref() {
+        None | Some("json") => {}
+        Some(format) => {
+            return Err(ApiError::new(
+                StatusCode::NOT_IMPLEMENTED,
+                "unsupported_format",
+                format!("no oEmbed {format} format, only json"),
+            ))
+        }
+    }
+    let unknown = || ApiError::not_found(format!("no post at {}", params.url));
+    let id = params
+        .url
+        .strip_prefix(base)
+        .and_then(|path| path.strip_prefix("/posts/"))
+        .map(|id| {
+            id.split(['?', '#'])
+                .next()
+                .unwrap_or(id)
+                .trim_end_matches('/')
+        })
+        .filter(|id| !id.is_empty() && !id.contains('/'))
+        .ok_or_else(unknown)?;
+    let key = state.config.id_scheme.parse_key(id).ok_or_else(unknown)?;
+    let post = match state.posts.get(key).await {
+        Ok(post) if post.status == PostStatus::Published.as_str() => post,
+        Ok(_) | Err(sqlx::Error::RowNotFound) => return Err(unknown()),
+        Err(e) => return Err(e.into()),
+    };
+    let author = match post.user_id {
+        Some(user_id) => match state.users.get(user_id).await {
+            Ok(user) => Some(user),
+            Err(sqlx::Error::RowNotFound) => None,
+            Err(e) => return Err(e.into()),
+        },
+        None => None,
+    };
+    let author_url = author
+        .as_ref()
+        .and_then(|user| state.config.id_scheme.segment(user.id, Some(user.uuid)))
+        .map(|user| base.to_owned() + &expand(USER_ROUTE, &user));
+
+    let post_url = base.to_owned() + &expand(POST_ROUTE, &post.public_id);
+    let byline = match (&author, &author_url) {
+        (Some(user), Some(url)) => {
+            format!(
+                "<a href=\"{}\">{}</a>, ",
+                escape(url),
+                escape(&user.username)
+            )
+        }
+        _ => String::new(),
+    };
+    let html = format!(
+        "<blockquote class=\"{PROVIDER_NAME}-post\"><p><strong>{}</strong></p><p>{}</p>\
+         <footer>&mdash; {byline}<a href=\"{}\">{}</a></footer></blockquote>",
+        escape(&post.title),
+        escape(&post.body),
+        escape(&post_url),
+        post.created_at.format("%B %-d, %Y"),
+    );
+
+    Ok(Json(Oembed {
+        version: "1.0",
+        kind: "rich",
+        title: post.title,
+        author_name: author.map(|user| user.username),
+        author_url,
+        provider_name: PROVIDER_NAME,
+        provider_url: base.to_owned(),
+        cache_age: CACHE_AGE,
+        html,
+        width: params.maxwidth.map_or(WIDTH, |max| max.min(WIDTH)),
+        height: None,
+    }))
+}
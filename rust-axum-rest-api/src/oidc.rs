@@ -0,0 +1,356 @@
+// Resource server mode: with OIDC_ISSUER set, bearer tokens issued by that
+// external OpenID Connect provider are accepted alongside our own (see
+// `jwt`). They must be RS256 JWTs signed with a key from the provider's JWKS,
+// name OIDC_AUDIENCE among their audiences and be current. Their subject acts
+// as the local user linked to it (`POST /admin/users/:id/identities`) or,
+// with OIDC_AUTO_PROVISION, as a user created from the token's username and
+// email claims the first time it is seen.
+//
+// The JWKS comes from OIDC_JWKS_URL, or the provider's discovery document
+// when unset. It is cached for an hour and fetched again early when a token
+// names a key we don't know, at most once a minute.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use chrono::Utc;
+use rsa::{BigUint, RsaPublicKey};
+use serde::Deserialize;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+
+use crate::audit::{self, Change};
+use crate::auth::RequireAdmin;
+use crate::email;
+use crate::error::{ApiError, OrNotFound};
+use crate::federation::http;
+use crate::jwt::{self, invalid};
+use crate::models::{CreateUser, ExternalIdentity, LinkIdentity};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::repo;
+use crate::state::AppState;
+use crate::usernames;
+
+// how long the provider's keys are trusted without asking again
+const KEYS_MAX_AGE: Duration = Duration::from_secs(3600);
+
+// the least time between fetches when tokens name unknown keys
+const REFETCH_AFTER: Duration = Duration::from_secs(60);
+
+// how far the clocks of the provider and ours may disagree
+const LEEWAY: i64 = 60;
+
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    // OIDC_ISSUER, the provider's `iss`
+    pub issuer: String,
+    // OIDC_AUDIENCE, who tokens must be meant for
+    pub audience: String,
+    // OIDC_JWKS_URL, discovered from the issuer when unset
+    pub jwks_url: Option<String>,
+    // OIDC_AUTO_PROVISION=true|false, off by default
+    pub auto_provision: bool,
+}
+
+impl OidcConfig {
+    pub fn from_env() -> Result<Option<OidcConfig>, String> {
+        let Some(issuer) = std::env::var("OIDC_ISSUER")
+            .ok()
+            .filter(|issuer| !issuer.is_empty())
+        else {
+            return Ok(None);
+        };
+        let audience = std::env::var("OIDC_AUDIENCE")
+            .ok()
+            .filter(|audience| !audience.is_empty())
+            .ok_or("OIDC_AUDIENCE must be set with OIDC_ISSUER")?;
+        let jwks_url = std::env::var("OIDC_JWKS_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+        let auto_provision = match std::env::var("OIDC_AUTO_PROVISION") {
+            Ok(value) => value.parse().map_err(|_| {
+                format!("invalid OIDC_AUTO_PROVISION {value:?}, expected true or false")
+            })?,
+            Err(_) => false,
+        };
+        Ok(Some(OidcConfig {
+            issuer,
+            audience,
+            jwks_url,
+            auto_provision,
+        }))
+    }
+}
+
+// The provider's keys as last fetched.
+#[derive(Default)]
+struct Keys {
+    by_kid: HashMap<String, RsaPublicKey>,
+    fetched_at: Option<Instant>,
+}
+
+impl Keys {
+    fn fetched_within(&self, age: Duration) -> bool {
+        self.fetched_at.is_some_and(|at| at.elapsed() < age)
+    }
+
+    // the key `kid` names, or the only one for tokens without a kid
+    fn find(&self, kid: Option<&str>) -> Option<RsaPublicKey> {
+        match kid {
+            Some(kid) => self.by_kid.get(kid).cloned(),
+            None if self.by_kid.len() == 1 => self.by_kid.values().next().cloned(),
+            None => None,
+        }
+    }
+}
+
+// The external provider tokens are checked against, see the top of the
+// module.
+pub struct Oidc {
+    config: OidcConfig,
+    keys: RwLock<Keys>,
+    // one fetch at a time
+    fetching: Mutex<()>,
+}
+
+#[derive(Deserialize)]
+struct Issuer {
+    iss: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(one) => one == audience,
+            Audience::Many(many) => many.iter().any(|one| one == audience),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExternalClaims {
+    sub: String,
+    aud: Audience,
+    exp: i64,
+    nbf: Option<i64>,
+    email: Option<String>,
+    preferred_username: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    #[serde(rename = "use")]
+    use_: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+impl Jwk {
+    // the RSA signing key this is, if any
+    fn rsa(&self) -> Option<RsaPublicKey> {
+        if self.kty != "RSA" || self.use_.as_deref().is_some_and(|use_| use_ != "sig") {
+            return None;
+        }
+        let n = BASE64URL.decode(self.n.as_ref()?).ok()?;
+        let e = BASE64URL.decode(self.e.as_ref()?).ok()?;
+        RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e)).ok()
+    }
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Oidc {
+            config,
+            keys: RwLock::new(Keys::default()),
+            fetching: Mutex::new(()),
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.config.issuer
+    }
+
+    // whether `token` claims to come from the provider, without checking it
+    pub fn issued(&self, token: &str) -> bool {
+        jwt::decode(token)
+            .and_then(|token| token.claims::<Issuer>())
+            .is_ok_and(|claims| claims.iss == self.config.issuer)
+    }
+
+    // The local user a valid token of the provider acts as, provisioned if
+    // need be.
+    pub async fn authenticate(&self, state: &AppState, token: &str) -> Result<i32, ApiError> {
+        let token = jwt::decode(token)?;
+        let key = self.key(token.kid.as_deref()).await?;
+        let claims: ExternalClaims = token.verify(key)?;
+        if !claims.aud.contains(&self.config.audience) {
+            return Err(invalid("the token is meant for someone else"));
+        }
+        let now = Utc::now().timestamp();
+        if claims.exp + LEEWAY <= now {
+            return Err(invalid("the token has expired"));
+        }
+        if claims.nbf.is_some_and(|nbf| nbf - LEEWAY > now) {
+            return Err(invalid("the token isn't valid yet"));
+        }
+
+        if let Some(user_id) =
+            repo::identities::user_for(&state.pool, self.issuer(), &claims.sub).await?
+        {
+            return Ok(user_id);
+        }
+        if !self.config.auto_provision {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "unknown_identity",
+                "no user is linked to the token's subject",
+            ));
+        }
+        self.provision(state, &claims).await
+    }
+
+    // create the user a subject acts as from its claims
+    async fn provision(&self, state: &AppState, claims: &ExternalClaims) -> Result<i32, ApiError> {
+        let missing = || {
+            ApiError::new(
+                StatusCode::FORBIDDEN,
+                "unknown_identity",
+                "the token's subject has no user and the token lacks the email to create one",
+            )
+        };
+        let email = email::validate(claims.email.as_deref().ok_or_else(missing)?)?;
+        let username = claims
+            .preferred_username
+            .as_deref()
+            .unwrap_or_else(|| email.split('@').next().unwrap_or_default());
+        let username = usernames::check(state, username).await?;
+        let user = state.users.create(&CreateUser { username, email }).await?;
+        repo::identities::link(&state.pool, self.issuer(), &claims.sub, user.id).await?;
+        info!(
+            user_id = user.id,
+            subject = claims.sub,
+            "provisioned a user for the identity provider"
+        );
+        Ok(user.id)
+    }
+
+    // the provider's key `kid` names, fetching the keys again when they are
+    // stale or don't have it
+    async fn key(&self, kid: Option<&str>) -> Result<RsaPublicKey, ApiError> {
+        {
+            let keys = self.keys.read().await;
+            if keys.fetched_within(KEYS_MAX_AGE) {
+                if let Some(key) = keys.find(kid) {
+                    return Ok(key);
+                }
+                if keys.fetched_within(REFETCH_AFTER) {
+                    return Err(invalid("the token is signed with an unknown key"));
+                }
+            }
+        }
+
+        let _fetching = self.fetching.lock().await;
+        // someone else may have just fetched them
+        if self.keys.read().await.fetched_within(REFETCH_AFTER) {
+            return self
+                .keys
+                .read()
+                .await
+                .find(kid)
+                .ok_or_else(|| invalid("the token is signed with an unknown key"));
+        }
+        let by_kid = self.fetch().await.map_err(|e| {
+            warn!("fetching the identity provider's keys failed: {e}");
+            ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "identity_provider_unavailable",
+                "the identity provider's keys can't be fetched",
+            )
+        })?;
+        let mut keys = self.keys.write().await;
+        *keys = Keys {
+            by_kid,
+            fetched_at: Some(Instant::now()),
+        };
+        keys.find(kid)
+            .ok_or_else(|| invalid("the token is signed with an unknown key"))
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, RsaPublicKey>, reqwest::Error> {
+        // the provider is configured, and may well be on a private network
+        let http = http(true);
+        let jwks_url = match &self.config.jwks_url {
+            Some(url) => url.clone(),
+            None => {
+                let discovery = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                let discovery: Discovery = http
+                    .get(discovery)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                discovery.jwks_uri
+            }
+        };
+        let jwks: Jwks = http
+            .get(jwks_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| Some((jwk.kid.clone().unwrap_or_default(), jwk.rsa()?)))
+            .collect())
+    }
+}
+
+// handler for "POST /admin/users/:id/identities" rest API endpoint, lets the
+// identity provider's `subject` act as the user
+pub async fn link_identity(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    Accept(format): Accept,
+    Payload(link): Payload<LinkIdentity>,
+) -> Result<Negotiated<ExternalIdentity>, ApiError> {
+    let oidc = state
+        .oidc
+        .as_ref()
+        .ok_or_else(|| ApiError::not_found("the identity provider is off, it takes OIDC_ISSUER"))?;
+    let user = state.users.get(user_id).await.or_not_found("user")?;
+    let identity =
+        repo::identities::link(&state.pool, oidc.issuer(), &link.subject, user.id).await?;
+    audit::record(Change::new("user.link_identity", "user", user.id).after(&identity));
+
+    Ok(format.respond(identity))
+}
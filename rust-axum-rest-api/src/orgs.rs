@@ -0,0 +1,157 @@
+// Organizations: teams of users sharing ownership of posts.
+//
+// Whoever creates an organization owns it. Owners and admins add members,
+// only owners make others admins or owners. Any member may post on behalf of
+// the organization and edit its posts, deleting them takes an admin or owner.
+// Users act through their API keys, see `auth::Actor`.
+
+use axum::extract::{Path, Query, State};
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+
+use crate::audit::{self, Change};
+use crate::auth::Actor;
+use crate::error::{ApiError, OrNotFound};
+use crate::links::Linked;
+use crate::models::{AddMember, CreateOrganization, Membership, OrgRole, Organization};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::pagination::{self, PageParams, Total};
+use crate::repo;
+use crate::repo::PostFilter;
+use crate::state::AppState;
+
+// the acting user's role in an organization, failing unless it is at least
+// `required`
+pub async fn require_role(
+    state: &AppState,
+    actor: &Actor,
+    org_id: i32,
+    required: OrgRole,
+) -> Result<OrgRole, ApiError> {
+    let user_id = require_user(actor)?;
+    match repo::orgs::role(&state.pool, org_id, user_id).await? {
+        Some(role) if role >= required => Ok(role),
+        _ => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            format!(
+                "this takes the {} role in organization {org_id}",
+                required.as_str()
+            ),
+        )),
+    }
+}
+
+fn require_user(actor: &Actor) -> Result<i32, ApiError> {
+    actor.user_id.ok_or_else(|| {
+        ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthenticated",
+            "this takes a user's API key",
+        )
+    })
+}
+
+// handler for "POST /orgs" rest API endpoint
+pub async fn create_org(
+    State(state): State<AppState>,
+    actor: Actor,
+    Accept(format): Accept,
+    Payload(org): Payload<CreateOrganization>,
+) -> Result<Negotiated<Organization>, ApiError> {
+    let owner = require_user(&actor)?;
+    let name = org.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "missing_name",
+            "organizations need a name",
+        )
+        .with("field", "name"));
+    }
+    let org = repo::orgs::create(&state.pool, name, owner).await?;
+    audit::record(Change::new("org.create", "org", org.id).after(&org));
+
+    Ok(format.respond(org))
+}
+
+// handler for "GET /orgs/:id" rest API endpoint
+pub async fn get_org(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Organization>, ApiError> {
+    let org = repo::orgs::get(&state.pool, id)
+        .await
+        .or_not_found("organization")?;
+
+    Ok(format.respond(org))
+}
+
+// handler for "GET /orgs/:id/members" rest API endpoint
+pub async fn list_members(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<Membership>>, ApiError> {
+    let org = repo::orgs::get(&state.pool, id)
+        .await
+        .or_not_found("organization")?;
+    let members = repo::orgs::members(&state.pool, org.id).await?;
+
+    Ok(format.respond(members))
+}
+
+// handler for "POST /orgs/:id/members" rest API endpoint, adding a member or
+// changing their role
+pub async fn add_member(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    actor: Actor,
+    Accept(format): Accept,
+    Payload(member): Payload<AddMember>,
+) -> Result<Negotiated<Membership>, ApiError> {
+    let org = repo::orgs::get(&state.pool, id)
+        .await
+        .or_not_found("organization")?;
+    let required = match member.role {
+        OrgRole::Member => OrgRole::Admin,
+        OrgRole::Admin | OrgRole::Owner => OrgRole::Owner,
+    };
+    require_role(&state, &actor, org.id, required).await?;
+    let membership =
+        repo::orgs::add_member(&state.pool, org.id, member.user_id, member.role).await?;
+    audit::record(Change::new("org.add_member", "org", org.id).after(&membership));
+
+    Ok(format.respond(membership))
+}
+
+// handler for "GET /orgs/:id/posts" rest API endpoint, the organization's
+// posts, filtered, sorted and paged like `GET /posts`
+pub async fn list_org_posts(
+    State(state): State<AppState>,
+    uri: Uri,
+    Path(id): Path<i32>,
+    Query(mut filter): Query<PostFilter>,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Response, ApiError> {
+    let org = repo::orgs::get(&state.pool, id)
+        .await
+        .or_not_found("organization")?;
+    filter.org_id = Some(org.id);
+    let exact = page.exact();
+    let page = page.page()?;
+    let total = if exact {
+        Total::from(state.posts.count(&filter).await?)
+    } else {
+        Total::estimated(state.posts.estimate(&filter).await?)
+    };
+    let posts = state.posts.list(&filter, page).await?;
+
+    Ok((
+        pagination::headers(&uri, page, total),
+        format.respond(Linked::all(posts, state.config.id_scheme)),
+    )
+        .into_response())
+}
@@ -0,0 +1,158 @@
+// Pagination of list endpoints with `?page=` (1-based) and `?per_page=`.
+//
+// Lists are only paginated when one of the two is given. Either way the
+// response carries `X-Total-Count` with the number of matching items, and
+// paginated responses an RFC 5988 `Link` header with the first, last, next
+// and previous pages, the way generic admin tooling expects.
+//
+// Counting every match gets slow on big tables, so `?exact=false` asks for
+// the query planner's estimate instead, where the list has one (the posts);
+// an estimated total comes with `X-Total-Count-Exact: false`.
+
+use axum::http::header::LINK;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+
+pub const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+pub const X_TOTAL_COUNT_EXACT: HeaderName = HeaderName::from_static("x-total-count-exact");
+
+const DEFAULT_PER_PAGE: u32 = 20;
+const MAX_PER_PAGE: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct PageParams {
+    page: Option<u32>,
+    per_page: Option<u32>,
+    exact: Option<bool>,
+}
+
+impl PageParams {
+    pub fn page(&self) -> Result<Option<Page>, ApiError> {
+        if self.page.is_none() && self.per_page.is_none() {
+            return Ok(None);
+        }
+        let number = self.page.unwrap_or(1);
+        let size = self.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if number == 0 || size == 0 || size > MAX_PER_PAGE {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_page",
+                format!("page starts at 1 and per_page must be between 1 and {MAX_PER_PAGE}"),
+            ));
+        }
+        Ok(Some(Page { number, size }))
+    }
+
+    // whether the total must be counted exactly, as it is by default
+    pub fn exact(&self) -> bool {
+        self.exact.unwrap_or(true)
+    }
+}
+
+// The number of matching items, or an estimate of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Total {
+    pub count: i64,
+    pub exact: bool,
+}
+
+impl Total {
+    pub fn estimated(count: i64) -> Self {
+        Total {
+            count,
+            exact: false,
+        }
+    }
+}
+
+impl From<i64> for Total {
+    fn from(count: i64) -> Self {
+        Total { count, exact: true }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub number: u32,
+    pub size: u32,
+}
+
+impl Page {
+    pub fn limit(self) -> i64 {
+        i64::from(self.size)
+    }
+
+    pub fn offset(self) -> i64 {
+        i64::from(self.number - 1) * i64::from(self.size)
+    }
+
+    // the number of the last page, 1 for an empty list
+    pub fn last(self, total: i64) -> u32 {
+        let pages = (total + self.limit() - 1) / self.limit();
+        pages.max(1) as u32
+    }
+}
+
+// X-Total-Count, plus the Link header for paginated lists
+pub fn headers(uri: &Uri, page: Option<Page>, total: impl Into<Total>) -> HeaderMap {
+    let Total {
+        count: total,
+        exact,
+    } = total.into();
+    let mut headers = HeaderMap::new();
+    headers.insert(X_TOTAL_COUNT, HeaderValue::from(total));
+    if !exact {
+        headers.insert(X_TOTAL_COUNT_EXACT, HeaderValue::from_static("false"));
+    }
+    let Some(page) = page else {
+        return headers;
+    };
+
+    let last = page.last(total);
+    let mut links = vec![(1, "first"), (last, "last")];
+    if page.number < last {
+        links.push((page.number + 1, "next"));
+    }
+    if page.number > 1 {
+        links.push(((page.number - 1).min(last), "prev"));
+    }
+    let link = links
+        .into_iter()
+        .map(|(number, rel)| format!("<{}>; rel=\"{rel}\"", page_uri(uri, number, page.size)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Ok(link) = HeaderValue::from_str(&link) {
+        headers.insert(LINK, link);
+    }
+    headers
+}
+
+// the pages of the Link header in `headers`, (rel, URI) in its order
+pub fn links(headers: &HeaderMap) -> Vec<(&str, &str)> {
+    let Some(link) = headers.get(LINK).and_then(|value| value.to_str().ok()) else {
+        return Vec::new();
+    };
+    // the URIs are form-encoded, so hold no ", "
+    link.split(", ")
+        .filter_map(|entry| {
+            let (uri, rel) = entry.split_once("; rel=")?;
+            let uri = uri.strip_prefix('<')?.strip_suffix('>')?;
+            Some((rel.trim_matches('"'), uri))
+        })
+        .collect()
+}
+
+// the request's URI with the page swapped out, other parameters kept
+fn page_uri(uri: &Uri, number: u32, size: u32) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes()) {
+        if key != "page" && key != "per_page" {
+            query.append_pair(&key, &value);
+        }
+    }
+    query.append_pair("page", &number.to_string());
+    query.append_pair("per_page", &size.to_string());
+    format!("{}?{}", uri.path(), query.finish())
+}
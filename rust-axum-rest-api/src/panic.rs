@@ -0,0 +1,34 @@
+// Panics in handlers. `CatchPanicLayer` turns them into a JSON 500 instead of
+// a dropped connection, and the hook installed by the server logs each one
+// together with the backtrace of where it happened and the request id.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+
+use axum::response::{IntoResponse, Response};
+use tracing::error;
+
+use crate::error::ApiError;
+use crate::request_id;
+
+// Log panics through tracing. The backtrace has to be captured here, by the
+// time `handle_panic` runs the stack has been unwound.
+pub fn install_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::force_capture();
+        let request_id = request_id::current().unwrap_or_default();
+        error!(request_id, "{info}\n{backtrace}");
+    }));
+}
+
+// response for a panic caught by `CatchPanicLayer`
+pub fn handle_panic(payload: Box<dyn Any + Send + 'static>) -> Response {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("a panic");
+    ApiError::internal()
+        .detail(format_args!("panicked: {message}"))
+        .into_response()
+}
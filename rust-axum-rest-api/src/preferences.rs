@@ -0,0 +1,40 @@
+// Notification preferences: which emails a user gets. `GET` and
+// `PUT /me/preferences` read and change the acting user's; everything is
+// off until the user opts in. The emails link to a one-click unsubscribe, see
+// `unsubscribe`.
+//
+//     weekly_digest   the new posts of the users they follow, see `digest`
+
+use axum::extract::State;
+
+use crate::auth::Actor;
+use crate::error::ApiError;
+use crate::models::{NotificationPreferences, UpdateNotificationPreferences};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::repo;
+use crate::state::AppState;
+
+// handler for "GET /me/preferences" rest API endpoint
+pub async fn my_preferences(
+    State(state): State<AppState>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<NotificationPreferences>, ApiError> {
+    let user_id = actor.require_user()?;
+    let preferences = repo::preferences::get(&state.pool, user_id).await?;
+
+    Ok(format.respond(preferences))
+}
+
+// handler for "PUT /me/preferences" rest API endpoint
+pub async fn update_my_preferences(
+    State(state): State<AppState>,
+    actor: Actor,
+    Accept(format): Accept,
+    Payload(update): Payload<UpdateNotificationPreferences>,
+) -> Result<Negotiated<NotificationPreferences>, ApiError> {
+    let user_id = actor.require_user()?;
+    let preferences = repo::preferences::update(&state.pool, user_id, &update).await?;
+
+    Ok(format.respond(preferences))
+}
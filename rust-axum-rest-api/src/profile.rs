@@ -0,0 +1,211 @@
+// Environment profiles. APP_ENV names the one in use: development, test or
+// production (dev and prod will do), production when it's unset so that a
+// deployment never gets development's leniency by leaving it out. It picks
+// the dotenv files read at startup, most specific first:
+//
+//     .env.{profile}.local   .env.{profile}   .env.local   .env
+//
+// A variable already set wins over the files, and a file over those after
+// it. The .local files are for one machine and stay out of git; .env.local
+// is skipped in test, for the tests to run the same everywhere. APP_ENV
+// itself may come from the environment or from .env.
+//
+// The profile also picks the defaults of
+//
+//                        development    test           production
+//     LOG_FORMAT         text           compact        json
+//     CORS origins       any            cors_origins   cors_origins
+//     ERROR_DETAILS      true           false          false
+//     EMAIL_LINK_SECRET  random         random         required
+//
+// the log's format (see `tunables::init_logging`), which origins may make
+// cross-origin requests when the config file allows none (see
+// `tunables::cors`), whether 500s say what went wrong (see `error`) and
+// whether unsubscribe links may be signed with a secret that dies with the
+// process (see `config`). LOG_FORMAT and ERROR_DETAILS override theirs.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    Development,
+    Test,
+    #[default]
+    Production,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "development" | "dev" => Ok(Profile::Development),
+            "test" => Ok(Profile::Test),
+            "production" | "prod" => Ok(Profile::Production),
+            _ => Err(format!(
+                "invalid APP_ENV {s:?}, expected development, test or production"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Development => "development",
+            Profile::Test => "test",
+            Profile::Production => "production",
+        }
+    }
+
+    // APP_ENV, production when it's unset
+    pub fn from_env() -> Result<Profile, String> {
+        match std::env::var("APP_ENV") {
+            Ok(name) if !name.is_empty() => name.parse(),
+            _ => Ok(Profile::default()),
+        }
+    }
+
+    // Read the dotenv files of the profile APP_ENV names from `dir`, and
+    // return it.
+    pub fn load(dir: &Path) -> Result<Profile, String> {
+        let profile = match std::env::var("APP_ENV") {
+            Ok(name) if !name.is_empty() => name.parse()?,
+            // from .env, if it says
+            _ => dotenv_var(&dir.join(".env"), "APP_ENV")?
+                .map_or(Ok(Profile::default()), |name| name.parse())?,
+        };
+        for file in profile.dotenv_files() {
+            let path = dir.join(&file);
+            match dotenvy::from_path(&path) {
+                Ok(()) => {}
+                Err(e) if e.not_found() => {}
+                Err(e) => return Err(format!("can't read {}: {e}", path.display())),
+            }
+        }
+        Ok(profile)
+    }
+
+    // the files read, most specific first
+    pub fn dotenv_files(self) -> Vec<String> {
+        let mut files = vec![format!(".env.{self}.local"), format!(".env.{self}")];
+        if self != Profile::Test {
+            files.push(String::from(".env.local"));
+        }
+        files.push(String::from(".env"));
+        files
+    }
+
+    pub fn log_format(self) -> LogFormat {
+        match self {
+            Profile::Development => LogFormat::Text,
+            Profile::Test => LogFormat::Compact,
+            Profile::Production => LogFormat::Json,
+        }
+    }
+
+    // whether any origin may make cross-origin requests when the config
+    // file lists none
+    pub fn any_origin(self) -> bool {
+        self == Profile::Development
+    }
+
+    pub fn error_details(self) -> bool {
+        self == Profile::Development
+    }
+}
+
+// `name` as set in the dotenv file at `path`, if it is
+fn dotenv_var(path: &Path, name: &str) -> Result<Option<String>, String> {
+    let entries = match dotenvy::from_path_iter(path) {
+        Ok(entries) => entries,
+        Err(e) if e.not_found() => return Ok(None),
+        Err(e) => return Err(format!("can't read {}: {e}", path.display())),
+    };
+    for entry in entries {
+        let (key, value) = entry.map_err(|e| format!("can't read {}: {e}", path.display()))?;
+        if key == name {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+// How log lines are written: `Text` is tracing's default format, `Compact`
+// the same on fewer columns and `Json` an object per line (see
+// `redact::JsonFormat`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Compact,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "invalid LOG_FORMAT {s:?}, expected text, compact or json"
+            )),
+        }
+    }
+}
+
+impl LogFormat {
+    // LOG_FORMAT, the profile's when it's unset
+    pub fn from_env(profile: Profile) -> Result<LogFormat, String> {
+        match std::env::var("LOG_FORMAT") {
+            Ok(format) if !format.is_empty() => format.parse(),
+            _ => Ok(profile.log_format()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_their_short_forms_parse() {
+        assert_eq!("dev".parse(), Ok(Profile::Development));
+        assert_eq!("Development".parse(), Ok(Profile::Development));
+        assert_eq!("PROD".parse(), Ok(Profile::Production));
+        assert_eq!("test".parse(), Ok(Profile::Test));
+        assert!("staging"
+            .parse::<Profile>()
+            .unwrap_err()
+            .contains("APP_ENV"));
+    }
+
+    #[test]
+    fn leaving_app_env_out_is_production() {
+        assert_eq!(Profile::default(), Profile::Production);
+        assert!(!Profile::default().any_origin());
+        assert!(!Profile::default().error_details());
+        if std::env::var_os("APP_ENV").is_none() {
+            let dir = std::env::temp_dir().join(format!("profile-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            assert_eq!(Profile::load(&dir), Ok(Profile::Production));
+            std::fs::remove_dir_all(&dir).ok();
+        }
+    }
+
+    #[test]
+    fn log_formats_parse() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("JSON".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+// On-demand CPU profiling, built with the pprof feature: `GET
+// /admin/debug/pprof/profile` (admins only, from the addresses the admin IP
+// rules permit, see `ip_filter`) samples every thread of the process for
+// `seconds` (30 by default, `MAX_SECONDS` at most) and answers with the
+// profile, in pprof's protobuf format for `go tool pprof` by default, or as a
+// flamegraph SVG with `format=flamegraph`:
+//
+//     go tool pprof -http :8080 'https://api.example.com/admin/debug/pprof/profile?seconds=10'
+//
+// One profile is taken at a time, as the profiler is the process's. Sampling
+// costs little, so it is safe to run against production traffic.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::extract::Query;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+use crate::auth::RequireAdmin;
+use crate::error::ApiError;
+
+const DEFAULT_SECONDS: u64 = 30;
+
+const MAX_SECONDS: u64 = 300;
+
+// samples per second, off the beat of timers firing on round numbers
+#[cfg(feature = "pprof")]
+const FREQUENCY: i32 = 99;
+
+// whether a profile is being taken
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+// The one profile being taken, until dropped. It goes with the sampling
+// rather than the request, which may be gone before the sampling ends.
+struct Profiling;
+
+impl Profiling {
+    fn start() -> Option<Profiling> {
+        (!PROFILING.swap(true, Ordering::AcqRel)).then_some(Profiling)
+    }
+}
+
+impl Drop for Profiling {
+    fn drop(&mut self) {
+        PROFILING.store(false, Ordering::Release);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    #[default]
+    Protobuf,
+    Flamegraph,
+}
+
+// `GET /admin/debug/pprof/profile` parameters
+#[derive(Deserialize)]
+pub struct ProfileParams {
+    seconds: Option<u64>,
+    #[serde(default)]
+    format: ProfileFormat,
+}
+
+// handler for "GET /admin/debug/pprof/profile" rest API endpoint
+pub async fn profile(
+    _admin: RequireAdmin,
+    Query(params): Query<ProfileParams>,
+) -> Result<Response, ApiError> {
+    let seconds = params.seconds.unwrap_or(DEFAULT_SECONDS);
+    if !(1..=MAX_SECONDS).contains(&seconds) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_seconds",
+            format!("seconds must be between 1 and {MAX_SECONDS}"),
+        ));
+    }
+    let Some(profiling) = Profiling::start() else {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "profiling",
+            "a profile is being taken already",
+        ));
+    };
+    // the sampling blocks a thread for as long as it runs
+    let profiled = tokio::task::spawn_blocking(move || {
+        let _profiling = profiling;
+        sample(seconds, params.format)
+    })
+    .await;
+    let (content_type, body) = profiled.map_err(|e| {
+        tracing::error!("profiling panicked: {e}");
+        ApiError::internal()
+    })??;
+    Ok(([(CONTENT_TYPE, content_type)], body).into_response())
+}
+
+// A profile of the next `seconds`, with its content type.
+#[cfg(feature = "pprof")]
+fn sample(seconds: u64, format: ProfileFormat) -> Result<(&'static str, Vec<u8>), ApiError> {
+    use pprof::protos::Message;
+
+    let failed = |e: pprof::Error| {
+        tracing::error!("profiling failed: {e}");
+        ApiError::internal()
+    };
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(FREQUENCY)
+        // unwinding through these can crash the process
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(failed)?;
+    std::thread::sleep(std::time::Duration::from_secs(seconds));
+    let report = guard.report().build().map_err(failed)?;
+    let mut body = Vec::new();
+    match format {
+        ProfileFormat::Protobuf => {
+            let profile = report.pprof().map_err(failed)?;
+            profile.write_to_vec(&mut body).map_err(|e| {
+                tracing::error!("encoding the profile failed: {e}");
+                ApiError::internal()
+            })?;
+            Ok(("application/octet-stream", body))
+        }
+        ProfileFormat::Flamegraph => {
+            report.flamegraph(&mut body).map_err(failed)?;
+            Ok(("image/svg+xml", body))
+        }
+    }
+}
+
+#[cfg(not(feature = "pprof"))]
+fn sample(_seconds: u64, _format: ProfileFormat) -> Result<(&'static str, Vec<u8>), ApiError> {
+    Err(ApiError::new(
+        StatusCode::NOT_IMPLEMENTED,
+        "profiling_unavailable",
+        "profiling needs a build with the pprof feature",
+    ))
+}
@@ -0,0 +1,183 @@
+// QR codes of posts: `GET /posts/:id/qr.png` is a PNG of a QR code for the
+// post's URL, to print it or show it on a screen. With `?link=<code>` it is
+// the URL of one of the post's share links instead, so that scans are
+// counted like clicks (see `short_links`).
+//
+// `?size=` is the most pixels a side may have, `DEFAULT_SIZE` by default;
+// the code is drawn with square modules as large as fit, inside the white
+// border of `QUIET_ZONE` modules scanners look for. A printed code is
+// useless with a relative URL, so this takes PUBLIC_URL; unset, the
+// endpoint is a 404.
+//
+// Images are cached per process, and by clients for a day.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use qrcode::{Color, QrCode};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::error::{ApiError, OrNotFound};
+use crate::ids::PathKey;
+use crate::models::PostStatus;
+use crate::repo;
+use crate::routes::{expand, POST_ROUTE};
+use crate::short_links;
+use crate::state::AppState;
+
+pub const DEFAULT_SIZE: u32 = 256;
+
+pub const MIN_SIZE: u32 = 64;
+
+pub const MAX_SIZE: u32 = 2048;
+
+// modules of white around the code
+const QUIET_ZONE: usize = 4;
+
+// images kept, the oldest ones dropped first
+const CACHE_ENTRIES: usize = 256;
+
+const MAX_AGE: &str = "public, max-age=86400";
+
+// Rendered images by URL and size.
+#[derive(Default)]
+pub struct QrCache {
+    entries: Mutex<CacheEntries>,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    images: HashMap<(String, u32), Bytes>,
+    // oldest first
+    order: VecDeque<(String, u32)>,
+}
+
+impl QrCache {
+    fn get(&self, key: &(String, u32)) -> Option<Bytes> {
+        let entries = self.entries.lock().expect("QR code cache lock poisoned");
+        entries.images.get(key).cloned()
+    }
+
+    fn insert(&self, key: (String, u32), image: Bytes) {
+        let mut entries = self.entries.lock().expect("QR code cache lock poisoned");
+        if entries.images.insert(key.clone(), image).is_some() {
+            return;
+        }
+        entries.order.push_back(key);
+        if entries.order.len() > CACHE_ENTRIES {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.images.remove(&oldest);
+            }
+        }
+    }
+}
+
+// `GET /posts/:id/qr.png` parameters
+#[derive(Deserialize)]
+pub struct QrParams {
+    size: Option<u32>,
+    // the code of a share link of the post
+    link: Option<String>,
+}
+
+// handler for "GET /posts/:id/qr.png" rest API endpoint
+pub async fn post_qr_code(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    Query(params): Query<QrParams>,
+) -> Result<Response, ApiError> {
+    let base = state
+        .config
+        .public_url
+        .as_deref()
+        .ok_or_else(|| ApiError::not_found("QR codes are off, they take PUBLIC_URL"))?;
+    let size = params.size.unwrap_or(DEFAULT_SIZE);
+    if !(MIN_SIZE..=MAX_SIZE).contains(&size) {
+        return Err(invalid_size(format!(
+            "size must be between {MIN_SIZE} and {MAX_SIZE}"
+        )));
+    }
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    if post.status != PostStatus::Published.as_str() {
+        return Err(ApiError::not_found("post not found"));
+    }
+    let url = match &params.link {
+        Some(code) => {
+            repo::short_links::get(&state.pool, code)
+                .await?
+                .filter(|link| link.post_id == post.id)
+                .ok_or_else(|| ApiError::not_found(format!("no share link {code} of the post")))?;
+            short_links::url(&state, code)
+        }
+        None => base.to_owned() + &expand(POST_ROUTE, &post.public_id),
+    };
+
+    let key = (url, size);
+    let image = match state.qr_codes.get(&key) {
+        Some(image) => image,
+        None => {
+            let image = Bytes::from(render(&key.0, size)?);
+            state.qr_codes.insert(key, image.clone());
+            image
+        }
+    };
+
+    Ok((
+        [(CONTENT_TYPE, "image/png"), (CACHE_CONTROL, MAX_AGE)],
+        image,
+    )
+        .into_response())
+}
+
+fn invalid_size(message: String) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, "invalid_size", message).with("field", "size")
+}
+
+// `text` as a PNG of a QR code at most `size` pixels wide
+fn render(text: &str, size: u32) -> Result<Vec<u8>, ApiError> {
+    let code = QrCode::new(text).map_err(|e| {
+        error!("no QR code for {text}: {e}");
+        ApiError::internal()
+    })?;
+    let width = code.width();
+    let modules = width + 2 * QUIET_ZONE;
+    let scale = size as usize / modules;
+    if scale == 0 {
+        return Err(invalid_size(format!(
+            "this code takes a size of at least {modules}"
+        )));
+    }
+    let side = modules * scale;
+    let mut pixels = vec![u8::MAX; side * side];
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Light {
+            continue;
+        }
+        let (x, y) = (i % width + QUIET_ZONE, i / width + QUIET_ZONE);
+        for row in y * scale..(y + 1) * scale {
+            pixels[row * side + x * scale..row * side + (x + 1) * scale].fill(0);
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(&pixels)?;
+            writer.finish()
+        })
+        .map_err(|e| {
+            error!("encoding a QR code failed: {e}");
+            ApiError::internal()
+        })?;
+    Ok(png)
+}
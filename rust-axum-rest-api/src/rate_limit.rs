@@ -0,0 +1,83 @@
+// A fixed-window rate limiter keyed by client address, for endpoints that
+// would otherwise let a client enumerate data (see `GET /users/check`), or
+// by anything else with a limit per key, like the users of a plan (see
+// `billing`).
+//
+// Counts are kept in memory per process, which is good enough to slow down
+// a single client hammering one instance.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+use crate::client_ip::ClientIp;
+use crate::error::ApiError;
+
+pub struct RateLimiter<K = Option<IpAddr>> {
+    // changed by config reloads, see `tunables`
+    limit: AtomicU32,
+    window: Duration,
+    // start of the client's current window and the requests made in it
+    hits: Mutex<HashMap<K, (Instant, u32)>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit: AtomicU32::new(limit),
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // applies to the windows in progress as well
+    pub fn set_limit(&self, limit: u32) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    // count a request under `key` against `limit` rather than the limiter's
+    // own, failing with a 429 once it is over
+    pub fn check_against(&self, key: K, limit: u32) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap();
+        hits.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        let (start, count) = hits.entry(key).or_insert((now, 0));
+        if *count >= limit {
+            return Err(RateLimited(self.window - now.duration_since(*start)));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+impl RateLimiter {
+    // count a request from `client`, failing with a 429 once it is over the
+    // limit. Requests without a known address share one allowance.
+    pub fn check(&self, ClientIp(client): ClientIp) -> Result<(), RateLimited> {
+        self.check_against(client, self.limit.load(Ordering::Relaxed))
+    }
+}
+
+// the rejection of a request over the limit, carrying the time until the
+// client's window resets
+#[derive(Debug)]
+pub struct RateLimited(Duration);
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        let seconds = self.0.as_secs() + 1;
+        let error = ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            format!("too many requests, retry in {seconds} seconds"),
+        );
+        ([(RETRY_AFTER, seconds.to_string())], error).into_response()
+    }
+}
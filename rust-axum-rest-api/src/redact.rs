@@ -0,0 +1,311 @@
+// Keeps personal data and credentials out of the log. Its fields are
+// formatted by `Redactor`: a field with a sensitive name shows as
+// [redacted] whatever it holds, and in everything else, messages included,
+// email addresses, Bearer and Basic credentials and `name=value` or
+// `name: value` pairs with a sensitive name are masked. Error reports, be it
+// a database error or a panic (see `error` and `panic`), are logged, so they
+// are masked too.
+//
+// A name is sensitive when it contains one of password, secret, token,
+// authorization, cookie, api_key and email, or of the comma separated
+// LOG_REDACT_FIELDS, which add to those.
+//
+// With LOG_FORMAT=json (see `profile`) each line is an object written by
+// `JsonFormat`, like
+//
+//     {"timestamp": "…", "level": "INFO", "target": "…", "fields": {"message": "…"}, "spans": […]}
+//
+// masked the same way.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+pub const REDACTED: &str = "[redacted]";
+
+const DEFAULT_FIELDS: &[&str] = &[
+    "password",
+    "secret",
+    "token",
+    "authorization",
+    "cookie",
+    "api_key",
+    "email",
+];
+
+// the schemes of the Authorization header whose credentials follow
+const SCHEMES: &[&str] = &["bearer ", "basic "];
+
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    // lowercase
+    fields: Vec<String>,
+}
+
+impl Redactor {
+    // the default sensitive names and `extra`
+    pub fn new<I, S>(extra: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let extra = extra
+            .into_iter()
+            .map(|name| name.as_ref().trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty());
+        let mut fields: Vec<String> = DEFAULT_FIELDS.iter().map(|name| name.to_string()).collect();
+        fields.extend(extra);
+        Redactor { fields }
+    }
+
+    pub fn from_env() -> Self {
+        let extra = std::env::var("LOG_REDACT_FIELDS").unwrap_or_default();
+        Redactor::new(extra.split(','))
+    }
+
+    pub fn is_sensitive(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.fields
+            .iter()
+            .any(|field| name.contains(field.as_str()))
+    }
+
+    // `text` with the credentials and email addresses in it masked
+    pub fn scrub<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let lower = text.to_ascii_lowercase();
+        let mut ranges = Vec::new();
+        for scheme in SCHEMES {
+            for (at, _) in lower.match_indices(scheme) {
+                let start = at + scheme.len();
+                ranges.push((start, value_end(text, start)));
+            }
+        }
+        for field in &self.fields {
+            for (at, _) in lower.match_indices(field.as_str()) {
+                if let Some(range) = assigned_value(text, at + field.len()) {
+                    ranges.push(range);
+                }
+            }
+        }
+        for (at, _) in text.match_indices('@') {
+            if let Some(range) = email(text, at) {
+                ranges.push(range);
+            }
+        }
+        if ranges.is_empty() {
+            return Cow::Borrowed(text);
+        }
+
+        ranges.sort_unstable();
+        let mut scrubbed = String::with_capacity(text.len());
+        let mut done = 0;
+        for (start, end) in ranges {
+            if end <= done {
+                continue;
+            }
+            scrubbed.push_str(&text[done..start.max(done)]);
+            if start >= done {
+                scrubbed.push_str(REDACTED);
+            }
+            done = end;
+        }
+        scrubbed.push_str(&text[done..]);
+        Cow::Owned(scrubbed)
+    }
+}
+
+// where a value starting at `start` ends
+fn value_end(text: &str, start: usize) -> usize {
+    text[start..]
+        .find(|c: char| c.is_whitespace() || "\"',;&)]}".contains(c))
+        .map_or(text.len(), |len| start + len)
+}
+
+// the value assigned to the name ending at `at`, as in `name=value`,
+// `"name": "value"` or `x-name: value`, if any
+fn assigned_value(text: &str, at: usize) -> Option<(usize, usize)> {
+    let rest = &text[at..];
+    // the rest of a longer name, `password_hash` say
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let rest = rest.strip_prefix(['"', '\'']).unwrap_or(rest);
+    let rest = rest.trim_start().strip_prefix(['=', ':'])?.trim_start();
+    let rest = rest.strip_prefix(['"', '\'']).unwrap_or(rest);
+    let start = text.len() - rest.len();
+    // the scheme goes with the credentials, as a header's value
+    let scheme = SCHEMES.iter().find(|scheme| {
+        rest.get(..scheme.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    });
+    let end = value_end(text, start + scheme.map_or(0, |scheme| scheme.len()));
+    (end > start).then_some((start, end))
+}
+
+// the address around the `@` at `at`, if it is one
+fn email(text: &str, at: usize) -> Option<(usize, usize)> {
+    let local = |c: char| c.is_ascii_alphanumeric() || "._%+-".contains(c);
+    let domain = |c: char| c.is_ascii_alphanumeric() || ".-".contains(c);
+    let start = text[..at]
+        .rfind(|c: char| !local(c))
+        .map_or(0, |before| before + 1);
+    let end = text[at + 1..]
+        .find(|c: char| !domain(c))
+        .map_or(text.len(), |len| at + 1 + len);
+    let host = text[at + 1..end].trim_end_matches('.');
+    let end = at + 1 + host.len();
+    (start < at && host.contains('.') && !host.starts_with('.')).then_some((start, end))
+}
+
+// Formats fields the way `tracing_subscriber`'s default does, the message
+// bare and the others as `name=value`, but redacted.
+impl<'writer> FormatFields<'writer> for Redactor {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'writer>, fields: R) -> fmt::Result {
+        let mut visitor = Visitor {
+            redactor: self,
+            writer,
+            first: true,
+            result: Ok(()),
+        };
+        fields.record(&mut visitor);
+        visitor.result
+    }
+}
+
+struct Visitor<'a, 'writer> {
+    redactor: &'a Redactor,
+    writer: Writer<'writer>,
+    first: bool,
+    result: fmt::Result,
+}
+
+impl Visit for Visitor<'_, '_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        // the fields `tracing-log` adds to events from the log crate
+        if self.result.is_err() || field.name().starts_with("log.") {
+            return;
+        }
+        let separator = if self.first { "" } else { " " };
+        self.first = false;
+        let value = format!("{value:?}");
+        self.result = match field.name() {
+            "message" => write!(self.writer, "{separator}{}", self.redactor.scrub(&value)),
+            name if self.redactor.is_sensitive(name) => {
+                write!(self.writer, "{separator}{name}={REDACTED}")
+            }
+            name => write!(
+                self.writer,
+                "{separator}{name}={}",
+                self.redactor.scrub(&value)
+            ),
+        };
+    }
+}
+
+// Writes events as JSON objects, a line each, with their fields redacted.
+// Spans show with their fields as `Redactor` formatted them.
+#[derive(Clone, Debug)]
+pub struct JsonFormat {
+    redactor: Redactor,
+}
+
+impl JsonFormat {
+    pub fn new(redactor: Redactor) -> Self {
+        JsonFormat { redactor }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut fields = JsonVisitor {
+            redactor: &self.redactor,
+            fields: Map::new(),
+        };
+        event.record(&mut fields);
+        let spans: Vec<Value> = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let extensions = span.extensions();
+                let fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .map_or("", |fields| fields.fields.as_str());
+                json!({ "name": span.name(), "fields": fields })
+            })
+            .collect();
+        let metadata = event.metadata();
+        let line = json!({
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": fields.fields,
+            "spans": spans,
+        });
+        writeln!(writer, "{line}")
+    }
+}
+
+struct JsonVisitor<'a> {
+    redactor: &'a Redactor,
+    fields: Map<String, Value>,
+}
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        let name = field.name();
+        // as in `Visitor`
+        if name.starts_with("log.") {
+            return;
+        }
+        let value = if name != "message" && self.redactor.is_sensitive(name) {
+            Value::from(REDACTED)
+        } else {
+            value
+        };
+        self.fields.insert(name.to_owned(), value);
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = self.redactor.scrub(value).into_owned();
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{value:?}");
+        self.insert(field, Value::from(self.redactor.scrub(&value).into_owned()));
+    }
+}
@@ -0,0 +1,39 @@
+// Redis, with REDIS_URL in a build with the redis feature, for what the
+// instances of a deployment share and Postgres would be a poor fit for; see
+// `locks` and `relay`. One multiplexed connection serves every command, opened on first
+// use and reconnecting by itself.
+
+use std::sync::Arc;
+
+use redis::aio::{ConnectionManager, PubSub};
+use redis::{Client, RedisError};
+use tokio::sync::OnceCell;
+
+#[derive(Clone)]
+pub struct RedisStore {
+    client: Client,
+    conn: Arc<OnceCell<ConnectionManager>>,
+}
+
+impl RedisStore {
+    // `url` was checked by `Config::from_env`
+    pub fn new(url: &str) -> RedisStore {
+        RedisStore {
+            client: Client::open(url).expect("REDIS_URL is valid"),
+            conn: Arc::default(),
+        }
+    }
+
+    // a connection of its own to subscribe to channels on
+    pub async fn pubsub(&self) -> Result<PubSub, RedisError> {
+        self.client.get_async_pubsub().await
+    }
+
+    // the shared connection, cheap to clone
+    pub async fn conn(&self) -> Result<ConnectionManager, RedisError> {
+        self.conn
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await
+            .cloned()
+    }
+}
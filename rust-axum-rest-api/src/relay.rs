@@ -0,0 +1,196 @@
+// Events across instances: with EVENT_RELAY=true, every instance passes the
+// events it publishes (see `events`) on to the others, so that live feeds
+// like the GraphQL subscriptions see every post and comment behind a load
+// balancer, whichever replica took them.
+//
+// What travels is a notice naming the event, its tenant and the row's id,
+//
+//     {"origin": "8c1f...", "tenant_id": 1, "type": "post_created", "id": 42}
+//
+// which the others load and hand to their listeners; instances ignore their
+// own notices. Notices go through Redis pub/sub with REDIS_URL (see
+// `redis_store`), and Postgres LISTEN/NOTIFY otherwise. Either way they are
+// fire and forget: an instance reconnecting misses what was sent meanwhile.
+
+use std::error::Error;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use sqlx::{Pool, Postgres};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::events::Event;
+use crate::ids::Key;
+#[cfg(feature = "redis")]
+use crate::redis_store::RedisStore;
+use crate::repo;
+use crate::state::AppState;
+use crate::tenant;
+
+const CHANNEL: &str = "events";
+
+// how long to wait before listening again after losing the connection
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Notice {
+    // the instance that published the event
+    origin: Uuid,
+    tenant_id: i32,
+    #[serde(flatten)]
+    event: Published,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Published {
+    PostCreated { id: i32 },
+    CommentAdded { id: i32 },
+}
+
+#[derive(Clone)]
+enum Transport {
+    Postgres(Pool<Postgres>),
+    #[cfg(feature = "redis")]
+    Redis(RedisStore),
+}
+
+// Listen to the other instances' notices and send them this one's, until
+// the process exits. Returns once listening, so that no notice sent after
+// is missed.
+pub async fn start(state: AppState) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let origin = Uuid::new_v4();
+    #[cfg(feature = "redis")]
+    let transport = match &state.redis {
+        Some(store) => Transport::Redis(store.clone()),
+        None => Transport::Postgres(state.pool.clone()),
+    };
+    #[cfg(not(feature = "redis"))]
+    let transport = Transport::Postgres(state.pool.clone());
+
+    match &transport {
+        Transport::Postgres(pool) => {
+            let mut listener = PgListener::connect_with(pool).await?;
+            listener.listen(CHANNEL).await?;
+            let state = state.clone();
+            tokio::spawn(async move {
+                loop {
+                    // reconnects by itself on the next call after an error
+                    match listener.recv().await {
+                        Ok(notification) => receive(&state, origin, notification.payload()).await,
+                        Err(e) => warn!("listening for relayed events failed: {e}"),
+                    }
+                }
+            });
+        }
+        #[cfg(feature = "redis")]
+        Transport::Redis(store) => {
+            let mut pubsub = store.pubsub().await?;
+            pubsub.subscribe(CHANNEL).await?;
+            let (state, store) = (state.clone(), store.clone());
+            tokio::spawn(async move {
+                use futures::StreamExt;
+                loop {
+                    let mut messages = pubsub.into_on_message();
+                    while let Some(message) = messages.next().await {
+                        match message.get_payload::<String>() {
+                            Ok(payload) => receive(&state, origin, &payload).await,
+                            Err(e) => warn!("reading a relayed event failed: {e}"),
+                        }
+                    }
+                    // the connection is gone, subscribe again on a new one
+                    pubsub = loop {
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        match store.pubsub().await {
+                            Ok(mut pubsub) => match pubsub.subscribe(CHANNEL).await {
+                                Ok(()) => break pubsub,
+                                Err(e) => warn!("listening for relayed events failed: {e}"),
+                            },
+                            Err(e) => warn!("listening for relayed events failed: {e}"),
+                        }
+                    };
+                }
+            });
+        }
+    }
+
+    let mut events = state.events.subscribe_local();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok((tenant_id, event)) => {
+                    let event = match event {
+                        Event::PostCreated(post) => Published::PostCreated { id: post.id },
+                        Event::CommentAdded(comment) => Published::CommentAdded { id: comment.id },
+                    };
+                    let notice = Notice {
+                        origin,
+                        tenant_id,
+                        event,
+                    };
+                    if let Err(e) = send(&transport, &notice).await {
+                        error!("relaying an event failed: {e}");
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => warn!("the relay missed {missed} events"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn send(transport: &Transport, notice: &Notice) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let payload = serde_json::to_string(notice)?;
+    match transport {
+        Transport::Postgres(pool) => {
+            sqlx::query!("SELECT pg_notify($1, $2)", CHANNEL, payload)
+                .execute(pool)
+                .await?;
+        }
+        #[cfg(feature = "redis")]
+        Transport::Redis(store) => {
+            let _: i64 = redis::cmd("PUBLISH")
+                .arg(CHANNEL)
+                .arg(payload)
+                .query_async(&mut store.conn().await?)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// hand another instance's event to this one's listeners
+async fn receive(state: &AppState, origin: Uuid, payload: &str) {
+    let notice: Notice = match serde_json::from_str(payload) {
+        Ok(notice) => notice,
+        Err(e) => {
+            warn!("ignoring a relayed event: {e}");
+            return;
+        }
+    };
+    if notice.origin == origin {
+        return;
+    }
+    let event = tenant::within(notice.tenant_id, async {
+        match notice.event {
+            Published::PostCreated { id } => state
+                .posts
+                .get(Key::Serial(id))
+                .await
+                .map(Event::PostCreated),
+            Published::CommentAdded { id } => repo::comments::get(&state.pool, id)
+                .await
+                .map(Event::CommentAdded),
+        }
+    })
+    .await;
+    match event {
+        Ok(event) => state.events.relay(notice.tenant_id, event),
+        Err(e) => warn!("loading a relayed event failed: {e}"),
+    }
+}
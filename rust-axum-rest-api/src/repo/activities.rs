@@ -0,0 +1,83 @@
+// Users' activity feeds, written by `activity::record`.
+
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+
+use crate::models::{Activity, NewActivity};
+use crate::pagination::Page;
+use crate::repo::Timed;
+use crate::tenant;
+
+// Activity storage. Activities are only ever appended, and go away with the
+// post or comment they are about.
+#[async_trait]
+pub trait ActivityRepository: Send + Sync {
+    async fn append(&self, activity: &NewActivity) -> Result<(), sqlx::Error>;
+    // a user's activities, newest first, leaving out those about posts or
+    // comments that aren't published
+    async fn list(&self, user_id: i32, page: Option<Page>) -> Result<Vec<Activity>, sqlx::Error>;
+    async fn count(&self, user_id: i32) -> Result<i64, sqlx::Error>;
+}
+
+pub struct PgActivityRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgActivityRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgActivityRepository { pool }
+    }
+}
+
+#[async_trait]
+impl ActivityRepository for PgActivityRepository {
+    async fn append(&self, activity: &NewActivity) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO activities (user_id, kind, post_id, comment_id, tenant_id) VALUES ($1, $2, $3, $4, $5)",
+            activity.user_id,
+            activity.kind.as_str(),
+            activity.post_id,
+            activity.comment_id,
+            tenant::current()
+        )
+        .execute(&self.pool)
+        .timed("activities::append")
+        .await?;
+        Ok(())
+    }
+
+    async fn list(&self, user_id: i32, page: Option<Page>) -> Result<Vec<Activity>, sqlx::Error> {
+        sqlx::query_as!(
+            Activity,
+            r#"SELECT a.id, a.user_id, a.kind, a.post_id, a.comment_id, a.created_at
+               FROM activities a JOIN posts p ON p.id = a.post_id
+               LEFT JOIN comments c ON c.id = a.comment_id
+               WHERE a.user_id = $1 AND a.tenant_id = $2 AND p.deleted_at IS NULL AND p.status = 'published'
+                 AND (a.comment_id IS NULL OR c.status = 'published')
+               ORDER BY a.id DESC
+               LIMIT $3 OFFSET $4"#,
+            user_id,
+            tenant::current(),
+            page.map(Page::limit),
+            page.map_or(0, Page::offset)
+        )
+        .fetch_all(&self.pool)
+        .timed("activities::list")
+        .await
+    }
+
+    async fn count(&self, user_id: i32) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!"
+               FROM activities a JOIN posts p ON p.id = a.post_id
+               LEFT JOIN comments c ON c.id = a.comment_id
+               WHERE a.user_id = $1 AND a.tenant_id = $2 AND p.deleted_at IS NULL AND p.status = 'published'
+                 AND (a.comment_id IS NULL OR c.status = 'published')"#,
+            user_id,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("activities::count")
+        .await
+    }
+}
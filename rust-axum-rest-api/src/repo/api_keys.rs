@@ -0,0 +1,113 @@
+// API keys are random secrets handed out once; only their SHA-256 hash is
+// stored, together with a short prefix so keys can be told apart in listings.
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+
+use crate::models::ApiKey;
+use crate::repo::Timed;
+use crate::tenant;
+
+pub const KEY_PREFIX: &str = "rk_";
+
+pub(crate) fn hash(key: &str) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Create a key for a user and return it along with the secret, which can't be
+// recovered later.
+pub async fn create(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    name: &str,
+) -> Result<(ApiKey, String), sqlx::Error> {
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    let key = format!("{KEY_PREFIX}{}", hex(&secret));
+    let prefix = key[..KEY_PREFIX.len() + 8].to_owned();
+
+    let api_key = sqlx::query_as!(
+        ApiKey,
+        r#"INSERT INTO api_keys (user_id, name, prefix, key_hash) VALUES ($1, $2, $3, $4)
+           RETURNING id, user_id, name, prefix, revoked_at IS NOT NULL AS "revoked!""#,
+        user_id,
+        name,
+        prefix,
+        hash(&key)
+    )
+    .fetch_one(pool)
+    .timed("api_keys::create")
+    .await?;
+    Ok((api_key, key))
+}
+
+pub async fn list_for_user(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"SELECT id, user_id, name, prefix, revoked_at IS NOT NULL AS "revoked!"
+           FROM api_keys WHERE user_id = $1 ORDER BY id"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .timed("api_keys::list_for_user")
+    .await
+}
+
+// Revoke a key, false when it doesn't exist or was already revoked.
+pub async fn revoke(pool: &Pool<Postgres>, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL",
+        id
+    )
+    .execute(pool)
+    .timed("api_keys::revoke")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Whether the key is live and belongs to an admin; records the use if so.
+pub async fn is_admin_key(pool: &Pool<Postgres>, key: &str) -> Result<bool, sqlx::Error> {
+    Ok(admin_key_owner(pool, key).await?.is_some())
+}
+
+// The user of the current tenant a live key belongs to, recording the use.
+pub async fn key_owner(pool: &Pool<Postgres>, key: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "UPDATE api_keys SET last_used_at = NOW()
+         FROM users
+         WHERE api_keys.key_hash = $1 AND api_keys.revoked_at IS NULL
+           AND users.id = api_keys.user_id AND users.tenant_id = $2
+         RETURNING api_keys.user_id",
+        hash(key),
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("api_keys::key_owner")
+    .await
+}
+
+// The admin a live key belongs to, recording the use; `None` for unknown,
+// revoked and non-admin keys, and for admins of another tenant.
+pub async fn admin_key_owner(pool: &Pool<Postgres>, key: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "UPDATE api_keys SET last_used_at = NOW()
+         FROM users
+         WHERE api_keys.key_hash = $1 AND api_keys.revoked_at IS NULL
+           AND users.id = api_keys.user_id AND users.role = 'admin'
+           AND users.tenant_id = $2
+         RETURNING api_keys.user_id",
+        hash(key),
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("api_keys::admin_key_owner")
+    .await
+}
@@ -0,0 +1,71 @@
+// Archived posts: the stub left in posts has the status `archived` and an
+// empty body, the body waits in archived_posts.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::repo::Timed;
+use crate::tenant;
+
+// Archive up to `limit` published posts, of every tenant, created before
+// `cutoff`, returning how many were.
+pub async fn archive_before(
+    pool: &Pool<Postgres>,
+    cutoff: DateTime<Utc>,
+    limit: i64,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "WITH old AS (
+             SELECT id, tenant_id, body FROM posts
+             WHERE status = 'published' AND deleted_at IS NULL AND created_at < $1
+             ORDER BY id LIMIT $2
+             FOR UPDATE SKIP LOCKED
+         ), archived AS (
+             INSERT INTO archived_posts (post_id, tenant_id, body)
+             SELECT id, tenant_id, body FROM old
+             RETURNING post_id
+         )
+         UPDATE posts SET status = 'archived', body = ''
+         FROM archived WHERE posts.id = archived.post_id",
+        cutoff,
+        limit
+    )
+    .execute(pool)
+    .timed("archive::archive_before")
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// When a post of the current tenant was archived, None unless it is.
+pub async fn archived_at(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT archived_at FROM archived_posts WHERE post_id = $1 AND tenant_id = $2",
+        post_id,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("archive::archived_at")
+    .await
+}
+
+// Put an archived post of the current tenant back, published; false unless
+// it was archived.
+pub async fn unarchive(pool: &Pool<Postgres>, post_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "WITH restored AS (
+             DELETE FROM archived_posts WHERE post_id = $1 AND tenant_id = $2
+             RETURNING post_id, body
+         )
+         UPDATE posts SET status = 'published', body = restored.body
+         FROM restored WHERE posts.id = restored.post_id",
+        post_id,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("archive::unarchive")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
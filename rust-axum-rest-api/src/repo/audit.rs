@@ -0,0 +1,117 @@
+// The audit log, written by `audit::log` and read by admins.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use sqlx::types::Json;
+use sqlx::{Pool, Postgres};
+
+use crate::models::{AuditEntry, FieldChange, NewAuditEntry};
+use crate::pagination::Page;
+use crate::repo::Timed;
+use crate::tenant;
+
+// Which entries `AuditRepository::list` returns, taken from the query string
+// of GET /admin/audit, e.g. `?entity_type=post&entity_id=3` or
+// `?actor=admin token`. Unset fields match everything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditFilter {
+    pub entity_type: Option<String>,
+    pub entity_id: Option<String>,
+    pub actor: Option<String>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        fn matches(wanted: &Option<String>, value: Option<&str>) -> bool {
+            wanted.as_deref().is_none_or(|wanted| value == Some(wanted))
+        }
+        matches(&self.entity_type, entry.entity_type.as_deref())
+            && matches(&self.entity_id, entry.entity_id.as_deref())
+            && matches(&self.actor, Some(&entry.actor))
+    }
+}
+
+// Audit log storage. Entries are only ever appended.
+#[async_trait]
+pub trait AuditRepository: Send + Sync {
+    async fn append(&self, entries: &[NewAuditEntry]) -> Result<(), sqlx::Error>;
+    // the matching entries, newest first
+    async fn list(
+        &self,
+        filter: &AuditFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<AuditEntry>, sqlx::Error>;
+    async fn count(&self, filter: &AuditFilter) -> Result<i64, sqlx::Error>;
+}
+
+pub struct PgAuditRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgAuditRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgAuditRepository { pool }
+    }
+}
+
+#[async_trait]
+impl AuditRepository for PgAuditRepository {
+    async fn append(&self, entries: &[NewAuditEntry]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for entry in entries {
+            sqlx::query!(
+                "INSERT INTO audit_log (actor, impersonator, action, entity_type, entity_id, before, after, changes, ip, country, region, tenant_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+                entry.actor,
+                entry.impersonator,
+                entry.action,
+                entry.entity_type,
+                entry.entity_id,
+                entry.before,
+                entry.after,
+                entry.changes.as_ref().map(Json) as Option<Json<&Vec<FieldChange>>>,
+                entry.ip,
+                entry.country,
+                entry.region,
+                tenant::current()
+            )
+            .execute(&mut *tx)
+            .timed("audit::append")
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn list(
+        &self,
+        filter: &AuditFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<AuditEntry>, sqlx::Error> {
+        sqlx::query_as!(
+            AuditEntry,
+            r#"SELECT id, actor, impersonator, action, entity_type, entity_id, before, after, changes AS "changes: Json<Vec<FieldChange>>", ip, country, region, created_at FROM audit_log WHERE ($1::text IS NULL OR entity_type = $1) AND ($2::text IS NULL OR entity_id = $2) AND ($3::text IS NULL OR actor = $3) AND tenant_id = $6 ORDER BY id DESC LIMIT $4 OFFSET $5"#,
+            filter.entity_type,
+            filter.entity_id,
+            filter.actor,
+            page.map(Page::limit),
+            page.map_or(0, Page::offset),
+            tenant::current()
+        )
+        .fetch_all(&self.pool)
+        .timed("audit::list")
+        .await
+    }
+
+    async fn count(&self, filter: &AuditFilter) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM audit_log WHERE ($1::text IS NULL OR entity_type = $1) AND ($2::text IS NULL OR entity_id = $2) AND ($3::text IS NULL OR actor = $3) AND tenant_id = $4"#,
+            filter.entity_type,
+            filter.entity_id,
+            filter.actor,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("audit::count")
+        .await
+    }
+}
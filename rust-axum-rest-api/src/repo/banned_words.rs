@@ -0,0 +1,91 @@
+// The content filter's word list, see `content_filter`.
+
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+
+use crate::models::{BannedWord, CreateBannedWord, FilterAction};
+use crate::repo::Timed;
+use crate::tenant;
+
+// Banned word storage as seen by the handlers. Words are stored in the
+// normalized form the filter compares against.
+#[async_trait]
+pub trait BannedWordRepository: Send + Sync {
+    async fn list(&self) -> Result<Vec<BannedWord>, sqlx::Error>;
+    // add a word, or change the action of one already listed
+    async fn add(&self, word: &CreateBannedWord) -> Result<BannedWord, sqlx::Error>;
+    // false when the word wasn't listed
+    async fn remove(&self, word: &str) -> Result<bool, sqlx::Error>;
+}
+
+pub struct PgBannedWordRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgBannedWordRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgBannedWordRepository { pool }
+    }
+}
+
+struct BannedWordRow {
+    word: String,
+    action: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<BannedWordRow> for BannedWord {
+    fn from(row: BannedWordRow) -> Self {
+        BannedWord {
+            word: row.word,
+            // the column's check constraint only allows the two actions
+            action: if row.action == FilterAction::Reject.as_str() {
+                FilterAction::Reject
+            } else {
+                FilterAction::Flag
+            },
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[async_trait]
+impl BannedWordRepository for PgBannedWordRepository {
+    async fn list(&self) -> Result<Vec<BannedWord>, sqlx::Error> {
+        let rows = sqlx::query_as!(
+            BannedWordRow,
+            "SELECT word, action, created_at FROM banned_words WHERE tenant_id = $1 ORDER BY word",
+            tenant::current()
+        )
+        .fetch_all(&self.pool)
+        .timed("banned_words::list")
+        .await?;
+        Ok(rows.into_iter().map(BannedWord::from).collect())
+    }
+
+    async fn add(&self, word: &CreateBannedWord) -> Result<BannedWord, sqlx::Error> {
+        let row = sqlx::query_as!(
+            BannedWordRow,
+            "INSERT INTO banned_words (word, action, tenant_id) VALUES ($1, $2, $3) ON CONFLICT (tenant_id, word) DO UPDATE SET action = EXCLUDED.action RETURNING word, action, created_at",
+            word.word,
+            word.action.as_str(),
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("banned_words::add")
+        .await?;
+        Ok(row.into())
+    }
+
+    async fn remove(&self, word: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM banned_words WHERE word = $1 AND tenant_id = $2",
+            word,
+            tenant::current()
+        )
+        .execute(&self.pool)
+        .timed("banned_words::remove")
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
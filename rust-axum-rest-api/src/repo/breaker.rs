@@ -0,0 +1,247 @@
+// A circuit breaker around the database. After DB_BREAKER_FAILURES queries
+// in a row failed for the database being unwell (unreachable, out of
+// connections, shutting down, the query cancelled for running too long, see
+// `is_unavailable`) the breaker opens: for DB_BREAKER_OPEN_SECS every query
+// fails at once with `Open`, which handlers answer with a 503 and a
+// Retry-After (see `error`), instead of piling up on the pool. Then it
+// half-opens and lets DB_BREAKER_PROBE_PERCENT of the queries through as
+// probes, failing the others fast still; a probe that succeeds closes it
+// again, one that fails opens it for another while. A query that fails for
+// itself, a missing row or a violated constraint, counts as a success.
+//
+//     DB_BREAKER_FAILURES         failures in a row that open it, 5 by
+//                                 default, 0 for never
+//     DB_BREAKER_OPEN_SECS        how long it stays open, 30 by default
+//     DB_BREAKER_PROBE_PERCENT    queries let through half-open, 10 by
+//                                 default
+//
+// Every `timed` query goes through it. Like the slow query counts (see
+// `slow`) it is per process, the repository functions being handed nothing
+// but a pool; its state is exported with the other metrics (see `metrics`).
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+// how long queries not picked as probes are told to wait
+const PROBE_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakerConfig {
+    // 0 to never open
+    pub failures: u32,
+    pub open_for: Duration,
+    // 1 to 100
+    pub probe_percent: u32,
+}
+
+impl BreakerConfig {
+    pub const DEFAULT: BreakerConfig = BreakerConfig {
+        failures: 5,
+        open_for: Duration::from_secs(30),
+        probe_percent: 10,
+    };
+
+    pub fn from_env() -> Result<BreakerConfig, String> {
+        let number = |name: &str, default: u64| match std::env::var(name) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid {name} {value:?}, expected a number")),
+            Err(_) => Ok(default),
+        };
+        let defaults = BreakerConfig::DEFAULT;
+        let failures = number("DB_BREAKER_FAILURES", defaults.failures.into())?;
+        let failures = u32::try_from(failures)
+            .map_err(|_| format!("DB_BREAKER_FAILURES {failures} is too large"))?;
+        let open_for = match number("DB_BREAKER_OPEN_SECS", defaults.open_for.as_secs())? {
+            0 => return Err(String::from("DB_BREAKER_OPEN_SECS must be at least 1")),
+            secs => Duration::from_secs(secs),
+        };
+        let probe_percent = number("DB_BREAKER_PROBE_PERCENT", defaults.probe_percent.into())?;
+        if !(1..=100).contains(&probe_percent) {
+            return Err(format!(
+                "DB_BREAKER_PROBE_PERCENT {probe_percent} must be between 1 and 100"
+            ));
+        }
+        Ok(BreakerConfig {
+            failures,
+            open_for,
+            probe_percent: probe_percent as u32,
+        })
+    }
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig::DEFAULT
+    }
+}
+
+// A query failed fast, the breaker being open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Open {
+    pub retry_after: Duration,
+}
+
+impl Open {
+    // for Retry-After, rounded up
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+impl fmt::Display for Open {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the database circuit breaker is open")
+    }
+}
+
+impl std::error::Error for Open {}
+
+// as the database being out of reach, which it is as far as callers go
+impl From<Open> for sqlx::Error {
+    fn from(open: Open) -> Self {
+        sqlx::Error::Io(io::Error::new(io::ErrorKind::ConnectionRefused, open))
+    }
+}
+
+// The breaker behind `err`, if it failed fast.
+pub fn open(err: &sqlx::Error) -> Option<&Open> {
+    match err {
+        sqlx::Error::Io(io) => io.get_ref()?.downcast_ref(),
+        _ => None,
+    }
+}
+
+// Whether `err` says the database is unwell, rather than the query wrong.
+pub fn is_unavailable(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_)
+        | sqlx::Error::Protocol(_)
+        | sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::WorkerCrashed => true,
+        // the SQLSTATE classes of connection exceptions, insufficient
+        // resources, operator intervention (which cancelled statements are
+        // too) and system errors
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            ["08", "53", "57", "58"]
+                .iter()
+                .any(|class| code.starts_with(class))
+        }),
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // with the failures in a row so far
+    Closed(u32),
+    Open(Instant),
+    HalfOpen,
+}
+
+pub struct Breaker {
+    config: Mutex<BreakerConfig>,
+    state: Mutex<State>,
+    // queries failed fast so far
+    rejected: AtomicU64,
+}
+
+static BREAKER: Breaker = Breaker::new(BreakerConfig::DEFAULT);
+
+// The process's breaker, the one `timed` queries go through.
+pub fn global() -> &'static Breaker {
+    &BREAKER
+}
+
+impl Breaker {
+    pub const fn new(config: BreakerConfig) -> Self {
+        Breaker {
+            config: Mutex::new(config),
+            state: Mutex::new(State::Closed(0)),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    // Apply `config` from now on, closed again.
+    pub fn configure(&self, config: BreakerConfig) {
+        *self.config.lock().expect("breaker lock") = config;
+        *self.state.lock().expect("breaker lock") = State::Closed(0);
+    }
+
+    // Whether a query may go ahead.
+    pub fn check(&self) -> Result<(), Open> {
+        let config = *self.config.lock().expect("breaker lock");
+        let mut state = self.state.lock().expect("breaker lock");
+        let refused = match *state {
+            State::Closed(_) => return Ok(()),
+            State::Open(until) => {
+                let now = Instant::now();
+                if now < until {
+                    until - now
+                } else {
+                    info!("database circuit breaker half-open");
+                    *state = State::HalfOpen;
+                    PROBE_RETRY_AFTER
+                }
+            }
+            State::HalfOpen => PROBE_RETRY_AFTER,
+        };
+        if *state == State::HalfOpen && rand::random_range(0..100) < config.probe_percent {
+            return Ok(());
+        }
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        Err(Open {
+            retry_after: refused,
+        })
+    }
+
+    // Count how a query that went ahead ended.
+    pub fn record<T>(&self, result: &Result<T, sqlx::Error>) {
+        let config = *self.config.lock().expect("breaker lock");
+        if config.failures == 0 {
+            return;
+        }
+        let failed = result.as_ref().is_err_and(is_unavailable);
+        let mut state = self.state.lock().expect("breaker lock");
+        *state = match (*state, failed) {
+            (State::Closed(_), false) => State::Closed(0),
+            (State::Closed(failures), true) if failures + 1 < config.failures => {
+                State::Closed(failures + 1)
+            }
+            (State::Closed(_) | State::HalfOpen, true) => {
+                warn!(
+                    "database circuit breaker open for {}s",
+                    config.open_for.as_secs()
+                );
+                State::Open(Instant::now() + config.open_for)
+            }
+            (State::HalfOpen, false) => {
+                info!("database circuit breaker closed");
+                State::Closed(0)
+            }
+            // queries that went ahead before it opened
+            (State::Open(until), _) => State::Open(until),
+        };
+    }
+
+    // "closed", "open" or "half_open"
+    pub fn state(&self) -> &'static str {
+        match *self.state.lock().expect("breaker lock") {
+            State::Closed(_) => "closed",
+            State::Open(until) if Instant::now() < until => "open",
+            State::Open(_) | State::HalfOpen => "half_open",
+        }
+    }
+
+    // The queries failed fast so far.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,114 @@
+// Comments on posts, within the current tenant.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::{Comment, CreateComment, PostStatus};
+use crate::pagination::Page;
+use crate::repo::Timed;
+use crate::tenant;
+
+pub async fn get(pool: &Pool<Postgres>, id: i32) -> Result<Comment, sqlx::Error> {
+    sqlx::query_as!(
+        Comment,
+        r#"SELECT id, post_id, parent_comment_id, user_id, body, status, cardinality(path) AS "depth!", created_at
+           FROM comments WHERE id = $1 AND tenant_id = $2"#,
+        id,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("comments::get")
+    .await
+}
+
+// Comment on a post, below the parent comment if there is one.
+pub async fn create(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+    comment: &CreateComment,
+    status: PostStatus,
+) -> Result<Comment, sqlx::Error> {
+    sqlx::query_as!(
+        Comment,
+        r#"INSERT INTO comments (post_id, parent_comment_id, path, user_id, body, status, tenant_id)
+           VALUES ($1, $2, COALESCE((SELECT path || id FROM comments WHERE id = $2), '{}'), $3, $4, $5, $6)
+           RETURNING id, post_id, parent_comment_id, user_id, body, status, cardinality(path) AS "depth!", created_at"#,
+        post_id,
+        comment.parent_comment_id,
+        comment.user_id,
+        comment.body,
+        status.as_str(),
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("comments::create")
+    .await
+}
+
+// The published comments of a post down to `max_depth`, in tree order:
+// every comment is followed by its replies, siblings oldest first. Replies
+// to held back comments are left out with them.
+pub async fn thread(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+    max_depth: i32,
+) -> Result<Vec<Comment>, sqlx::Error> {
+    sqlx::query_as!(
+        Comment,
+        r#"SELECT id, post_id, parent_comment_id, user_id, body, status, cardinality(path) AS "depth!", created_at
+           FROM comments c
+           WHERE post_id = $1 AND tenant_id = $2 AND cardinality(path) <= $3
+             AND NOT EXISTS (
+                 SELECT 1 FROM comments held
+                 WHERE held.id = ANY(c.path || c.id) AND held.status <> 'published'
+             )
+           ORDER BY path || id"#,
+        post_id,
+        tenant::current(),
+        max_depth
+    )
+    .fetch_all(pool)
+    .timed("comments::thread")
+    .await
+}
+
+// the comments with a status, oldest first, for the moderators
+pub async fn queue(
+    pool: &Pool<Postgres>,
+    status: PostStatus,
+    page: Option<Page>,
+) -> Result<Vec<Comment>, sqlx::Error> {
+    sqlx::query_as!(
+        Comment,
+        r#"SELECT id, post_id, parent_comment_id, user_id, body, status, cardinality(path) AS "depth!", created_at
+           FROM comments WHERE status = $1 AND tenant_id = $2
+           ORDER BY created_at, id LIMIT $3 OFFSET $4"#,
+        status.as_str(),
+        tenant::current(),
+        page.map(Page::limit),
+        page.map_or(0, Page::offset)
+    )
+    .fetch_all(pool)
+    .timed("comments::queue")
+    .await
+}
+
+pub async fn moderate(
+    pool: &Pool<Postgres>,
+    id: i32,
+    status: PostStatus,
+    reason: Option<&str>,
+) -> Result<Comment, sqlx::Error> {
+    sqlx::query_as!(
+        Comment,
+        r#"UPDATE comments SET status = $1, moderation_reason = $2, moderated_at = NOW()
+           WHERE id = $3 AND tenant_id = $4
+           RETURNING id, post_id, parent_comment_id, user_id, body, status, cardinality(path) AS "depth!", created_at"#,
+        status.as_str(),
+        reason,
+        id,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("comments::moderate")
+    .await
+}
@@ -0,0 +1,215 @@
+// Users' emails encrypted by the application, so that a copy of the
+// database doesn't give the addresses away. With EMAIL_ENCRYPTION_KEY set
+// (32 bytes, base64; hand it over from a KMS through the environment),
+// `users.email` holds the address sealed with AES-256-GCM under a random
+// nonce, as `enc:v1:<base64 of nonce and ciphertext>`, and
+// `users.email_hash` an HMAC-SHA256 of it under a key derived from the same
+// one. The ciphertext differs every time, so lookups and uniqueness go by
+// the hash.
+//
+// Rows written before the key was set keep the address in the clear with no
+// hash, and are read as they are: `app encrypt-emails` seals them, a batch
+// at a time, after which nothing is left in the clear. Until then, a user
+// signing up with the address of such a row is refused like any duplicate
+// (see `users`). Without the key, addresses are stored in the clear as
+// before. The invited addresses of `org_invitations` are sealed the same
+// way, with no hash, as they are only looked up by token.
+//
+// Like the other repository settings (see `breaker`) the key is per
+// process, set once at startup with `configure`.
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{Pool, Postgres};
+
+use crate::models::User;
+use crate::repo::api_keys::hex;
+use crate::repo::Timed;
+
+const PREFIX: &str = "enc:v1:";
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+pub struct EmailKey {
+    cipher: Aes256Gcm,
+    hash_key: [u8; 32],
+}
+
+impl EmailKey {
+    // from the base64 of 32 random bytes
+    pub fn from_base64(encoded: &str) -> Result<EmailKey, String> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("EMAIL_ENCRYPTION_KEY isn't base64: {e}"))?;
+        let key: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| String::from("EMAIL_ENCRYPTION_KEY must be 32 bytes"))?;
+        // the hash key is derived, so that one key is all there is to keep
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key).expect("HMAC takes any key");
+        mac.update(b"email lookup");
+        Ok(EmailKey {
+            cipher: Aes256Gcm::new(&key.into()),
+            hash_key: mac.finalize().into_bytes().into(),
+        })
+    }
+
+    // EMAIL_ENCRYPTION_KEY, if set
+    pub fn from_env() -> Result<Option<EmailKey>, String> {
+        match std::env::var("EMAIL_ENCRYPTION_KEY") {
+            Ok(key) if !key.is_empty() => EmailKey::from_base64(&key).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl fmt::Debug for EmailKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EmailKey(..)")
+    }
+}
+
+static KEY: RwLock<Option<Arc<EmailKey>>> = RwLock::new(None);
+
+// Seal the emails written from now on with `key`, and open those read.
+pub fn configure(key: EmailKey) {
+    *KEY.write().expect("email key lock") = Some(Arc::new(key));
+}
+
+fn key() -> Option<Arc<EmailKey>> {
+    KEY.read().expect("email key lock").clone()
+}
+
+// whether emails are sealed, there being a key
+pub fn enabled() -> bool {
+    key().is_some()
+}
+
+// An email as it goes into `users`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sealed {
+    // for `email`
+    pub stored: String,
+    // for `email_hash`, None without a key
+    pub hash: Option<String>,
+}
+
+pub fn seal(email: &str) -> Sealed {
+    let Some(key) = key() else {
+        return Sealed {
+            stored: email.to_owned(),
+            hash: None,
+        };
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher
+        .encrypt(&nonce, email.as_bytes())
+        .expect("AES-GCM encrypts any address");
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Sealed {
+        stored: format!("{PREFIX}{}", STANDARD.encode(sealed)),
+        hash: Some(hash_with(&key, email)),
+    }
+}
+
+// What `email_hash` holds for `email`, to look it up by; None without a key.
+pub fn hash(email: &str) -> Option<String> {
+    key().map(|key| hash_with(&key, email))
+}
+
+fn hash_with(key: &EmailKey, email: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key.hash_key).expect("HMAC takes any key");
+    mac.update(email.as_bytes());
+    hex(&mac.finalize().into_bytes())
+}
+
+// The address in `email` as stored, sealed or not.
+pub fn open(stored: &str) -> Result<String, sqlx::Error> {
+    let Some(encoded) = stored.strip_prefix(PREFIX) else {
+        return Ok(stored.to_owned());
+    };
+    let key = key().ok_or_else(|| decode_error("EMAIL_ENCRYPTION_KEY isn't set"))?;
+    let sealed = STANDARD
+        .decode(encoded)
+        .map_err(|_| decode_error("the email isn't base64"))?;
+    if sealed.len() < NONCE_LEN {
+        return Err(decode_error("the email is too short"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let email = key
+        .cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| decode_error("the email doesn't open with EMAIL_ENCRYPTION_KEY"))?;
+    String::from_utf8(email).map_err(|_| decode_error("the email isn't UTF-8"))
+}
+
+// `user` with their email opened.
+pub fn open_user(mut user: User) -> Result<User, sqlx::Error> {
+    user.email = open(&user.email)?;
+    Ok(user)
+}
+
+fn decode_error(message: &str) -> sqlx::Error {
+    sqlx::Error::ColumnDecode {
+        index: String::from("email"),
+        source: message.into(),
+    }
+}
+
+// Seal up to `limit` of the emails still in the clear, of any tenant, the
+// users' and then the invitations', returning how many were. None are
+// without a key.
+pub async fn seal_clear(pool: &Pool<Postgres>, limit: i64) -> Result<u64, sqlx::Error> {
+    if !enabled() {
+        return Ok(0);
+    }
+    let mut tx = pool.begin().await?;
+    let users = sqlx::query!(
+        "SELECT id, email FROM users WHERE email_hash IS NULL ORDER BY id LIMIT $1 FOR UPDATE SKIP LOCKED",
+        limit
+    )
+    .fetch_all(&mut *tx)
+    .timed("emails::seal_clear")
+    .await?;
+    for user in &users {
+        let email = seal(&user.email);
+        sqlx::query!(
+            "UPDATE users SET email = $1, email_hash = $2 WHERE id = $3",
+            email.stored,
+            email.hash,
+            user.id
+        )
+        .execute(&mut *tx)
+        .timed("emails::seal_clear")
+        .await?;
+    }
+    let invitations = sqlx::query!(
+        "SELECT id, email FROM org_invitations WHERE email NOT LIKE $1 || '%' ORDER BY id LIMIT $2 FOR UPDATE SKIP LOCKED",
+        PREFIX,
+        limit - users.len() as i64
+    )
+    .fetch_all(&mut *tx)
+    .timed("emails::seal_clear")
+    .await?;
+    for invitation in &invitations {
+        sqlx::query!(
+            "UPDATE org_invitations SET email = $1 WHERE id = $2",
+            seal(&invitation.email).stored,
+            invitation.id
+        )
+        .execute(&mut *tx)
+        .timed("emails::seal_clear")
+        .await?;
+    }
+    tx.commit().await?;
+    Ok((users.len() + invitations.len()) as u64)
+}
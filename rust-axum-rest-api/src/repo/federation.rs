@@ -0,0 +1,243 @@
+// Federation's storage: actor keys, remote followers and the delivery
+// queue. Followers are kept per tenant; keys and deliveries belong to users,
+// whose ids are unique across tenants, and the delivery worker runs outside
+// any request, so those aren't scoped.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+
+use crate::repo::Timed;
+use crate::tenant;
+
+pub struct ActorKeys {
+    pub public_key_pem: String,
+    pub private_key_pem: String,
+}
+
+// A published post as federation shows it.
+pub struct Note {
+    pub public_id: String,
+    pub title: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct Delivery {
+    pub id: i64,
+    pub sender_id: i32,
+    pub inbox: String,
+    pub activity: Value,
+    pub attempts: i32,
+}
+
+pub async fn keys(pool: &Pool<Postgres>, user_id: i32) -> Result<Option<ActorKeys>, sqlx::Error> {
+    sqlx::query_as!(
+        ActorKeys,
+        "SELECT public_key_pem, private_key_pem FROM actor_keys WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .timed("federation::keys")
+    .await
+}
+
+// Store a user's keys unless they have some already, returning the ones
+// they end up with.
+pub async fn store_keys(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    keys: &ActorKeys,
+) -> Result<ActorKeys, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO actor_keys (user_id, public_key_pem, private_key_pem) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        user_id,
+        keys.public_key_pem,
+        keys.private_key_pem
+    )
+    .execute(pool)
+    .timed("federation::store_keys")
+    .await?;
+    sqlx::query_as!(
+        ActorKeys,
+        "SELECT public_key_pem, private_key_pem FROM actor_keys WHERE user_id = $1",
+        user_id
+    )
+    .fetch_one(pool)
+    .timed("federation::store_keys")
+    .await
+}
+
+pub async fn add_follower(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    actor: &str,
+    inbox: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO remote_followers (user_id, actor, inbox, tenant_id) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (user_id, actor) DO UPDATE SET inbox = EXCLUDED.inbox",
+        user_id,
+        actor,
+        inbox,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("federation::add_follower")
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_follower(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    actor: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM remote_followers WHERE user_id = $1 AND actor = $2 AND tenant_id = $3",
+        user_id,
+        actor,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("federation::remove_follower")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// where to deliver a user's activities, each inbox once
+pub async fn follower_inboxes(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT DISTINCT inbox FROM remote_followers WHERE user_id = $1 AND tenant_id = $2 ORDER BY inbox",
+        user_id,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("federation::follower_inboxes")
+    .await
+}
+
+pub async fn count_followers(pool: &Pool<Postgres>, user_id: i32) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM remote_followers WHERE user_id = $1 AND tenant_id = $2"#,
+        user_id,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("federation::count_followers")
+    .await
+}
+
+// A user's latest published posts, newest first.
+pub async fn notes(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    limit: i64,
+) -> Result<Vec<Note>, sqlx::Error> {
+    sqlx::query_as!(
+        Note,
+        "SELECT public_id, title, body, created_at FROM posts
+         WHERE user_id = $1 AND tenant_id = $2 AND deleted_at IS NULL AND status = 'published'
+         ORDER BY created_at DESC, id DESC LIMIT $3",
+        user_id,
+        tenant::current(),
+        limit
+    )
+    .fetch_all(pool)
+    .timed("federation::notes")
+    .await
+}
+
+pub async fn count_notes(pool: &Pool<Postgres>, user_id: i32) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM posts
+           WHERE user_id = $1 AND tenant_id = $2 AND deleted_at IS NULL AND status = 'published'"#,
+        user_id,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("federation::count_notes")
+    .await
+}
+
+// Queue an activity of `sender_id` for each of `inboxes`.
+pub async fn enqueue(
+    pool: &Pool<Postgres>,
+    sender_id: i32,
+    inboxes: &[String],
+    activity: &Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO deliveries (sender_id, inbox, activity) SELECT $1, UNNEST($2::text[]), $3",
+        sender_id,
+        inboxes,
+        activity
+    )
+    .execute(pool)
+    .timed("federation::enqueue")
+    .await?;
+    Ok(())
+}
+
+// Take up to `limit` deliveries that are due, oldest first. They are leased
+// for `lease_secs`, so that other workers skip them meanwhile and they come
+// back if this one dies before reporting the outcome.
+pub async fn claim_due(
+    pool: &Pool<Postgres>,
+    limit: i64,
+    lease_secs: f64,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        "UPDATE deliveries SET next_attempt_at = NOW() + make_interval(secs => $2)
+         WHERE id IN (
+             SELECT id FROM deliveries
+             WHERE delivered_at IS NULL AND failed_at IS NULL AND next_attempt_at <= NOW()
+             ORDER BY id LIMIT $1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING id, sender_id, inbox, activity, attempts",
+        limit,
+        lease_secs
+    )
+    .fetch_all(pool)
+    .timed("federation::claim_due")
+    .await
+}
+
+pub async fn delivered(pool: &Pool<Postgres>, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE deliveries SET delivered_at = NOW(), attempts = attempts + 1, last_error = NULL WHERE id = $1",
+        id
+    )
+    .execute(pool)
+    .timed("federation::delivered")
+    .await?;
+    Ok(())
+}
+
+// Record a failed attempt, to be retried at `retry_at`, or given up on when
+// that is None.
+pub async fn failed(
+    pool: &Pool<Postgres>,
+    id: i64,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE deliveries SET attempts = attempts + 1, last_error = $2,
+             next_attempt_at = COALESCE($3, next_attempt_at),
+             failed_at = CASE WHEN $3::timestamptz IS NULL THEN NOW() END
+         WHERE id = $1",
+        id,
+        error,
+        retry_at
+    )
+    .execute(pool)
+    .timed("federation::failed")
+    .await?;
+    Ok(())
+}
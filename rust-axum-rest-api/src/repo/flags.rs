@@ -0,0 +1,195 @@
+// Feature flags and their per-user overrides, see `flags`.
+
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+
+use crate::models::{CreateFeatureFlag, FeatureFlag, FlagOverride, FlagRule, UpdateFeatureFlag};
+use crate::repo::Timed;
+use crate::tenant;
+
+#[async_trait]
+pub trait FlagRepository: Send + Sync {
+    async fn list(&self) -> Result<Vec<FeatureFlag>, sqlx::Error>;
+    async fn get(&self, key: &str) -> Result<FeatureFlag, sqlx::Error>;
+    async fn create(&self, flag: &CreateFeatureFlag) -> Result<FeatureFlag, sqlx::Error>;
+    async fn update(&self, key: &str, flag: &UpdateFeatureFlag)
+        -> Result<FeatureFlag, sqlx::Error>;
+    // false when there was no such flag
+    async fn delete(&self, key: &str) -> Result<bool, sqlx::Error>;
+    async fn overrides(&self, key: &str) -> Result<Vec<FlagOverride>, sqlx::Error>;
+    async fn set_override(
+        &self,
+        key: &str,
+        user_id: i32,
+        enabled: bool,
+    ) -> Result<FlagOverride, sqlx::Error>;
+    // false when the user had no override
+    async fn remove_override(&self, key: &str, user_id: i32) -> Result<bool, sqlx::Error>;
+    // how a flag is decided for a user, None for unknown flags
+    async fn rule(&self, key: &str, user_id: Option<i32>) -> Result<Option<FlagRule>, sqlx::Error>;
+    // the same for every flag, by key
+    async fn rules(&self, user_id: Option<i32>) -> Result<Vec<FlagRule>, sqlx::Error>;
+}
+
+pub struct PgFlagRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgFlagRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgFlagRepository { pool }
+    }
+}
+
+#[async_trait]
+impl FlagRepository for PgFlagRepository {
+    async fn list(&self) -> Result<Vec<FeatureFlag>, sqlx::Error> {
+        sqlx::query_as!(
+            FeatureFlag,
+            "SELECT key, description, rollout_percent, created_at, updated_at FROM feature_flags WHERE tenant_id = $1 ORDER BY key",
+            tenant::current()
+        )
+        .fetch_all(&self.pool)
+        .timed("flags::list")
+        .await
+    }
+
+    async fn get(&self, key: &str) -> Result<FeatureFlag, sqlx::Error> {
+        sqlx::query_as!(
+            FeatureFlag,
+            "SELECT key, description, rollout_percent, created_at, updated_at FROM feature_flags WHERE key = $1 AND tenant_id = $2",
+            key,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("flags::get")
+        .await
+    }
+
+    async fn create(&self, flag: &CreateFeatureFlag) -> Result<FeatureFlag, sqlx::Error> {
+        sqlx::query_as!(
+            FeatureFlag,
+            "INSERT INTO feature_flags (key, description, rollout_percent, tenant_id) VALUES ($1, $2, $3, $4)
+             RETURNING key, description, rollout_percent, created_at, updated_at",
+            flag.key,
+            flag.description,
+            flag.rollout_percent,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("flags::create")
+        .await
+    }
+
+    async fn update(
+        &self,
+        key: &str,
+        flag: &UpdateFeatureFlag,
+    ) -> Result<FeatureFlag, sqlx::Error> {
+        sqlx::query_as!(
+            FeatureFlag,
+            "UPDATE feature_flags SET description = $2, rollout_percent = $3, updated_at = NOW()
+             WHERE key = $1 AND tenant_id = $4
+             RETURNING key, description, rollout_percent, created_at, updated_at",
+            key,
+            flag.description,
+            flag.rollout_percent,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("flags::update")
+        .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM feature_flags WHERE key = $1 AND tenant_id = $2",
+            key,
+            tenant::current()
+        )
+        .execute(&self.pool)
+        .timed("flags::delete")
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn overrides(&self, key: &str) -> Result<Vec<FlagOverride>, sqlx::Error> {
+        sqlx::query_as!(
+            FlagOverride,
+            "SELECT flag_key, user_id, enabled FROM feature_flag_overrides WHERE flag_key = $1 AND tenant_id = $2 ORDER BY user_id",
+            key,
+            tenant::current()
+        )
+        .fetch_all(&self.pool)
+        .timed("flags::overrides")
+        .await
+    }
+
+    async fn set_override(
+        &self,
+        key: &str,
+        user_id: i32,
+        enabled: bool,
+    ) -> Result<FlagOverride, sqlx::Error> {
+        sqlx::query_as!(
+            FlagOverride,
+            "INSERT INTO feature_flag_overrides (flag_key, user_id, enabled, tenant_id) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (tenant_id, flag_key, user_id) DO UPDATE SET enabled = EXCLUDED.enabled
+             RETURNING flag_key, user_id, enabled",
+            key,
+            user_id,
+            enabled,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("flags::set_override")
+        .await
+    }
+
+    async fn remove_override(&self, key: &str, user_id: i32) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM feature_flag_overrides WHERE flag_key = $1 AND user_id = $2 AND tenant_id = $3",
+            key,
+            user_id,
+            tenant::current()
+        )
+        .execute(&self.pool)
+        .timed("flags::remove_override")
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn rule(&self, key: &str, user_id: Option<i32>) -> Result<Option<FlagRule>, sqlx::Error> {
+        sqlx::query_as!(
+            FlagRule,
+            r#"SELECT f.key, f.rollout_percent, o.enabled AS "user_override?"
+               FROM feature_flags f
+               LEFT JOIN feature_flag_overrides o
+                   ON o.tenant_id = f.tenant_id AND o.flag_key = f.key AND o.user_id = $2
+               WHERE f.key = $1 AND f.tenant_id = $3"#,
+            key,
+            user_id,
+            tenant::current()
+        )
+        .fetch_optional(&self.pool)
+        .timed("flags::rule")
+        .await
+    }
+
+    async fn rules(&self, user_id: Option<i32>) -> Result<Vec<FlagRule>, sqlx::Error> {
+        sqlx::query_as!(
+            FlagRule,
+            r#"SELECT f.key, f.rollout_percent, o.enabled AS "user_override?"
+               FROM feature_flags f
+               LEFT JOIN feature_flag_overrides o
+                   ON o.tenant_id = f.tenant_id AND o.flag_key = f.key AND o.user_id = $1
+               WHERE f.tenant_id = $2
+               ORDER BY f.key"#,
+            user_id,
+            tenant::current()
+        )
+        .fetch_all(&self.pool)
+        .timed("flags::rules")
+        .await
+    }
+}
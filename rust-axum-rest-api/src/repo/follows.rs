@@ -0,0 +1,101 @@
+// Users following other users of their tenant.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::models::User;
+use crate::repo::{emails, Timed};
+use crate::tenant;
+
+// A post as a digest lists it.
+pub struct DigestPost {
+    pub public_id: String,
+    pub title: String,
+    pub body: String,
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    // posts there are in all, beyond the ones returned
+    pub total: i64,
+}
+
+// Follow a user; false when already following them.
+pub async fn follow(
+    pool: &Pool<Postgres>,
+    follower_id: i32,
+    followee_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "INSERT INTO follows (follower_id, followee_id, tenant_id) VALUES ($1, $2, $3)
+         ON CONFLICT DO NOTHING",
+        follower_id,
+        followee_id,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("follows::follow")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Stop following a user; false when not following them.
+pub async fn unfollow(
+    pool: &Pool<Postgres>,
+    follower_id: i32,
+    followee_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM follows WHERE follower_id = $1 AND followee_id = $2 AND tenant_id = $3",
+        follower_id,
+        followee_id,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("follows::unfollow")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// The users a user follows, by username.
+pub async fn following(pool: &Pool<Postgres>, user_id: i32) -> Result<Vec<User>, sqlx::Error> {
+    let users = sqlx::query_as!(
+        User,
+        "SELECT u.id, u.uuid, u.username, u.email, u.avatar_url, u.posts_count, u.created_at, u.updated_at
+         FROM follows f JOIN users u ON u.id = f.followee_id
+         WHERE f.follower_id = $1 AND f.tenant_id = $2
+         ORDER BY LOWER(u.username)",
+        user_id,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("follows::following")
+    .await?;
+    users.into_iter().map(emails::open_user).collect()
+}
+
+// The latest `limit` posts published since `since` by the users a user
+// follows, newest first. For the digest job, which runs outside any tenant.
+pub async fn posts_since(
+    pool: &Pool<Postgres>,
+    follower_id: i32,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<DigestPost>, sqlx::Error> {
+    sqlx::query_as!(
+        DigestPost,
+        r#"SELECT p.public_id, p.title, p.body, u.username AS author, p.created_at,
+               COUNT(*) OVER () AS "total!"
+           FROM follows f
+           JOIN posts p ON p.user_id = f.followee_id
+           JOIN users u ON u.id = p.user_id
+           WHERE f.follower_id = $1 AND p.created_at > $2
+             AND p.deleted_at IS NULL AND p.status = 'published'
+           ORDER BY p.created_at DESC, p.id DESC
+           LIMIT $3"#,
+        follower_id,
+        since,
+        limit
+    )
+    .fetch_all(pool)
+    .timed("follows::posts_since")
+    .await
+}
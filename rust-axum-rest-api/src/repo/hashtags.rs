@@ -0,0 +1,106 @@
+// Tags of posts and the hashtags behind them, within the current tenant.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::{Post, TrendingTag};
+use crate::pagination::Page;
+use crate::repo::Timed;
+use crate::tenant;
+
+// Make `tags` the tags of a post, creating the ones new to the tenant.
+pub async fn sync(pool: &Pool<Postgres>, post_id: i32, tags: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO tags (tenant_id, name) SELECT $1, UNNEST($2::text[]) ON CONFLICT DO NOTHING",
+        tenant::current(),
+        tags
+    )
+    .execute(&mut *tx)
+    .timed("hashtags::sync")
+    .await?;
+    sqlx::query!(
+        "DELETE FROM post_tags pt USING tags t
+         WHERE t.id = pt.tag_id AND pt.post_id = $1 AND pt.tenant_id = $2 AND NOT (t.name = ANY($3))",
+        post_id,
+        tenant::current(),
+        tags
+    )
+    .execute(&mut *tx)
+    .timed("hashtags::sync")
+    .await?;
+    sqlx::query!(
+        "INSERT INTO post_tags (post_id, tag_id, tenant_id)
+         SELECT $1, id, tenant_id FROM tags WHERE tenant_id = $2 AND name = ANY($3)
+         ON CONFLICT DO NOTHING",
+        post_id,
+        tenant::current(),
+        tags
+    )
+    .execute(&mut *tx)
+    .timed("hashtags::sync")
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// The tags put on the most published posts in the last `hours`, busiest
+// first.
+pub async fn trending(
+    pool: &Pool<Postgres>,
+    hours: i32,
+    limit: i64,
+) -> Result<Vec<TrendingTag>, sqlx::Error> {
+    sqlx::query_as!(
+        TrendingTag,
+        r#"SELECT t.name AS tag, COUNT(*) AS "posts!"
+           FROM post_tags pt JOIN tags t ON t.id = pt.tag_id JOIN posts p ON p.id = pt.post_id
+           WHERE pt.tenant_id = $1 AND pt.created_at > NOW() - make_interval(hours => $2)
+             AND p.deleted_at IS NULL AND p.status = 'published'
+           GROUP BY t.name
+           ORDER BY COUNT(*) DESC, t.name
+           LIMIT $3"#,
+        tenant::current(),
+        hours,
+        limit
+    )
+    .fetch_all(pool)
+    .timed("hashtags::trending")
+    .await
+}
+
+// The published posts with a tag, most recent first.
+pub async fn posts_tagged(
+    pool: &Pool<Postgres>,
+    tag: &str,
+    page: Option<Page>,
+) -> Result<Vec<Post>, sqlx::Error> {
+    sqlx::query_as!(
+        Post,
+        r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at
+           FROM posts
+           WHERE id IN (SELECT pt.post_id FROM post_tags pt JOIN tags t ON t.id = pt.tag_id WHERE t.name = $1 AND t.tenant_id = $2)
+             AND deleted_at IS NULL AND status = 'published' AND tenant_id = $2
+           ORDER BY created_at DESC, id DESC
+           LIMIT $3 OFFSET $4"#,
+        tag,
+        tenant::current(),
+        page.map(Page::limit),
+        page.map_or(0, Page::offset)
+    )
+    .fetch_all(pool)
+    .timed("hashtags::posts_tagged")
+    .await
+}
+
+pub async fn count_tagged(pool: &Pool<Postgres>, tag: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM posts
+           WHERE id IN (SELECT pt.post_id FROM post_tags pt JOIN tags t ON t.id = pt.tag_id WHERE t.name = $1 AND t.tenant_id = $2)
+             AND deleted_at IS NULL AND status = 'published' AND tenant_id = $2"#,
+        tag,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("hashtags::count_tagged")
+    .await
+}
@@ -0,0 +1,47 @@
+// Links between external identity providers' subjects and local users, see
+// `oidc`.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::ExternalIdentity;
+use crate::repo::Timed;
+use crate::tenant;
+
+// the user `subject` of `issuer` acts as
+pub async fn user_for(
+    pool: &Pool<Postgres>,
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT user_id FROM external_identities WHERE tenant_id = $3 AND issuer = $1 AND subject = $2",
+        issuer,
+        subject,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("identities::user_for")
+    .await
+}
+
+// Link `subject` of `issuer` to `user_id`; a subject that is already linked
+// is a unique violation.
+pub async fn link(
+    pool: &Pool<Postgres>,
+    issuer: &str,
+    subject: &str,
+    user_id: i32,
+) -> Result<ExternalIdentity, sqlx::Error> {
+    sqlx::query_as!(
+        ExternalIdentity,
+        "INSERT INTO external_identities (tenant_id, issuer, subject, user_id) VALUES ($4, $1, $2, $3)
+         RETURNING issuer, subject, user_id, created_at",
+        issuer,
+        subject,
+        user_id,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("identities::link")
+    .await
+}
@@ -0,0 +1,65 @@
+// Impersonation tokens: like API keys, random secrets of which only the
+// hash is stored, but bound to the admin who asked for them and valid for a
+// few minutes only.
+
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use sqlx::{PgExecutor, Pool, Postgres};
+
+use crate::repo::api_keys::{hash, hex};
+use crate::repo::Timed;
+use crate::tenant;
+
+pub const TOKEN_PREFIX: &str = "imp_";
+
+// Create a token acting as `user_id` for `ttl`, returning the secret and
+// when it expires.
+pub async fn create(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    impersonator: &str,
+    ttl: Duration,
+) -> Result<(String, DateTime<Utc>), sqlx::Error> {
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    let token = format!("{TOKEN_PREFIX}{}", hex(&secret));
+    let expires_at = Utc::now() + ttl;
+    sqlx::query!(
+        "INSERT INTO impersonation_tokens (token_hash, user_id, impersonator, expires_at) VALUES ($1, $2, $3, $4)",
+        hash(&token),
+        user_id,
+        impersonator,
+        expires_at
+    )
+    .execute(pool)
+    .timed("impersonation::create")
+    .await?;
+    Ok((token, expires_at))
+}
+
+// The user a live token acts as and who is impersonating them, as long as
+// the user belongs to the current tenant.
+pub async fn resolve(
+    pool: &Pool<Postgres>,
+    token: &str,
+) -> Result<Option<(i32, String)>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT t.user_id, t.impersonator FROM impersonation_tokens t JOIN users u ON u.id = t.user_id WHERE t.token_hash = $1 AND t.expires_at > NOW() AND u.tenant_id = $2",
+        hash(token),
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("impersonation::resolve")
+    .await?;
+    Ok(row.map(|row| (row.user_id, row.impersonator)))
+}
+
+// Remove the tokens that expired, of every tenant, returning how many were
+// removed.
+pub async fn purge_expired(executor: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM impersonation_tokens WHERE expires_at <= NOW()")
+        .execute(executor)
+        .timed("impersonation::purge_expired")
+        .await?;
+    Ok(result.rows_affected())
+}
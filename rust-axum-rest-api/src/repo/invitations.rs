@@ -0,0 +1,189 @@
+// Invitations to organizations. Like impersonation tokens, the secret in the
+// link is random and only its hash is stored, so a link can neither be
+// guessed nor recovered from the database, and each one is good for a
+// single use before it expires or is revoked. The invited address is sealed
+// like users' (see `emails`).
+
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use sqlx::{PgExecutor, Pool, Postgres};
+
+use crate::models::{Invitation, Membership, OrgRole};
+use crate::repo::api_keys::{hash, hex};
+use crate::repo::{emails, Timed};
+use crate::tenant;
+
+pub const TOKEN_PREFIX: &str = "inv_";
+
+struct InvitationRow {
+    id: i32,
+    org_id: i32,
+    email: String,
+    role: String,
+    invited_by: Option<i32>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl TryFrom<InvitationRow> for Invitation {
+    type Error = sqlx::Error;
+
+    fn try_from(row: InvitationRow) -> Result<Self, Self::Error> {
+        Ok(Invitation {
+            id: row.id,
+            org_id: row.org_id,
+            email: emails::open(&row.email)?,
+            role: OrgRole::from_column(&row.role),
+            invited_by: row.invited_by,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+// Invite `email` to an organization for `ttl`, returning the invitation and
+// the token for the link.
+pub async fn create(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    email: &str,
+    role: OrgRole,
+    invited_by: Option<i32>,
+    ttl: Duration,
+) -> Result<(Invitation, String), sqlx::Error> {
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    let token = format!("{TOKEN_PREFIX}{}", hex(&secret));
+    let row = sqlx::query_as!(
+        InvitationRow,
+        "INSERT INTO org_invitations (tenant_id, org_id, email, role, token_hash, invited_by, expires_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         RETURNING id, org_id, email, role, invited_by, created_at, expires_at",
+        tenant::current(),
+        org_id,
+        emails::seal(email).stored,
+        role.as_str(),
+        hash(&token),
+        invited_by,
+        Utc::now() + ttl
+    )
+    .fetch_one(pool)
+    .timed("invitations::create")
+    .await?;
+    Ok((row.try_into()?, token))
+}
+
+// The invitations of an organization that can still be accepted, newest
+// first.
+pub async fn pending(pool: &Pool<Postgres>, org_id: i32) -> Result<Vec<Invitation>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        InvitationRow,
+        "SELECT id, org_id, email, role, invited_by, created_at, expires_at FROM org_invitations
+         WHERE org_id = $1 AND tenant_id = $2
+           AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > NOW()
+         ORDER BY id DESC",
+        org_id,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("invitations::pending")
+    .await?;
+    rows.into_iter().map(Invitation::try_from).collect()
+}
+
+// Revoke a pending invitation, false when there is none with the id.
+pub async fn revoke(pool: &Pool<Postgres>, org_id: i32, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE org_invitations SET revoked_at = NOW()
+         WHERE id = $1 AND org_id = $2 AND tenant_id = $3
+           AND accepted_at IS NULL AND revoked_at IS NULL",
+        id,
+        org_id,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("invitations::revoke")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// The invitation a token belongs to, if it can still be accepted.
+pub async fn find_pending(
+    pool: &Pool<Postgres>,
+    token: &str,
+) -> Result<Option<Invitation>, sqlx::Error> {
+    let row = sqlx::query_as!(
+        InvitationRow,
+        "SELECT id, org_id, email, role, invited_by, created_at, expires_at FROM org_invitations
+         WHERE token_hash = $1 AND tenant_id = $2
+           AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > NOW()",
+        hash(token),
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("invitations::find_pending")
+    .await?;
+    row.map(Invitation::try_from).transpose()
+}
+
+// Use up an invitation for `user_id`, making them a member with the invited
+// role (members already in the organization keep theirs). `None` when the
+// invitation was used, revoked or expired in the meantime.
+pub async fn accept(
+    pool: &Pool<Postgres>,
+    id: i32,
+    user_id: i32,
+) -> Result<Option<Membership>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let Some(invitation) = sqlx::query!(
+        "UPDATE org_invitations SET accepted_at = NOW(), accepted_by = $2
+         WHERE id = $1 AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > NOW()
+         RETURNING org_id, role, tenant_id",
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .timed("invitations::accept")
+    .await?
+    else {
+        return Ok(None);
+    };
+    let membership = sqlx::query!(
+        "INSERT INTO org_memberships (org_id, user_id, tenant_id, role) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (org_id, user_id) DO UPDATE SET role = org_memberships.role
+         RETURNING org_id, user_id, role, created_at",
+        invitation.org_id,
+        user_id,
+        invitation.tenant_id,
+        invitation.role
+    )
+    .fetch_one(&mut *tx)
+    .timed("invitations::accept")
+    .await?;
+    tx.commit().await?;
+    Ok(Some(Membership {
+        org_id: membership.org_id,
+        user_id: membership.user_id,
+        role: OrgRole::from_column(&membership.role),
+        created_at: membership.created_at,
+    }))
+}
+
+// Remove the invitations of every tenant that expired unaccepted, or were
+// revoked, more than `older_than_days` days ago, returning how many were
+// removed. Accepted ones are kept, they tell who invited a member.
+pub async fn purge_expired(
+    executor: impl PgExecutor<'_>,
+    older_than_days: i32,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM org_invitations
+         WHERE accepted_at IS NULL
+             AND COALESCE(revoked_at, expires_at) < NOW() - make_interval(days => $1)",
+        older_than_days
+    )
+    .execute(executor)
+    .timed("invitations::purge_expired")
+    .await?;
+    Ok(result.rows_affected())
+}
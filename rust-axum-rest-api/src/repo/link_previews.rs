@@ -0,0 +1,122 @@
+// Link previews and the links of posts. Previews are keyed by URL alone and
+// shared across tenants, since a page says the same about itself whoever
+// links it; post ids are unique across tenants, so links aren't scoped
+// either.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::LinkPreview;
+use crate::repo::Timed;
+
+// Make `urls` the links of a post, queueing a fetch of the ones without a
+// preview or with one older than `max_age_days`.
+pub async fn sync(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+    urls: &[String],
+    max_age_days: i32,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO link_previews (url) SELECT UNNEST($1::text[])
+         ON CONFLICT (url) DO UPDATE SET fetched_at = NULL, next_attempt_at = NOW()
+         WHERE link_previews.fetched_at < NOW() - make_interval(days => $2)",
+        urls,
+        max_age_days
+    )
+    .execute(&mut *tx)
+    .timed("link_previews::sync")
+    .await?;
+    sqlx::query!("DELETE FROM post_links WHERE post_id = $1", post_id)
+        .execute(&mut *tx)
+        .timed("link_previews::sync")
+        .await?;
+    sqlx::query!(
+        "INSERT INTO post_links (post_id, url, position)
+         SELECT $1, url, position FROM UNNEST($2::text[]) WITH ORDINALITY AS links (url, position)",
+        post_id,
+        urls
+    )
+    .execute(&mut *tx)
+    .timed("link_previews::sync")
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// The previews of a post's links that were fetched, in the order of the
+// links.
+pub async fn for_post(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+) -> Result<Vec<LinkPreview>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkPreview,
+        "SELECT lp.url, lp.title, lp.description, lp.image_url, lp.site_name
+         FROM post_links pl JOIN link_previews lp ON lp.url = pl.url
+         WHERE pl.post_id = $1 AND lp.fetched_at IS NOT NULL AND lp.error IS NULL
+         ORDER BY pl.position",
+        post_id
+    )
+    .fetch_all(pool)
+    .timed("link_previews::for_post")
+    .await
+}
+
+// Take up to `limit` URLs waiting to be fetched, leased for `lease_secs` so
+// that other workers skip them meanwhile.
+pub async fn claim_due(
+    pool: &Pool<Postgres>,
+    limit: i64,
+    lease_secs: f64,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "UPDATE link_previews SET next_attempt_at = NOW() + make_interval(secs => $2)
+         WHERE url IN (
+             SELECT url FROM link_previews
+             WHERE fetched_at IS NULL AND next_attempt_at <= NOW()
+             ORDER BY next_attempt_at LIMIT $1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING url",
+        limit,
+        lease_secs
+    )
+    .fetch_all(pool)
+    .timed("link_previews::claim_due")
+    .await
+}
+
+pub async fn store(pool: &Pool<Postgres>, preview: &LinkPreview) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE link_previews
+         SET title = $2, description = $3, image_url = $4, site_name = $5, error = NULL,
+             fetched_at = NOW()
+         WHERE url = $1",
+        preview.url,
+        preview.title,
+        preview.description,
+        preview.image_url,
+        preview.site_name
+    )
+    .execute(pool)
+    .timed("link_previews::store")
+    .await?;
+    Ok(())
+}
+
+// Record that a page has no preview, until it is fetched again.
+pub async fn store_error(pool: &Pool<Postgres>, url: &str, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE link_previews
+         SET title = NULL, description = NULL, image_url = NULL, site_name = NULL, error = $2,
+             fetched_at = NOW()
+         WHERE url = $1",
+        url,
+        error
+    )
+    .execute(pool)
+    .timed("link_previews::store_error")
+    .await?;
+    Ok(())
+}
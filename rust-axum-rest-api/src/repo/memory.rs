@@ -0,0 +1,673 @@
+// In-memory repositories for handler tests that shouldn't need a database.
+// Only compiled with the `test-support` feature. They hold the default
+// tenant's rows only, see `tenant`.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::types::Json;
+use uuid::Uuid;
+
+use crate::ids::{Key, PublicId};
+use crate::models::{
+    Activity, AuditEntry, BannedWord, CreateBannedWord, CreateFeatureFlag, CreatePost, CreateUser,
+    FeatureFlag, FlagOverride, FlagRule, NewActivity, NewAuditEntry, Post, PostStatus,
+    PostWithAuthor, PublicUser, Report, ReportOutcome, UpdateFeatureFlag, UpdatePost, User,
+};
+use crate::pagination::Page;
+use crate::repo::activities::ActivityRepository;
+use crate::repo::audit::{AuditFilter, AuditRepository};
+use crate::repo::banned_words::BannedWordRepository;
+use crate::repo::flags::FlagRepository;
+use crate::repo::posts::{PostFilter, PostRepository, SortField, SortOrder};
+use crate::repo::reports::ReportRepository;
+use crate::repo::users::UserRepository;
+
+#[derive(Default)]
+pub struct InMemoryPosts {
+    // (post, deleted)
+    rows: Mutex<Vec<(Post, bool)>>,
+    // the ids of those published at some point
+    published: Mutex<HashSet<i32>>,
+    // where authors are looked up, posts have no authors without it
+    users: Option<Arc<InMemoryUsers>>,
+}
+
+impl InMemoryPosts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_users(users: Arc<InMemoryUsers>) -> Self {
+        InMemoryPosts {
+            users: Some(users),
+            ..Self::default()
+        }
+    }
+
+    fn uuid_of(&self, user_id: Option<i32>) -> Option<Uuid> {
+        let users = self.users.as_ref()?;
+        users.find(user_id?).map(|user| user.uuid)
+    }
+
+    fn with_author(&self, post: Post) -> PostWithAuthor {
+        let author = post
+            .user_id
+            .zip(self.users.as_ref())
+            .and_then(|(id, users)| users.find(id))
+            .map(PublicUser::from);
+        PostWithAuthor { post, author }
+    }
+}
+
+#[async_trait]
+impl PostRepository for InMemoryPosts {
+    async fn list(
+        &self,
+        filter: &PostFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        let mut posts: Vec<Post> = rows
+            .iter()
+            .filter(|(post, deleted)| {
+                !deleted && post.status == PostStatus::Published.as_str() && filter.matches(post)
+            })
+            .map(|(post, _)| post.clone())
+            .collect();
+        posts.sort_by_key(|post| match filter.sort {
+            SortField::Id => (None, post.id),
+            SortField::CreatedAt => (Some(post.created_at), post.id),
+            SortField::UpdatedAt => (Some(post.updated_at), post.id),
+        });
+        if filter.order == SortOrder::Desc {
+            posts.reverse();
+        }
+        if let Some(page) = page {
+            posts = posts
+                .into_iter()
+                .skip(page.offset() as usize)
+                .take(page.limit() as usize)
+                .collect();
+        }
+        Ok(posts)
+    }
+
+    async fn count(&self, filter: &PostFilter) -> Result<i64, sqlx::Error> {
+        Ok(self.list(filter, None).await?.len() as i64)
+    }
+
+    // counting is quick enough here
+    async fn estimate(&self, filter: &PostFilter) -> Result<i64, sqlx::Error> {
+        self.count(filter).await
+    }
+
+    async fn list_with_authors(
+        &self,
+        filter: &PostFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<PostWithAuthor>, sqlx::Error> {
+        let posts = self.list(filter, page).await?;
+        Ok(posts
+            .into_iter()
+            .map(|post| self.with_author(post))
+            .collect())
+    }
+
+    async fn get_with_author(&self, key: Key) -> Result<PostWithAuthor, sqlx::Error> {
+        let post = self.get(key).await?;
+        Ok(self.with_author(post))
+    }
+
+    async fn get(&self, key: Key) -> Result<Post, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        rows.iter()
+            .find(|(post, deleted)| matches_key(post, key) && !deleted)
+            .map(|(post, _)| post.clone())
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn create(&self, new_post: &CreatePost, status: PostStatus) -> Result<Post, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        let now = Utc::now();
+        let post = Post {
+            id: rows.len() as i32 + 1,
+            uuid: Uuid::new_v4(),
+            public_id: PublicId::new(now.timestamp_millis() as u64, rand::random()).to_string(),
+            user_id: new_post.user_id,
+            user_uuid: self.uuid_of(new_post.user_id),
+            org_id: new_post.org_id,
+            authors: new_post.user_id.into_iter().collect(),
+            title: new_post.title.clone(),
+            body: new_post.body.clone(),
+            status: status.as_str().to_owned(),
+            // there are no comments here to count
+            comments_count: 0,
+            created_at: now,
+            updated_at: now,
+        };
+        rows.push((post.clone(), false));
+        if status == PostStatus::Published {
+            self.published.lock().unwrap().insert(post.id);
+        }
+        Ok(post)
+    }
+
+    async fn update(
+        &self,
+        key: Key,
+        updated: &UpdatePost,
+        status: Option<PostStatus>,
+    ) -> Result<Post, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        let (post, _) = rows
+            .iter_mut()
+            .find(|(post, deleted)| matches_key(post, key) && !deleted)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        post.title = updated.title.clone();
+        post.body = updated.body.clone();
+        post.user_id = updated.user_id;
+        post.user_uuid = self.uuid_of(updated.user_id);
+        post.authors = updated.user_id.into_iter().collect();
+        if let Some(status) = status {
+            post.status = status.as_str().to_owned();
+        }
+        post.updated_at = Utc::now();
+        Ok(post.clone())
+    }
+
+    async fn delete(&self, key: Key) -> Result<bool, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        match rows
+            .iter_mut()
+            .find(|(post, deleted)| matches_key(post, key) && !deleted)
+        {
+            Some((_, deleted)) => {
+                *deleted = true;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn queue(
+        &self,
+        status: PostStatus,
+        page: Option<Page>,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        let posts = rows
+            .iter()
+            .filter(|(post, deleted)| !deleted && post.status == status.as_str())
+            .map(|(post, _)| post.clone());
+        Ok(match page {
+            Some(page) => posts
+                .skip(page.offset() as usize)
+                .take(page.limit() as usize)
+                .collect(),
+            None => posts.collect(),
+        })
+    }
+
+    async fn moderate(
+        &self,
+        key: Key,
+        status: PostStatus,
+        _reason: Option<&str>,
+    ) -> Result<Post, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        let (post, _) = rows
+            .iter_mut()
+            .find(|(post, deleted)| matches_key(post, key) && !deleted)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        post.status = status.as_str().to_owned();
+        Ok(post.clone())
+    }
+
+    async fn mark_published(&self, id: i32) -> Result<bool, sqlx::Error> {
+        Ok(self.published.lock().unwrap().insert(id))
+    }
+}
+
+fn matches_key(post: &Post, key: Key) -> bool {
+    match key {
+        Key::Serial(id) => post.id == id,
+        Key::Uuid(uuid) => post.uuid == uuid,
+        Key::Public(public_id) => post.public_id == public_id.as_str(),
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryUsers {
+    rows: Mutex<Vec<User>>,
+}
+
+impl InMemoryUsers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn find(&self, id: i32) -> Option<User> {
+        let rows = self.rows.lock().unwrap();
+        rows.iter().find(|user| user.id == id).cloned()
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryUsers {
+    async fn create(&self, new_user: &CreateUser) -> Result<User, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        let now = Utc::now();
+        let user = User {
+            id: rows.len() as i32 + 1,
+            uuid: Uuid::new_v4(),
+            username: new_user.username.clone(),
+            email: new_user.email.clone(),
+            avatar_url: None,
+            // nor their posts
+            posts_count: 0,
+            created_at: now,
+            updated_at: now,
+        };
+        rows.push(user.clone());
+        Ok(user)
+    }
+
+    async fn get(&self, id: i32) -> Result<User, sqlx::Error> {
+        self.find(id).ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<User, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        rows.iter()
+            .find(|user| user.uuid == uuid)
+            .cloned()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn taken(&self, username: &str, email: &str) -> Result<(bool, bool), sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        Ok((
+            rows.iter()
+                .any(|user| user.username.to_lowercase() == username.to_lowercase()),
+            rows.iter().any(|user| user.email == email),
+        ))
+    }
+
+    async fn emails(&self, ids: &[i32]) -> Result<Vec<(i32, String)>, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        Ok(rows
+            .iter()
+            .filter(|user| ids.contains(&user.id))
+            .map(|user| (user.id, user.email.clone()))
+            .collect())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryBannedWords {
+    rows: Mutex<Vec<BannedWord>>,
+}
+
+impl InMemoryBannedWords {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BannedWordRepository for InMemoryBannedWords {
+    async fn list(&self) -> Result<Vec<BannedWord>, sqlx::Error> {
+        let mut words = self.rows.lock().unwrap().clone();
+        words.sort_by(|a, b| a.word.cmp(&b.word));
+        Ok(words)
+    }
+
+    async fn add(&self, word: &CreateBannedWord) -> Result<BannedWord, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        if let Some(listed) = rows.iter_mut().find(|listed| listed.word == word.word) {
+            listed.action = word.action;
+            return Ok(listed.clone());
+        }
+        let listed = BannedWord {
+            word: word.word.clone(),
+            action: word.action,
+            created_at: Utc::now(),
+        };
+        rows.push(listed.clone());
+        Ok(listed)
+    }
+
+    async fn remove(&self, word: &str) -> Result<bool, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        let before = rows.len();
+        rows.retain(|listed| listed.word != word);
+        Ok(rows.len() < before)
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryReports {
+    rows: Mutex<Vec<Report>>,
+}
+
+impl InMemoryReports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ReportRepository for InMemoryReports {
+    async fn create(
+        &self,
+        post_id: i32,
+        comment_id: Option<i32>,
+        reporter_id: i32,
+        reason: &str,
+    ) -> Result<Option<Report>, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        if rows.iter().any(|report| {
+            report.post_id == post_id
+                && report.comment_id == comment_id
+                && report.reporter_id == Some(reporter_id)
+        }) {
+            return Ok(None);
+        }
+        let report = Report {
+            id: rows.len() as i32 + 1,
+            post_id,
+            comment_id,
+            reporter_id: Some(reporter_id),
+            reason: reason.to_owned(),
+            created_at: Utc::now(),
+            resolved_at: None,
+            outcome: None,
+        };
+        rows.push(report.clone());
+        Ok(Some(report))
+    }
+
+    async fn count_open(&self, post_id: i32, comment_id: Option<i32>) -> Result<i64, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        let mut reporters: Vec<_> = rows
+            .iter()
+            .filter(|report| {
+                report.post_id == post_id
+                    && report.comment_id == comment_id
+                    && report.resolved_at.is_none()
+            })
+            .filter_map(|report| report.reporter_id)
+            .collect();
+        reporters.sort();
+        reporters.dedup();
+        Ok(reporters.len() as i64)
+    }
+
+    async fn list(&self, resolved: bool) -> Result<Vec<Report>, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        Ok(rows
+            .iter()
+            .filter(|report| report.resolved_at.is_some() == resolved)
+            .cloned()
+            .collect())
+    }
+
+    async fn resolve(
+        &self,
+        id: i32,
+        outcome: ReportOutcome,
+        _note: Option<&str>,
+    ) -> Result<Report, sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        let report = rows
+            .iter_mut()
+            .find(|report| report.id == id && report.resolved_at.is_none())
+            .ok_or(sqlx::Error::RowNotFound)?;
+        report.resolved_at = Some(Utc::now());
+        report.outcome = Some(outcome.as_str().to_owned());
+        Ok(report.clone())
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryAuditLog {
+    rows: Mutex<Vec<AuditEntry>>,
+}
+
+impl InMemoryAuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AuditRepository for InMemoryAuditLog {
+    async fn append(&self, entries: &[NewAuditEntry]) -> Result<(), sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        for entry in entries {
+            let entry = entry.clone();
+            let id = rows.len() as i64 + 1;
+            rows.push(AuditEntry {
+                id,
+                actor: entry.actor,
+                impersonator: entry.impersonator,
+                action: entry.action,
+                entity_type: entry.entity_type,
+                entity_id: entry.entity_id,
+                before: entry.before,
+                after: entry.after,
+                changes: entry.changes.map(Json),
+                ip: entry.ip,
+                country: entry.country,
+                region: entry.region,
+                created_at: Utc::now(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        filter: &AuditFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<AuditEntry>, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        let entries = rows
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry))
+            .cloned();
+        Ok(match page {
+            Some(page) => entries
+                .skip(page.offset() as usize)
+                .take(page.limit() as usize)
+                .collect(),
+            None => entries.collect(),
+        })
+    }
+
+    async fn count(&self, filter: &AuditFilter) -> Result<i64, sqlx::Error> {
+        Ok(self.list(filter, None).await?.len() as i64)
+    }
+}
+
+// Knows nothing of the posts, so unlike the Postgres one it keeps activities
+// about posts that aren't published.
+#[derive(Default)]
+pub struct InMemoryActivities {
+    rows: Mutex<Vec<Activity>>,
+}
+
+impl InMemoryActivities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ActivityRepository for InMemoryActivities {
+    async fn append(&self, activity: &NewActivity) -> Result<(), sqlx::Error> {
+        let mut rows = self.rows.lock().unwrap();
+        let id = rows.len() as i64 + 1;
+        rows.push(Activity {
+            id,
+            user_id: activity.user_id,
+            kind: activity.kind.as_str().to_owned(),
+            post_id: activity.post_id,
+            comment_id: activity.comment_id,
+            created_at: Utc::now(),
+        });
+        Ok(())
+    }
+
+    async fn list(&self, user_id: i32, page: Option<Page>) -> Result<Vec<Activity>, sqlx::Error> {
+        let rows = self.rows.lock().unwrap();
+        let activities = rows
+            .iter()
+            .rev()
+            .filter(|activity| activity.user_id == user_id)
+            .cloned();
+        Ok(match page {
+            Some(page) => activities
+                .skip(page.offset() as usize)
+                .take(page.limit() as usize)
+                .collect(),
+            None => activities.collect(),
+        })
+    }
+
+    async fn count(&self, user_id: i32) -> Result<i64, sqlx::Error> {
+        Ok(self.list(user_id, None).await?.len() as i64)
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryFlags {
+    flags: Mutex<Vec<FeatureFlag>>,
+    overrides: Mutex<Vec<FlagOverride>>,
+}
+
+impl InMemoryFlags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl FlagRepository for InMemoryFlags {
+    async fn list(&self) -> Result<Vec<FeatureFlag>, sqlx::Error> {
+        let mut flags = self.flags.lock().unwrap().clone();
+        flags.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(flags)
+    }
+
+    async fn get(&self, key: &str) -> Result<FeatureFlag, sqlx::Error> {
+        let flags = self.flags.lock().unwrap();
+        flags
+            .iter()
+            .find(|flag| flag.key == key)
+            .cloned()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn create(&self, flag: &CreateFeatureFlag) -> Result<FeatureFlag, sqlx::Error> {
+        let mut flags = self.flags.lock().unwrap();
+        let now = Utc::now();
+        let flag = FeatureFlag {
+            key: flag.key.clone(),
+            description: flag.description.clone(),
+            rollout_percent: flag.rollout_percent,
+            created_at: now,
+            updated_at: now,
+        };
+        flags.push(flag.clone());
+        Ok(flag)
+    }
+
+    async fn update(
+        &self,
+        key: &str,
+        flag: &UpdateFeatureFlag,
+    ) -> Result<FeatureFlag, sqlx::Error> {
+        let mut flags = self.flags.lock().unwrap();
+        let existing = flags
+            .iter_mut()
+            .find(|existing| existing.key == key)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        existing.description = flag.description.clone();
+        existing.rollout_percent = flag.rollout_percent;
+        existing.updated_at = Utc::now();
+        Ok(existing.clone())
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, sqlx::Error> {
+        let mut flags = self.flags.lock().unwrap();
+        let before = flags.len();
+        flags.retain(|flag| flag.key != key);
+        self.overrides.lock().unwrap().retain(|o| o.flag_key != key);
+        Ok(flags.len() < before)
+    }
+
+    async fn overrides(&self, key: &str) -> Result<Vec<FlagOverride>, sqlx::Error> {
+        let mut overrides: Vec<_> = self
+            .overrides
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|o| o.flag_key == key)
+            .cloned()
+            .collect();
+        overrides.sort_by_key(|o| o.user_id);
+        Ok(overrides)
+    }
+
+    async fn set_override(
+        &self,
+        key: &str,
+        user_id: i32,
+        enabled: bool,
+    ) -> Result<FlagOverride, sqlx::Error> {
+        self.get(key).await?;
+        let mut overrides = self.overrides.lock().unwrap();
+        overrides.retain(|o| o.flag_key != key || o.user_id != user_id);
+        let flag_override = FlagOverride {
+            flag_key: key.to_owned(),
+            user_id,
+            enabled,
+        };
+        overrides.push(flag_override.clone());
+        Ok(flag_override)
+    }
+
+    async fn remove_override(&self, key: &str, user_id: i32) -> Result<bool, sqlx::Error> {
+        let mut overrides = self.overrides.lock().unwrap();
+        let before = overrides.len();
+        overrides.retain(|o| o.flag_key != key || o.user_id != user_id);
+        Ok(overrides.len() < before)
+    }
+
+    async fn rule(&self, key: &str, user_id: Option<i32>) -> Result<Option<FlagRule>, sqlx::Error> {
+        let rules = self.rules(user_id).await?;
+        Ok(rules.into_iter().find(|rule| rule.key == key))
+    }
+
+    async fn rules(&self, user_id: Option<i32>) -> Result<Vec<FlagRule>, sqlx::Error> {
+        let overrides = self.overrides.lock().unwrap().clone();
+        Ok(self
+            .list()
+            .await?
+            .into_iter()
+            .map(|flag| FlagRule {
+                user_override: overrides
+                    .iter()
+                    .find(|o| o.flag_key == flag.key && Some(o.user_id) == user_id)
+                    .map(|o| o.enabled),
+                key: flag.key,
+                rollout_percent: flag.rollout_percent,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,113 @@
+// Users mentioned in posts and comments, within the current tenant.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::Post;
+use crate::pagination::Page;
+use crate::repo::Timed;
+use crate::tenant;
+
+// Make the mentions of a post's body (`comment_id` None) or of one of its
+// comments those of `usernames`, lowercased. Names of no user are ignored,
+// and the `writer` mentioning themselves needs no notification.
+pub async fn sync(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+    comment_id: Option<i32>,
+    usernames: &[String],
+    writer: Option<i32>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "DELETE FROM mentions m USING users u
+         WHERE u.id = m.user_id AND m.post_id = $1 AND m.comment_id IS NOT DISTINCT FROM $2
+           AND m.tenant_id = $3 AND NOT (LOWER(u.username) = ANY($4))",
+        post_id,
+        comment_id,
+        tenant::current(),
+        usernames
+    )
+    .execute(&mut *tx)
+    .timed("mentions::sync")
+    .await?;
+    sqlx::query!(
+        "INSERT INTO mentions (user_id, post_id, comment_id, tenant_id, notified_at)
+         SELECT id, $1, $2, $3, CASE WHEN id = $5 THEN NOW() END
+         FROM users WHERE tenant_id = $3 AND LOWER(username) = ANY($4)
+         ON CONFLICT DO NOTHING",
+        post_id,
+        comment_id,
+        tenant::current(),
+        usernames,
+        writer
+    )
+    .execute(&mut *tx)
+    .timed("mentions::sync")
+    .await?;
+    tx.commit().await
+}
+
+// The mentions of a post and its comments no one was told about yet, as
+// (user, comment) pairs, marked notified; none while the post or comment is
+// held back.
+pub async fn take_unnotified(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+) -> Result<Vec<(i32, Option<i32>)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "UPDATE mentions m SET notified_at = NOW()
+         WHERE m.post_id = $1 AND m.notified_at IS NULL AND m.tenant_id = $2
+           AND EXISTS (SELECT 1 FROM posts p WHERE p.id = m.post_id AND p.status = 'published')
+           AND (m.comment_id IS NULL OR EXISTS (
+               SELECT 1 FROM comments c WHERE c.id = m.comment_id AND c.status = 'published'
+           ))
+         RETURNING m.user_id, m.comment_id",
+        post_id,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("mentions::take_unnotified")
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.user_id, row.comment_id))
+        .collect())
+}
+
+// The published posts mentioning a user in their body or comments, most
+// recent first.
+pub async fn posts_mentioning(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    page: Option<Page>,
+) -> Result<Vec<Post>, sqlx::Error> {
+    sqlx::query_as!(
+        Post,
+        r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at
+           FROM posts
+           WHERE id IN (SELECT post_id FROM mentions WHERE user_id = $1)
+             AND deleted_at IS NULL AND status = 'published' AND tenant_id = $2
+           ORDER BY created_at DESC, id DESC
+           LIMIT $3 OFFSET $4"#,
+        user_id,
+        tenant::current(),
+        page.map(Page::limit),
+        page.map_or(0, Page::offset)
+    )
+    .fetch_all(pool)
+    .timed("mentions::posts_mentioning")
+    .await
+}
+
+pub async fn count_mentioning(pool: &Pool<Postgres>, user_id: i32) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM posts
+           WHERE id IN (SELECT post_id FROM mentions WHERE user_id = $1)
+             AND deleted_at IS NULL AND status = 'published' AND tenant_id = $2"#,
+        user_id,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("mentions::count_mentioning")
+    .await
+}
@@ -0,0 +1,51 @@
+// Database access, one module per table. The handlers go through the
+// repository traits (`PostRepository`, `UserRepository` and so on) so they can be tested against
+// the in-memory implementations in `memory` (feature `test-support`); the
+// remaining functions take the pool explicitly. Errors are sqlx errors,
+// untouched, so callers decide how to report them. Queries are timed, see
+// `slow`, and fail fast while the database is unwell, see `breaker`; reads
+// are tried again after transient errors, see `retry`. Users' emails are
+// sealed on the way in and opened on the way out, see `emails`.
+
+pub mod activities;
+pub mod api_keys;
+pub mod archive;
+pub mod audit;
+pub mod banned_words;
+pub mod breaker;
+pub mod comments;
+pub mod emails;
+pub mod federation;
+pub mod flags;
+pub mod follows;
+pub mod hashtags;
+pub mod identities;
+pub mod impersonation;
+pub mod invitations;
+pub mod link_previews;
+#[cfg(feature = "test-support")]
+pub mod memory;
+pub mod mentions;
+pub mod orgs;
+pub mod posts;
+pub mod preferences;
+pub mod reports;
+pub mod retry;
+pub mod sessions;
+pub mod short_links;
+pub mod signing_keys;
+pub mod slow;
+pub mod subscriptions;
+pub mod tenants;
+pub mod usage;
+pub mod users;
+pub mod webhooks;
+
+pub use activities::{ActivityRepository, PgActivityRepository};
+pub use audit::{AuditFilter, AuditRepository, PgAuditRepository};
+pub use banned_words::{BannedWordRepository, PgBannedWordRepository};
+pub use flags::{FlagRepository, PgFlagRepository};
+pub use posts::{PgPostRepository, PostFilter, PostRepository};
+pub use reports::{PgReportRepository, ReportRepository};
+pub use slow::Timed;
+pub use users::{PgUserRepository, UserRepository};
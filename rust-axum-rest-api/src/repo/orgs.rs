@@ -0,0 +1,124 @@
+// Organizations and their memberships, within the current tenant.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::{Membership, OrgRole, Organization};
+use crate::repo::Timed;
+use crate::tenant;
+
+struct MembershipRow {
+    org_id: i32,
+    user_id: i32,
+    role: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<MembershipRow> for Membership {
+    fn from(row: MembershipRow) -> Self {
+        Membership {
+            org_id: row.org_id,
+            user_id: row.user_id,
+            role: OrgRole::from_column(&row.role),
+            created_at: row.created_at,
+        }
+    }
+}
+
+// Create an organization owned by `owner_id`.
+pub async fn create(
+    pool: &Pool<Postgres>,
+    name: &str,
+    owner_id: i32,
+) -> Result<Organization, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let org = sqlx::query_as!(
+        Organization,
+        "INSERT INTO organizations (name, tenant_id) VALUES ($1, $2) RETURNING id, name, created_at",
+        name,
+        tenant::current()
+    )
+    .fetch_one(&mut *tx)
+    .timed("orgs::create")
+    .await?;
+    sqlx::query!(
+        "INSERT INTO org_memberships (org_id, user_id, tenant_id, role) VALUES ($1, $2, $3, $4)",
+        org.id,
+        owner_id,
+        tenant::current(),
+        OrgRole::Owner.as_str()
+    )
+    .execute(&mut *tx)
+    .timed("orgs::create")
+    .await?;
+    tx.commit().await?;
+    Ok(org)
+}
+
+pub async fn get(pool: &Pool<Postgres>, id: i32) -> Result<Organization, sqlx::Error> {
+    sqlx::query_as!(
+        Organization,
+        "SELECT id, name, created_at FROM organizations WHERE id = $1 AND tenant_id = $2",
+        id,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("orgs::get")
+    .await
+}
+
+// The role of a user in an organization, `None` for non-members.
+pub async fn role(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    user_id: i32,
+) -> Result<Option<OrgRole>, sqlx::Error> {
+    let role = sqlx::query_scalar!(
+        "SELECT role FROM org_memberships WHERE org_id = $1 AND user_id = $2 AND tenant_id = $3",
+        org_id,
+        user_id,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("orgs::role")
+    .await?;
+    Ok(role.as_deref().map(OrgRole::from_column))
+}
+
+// Add a member, or change the role of an existing one.
+pub async fn add_member(
+    pool: &Pool<Postgres>,
+    org_id: i32,
+    user_id: i32,
+    role: OrgRole,
+) -> Result<Membership, sqlx::Error> {
+    let row = sqlx::query_as!(
+        MembershipRow,
+        "INSERT INTO org_memberships (org_id, user_id, tenant_id, role) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (org_id, user_id) DO UPDATE SET role = EXCLUDED.role
+         RETURNING org_id, user_id, role, created_at",
+        org_id,
+        user_id,
+        tenant::current(),
+        role.as_str()
+    )
+    .fetch_one(pool)
+    .timed("orgs::add_member")
+    .await?;
+    Ok(row.into())
+}
+
+// The members of an organization, owners first.
+pub async fn members(pool: &Pool<Postgres>, org_id: i32) -> Result<Vec<Membership>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        MembershipRow,
+        "SELECT org_id, user_id, role, created_at FROM org_memberships
+         WHERE org_id = $1 AND tenant_id = $2
+         ORDER BY CASE role WHEN 'owner' THEN 0 WHEN 'admin' THEN 1 ELSE 2 END, user_id",
+        org_id,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("orgs::members")
+    .await?;
+    Ok(rows.into_iter().map(Membership::from).collect())
+}
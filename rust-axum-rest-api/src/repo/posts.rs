@@ -0,0 +1,491 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{PgExecutor, Pool, Postgres};
+use uuid::Uuid;
+
+use crate::ids::Key;
+use crate::models::{CreatePost, Post, PostStatus, PostWithAuthor, PublicUser, UpdatePost};
+use crate::pagination::Page;
+use crate::repo::{retry, Timed};
+use crate::tenant;
+
+// Which posts `PostRepository::list` returns and in what order, taken from
+// the query string of GET /posts, e.g.
+// `?sort=updated_at&order=desc&created_after=2025-01-01T00:00:00Z`. The
+// `_after` bounds are inclusive, the `_before` bounds exclusive.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PostFilter {
+    pub sort: SortField,
+    pub order: SortOrder,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    // only the posts of an organization, see `GET /orgs/:id/posts`
+    pub org_id: Option<i32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Id,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl SortField {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl PostFilter {
+    // whether a post passes the time bounds
+    pub fn matches(&self, post: &Post) -> bool {
+        self.created_after.is_none_or(|t| post.created_at >= t)
+            && self.created_before.is_none_or(|t| post.created_at < t)
+            && self.updated_after.is_none_or(|t| post.updated_at >= t)
+            && self.updated_before.is_none_or(|t| post.updated_at < t)
+            && self.org_id.is_none_or(|id| post.org_id == Some(id))
+    }
+}
+
+// Post storage as seen by the handlers. Lookups of a missing (or
+// soft-deleted) post fail with `sqlx::Error::RowNotFound`. Lists and counts
+// only include published posts, lookups by key find any status.
+#[async_trait]
+pub trait PostRepository: Send + Sync {
+    // the matching posts, all of them without a page
+    async fn list(&self, filter: &PostFilter, page: Option<Page>)
+        -> Result<Vec<Post>, sqlx::Error>;
+    // `list` with each post's author joined in
+    async fn list_with_authors(
+        &self,
+        filter: &PostFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<PostWithAuthor>, sqlx::Error>;
+    // the number of posts `list` would return without a page
+    async fn count(&self, filter: &PostFilter) -> Result<i64, sqlx::Error>;
+    // the planner's guess at `count`, as quick for millions of posts as for
+    // a few
+    async fn estimate(&self, filter: &PostFilter) -> Result<i64, sqlx::Error>;
+    async fn get(&self, key: Key) -> Result<Post, sqlx::Error>;
+    async fn get_with_author(&self, key: Key) -> Result<PostWithAuthor, sqlx::Error>;
+    async fn create(&self, post: &CreatePost, status: PostStatus) -> Result<Post, sqlx::Error>;
+    // `status` replaces the post's status, `None` keeps it
+    async fn update(
+        &self,
+        key: Key,
+        post: &UpdatePost,
+        status: Option<PostStatus>,
+    ) -> Result<Post, sqlx::Error>;
+    // soft-delete a post, false when there was nothing to delete
+    async fn delete(&self, key: Key) -> Result<bool, sqlx::Error>;
+    // the posts with a status, oldest first, for the moderators
+    async fn queue(&self, status: PostStatus, page: Option<Page>)
+        -> Result<Vec<Post>, sqlx::Error>;
+    // record a moderator's decision, the reason goes with rejections
+    async fn moderate(
+        &self,
+        key: Key,
+        status: PostStatus,
+        reason: Option<&str>,
+    ) -> Result<Post, sqlx::Error>;
+    // note that a post is out, false when it was published before
+    async fn mark_published(&self, id: i32) -> Result<bool, sqlx::Error>;
+}
+
+// A post joined with its (optional) author.
+struct PostAuthorRow {
+    id: i32,
+    uuid: Uuid,
+    public_id: String,
+    user_id: Option<i32>,
+    org_id: Option<i32>,
+    authors: Vec<i32>,
+    title: String,
+    body: String,
+    status: String,
+    comments_count: i64,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    author_uuid: Option<Uuid>,
+    author_username: Option<String>,
+    author_avatar_url: Option<String>,
+    author_posts_count: Option<i64>,
+    author_created_at: Option<DateTime<Utc>>,
+    author_updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<PostAuthorRow> for PostWithAuthor {
+    fn from(row: PostAuthorRow) -> Self {
+        let author = match (
+            row.user_id,
+            row.author_uuid,
+            row.author_username,
+            row.author_created_at,
+            row.author_updated_at,
+        ) {
+            (Some(id), Some(uuid), Some(username), Some(created_at), Some(updated_at)) => {
+                Some(PublicUser {
+                    id,
+                    uuid,
+                    username,
+                    avatar_url: row.author_avatar_url,
+                    posts_count: row.author_posts_count.unwrap_or_default(),
+                    created_at,
+                    updated_at,
+                })
+            }
+            _ => None,
+        };
+        PostWithAuthor {
+            post: Post {
+                id: row.id,
+                uuid: row.uuid,
+                public_id: row.public_id,
+                user_id: row.user_id,
+                user_uuid: row.author_uuid,
+                org_id: row.org_id,
+                authors: row.authors,
+                title: row.title,
+                body: row.body,
+                status: row.status,
+                comments_count: row.comments_count,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            },
+            author,
+        }
+    }
+}
+
+pub struct PgPostRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgPostRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgPostRepository { pool }
+    }
+}
+
+#[async_trait]
+impl PostRepository for PgPostRepository {
+    async fn list(
+        &self,
+        filter: &PostFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        // one query serves both listings, the join is cheap next to
+        // duplicating the filtering and sorting
+        let posts = self.list_with_authors(filter, page).await?;
+        Ok(posts.into_iter().map(|post| post.post).collect())
+    }
+
+    async fn list_with_authors(
+        &self,
+        filter: &PostFilter,
+        page: Option<Page>,
+    ) -> Result<Vec<PostWithAuthor>, sqlx::Error> {
+        // the sort column can't be a bind parameter, so the timestamp to sort
+        // by is picked with CASE; it is NULL when sorting by id, leaving the
+        // trailing id terms to do the ordering
+        let rows = retry::read("posts::list_with_authors", move || {
+            sqlx::query_as!(
+                PostAuthorRow,
+                r#"SELECT p.id, p.uuid, p.public_id, p.user_id, p.org_id, post_authors_of(p) AS "authors!", p.title, p.body, p.status, p.comments_count, p.created_at, p.updated_at,
+                      u.uuid AS "author_uuid?", u.username AS "author_username?", u.avatar_url AS "author_avatar_url", u.posts_count AS "author_posts_count?",
+                      u.created_at AS "author_created_at?", u.updated_at AS "author_updated_at?"
+               FROM posts p LEFT JOIN users u ON u.id = p.user_id
+               WHERE p.deleted_at IS NULL AND p.status = 'published' AND p.tenant_id = $9
+                 AND ($10::int4 IS NULL OR p.org_id = $10)
+                 AND ($1::timestamptz IS NULL OR p.created_at >= $1)
+                 AND ($2::timestamptz IS NULL OR p.created_at < $2)
+                 AND ($3::timestamptz IS NULL OR p.updated_at >= $3)
+                 AND ($4::timestamptz IS NULL OR p.updated_at < $4)
+               ORDER BY
+                 CASE WHEN $6 THEN CASE $5 WHEN 'created_at' THEN p.created_at WHEN 'updated_at' THEN p.updated_at END END DESC,
+                 CASE WHEN NOT $6 THEN CASE $5 WHEN 'created_at' THEN p.created_at WHEN 'updated_at' THEN p.updated_at END END ASC,
+                 CASE WHEN $6 THEN p.id END DESC,
+                 p.id ASC
+               LIMIT $7 OFFSET $8"#,
+                filter.created_after,
+                filter.created_before,
+                filter.updated_after,
+                filter.updated_before,
+                filter.sort.as_str(),
+                filter.order == SortOrder::Desc,
+                page.map(Page::limit),
+                page.map_or(0, Page::offset),
+                tenant::current(),
+                filter.org_id
+            )
+            .fetch_all(&self.pool)
+        })
+        .await?;
+        Ok(rows.into_iter().map(PostWithAuthor::from).collect())
+    }
+
+    async fn count(&self, filter: &PostFilter) -> Result<i64, sqlx::Error> {
+        retry::read("posts::count", move || {
+            sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM posts
+               WHERE deleted_at IS NULL AND status = 'published' AND tenant_id = $5
+                 AND ($6::int4 IS NULL OR org_id = $6)
+                 AND ($1::timestamptz IS NULL OR created_at >= $1)
+                 AND ($2::timestamptz IS NULL OR created_at < $2)
+                 AND ($3::timestamptz IS NULL OR updated_at >= $3)
+                 AND ($4::timestamptz IS NULL OR updated_at < $4)"#,
+                filter.created_after,
+                filter.created_before,
+                filter.updated_after,
+                filter.updated_before,
+                tenant::current(),
+                filter.org_id
+            )
+            .fetch_one(&self.pool)
+        })
+        .await
+    }
+
+    async fn estimate(&self, filter: &PostFilter) -> Result<i64, sqlx::Error> {
+        // the rows the plan of the count's query expects, going by the
+        // table's statistics (see ANALYZE)
+        let plan: serde_json::Value = retry::read("posts::estimate", move || {
+            sqlx::query_scalar(
+                r#"EXPLAIN (FORMAT JSON) SELECT 1 FROM posts
+               WHERE deleted_at IS NULL AND status = 'published' AND tenant_id = $5
+                 AND ($6::int4 IS NULL OR org_id = $6)
+                 AND ($1::timestamptz IS NULL OR created_at >= $1)
+                 AND ($2::timestamptz IS NULL OR created_at < $2)
+                 AND ($3::timestamptz IS NULL OR updated_at >= $3)
+                 AND ($4::timestamptz IS NULL OR updated_at < $4)"#,
+            )
+            .bind(filter.created_after)
+            .bind(filter.created_before)
+            .bind(filter.updated_after)
+            .bind(filter.updated_before)
+            .bind(tenant::current())
+            .bind(filter.org_id)
+            .fetch_one(&self.pool)
+        })
+        .await?;
+        plan[0]["Plan"]["Plan Rows"]
+            .as_f64()
+            .map(|rows| rows.round() as i64)
+            .ok_or_else(|| sqlx::Error::Protocol(format!("no row estimate in {plan}")))
+    }
+
+    async fn get(&self, key: Key) -> Result<Post, sqlx::Error> {
+        let (id, uuid, public_id) = key.binds();
+        retry::read("posts::get", move || {
+            sqlx::query_as!(
+                Post,
+                r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at FROM posts WHERE (id = $1 OR uuid = $2 OR public_id = $3) AND deleted_at IS NULL AND tenant_id = $4"#,
+                id,
+                uuid,
+                public_id,
+                tenant::current()
+            )
+            .fetch_one(&self.pool)
+        })
+        .await
+    }
+
+    async fn get_with_author(&self, key: Key) -> Result<PostWithAuthor, sqlx::Error> {
+        let (id, uuid, public_id) = key.binds();
+        let row = retry::read("posts::get_with_author", move || {
+            sqlx::query_as!(
+                PostAuthorRow,
+                r#"SELECT p.id, p.uuid, p.public_id, p.user_id, p.org_id, post_authors_of(p) AS "authors!", p.title, p.body, p.status, p.comments_count, p.created_at, p.updated_at,
+                      u.uuid AS "author_uuid?", u.username AS "author_username?", u.avatar_url AS "author_avatar_url", u.posts_count AS "author_posts_count?",
+                      u.created_at AS "author_created_at?", u.updated_at AS "author_updated_at?"
+               FROM posts p LEFT JOIN users u ON u.id = p.user_id
+               WHERE (p.id = $1 OR p.uuid = $2 OR p.public_id = $3) AND p.deleted_at IS NULL AND p.tenant_id = $4"#,
+                id,
+                uuid,
+                public_id,
+                tenant::current()
+            )
+            .fetch_one(&self.pool)
+        })
+        .await?;
+        Ok(row.into())
+    }
+
+    async fn create(&self, post: &CreatePost, status: PostStatus) -> Result<Post, sqlx::Error> {
+        sqlx::query_as!(
+            Post,
+            r#"INSERT INTO posts (user_id, title, body, status, tenant_id, org_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, uuid, public_id, title, body, status, user_id, org_id, ARRAY_REMOVE(ARRAY[user_id], NULL) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, comments_count, created_at, updated_at"#,
+            post.user_id,
+            post.title,
+            post.body,
+            status.as_str(),
+            tenant::current(),
+            post.org_id
+        )
+        .fetch_one(&self.pool)
+        .timed("posts::create")
+        .await
+    }
+
+    async fn update(
+        &self,
+        key: Key,
+        post: &UpdatePost,
+        status: Option<PostStatus>,
+    ) -> Result<Post, sqlx::Error> {
+        let (id, uuid, public_id) = key.binds();
+        sqlx::query_as!(
+            Post,
+            r#"UPDATE posts SET title = $1, body = $2, user_id = $3, status = COALESCE($7, status), updated_at = NOW() WHERE (id = $4 OR uuid = $5 OR public_id = $6) AND deleted_at IS NULL AND tenant_id = $8 RETURNING id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at"#,
+            post.title,
+            post.body,
+            post.user_id,
+            id,
+            uuid,
+            public_id,
+            status.map(PostStatus::as_str),
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("posts::update")
+        .await
+    }
+
+    async fn delete(&self, key: Key) -> Result<bool, sqlx::Error> {
+        let (id, uuid, public_id) = key.binds();
+        let result = sqlx::query!(
+            "UPDATE posts SET deleted_at = NOW() WHERE (id = $1 OR uuid = $2 OR public_id = $3) AND deleted_at IS NULL AND tenant_id = $4",
+            id,
+            uuid,
+            public_id,
+            tenant::current()
+        )
+        .execute(&self.pool)
+        .timed("posts::delete")
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn queue(
+        &self,
+        status: PostStatus,
+        page: Option<Page>,
+    ) -> Result<Vec<Post>, sqlx::Error> {
+        retry::read("posts::queue", move || {
+            sqlx::query_as!(
+                Post,
+                r#"SELECT id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at FROM posts WHERE status = $1 AND deleted_at IS NULL AND tenant_id = $4 ORDER BY created_at, id LIMIT $2 OFFSET $3"#,
+                status.as_str(),
+                page.map(Page::limit),
+                page.map_or(0, Page::offset),
+                tenant::current()
+            )
+            .fetch_all(&self.pool)
+        })
+        .await
+    }
+
+    async fn moderate(
+        &self,
+        key: Key,
+        status: PostStatus,
+        reason: Option<&str>,
+    ) -> Result<Post, sqlx::Error> {
+        let (id, uuid, public_id) = key.binds();
+        sqlx::query_as!(
+            Post,
+            r#"UPDATE posts SET status = $1, moderation_reason = $2, moderated_at = NOW() WHERE (id = $3 OR uuid = $4 OR public_id = $5) AND deleted_at IS NULL AND tenant_id = $6 RETURNING id, uuid, public_id, user_id, org_id, post_authors_of(posts) AS "authors!", (SELECT u.uuid FROM users u WHERE u.id = posts.user_id) AS user_uuid, title, body, status, comments_count, created_at, updated_at"#,
+            status.as_str(),
+            reason,
+            id,
+            uuid,
+            public_id,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("posts::moderate")
+        .await
+    }
+
+    async fn mark_published(&self, id: i32) -> Result<bool, sqlx::Error> {
+        let marked = sqlx::query_scalar!(
+            "UPDATE posts SET published_at = NOW() WHERE id = $1 AND published_at IS NULL AND tenant_id = $2 RETURNING id",
+            id,
+            tenant::current()
+        )
+        .fetch_optional(&self.pool)
+        .timed("posts::mark_published")
+        .await?;
+        Ok(marked.is_some())
+    }
+}
+
+// Make a user a co-author of a post, false when they already are one.
+pub async fn add_author(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+    user_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "INSERT INTO post_authors (post_id, user_id, tenant_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        post_id,
+        user_id,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("posts::add_author")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Remove a co-author from a post, false when they weren't one.
+pub async fn remove_author(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+    user_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM post_authors WHERE post_id = $1 AND user_id = $2 AND tenant_id = $3",
+        post_id,
+        user_id,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("posts::remove_author")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Permanently remove posts that were soft-deleted more than `older_than_days`
+// days ago, returning how many were removed. Takes the pool or a transaction.
+pub async fn purge_deleted(
+    executor: impl PgExecutor<'_>,
+    older_than_days: i32,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM posts WHERE deleted_at IS NOT NULL AND deleted_at < NOW() - make_interval(days => $1)",
+        older_than_days
+    )
+    .execute(executor)
+    .timed("posts::purge_deleted")
+    .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,111 @@
+// Users' notification preferences. Users without a row have the defaults,
+// which opt them out of everything.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::models::{NotificationPreferences, UpdateNotificationPreferences};
+use crate::repo::{emails, Timed};
+
+// A user whose digest is due.
+pub struct DigestRecipient {
+    pub user_id: i32,
+    pub tenant_id: i32,
+    pub username: String,
+    pub email: String,
+    // the posts published since are in the digest
+    pub since: DateTime<Utc>,
+}
+
+pub async fn get(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+) -> Result<NotificationPreferences, sqlx::Error> {
+    let preferences = sqlx::query_as!(
+        NotificationPreferences,
+        "SELECT weekly_digest FROM notification_preferences WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .timed("preferences::get")
+    .await?;
+    Ok(preferences.unwrap_or_default())
+}
+
+// Change the preferences given, returning them all.
+pub async fn update(
+    pool: &Pool<Postgres>,
+    user_id: i32,
+    update: &UpdateNotificationPreferences,
+) -> Result<NotificationPreferences, sqlx::Error> {
+    sqlx::query_as!(
+        NotificationPreferences,
+        "INSERT INTO notification_preferences (user_id, weekly_digest)
+         VALUES ($1, COALESCE($2, FALSE))
+         ON CONFLICT (user_id) DO UPDATE
+         SET weekly_digest = COALESCE($2, notification_preferences.weekly_digest),
+             updated_at = NOW()
+         RETURNING weekly_digest",
+        user_id,
+        update.weekly_digest
+    )
+    .fetch_one(pool)
+    .timed("preferences::update")
+    .await
+}
+
+// Take up to `limit` of the users, of every tenant, who opted into the
+// digest and last got one at least `period_secs` ago, marking it sent. The
+// first digest covers the last period.
+pub async fn claim_due_digests(
+    pool: &Pool<Postgres>,
+    period_secs: f64,
+    limit: i64,
+) -> Result<Vec<DigestRecipient>, sqlx::Error> {
+    let recipients = sqlx::query_as!(
+        DigestRecipient,
+        r#"WITH due AS (
+               SELECT user_id,
+                   COALESCE(digest_sent_at, NOW() - make_interval(secs => $1)) AS since
+               FROM notification_preferences
+               WHERE weekly_digest
+                   AND (digest_sent_at IS NULL OR digest_sent_at <= NOW() - make_interval(secs => $1))
+               ORDER BY user_id LIMIT $2
+               FOR UPDATE SKIP LOCKED
+           ), claimed AS (
+               UPDATE notification_preferences p SET digest_sent_at = NOW()
+               FROM due WHERE p.user_id = due.user_id
+               RETURNING p.user_id, due.since
+           )
+           SELECT u.id AS user_id, u.tenant_id, u.username, u.email, claimed.since AS "since!"
+           FROM claimed JOIN users u ON u.id = claimed.user_id
+           ORDER BY u.id"#,
+        period_secs,
+        limit
+    )
+    .fetch_all(pool)
+    .timed("preferences::claim_due_digests")
+    .await?;
+    recipients
+        .into_iter()
+        .map(|recipient| {
+            Ok(DigestRecipient {
+                email: emails::open(&recipient.email)?,
+                ..recipient
+            })
+        })
+        .collect()
+}
+
+// Turn the digest off for a user of any tenant, returning whether it was on.
+pub async fn unsubscribe(pool: &Pool<Postgres>, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE notification_preferences SET weekly_digest = FALSE, updated_at = NOW()
+         WHERE user_id = $1 AND weekly_digest",
+        user_id
+    )
+    .execute(pool)
+    .timed("preferences::unsubscribe")
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,112 @@
+// Reports of posts and comments by readers, see `POST /posts/:id/report`
+// and `POST /comments/:id/report`.
+
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+
+use crate::models::{Report, ReportOutcome};
+use crate::repo::Timed;
+use crate::tenant;
+
+// Report storage as seen by the handlers.
+#[async_trait]
+pub trait ReportRepository: Send + Sync {
+    // a report of the post, or of its comment `comment_id`; None when the
+    // reporter already reported it
+    async fn create(
+        &self,
+        post_id: i32,
+        comment_id: Option<i32>,
+        reporter_id: i32,
+        reason: &str,
+    ) -> Result<Option<Report>, sqlx::Error>;
+    // the number of readers with unresolved reports of a post itself, or of
+    // its comment `comment_id`
+    async fn count_open(&self, post_id: i32, comment_id: Option<i32>) -> Result<i64, sqlx::Error>;
+    // open or resolved reports, oldest first
+    async fn list(&self, resolved: bool) -> Result<Vec<Report>, sqlx::Error>;
+    // close an open report, failing with RowNotFound for unknown or already
+    // resolved ones
+    async fn resolve(
+        &self,
+        id: i32,
+        outcome: ReportOutcome,
+        note: Option<&str>,
+    ) -> Result<Report, sqlx::Error>;
+}
+
+pub struct PgReportRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgReportRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgReportRepository { pool }
+    }
+}
+
+#[async_trait]
+impl ReportRepository for PgReportRepository {
+    async fn create(
+        &self,
+        post_id: i32,
+        comment_id: Option<i32>,
+        reporter_id: i32,
+        reason: &str,
+    ) -> Result<Option<Report>, sqlx::Error> {
+        sqlx::query_as!(
+            Report,
+            "INSERT INTO reports (post_id, comment_id, reporter_id, reason, tenant_id) VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING RETURNING id, post_id, comment_id, reporter_id, reason, created_at, resolved_at, outcome",
+            post_id,
+            comment_id,
+            reporter_id,
+            reason,
+            tenant::current()
+        )
+        .fetch_optional(&self.pool)
+        .timed("reports::create")
+        .await
+    }
+
+    async fn count_open(&self, post_id: i32, comment_id: Option<i32>) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"SELECT COUNT(DISTINCT reporter_id) AS "count!" FROM reports WHERE post_id = $1 AND comment_id IS NOT DISTINCT FROM $2 AND resolved_at IS NULL"#,
+            post_id,
+            comment_id
+        )
+        .fetch_one(&self.pool)
+        .timed("reports::count_open")
+        .await
+    }
+
+    async fn list(&self, resolved: bool) -> Result<Vec<Report>, sqlx::Error> {
+        sqlx::query_as!(
+            Report,
+            "SELECT id, post_id, comment_id, reporter_id, reason, created_at, resolved_at, outcome FROM reports WHERE (resolved_at IS NOT NULL) = $1 AND tenant_id = $2 ORDER BY created_at, id",
+            resolved,
+            tenant::current()
+        )
+        .fetch_all(&self.pool)
+        .timed("reports::list")
+        .await
+    }
+
+    async fn resolve(
+        &self,
+        id: i32,
+        outcome: ReportOutcome,
+        note: Option<&str>,
+    ) -> Result<Report, sqlx::Error> {
+        sqlx::query_as!(
+            Report,
+            "UPDATE reports SET resolved_at = NOW(), outcome = $2, note = $3 WHERE id = $1 AND resolved_at IS NULL AND tenant_id = $4 RETURNING id, post_id, comment_id, reporter_id, reason, created_at, resolved_at, outcome",
+            id,
+            outcome.as_str(),
+            note,
+            tenant::current()
+        )
+        .fetch_one(&self.pool)
+        .timed("reports::resolve")
+        .await
+    }
+}
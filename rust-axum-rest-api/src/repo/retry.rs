@@ -0,0 +1,136 @@
+// Reads run again after transient errors: a serialization failure or
+// deadlock, a connection reset or closed by a restarting server, the pool
+// timing out. Up to DB_READ_RETRIES more times (2 by default, 0 for never),
+// after a backoff doubling from DB_RETRY_BACKOFF_MS (50 by default) with
+// half of it random, so that the retries of many requests don't all land
+// at once. Writes aren't retried, as one may have gone through before its
+// connection broke.
+//
+// The retries are counted by repository function for
+// `db_query_retries_total` (see `metrics`). Like `slow`, the settings and
+// counts are per process.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::repo::{breaker, Timed};
+
+// the longest backoff, however many retries
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryConfig {
+    // 0 for never
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl RetryConfig {
+    pub const DEFAULT: RetryConfig = RetryConfig {
+        retries: 2,
+        backoff: Duration::from_millis(50),
+    };
+
+    pub fn from_env() -> Result<RetryConfig, String> {
+        let number = |name: &str, default: u64| match std::env::var(name) {
+            Ok(value) => value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid {name} {value:?}, expected a number")),
+            Err(_) => Ok(default),
+        };
+        let defaults = RetryConfig::DEFAULT;
+        let retries = number("DB_READ_RETRIES", defaults.retries.into())?;
+        let retries = u32::try_from(retries)
+            .map_err(|_| format!("DB_READ_RETRIES {retries} is too large"))?;
+        let backoff = number("DB_RETRY_BACKOFF_MS", defaults.backoff.as_millis() as u64)?;
+        Ok(RetryConfig {
+            retries,
+            backoff: Duration::from_millis(backoff),
+        })
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig::DEFAULT
+    }
+}
+
+static RETRIES: AtomicU32 = AtomicU32::new(RetryConfig::DEFAULT.retries);
+
+static BACKOFF_MS: AtomicU64 = AtomicU64::new(RetryConfig::DEFAULT.backoff.as_millis() as u64);
+
+// retries so far, by name
+static COUNTS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+// Retry reads as `config` says from now on.
+pub fn configure(config: RetryConfig) {
+    RETRIES.store(config.retries, Ordering::Relaxed);
+    BACKOFF_MS.store(config.backoff.as_millis() as u64, Ordering::Relaxed);
+}
+
+// The retries so far, by name.
+pub fn counts() -> Vec<(&'static str, u64)> {
+    let counts = COUNTS.lock().expect("retry lock");
+    counts.iter().map(|(name, count)| (*name, *count)).collect()
+}
+
+// Whether `err` may well not happen again, the query being fine.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        // the breaker failed it fast, trying again would too
+        err if breaker::open(err).is_some() => false,
+        sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Io(io) => matches!(
+            io.kind(),
+            ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof
+        ),
+        // serialization failures, deadlocks, the server shutting down and
+        // connection exceptions
+        sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+            matches!(code.as_ref(), "40001" | "40P01" | "57P01") || code.starts_with("08")
+        }),
+        _ => false,
+    }
+}
+
+// How long to wait before the `retry`th retry, from 1.
+fn backoff(retry: u32) -> Duration {
+    let base = Duration::from_millis(BACKOFF_MS.load(Ordering::Relaxed));
+    let full = base
+        .saturating_mul(1 << (retry - 1).min(16))
+        .min(MAX_BACKOFF);
+    let half = full / 2;
+    half + half.mul_f64(rand::random())
+}
+
+// Run the read of the repository function `name` that `query` makes, timed,
+// and make it again after a transient error.
+pub async fn read<T, F, Fut>(name: &'static str, mut query: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut retry = 0;
+    loop {
+        match query().timed(name).await {
+            Err(err) if retry < RETRIES.load(Ordering::Relaxed) && is_transient(&err) => {
+                retry += 1;
+                *COUNTS.lock().expect("retry lock").entry(name).or_default() += 1;
+                let wait = backoff(retry);
+                debug!("retry {retry} of {name} in {wait:?} after {err}");
+                tokio::time::sleep(wait).await;
+            }
+            result => return result,
+        }
+    }
+}
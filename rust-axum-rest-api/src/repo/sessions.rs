@@ -0,0 +1,56 @@
+// Browser sessions kept in Postgres, see `sessions`. Rows are looked up by
+// the hash of the session id, of any tenant; the caller checks the tenant.
+
+use sqlx::{PgExecutor, Pool, Postgres};
+
+use crate::models::Session;
+use crate::repo::Timed;
+
+pub async fn insert(
+    pool: &Pool<Postgres>,
+    id_hash: &str,
+    session: &Session,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO sessions (id_hash, tenant_id, user_id, expires_at) VALUES ($1, $2, $3, $4)",
+        id_hash,
+        session.tenant_id,
+        session.user_id,
+        session.expires_at
+    )
+    .execute(pool)
+    .timed("sessions::insert")
+    .await?;
+    Ok(())
+}
+
+// The session under `id_hash` unless it expired.
+pub async fn get(pool: &Pool<Postgres>, id_hash: &str) -> Result<Option<Session>, sqlx::Error> {
+    sqlx::query_as!(
+        Session,
+        "SELECT tenant_id, user_id, expires_at FROM sessions WHERE id_hash = $1 AND expires_at > NOW()",
+        id_hash
+    )
+    .fetch_optional(pool)
+    .timed("sessions::get")
+    .await
+}
+
+// End a session; false when there was none.
+pub async fn delete(pool: &Pool<Postgres>, id_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM sessions WHERE id_hash = $1", id_hash)
+        .execute(pool)
+        .timed("sessions::delete")
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Remove the sessions that expired, of every tenant, returning how many were
+// removed.
+pub async fn purge_expired(executor: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!("DELETE FROM sessions WHERE expires_at <= NOW()")
+        .execute(executor)
+        .timed("sessions::purge_expired")
+        .await?;
+    Ok(result.rows_affected())
+}
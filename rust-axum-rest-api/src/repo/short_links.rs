@@ -0,0 +1,122 @@
+// Share links of posts, within the current tenant. Codes are unique across
+// tenants, a link only resolves in its own.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::{CountryClicks, ShortLink};
+use crate::repo::Timed;
+use crate::tenant;
+
+// Mint a link to a post under `code`; None when the code is taken.
+pub async fn create(
+    pool: &Pool<Postgres>,
+    code: &str,
+    post_id: i32,
+    created_by: Option<i32>,
+) -> Result<Option<ShortLink>, sqlx::Error> {
+    sqlx::query_as!(
+        ShortLink,
+        "INSERT INTO short_links (code, post_id, created_by, tenant_id) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (code) DO NOTHING
+         RETURNING code, post_id, created_by, clicks, last_clicked_at, created_at",
+        code,
+        post_id,
+        created_by,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("short_links::create")
+    .await
+}
+
+pub async fn get(pool: &Pool<Postgres>, code: &str) -> Result<Option<ShortLink>, sqlx::Error> {
+    sqlx::query_as!(
+        ShortLink,
+        "SELECT code, post_id, created_by, clicks, last_clicked_at, created_at
+         FROM short_links WHERE code = $1 AND tenant_id = $2",
+        code,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("short_links::get")
+    .await
+}
+
+// The links to a post, oldest first.
+pub async fn for_post(pool: &Pool<Postgres>, post_id: i32) -> Result<Vec<ShortLink>, sqlx::Error> {
+    sqlx::query_as!(
+        ShortLink,
+        "SELECT code, post_id, created_by, clicks, last_clicked_at, created_at
+         FROM short_links WHERE post_id = $1 AND tenant_id = $2
+         ORDER BY created_at, code",
+        post_id,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("short_links::for_post")
+    .await
+}
+
+// The public id of the published post a link goes to, without counting a
+// click; None like `click`.
+pub async fn target(pool: &Pool<Postgres>, code: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT p.public_id FROM short_links l JOIN posts p ON p.id = l.post_id
+         WHERE l.code = $1 AND l.tenant_id = $2 AND p.deleted_at IS NULL AND p.status = 'published'",
+        code,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("short_links::target")
+    .await
+}
+
+// Count a click on a link to a published post, from `country` if known,
+// returning the post's public id; None for unknown codes and posts that are
+// deleted or unpublished.
+pub async fn click(
+    pool: &Pool<Postgres>,
+    code: &str,
+    country: Option<&str>,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"WITH clicked AS (
+             UPDATE short_links l SET clicks = clicks + 1, last_clicked_at = NOW()
+             FROM posts p
+             WHERE l.code = $1 AND l.tenant_id = $2 AND p.id = l.post_id
+                 AND p.deleted_at IS NULL AND p.status = 'published'
+             RETURNING l.code, p.public_id
+         ), counted AS (
+             INSERT INTO short_link_countries (code, country, clicks)
+             SELECT code, $3, 1 FROM clicked WHERE $3::text IS NOT NULL
+             ON CONFLICT (code, country)
+                 DO UPDATE SET clicks = short_link_countries.clicks + 1
+         )
+         SELECT public_id AS "public_id!" FROM clicked"#,
+        code,
+        tenant::current(),
+        country
+    )
+    .fetch_optional(pool)
+    .timed("short_links::click")
+    .await
+}
+
+// Clicks on the post's links by country, most first.
+pub async fn countries_for_post(
+    pool: &Pool<Postgres>,
+    post_id: i32,
+) -> Result<Vec<CountryClicks>, sqlx::Error> {
+    sqlx::query_as!(
+        CountryClicks,
+        r#"SELECT c.country, SUM(c.clicks)::BIGINT AS "clicks!"
+         FROM short_link_countries c JOIN short_links l ON l.code = c.code
+         WHERE l.post_id = $1 AND l.tenant_id = $2
+         GROUP BY c.country ORDER BY 2 DESC, 1"#,
+        post_id,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("short_links::countries_for_post")
+    .await
+}
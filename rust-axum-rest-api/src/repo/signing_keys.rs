@@ -0,0 +1,89 @@
+// The keys signing the API's JWTs, see `jwt`. They belong to the server,
+// not to a tenant.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::repo::Timed;
+
+pub struct SigningKey {
+    pub kid: String,
+    pub public_key_pem: String,
+    pub private_key_pem: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// the key new tokens are signed with
+pub async fn current(pool: &Pool<Postgres>) -> Result<Option<SigningKey>, sqlx::Error> {
+    sqlx::query_as!(
+        SigningKey,
+        "SELECT kid, public_key_pem, private_key_pem, created_at FROM signing_keys WHERE retired_at IS NULL"
+    )
+    .fetch_optional(pool)
+    .timed("signing_keys::current")
+    .await
+}
+
+// The keys tokens may still be signed with: the current one and those
+// retired less than `grace` ago, newest first.
+pub async fn published(
+    pool: &Pool<Postgres>,
+    grace: Duration,
+) -> Result<Vec<SigningKey>, sqlx::Error> {
+    sqlx::query_as!(
+        SigningKey,
+        "SELECT kid, public_key_pem, private_key_pem, created_at FROM signing_keys
+         WHERE retired_at IS NULL OR retired_at > NOW() - make_interval(secs => $1)
+         ORDER BY created_at DESC",
+        grace.num_seconds() as f64
+    )
+    .fetch_all(pool)
+    .timed("signing_keys::published")
+    .await
+}
+
+// Make `key` the current key, retiring the one before it and dropping the
+// keys retired more than `grace` ago. With `only_first`, nothing happens
+// when there already is a current key, so that concurrent first uses agree
+// on one.
+pub async fn rotate(
+    pool: &Pool<Postgres>,
+    key: &SigningKey,
+    grace: Duration,
+    only_first: bool,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // one rotation at a time
+    sqlx::query!("LOCK TABLE signing_keys IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .timed("signing_keys::rotate")
+        .await?;
+    let retired =
+        sqlx::query!("UPDATE signing_keys SET retired_at = NOW() WHERE retired_at IS NULL")
+            .execute(&mut *tx)
+            .timed("signing_keys::rotate")
+            .await?;
+    if only_first && retired.rows_affected() > 0 {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    sqlx::query!(
+        "INSERT INTO signing_keys (kid, public_key_pem, private_key_pem, created_at) VALUES ($1, $2, $3, $4)",
+        key.kid,
+        key.public_key_pem,
+        key.private_key_pem,
+        key.created_at
+    )
+    .execute(&mut *tx)
+    .timed("signing_keys::rotate")
+    .await?;
+    sqlx::query!(
+        "DELETE FROM signing_keys WHERE retired_at < NOW() - make_interval(secs => $1)",
+        grace.num_seconds() as f64
+    )
+    .execute(&mut *tx)
+    .timed("signing_keys::rotate")
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
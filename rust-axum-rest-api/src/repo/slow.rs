@@ -0,0 +1,71 @@
+// Slow query logging: every query of the repositories is `timed`, which logs those that took longer than the `slow_query_ms` tunable (see
+// `tunables`, 500 by default) as warnings under the `slow_query` target,
+// with the name of the repository function and how long it took, and counts
+// them by name for `db_slow_queries_total` (see `metrics`). The time is the
+// caller's, so waiting for a pooled connection counts too.
+//
+// The threshold and counts are per process rather than per `AppState`, as
+// the repository functions are handed nothing but a pool.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::repo::breaker;
+
+pub const DEFAULT_THRESHOLD_MS: u64 = 500;
+
+static THRESHOLD_MS: AtomicU64 = AtomicU64::new(DEFAULT_THRESHOLD_MS);
+
+// slow queries so far, by name
+static COUNTS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+// Log queries slower than `threshold` from now on.
+pub fn set_threshold(threshold: Duration) {
+    THRESHOLD_MS.store(threshold.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub fn threshold() -> Duration {
+    Duration::from_millis(THRESHOLD_MS.load(Ordering::Relaxed))
+}
+
+// The slow queries so far, by name.
+pub fn counts() -> Vec<(&'static str, u64)> {
+    let counts = COUNTS.lock().expect("slow query lock");
+    counts.iter().map(|(name, count)| (*name, *count)).collect()
+}
+
+pub trait Timed<T>: Future<Output = Result<T, sqlx::Error>> + Sized {
+    // Run the query of the repository function `name`, logging it if it was
+    // slow, unless the circuit breaker is open (see `breaker`).
+    fn timed(self, name: &'static str) -> impl Future<Output = Self::Output>;
+}
+
+impl<T, F: Future<Output = Result<T, sqlx::Error>>> Timed<T> for F {
+    async fn timed(self, name: &'static str) -> F::Output {
+        let breaker = breaker::global();
+        breaker.check()?;
+        let started = Instant::now();
+        let output = self.await;
+        breaker.record(&output);
+        let elapsed = started.elapsed();
+        if elapsed > threshold() {
+            warn!(
+                target: "slow_query",
+                query = name,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "slow query {name}"
+            );
+            *COUNTS
+                .lock()
+                .expect("slow query lock")
+                .entry(name)
+                .or_default() += 1;
+        }
+        output
+    }
+}
@@ -0,0 +1,101 @@
+// Stripe subscriptions of users and organizations, and what they count
+// against. The webhook knows no tenant, subscriptions are kept in their
+// owner's.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::models::{Plan, Subscription};
+use crate::repo::Timed;
+
+// Stripe statuses that keep the plan: unpaid invoices are retried for a while
+// before the subscription is canceled
+const IN_GOOD_STANDING: &[&str] = &["active", "trialing", "past_due"];
+
+// A subscription as Stripe describes it.
+pub struct SubscriptionSync {
+    pub stripe_subscription_id: String,
+    pub stripe_customer_id: String,
+    pub user_id: Option<i32>,
+    pub org_id: Option<i32>,
+    pub plan: Plan,
+    pub status: String,
+    pub current_period_end: Option<DateTime<Utc>>,
+}
+
+// Record a subscription or its changes; None when its owner doesn't exist.
+pub async fn sync(
+    pool: &Pool<Postgres>,
+    sync: &SubscriptionSync,
+) -> Result<Option<Subscription>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscription,
+        "INSERT INTO subscriptions (stripe_subscription_id, stripe_customer_id, user_id, org_id,
+             plan, status, current_period_end, tenant_id)
+         SELECT $1, $2, $3, $4, $5, $6, $7, owner.tenant_id
+         FROM (SELECT tenant_id FROM users WHERE id = $3
+               UNION ALL SELECT tenant_id FROM organizations WHERE id = $4) owner
+         ON CONFLICT (stripe_subscription_id) DO UPDATE
+         SET plan = EXCLUDED.plan, status = EXCLUDED.status,
+             current_period_end = EXCLUDED.current_period_end, updated_at = NOW()
+         RETURNING stripe_subscription_id, stripe_customer_id, user_id, org_id, plan, status,
+             current_period_end, updated_at",
+        sync.stripe_subscription_id,
+        sync.stripe_customer_id,
+        sync.user_id,
+        sync.org_id,
+        sync.plan.as_str(),
+        sync.status,
+        sync.current_period_end
+    )
+    .fetch_optional(pool)
+    .timed("subscriptions::sync")
+    .await
+}
+
+// the plans of the user's subscriptions in good standing
+pub async fn user_plans(pool: &Pool<Postgres>, user_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT plan FROM subscriptions WHERE user_id = $1 AND status = ANY($2)",
+        user_id,
+        IN_GOOD_STANDING as &[&str]
+    )
+    .fetch_all(pool)
+    .timed("subscriptions::user_plans")
+    .await
+}
+
+// the plans of the organization's subscriptions in good standing
+pub async fn org_plans(pool: &Pool<Postgres>, org_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT plan FROM subscriptions WHERE org_id = $1 AND status = ANY($2)",
+        org_id,
+        IN_GOOD_STANDING as &[&str]
+    )
+    .fetch_all(pool)
+    .timed("subscriptions::org_plans")
+    .await
+}
+
+// the posts a user has outside organizations, deleted ones aside
+pub async fn user_post_count(pool: &Pool<Postgres>, user_id: i32) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM posts
+           WHERE user_id = $1 AND org_id IS NULL AND deleted_at IS NULL"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .timed("subscriptions::user_post_count")
+    .await
+}
+
+// the posts of an organization, deleted ones aside
+pub async fn org_post_count(pool: &Pool<Postgres>, org_id: i32) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM posts WHERE org_id = $1 AND deleted_at IS NULL"#,
+        org_id
+    )
+    .fetch_one(pool)
+    .timed("subscriptions::org_post_count")
+    .await
+}
@@ -0,0 +1,39 @@
+// Tenants, looked up by `tenant::scope` on every request that names one.
+
+use sqlx::{Pool, Postgres};
+
+use crate::models::{CreateTenant, Tenant};
+use crate::repo::Timed;
+
+pub async fn find(pool: &Pool<Postgres>, slug: &str) -> Result<Option<Tenant>, sqlx::Error> {
+    sqlx::query_as!(
+        Tenant,
+        "SELECT id, slug, name, created_at FROM tenants WHERE slug = $1",
+        slug
+    )
+    .fetch_optional(pool)
+    .timed("tenants::find")
+    .await
+}
+
+pub async fn list(pool: &Pool<Postgres>) -> Result<Vec<Tenant>, sqlx::Error> {
+    sqlx::query_as!(
+        Tenant,
+        "SELECT id, slug, name, created_at FROM tenants ORDER BY id"
+    )
+    .fetch_all(pool)
+    .timed("tenants::list")
+    .await
+}
+
+pub async fn create(pool: &Pool<Postgres>, tenant: &CreateTenant) -> Result<Tenant, sqlx::Error> {
+    sqlx::query_as!(
+        Tenant,
+        "INSERT INTO tenants (slug, name) VALUES ($1, $2) RETURNING id, slug, name, created_at",
+        tenant.slug,
+        tenant.name
+    )
+    .fetch_one(pool)
+    .timed("tenants::create")
+    .await
+}
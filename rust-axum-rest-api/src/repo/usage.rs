@@ -0,0 +1,67 @@
+// Usage counters of users and organizations per billing period. Written in
+// batches by the meter in `usage`, which keys them by tenant already.
+
+use chrono::NaiveDate;
+use sqlx::{Pool, Postgres};
+
+use crate::repo::Timed;
+
+// Counts to add, one entry per counter in each of the columns.
+#[derive(Default)]
+pub struct UsageBatch {
+    pub tenant_ids: Vec<i32>,
+    pub user_ids: Vec<Option<i32>>,
+    pub org_ids: Vec<Option<i32>>,
+    pub periods: Vec<NaiveDate>,
+    pub metrics: Vec<&'static str>,
+    pub counts: Vec<i64>,
+}
+
+// What a user, or an organization, used of `metric` in the period starting
+// at `period_start`.
+pub async fn get(
+    pool: &Pool<Postgres>,
+    user_id: Option<i32>,
+    org_id: Option<i32>,
+    period_start: NaiveDate,
+    metric: &str,
+) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query_scalar!(
+        "SELECT count FROM usage
+         WHERE user_id IS NOT DISTINCT FROM $1 AND org_id IS NOT DISTINCT FROM $2
+             AND period_start = $3 AND metric = $4",
+        user_id,
+        org_id,
+        period_start,
+        metric
+    )
+    .fetch_optional(pool)
+    .timed("usage::get")
+    .await?;
+    Ok(count.unwrap_or(0))
+}
+
+// Add a batch of counts in one statement. Counts of users and organizations
+// deleted since are dropped.
+pub async fn add(pool: &Pool<Postgres>, batch: &UsageBatch) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO usage (tenant_id, user_id, org_id, period_start, metric, count)
+         SELECT batch.* FROM UNNEST($1::int4[], $2::int4[], $3::int4[], $4::date[], $5::text[],
+             $6::int8[]) AS batch (tenant_id, user_id, org_id, period_start, metric, count)
+         WHERE (batch.user_id IS NULL OR EXISTS (SELECT 1 FROM users WHERE id = batch.user_id))
+             AND (batch.org_id IS NULL
+                 OR EXISTS (SELECT 1 FROM organizations WHERE id = batch.org_id))
+         ON CONFLICT (user_id, org_id, period_start, metric) DO UPDATE
+         SET count = usage.count + EXCLUDED.count, updated_at = NOW()",
+        &batch.tenant_ids,
+        &batch.user_ids as &[Option<i32>],
+        &batch.org_ids as &[Option<i32>],
+        &batch.periods,
+        &batch.metrics as &[&str],
+        &batch.counts
+    )
+    .execute(pool)
+    .timed("usage::add")
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::models::{CreateUser, Role, User};
+use crate::repo::{emails, retry, Timed};
+use crate::tenant;
+
+// User storage as seen by the handlers.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn create(&self, user: &CreateUser) -> Result<User, sqlx::Error>;
+    async fn get(&self, id: i32) -> Result<User, sqlx::Error>;
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<User, sqlx::Error>;
+    // whether the username (ignoring case) and the email are in use, by the
+    // same or different users; both are always looked up
+    async fn taken(&self, username: &str, email: &str) -> Result<(bool, bool), sqlx::Error>;
+    // the emails of those of `ids` that exist, for their Gravatars
+    async fn emails(&self, ids: &[i32]) -> Result<Vec<(i32, String)>, sqlx::Error>;
+}
+
+pub struct PgUserRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgUserRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgUserRepository { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository {
+    async fn create(&self, user: &CreateUser) -> Result<User, sqlx::Error> {
+        let email = emails::seal(&user.email);
+        // a row from before the key with the address in the clear gets the
+        // address in the clear again, for the old constraint to refuse the
+        // duplicate a sealed copy would slip past
+        let created = sqlx::query_as!(
+            User,
+            r#"WITH clear AS (
+                   SELECT EXISTS (SELECT 1 FROM users WHERE tenant_id = $4 AND email_hash IS NULL AND email = $5) AS taken
+               )
+               INSERT INTO users (username, email, email_hash, tenant_id)
+               SELECT $1, CASE WHEN taken THEN $5 ELSE $2 END, CASE WHEN taken THEN NULL ELSE $3 END, $4 FROM clear
+               RETURNING id, uuid, username, email, avatar_url, posts_count, created_at, updated_at"#,
+            user.username,
+            email.stored,
+            email.hash,
+            tenant::current(),
+            user.email
+        )
+        .fetch_one(&self.pool)
+        .timed("users::create")
+        .await?;
+        emails::open_user(created)
+    }
+
+    async fn get(&self, id: i32) -> Result<User, sqlx::Error> {
+        let user = retry::read("users::get", move || {
+            sqlx::query_as!(
+                User,
+                "SELECT id, uuid, username, email, avatar_url, posts_count, created_at, updated_at FROM users WHERE id = $1 AND tenant_id = $2",
+                id,
+                tenant::current()
+            )
+            .fetch_one(&self.pool)
+        })
+        .await?;
+        emails::open_user(user)
+    }
+
+    async fn get_by_uuid(&self, uuid: Uuid) -> Result<User, sqlx::Error> {
+        let user = retry::read("users::get_by_uuid", move || {
+            sqlx::query_as!(
+                User,
+                "SELECT id, uuid, username, email, avatar_url, posts_count, created_at, updated_at FROM users WHERE uuid = $1 AND tenant_id = $2",
+                uuid,
+                tenant::current()
+            )
+            .fetch_one(&self.pool)
+        })
+        .await?;
+        emails::open_user(user)
+    }
+
+    async fn taken(&self, username: &str, email: &str) -> Result<(bool, bool), sqlx::Error> {
+        let hash = emails::hash(email);
+        let hash = hash.as_deref();
+        let row = retry::read("users::taken", move || {
+            // sealed by its hash, or still in the clear
+            sqlx::query!(
+                r#"SELECT EXISTS (SELECT 1 FROM users WHERE tenant_id = $3 AND LOWER(username) = LOWER($1)) AS "username!",
+                      EXISTS (SELECT 1 FROM users WHERE tenant_id = $3 AND (email_hash = $4 OR email = $2)) AS "email!""#,
+                username,
+                email,
+                tenant::current(),
+                hash
+            )
+            .fetch_one(&self.pool)
+        })
+        .await?;
+        Ok((row.username, row.email))
+    }
+
+    async fn emails(&self, ids: &[i32]) -> Result<Vec<(i32, String)>, sqlx::Error> {
+        let rows = retry::read("users::emails", move || {
+            sqlx::query!(
+                "SELECT id, email FROM users WHERE id = ANY($1) AND tenant_id = $2",
+                ids,
+                tenant::current()
+            )
+            .fetch_all(&self.pool)
+        })
+        .await?;
+        rows.into_iter()
+            .map(|row| Ok((row.id, emails::open(&row.email)?)))
+            .collect()
+    }
+}
+
+pub async fn find_by_username(
+    pool: &Pool<Postgres>,
+    username: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    let user = retry::read("users::find_by_username", move || {
+        sqlx::query_as!(
+            User,
+            "SELECT id, uuid, username, email, avatar_url, posts_count, created_at, updated_at FROM users WHERE tenant_id = $2 AND LOWER(username) = LOWER($1)",
+            username,
+            tenant::current()
+        )
+        .fetch_optional(pool)
+    })
+    .await?;
+    user.map(emails::open_user).transpose()
+}
+
+pub async fn find_by_email(
+    pool: &Pool<Postgres>,
+    email: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    let hash = emails::hash(email);
+    let hash = hash.as_deref();
+    let user = retry::read("users::find_by_email", move || {
+        sqlx::query_as!(
+            User,
+            "SELECT id, uuid, username, email, avatar_url, posts_count, created_at, updated_at FROM users WHERE tenant_id = $2 AND (email_hash = $3 OR email = $1)",
+            email,
+            tenant::current(),
+            hash
+        )
+        .fetch_optional(pool)
+    })
+    .await?;
+    user.map(emails::open_user).transpose()
+}
+
+// Change the role of a user, `None` when there is no such user. Nothing
+// changes unless the user can be read back, their email opened included.
+pub async fn set_role(
+    pool: &Pool<Postgres>,
+    username: &str,
+    role: Role,
+) -> Result<Option<User>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let user = sqlx::query_as!(
+        User,
+        "UPDATE users SET role = $1, updated_at = NOW() WHERE tenant_id = $3 AND LOWER(username) = LOWER($2) RETURNING id, uuid, username, email, avatar_url, posts_count, created_at, updated_at",
+        role.as_str(),
+        username,
+        tenant::current()
+    )
+    .fetch_optional(&mut *tx)
+    .timed("users::set_role")
+    .await?;
+    let user = user.map(emails::open_user).transpose()?;
+    tx.commit().await?;
+    Ok(user)
+}
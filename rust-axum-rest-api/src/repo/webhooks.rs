@@ -0,0 +1,268 @@
+// Webhooks' storage: the endpoints, per tenant, and the queue of deliveries
+// to them. The delivery worker runs outside any request, so the queue isn't
+// scoped; deliveries belong to an endpoint, and with it to its tenant.
+//
+// Unlike API keys, endpoint secrets are stored as they are, since every
+// delivery is signed with them.
+
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::models::{WebhookDelivery, WebhookEndpoint};
+use crate::pagination::Page;
+use crate::repo::api_keys::hex;
+use crate::repo::Timed;
+use crate::tenant;
+
+pub const SECRET_PREFIX: &str = "whsec_";
+
+// A queued delivery along with where it goes.
+pub struct Delivery {
+    pub id: i64,
+    pub endpoint_id: i32,
+    pub event_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub payload: Value,
+    pub attempts: i32,
+}
+
+// Create an endpoint with a fresh secret, returned this once.
+pub async fn create(
+    pool: &Pool<Postgres>,
+    url: &str,
+    events: &[String],
+) -> Result<WebhookEndpoint, sqlx::Error> {
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    let secret = format!("{SECRET_PREFIX}{}", hex(&secret));
+    sqlx::query_as!(
+        WebhookEndpoint,
+        r#"INSERT INTO webhook_endpoints (url, secret, events, tenant_id) VALUES ($1, $2, $3, $4)
+           RETURNING id, url, events, secret AS "secret?", consecutive_failures, disabled_at,
+               created_at"#,
+        url,
+        secret,
+        events,
+        tenant::current()
+    )
+    .fetch_one(pool)
+    .timed("webhooks::create")
+    .await
+}
+
+pub async fn list(pool: &Pool<Postgres>) -> Result<Vec<WebhookEndpoint>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookEndpoint,
+        r#"SELECT id, url, events, NULL::text AS "secret?", consecutive_failures, disabled_at,
+               created_at
+           FROM webhook_endpoints WHERE tenant_id = $1 ORDER BY id"#,
+        tenant::current()
+    )
+    .fetch_all(pool)
+    .timed("webhooks::list")
+    .await
+}
+
+// Delete an endpoint along with its queued deliveries.
+pub async fn delete(pool: &Pool<Postgres>, id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM webhook_endpoints WHERE id = $1 AND tenant_id = $2",
+        id,
+        tenant::current()
+    )
+    .execute(pool)
+    .timed("webhooks::delete")
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+// Enable an endpoint again, its queued deliveries due right away.
+pub async fn enable(
+    pool: &Pool<Postgres>,
+    id: i32,
+) -> Result<Option<WebhookEndpoint>, sqlx::Error> {
+    let endpoint = sqlx::query_as!(
+        WebhookEndpoint,
+        r#"UPDATE webhook_endpoints SET disabled_at = NULL, consecutive_failures = 0
+           WHERE id = $1 AND tenant_id = $2
+           RETURNING id, url, events, NULL::text AS "secret?", consecutive_failures, disabled_at,
+               created_at"#,
+        id,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("webhooks::enable")
+    .await?;
+    if endpoint.is_some() {
+        sqlx::query!(
+            "UPDATE webhook_deliveries SET next_attempt_at = NOW()
+             WHERE endpoint_id = $1 AND delivered_at IS NULL AND failed_at IS NULL",
+            id
+        )
+        .execute(pool)
+        .timed("webhooks::enable")
+        .await?;
+    }
+    Ok(endpoint)
+}
+
+// Queue an event for each of the current tenant's enabled endpoints
+// subscribed to `event_type`, returning how many there were.
+pub async fn enqueue(
+    pool: &Pool<Postgres>,
+    event_id: Uuid,
+    event_type: &str,
+    payload: &Value,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, payload)
+         SELECT id, $2, $3, $4 FROM webhook_endpoints
+         WHERE tenant_id = $1 AND disabled_at IS NULL
+             AND (cardinality(events) = 0 OR $3 = ANY(events))",
+        tenant::current(),
+        event_id,
+        event_type,
+        payload
+    )
+    .execute(pool)
+    .timed("webhooks::enqueue")
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// Take up to `limit` deliveries to enabled endpoints that are due, oldest
+// first, leased for `lease_secs` like federation's.
+pub async fn claim_due(
+    pool: &Pool<Postgres>,
+    limit: i64,
+    lease_secs: f64,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        "WITH claimed AS (
+             UPDATE webhook_deliveries SET next_attempt_at = NOW() + make_interval(secs => $2)
+             WHERE id IN (
+                 SELECT d.id FROM webhook_deliveries d
+                 JOIN webhook_endpoints e ON e.id = d.endpoint_id AND e.disabled_at IS NULL
+                 WHERE d.delivered_at IS NULL AND d.failed_at IS NULL AND d.next_attempt_at <= NOW()
+                 ORDER BY d.id LIMIT $1
+                 FOR UPDATE OF d SKIP LOCKED
+             )
+             RETURNING id, endpoint_id, event_id, payload, attempts
+         )
+         SELECT claimed.id, claimed.endpoint_id, claimed.event_id, e.url, e.secret, claimed.payload, claimed.attempts
+         FROM claimed JOIN webhook_endpoints e ON e.id = claimed.endpoint_id
+         ORDER BY claimed.id",
+        limit,
+        lease_secs
+    )
+    .fetch_all(pool)
+    .timed("webhooks::claim_due")
+    .await
+}
+
+// Record a successful attempt, which also counts as one for the endpoint.
+pub async fn delivered(pool: &Pool<Postgres>, delivery: &Delivery) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET delivered_at = NOW(), attempts = attempts + 1, last_error = NULL
+         WHERE id = $1",
+        delivery.id
+    )
+    .execute(pool)
+    .timed("webhooks::delivered")
+    .await?;
+    sqlx::query!(
+        "UPDATE webhook_endpoints SET consecutive_failures = 0 WHERE id = $1",
+        delivery.endpoint_id
+    )
+    .execute(pool)
+    .timed("webhooks::delivered")
+    .await?;
+    Ok(())
+}
+
+// Record a failed attempt, to be retried at `retry_at`, or given up on when
+// that is None. The endpoint is disabled once it has failed `disable_after`
+// times in a row; returns whether that happened now.
+pub async fn failed(
+    pool: &Pool<Postgres>,
+    delivery: &Delivery,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+    disable_after: i32,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET attempts = attempts + 1, last_error = $2,
+             next_attempt_at = COALESCE($3, next_attempt_at),
+             failed_at = CASE WHEN $3::timestamptz IS NULL THEN NOW() END
+         WHERE id = $1",
+        delivery.id,
+        error,
+        retry_at
+    )
+    .execute(pool)
+    .timed("webhooks::failed")
+    .await?;
+    let disabled = sqlx::query_scalar!(
+        r#"UPDATE webhook_endpoints SET consecutive_failures = consecutive_failures + 1,
+               disabled_at = CASE
+                   WHEN disabled_at IS NULL AND consecutive_failures + 1 >= $2 THEN NOW()
+                   ELSE disabled_at
+               END
+           WHERE id = $1
+           RETURNING consecutive_failures = $2 AS "disabled!""#,
+        delivery.endpoint_id,
+        disable_after
+    )
+    .fetch_optional(pool)
+    .timed("webhooks::failed")
+    .await?;
+    Ok(disabled.unwrap_or(false))
+}
+
+// The current tenant's deliveries that were given up on, most recent first.
+pub async fn dead_letters(
+    pool: &Pool<Postgres>,
+    page: Option<Page>,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"SELECT d.id, d.endpoint_id, d.event_id, d.event_type, d.attempts, d.last_error,
+               d.next_attempt_at, d.delivered_at, d.failed_at, d.created_at
+           FROM webhook_deliveries d JOIN webhook_endpoints e ON e.id = d.endpoint_id
+           WHERE d.failed_at IS NOT NULL AND e.tenant_id = $1
+           ORDER BY d.failed_at DESC, d.id DESC LIMIT $2 OFFSET $3"#,
+        tenant::current(),
+        page.map(Page::limit),
+        page.map_or(0, Page::offset)
+    )
+    .fetch_all(pool)
+    .timed("webhooks::dead_letters")
+    .await
+}
+
+// Queue one of the current tenant's deliveries again, whether it was given
+// up on or delivered, with a fresh round of attempts.
+pub async fn redeliver(
+    pool: &Pool<Postgres>,
+    id: i64,
+) -> Result<Option<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"UPDATE webhook_deliveries d
+           SET attempts = 0, next_attempt_at = NOW(), delivered_at = NULL, failed_at = NULL
+           FROM webhook_endpoints e
+           WHERE d.id = $1 AND e.id = d.endpoint_id AND e.tenant_id = $2
+           RETURNING d.id, d.endpoint_id, d.event_id, d.event_type, d.attempts, d.last_error,
+               d.next_attempt_at, d.delivered_at, d.failed_at, d.created_at"#,
+        id,
+        tenant::current()
+    )
+    .fetch_optional(pool)
+    .timed("webhooks::redeliver")
+    .await
+}
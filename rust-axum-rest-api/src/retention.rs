@@ -0,0 +1,123 @@
+// Data retention: a background job permanently deleting what is past
+// keeping, for every tenant:
+//
+//     posts                 soft-deleted more than RETENTION_DAYS ago
+//     impersonation tokens  expired
+//     invitations           expired unaccepted, or revoked, more than
+//                           RETENTION_DAYS ago
+//
+// Users aren't soft-deleted, the API has no way to delete them. The job runs
+// every `INTERVAL` with RETENTION_DAYS set, and not at all without. With
+// RETENTION_DRY_RUN=true it only reports what it would delete: the deletes
+// run in a transaction that is rolled back. Each run logs its report, with
+// the counts as fields for log-based metrics. One instance at a time runs it,
+// see `jobs`.
+
+use std::fmt;
+use std::time::Duration;
+
+use sqlx::{Pool, Postgres};
+use tracing::{error, info};
+
+use crate::jobs;
+use crate::locks::Locks;
+use crate::repo;
+
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Retention settings, see the top of the module
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retention {
+    // RETENTION_DAYS, how long soft-deleted data is kept
+    pub days: i32,
+    // RETENTION_DRY_RUN=true|false, off by default
+    pub dry_run: bool,
+}
+
+impl Retention {
+    pub fn from_env() -> Result<Option<Retention>, String> {
+        let days = match std::env::var("RETENTION_DAYS") {
+            Ok(days) => match days.parse() {
+                Ok(days) if days >= 0 => days,
+                _ => {
+                    return Err(format!(
+                        "invalid RETENTION_DAYS {days:?}, expected a number of days"
+                    ))
+                }
+            },
+            Err(_) => return Ok(None),
+        };
+        let dry_run = match std::env::var("RETENTION_DRY_RUN") {
+            Ok(value) => value.parse().map_err(|_| {
+                format!("invalid RETENTION_DRY_RUN {value:?}, expected true or false")
+            })?,
+            Err(_) => false,
+        };
+        Ok(Some(Retention { days, dry_run }))
+    }
+}
+
+// What a purge deleted, or would have in a dry run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub posts: u64,
+    pub impersonation_tokens: u64,
+    pub invitations: u64,
+    pub dry_run: bool,
+}
+
+impl fmt::Display for PurgeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.dry_run {
+            "would have purged"
+        } else {
+            "purged"
+        };
+        write!(
+            f,
+            "{verb} {} posts, {} impersonation tokens and {} invitations",
+            self.posts, self.impersonation_tokens, self.invitations
+        )
+    }
+}
+
+// Purge once, see the top of the module.
+pub async fn purge(
+    pool: &Pool<Postgres>,
+    retention: Retention,
+) -> Result<PurgeReport, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let report = PurgeReport {
+        posts: repo::posts::purge_deleted(&mut *tx, retention.days).await?,
+        impersonation_tokens: repo::impersonation::purge_expired(&mut *tx).await?,
+        invitations: repo::invitations::purge_expired(&mut *tx, retention.days).await?,
+        dry_run: retention.dry_run,
+    };
+    if retention.dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+    Ok(report)
+}
+
+// Purge every `INTERVAL` until the process exits.
+pub fn spawn(pool: Pool<Postgres>, locks: Locks, retention: Retention) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(INTERVAL);
+        loop {
+            interval.tick().await;
+            match jobs::exclusive(&locks, "retention", purge(&pool, retention)).await {
+                Ok(None) => {}
+                Ok(Some(report)) => info!(
+                    posts = report.posts,
+                    impersonation_tokens = report.impersonation_tokens,
+                    invitations = report.invitations,
+                    dry_run = report.dry_run,
+                    "retention: {report}"
+                ),
+                Err(e) => error!("purging old data failed: {e}"),
+            }
+        }
+    });
+}
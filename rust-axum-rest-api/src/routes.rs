@@ -0,0 +1,305 @@
+// The application's routes.
+
+use axum::middleware;
+use axum::routing::{delete, get, post, put};
+use axum::Router;
+use tower_http::catch_panic::CatchPanicLayer;
+
+use crate::state::AppState;
+use crate::{
+    activity, archive, audit, auth, authors, billing, case, client_ip, comments, envelope, error,
+    export, federation, flags, follows, graphql, handlers, hashtags, impersonation, invitations,
+    ip_filter, jobs, jwt, maintenance, mentions, meta, methods, metrics, moderation, oembed, oidc,
+    orgs, panic, preferences, profiling, qr, request_id, sessions, short_links, tenant, tunables,
+    unsubscribe, usage, webhooks,
+};
+
+// the entry points listed in the body of 404 responses
+pub const TOP_LEVEL_ROUTES: &[&str] = &["/", "/posts", "/users"];
+
+// templates of the routes that `links` points at
+pub const POST_ROUTE: &str = "/posts/:id";
+pub const USER_ROUTE: &str = "/users/:id";
+pub const COMMENTS_ROUTE: &str = "/posts/:id/comments";
+
+// fill the `:id` of a route template
+pub fn expand(template: &str, id: &str) -> String {
+    template.replace(":id", id)
+}
+
+// Which routes a listener serves (see `server`): everything, or split
+// between the public API and /admin when a listener of its own is given to
+// the latter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Surface {
+    All,
+    Api,
+    // /admin and /readyz, without CORS or the maintenance guard: nothing
+    // there is for browsers, and admins have to get through in maintenance
+    Admin,
+}
+
+// build the router for our application, used by the server binary as well as
+// the integration tests
+pub fn build_router(state: AppState) -> Router {
+    build_router_for(state, Surface::All)
+}
+
+// the router of the listeners serving `surface`
+pub fn build_router_for(state: AppState, surface: Surface) -> Router {
+    let routes = match surface {
+        Surface::All => api_routes().merge(admin_routes()),
+        Surface::Api => api_routes(),
+        Surface::Admin => admin_routes().route("/readyz", get(maintenance::readyz)),
+    };
+    let router = routes
+        .fallback(handlers::not_found)
+        // the envelope and key case of successful JSON responses, inside
+        // `method_semantics` so that its headers describe the final body
+        .layer(middleware::from_fn(envelope::wrap))
+        .layer(middleware::from_fn(case::rewrite))
+        .layer(middleware::from_fn_with_state(state.clone(), audit::log))
+        // inside `auth::actor`, to know who is acting
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            billing::enforce_limits,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), auth::actor))
+        .route_layer(middleware::from_fn(metrics::matched))
+        .with_state(state.clone());
+
+    let mut app = Router::new();
+    if surface != Surface::Admin {
+        // oEmbed's format is fixed by its spec, so it is served outside the
+        // envelope and key case
+        app = app
+            .route("/oembed", get(oembed::oembed))
+            .route_layer(middleware::from_fn(metrics::matched));
+    }
+    // correct HEAD, OPTIONS and 405 answers for all of the above; wrapped around
+    // the whole router rather than layered onto the routes so the middleware
+    // sees the Allow header axum adds to its 405 responses
+    let mut app = app
+        .with_state(state.clone())
+        .fallback_service(router)
+        .layer(middleware::from_fn(methods::method_semantics))
+        .layer(CatchPanicLayer::custom(panic::handle_panic));
+    if surface != Surface::Admin {
+        app = app.layer(middleware::from_fn_with_state(
+            state.clone(),
+            maintenance::guard,
+        ));
+    }
+    let app = app
+        // before anything else can tell a turned away client about the API
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            ip_filter::guard,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), tenant::scope))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            envelope::scope,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), case::scope))
+        .layer(middleware::from_fn_with_state(state.clone(), error::scope))
+        // around everything that can answer, turned away clients and panics
+        // included
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::record,
+        ))
+        // inside `request_id`, for the access log to have it
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            client_ip::resolve,
+        ))
+        .layer(middleware::from_fn(request_id::request_id));
+    if surface == Surface::Admin {
+        return app;
+    }
+    // outermost, so that preflight requests are answered before anything
+    // else and errors carry the CORS headers too
+    app.layer(middleware::from_fn_with_state(state, tunables::cors))
+}
+
+// everything but /admin
+fn api_routes() -> Router<AppState> {
+    Router::new()
+        // `GET /` goes to `root`
+        .route("/", get(handlers::root))
+        .route("/readyz", get(maintenance::readyz))
+        .route(
+            "/posts",
+            get(handlers::get_posts).post(handlers::create_post),
+        )
+        .route("/posts/count", get(handlers::count_posts))
+        .route("/posts/export.ndjson", get(export::posts_ndjson))
+        .route("/posts.csv", get(export::posts_csv))
+        .route(
+            POST_ROUTE,
+            get(handlers::get_post)
+                .put(handlers::update_post)
+                .delete(handlers::delete_post),
+        )
+        .route("/posts/:id/history", get(handlers::get_post_history))
+        .route("/posts/:id/authors", post(authors::add_author))
+        .route(
+            COMMENTS_ROUTE,
+            get(comments::list_comments).post(comments::create_comment),
+        )
+        .route(
+            "/posts/:id/authors/:user_id",
+            delete(authors::remove_author),
+        )
+        .route("/posts/:id/meta", get(meta::post_meta))
+        .route(
+            "/posts/:id/share-links",
+            post(short_links::create_share_link),
+        )
+        .route("/posts/:id/qr.png", get(qr::post_qr_code))
+        .route("/posts/:id/analytics", get(short_links::post_analytics))
+        .route("/s/:code", get(short_links::follow_short_link))
+        .route("/posts/:id/report", post(handlers::report_post))
+        .route("/comments/:id/report", post(comments::report_comment))
+        .route("/posts/:id/unarchive", post(archive::unarchive_post))
+        .route("/users", post(handlers::create_user))
+        .route("/users/check", get(handlers::check_user))
+        .route(USER_ROUTE, get(handlers::get_user))
+        .route("/users/:id/activity", get(activity::get_user_activity))
+        .route("/users.csv", get(export::users_csv))
+        .route("/me/mentions", get(mentions::my_mentions))
+        .route("/me/flags", get(flags::my_flags))
+        .route("/me/following", get(follows::my_following))
+        .route(
+            "/me/following/:user_id",
+            put(follows::follow_user).delete(follows::unfollow_user),
+        )
+        .route(
+            "/me/preferences",
+            get(preferences::my_preferences).put(preferences::update_my_preferences),
+        )
+        .route("/me/usage", get(usage::my_usage))
+        .route("/me/export.zip", get(export::my_posts_zip))
+        .route("/billing/webhook", post(billing::stripe_webhook))
+        .route(
+            "/email/unsubscribe",
+            get(unsubscribe::unsubscribe).post(unsubscribe::unsubscribe),
+        )
+        .route("/hashtags/trending", get(hashtags::trending_hashtags))
+        .route("/hashtags/:tag/posts", get(hashtags::hashtag_posts))
+        .route("/auth/token", post(jwt::create_token))
+        .route(
+            "/auth/session",
+            post(sessions::create_session).delete(sessions::delete_session),
+        )
+        .route("/.well-known/jwks.json", get(jwt::jwks))
+        .route("/.well-known/webfinger", get(federation::webfinger))
+        .route("/ap/users/:id", get(federation::actor))
+        .route("/ap/users/:id/inbox", post(federation::inbox))
+        .route("/ap/users/:id/outbox", get(federation::outbox))
+        .route("/ap/users/:id/followers", get(federation::followers))
+        .route("/graphql", get(graphql::subscribe).post(graphql::execute))
+        .route("/orgs", post(orgs::create_org))
+        .route("/orgs/:id", get(orgs::get_org))
+        .route(
+            "/orgs/:id/members",
+            get(orgs::list_members).post(orgs::add_member),
+        )
+        .route("/orgs/:id/posts", get(orgs::list_org_posts))
+        .route(
+            "/orgs/:id/invitations",
+            get(invitations::list_invitations).post(invitations::create_invitation),
+        )
+        .route(
+            "/orgs/:id/invitations/:invitation_id",
+            delete(invitations::revoke_invitation),
+        )
+        .route(
+            "/invitations/:token/accept",
+            post(invitations::accept_invitation),
+        )
+}
+
+fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/admin/banned-words",
+            get(moderation::list_banned_words).post(moderation::add_banned_word),
+        )
+        .route(
+            "/admin/banned-words/:word",
+            delete(moderation::remove_banned_word),
+        )
+        .route(
+            "/admin/moderation/posts",
+            get(moderation::moderation_queue).post(moderation::moderate_posts),
+        )
+        .route(
+            "/admin/moderation/posts/:id/approve",
+            post(moderation::approve_post),
+        )
+        .route(
+            "/admin/moderation/posts/:id/reject",
+            post(moderation::reject_post),
+        )
+        .route("/admin/moderation/comments", get(moderation::comment_queue))
+        .route(
+            "/admin/moderation/comments/:id/approve",
+            post(moderation::approve_comment),
+        )
+        .route(
+            "/admin/moderation/comments/:id/reject",
+            post(moderation::reject_comment),
+        )
+        .route(
+            "/admin/users/:id/impersonate",
+            post(impersonation::impersonate_user),
+        )
+        .route("/admin/users/:id/identities", post(oidc::link_identity))
+        .route("/admin/reports", get(moderation::list_reports))
+        .route("/admin/audit", get(audit::list_audit_log))
+        .route("/admin/jobs", get(jobs::list_jobs))
+        .route("/admin/metrics", get(metrics::export))
+        .route("/admin/debug/pprof/profile", get(profiling::profile))
+        .route("/admin/config", get(tunables::get_config))
+        .route("/admin/config/reload", post(tunables::reload_config))
+        .route(
+            "/admin/flags",
+            get(flags::list_flags).post(flags::create_flag),
+        )
+        .route(
+            "/admin/flags/:key",
+            get(flags::get_flag)
+                .put(flags::update_flag)
+                .delete(flags::delete_flag),
+        )
+        .route("/admin/flags/:key/overrides", get(flags::list_overrides))
+        .route(
+            "/admin/flags/:key/overrides/:user_id",
+            put(flags::set_override).delete(flags::remove_override),
+        )
+        .route(
+            "/admin/maintenance",
+            get(maintenance::get_maintenance).put(maintenance::set_maintenance),
+        )
+        .route(
+            "/admin/tenants",
+            get(tenant::list_tenants).post(tenant::create_tenant),
+        )
+        .route(
+            "/admin/webhooks",
+            get(webhooks::list_webhooks).post(webhooks::create_webhook),
+        )
+        .route("/admin/webhooks/:id", delete(webhooks::delete_webhook))
+        .route("/admin/webhooks/:id/enable", post(webhooks::enable_webhook))
+        .route("/admin/webhooks/dead-letters", get(webhooks::dead_letters))
+        .route(
+            "/admin/webhooks/deliveries/:id/redeliver",
+            post(webhooks::redeliver),
+        )
+        .route(
+            "/admin/reports/:id/resolve",
+            post(moderation::resolve_report),
+        )
+}
@@ -0,0 +1,176 @@
+// What the tokio runtime is up to, for telling an async stall from a slow
+// database: `GET /admin/metrics` carries the worker threads, the tasks alive
+// and the global queue, and how long each worker was busy.
+//
+// Built with RUSTFLAGS="--cfg tokio_unstable", the metrics also count the
+// tasks spawned, the polls and mean poll time and the local queue of every
+// worker, and the blocking pool. The tokio-console feature, which needs that
+// flag too, instruments the tasks for `tokio-console` as well, listening on
+// 127.0.0.1:6669 (TOKIO_CONSOLE_BIND and friends, see console-subscriber):
+//
+//     RUSTFLAGS="--cfg tokio_unstable" cargo run --features tokio-console
+//     tokio-console
+
+use std::fmt::Write;
+
+use tokio::runtime::Handle;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+#[cfg(all(feature = "tokio-console", not(tokio_unstable)))]
+compile_error!("the tokio-console feature needs RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+// The layer that feeds `tokio-console`, with its server spawned on a thread
+// of its own, if built with the tokio-console feature.
+#[cfg(feature = "tokio-console")]
+pub fn console_layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Some(console_subscriber::spawn())
+}
+
+#[cfg(not(feature = "tokio-console"))]
+pub fn console_layer<S>() -> Option<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    None::<tracing_subscriber::layer::Identity>
+}
+
+// The runtime's gauges and counters in the Prometheus text format, nothing
+// outside of one.
+pub fn render() -> String {
+    let mut out = String::new();
+    let Ok(handle) = Handle::try_current() else {
+        return out;
+    };
+    let metrics = handle.metrics();
+    let workers = metrics.num_workers();
+
+    out.push_str("# HELP tokio_workers Worker threads of the runtime.\n");
+    out.push_str("# TYPE tokio_workers gauge\n");
+    let _ = writeln!(out, "tokio_workers {workers}");
+    out.push_str("# HELP tokio_alive_tasks Tasks spawned and not yet done.\n");
+    out.push_str("# TYPE tokio_alive_tasks gauge\n");
+    let _ = writeln!(out, "tokio_alive_tasks {}", metrics.num_alive_tasks());
+    out.push_str("# HELP tokio_global_queue_depth Tasks waiting in the global queue.\n");
+    out.push_str("# TYPE tokio_global_queue_depth gauge\n");
+    let _ = writeln!(
+        out,
+        "tokio_global_queue_depth {}",
+        metrics.global_queue_depth()
+    );
+
+    out.push_str(
+        "# HELP tokio_worker_busy_seconds_total How long each worker spent running tasks.\n",
+    );
+    out.push_str("# TYPE tokio_worker_busy_seconds_total counter\n");
+    for worker in 0..workers {
+        let _ = writeln!(
+            out,
+            "tokio_worker_busy_seconds_total{{worker=\"{worker}\"}} {}",
+            metrics.worker_total_busy_duration(worker).as_secs_f64()
+        );
+    }
+    out.push_str("# HELP tokio_worker_parks_total Times each worker ran out of work.\n");
+    out.push_str("# TYPE tokio_worker_parks_total counter\n");
+    for worker in 0..workers {
+        let _ = writeln!(
+            out,
+            "tokio_worker_parks_total{{worker=\"{worker}\"}} {}",
+            metrics.worker_park_count(worker)
+        );
+    }
+
+    #[cfg(tokio_unstable)]
+    render_unstable(&mut out, &metrics);
+    out
+}
+
+// The metrics tokio only has with `--cfg tokio_unstable`.
+#[cfg(tokio_unstable)]
+fn render_unstable(out: &mut String, metrics: &tokio::runtime::RuntimeMetrics) {
+    let workers = metrics.num_workers();
+
+    out.push_str("# HELP tokio_spawned_tasks_total Tasks spawned since the runtime started.\n");
+    out.push_str("# TYPE tokio_spawned_tasks_total counter\n");
+    let _ = writeln!(
+        out,
+        "tokio_spawned_tasks_total {}",
+        metrics.spawned_tasks_count()
+    );
+    out.push_str(
+        "# HELP tokio_budget_forced_yields_total Tasks made to yield for using up their budget.\n",
+    );
+    out.push_str("# TYPE tokio_budget_forced_yields_total counter\n");
+    let _ = writeln!(
+        out,
+        "tokio_budget_forced_yields_total {}",
+        metrics.budget_forced_yield_count()
+    );
+    out.push_str("# HELP tokio_blocking_threads Threads of the blocking pool, by state.\n");
+    out.push_str("# TYPE tokio_blocking_threads gauge\n");
+    let idle = metrics.num_idle_blocking_threads();
+    let _ = writeln!(out, "tokio_blocking_threads{{state=\"idle\"}} {idle}");
+    let _ = writeln!(
+        out,
+        "tokio_blocking_threads{{state=\"busy\"}} {}",
+        metrics.num_blocking_threads().saturating_sub(idle)
+    );
+    out.push_str("# HELP tokio_blocking_queue_depth Blocking tasks waiting for a thread.\n");
+    out.push_str("# TYPE tokio_blocking_queue_depth gauge\n");
+    let _ = writeln!(
+        out,
+        "tokio_blocking_queue_depth {}",
+        metrics.blocking_queue_depth()
+    );
+
+    out.push_str("# HELP tokio_worker_polls_total Tasks each worker polled.\n");
+    out.push_str("# TYPE tokio_worker_polls_total counter\n");
+    for worker in 0..workers {
+        let _ = writeln!(
+            out,
+            "tokio_worker_polls_total{{worker=\"{worker}\"}} {}",
+            metrics.worker_poll_count(worker)
+        );
+    }
+    out.push_str("# HELP tokio_worker_mean_poll_seconds A moving average of how long each worker's polls took.\n");
+    out.push_str("# TYPE tokio_worker_mean_poll_seconds gauge\n");
+    for worker in 0..workers {
+        let _ = writeln!(
+            out,
+            "tokio_worker_mean_poll_seconds{{worker=\"{worker}\"}} {}",
+            metrics.worker_mean_poll_time(worker).as_secs_f64()
+        );
+    }
+    out.push_str("# HELP tokio_worker_local_queue_depth Tasks waiting in each worker's queue.\n");
+    out.push_str("# TYPE tokio_worker_local_queue_depth gauge\n");
+    for worker in 0..workers {
+        let _ = writeln!(
+            out,
+            "tokio_worker_local_queue_depth{{worker=\"{worker}\"}} {}",
+            metrics.worker_local_queue_depth(worker)
+        );
+    }
+    out.push_str("# HELP tokio_worker_steals_total Tasks each worker stole from the others.\n");
+    out.push_str("# TYPE tokio_worker_steals_total counter\n");
+    for worker in 0..workers {
+        let _ = writeln!(
+            out,
+            "tokio_worker_steals_total{{worker=\"{worker}\"}} {}",
+            metrics.worker_steal_count(worker)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_is_rendered_outside_a_runtime() {
+        assert_eq!(render(), "");
+    }
+}
@@ -0,0 +1,194 @@
+// A secret in AWS Secrets Manager, its SecretString a JSON object, read with
+// GetSecretValue:
+//
+//     POST https://secretsmanager.{AWS_REGION}.amazonaws.com/
+//     X-Amz-Target: secretsmanager.GetSecretValue
+//
+//     {"SecretId": "{AWS_SECRET_ID}"}
+//
+// signed with Signature Version 4 under the access key in the environment.
+// Roles assumed from instance or task metadata aren't looked up; hand their
+// credentials over as AWS_ACCESS_KEY_ID and friends.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::{http, variable, SecretsError, SecretsProvider};
+use crate::repo::api_keys::hex;
+
+const SERVICE: &str = "secretsmanager";
+
+const TARGET: &str = "secretsmanager.GetSecretValue";
+
+const JSON: &str = "application/x-amz-json-1.1";
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // for temporary credentials
+    pub session_token: Option<String>,
+}
+
+pub struct SecretsManager {
+    endpoint: Url,
+    region: String,
+    secret_id: String,
+    credentials: Credentials,
+}
+
+impl SecretsManager {
+    // in `region`'s endpoint
+    pub fn new(region: &str, secret_id: &str, credentials: Credentials) -> Self {
+        let endpoint = format!("https://{SERVICE}.{region}.amazonaws.com/");
+        SecretsManager {
+            endpoint: Url::parse(&endpoint).expect("the endpoint of a region is a URL"),
+            region: region.to_owned(),
+            secret_id: secret_id.to_owned(),
+            credentials,
+        }
+    }
+
+    // at another endpoint, a VPC endpoint or LocalStack say
+    pub fn with_endpoint(mut self, endpoint: &str) -> Result<Self, String> {
+        self.endpoint =
+            Url::parse(endpoint).map_err(|e| format!("invalid endpoint {endpoint:?}: {e}"))?;
+        Ok(self)
+    }
+
+    pub fn from_env() -> Result<SecretsManager, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let required = |name: &str| {
+            var(name).ok_or_else(|| format!("{name} must be set with SECRETS_PROVIDER=aws"))
+        };
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .ok_or("AWS_REGION must be set with SECRETS_PROVIDER=aws")?;
+        let credentials = Credentials {
+            access_key_id: required("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: required("AWS_SECRET_ACCESS_KEY")?,
+            session_token: var("AWS_SESSION_TOKEN"),
+        };
+        let manager = SecretsManager::new(&region, &required("AWS_SECRET_ID")?, credentials);
+        match var("AWS_ENDPOINT_URL") {
+            Some(endpoint) => manager.with_endpoint(&endpoint),
+            None => Ok(manager),
+        }
+    }
+
+    // the Host header, with the port unless it's the scheme's
+    fn host(&self) -> String {
+        let host = self.endpoint.host_str().unwrap_or_default();
+        match self.endpoint.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_owned(),
+        }
+    }
+
+    // the headers signing a request with `body` at `now`, Authorization
+    // among them
+    fn sign(&self, body: &[u8], now: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let mut headers = vec![
+            ("content-type", JSON.to_owned()),
+            ("host", self.host()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        headers.push(("x-amz-target", TARGET.to_owned()));
+
+        // the headers sorted by name, as they are
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "POST\n{}\n\n{canonical_headers}\n{signed_headers}\n{}",
+            self.endpoint.path(),
+            hex(&Sha256::digest(body))
+        );
+        let scope = format!("{date}/{}/{SERVICE}/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let secret = format!("AWS4{}", self.credentials.secret_access_key);
+        let key = hmac(secret.as_bytes(), date.as_bytes());
+        let key = hmac(&key, self.region.as_bytes());
+        let key = hmac(&key, SERVICE.as_bytes());
+        let key = hmac(&key, b"aws4_request");
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        headers.push((
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                self.credentials.access_key_id
+            ),
+        ));
+        // reqwest sets it from the URL
+        headers.retain(|(name, _)| *name != "host");
+        headers
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes any key");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SecretValue {
+    // None for a binary secret
+    secret_string: Option<String>,
+}
+
+#[async_trait]
+impl SecretsProvider for SecretsManager {
+    fn name(&self) -> &'static str {
+        "AWS Secrets Manager"
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, String>, SecretsError> {
+        let body = serde_json::to_vec(&json!({ "SecretId": self.secret_id }))?;
+        let mut request = http().post(self.endpoint.clone());
+        for (name, value) in self.sign(&body, Utc::now()) {
+            request = request.header(name, value);
+        }
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            // {"__type": "ResourceNotFoundException", "message": "…"}
+            let error = response.text().await.unwrap_or_default();
+            return Err(format!("GetSecretValue answered {status}: {error}").into());
+        }
+        let value: SecretValue = response.json().await?;
+        let secret = value
+            .secret_string
+            .ok_or("the secret is binary, expected a JSON object")?;
+        let values: HashMap<String, serde_json::Value> = serde_json::from_str(&secret)
+            .map_err(|e| format!("the secret isn't a JSON object: {e}"))?;
+        Ok(values
+            .into_iter()
+            .map(|(name, value)| (name, variable(value)))
+            .collect())
+    }
+}
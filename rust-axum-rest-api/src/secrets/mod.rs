@@ -0,0 +1,192 @@
+// Secrets from a secrets backend instead of only the environment and .env.
+// With SECRETS_PROVIDER set, the secret it names is fetched at startup,
+// before the configuration is read, and each of its keys is set as the
+// environment variable of that name, over what .env says:
+//
+//     SECRETS_PROVIDER=vault      VAULT_ADDR, VAULT_TOKEN and
+//                                 VAULT_SECRET_PATH, the API path of a KV
+//                                 version 2 secret such as
+//                                 secret/data/rust-axum-rest-api, and
+//                                 VAULT_NAMESPACE if there is one (see `vault`)
+//     SECRETS_PROVIDER=aws        AWS_SECRET_ID in AWS_REGION, a secret whose
+//                                 string is a JSON object, read with
+//                                 AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and
+//                                 AWS_SESSION_TOKEN; AWS_ENDPOINT_URL for
+//                                 another endpoint (see `aws`)
+//
+// A secret like {"DATABASE_URL": "…", "ADMIN_TOKEN": "…"} then stands for
+// those variables, and the same goes for EMAIL_LINK_SECRET,
+// EMAIL_ENCRYPTION_KEY, STRIPE_WEBHOOK_SECRET and the rest. The JWT signing
+// keys aren't among them, they are kept in the database (see `jwt`), and
+// nothing is mailed over SMTP yet (see `notify`), so there are no SMTP
+// credentials to fetch; when there are, they come from here like any other
+// variable.
+//
+// The secret is fetched again every SECRETS_REFRESH_SECS, 300 by default, 0
+// for never. A new DATABASE_URL, a rotated password say, is used for the
+// connections opened from then on; the other variables are read once, so a
+// change to them is logged, by name only, and takes a restart.
+//
+// Other backends implement `SecretsProvider`.
+
+pub mod aws;
+pub mod vault;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{Pool, Postgres};
+use tracing::{error, info, warn};
+
+pub type SecretsError = Box<dyn Error + Send + Sync>;
+
+const DEFAULT_REFRESH: Duration = Duration::from_secs(5 * 60);
+
+// Where the secret is kept.
+#[async_trait]
+pub trait SecretsProvider: Send + Sync {
+    // for the log
+    fn name(&self) -> &'static str;
+
+    // the secret's values, by environment variable name
+    async fn fetch(&self) -> Result<HashMap<String, String>, SecretsError>;
+}
+
+// the client talking to the secrets backends
+fn http() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(concat!("rust-axum-rest-api/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("the secrets HTTP client builds")
+    })
+}
+
+// a secret's value as the variable is set, JSON strings unquoted
+fn variable(value: serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => value,
+        value => value.to_string(),
+    }
+}
+
+pub struct Secrets {
+    provider: Box<dyn SecretsProvider>,
+    // None to fetch at startup only
+    refresh: Option<Duration>,
+    // the values last fetched
+    current: HashMap<String, String>,
+}
+
+impl Secrets {
+    pub fn new(provider: Box<dyn SecretsProvider>, refresh: Option<Duration>) -> Self {
+        Secrets {
+            provider,
+            refresh,
+            current: HashMap::new(),
+        }
+    }
+
+    // SECRETS_PROVIDER and its settings; None when it's unset
+    pub fn from_env() -> Result<Option<Secrets>, String> {
+        let provider: Box<dyn SecretsProvider> = match std::env::var("SECRETS_PROVIDER") {
+            Ok(name) if name == "vault" => Box::new(vault::Vault::from_env()?),
+            Ok(name) if name == "aws" => Box::new(aws::SecretsManager::from_env()?),
+            Ok(name) if !name.is_empty() => {
+                return Err(format!(
+                    "invalid SECRETS_PROVIDER {name:?}, expected vault or aws"
+                ))
+            }
+            _ => return Ok(None),
+        };
+        let refresh = match std::env::var("SECRETS_REFRESH_SECS") {
+            Ok(secs) => match secs.parse::<u64>() {
+                Ok(0) => None,
+                Ok(secs) => Some(Duration::from_secs(secs)),
+                Err(_) => {
+                    return Err(format!(
+                        "invalid SECRETS_REFRESH_SECS {secs:?}, expected a number"
+                    ))
+                }
+            },
+            Err(_) => Some(DEFAULT_REFRESH),
+        };
+        Ok(Some(Secrets::new(provider, refresh)))
+    }
+
+    // Fetch the secret and set its variables. Only at startup, before
+    // anything else reads the environment.
+    pub async fn load(&mut self) -> Result<(), SecretsError> {
+        self.current = self.provider.fetch().await?;
+        for (name, value) in &self.current {
+            std::env::set_var(name, value);
+        }
+        info!(
+            "Read {} variables from {}",
+            self.current.len(),
+            self.provider.name()
+        );
+        Ok(())
+    }
+
+    // Fetch the secret again, pointing `pool` at a new DATABASE_URL, and
+    // return the names of the variables that changed.
+    pub async fn refresh(&mut self, pool: &Pool<Postgres>) -> Result<Vec<String>, SecretsError> {
+        let fetched = self.provider.fetch().await?;
+        let mut changed: Vec<String> = fetched
+            .iter()
+            .filter(|(name, value)| self.current.get(*name) != Some(*value))
+            .map(|(name, _)| name.clone())
+            .chain(
+                self.current
+                    .keys()
+                    .filter(|name| !fetched.contains_key(*name))
+                    .cloned(),
+            )
+            .collect();
+        changed.sort();
+        if let Some(url) = fetched.get("DATABASE_URL") {
+            if changed.iter().any(|name| name == "DATABASE_URL") {
+                pool.set_connect_options(url.parse::<PgConnectOptions>()?);
+            }
+        }
+        self.current = fetched;
+        Ok(changed)
+    }
+
+    // Refresh every SECRETS_REFRESH_SECS, if at all.
+    pub fn spawn_refresh(mut self, pool: Pool<Postgres>) {
+        let Some(every) = self.refresh else {
+            return;
+        };
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            // the first tick is right away, and the secret was just loaded
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match self.refresh(&pool).await {
+                    Ok(changed) => {
+                        for name in changed {
+                            if name == "DATABASE_URL" {
+                                info!("DATABASE_URL changed, new connections use it");
+                            } else {
+                                warn!(
+                                    "{name} changed in {}, a restart picks it up",
+                                    self.provider.name()
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => error!("Failed to refresh the secrets: {e}"),
+                }
+            }
+        });
+    }
+}
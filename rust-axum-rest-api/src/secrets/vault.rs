@@ -0,0 +1,88 @@
+// A KV version 2 secret in HashiCorp Vault, read with a token:
+//
+//     GET {VAULT_ADDR}/v1/{VAULT_SECRET_PATH}
+//     X-Vault-Token: {VAULT_TOKEN}
+//
+// answering {"data": {"data": {…}, "metadata": {…}}}, the latest version.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{http, variable, SecretsError, SecretsProvider};
+
+pub struct Vault {
+    addr: String,
+    token: String,
+    path: String,
+    namespace: Option<String>,
+}
+
+impl Vault {
+    pub fn new(addr: &str, token: &str, path: &str) -> Self {
+        Vault {
+            addr: addr.trim_end_matches('/').to_owned(),
+            token: token.to_owned(),
+            path: path.trim_start_matches('/').to_owned(),
+            namespace: None,
+        }
+    }
+
+    // for Vault Enterprise namespaces
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_owned());
+        self
+    }
+
+    pub fn from_env() -> Result<Vault, String> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("{name} must be set with SECRETS_PROVIDER=vault"))
+        };
+        let vault = Vault::new(
+            &var("VAULT_ADDR")?,
+            &var("VAULT_TOKEN")?,
+            &var("VAULT_SECRET_PATH")?,
+        );
+        Ok(match std::env::var("VAULT_NAMESPACE") {
+            Ok(namespace) if !namespace.is_empty() => vault.with_namespace(&namespace),
+            _ => vault,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    data: Data,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    data: HashMap<String, serde_json::Value>,
+}
+
+#[async_trait]
+impl SecretsProvider for Vault {
+    fn name(&self) -> &'static str {
+        "Vault"
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, String>, SecretsError> {
+        let mut request = http()
+            .get(format!("{}/v1/{}", self.addr, self.path))
+            .header("X-Vault-Token", &self.token);
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        let response: Response = request.send().await?.error_for_status()?.json().await?;
+        Ok(response
+            .data
+            .data
+            .into_iter()
+            .map(|(name, value)| (name, variable(value)))
+            .collect())
+    }
+}
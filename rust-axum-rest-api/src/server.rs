@@ -0,0 +1,291 @@
+// Serving connections ourselves rather than with `axum::serve`, for the
+// HTTP/2 settings it has no say in.
+//
+// Over TLS (see `tls`) clients get HTTP/2 when they pick it over ALPN, and
+// HTTP/1.1 otherwise. Plain connections speak HTTP/1.1, and with H2C=true
+// HTTP/2 with prior knowledge as well (h2c), for gRPC and other multiplexing
+// clients on internal networks; upgrading an HTTP/1.1 connection with
+// `Upgrade: h2c` isn't supported. HTTP2_MAX_CONCURRENT_STREAMS caps how many
+// requests a client may have in flight at once on one HTTP/2 connection,
+// hyper's default of 200 when unset.
+//
+// With UNIX_SOCKET set to a path, the API is served on that unix domain
+// socket too, for a reverse proxy on the same host; only there unless
+// BIND_ADDR is set as well. UNIX_SOCKET_MODE sets the socket's permissions
+// (octal, `660` to let the proxy's group in, say). The proxy terminates TLS,
+// and its connections count as coming from 127.0.0.1.
+//
+// LISTENERS binds more addresses, each serving part of the routes (a
+// `Surface`, see `routes`): `admin=127.0.0.1:9000,api=10.0.0.2:5001` for
+// instance. Once there is an admin listener /admin is only served there,
+// without the middleware meant for browsers, and no longer on BIND_ADDR or
+// the unix socket. These listeners speak plain HTTP.
+//
+// All of them stop accepting when the instance shuts down, and close their
+// connections once the requests in flight are answered (see `shutdown`).
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request};
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::service::TowerToHyperService;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, TcpStream};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+use crate::routes::Surface;
+use crate::shutdown::Shutdown;
+use crate::tls::ClientIdentity;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Http2 {
+    // HTTP/2 on plain connections too, H2C=true|false, off by default
+    pub h2c: bool,
+    // HTTP2_MAX_CONCURRENT_STREAMS
+    pub max_concurrent_streams: Option<u32>,
+}
+
+impl Http2 {
+    pub fn from_env() -> Result<Http2, String> {
+        let h2c = match std::env::var("H2C") {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| format!("invalid H2C {value:?}, expected true or false"))?,
+            Err(_) => false,
+        };
+        let max_concurrent_streams = match std::env::var("HTTP2_MAX_CONCURRENT_STREAMS") {
+            Ok(max) => match max.parse() {
+                Ok(0) | Err(_) => {
+                    return Err(format!(
+                        "invalid HTTP2_MAX_CONCURRENT_STREAMS {max:?}, expected a positive number"
+                    ))
+                }
+                Ok(max) => Some(max),
+            },
+            Err(_) => None,
+        };
+        Ok(Http2 {
+            h2c,
+            max_concurrent_streams,
+        })
+    }
+
+    // a connection builder speaking HTTP/1.1 and HTTP/2, whichever the client
+    // does
+    fn builder(&self) -> Builder<TokioExecutor> {
+        let mut builder = Builder::new(TokioExecutor::new());
+        if let Some(max) = self.max_concurrent_streams {
+            builder.http2().max_concurrent_streams(max);
+        }
+        builder
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Listener {
+    pub surface: Surface,
+    pub addr: SocketAddr,
+}
+
+impl Listener {
+    // LISTENERS, comma separated `api=<address>` and `admin=<address>`
+    pub fn from_env() -> Result<Vec<Listener>, String> {
+        let listeners = std::env::var("LISTENERS").unwrap_or_default();
+        listeners
+            .split(',')
+            .map(str::trim)
+            .filter(|listener| !listener.is_empty())
+            .map(|listener| {
+                let invalid =
+                    |e: String| format!("invalid listener {listener:?} in LISTENERS: {e}");
+                let (surface, addr) = listener
+                    .split_once('=')
+                    .ok_or_else(|| invalid(String::from("expected <api|admin>=<address>")))?;
+                let surface = match surface.trim() {
+                    "api" => Surface::Api,
+                    "admin" => Surface::Admin,
+                    surface => return Err(invalid(format!("unknown surface {surface:?}"))),
+                };
+                let addr = addr.trim().parse().map_err(|e| invalid(format!("{e}")))?;
+                Ok(Listener { surface, addr })
+            })
+            .collect()
+    }
+}
+
+// the peer address of connections on the unix socket
+#[cfg_attr(not(unix), allow(dead_code))]
+const UNIX_PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+#[derive(Clone, Debug)]
+pub struct UnixSocket {
+    // UNIX_SOCKET
+    pub path: PathBuf,
+    // UNIX_SOCKET_MODE; unset, what the umask leaves
+    pub mode: Option<u32>,
+}
+
+impl UnixSocket {
+    pub fn from_env() -> Result<Option<UnixSocket>, String> {
+        let Some(path) = std::env::var_os("UNIX_SOCKET").filter(|path| !path.is_empty()) else {
+            if let Ok(mode) = std::env::var("UNIX_SOCKET_MODE") {
+                return Err(format!("UNIX_SOCKET_MODE {mode:?} takes UNIX_SOCKET"));
+            }
+            return Ok(None);
+        };
+        if cfg!(not(unix)) {
+            return Err(String::from("UNIX_SOCKET is only supported on unix"));
+        }
+        let mode = match std::env::var("UNIX_SOCKET_MODE") {
+            Ok(mode) => match u32::from_str_radix(&mode, 8) {
+                Ok(bits) if bits <= 0o777 => Some(bits),
+                _ => {
+                    return Err(format!(
+                        "invalid UNIX_SOCKET_MODE {mode:?}, expected octal permissions like 660"
+                    ))
+                }
+            },
+            Err(_) => None,
+        };
+        Ok(Some(UnixSocket {
+            path: path.into(),
+            mode,
+        }))
+    }
+
+    // Listen on the socket, replacing the one a previous run left behind.
+    #[cfg(unix)]
+    pub fn bind(&self) -> io::Result<UnixListener> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        // only ever a socket, never a file that happens to be there
+        if let Ok(metadata) = std::fs::symlink_metadata(&self.path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(&self.path)?;
+            }
+        }
+        let listener = UnixListener::bind(&self.path)?;
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(listener)
+    }
+}
+
+// Serve `app` over plain HTTP on `listener` until `shutdown` stops it.
+pub async fn serve(listener: TcpListener, http2: Http2, app: Router, shutdown: Shutdown) {
+    loop {
+        let (tcp, peer) = tokio::select! {
+            accepted = accept(&listener) => accepted,
+            _ = shutdown.stopped() => return,
+        };
+        let connection =
+            serve_connection(tcp, peer, false, None, http2, app.clone(), shutdown.clone());
+        shutdown.track(connection);
+    }
+}
+
+// Serve `app` on the unix socket `listener` until `shutdown` stops it.
+#[cfg(unix)]
+pub async fn serve_unix(listener: UnixListener, http2: Http2, app: Router, shutdown: Shutdown) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.stopped() => return,
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                let connection = serve_connection(
+                    stream,
+                    UNIX_PEER,
+                    false,
+                    None,
+                    http2,
+                    app.clone(),
+                    shutdown.clone(),
+                );
+                shutdown.track(connection);
+            }
+            Err(e) => accept_failed(e).await,
+        }
+    }
+}
+
+// the next connection on `listener`, waiting out the errors
+pub(crate) async fn accept(listener: &TcpListener) -> (TcpStream, SocketAddr) {
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return accepted,
+            Err(e) => accept_failed(e).await,
+        }
+    }
+}
+
+async fn accept_failed(e: io::Error) {
+    // out of file descriptors and the like, give it a moment
+    warn!("accepting a connection failed: {e}");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+// Serve a connection from `peer`, a TLS one if `tls`, with the peer address
+// available as `ConnectInfo` and the client certificate's identity if any,
+// until it's idle after `shutdown` stops.
+pub(crate) async fn serve_connection<IO>(
+    io: IO,
+    peer: SocketAddr,
+    tls: bool,
+    identity: Option<ClientIdentity>,
+    http2: Http2,
+    app: Router,
+    shutdown: Shutdown,
+) where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = app.map_request(move |request: Request<Incoming>| {
+        let mut request = request.map(Body::new);
+        request.extensions_mut().insert(ConnectInfo(peer));
+        if let Some(identity) = &identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        request
+    });
+    let (io, service) = (TokioIo::new(io), TowerToHyperService::new(service));
+    // the connection, closed gracefully once stopped: after the request in
+    // flight on HTTP/1.1, with a GOAWAY on HTTP/2
+    macro_rules! until_stopped {
+        ($connection:expr) => {{
+            let connection = $connection;
+            tokio::pin!(connection);
+            tokio::select! {
+                served = connection.as_mut() => served,
+                _ = shutdown.stopped() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            }
+        }};
+    }
+    let served = if tls || http2.h2c {
+        let builder = http2.builder();
+        until_stopped!(builder.serve_connection_with_upgrades(io, service))
+    } else {
+        until_stopped!(http1::Builder::new()
+            .serve_connection(io, service)
+            .with_upgrades())
+        .map_err(Into::into)
+    };
+    if let Err(e) = served {
+        debug!(%peer, "connection failed: {e}");
+    }
+}
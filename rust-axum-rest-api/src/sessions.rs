@@ -0,0 +1,270 @@
+// Browser sessions: `POST /auth/session`, made with an API key or an access
+// token, sets a `session` cookie acting as the same user (see `auth`) for
+// `TTL_DAYS`, or until `DELETE /auth/session` ends it. The cookie is
+// HttpOnly and SameSite=Lax, and Secure when the API is served over HTTPS;
+// impersonation tokens can't start a session.
+//
+// Sessions are kept by a `SessionStore`, in Postgres, or in Redis with
+// REDIS_URL (see `redis_store`), so that every instance knows them and they
+// survive restarts. Only the hash of the session id is stored. Redis expires
+// sessions by itself; from Postgres they are purged every `CLEANUP_INTERVAL`
+// by one instance at a time (see `jobs`).
+
+use std::error::Error;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::header::{COOKIE, SET_COOKIE};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use chrono::Utc;
+use rand::RngCore;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use tracing::{error, info};
+
+use crate::auth::Actor;
+use crate::error::ApiError;
+use crate::jobs;
+use crate::models::{Message, Session};
+use crate::negotiate::{Accept, XmlElement};
+#[cfg(feature = "redis")]
+use crate::redis_store::RedisStore;
+use crate::repo;
+use crate::repo::api_keys::{hash, hex};
+use crate::state::AppState;
+use crate::tenant;
+
+pub const COOKIE_NAME: &str = "session";
+
+const ID_PREFIX: &str = "sess_";
+
+const TTL_DAYS: i64 = 7;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub type StoreError = Box<dyn Error + Send + Sync>;
+
+// Where sessions are kept, under the hash of their id.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn insert(&self, id_hash: &str, session: &Session) -> Result<(), StoreError>;
+
+    // the session under `id_hash` unless it expired
+    async fn get(&self, id_hash: &str) -> Result<Option<Session>, StoreError>;
+
+    // end a session, false when there was none
+    async fn remove(&self, id_hash: &str) -> Result<bool, StoreError>;
+
+    // drop the expired sessions, returning how many there were
+    async fn purge_expired(&self) -> Result<u64, StoreError>;
+}
+
+pub struct PgSessionStore {
+    pool: Pool<Postgres>,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PgSessionStore { pool }
+    }
+}
+
+#[async_trait]
+impl SessionStore for PgSessionStore {
+    async fn insert(&self, id_hash: &str, session: &Session) -> Result<(), StoreError> {
+        Ok(repo::sessions::insert(&self.pool, id_hash, session).await?)
+    }
+
+    async fn get(&self, id_hash: &str) -> Result<Option<Session>, StoreError> {
+        Ok(repo::sessions::get(&self.pool, id_hash).await?)
+    }
+
+    async fn remove(&self, id_hash: &str) -> Result<bool, StoreError> {
+        Ok(repo::sessions::delete(&self.pool, id_hash).await?)
+    }
+
+    async fn purge_expired(&self) -> Result<u64, StoreError> {
+        Ok(repo::sessions::purge_expired(&self.pool).await?)
+    }
+}
+
+// Sessions as JSON under `session:<hash>` keys expiring with them.
+#[cfg(feature = "redis")]
+pub struct RedisSessionStore {
+    store: RedisStore,
+}
+
+#[cfg(feature = "redis")]
+impl RedisSessionStore {
+    pub fn new(store: RedisStore) -> Self {
+        RedisSessionStore { store }
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn insert(&self, id_hash: &str, session: &Session) -> Result<(), StoreError> {
+        let ttl = (session.expires_at - Utc::now()).num_milliseconds().max(1);
+        let _: () = redis::cmd("SET")
+            .arg(format!("session:{id_hash}"))
+            .arg(serde_json::to_string(session)?)
+            .arg("PX")
+            .arg(ttl)
+            .query_async(&mut self.store.conn().await?)
+            .await?;
+        Ok(())
+    }
+
+    async fn get(&self, id_hash: &str) -> Result<Option<Session>, StoreError> {
+        let value: Option<String> = redis::cmd("GET")
+            .arg(format!("session:{id_hash}"))
+            .query_async(&mut self.store.conn().await?)
+            .await?;
+        Ok(value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?)
+    }
+
+    async fn remove(&self, id_hash: &str) -> Result<bool, StoreError> {
+        let removed: i64 = redis::cmd("DEL")
+            .arg(format!("session:{id_hash}"))
+            .query_async(&mut self.store.conn().await?)
+            .await?;
+        Ok(removed == 1)
+    }
+
+    async fn purge_expired(&self) -> Result<u64, StoreError> {
+        Ok(0)
+    }
+}
+
+fn store_error(e: StoreError) -> ApiError {
+    error!("session store error: {e}");
+    ApiError::internal()
+}
+
+// The session id in the request's cookies, if any.
+fn session_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, id)| id)
+}
+
+// The user the request's session cookie acts for, in the current tenant.
+pub async fn resolve(state: &AppState, headers: &HeaderMap) -> Result<Option<i32>, ApiError> {
+    let Some(id) = session_id(headers) else {
+        return Ok(None);
+    };
+    let session = state.sessions.get(&hash(id)).await.map_err(store_error)?;
+    Ok(session
+        .filter(|session| session.tenant_id == tenant::current() && session.expires_at > Utc::now())
+        .map(|session| session.user_id))
+}
+
+fn cookie(state: &AppState, value: &str, max_age: i64) -> String {
+    let https = state.config.tls.is_some()
+        || state
+            .config
+            .public_url
+            .as_deref()
+            .is_some_and(|url| url.starts_with("https://"));
+    let secure = if https { "; Secure" } else { "" };
+    format!("{COOKIE_NAME}={value}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}")
+}
+
+// `POST /auth/session` response
+#[derive(Serialize)]
+pub struct SessionCreated {
+    user_id: i32,
+    expires_at: chrono::DateTime<Utc>,
+}
+
+impl XmlElement for SessionCreated {
+    const ELEMENT: &'static str = "session";
+    const LIST: &'static str = "sessions";
+}
+
+// handler for "POST /auth/session" rest API endpoint
+pub async fn create_session(
+    State(state): State<AppState>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<impl IntoResponse, ApiError> {
+    let user_id = actor.require_user()?;
+    if actor.impersonator.is_some() {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "impersonation tokens can't start a session",
+        ));
+    }
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    let id = format!("{ID_PREFIX}{}", hex(&secret));
+    let session = Session {
+        tenant_id: tenant::current(),
+        user_id,
+        expires_at: Utc::now() + chrono::Duration::days(TTL_DAYS),
+    };
+    state
+        .sessions
+        .insert(&hash(&id), &session)
+        .await
+        .map_err(store_error)?;
+    let max_age = (session.expires_at - Utc::now()).num_seconds();
+
+    Ok((
+        [(SET_COOKIE, cookie(&state, &id, max_age))],
+        format.respond(SessionCreated {
+            user_id,
+            expires_at: session.expires_at,
+        }),
+    ))
+}
+
+// handler for "DELETE /auth/session" rest API endpoint, ending the session
+// of the request's cookie
+pub async fn delete_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Accept(format): Accept,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(id) = session_id(&headers) {
+        state
+            .sessions
+            .remove(&hash(id))
+            .await
+            .map_err(store_error)?;
+    }
+
+    Ok((
+        [(SET_COOKIE, cookie(&state, "", 0))],
+        format.respond(Message {
+            message: String::from("Logged out"),
+        }),
+    ))
+}
+
+// Purge expired sessions every `CLEANUP_INTERVAL` until the process exits.
+pub fn spawn_cleanup(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let purge = state.sessions.purge_expired();
+            match jobs::exclusive(&state.locks, "session-cleanup", purge).await {
+                Ok(None | Some(0)) => {}
+                Ok(Some(purged)) => info!(purged, "purged expired sessions"),
+                Err(e) => error!("purging expired sessions failed: {e}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,149 @@
+// Share links: `POST /posts/:id/share-links` mints a short link to a
+// published post, `GET /s/:code` redirects to the post and counts the
+// click. `GET /posts/:id/analytics` shows the post's authors and admins how
+// often each of its links was followed, and from which countries.
+//
+// Codes are `CODE_LENGTH` random letters and digits. Links are absolute
+// with PUBLIC_URL, relative otherwise, like the post they redirect to.
+
+use axum::extract::{Path, State};
+use axum::http::header::LOCATION;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use rand::Rng;
+use serde::Serialize;
+use tracing::error;
+
+use crate::audit::{self, Change};
+use crate::auth::{Actor, RequireAdmin};
+use crate::authors::require_author;
+use crate::error::{ApiError, OrNotFound};
+use crate::geoip::Location;
+use crate::ids::PathKey;
+use crate::methods::Head;
+use crate::models::{CountryClicks, PostStatus, ShortLink};
+use crate::negotiate::{Accept, Negotiated, XmlElement};
+use crate::repo;
+use crate::routes::{expand, POST_ROUTE};
+use crate::state::AppState;
+
+const CODE_LENGTH: usize = 7;
+
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+// codes tried before giving up, each taken one being unlikely already
+const ATTEMPTS: usize = 5;
+
+// A link along with where it is reachable.
+#[derive(Serialize)]
+pub struct SharedLink {
+    #[serde(flatten)]
+    link: ShortLink,
+    url: String,
+}
+
+impl XmlElement for SharedLink {
+    const ELEMENT: &'static str = ShortLink::ELEMENT;
+    const LIST: &'static str = ShortLink::LIST;
+}
+
+// `GET /posts/:id/analytics` response
+#[derive(Serialize)]
+pub struct PostAnalytics {
+    post_id: i32,
+    // clicks on all of the post's links
+    clicks: i64,
+    // those clicks by the clients' country, where it is known (see `geoip`)
+    countries: Vec<CountryClicks>,
+    short_links: Vec<SharedLink>,
+}
+
+impl XmlElement for PostAnalytics {
+    const ELEMENT: &'static str = "analytics";
+    const LIST: &'static str = "analytics";
+}
+
+// where the link with `code` is reachable
+pub fn url(state: &AppState, code: &str) -> String {
+    let base = state.config.public_url.as_deref().unwrap_or_default();
+    format!("{base}/s/{code}")
+}
+
+fn shared(state: &AppState, link: ShortLink) -> SharedLink {
+    SharedLink {
+        url: url(state, &link.code),
+        link,
+    }
+}
+
+fn random_code() -> String {
+    let mut rng = rand::rng();
+    (0..CODE_LENGTH)
+        .map(|_| char::from(ALPHABET[rng.random_range(0..ALPHABET.len())]))
+        .collect()
+}
+
+// handler for "POST /posts/:id/share-links" rest API endpoint
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<SharedLink>, ApiError> {
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    if post.status != PostStatus::Published.as_str() {
+        return Err(ApiError::not_found("post not found"));
+    }
+    for _ in 0..ATTEMPTS {
+        let code = random_code();
+        if let Some(link) =
+            repo::short_links::create(&state.pool, &code, post.id, actor.user_id).await?
+        {
+            audit::record(Change::new("short_link.create", "short_link", &link.code).after(&link));
+            return Ok(format.respond(shared(&state, link)));
+        }
+    }
+    error!("no free short link code after {ATTEMPTS} attempts");
+    Err(ApiError::internal())
+}
+
+// handler for "GET /posts/:id/analytics" rest API endpoint
+pub async fn post_analytics(
+    State(state): State<AppState>,
+    PathKey(key): PathKey,
+    actor: Actor,
+    admin: Option<RequireAdmin>,
+    Accept(format): Accept,
+) -> Result<Negotiated<PostAnalytics>, ApiError> {
+    let post = state.posts.get(key).await.or_not_found("post")?;
+    require_author(&post, &actor, admin, "see its analytics")?;
+    let links = repo::short_links::for_post(&state.pool, post.id).await?;
+    let countries = repo::short_links::countries_for_post(&state.pool, post.id).await?;
+
+    Ok(format.respond(PostAnalytics {
+        post_id: post.id,
+        clicks: links.iter().map(|link| link.clicks).sum(),
+        countries,
+        short_links: links.into_iter().map(|link| shared(&state, link)).collect(),
+    }))
+}
+
+// handler for "GET /s/:code" rest API endpoint, redirecting to the post; a
+// HEAD isn't counted as a click
+pub async fn follow_short_link(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    location: Location,
+    head: Option<Extension<Head>>,
+) -> Result<Response, ApiError> {
+    let public_id = match head {
+        Some(_) => repo::short_links::target(&state.pool, &code).await?,
+        None => repo::short_links::click(&state.pool, &code, location.country.as_deref()).await?,
+    }
+    .ok_or_else(|| ApiError::not_found(format!("no short link {code}")))?;
+    let base = state.config.public_url.as_deref().unwrap_or_default();
+    let location = base.to_owned() + &expand(POST_ROUTE, &public_id);
+
+    Ok((StatusCode::FOUND, [(LOCATION, location)]).into_response())
+}
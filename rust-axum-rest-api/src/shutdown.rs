@@ -0,0 +1,149 @@
+// Stopping without failing requests, for rolling deploys behind a load
+// balancer (Kubernetes and the like). On SIGTERM, or Ctrl-C:
+//
+//  1. `/readyz` answers 503 {"status": "shutting_down"}, and everything else
+//     is served as before;
+//  2. after SHUTDOWN_DRAIN_SECS, 5 by default, for the load balancers to
+//     notice and send no more traffic (a readiness probe's period times its
+//     failure threshold, and the time endpoints take to update), the
+//     listeners stop accepting connections;
+//  3. idle connections are closed and the others once their requests are
+//     answered, for up to SHUTDOWN_TIMEOUT_SECS, 25 by default, then the
+//     process exits.
+//
+// Kubernetes kills the pod terminationGracePeriodSeconds (30 by default)
+// after SIGTERM, so keep the two settings below that.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShutdownConfig {
+    // SHUTDOWN_DRAIN_SECS, not ready but still serving
+    pub drain: Duration,
+    // SHUTDOWN_TIMEOUT_SECS, waiting for the requests in flight
+    pub timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            drain: Duration::from_secs(5),
+            timeout: Duration::from_secs(25),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn from_env() -> Result<ShutdownConfig, String> {
+        let defaults = ShutdownConfig::default();
+        let secs = |name: &str, default: Duration| match std::env::var(name) {
+            Ok(value) => value
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| format!("invalid {name} {value:?}, expected a number of seconds")),
+            Err(_) => Ok(default),
+        };
+        Ok(ShutdownConfig {
+            drain: secs("SHUTDOWN_DRAIN_SECS", defaults.drain)?,
+            timeout: secs("SHUTDOWN_TIMEOUT_SECS", defaults.timeout)?,
+        })
+    }
+}
+
+// Where the instance is in shutting down, shared by `/readyz` and the
+// listeners (see `server`), which serve their connections through it.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    draining: Arc<AtomicBool>,
+    stop: CancellationToken,
+    connections: TaskTracker,
+}
+
+impl Shutdown {
+    // whether `/readyz` says not to send any more traffic
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    // stop accepting connections, and close those open once they are idle
+    pub fn stop(&self) {
+        self.drain();
+        self.stop.cancel();
+    }
+
+    // until `stop`
+    pub async fn stopped(&self) {
+        self.stop.cancelled().await
+    }
+
+    // serve a connection, for `finished` to wait for
+    pub(crate) fn track<F>(&self, connection: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.connections.spawn(connection);
+    }
+
+    // Wait for the connections to close, up to `timeout`; false if some were
+    // still open.
+    pub async fn finished(&self, timeout: Duration) -> bool {
+        self.connections.close();
+        tokio::time::timeout(timeout, self.connections.wait())
+            .await
+            .is_ok()
+    }
+
+    // The sequence after SIGTERM: not ready, then after `config.drain` no
+    // more connections, and the open ones closed within `config.timeout`.
+    pub async fn run(&self, config: ShutdownConfig) {
+        self.drain();
+        info!("Shutting down, not ready for {:?}", config.drain);
+        tokio::time::sleep(config.drain).await;
+        self.stop();
+        info!(
+            "Stopped accepting connections, finishing {} of them",
+            self.connections.len()
+        );
+        if !self.finished(config.timeout).await {
+            warn!(
+                "{} connections still open after {:?}, closing them",
+                self.connections.len(),
+                config.timeout
+            );
+        }
+    }
+}
+
+// until the process is asked to stop, by SIGTERM or Ctrl-C
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::error!("can't listen for SIGTERM: {e}"),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!("can't listen for Ctrl-C: {e}");
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,245 @@
+// Heuristic spam detection for new posts.
+//
+// Each `SpamChecker` looks at a submission and returns a verdict; the
+// checkers configured in `from_config` run in turn and the worst verdict
+// wins. Submissions judged `Suspicious` are held back as pending for a
+// moderator, `Spam` is stored with the spam status so false positives can
+// still be recovered, neither is listed.
+//
+// The built-in checkers count links (`LinkDensity`), remember recent bodies
+// (`DuplicateContent`) and count submissions per author or address
+// (`SubmissionRate`). With SPAM_CHECK_URL set, `ExternalChecker` also asks
+// an Akismet-style service, failing open when it can't be reached.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::config::Config;
+
+// what is being submitted, and by whom
+pub struct Submission<'a> {
+    pub user_id: Option<i32>,
+    pub client: Option<IpAddr>,
+    pub title: &'a str,
+    pub body: &'a str,
+}
+
+// ordered from harmless to certainly spam
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpamVerdict {
+    Ham,
+    Suspicious(String),
+    Spam(String),
+}
+
+#[async_trait]
+pub trait SpamChecker: Send + Sync {
+    async fn check(&self, submission: &Submission<'_>) -> SpamVerdict;
+}
+
+// Runs several checkers, the worst verdict wins.
+pub struct SpamCheckers(pub Vec<Box<dyn SpamChecker>>);
+
+#[async_trait]
+impl SpamChecker for SpamCheckers {
+    async fn check(&self, submission: &Submission<'_>) -> SpamVerdict {
+        let mut verdict = SpamVerdict::Ham;
+        for checker in &self.0 {
+            verdict = verdict.max(checker.check(submission).await);
+            if matches!(verdict, SpamVerdict::Spam(_)) {
+                break;
+            }
+        }
+        verdict
+    }
+}
+
+// the built-in checkers, plus the external one when configured
+pub fn from_config(config: &Config) -> SpamCheckers {
+    let mut checkers: Vec<Box<dyn SpamChecker>> = vec![
+        Box::new(LinkDensity {
+            suspicious: 3,
+            spam: 10,
+        }),
+        Box::new(DuplicateContent::new(Duration::from_secs(60 * 60), 1000)),
+        Box::new(SubmissionRate::new(5, Duration::from_secs(60))),
+    ];
+    if let Some(url) = &config.spam_check_url {
+        checkers.push(Box::new(ExternalChecker::new(url.clone())));
+    }
+    SpamCheckers(checkers)
+}
+
+// Posts that are mostly links: suspicious from `suspicious` links on, spam
+// from `spam` on.
+pub struct LinkDensity {
+    pub suspicious: usize,
+    pub spam: usize,
+}
+
+#[async_trait]
+impl SpamChecker for LinkDensity {
+    async fn check(&self, submission: &Submission<'_>) -> SpamVerdict {
+        let links = [submission.title, submission.body]
+            .iter()
+            .flat_map(|text| text.split_whitespace())
+            .filter(|word| {
+                let word = word.to_ascii_lowercase();
+                word.contains("http://") || word.contains("https://") || word.starts_with("www.")
+            })
+            .count();
+        if links >= self.spam {
+            SpamVerdict::Spam(format!("{links} links"))
+        } else if links >= self.suspicious {
+            SpamVerdict::Suspicious(format!("{links} links"))
+        } else {
+            SpamVerdict::Ham
+        }
+    }
+}
+
+// The same body submitted again within `window`, by anyone. Only hashes of
+// the last `capacity` bodies are kept.
+pub struct DuplicateContent {
+    window: Duration,
+    capacity: usize,
+    seen: Mutex<VecDeque<(Instant, [u8; 32])>>,
+}
+
+impl DuplicateContent {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        DuplicateContent {
+            window,
+            capacity,
+            seen: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl SpamChecker for DuplicateContent {
+    async fn check(&self, submission: &Submission<'_>) -> SpamVerdict {
+        // short bodies ("thanks!") repeat innocently
+        let body: String = submission
+            .body
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if body.len() < 40 {
+            return SpamVerdict::Ham;
+        }
+        let hash: [u8; 32] = Sha256::digest(body.to_lowercase().as_bytes()).into();
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        while seen.front().is_some_and(|(at, _)| {
+            now.duration_since(*at) >= self.window || seen.len() >= self.capacity
+        }) {
+            seen.pop_front();
+        }
+        let duplicate = seen.iter().any(|(_, seen)| *seen == hash);
+        seen.push_back((now, hash));
+        if duplicate {
+            SpamVerdict::Spam(String::from("duplicate of a recent post"))
+        } else {
+            SpamVerdict::Ham
+        }
+    }
+}
+
+// More than `limit` submissions within `window` from the same author, or
+// the same address for anonymous posts.
+pub struct SubmissionRate {
+    limit: usize,
+    window: Duration,
+    recent: Mutex<HashMap<SubmitterKey, VecDeque<Instant>>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum SubmitterKey {
+    User(i32),
+    Client(Option<IpAddr>),
+}
+
+impl SubmissionRate {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        SubmissionRate {
+            limit,
+            window,
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl SpamChecker for SubmissionRate {
+    async fn check(&self, submission: &Submission<'_>) -> SpamVerdict {
+        let key = match submission.user_id {
+            Some(id) => SubmitterKey::User(id),
+            None => SubmitterKey::Client(submission.client),
+        };
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent.retain(|_, times| {
+            times.retain(|at| now.duration_since(*at) < self.window);
+            !times.is_empty()
+        });
+        let times = recent.entry(key).or_default();
+        times.push_back(now);
+        if times.len() > self.limit {
+            SpamVerdict::Suspicious(format!("{} posts within {:?}", times.len(), self.window))
+        } else {
+            SpamVerdict::Ham
+        }
+    }
+}
+
+// An Akismet-style service: the submission is POSTed as a form to `url`,
+// which answers `true` for spam and `false` otherwise.
+pub struct ExternalChecker {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl ExternalChecker {
+    pub fn new(url: String) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(3))
+            .build()
+            .expect("static client configuration");
+        ExternalChecker { url, http }
+    }
+}
+
+#[async_trait]
+impl SpamChecker for ExternalChecker {
+    async fn check(&self, submission: &Submission<'_>) -> SpamVerdict {
+        let client = submission
+            .client
+            .map(|ip| ip.to_string())
+            .unwrap_or_default();
+        let form = [
+            ("user_ip", client.as_str()),
+            ("comment_type", "blog-post"),
+            ("comment_title", submission.title),
+            ("comment_content", submission.body),
+        ];
+        let answer = match self.http.post(&self.url).form(&form).send().await {
+            Ok(response) => response.text().await,
+            Err(e) => Err(e),
+        };
+        match answer.as_deref().map(str::trim) {
+            Ok("true") => SpamVerdict::Spam(String::from("flagged by the spam service")),
+            Ok(_) => SpamVerdict::Ham,
+            Err(e) => {
+                warn!("spam service unavailable, accepting the post: {e}");
+                SpamVerdict::Ham
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// From the environment to a connected database, the same way for the
+// server, its maintenance commands and the admin CLI, so that none of them
+// ends up on another database or with other settings: the secrets backend
+// over the .env files `Profile::load` read (see `secrets`), the
+// configuration, the email key (see `repo::emails`) and the pool (see
+// `db`).
+
+use std::error::Error;
+
+use sqlx::{Pool, Postgres};
+
+use crate::config::Config;
+use crate::repo::emails;
+use crate::secrets::Secrets;
+
+pub struct Started {
+    pub config: Config,
+    pub pool: Pool<Postgres>,
+    // the secrets backend, if there is one, for long running processes to
+    // keep refreshing (see `Secrets::spawn_refresh`)
+    pub secrets: Option<Secrets>,
+}
+
+pub async fn connect() -> Result<Started, Box<dyn Error + Send + Sync>> {
+    let secrets = match Secrets::from_env()? {
+        Some(mut secrets) => {
+            secrets.load().await?;
+            Some(secrets)
+        }
+        None => None,
+    };
+    let config = Config::from_env()?;
+    if let Some(key) = &config.email_key {
+        emails::configure(key.clone());
+    }
+    let pool = config.pool.connect(&config.database_url).await?;
+    Ok(Started {
+        config,
+        pool,
+        secrets,
+    })
+}
@@ -0,0 +1,150 @@
+// State shared by all handlers.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+
+use sqlx::{Pool, Postgres};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::flags::FlagService;
+use crate::geoip::GeoIp;
+use crate::locks::Locks;
+use crate::maintenance::Maintenance;
+use crate::metrics::Metrics;
+use crate::notify::{LogNotifier, Notifier};
+use crate::oidc::Oidc;
+use crate::qr::QrCache;
+use crate::rate_limit::RateLimiter;
+#[cfg(feature = "redis")]
+use crate::redis_store::RedisStore;
+use crate::repo::{
+    ActivityRepository, AuditRepository, BannedWordRepository, PgActivityRepository,
+    PgAuditRepository, PgBannedWordRepository, PgFlagRepository, PgPostRepository,
+    PgReportRepository, PgUserRepository, PostRepository, ReportRepository, UserRepository,
+};
+#[cfg(feature = "redis")]
+use crate::sessions::RedisSessionStore;
+use crate::sessions::{PgSessionStore, SessionStore};
+use crate::shutdown::Shutdown;
+use crate::spam::{self, SpamChecker};
+use crate::tunables::Tunables;
+use crate::usage::UsageMeter;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: Pool<Postgres>,
+    pub config: Arc<Config>,
+    pub posts: Arc<dyn PostRepository>,
+    pub users: Arc<dyn UserRepository>,
+    // the content filter's list
+    pub banned_words: Arc<dyn BannedWordRepository>,
+    pub reports: Arc<dyn ReportRepository>,
+    pub audit: Arc<dyn AuditRepository>,
+    // users' activity feeds
+    pub activities: Arc<dyn ActivityRepository>,
+    // the external identity provider whose tokens are accepted, see `oidc`
+    pub oidc: Option<Arc<Oidc>>,
+    // feature flags, see `flags`
+    pub flags: FlagService,
+    // judges new posts, see `spam`
+    pub spam: Arc<dyn SpamChecker>,
+    // tells users about moderation decisions
+    pub notifier: Arc<dyn Notifier>,
+    // what was just published, see `events`
+    pub events: EventBus,
+    // limits `GET /users/check` per client
+    pub check_limiter: Arc<RateLimiter>,
+    // limits users to their plan's rate, see `billing`
+    pub plan_limiter: Arc<RateLimiter<i32>>,
+    // the settings reloadable at runtime, see `tunables`
+    pub tunables: Arc<ArcSwap<Tunables>>,
+    // whether requests are turned away, see `maintenance`
+    pub maintenance: Arc<RwLock<Maintenance>>,
+    // rendered QR codes, see `qr`
+    pub qr_codes: Arc<QrCache>,
+    // usage counted since the last flush, see `usage`
+    pub usage: Arc<UsageMeter>,
+    // with REDIS_URL, see `redis_store`
+    #[cfg(feature = "redis")]
+    pub redis: Option<RedisStore>,
+    // where instances take turns, see `locks`
+    pub locks: Locks,
+    // browser sessions, see `sessions`
+    pub sessions: Arc<dyn SessionStore>,
+    // where clients are, see `geoip`
+    pub geoip: Arc<GeoIp>,
+    // request counts and latencies, see `metrics`
+    pub metrics: Arc<Metrics>,
+    // whether the instance is going away, see `shutdown`
+    pub shutdown: Shutdown,
+}
+
+impl AppState {
+    // state backed by Postgres repositories
+    pub fn new(pool: Pool<Postgres>, config: Config) -> Self {
+        #[cfg(feature = "redis")]
+        let redis = config.redis_url.as_deref().map(RedisStore::new);
+        #[cfg(feature = "redis")]
+        let (locks, sessions): (_, Arc<dyn SessionStore>) = match &redis {
+            Some(redis) => (
+                Locks::Redis(redis.clone()),
+                Arc::new(RedisSessionStore::new(redis.clone())),
+            ),
+            None => (
+                Locks::Postgres(pool.clone()),
+                Arc::new(PgSessionStore::new(pool.clone())),
+            ),
+        };
+        #[cfg(not(feature = "redis"))]
+        let (locks, sessions): (_, Arc<dyn SessionStore>) = (
+            Locks::Postgres(pool.clone()),
+            Arc::new(PgSessionStore::new(pool.clone())),
+        );
+        AppState {
+            posts: Arc::new(PgPostRepository::new(pool.clone())),
+            users: Arc::new(PgUserRepository::new(pool.clone())),
+            banned_words: Arc::new(PgBannedWordRepository::new(pool.clone())),
+            reports: Arc::new(PgReportRepository::new(pool.clone())),
+            audit: Arc::new(PgAuditRepository::new(pool.clone())),
+            activities: Arc::new(PgActivityRepository::new(pool.clone())),
+            oidc: config.oidc.clone().map(|oidc| Arc::new(Oidc::new(oidc))),
+            flags: FlagService::new(Arc::new(PgFlagRepository::new(pool.clone()))),
+            spam: Arc::new(spam::from_config(&config)),
+            notifier: Arc::new(LogNotifier),
+            events: EventBus::default(),
+            maintenance: Arc::new(RwLock::new(Maintenance::new(config.maintenance))),
+            check_limiter: Arc::new(RateLimiter::new(
+                config.tunables.check_rate_limit,
+                Duration::from_secs(60),
+            )),
+            // each user's limit is their plan's
+            plan_limiter: Arc::new(RateLimiter::new(0, Duration::from_secs(60))),
+            tunables: Arc::new(ArcSwap::from_pointee(config.tunables.clone())),
+            qr_codes: Arc::default(),
+            usage: Arc::default(),
+            #[cfg(feature = "redis")]
+            redis,
+            locks,
+            sessions,
+            geoip: Arc::default(),
+            metrics: Arc::default(),
+            shutdown: Shutdown::default(),
+            pool,
+            config: Arc::new(config),
+        }
+    }
+
+    // swap the repositories, e.g. for the in-memory ones in tests
+    pub fn with_repositories(
+        mut self,
+        posts: Arc<dyn PostRepository>,
+        users: Arc<dyn UserRepository>,
+    ) -> Self {
+        self.posts = posts;
+        self.users = users;
+        self
+    }
+}
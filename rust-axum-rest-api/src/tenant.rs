@@ -0,0 +1,151 @@
+// Multi-tenancy: one deployment serving several customers from one database.
+//
+// Every request belongs to a tenant, named by its slug in the X-Tenant header
+// or as the subdomain of TENANT_DOMAIN (`acme.example.com` with
+// TENANT_DOMAIN=example.com), the header winning; requests naming neither
+// belong to the default tenant. `scope` resolves the tenant for the whole
+// request and the Postgres repositories scope every query by `current()`, so
+// lists, counts and pagination only ever see the tenant's rows, and
+// usernames, emails and banned words are unique per tenant.
+//
+// Tenants are provisioned through `/admin/tenants` with the ADMIN_TOKEN, which
+// also passes as admin in every tenant; admin API keys only do so in their
+// owner's tenant. The in-memory repositories and the CLI subcommands know
+// only the default tenant.
+
+use std::future::Future;
+
+use axum::extract::{Request, State};
+use axum::http::header::HOST;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::audit::{self, Change};
+use crate::auth::{Admin, RequireAdmin};
+use crate::error::ApiError;
+use crate::models::{CreateTenant, Tenant};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::repo;
+use crate::state::AppState;
+
+// the tenant of requests that don't name one, and of everything created
+// before there were tenants
+pub const DEFAULT_TENANT: i32 = 1;
+
+pub const X_TENANT: &str = "x-tenant";
+
+tokio::task_local! {
+    static TENANT: i32;
+}
+
+// the id of the tenant the request being handled belongs to
+pub fn current() -> i32 {
+    TENANT.try_with(|id| *id).unwrap_or(DEFAULT_TENANT)
+}
+
+// run work that outlives the request, such as a WebSocket, in its tenant
+pub async fn within<F: Future>(tenant_id: i32, work: F) -> F::Output {
+    TENANT.scope(tenant_id, work).await
+}
+
+// resolve the tenant for the whole request
+pub async fn scope(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let header = request
+        .headers()
+        .get(X_TENANT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let slug = header.or_else(|| {
+        let domain = state.config.tenant_domain.as_deref()?;
+        let host = request.headers().get(HOST)?.to_str().ok()?;
+        subdomain(host, domain)
+    });
+    let Some(slug) = slug else {
+        return TENANT.scope(DEFAULT_TENANT, next.run(request)).await;
+    };
+    match repo::tenants::find(&state.pool, &slug).await {
+        Ok(Some(tenant)) => TENANT.scope(tenant.id, next.run(request)).await,
+        Ok(None) => ApiError::new(
+            StatusCode::NOT_FOUND,
+            "unknown_tenant",
+            format!("no tenant {slug:?}"),
+        )
+        .into_response(),
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+// `acme` of `acme.example.com:5000` under `example.com`
+fn subdomain(host: &str, domain: &str) -> Option<String> {
+    let host = host.split(':').next()?.to_ascii_lowercase();
+    let label = host.strip_suffix(domain)?.strip_suffix('.')?;
+    (!label.is_empty() && !label.contains('.')).then(|| label.to_owned())
+}
+
+// handler for "GET /admin/tenants" rest API endpoint
+pub async fn list_tenants(
+    RequireAdmin(admin): RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<Tenant>>, ApiError> {
+    require_operator(admin)?;
+    let tenants = repo::tenants::list(&state.pool).await?;
+
+    Ok(format.respond(tenants))
+}
+
+// handler for "POST /admin/tenants" rest API endpoint, provisioning a tenant
+pub async fn create_tenant(
+    RequireAdmin(admin): RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Payload(mut tenant): Payload<CreateTenant>,
+) -> Result<Negotiated<Tenant>, ApiError> {
+    require_operator(admin)?;
+    tenant.slug = tenant.slug.trim().to_ascii_lowercase();
+    tenant.name = tenant.name.trim().to_owned();
+    if !valid_slug(&tenant.slug) {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_slug",
+            "slugs are 1 to 63 letters, digits and inner hyphens, usable as a subdomain",
+        )
+        .with("field", "slug"));
+    }
+    if tenant.name.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "missing_name",
+            "tenants need a name",
+        )
+        .with("field", "name"));
+    }
+    let tenant = repo::tenants::create(&state.pool, &tenant).await?;
+    audit::record(Change::new("tenant.create", "tenant", tenant.id).after(&tenant));
+
+    Ok(format.respond(tenant))
+}
+
+// tenants are managed by whoever runs the deployment, not by the admins of
+// one of them
+fn require_operator(admin: Admin) -> Result<(), ApiError> {
+    match admin {
+        Admin::Token | Admin::Service(_) => Ok(()),
+        Admin::User(_) => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "tenants are managed with the ADMIN_TOKEN",
+        )),
+    }
+}
+
+// a DNS label
+fn valid_slug(slug: &str) -> bool {
+    (1..=63).contains(&slug.len())
+        && slug
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+}
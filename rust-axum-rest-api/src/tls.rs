@@ -0,0 +1,420 @@
+// Native HTTPS, for exposing the binary without a reverse proxy in front.
+//
+// With TLS_CERT_FILE and TLS_KEY_FILE (PEM) set the server speaks TLS on
+// BIND_ADDR itself. Built with the `acme` feature, ACME_DOMAINS gets the
+// certificates from Let's Encrypt instead, answering its tls-alpn-01
+// challenges on that same port. Either way HTTP_REDIRECT_ADDR, when set, also
+// serves plain HTTP, redirecting every request to its HTTPS URL.
+//
+// Internal services can authenticate with client certificates: with
+// TLS_CLIENT_CA_FILE set, certificates issued by that CA bundle are accepted
+// (TLS_CLIENT_AUTH=required turns away connections without one, `optional` by
+// default). The certificate's first DNS or URI SAN, or else its CN, becomes
+// the request's `ClientIdentity`; MTLS_ADMIN_IDENTITIES lists the identities
+// that count as admins (see `auth`).
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::extract::Request;
+use axum::http::header::{HOST, LOCATION};
+use axum::http::request::Parts;
+use axum::http::uri::Authority;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+use x509_parser::extensions::GeneralName;
+
+use crate::error::ApiError;
+use crate::server::{self, Http2};
+use crate::shutdown::Shutdown;
+
+// how long clients get to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// what clients may pick over ALPN, HTTP/2 first
+const ALPN: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+#[derive(Clone, Debug)]
+pub enum TlsConfig {
+    // a certificate chain and its key, TLS_CERT_FILE and TLS_KEY_FILE, and
+    // which client certificates are accepted
+    Files {
+        cert: PathBuf,
+        key: PathBuf,
+        client_auth: Option<ClientAuth>,
+    },
+    #[cfg(feature = "acme")]
+    Acme(AcmeConfig),
+}
+
+// Which client certificates are accepted.
+#[derive(Clone, Debug)]
+pub struct ClientAuth {
+    // the CA bundle they must be issued by, TLS_CLIENT_CA_FILE
+    pub ca: PathBuf,
+    // whether connections need one, TLS_CLIENT_AUTH=required|optional
+    pub required: bool,
+}
+
+impl ClientAuth {
+    fn from_env() -> Result<Option<ClientAuth>, String> {
+        let Some(ca) = std::env::var_os("TLS_CLIENT_CA_FILE").filter(|ca| !ca.is_empty()) else {
+            return Ok(None);
+        };
+        let required = match std::env::var("TLS_CLIENT_AUTH").as_deref() {
+            Ok("required") => true,
+            Ok("optional") | Err(_) => false,
+            Ok(other) => {
+                return Err(format!(
+                    "invalid TLS_CLIENT_AUTH {other:?}, expected required or optional"
+                ))
+            }
+        };
+        Ok(Some(ClientAuth {
+            ca: ca.into(),
+            required,
+        }))
+    }
+}
+
+// Who the client certificate of a connection names, in the extensions of
+// its requests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientIdentity(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIdentity {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientIdentity>()
+            .cloned()
+            .ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::UNAUTHORIZED,
+                    "client_certificate_required",
+                    "a client certificate is required",
+                )
+            })
+    }
+}
+
+// Where certificates come from with ACME.
+#[cfg(feature = "acme")]
+#[derive(Clone, Debug)]
+pub struct AcmeConfig {
+    // the names certificates are for, comma separated ACME_DOMAINS
+    pub domains: Vec<String>,
+    // who Let's Encrypt tells about problems, ACME_CONTACT (an email)
+    pub contact: Option<String>,
+    // where the account and certificates are kept across restarts,
+    // ACME_CACHE_DIR, acme-cache by default
+    pub cache_dir: PathBuf,
+    // Let's Encrypt's production directory rather than its staging one,
+    // ACME_PRODUCTION=true|false, off by default
+    pub production: bool,
+}
+
+impl TlsConfig {
+    pub fn from_env() -> Result<Option<TlsConfig>, String> {
+        let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+        let client_auth = ClientAuth::from_env()?;
+        match (var("TLS_CERT_FILE"), var("TLS_KEY_FILE")) {
+            (Some(cert), Some(key)) => {
+                return Ok(Some(TlsConfig::Files {
+                    cert: cert.into(),
+                    key: key.into(),
+                    client_auth,
+                }))
+            }
+            (None, None) => {}
+            _ => return Err(String::from("TLS_CERT_FILE and TLS_KEY_FILE go together")),
+        }
+
+        let domains: Vec<String> = std::env::var("ACME_DOMAINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|domain| !domain.is_empty())
+            .map(str::to_owned)
+            .collect();
+        if domains.is_empty() {
+            return match client_auth {
+                Some(_) => Err(String::from("TLS_CLIENT_CA_FILE takes TLS_CERT_FILE")),
+                None => Ok(None),
+            };
+        }
+        if client_auth.is_some() {
+            return Err(String::from(
+                "client certificates aren't supported with ACME_DOMAINS",
+            ));
+        }
+        #[cfg(feature = "acme")]
+        {
+            let production = match std::env::var("ACME_PRODUCTION") {
+                Ok(value) => value.parse().map_err(|_| {
+                    format!("invalid ACME_PRODUCTION {value:?}, expected true or false")
+                })?,
+                Err(_) => false,
+            };
+            Ok(Some(TlsConfig::Acme(AcmeConfig {
+                domains,
+                contact: std::env::var("ACME_CONTACT")
+                    .ok()
+                    .filter(|contact| !contact.is_empty()),
+                cache_dir: var("ACME_CACHE_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("acme-cache")),
+                production,
+            })))
+        }
+        #[cfg(not(feature = "acme"))]
+        Err(String::from(
+            "ACME_DOMAINS needs a build with the acme feature",
+        ))
+    }
+}
+
+// the rustls configuration serving the certificate chain and key in the PEM
+// files `cert` and `key`, accepting client certificates as `client_auth` says
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_auth: Option<&ClientAuth>,
+) -> Result<ServerConfig, String> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("can't read {}: {e}", path.display()))
+    };
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid certificate in {}: {e}", cert.display()))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", cert.display()));
+    }
+    let key = rustls_pemfile::private_key(&mut open(key)?)
+        .map_err(|e| format!("invalid key in {}: {e}", key.display()))?
+        .ok_or_else(|| format!("no private key in {}", key.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = match client_auth {
+        None => builder.with_no_client_auth(),
+        Some(client_auth) => {
+            let mut roots = RootCertStore::empty();
+            for ca in rustls_pemfile::certs(&mut open(&client_auth.ca)?) {
+                let ca = ca.map_err(|e| {
+                    format!("invalid certificate in {}: {e}", client_auth.ca.display())
+                })?;
+                roots
+                    .add(ca)
+                    .map_err(|e| format!("unusable CA in {}: {e}", client_auth.ca.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match client_auth.required {
+                true => verifier.build(),
+                false => verifier.allow_unauthenticated().build(),
+            };
+            builder.with_client_cert_verifier(
+                verifier
+                    .map_err(|e| format!("unusable CA bundle {}: {e}", client_auth.ca.display()))?,
+            )
+        }
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("unusable certificate or key: {e}"))?;
+    config.alpn_protocols = ALPN.iter().map(|protocol| protocol.to_vec()).collect();
+    Ok(config)
+}
+
+// Serve `app` over TLS on `listener` until `shutdown` stops it.
+pub async fn serve(
+    listener: TcpListener,
+    tls: &TlsConfig,
+    http2: Http2,
+    app: Router,
+    shutdown: Shutdown,
+) -> Result<(), String> {
+    match tls {
+        TlsConfig::Files {
+            cert,
+            key,
+            client_auth,
+        } => {
+            let config = server_config(cert, key, client_auth.as_ref())?;
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+            serve_with(listener, acceptor, http2, app, shutdown).await;
+        }
+        #[cfg(feature = "acme")]
+        TlsConfig::Acme(acme) => acme::serve(listener, acme, http2, app, shutdown).await,
+    }
+    Ok(())
+}
+
+// serve `app` on the connections `acceptor` completes the handshake of
+pub async fn serve_with(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    http2: Http2,
+    app: Router,
+    shutdown: Shutdown,
+) {
+    loop {
+        let (tcp, peer) = tokio::select! {
+            accepted = server::accept(&listener) => accepted,
+            _ = shutdown.stopped() => return,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let stopping = shutdown.clone();
+        shutdown.track(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp)).await {
+                Ok(Ok(stream)) => {
+                    let identity = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|certs| certs.first())
+                        .and_then(client_identity);
+                    server::serve_connection(stream, peer, true, identity, http2, app, stopping)
+                        .await
+                }
+                Ok(Err(e)) => debug!(%peer, "TLS handshake failed: {e}"),
+                Err(_) => debug!(%peer, "TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+// the first DNS or URI SAN of a client certificate, or else its CN
+fn client_identity(cert: &CertificateDer) -> Option<ClientIdentity> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        let name = san.value.general_names.iter().find_map(|name| match name {
+            GeneralName::DNSName(name) | GeneralName::URI(name) => Some(name.to_string()),
+            _ => None,
+        });
+        if let Some(name) = name {
+            return Some(ClientIdentity(name));
+        }
+    }
+    let cn = cert.subject().iter_common_name().next()?;
+    cn.as_str().ok().map(|cn| ClientIdentity(cn.to_owned()))
+}
+
+// Answer plain HTTP on `listener` with permanent redirects to the same URL
+// over HTTPS on `https_port`.
+pub async fn redirect_to_https(listener: TcpListener, https_port: u16) {
+    let app = Router::new()
+        .fallback(move |request: Request| async move { https_redirect(&request, https_port) });
+    if let Err(e) = axum::serve(listener, app).await {
+        warn!("the HTTPS redirect stopped: {e}");
+    }
+}
+
+fn https_redirect(request: &Request, https_port: u16) -> Response {
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok());
+    let Some(host) = host else {
+        return ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "missing_host",
+            "a Host header is required",
+        )
+        .into_response();
+    };
+    let port = match https_port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str());
+    let location = format!("https://{}{port}{path}", host.host());
+    // 308 rather than 301 so that clients repeat POSTs as they were
+    (StatusCode::PERMANENT_REDIRECT, [(LOCATION, location)]).into_response()
+}
+
+#[cfg(feature = "acme")]
+mod acme {
+    use axum::Router;
+    use futures::StreamExt;
+    use rustls_acme::caches::DirCache;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tracing::warn;
+
+    use super::{AcmeConfig, ALPN};
+    use crate::server::{self, Http2};
+    use crate::shutdown::Shutdown;
+
+    // serve `app` with certificates from Let's Encrypt, which rustls-acme
+    // gets and renews while accepting connections
+    pub async fn serve(
+        listener: TcpListener,
+        config: &AcmeConfig,
+        http2: Http2,
+        app: Router,
+        shutdown: Shutdown,
+    ) {
+        let mut incoming = rustls_acme::AcmeConfig::new(&config.domains)
+            .contact(config.contact.iter().map(|email| format!("mailto:{email}")))
+            .cache(DirCache::new(config.cache_dir.clone()))
+            .directory_lets_encrypt(config.production)
+            .tokio_incoming(
+                TcpListenerStream::new(listener),
+                ALPN.iter().map(|protocol| protocol.to_vec()).collect(),
+            );
+        loop {
+            let accepted = tokio::select! {
+                accepted = incoming.next() => accepted,
+                _ = shutdown.stopped() => return,
+            };
+            let Some(accepted) = accepted else {
+                return;
+            };
+            let stream = match accepted {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("accepting a connection failed: {e}");
+                    continue;
+                }
+            };
+            let Ok(peer) = stream.get_ref().get_ref().0.get_ref().peer_addr() else {
+                continue;
+            };
+            let connection = server::serve_connection(
+                stream,
+                peer,
+                true,
+                None,
+                http2,
+                app.clone(),
+                shutdown.clone(),
+            );
+            shutdown.track(connection);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+// Runtime tunables: settings that can change without a restart, read from
+// the TOML file CONFIG_FILE names, e.g.
+//
+//     check_rate_limit = 20
+//     log_level = "debug"
+//     cors_origins = ["https://app.example.com"]
+//     geoip_db = "/var/lib/GeoIP/GeoLite2-City.mmdb"
+//     slow_query_ms = 250
+//
+// The file is read at startup and again on SIGHUP or `POST
+// /admin/config/reload`. A reload swaps the settings in place, so requests
+// and connections in flight carry on; a file that doesn't parse is
+// rejected and the settings in use are kept. Without CONFIG_FILE the
+// defaults apply.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use axum::extract::{Request, State};
+use axum::http::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use crate::audit::{self, Change};
+use crate::auth::RequireAdmin;
+use crate::error::ApiError;
+use crate::negotiate::{Accept, Negotiated, XmlElement};
+use crate::profile::LogFormat;
+use crate::redact::{JsonFormat, Redactor};
+use crate::repo::slow;
+use crate::runtime;
+use crate::state::AppState;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tunables {
+    // requests a client may make to `GET /users/check` per minute
+    pub check_rate_limit: u32,
+    // the most verbose level logged: error, warn, info, debug or trace
+    pub log_level: String,
+    // origins allowed to make cross-origin requests, "*" for any; none by
+    // default, any in the development profile (see `profile`)
+    pub cors_origins: Vec<String>,
+    // the MaxMind database clients are located with, see `geoip`
+    pub geoip_db: Option<PathBuf>,
+    // queries taking longer are logged, see `repo::slow`
+    pub slow_query_ms: u64,
+}
+
+impl Default for Tunables {
+    fn default() -> Self {
+        Tunables {
+            check_rate_limit: 20,
+            log_level: String::from("info"),
+            cors_origins: Vec::new(),
+            geoip_db: None,
+            slow_query_ms: slow::DEFAULT_THRESHOLD_MS,
+        }
+    }
+}
+
+impl XmlElement for Tunables {
+    const ELEMENT: &'static str = "config";
+    const LIST: &'static str = "configs";
+}
+
+impl Tunables {
+    // the tunables in `path`, the defaults without one
+    pub fn load(path: Option<&Path>) -> Result<Tunables, String> {
+        let Some(path) = path else {
+            return Ok(Tunables::default());
+        };
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("can't read {}: {e}", path.display()))?;
+        let tunables: Tunables =
+            toml::from_str(&text).map_err(|e| format!("invalid {}: {e}", path.display()))?;
+        tunables.level()?;
+        Ok(tunables)
+    }
+
+    fn level(&self) -> Result<LevelFilter, String> {
+        self.log_level
+            .parse()
+            .map_err(|_| format!("unknown log level {:?}", self.log_level))
+    }
+
+    // `any_origin` when none are listed
+    fn allows_origin(&self, origin: &HeaderValue, any_origin: bool) -> bool {
+        if self.cors_origins.is_empty() {
+            return any_origin;
+        }
+        self.cors_origins
+            .iter()
+            .any(|allowed| allowed == "*" || origin.as_bytes() == allowed.as_bytes())
+    }
+}
+
+// response headers cross-origin callers may read besides the basic ones
+const EXPOSED_HEADERS: &str = "x-total-count, x-total-count-exact, link, x-request-id, retry-after";
+
+// seconds browsers may cache a preflight answer
+const PREFLIGHT_MAX_AGE: u32 = 600;
+
+static LOG_LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+// log to stdout at INFO, at whatever level the tunables say once they are
+// applied, in `format` and with personal data and credentials masked (see
+// `redact`)
+pub fn init_logging(format: LogFormat) {
+    let (filter, handle) = reload::Layer::new(LevelFilter::INFO);
+    let redactor = Redactor::from_env();
+    let log = match format {
+        LogFormat::Text => fmt::layer().fmt_fields(redactor).boxed(),
+        LogFormat::Compact => fmt::layer().compact().fmt_fields(redactor).boxed(),
+        LogFormat::Json => fmt::layer()
+            .event_format(JsonFormat::new(redactor.clone()))
+            .fmt_fields(redactor)
+            .boxed(),
+    };
+    // the level only filters the log, tokio-console wants the runtime's
+    // trace events whatever it is, see `runtime`
+    tracing_subscriber::registry()
+        .with(log.with_filter(filter))
+        .with(runtime::console_layer())
+        .init();
+    let _ = LOG_LEVEL.set(handle);
+}
+
+// Put new tunables into effect, unless the GeoIP database can't be read.
+pub fn apply(state: &AppState, tunables: Tunables) -> Result<(), String> {
+    // first, so that nothing changes if it fails
+    state.geoip.load(tunables.geoip_db.as_deref())?;
+    if let (Some(handle), Ok(level)) = (LOG_LEVEL.get(), tunables.level()) {
+        if let Err(e) = handle.modify(|filter| *filter = level) {
+            tracing::error!("can't change the log level: {e}");
+        }
+    }
+    state.check_limiter.set_limit(tunables.check_rate_limit);
+    slow::set_threshold(Duration::from_millis(tunables.slow_query_ms));
+    state.tunables.store(tunables.into());
+    Ok(())
+}
+
+// Read the file again and apply it.
+pub fn reload(state: &AppState) -> Result<Tunables, String> {
+    let path = state
+        .config
+        .config_file
+        .as_deref()
+        .ok_or("there is no CONFIG_FILE to reload")?;
+    let tunables = Tunables::load(Some(path))?;
+    apply(state, tunables.clone())?;
+    info!("reloaded {}", path.display());
+    Ok(tunables)
+}
+
+// Reload on SIGHUP until the process exits.
+#[cfg(unix)]
+pub fn reload_on_sighup(state: AppState) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => return tracing::error!("can't listen for SIGHUP: {e}"),
+        };
+        while hangups.recv().await.is_some() {
+            if let Err(e) = reload(&state) {
+                tracing::error!("keeping the current config: {e}");
+            }
+        }
+    });
+}
+
+// Answer CORS preflight requests from allowed origins and let those
+// origins read the responses. Requests from other origins, and plain
+// OPTIONS requests, go through untouched.
+pub async fn cors(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let origin = request
+        .headers()
+        .get(ORIGIN)
+        .filter(|origin| {
+            let any_origin = state.config.profile.any_origin();
+            state.tunables.load().allows_origin(origin, any_origin)
+        })
+        .cloned();
+    let Some(origin) = origin else {
+        let mut response = next.run(request).await;
+        response
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static("origin"));
+        return response;
+    };
+
+    let headers = request.headers();
+    let preflight =
+        request.method() == Method::OPTIONS && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+    let mut response = if preflight {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let allowed = response.headers_mut();
+        for (requested, allow) in [
+            (ACCESS_CONTROL_REQUEST_METHOD, ACCESS_CONTROL_ALLOW_METHODS),
+            (ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_ALLOW_HEADERS),
+        ] {
+            if let Some(value) = headers.get(requested) {
+                allowed.insert(allow, value.clone());
+            }
+        }
+        allowed.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(PREFLIGHT_MAX_AGE));
+        response
+    } else {
+        let mut response = next.run(request).await;
+        response.headers_mut().insert(
+            ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSED_HEADERS),
+        );
+        response
+    };
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.append(VARY, HeaderValue::from_static("origin"));
+    response
+}
+
+// handler for "GET /admin/config" rest API endpoint, the tunables in effect
+pub async fn get_config(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+) -> Negotiated<Tunables> {
+    format.respond(Tunables::clone(&state.tunables.load()))
+}
+
+// handler for "POST /admin/config/reload" rest API endpoint
+pub async fn reload_config(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Tunables>, ApiError> {
+    let before = Tunables::clone(&state.tunables.load());
+    let tunables = reload(&state)
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_config", e))?;
+    audit::record(
+        Change::new("config.reload", "config", "tunables")
+            .before(&before)
+            .after(&tunables),
+    );
+
+    Ok(format.respond(tunables))
+}
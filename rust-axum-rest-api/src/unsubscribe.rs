@@ -0,0 +1,105 @@
+// One-click unsubscribe: every digest (see `digest`) carries a link to
+// `/email/unsubscribe?token=<token>` that turns it off without logging in,
+// along with the List-Unsubscribe and List-Unsubscribe-Post headers of
+// RFC 8058, with which mail clients show their own unsubscribe button:
+//
+//     List-Unsubscribe: <https://api.example.com/email/unsubscribe?token=42.kq...>
+//     List-Unsubscribe-Post: List-Unsubscribe=One-Click
+//
+// Mail clients `POST` to the link, people following it `GET` it; both
+// unsubscribe. Tokens are the user's id and its HMAC-SHA256 under
+// EMAIL_LINK_SECRET; they don't expire, the link in an old digest keeps
+// working. The headers take absolute URLs, so they are only sent with
+// PUBLIC_URL.
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Html;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::info;
+
+use crate::error::ApiError;
+use crate::repo;
+use crate::state::AppState;
+
+const PATH: &str = "/email/unsubscribe";
+
+// `/email/unsubscribe` parameters
+#[derive(Deserialize)]
+pub struct UnsubscribeParams {
+    token: String,
+}
+
+fn mac(secret: &str, user_id: i32) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(b"unsubscribe.");
+    mac.update(user_id.to_string().as_bytes());
+    mac
+}
+
+// The token unsubscribing `user_id`, signed with `secret`.
+pub fn token(secret: &str, user_id: i32) -> String {
+    let signature = mac(secret, user_id).finalize().into_bytes();
+    format!("{user_id}.{}", BASE64URL.encode(signature))
+}
+
+// The user `token` unsubscribes, if `secret` signed it.
+pub fn verify(secret: &str, token: &str) -> Option<i32> {
+    let (user_id, signature) = token.split_once('.')?;
+    let user_id = user_id.parse().ok()?;
+    let signature = BASE64URL.decode(signature).ok()?;
+    mac(secret, user_id)
+        .verify_slice(&signature)
+        .ok()
+        .map(|()| user_id)
+}
+
+// The link unsubscribing `user_id`, absolute with PUBLIC_URL.
+pub fn link(state: &AppState, user_id: i32) -> String {
+    let base = state.config.public_url.as_deref().unwrap_or_default();
+    let token = token(&state.config.email_link_secret, user_id);
+    format!("{base}{PATH}?token={token}")
+}
+
+// The RFC 8058 headers of an email to `user_id`, none without PUBLIC_URL.
+pub fn headers(state: &AppState, user_id: i32) -> Vec<(&'static str, String)> {
+    if state.config.public_url.is_none() {
+        return Vec::new();
+    }
+    vec![
+        ("List-Unsubscribe", format!("<{}>", link(state, user_id))),
+        (
+            "List-Unsubscribe-Post",
+            String::from("List-Unsubscribe=One-Click"),
+        ),
+    ]
+}
+
+// handler for "GET /email/unsubscribe" and "POST /email/unsubscribe" rest API
+// endpoints
+pub async fn unsubscribe(
+    State(state): State<AppState>,
+    Query(params): Query<UnsubscribeParams>,
+) -> Result<Html<&'static str>, ApiError> {
+    let user_id = verify(&state.config.email_link_secret, &params.token).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_token",
+            "this unsubscribe link is not valid",
+        )
+        .with("field", "token")
+    })?;
+    if repo::preferences::unsubscribe(&state.pool, user_id).await? {
+        info!(user_id, "unsubscribed from the weekly digest");
+    }
+
+    Ok(Html(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Unsubscribed</title></head>\n\
+         <body><p>You won't get the weekly digest anymore.</p></body>\n</html>\n",
+    ))
+}
@@ -0,0 +1,376 @@
+// Usage metering: the API calls of users, and the posts and comments users
+// and organizations create, counted per billing period, a calendar month in
+// UTC. `GET /me/usage` shows the acting user's along with their plan's
+// quotas (see `billing`).
+//
+// Counts are kept in memory and added to the usage table every
+// `FLUSH_INTERVAL` in one statement, rather than written on every request;
+// what wasn't flushed yet is lost with the process. Each process reads a
+// counter once and then only adds its own counts, so with several instances
+// a quota may be overrun by what the others counted since.
+//
+// With billing on, quotas are enforced: past `SOFT_LIMIT_PERCENT` of one,
+// responses carry an X-Usage-Warning header; past all of it, API calls are
+// 429s until the next period and creating posts or comments is a 402 until
+// an upgrade, both saying what was used of what.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use serde::Serialize;
+use tracing::error;
+
+use crate::auth::Actor;
+use crate::billing;
+use crate::error::ApiError;
+use crate::models::Plan;
+use crate::negotiate::{Accept, Negotiated, XmlElement};
+use crate::repo;
+use crate::repo::usage::UsageBatch;
+use crate::state::AppState;
+use crate::tenant;
+
+pub const WARNING_HEADER: &str = "x-usage-warning";
+
+// how much of a quota is used before responses warn about it
+pub const SOFT_LIMIT_PERCENT: i64 = 80;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+// Who usage counts against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Owner {
+    User(i32),
+    Org(i32),
+}
+
+impl Owner {
+    // the owner of what a user creates, in an organization or not
+    pub fn of(user_id: Option<i32>, org_id: Option<i32>) -> Option<Owner> {
+        match (org_id, user_id) {
+            (Some(org_id), _) => Some(Owner::Org(org_id)),
+            (None, Some(user_id)) => Some(Owner::User(user_id)),
+            (None, None) => None,
+        }
+    }
+
+    fn columns(self) -> (Option<i32>, Option<i32>) {
+        match self {
+            Owner::User(id) => (Some(id), None),
+            Owner::Org(id) => (None, Some(id)),
+        }
+    }
+}
+
+// What is counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    ApiCalls,
+    Posts,
+    Comments,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 3] = [Metric::ApiCalls, Metric::Posts, Metric::Comments];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Metric::ApiCalls => "api_calls",
+            Metric::Posts => "posts",
+            Metric::Comments => "comments",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Metric::ApiCalls => "API calls",
+            Metric::Posts => "posts",
+            Metric::Comments => "comments",
+        }
+    }
+}
+
+// The first day of the billing period `now` is in, and when it ends.
+pub fn period(now: DateTime<Utc>) -> (NaiveDate, DateTime<Utc>) {
+    let start = now
+        .date_naive()
+        .with_day(1)
+        .expect("months have a first day");
+    let end = start + Months::new(1);
+    (start, end.and_time(Default::default()).and_utc())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct CounterKey {
+    tenant_id: i32,
+    owner: Owner,
+    period: NaiveDate,
+    metric: Metric,
+}
+
+#[derive(Default)]
+struct Counter {
+    // what the table had when first read, plus what was flushed since
+    stored: Option<i64>,
+    // counted since the last flush
+    pending: i64,
+}
+
+// Counts of this process, see the top of the module.
+#[derive(Default)]
+pub struct UsageMeter {
+    counters: Mutex<HashMap<CounterKey, Counter>>,
+}
+
+impl UsageMeter {
+    // count `n` of `metric` for `owner` of the current tenant, in the current
+    // period
+    pub fn record(&self, owner: Owner, metric: Metric, n: i64) {
+        let key = CounterKey {
+            tenant_id: tenant::current(),
+            owner,
+            period: period(Utc::now()).0,
+            metric,
+        };
+        let mut counters = self.counters.lock().expect("usage meter lock poisoned");
+        counters.entry(key).or_default().pending += n;
+    }
+
+    // what `owner` used of `metric` in the current period
+    pub async fn used(
+        &self,
+        pool: &sqlx::Pool<sqlx::Postgres>,
+        owner: Owner,
+        metric: Metric,
+    ) -> Result<i64, sqlx::Error> {
+        let key = CounterKey {
+            tenant_id: tenant::current(),
+            owner,
+            period: period(Utc::now()).0,
+            metric,
+        };
+        if let Some(used) = self.peek(&key) {
+            return Ok(used);
+        }
+        let (user_id, org_id) = owner.columns();
+        let stored = repo::usage::get(pool, user_id, org_id, key.period, metric.as_str()).await?;
+        let mut counters = self.counters.lock().expect("usage meter lock poisoned");
+        let counter = counters.entry(key).or_default();
+        let stored = *counter.stored.get_or_insert(stored);
+        Ok(stored + counter.pending)
+    }
+
+    fn peek(&self, key: &CounterKey) -> Option<i64> {
+        let counters = self.counters.lock().expect("usage meter lock poisoned");
+        counters
+            .get(key)
+            .and_then(|counter| Some(counter.stored? + counter.pending))
+    }
+
+    // Add the pending counts to the table, returning how many counters had
+    // some. Counters of past periods are dropped once flushed.
+    pub async fn flush(&self, pool: &sqlx::Pool<sqlx::Postgres>) -> Result<usize, sqlx::Error> {
+        let mut batch = UsageBatch::default();
+        let mut taken = Vec::new();
+        {
+            let mut counters = self.counters.lock().expect("usage meter lock poisoned");
+            for (key, counter) in counters.iter_mut() {
+                if counter.pending == 0 {
+                    continue;
+                }
+                let (user_id, org_id) = key.owner.columns();
+                batch.tenant_ids.push(key.tenant_id);
+                batch.user_ids.push(user_id);
+                batch.org_ids.push(org_id);
+                batch.periods.push(key.period);
+                batch.metrics.push(key.metric.as_str());
+                batch.counts.push(counter.pending);
+                taken.push((*key, counter.pending));
+                counter.pending = 0;
+            }
+        }
+        if taken.is_empty() {
+            return Ok(0);
+        }
+
+        let result = repo::usage::add(pool, &batch).await;
+        let current = period(Utc::now()).0;
+        let mut counters = self.counters.lock().expect("usage meter lock poisoned");
+        for (key, count) in &taken {
+            let counter = counters.entry(*key).or_default();
+            match (&result, counter.stored.as_mut()) {
+                // counted again with the next flush
+                (Err(_), _) => counter.pending += count,
+                (Ok(()), Some(stored)) => *stored += count,
+                (Ok(()), None) => {}
+            }
+        }
+        counters.retain(|key, counter| key.period >= current || counter.pending > 0);
+        result.map(|()| taken.len())
+    }
+}
+
+// Flush the meter every `FLUSH_INTERVAL` until the process exits.
+pub fn spawn_flusher(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.usage.flush(&state.pool).await {
+                error!("flushing usage failed: {e}");
+            }
+        }
+    });
+}
+
+// Check `owner`'s use of `metric` against `plan`'s quota, returning the
+// X-Usage-Warning past the soft limit and the error past the hard one.
+pub async fn check(
+    state: &AppState,
+    owner: Owner,
+    plan: Plan,
+    metric: Metric,
+) -> Result<Option<String>, ApiError> {
+    let Some(quota) = billing::limits(plan).quota(metric) else {
+        return Ok(None);
+    };
+    let used = state.usage.used(&state.pool, owner, metric).await?;
+    if used >= quota {
+        return Err(exceeded(plan, metric, used, quota));
+    }
+    if used * 100 >= quota * SOFT_LIMIT_PERCENT {
+        return Ok(Some(format!(
+            "{used} of the {} plan's {quota} {} used this period",
+            plan.as_str(),
+            metric.describe()
+        )));
+    }
+    Ok(None)
+}
+
+fn exceeded(plan: Plan, metric: Metric, used: i64, quota: i64) -> ApiError {
+    let (_, period_end) = period(Utc::now());
+    let (status, advice) = match metric {
+        Metric::ApiCalls => (StatusCode::TOO_MANY_REQUESTS, "wait for the next period"),
+        Metric::Posts | Metric::Comments => (StatusCode::PAYMENT_REQUIRED, "upgrade for more"),
+    };
+    ApiError::new(
+        status,
+        "usage_exceeded",
+        format!(
+            "the {} plan allows {quota} {} a period, {advice}",
+            plan.as_str(),
+            metric.describe()
+        ),
+    )
+    .with("metric", metric)
+    .with("used", used)
+    .with("limit", quota)
+    .with("plan", plan)
+    .with("period_end", period_end)
+}
+
+// The response to a request over its quota, with a Retry-After for the ones
+// that pass with the period.
+pub fn reject(error: ApiError) -> Response {
+    if error.status != StatusCode::TOO_MANY_REQUESTS {
+        return error.into_response();
+    }
+    let (_, period_end) = period(Utc::now());
+    let seconds = (period_end - Utc::now()).num_seconds().max(0) + 1;
+    ([(RETRY_AFTER, seconds.to_string())], error).into_response()
+}
+
+// Refuse creating one more of `metric` for `owner` past their plan's quota;
+// nothing is refused with billing off or without an owner.
+pub async fn check_resource(
+    state: &AppState,
+    owner: Option<Owner>,
+    metric: Metric,
+) -> Result<(), ApiError> {
+    let (Some(owner), Some(_)) = (owner, &state.config.stripe) else {
+        return Ok(());
+    };
+    let plan = match owner {
+        Owner::User(user_id) => billing::user_plan(state, user_id).await?,
+        Owner::Org(org_id) => billing::org_plan(state, org_id).await?,
+    };
+    check(state, owner, plan, metric).await.map(|_| ())
+}
+
+// Count one more of `metric` created for `owner`.
+pub fn record_resource(state: &AppState, owner: Option<Owner>, metric: Metric) {
+    if let Some(owner) = owner {
+        state.usage.record(owner, metric, 1);
+    }
+}
+
+#[derive(Serialize)]
+pub struct MetricUsage {
+    metric: Metric,
+    used: i64,
+    // None without a quota
+    limit: Option<i64>,
+    soft_limit: Option<i64>,
+}
+
+impl XmlElement for MetricUsage {
+    const ELEMENT: &'static str = "metric";
+    const LIST: &'static str = "metrics";
+}
+
+// `GET /me/usage` response
+#[derive(Serialize)]
+pub struct Usage {
+    // None with billing off
+    plan: Option<Plan>,
+    period_start: NaiveDate,
+    period_end: DateTime<Utc>,
+    metrics: Vec<MetricUsage>,
+}
+
+impl XmlElement for Usage {
+    const ELEMENT: &'static str = "usage";
+    const LIST: &'static str = "usage";
+}
+
+// handler for "GET /me/usage" rest API endpoint
+pub async fn my_usage(
+    State(state): State<AppState>,
+    actor: Actor,
+    Accept(format): Accept,
+) -> Result<Negotiated<Usage>, ApiError> {
+    let user_id = actor.require_user()?;
+    let plan = match state.config.stripe {
+        Some(_) => Some(billing::user_plan(&state, user_id).await?),
+        None => None,
+    };
+    let (period_start, period_end) = period(Utc::now());
+    let mut metrics = Vec::new();
+    for metric in Metric::ALL {
+        let limit = plan.and_then(|plan| billing::limits(plan).quota(metric));
+        metrics.push(MetricUsage {
+            metric,
+            used: state
+                .usage
+                .used(&state.pool, Owner::User(user_id), metric)
+                .await?,
+            limit,
+            soft_limit: limit.map(|limit| limit * SOFT_LIMIT_PERCENT / 100),
+        });
+    }
+
+    Ok(format.respond(Usage {
+        plan,
+        period_start,
+        period_end,
+        metrics,
+    }))
+}
@@ -0,0 +1,79 @@
+// Rules for usernames, applied before anything is stored.
+//
+// Usernames are normalized to Unicode NFKC and trimmed, so that look-alike
+// spellings ("ｒｏｏｔ", a trailing space) map to the same name, must be
+// printable without whitespace, and may not be one of the reserved names
+// (RESERVED_USERNAMES, compared ignoring case). Uniqueness ignores case too,
+// enforced per tenant by a unique index on LOWER(username).
+//
+// Every way of signing up goes through `check`, which also runs the name by
+// the banned words (see `content_filter`).
+
+use axum::http::StatusCode;
+use tracing::warn;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::content_filter::{self, Verdict};
+use crate::error::ApiError;
+use crate::state::AppState;
+
+pub const MAX_LENGTH: usize = 64;
+
+// reserved unless RESERVED_USERNAMES says otherwise
+pub const DEFAULT_RESERVED: &[&str] = &[
+    "admin",
+    "administrator",
+    "api",
+    "me",
+    "null",
+    "root",
+    "support",
+    "system",
+];
+
+// the normalized form of `raw`, or why it can't be a username
+pub fn normalize(raw: &str, reserved: &[String]) -> Result<String, ApiError> {
+    let name: String = raw.trim().nfkc().collect();
+    if name.is_empty() || name.chars().count() > MAX_LENGTH {
+        return Err(invalid(format!(
+            "usernames are 1 to {MAX_LENGTH} characters long"
+        )));
+    }
+    if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(invalid("usernames may not contain whitespace"));
+    }
+    let folded = name.to_lowercase();
+    if reserved
+        .iter()
+        .any(|reserved| reserved.to_lowercase() == folded)
+    {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "reserved_username",
+            format!("the username {name:?} is reserved"),
+        )
+        .with("field", "username"));
+    }
+    Ok(name)
+}
+
+// the username `raw` signs up as, normalized and with the reserved names
+// of the configuration refused; rejected words are refused too, flagged
+// ones logged
+pub async fn check(state: &AppState, raw: &str) -> Result<String, ApiError> {
+    let name = normalize(raw, &state.config.reserved_usernames)?;
+    let texts = [("username", name.as_str())];
+    if content_filter::check(state.banned_words.as_ref(), &texts).await? == Verdict::Flag {
+        warn!("username {name:?} matches a flagged word");
+    }
+    Ok(name)
+}
+
+fn invalid(message: impl Into<String>) -> ApiError {
+    ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_username",
+        message,
+    )
+    .with("field", "username")
+}
@@ -0,0 +1,112 @@
+// The delivery worker: POSTs queued events to their endpoints, signed with
+// the endpoint's secret at the time of sending. An endpoint answering with
+// anything but a 2xx fails the attempt, which is retried with exponential
+// backoff starting at `FIRST_RETRY`; after `MAX_ATTEMPTS` the delivery is
+// given up on and becomes a dead letter, which admins can redeliver.
+// Endpoints failing `DISABLE_AFTER` attempts in a row are disabled until an
+// admin enables them again.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{Pool, Postgres};
+use tracing::{error, info, warn};
+
+use crate::repo;
+use crate::repo::webhooks::Delivery;
+use crate::webhooks::{signature, ID_HEADER, SIGNATURE_HEADER};
+
+// deliveries taken at once
+const BATCH: i64 = 20;
+
+// how long a taken delivery is held before another worker may retry it,
+// longer than a request can take
+const LEASE: Duration = Duration::from_secs(60);
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const FIRST_RETRY: chrono::Duration = chrono::Duration::minutes(1);
+
+pub const MAX_ATTEMPTS: i32 = 8;
+
+pub const DISABLE_AFTER: i32 = 20;
+
+fn http() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(concat!("rust-axum-rest-api/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .expect("the webhook HTTP client builds")
+    })
+}
+
+// Poll for due deliveries until the process exits.
+pub fn spawn(pool: Pool<Postgres>) {
+    tokio::spawn(async move {
+        loop {
+            match run_once(&pool).await {
+                // there may be more
+                Ok(count) if count as i64 == BATCH => continue,
+                Ok(_) => {}
+                Err(e) => error!("webhook delivery failed: {e}"),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+// Deliver one batch of due events, returning how many were attempted.
+pub async fn run_once(pool: &Pool<Postgres>) -> Result<usize, sqlx::Error> {
+    let due = repo::webhooks::claim_due(pool, BATCH, LEASE.as_secs_f64()).await?;
+    for delivery in &due {
+        match send(delivery).await {
+            Ok(()) => repo::webhooks::delivered(pool, delivery).await?,
+            Err(reason) => {
+                let attempts = delivery.attempts + 1;
+                let retry_at = (attempts < MAX_ATTEMPTS)
+                    .then(|| Utc::now() + FIRST_RETRY * 2i32.pow(attempts as u32 - 1));
+                match retry_at {
+                    Some(at) => info!(
+                        "webhook delivery {} to {} failed, retrying at {at}: {reason}",
+                        delivery.id, delivery.url
+                    ),
+                    None => warn!(
+                        "giving up on webhook delivery {} to {}: {reason}",
+                        delivery.id, delivery.url
+                    ),
+                }
+                let disabled =
+                    repo::webhooks::failed(pool, delivery, &reason, retry_at, DISABLE_AFTER)
+                        .await?;
+                if disabled {
+                    warn!(
+                        "disabled webhook {} after {DISABLE_AFTER} failures in a row",
+                        delivery.endpoint_id
+                    );
+                }
+            }
+        }
+    }
+    Ok(due.len())
+}
+
+async fn send(delivery: &Delivery) -> Result<(), String> {
+    let body = serde_json::to_vec(&delivery.payload).map_err(|e| e.to_string())?;
+    let signature = signature(&delivery.secret, Utc::now().timestamp(), &body);
+    let response = http()
+        .post(&delivery.url)
+        .header("content-type", "application/json")
+        .header(ID_HEADER, delivery.event_id.to_string())
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("the endpoint answered {}", response.status()));
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+// Webhooks: admins register endpoints that their tenant's events are POSTed
+// to, as JSON like
+//
+//     {"id": "…", "type": "post.created", "created_at": "…", "data": {…}}
+//
+// with the post or comment as `data`. Every delivery is signed with the
+// endpoint's secret, handed out once when the endpoint is created:
+//
+//     X-Signature: t=1736675000,v1=5f0c…
+//
+// `t` is when the delivery was sent and `v1` the hex HMAC-SHA256 of
+// "{t}.{body}". Receivers recompute it and turn away old timestamps, so a
+// captured delivery can't be replayed later; `api_client::webhooks::verify`
+// does both. X-Webhook-Id holds the event id, the same for every endpoint
+// and attempt, to tell duplicates apart.
+//
+// Events come from the `events` bus: the dispatcher queues each one for the
+// subscribed endpoints and the worker in `delivery` sends the queue, retrying
+// failed attempts. Admins see the deliveries given up on at
+// `/admin/webhooks/dead-letters` and can queue them again.
+
+pub mod delivery;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{Pool, Postgres};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::audit::{self, Change};
+use crate::auth::RequireAdmin;
+use crate::error::ApiError;
+use crate::events::Event;
+use crate::models::{CreateWebhookEndpoint, Message, WebhookDelivery, WebhookEndpoint};
+use crate::negotiate::{Accept, Negotiated, Payload};
+use crate::pagination::PageParams;
+use crate::repo;
+use crate::repo::api_keys::hex;
+use crate::state::AppState;
+use crate::tenant;
+
+pub const SIGNATURE_HEADER: &str = "x-signature";
+pub const ID_HEADER: &str = "x-webhook-id";
+
+// what endpoints can subscribe to
+pub const EVENT_TYPES: &[&str] = &["post.created", "comment.added"];
+
+// The X-Signature of `body` sent at `timestamp`, in Unix seconds.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("t={timestamp},v1={}", hex(&mac.finalize().into_bytes()))
+}
+
+// Queue deliveries of the events published from now on, until the process
+// exits. Events the dispatcher falls too far behind on are lost.
+pub fn spawn_dispatcher(state: &AppState) {
+    let pool = state.pool.clone();
+    let mut events = state.events.subscribe_local();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok((tenant_id, event)) => {
+                    if let Err(e) = tenant::within(tenant_id, enqueue(&pool, &event)).await {
+                        error!("queueing webhooks failed: {e}");
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => warn!("webhooks missed {missed} events"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+// Queue an event for the current tenant's endpoints subscribed to it,
+// returning how many there were.
+pub async fn enqueue(pool: &Pool<Postgres>, event: &Event) -> Result<u64, sqlx::Error> {
+    let (event_type, data) = match event {
+        Event::PostCreated(post) => ("post.created", json!(post)),
+        Event::CommentAdded(comment) => ("comment.added", json!(comment)),
+    };
+    let id = Uuid::new_v4();
+    let payload = json!({
+        "id": id,
+        "type": event_type,
+        "created_at": Utc::now(),
+        "data": data,
+    });
+    repo::webhooks::enqueue(pool, id, event_type, &payload).await
+}
+
+// handler for "GET /admin/webhooks" rest API endpoint
+pub async fn list_webhooks(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<WebhookEndpoint>>, ApiError> {
+    let endpoints = repo::webhooks::list(&state.pool).await?;
+
+    Ok(format.respond(endpoints))
+}
+
+// handler for "POST /admin/webhooks" rest API endpoint, answering with the
+// endpoint's secret
+pub async fn create_webhook(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Accept(format): Accept,
+    Payload(endpoint): Payload<CreateWebhookEndpoint>,
+) -> Result<Negotiated<WebhookEndpoint>, ApiError> {
+    let url = reqwest::Url::parse(&endpoint.url).ok();
+    if !url.is_some_and(|url| matches!(url.scheme(), "http" | "https")) {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_url",
+            "expected an http or https URL",
+        )
+        .with("field", "url"));
+    }
+    if let Some(unknown) = endpoint
+        .events
+        .iter()
+        .find(|event| !EVENT_TYPES.contains(&event.as_str()))
+    {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_event",
+            format!("unknown event type {unknown:?}"),
+        )
+        .with("field", "events"));
+    }
+    let created = repo::webhooks::create(&state.pool, &endpoint.url, &endpoint.events).await?;
+    let recorded = WebhookEndpoint {
+        secret: None,
+        ..created.clone()
+    };
+    audit::record(Change::new("webhook.create", "webhook", created.id).after(&recorded));
+
+    Ok(format.respond(created))
+}
+
+// handler for "DELETE /admin/webhooks/:id" rest API endpoint, dropping the
+// deliveries still queued for it too
+pub async fn delete_webhook(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Message>, ApiError> {
+    if !repo::webhooks::delete(&state.pool, id).await? {
+        return Err(ApiError::not_found("webhook not found"));
+    }
+    audit::record(Change::new("webhook.delete", "webhook", id));
+
+    Ok(format.respond(Message {
+        message: String::from("Webhook deleted"),
+    }))
+}
+
+// handler for "POST /admin/webhooks/:id/enable" rest API endpoint, for
+// endpoints disabled after failing too often
+pub async fn enable_webhook(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Accept(format): Accept,
+) -> Result<Negotiated<WebhookEndpoint>, ApiError> {
+    let endpoint = repo::webhooks::enable(&state.pool, id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("webhook not found"))?;
+    audit::record(Change::new("webhook.enable", "webhook", id).after(&endpoint));
+
+    Ok(format.respond(endpoint))
+}
+
+// handler for "GET /admin/webhooks/dead-letters" rest API endpoint, the
+// deliveries given up on, most recent first
+pub async fn dead_letters(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Query(page): Query<PageParams>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<WebhookDelivery>>, ApiError> {
+    let deliveries = repo::webhooks::dead_letters(&state.pool, page.page()?).await?;
+
+    Ok(format.respond(deliveries))
+}
+
+// handler for "POST /admin/webhooks/deliveries/:id/redeliver" rest API
+// endpoint, queueing a delivery again with a fresh round of attempts
+pub async fn redeliver(
+    _admin: RequireAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Accept(format): Accept,
+) -> Result<Negotiated<WebhookDelivery>, ApiError> {
+    let delivery = repo::webhooks::redeliver(&state.pool, id)
+        .await?
+        .ok_or_else(|| ApiError::not_found("delivery not found"))?;
+    audit::record(Change::new("webhook.redeliver", "webhook_delivery", id));
+
+    Ok(format.respond(delivery))
+}
@@ -0,0 +1,98 @@
+// The typed client against a served app, so that the `api-types` it decodes
+// and the handlers' responses can't drift apart unnoticed.
+
+mod common;
+
+use api_client::{Client, CreatePost, CreateReport, CreateUser, Error, FieldChange, UpdatePost};
+use common::{unique, TestApp, ADMIN_TOKEN};
+use reqwest::StatusCode;
+use rust_axum_rest_api::repo::api_keys;
+use serde_json::json;
+
+#[tokio::test]
+async fn client_round_trips_posts_and_users() {
+    let app = TestApp::new().await;
+    let base = format!("http://{}", app.serve().await);
+    let anonymous = Client::new(&base);
+    let admin = Client::new(&base).with_token(ADMIN_TOKEN);
+
+    let username = unique("user");
+    let user = anonymous
+        .create_user(&CreateUser {
+            username: username.clone(),
+            email: format!("{username}@example.com"),
+        })
+        .await
+        .unwrap();
+    assert_eq!(user.username, username);
+
+    let post = admin
+        .create_post(&CreatePost {
+            title: String::from("Hello"),
+            body: String::from("World"),
+            user_id: Some(user.id),
+            org_id: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        (post.title.as_str(), post.user_id),
+        ("Hello", Some(user.id))
+    );
+    let fetched = anonymous.get_post(&post.public_id).await.unwrap();
+    assert_eq!((fetched.id, fetched.uuid), (post.id, post.uuid));
+    assert_eq!(anonymous.count_posts().await.unwrap(), 1);
+    let listed = anonymous.list_posts().await.unwrap();
+    assert_eq!(listed.iter().map(|p| p.id).collect::<Vec<_>>(), [post.id]);
+
+    let updated = admin
+        .update_post(
+            post.id,
+            &UpdatePost {
+                title: String::from("Hello again"),
+                body: String::from("World"),
+                user_id: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        (updated.title.as_str(), updated.user_id),
+        ("Hello again", Some(user.id))
+    );
+    let history = admin.post_history(post.id).await.unwrap();
+    assert_eq!(history[0].action, "post.update");
+    assert!(history[0].changes.contains(&FieldChange {
+        field: String::from("title"),
+        old: json!("Hello"),
+        new: json!("Hello again"),
+    }));
+
+    let (_, key) = api_keys::create(&app.pool, user.id, "client")
+        .await
+        .unwrap();
+    let report = Client::new(&base)
+        .with_token(key)
+        .report_post(
+            post.id,
+            &CreateReport {
+                reason: String::from("spam"),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        (report.post_id, report.reporter_id),
+        (post.id, Some(user.id))
+    );
+    assert_eq!(report.outcome, None);
+
+    admin.delete_post(post.id).await.unwrap();
+    match anonymous.get_post(post.id).await {
+        Err(Error::Status { status, body }) => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert!(body.contains("not_found"), "{body}");
+        }
+        other => panic!("expected a 404, got {other:?}"),
+    }
+}
@@ -0,0 +1,299 @@
+// The maintenance commands of the binary, run the way operators do against a
+// disposable database.
+
+mod common;
+
+use std::process::Output;
+
+use common::db::TestDb;
+use common::unique;
+use rust_axum_rest_api::repo::api_keys;
+use tokio::process::Command;
+
+// run `app <args>` on `db` with just the variables of `env` set (APP_ENV
+// included, production when it isn't), away from any .env files
+async fn app(db: &TestDb, env: &[(&str, &str)], args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rust-axum-rest-api"))
+        .args(args)
+        .current_dir(std::env::temp_dir())
+        .env("DATABASE_URL", &db.url)
+        .env_remove("APP_ENV")
+        .env_remove("EMAIL_LINK_SECRET")
+        .envs(env.iter().copied())
+        .output()
+        .await
+        .expect("run the binary")
+}
+
+const PRODUCTION: &[(&str, &str)] = &[("APP_ENV", "production"), ("EMAIL_LINK_SECRET", "links")];
+
+fn assert_ok(out: &Output) {
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+async fn count(db: &TestDb, sql: &str) -> i64 {
+    sqlx::query_scalar(sql).fetch_one(&db.pool).await.unwrap()
+}
+
+#[tokio::test]
+async fn seeding_is_idempotent() {
+    let db = TestDb::new().await;
+
+    for _ in 0..2 {
+        let out = app(
+            &db,
+            &[("APP_ENV", "test")],
+            &["seed", "--users", "3", "--posts", "10"],
+        )
+        .await;
+        assert_ok(&out);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM users").await, 3);
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM posts").await, 10);
+    }
+
+    // topped up, not started over
+    let out = app(
+        &db,
+        &[("APP_ENV", "dev")],
+        &["seed", "--users", "3", "--posts", "12"],
+    )
+    .await;
+    assert_ok(&out);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM posts").await, 12);
+}
+
+#[tokio::test]
+async fn seeding_takes_a_development_profile_or_force() {
+    let db = TestDb::new().await;
+
+    for env in [&[("EMAIL_LINK_SECRET", "links")], PRODUCTION] {
+        let out = app(&db, env, &["seed", "--users", "3", "--posts", "10"]).await;
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("--force"));
+        assert_eq!(count(&db, "SELECT COUNT(*) FROM users").await, 0);
+    }
+
+    let out = app(
+        &db,
+        PRODUCTION,
+        &["seed", "--users", "3", "--posts", "10", "--force"],
+    )
+    .await;
+    assert_ok(&out);
+    assert_eq!(count(&db, "SELECT COUNT(*) FROM users").await, 3);
+}
+
+#[tokio::test]
+async fn production_takes_an_email_link_secret() {
+    let db = TestDb::new().await;
+
+    for env in [&[][..], &[("APP_ENV", "production")]] {
+        let out = app(&db, env, &["rotate-signing-key"]).await;
+        assert!(!out.status.success());
+        assert!(String::from_utf8_lossy(&out.stderr).contains("EMAIL_LINK_SECRET"));
+    }
+
+    assert_ok(&app(&db, PRODUCTION, &["rotate-signing-key"]).await);
+    assert_ok(&app(&db, &[("APP_ENV", "development")], &["rotate-signing-key"]).await);
+}
+
+#[tokio::test]
+async fn backups_restore_and_truncate_only_when_forced() {
+    let (db, copy) = (TestDb::new().await, TestDb::new().await);
+    let username = unique("user");
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO users (username, email) VALUES ($1, $1 || '@example.com') RETURNING id",
+    )
+    .bind(&username)
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO posts (user_id, title, body) VALUES ($1, 'first', 'b'), ($1, 'second', 'b')",
+    )
+    .bind(user_id)
+    .execute(&db.pool)
+    .await
+    .unwrap();
+    let archive = std::env::temp_dir().join(format!("{}.jsonl.gz", unique("backup")));
+    let archive = archive.to_str().unwrap();
+    let dev = &[("APP_ENV", "development")];
+
+    assert_ok(&app(&db, dev, &["backup", "--out", archive]).await);
+    assert_ok(&app(&copy, dev, &["restore", "--file", archive]).await);
+    let users: Vec<(i32, String)> = sqlx::query_as("SELECT id, username FROM users")
+        .fetch_all(&copy.pool)
+        .await
+        .unwrap();
+    assert_eq!(users, [(user_id, username)]);
+    let posts: Vec<(Option<i32>, String)> =
+        sqlx::query_as("SELECT user_id, title FROM posts ORDER BY id")
+            .fetch_all(&copy.pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        posts,
+        [
+            (Some(user_id), String::from("first")),
+            (Some(user_id), String::from("second"))
+        ]
+    );
+    // the sequences moved past the restored ids
+    let next_id: i32 = sqlx::query_scalar(
+        "INSERT INTO users (username, email) VALUES ('next', 'next@example.com') RETURNING id",
+    )
+    .fetch_one(&copy.pool)
+    .await
+    .unwrap();
+    assert!(next_id > user_id);
+
+    // refused while there's more to lose than users and posts
+    let out = app(&copy, dev, &["restore", "--file", archive]).await;
+    assert!(!out.status.success());
+    api_keys::create(&copy.pool, user_id, "ci").await.unwrap();
+    let out = app(&copy, dev, &["restore", "--file", archive, "--truncate"]).await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("api_keys (1 rows)"), "{stderr}");
+    let forced = ["restore", "--file", archive, "--truncate", "--force"];
+    assert_ok(&app(&copy, dev, &forced).await);
+    assert_eq!(count(&copy, "SELECT COUNT(*) FROM api_keys").await, 0);
+    assert_eq!(count(&copy, "SELECT COUNT(*) FROM users").await, 1);
+    std::fs::remove_file(archive).ok();
+}
+
+#[tokio::test]
+async fn bad_config_is_refused_at_startup() {
+    let db = TestDb::new().await;
+
+    for (name, value) in [
+        ("APP_ENV", "staging"),
+        ("BIND_ADDR", "localhost"),
+        ("ID_SCHEME", "ulid"),
+        ("IP_ALLOW", "10.0.0.0/33"),
+        ("JSON_CASE", "kebab"),
+        ("MAINTENANCE", "on"),
+        ("RESPONSE_ENVELOPE", "yes"),
+    ] {
+        let env = [("APP_ENV", "test"), (name, value)];
+        let out = app(&db, &env, &["rotate-signing-key"]).await;
+        assert!(!out.status.success(), "{name}={value}");
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains(name), "{name}={value}: {stderr}");
+    }
+}
+
+#[tokio::test]
+async fn posts_and_users_are_exported_as_dated_parquet_partitions() {
+    use arrow_array::{Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let db = TestDb::new().await;
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO users (username, email, created_at) VALUES ('ada', 'ada@example.com', '2024-12-01 10:00') RETURNING id",
+    )
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO posts (user_id, title, body, created_at) VALUES
+             ($1, 'first', 'b', '2024-12-01 11:00'), ($1, 'second', 'b', '2024-12-01 12:00'),
+             ($1, 'later', 'b', '2024-12-02 09:00')",
+    )
+    .bind(user_id)
+    .execute(&db.pool)
+    .await
+    .unwrap();
+    let out = std::env::temp_dir().join(unique("parquet"));
+
+    let run = app(
+        &db,
+        &[("APP_ENV", "test")],
+        &["export-parquet", "--out", out.to_str().unwrap()],
+    )
+    .await;
+    assert_ok(&run);
+
+    // the values of a column of a partition
+    let column = |dataset: &str, day: &str, name: &str| -> Vec<String> {
+        let path = out.join(format!("{dataset}/created_on={day}/part-0.parquet"));
+        let file = std::fs::File::open(&path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut values = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let strings = batch
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            values.extend((0..strings.len()).map(|i| strings.value(i).to_owned()));
+        }
+        values
+    };
+    assert_eq!(column("posts", "2024-12-01", "title"), ["first", "second"]);
+    assert_eq!(column("posts", "2024-12-02", "title"), ["later"]);
+    assert_eq!(column("users", "2024-12-01", "email"), ["ada@example.com"]);
+    std::fs::remove_dir_all(&out).ok();
+}
+
+#[tokio::test]
+async fn imports_report_what_they_did_with_each_line() {
+    let db = TestDb::new().await;
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO users (username, email) VALUES ('ada', 'ada@example.com') RETURNING id",
+    )
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    let file = std::env::temp_dir().join(format!("{}.ndjson", unique("import")));
+    let lines = [
+        format!(r#"{{"id": 100, "title": "kept", "body": "b", "user_id": {user_id}}}"#),
+        String::from(r#"{"title": "anonymous", "body": "b", "user_id": null}"#),
+        String::from(r#"{"title": "truncated""#),
+        String::from(r#"{"title": " ", "body": "b", "user_id": null}"#),
+        String::from(r#"{"title": "unknown author", "body": "b", "user_id": 999999}"#),
+        String::from(r#"{"id": 100, "title": "again", "body": "b", "user_id": null}"#),
+    ];
+    std::fs::write(&file, lines.join("\n")).unwrap();
+    let file = file.to_str().unwrap();
+    let titles = || async {
+        sqlx::query_scalar::<_, String>("SELECT title FROM posts ORDER BY id")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap()
+    };
+
+    let run = app(
+        &db,
+        &[("APP_ENV", "test")],
+        &["import", "--file", file, "--batch-size", "2"],
+    )
+    .await;
+    assert_ok(&run);
+    let log = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        log.contains("6 lines, 2 inserted, 0 updated, 1 skipped, 2 invalid, 1 failed"),
+        "{log}"
+    );
+    assert_eq!(titles().await, ["anonymous", "kept"]);
+
+    let run = app(
+        &db,
+        &[("APP_ENV", "test")],
+        &["import", "--file", file, "--on-duplicate", "update"],
+    )
+    .await;
+    assert_ok(&run);
+    // posts without ids now come after the imported ones
+    assert_eq!(titles().await, ["anonymous", "again", "anonymous"]);
+    std::fs::remove_file(file).ok();
+}
@@ -0,0 +1,402 @@
+// In-process test harness: the real router driven through
+// `tower::ServiceExt::oneshot`, no sockets involved.
+//
+//     let app = TestApp::new().await; // or TestApp::in_memory() without a database
+//     let post: Post = app.post("/posts").json(&body).send().await
+//         .assert_status(StatusCode::OK)
+//         .json();
+
+#![allow(dead_code)]
+
+pub mod db;
+#[cfg(feature = "redis")]
+pub mod redis;
+
+pub use db::unique;
+
+use axum::body::{to_bytes, Body, Bytes};
+use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::Router;
+use rust_axum_rest_api::case::KeyCase;
+use rust_axum_rest_api::config::Config;
+use rust_axum_rest_api::db::PoolConfig;
+use rust_axum_rest_api::flags::FlagService;
+use rust_axum_rest_api::ids::IdScheme;
+use rust_axum_rest_api::ip_filter::IpRules;
+use rust_axum_rest_api::maintenance::MaintenanceMode;
+use rust_axum_rest_api::metrics::SloTargets;
+use rust_axum_rest_api::notify::RecordingNotifier;
+use rust_axum_rest_api::profile::Profile;
+use rust_axum_rest_api::repo::memory::{
+    InMemoryActivities, InMemoryAuditLog, InMemoryBannedWords, InMemoryFlags, InMemoryPosts,
+    InMemoryReports, InMemoryUsers,
+};
+use rust_axum_rest_api::routes::{build_router_for, Surface};
+use rust_axum_rest_api::server::{self, Http2, UnixSocket};
+use rust_axum_rest_api::shutdown::ShutdownConfig;
+use rust_axum_rest_api::tls;
+use rust_axum_rest_api::tunables::Tunables;
+use rust_axum_rest_api::{build_router, AppState};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use db::TestDb;
+
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+// what `db` migrates its databases with
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+// The configuration apps are built with before their own changes: the test
+// profile, ADMIN_TOKEN as the admin token, and everything optional off. The
+// database URL is left for the app to fill in.
+pub fn test_config() -> Config {
+    Config {
+        database_url: String::new(),
+        bind_addr: Some(([127, 0, 0, 1], 0).into()),
+        unix_socket: None,
+        pool: PoolConfig::default(),
+        listeners: Vec::new(),
+        trusted_proxies: Vec::new(),
+        slo: SloTargets::default(),
+        admin_token: Some(ADMIN_TOKEN.to_owned()),
+        id_scheme: IdScheme::Serial,
+        envelope: false,
+        json_case: KeyCase::Snake,
+        reserved_usernames: vec![String::from("admin")],
+        email_mx_check: false,
+        spam_check_url: None,
+        tenant_domain: None,
+        public_url: None,
+        federation_private_hosts: false,
+        email_link_secret: String::from("secret"),
+        email_key: None,
+        maintenance: MaintenanceMode::Off,
+        config_file: None,
+        tunables: Tunables::default(),
+        signing_key_rotation: None,
+        oidc: None,
+        tls: None,
+        http_redirect_addr: None,
+        http2: Http2::default(),
+        admin_identities: Vec::new(),
+        ip_rules: IpRules::default(),
+        admin_ip_rules: IpRules::default(),
+        gravatar: None,
+        stripe: None,
+        retention: None,
+        archive_after_years: None,
+        redis_url: None,
+        event_relay: false,
+        profile: Profile::Test,
+        error_details: false,
+        shutdown: ShutdownConfig::default(),
+    }
+}
+
+pub struct TestApp {
+    router: Router,
+    pub pool: Pool<Postgres>,
+    // the notifications the app sent
+    pub notifier: Arc<RecordingNotifier>,
+    // what the router was built with, for background work such as workers
+    pub state: AppState,
+    // keeps the database alive for as long as the app, `None` for in-memory
+    // apps
+    _db: Option<TestDb>,
+}
+
+impl TestApp {
+    // an app backed by its own freshly migrated database, with ADMIN_TOKEN as
+    // the admin token
+    pub async fn new() -> Self {
+        Self::with_config(|_| {}).await
+    }
+
+    // a database-backed app whose configuration `adjust` changes
+    pub async fn with_config(adjust: impl FnOnce(&mut Config)) -> Self {
+        let db = TestDb::new().await;
+        let mut config = Config {
+            database_url: db.url.clone(),
+            ..test_config()
+        };
+        adjust(&mut config);
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut state = AppState::new(db.pool.clone(), config);
+        state.notifier = notifier.clone();
+        TestApp {
+            router: build_router(state.clone()),
+            pool: db.pool.clone(),
+            notifier,
+            state,
+            _db: Some(db),
+        }
+    }
+
+    // an app whose posts and users live in memory. `pool` never connects, so
+    // only the handlers going through the repositories work.
+    pub fn in_memory() -> Self {
+        Self::in_memory_with_ids(IdScheme::Serial)
+    }
+
+    // an in-memory app addressing posts with the given id scheme
+    pub fn in_memory_with_ids(id_scheme: IdScheme) -> Self {
+        Self::in_memory_with_config(|config| config.id_scheme = id_scheme)
+    }
+
+    // an in-memory app whose configuration `adjust` changes
+    pub fn in_memory_with_config(adjust: impl FnOnce(&mut Config)) -> Self {
+        let url = "postgres://unused@localhost/unused";
+        let pool = PgPoolOptions::new()
+            .connect_lazy(url)
+            .expect("valid database url");
+        let mut config = Config {
+            database_url: url.to_owned(),
+            ..test_config()
+        };
+        adjust(&mut config);
+        let users = Arc::new(InMemoryUsers::new());
+        let mut state = AppState::new(pool.clone(), config)
+            .with_repositories(Arc::new(InMemoryPosts::with_users(users.clone())), users);
+        state.banned_words = Arc::new(InMemoryBannedWords::new());
+        state.reports = Arc::new(InMemoryReports::new());
+        state.audit = Arc::new(InMemoryAuditLog::new());
+        state.activities = Arc::new(InMemoryActivities::new());
+        state.flags = FlagService::new(Arc::new(InMemoryFlags::new()));
+        let notifier = Arc::new(RecordingNotifier::default());
+        state.notifier = notifier.clone();
+        TestApp {
+            router: build_router(state.clone()),
+            pool,
+            notifier,
+            state,
+            _db: None,
+        }
+    }
+
+    // serve the app on a local port, for clients that need a real socket
+    // such as WebSockets
+    pub async fn serve(&self) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = self
+            .router
+            .clone()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    // serve the app on a local port the way the binary does without TLS
+    pub async fn serve_plain(&self, http2: Http2) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = self.state.shutdown.clone();
+        tokio::spawn(server::serve(
+            listener,
+            http2,
+            self.router.clone(),
+            shutdown,
+        ));
+        addr
+    }
+
+    // serve part of the routes on a local port, like the LISTENERS do
+    pub async fn serve_surface(&self, surface: Surface) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router_for(self.state.clone(), surface);
+        let shutdown = self.state.shutdown.clone();
+        tokio::spawn(server::serve(listener, Http2::default(), app, shutdown));
+        addr
+    }
+
+    // serve the app on a unix socket
+    #[cfg(unix)]
+    pub fn serve_unix(&self, socket: &UnixSocket) {
+        let listener = socket.bind().unwrap();
+        let app = self.router.clone();
+        let shutdown = self.state.shutdown.clone();
+        tokio::spawn(server::serve_unix(
+            listener,
+            Http2::default(),
+            app,
+            shutdown,
+        ));
+    }
+
+    // serve the app over TLS on a local port
+    pub async fn serve_tls(&self, config: ServerConfig) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let app = self.router.clone();
+        let shutdown = self.state.shutdown.clone();
+        tokio::spawn(tls::serve_with(
+            listener,
+            acceptor,
+            Http2::default(),
+            app,
+            shutdown,
+        ));
+        addr
+    }
+
+    pub fn request(&self, method: Method, uri: &str) -> TestRequest<'_> {
+        TestRequest {
+            app: self,
+            builder: Request::builder().method(method).uri(uri),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn get(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::GET, uri)
+    }
+
+    pub fn post(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::POST, uri)
+    }
+
+    pub fn put(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::PUT, uri)
+    }
+
+    pub fn delete(&self, uri: &str) -> TestRequest<'_> {
+        self.request(Method::DELETE, uri)
+    }
+}
+
+pub struct TestRequest<'a> {
+    app: &'a TestApp,
+    builder: axum::http::request::Builder,
+    body: Body,
+}
+
+impl TestRequest<'_> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.builder = self.builder.header(name, value);
+        self
+    }
+
+    pub fn accept(self, media_type: &str) -> Self {
+        self.header(ACCEPT.as_str(), media_type)
+    }
+
+    pub fn bearer(self, token: &str) -> Self {
+        self.header(AUTHORIZATION.as_str(), &format!("Bearer {token}"))
+    }
+
+    pub fn admin(self) -> Self {
+        self.bearer(ADMIN_TOKEN)
+    }
+
+    pub fn json<T: Serialize>(mut self, value: &T) -> Self {
+        self.builder = self.builder.header(CONTENT_TYPE, "application/json");
+        self.body = Body::from(serde_json::to_vec(value).expect("serializable body"));
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Body>) -> Self {
+        self.builder = self.builder.header(CONTENT_TYPE, content_type);
+        self.body = body.into();
+        self
+    }
+
+    pub async fn send(self) -> TestResponse {
+        let request = self.builder.body(self.body).expect("valid request");
+        let response = self
+            .app
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("router is infallible");
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("readable body");
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    #[track_caller]
+    pub fn assert_status(self, expected: StatusCode) -> Self {
+        assert_eq!(
+            self.status,
+            expected,
+            "unexpected status, body: {}",
+            self.text()
+        );
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&HeaderValue> {
+        self.headers.get(name)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    #[track_caller]
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("invalid JSON body ({e}): {}", self.text()))
+    }
+
+    // assert that every field in `expected` is present in the body with the
+    // same value; fields not mentioned are ignored
+    #[track_caller]
+    pub fn assert_json_includes(self, expected: Value) -> Self {
+        let actual: Value = self.json();
+        if let Err(path) = includes(&actual, &expected, String::from("$")) {
+            panic!("JSON mismatch at {path}\nexpected: {expected:#}\nactual: {actual:#}");
+        }
+        self
+    }
+}
+
+fn includes(actual: &Value, expected: &Value, path: String) -> Result<(), String> {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => {
+            for (key, value) in expected {
+                let path = format!("{path}.{key}");
+                match actual.get(key) {
+                    Some(found) => includes(found, value, path)?,
+                    None => return Err(path),
+                }
+            }
+            Ok(())
+        }
+        (Value::Array(actual), Value::Array(expected)) if actual.len() == expected.len() => actual
+            .iter()
+            .zip(expected)
+            .enumerate()
+            .try_for_each(|(i, (a, e))| includes(a, e, format!("{path}[{i}]"))),
+        _ if actual == expected => Ok(()),
+        _ => Err(path),
+    }
+}
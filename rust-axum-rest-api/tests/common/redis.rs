@@ -0,0 +1,39 @@
+// A Redis server for tests of what the instances of a deployment share
+// through it, see `redis_store`.
+//
+// The server at TEST_REDIS_URL when that is set, otherwise a throwaway Redis
+// container, which needs a running Docker daemon. Keys aren't cleaned up,
+// tests use unique ones instead.
+
+use rust_axum_rest_api::redis_store::RedisStore;
+use testcontainers_modules::redis::{Redis, REDIS_PORT};
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::ContainerAsync;
+
+pub struct TestRedis {
+    pub store: RedisStore,
+    _container: Option<ContainerAsync<Redis>>,
+}
+
+impl TestRedis {
+    pub async fn new() -> Self {
+        if let Ok(url) = std::env::var("TEST_REDIS_URL") {
+            return TestRedis {
+                store: RedisStore::new(&url),
+                _container: None,
+            };
+        }
+        let container = Redis::default()
+            .start()
+            .await
+            .expect("failed to start Redis container, is Docker running? (or set TEST_REDIS_URL)");
+        let port = container
+            .get_host_port_ipv4(REDIS_PORT)
+            .await
+            .expect("mapped Redis port");
+        TestRedis {
+            store: RedisStore::new(&format!("redis://127.0.0.1:{port}")),
+            _container: Some(container),
+        }
+    }
+}
@@ -0,0 +1,2311 @@
+// Handler behaviour against the in-memory repositories, no database needed.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::http::{HeaderMap, Method, StatusCode};
+use common::TestApp;
+use futures::{SinkExt, StreamExt};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+use rust_axum_rest_api::avatars::Gravatar;
+use rust_axum_rest_api::client_ip::ClientIp;
+use rust_axum_rest_api::config::Config;
+use rust_axum_rest_api::ids::IdScheme;
+use rust_axum_rest_api::models::{FieldChange, Post, PostRevision, PostWithAuthor, User};
+use rust_axum_rest_api::routes::Surface;
+use rust_axum_rest_api::server::{Http2, UnixSocket};
+use rust_axum_rest_api::tls::{self, ClientAuth};
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn missing_post_is_not_found() {
+    let app = TestApp::in_memory();
+    app.get("/posts/42")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND)
+        .assert_json_includes(json!({ "error": "not_found", "message": "post not found" }));
+    app.put("/posts/42")
+        .json(&json!({ "title": "a", "body": "b", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    app.delete("/posts/42")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn deleted_post_disappears() {
+    let app = TestApp::in_memory();
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+
+    app.delete(&format!("/posts/{}", post.id))
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get(&format!("/posts/{}", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    let posts: Vec<Post> = app.get("/posts").send().await.json();
+    assert!(posts.is_empty());
+}
+
+#[tokio::test]
+async fn rejects_malformed_bodies() {
+    let app = TestApp::in_memory();
+    app.post("/posts")
+        .body("text/plain", "hello")
+        .send()
+        .await
+        .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    app.post("/posts")
+        .json(&json!({ "title": "missing body" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn users_csv_rejects_missing_token() {
+    let app = TestApp::in_memory();
+    app.get("/users.csv")
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn uuid_scheme_only_accepts_uuids() {
+    let app = TestApp::in_memory_with_ids(IdScheme::Uuid);
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+
+    app.get(&format!("/posts/{}", post.uuid))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "id": post.id, "title": "Hello" }));
+    app.get(&format!("/posts/{}", post.public_id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get(&format!("/posts/{}", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn head_matches_get_without_body() {
+    let app = TestApp::in_memory();
+    app.post("/posts")
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+
+    let get = app.get("/posts").send().await.assert_status(StatusCode::OK);
+    let head = app
+        .request(Method::HEAD, "/posts")
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert!(head.body.is_empty());
+    assert_eq!(
+        head.header("content-length").unwrap(),
+        get.body.len().to_string().as_str()
+    );
+    assert!(get.header("etag").is_some());
+    assert_eq!(head.header("etag"), get.header("etag"));
+}
+
+#[tokio::test]
+async fn options_lists_allowed_methods() {
+    let app = TestApp::in_memory();
+    let response = app
+        .request(Method::OPTIONS, "/posts/1")
+        .send()
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    let allow: Vec<&str> = response
+        .header("allow")
+        .and_then(|value| value.to_str().ok())
+        .unwrap()
+        .split(", ")
+        .collect();
+    for method in ["GET", "HEAD", "PUT", "DELETE", "OPTIONS"] {
+        assert!(allow.contains(&method), "{method} missing from {allow:?}");
+    }
+    assert!(!allow.contains(&"POST"));
+
+    app.request(Method::OPTIONS, "/nowhere")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unknown_path_is_a_json_404() {
+    let app = TestApp::in_memory();
+    let response = app
+        .get("/nowhere")
+        .header("x-request-id", "abc-123")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND)
+        .assert_json_includes(json!({
+            "error": "not_found",
+            "request_id": "abc-123",
+            "routes": ["/", "/posts", "/users"],
+        }));
+    assert_eq!(response.header("x-request-id").unwrap(), "abc-123");
+}
+
+#[tokio::test]
+async fn unsupported_method_is_a_json_405() {
+    let app = TestApp::in_memory();
+    let response = app
+        .request(Method::PATCH, "/users")
+        .send()
+        .await
+        .assert_status(StatusCode::METHOD_NOT_ALLOWED)
+        .assert_json_includes(json!({
+            "error": "method_not_allowed",
+            "allowed": ["POST", "OPTIONS"],
+        }));
+    assert_eq!(response.header("allow").unwrap(), "POST, OPTIONS");
+}
+
+#[tokio::test]
+async fn panics_become_json_500s() {
+    use axum::body::{to_bytes, Body};
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+
+    let router: Router = Router::new()
+        .route("/boom", get(|| async { panic!("boom") as &'static str }))
+        .layer(CatchPanicLayer::custom(
+            rust_axum_rest_api::panic::handle_panic,
+        ));
+    let response = router
+        .oneshot(
+            axum::http::Request::get("/boom")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "internal");
+}
+
+#[tokio::test]
+async fn includes_author_on_request() {
+    let app = TestApp::in_memory();
+    let user: User = app
+        .post("/users")
+        .json(&json!({ "username": "ann", "email": "ann@example.com" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    let post: Post = app
+        .post("/posts")
+        .admin()
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": user.id }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+
+    let response = app
+        .get(&format!("/posts/{}", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert!(response.json::<serde_json::Value>().get("author").is_none());
+
+    let with_author: PostWithAuthor = app
+        .get(&format!("/posts/{}?include=author", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(with_author.author.unwrap().username, "ann");
+
+    let posts: Vec<PostWithAuthor> = app
+        .get("/posts?include=author")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(posts[0].author.as_ref().unwrap().id, user.id);
+    let xml = app
+        .get("/posts?include=author")
+        .accept("application/xml")
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert!(xml.text().contains("<author>"));
+    // without their email
+    for path in [
+        "/posts?include=author".to_owned(),
+        format!("/posts/{}?include=author", post.id),
+    ] {
+        let response = app.get(&path).send().await;
+        assert!(!response.text().contains("ann@example.com"), "{path}");
+    }
+    assert!(!xml.text().contains("ann@example.com"));
+
+    app.get("/posts?include=comments")
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn projects_requested_fields() {
+    let app = TestApp::in_memory();
+    app.post("/posts")
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+
+    let posts: serde_json::Value = app
+        .get("/posts?fields=title,id")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    let keys: Vec<&String> = posts[0].as_object().unwrap().keys().collect();
+    assert_eq!(keys, ["_links", "id", "title"]);
+
+    let posts: serde_json::Value = app
+        .get("/posts?fields=author&include=author")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(posts[0]["author"], json!(null));
+    assert!(posts[0].get("title").is_none());
+
+    app.get("/posts?fields=password")
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn posts_link_to_themselves_and_their_author() {
+    let app = TestApp::in_memory();
+    let user: User = app
+        .post("/users")
+        .json(&json!({ "username": "bob", "email": "bob@example.com" }))
+        .send()
+        .await
+        .json();
+    let post: Post = app
+        .post("/posts")
+        .admin()
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": user.id }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({
+            "_links": { "author": { "href": format!("/users/{}", user.id) } },
+        }))
+        .json();
+
+    let response = app
+        .get(&format!("/posts/{}", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    let links: serde_json::Value = response.json();
+    assert_eq!(
+        links["_links"]["comments"]["href"],
+        format!("/posts/{}/comments", post.public_id)
+    );
+    let self_href = links["_links"]["self"]["href"].as_str().unwrap().to_owned();
+    let author_href = links["_links"]["author"]["href"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    app.get(&self_href)
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "id": post.id }));
+    app.get(&author_href)
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "username": "bob" }));
+}
+
+#[tokio::test]
+async fn uuid_scheme_addresses_users_by_uuid() {
+    let app = TestApp::in_memory_with_ids(IdScheme::Uuid);
+    let user: User = app
+        .post("/users")
+        .json(&json!({ "username": "bob", "email": "bob@example.com" }))
+        .send()
+        .await
+        .json();
+    app.post("/posts")
+        .admin()
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": user.id }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({
+            "_links": { "author": { "href": format!("/users/{}", user.uuid) } },
+        }));
+
+    app.get(&format!("/users/{}", user.uuid))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "username": "bob" }));
+    app.get(&format!("/users/{}", user.id))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn paginates_posts_with_link_headers() {
+    let app = TestApp::in_memory();
+    for n in 0..5 {
+        app.post("/posts")
+            .json(&json!({ "title": format!("post {n}"), "body": "b", "user_id": null }))
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    let response = app
+        .get("/posts?sort=id&page=2&per_page=2")
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(response.header("x-total-count").unwrap(), "5");
+    let posts: Vec<Post> = response.json();
+    let titles: Vec<&str> = posts.iter().map(|post| post.title.as_str()).collect();
+    assert_eq!(titles, ["post 2", "post 3"]);
+    let link = response.header("link").unwrap().to_str().unwrap();
+    for expected in [
+        r#"</posts?sort=id&page=1&per_page=2>; rel="first""#,
+        r#"</posts?sort=id&page=3&per_page=2>; rel="last""#,
+        r#"</posts?sort=id&page=3&per_page=2>; rel="next""#,
+        r#"</posts?sort=id&page=1&per_page=2>; rel="prev""#,
+    ] {
+        assert!(link.contains(expected), "{expected} missing from {link}");
+    }
+
+    // unpaginated lists still report the total
+    let response = app.get("/posts").send().await.assert_status(StatusCode::OK);
+    assert_eq!(response.header("x-total-count").unwrap(), "5");
+    assert!(response.header("link").is_none());
+    assert!(response.header("x-total-count-exact").is_none());
+
+    // estimates are marked as such
+    let response = app
+        .get("/posts?exact=false")
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(response.header("x-total-count").unwrap(), "5");
+    assert_eq!(response.header("x-total-count-exact").unwrap(), "false");
+
+    app.get("/posts?per_page=1000")
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn envelopes_responses_on_request() {
+    let app = TestApp::in_memory();
+    app.post("/posts")
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+
+    let response = app
+        .get("/posts?envelope=true&page=1&per_page=10")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "meta": { "total_count": 1 }, "errors": [] }));
+    let body: serde_json::Value = response.json();
+    assert_eq!(body["data"][0]["title"], "Hello");
+    assert!(body["meta"]["request_id"].is_string());
+    assert_eq!(
+        body["_links"]["last"]["href"],
+        "/posts?envelope=true&page=1&per_page=10"
+    );
+    assert!(body["_links"]["next"].is_null());
+
+    // the pages of a list are its links
+    app.post("/posts")
+        .json(&json!({ "title": "Again", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    let body: serde_json::Value = app
+        .get("/posts?envelope=true&page=2&per_page=1")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(
+        body["_links"]["prev"]["href"],
+        "/posts?envelope=true&page=1&per_page=1"
+    );
+    assert!(body["_links"]["next"].is_null());
+
+    let body: serde_json::Value = app
+        .get("/posts/999?envelope=true")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND)
+        .json();
+    assert_eq!(body["data"], json!(null));
+    assert_eq!(body["errors"][0]["error"], "not_found");
+    assert!(body["meta"]["request_id"].is_string());
+
+    // errors from outside the handlers are enveloped too
+    app.request(Method::PATCH, "/posts?envelope=true")
+        .send()
+        .await
+        .assert_status(StatusCode::METHOD_NOT_ALLOWED)
+        .assert_json_includes(json!({ "errors": [{ "error": "method_not_allowed" }] }));
+
+    // bare bodies without the parameter
+    let posts: Vec<Post> = app.get("/posts").send().await.json();
+    assert_eq!(posts.len(), 2);
+}
+
+#[tokio::test]
+async fn speaks_camel_case_on_request() {
+    let app = TestApp::in_memory();
+    let post: serde_json::Value = app
+        .post("/posts")
+        .header("x-json-case", "camel")
+        .json(&json!({ "title": "Hello", "body": "World", "userId": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert!(post["publicId"].is_string());
+    assert!(post["createdAt"].is_string());
+    assert!(post.get("created_at").is_none());
+    assert!(post["_links"]["self"].is_object());
+
+    let since = post["createdAt"].as_str().unwrap();
+    let posts: serde_json::Value = app
+        .get(&format!("/posts?createdBefore={since}"))
+        .header("x-json-case", "camel")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(posts, json!([]));
+
+    app.get("/posts/999?envelope=true")
+        .header("x-json-case", "camel")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND)
+        .assert_json_includes(json!({ "meta": {}, "errors": [{ "error": "not_found" }] }));
+    let error: serde_json::Value = app
+        .get("/posts/999")
+        .header("x-json-case", "camel")
+        .send()
+        .await
+        .json();
+    assert!(error["requestId"].is_string());
+}
+
+#[tokio::test]
+async fn counts_posts_and_checks_existence() {
+    let app = TestApp::in_memory();
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .json();
+
+    app.get("/posts/count")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "count": 1 }));
+    let since = post
+        .created_at
+        .to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+    app.get(&format!("/posts/count?created_before={since}"))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "count": 0 }));
+
+    let found = app
+        .request(Method::HEAD, &format!("/posts/{}", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert!(found.body.is_empty());
+    let missing = app
+        .request(Method::HEAD, "/posts/999")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    assert!(missing.body.is_empty());
+}
+
+#[tokio::test]
+async fn checks_username_and_email_availability() {
+    let app = TestApp::in_memory();
+    app.post("/users")
+        .json(&json!({ "username": "ada", "email": "ada@example.com" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+
+    let availability: serde_json::Value = app
+        .get("/users/check?username=ada&email=grace@example.com")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(availability, json!({ "username": false, "email": true }));
+    app.get("/users/check?email=ada@example.com")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "email": false }));
+    app.get("/users/check")
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let mut limited = None;
+    for _ in 0..20 {
+        let response = app.get("/users/check?username=grace").send().await;
+        if response.status == StatusCode::TOO_MANY_REQUESTS {
+            limited = Some(response);
+            break;
+        }
+    }
+    let limited = limited.expect("rate limited within the allowance");
+    assert!(limited.header("retry-after").is_some());
+}
+
+#[tokio::test]
+async fn normalizes_and_reserves_usernames() {
+    let app = TestApp::in_memory();
+    let user: User = app
+        .post("/users")
+        .json(&json!({ "username": " ｇｒａｃｅ ", "email": "grace@example.com" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(user.username, "grace");
+
+    app.post("/users")
+        .json(&json!({ "username": "Admin", "email": "admin@example.com" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .assert_json_includes(json!({ "error": "reserved_username", "field": "username" }));
+    app.post("/users")
+        .json(&json!({ "username": "two words", "email": "two@example.com" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .assert_json_includes(json!({ "error": "invalid_username" }));
+
+    app.get("/users/check?username=GRACE")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "username": false }));
+    app.get("/users/check?username=admin")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "username": false }));
+}
+
+#[tokio::test]
+async fn rejects_invalid_emails() {
+    let app = TestApp::in_memory();
+    for email in [
+        "no-at-sign",
+        "two@@example.com",
+        "dot.@example.com",
+        "a@localhost",
+        "a@-x.com",
+    ] {
+        app.post("/users")
+            .json(&json!({ "username": "ada", "email": email }))
+            .send()
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+            .assert_json_includes(json!({ "error": "invalid_email", "field": "email" }));
+    }
+    let user: User = app
+        .post("/users")
+        .json(&json!({ "username": "ada", "email": " ada+posts@mail.example.com " }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(user.email, "ada+posts@mail.example.com");
+}
+
+#[tokio::test]
+async fn filters_banned_words() {
+    let app = TestApp::in_memory();
+    app.post("/admin/banned-words")
+        .json(&json!({ "word": "spam", "action": "flag" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    for (word, action) in [("Spam", "flag"), ("forbidden", "reject")] {
+        app.post("/admin/banned-words")
+            .admin()
+            .json(&json!({ "word": word, "action": action }))
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+    let words: serde_json::Value = app.get("/admin/banned-words").admin().send().await.json();
+    assert_eq!(words[1]["word"], "spam");
+
+    app.post("/posts")
+        .json(&json!({ "title": "A FORBIDDEN title", "body": "b", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .assert_json_includes(json!({
+            "error": "banned_content",
+            "field": "title",
+            "words": ["forbidden"],
+        }));
+    app.post("/users")
+        .json(&json!({ "username": "forbidden", "email": "f@example.com" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .assert_json_includes(json!({ "field": "username" }));
+
+    // flagged posts wait for a moderator, whole words only
+    let flagged: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "t", "body": "buy ｓｐａｍ!", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(flagged.status, "pending");
+    let clean: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "t", "body": "a spammer", "user_id": null }))
+        .send()
+        .await
+        .json();
+    assert_eq!(clean.status, "published");
+    let posts: Vec<Post> = app.get("/posts").send().await.json();
+    let ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+    assert_eq!(ids, [clean.id]);
+
+    app.delete("/admin/banned-words/forbidden")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.post("/posts")
+        .json(&json!({ "title": "forbidden", "body": "b", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn holds_back_spam() {
+    let app = TestApp::in_memory();
+    let links = "see https://a.example https://b.example www.c.example";
+    let linky: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "deals", "body": links, "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(linky.status, "pending");
+
+    let body = "An ordinary post that is long enough to be remembered.";
+    let mut posts = Vec::new();
+    for _ in 0..2 {
+        let post: Post = app
+            .post("/posts")
+            .json(&json!({ "title": "t", "body": body, "user_id": null }))
+            .send()
+            .await
+            .json();
+        posts.push(post);
+    }
+    let statuses: Vec<&str> = posts.iter().map(|post| post.status.as_str()).collect();
+    assert_eq!(statuses, ["published", "spam"]);
+
+    let listed: Vec<Post> = app.get("/posts").send().await.json();
+    assert_eq!(listed.len(), 1);
+
+    // held back posts are as good as missing, by id too
+    let spam = &posts[1];
+    for path in [
+        format!("/posts/{}", spam.id),
+        format!("/posts/{}/history", spam.id),
+        format!("/posts/{}/comments", spam.id),
+    ] {
+        app.get(&path)
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+    app.get(&format!("/posts/{}", linky.id))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    app.get(&format!("/posts/{}", spam.id))
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get(&format!("/posts/{}", posts[0].id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+
+    let found: Value = app
+        .post("/graphql")
+        .json(&json!({ "query": format!("{{ post(id: \"{}\") {{ id }} }}", spam.id) }))
+        .send()
+        .await
+        .json();
+    assert_eq!(found["data"]["post"], Value::Null);
+}
+
+#[tokio::test]
+async fn holds_back_spammy_edits() {
+    let app = TestApp::in_memory();
+    let body = "An ordinary post that is long enough to be remembered.";
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "t", "body": body, "user_id": null }))
+        .send()
+        .await
+        .json();
+    assert_eq!(post.status, "published");
+
+    // the body it already had isn't a duplicate
+    let retitled: Post = app
+        .put(&format!("/posts/{}", post.id))
+        .admin()
+        .json(&json!({ "title": "better", "body": body, "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(retitled.status, "published");
+
+    let links = "see https://a.example https://b.example www.c.example";
+    let edited: Post = app
+        .put(&format!("/posts/{}", post.id))
+        .admin()
+        .json(&json!({ "title": "deals", "body": links, "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(edited.status, "pending");
+    app.get(&format!("/posts/{}", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn moderators_approve_and_reject_held_posts() {
+    let app = TestApp::in_memory();
+    let author: User = app
+        .post("/users")
+        .json(&json!({ "username": "ann", "email": "ann@example.com" }))
+        .send()
+        .await
+        .json();
+    app.post("/admin/banned-words")
+        .admin()
+        .json(&json!({ "word": "casino", "action": "flag" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    let mut held = Vec::new();
+    for title in ["casino one", "casino two", "casino three"] {
+        let post: Post = app
+            .post("/posts")
+            .admin()
+            .json(&json!({ "title": title, "body": "b", "user_id": author.id }))
+            .send()
+            .await
+            .json();
+        held.push(post);
+    }
+
+    app.get("/admin/moderation/posts")
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    let queue: Vec<Post> = app
+        .get("/admin/moderation/posts")
+        .admin()
+        .send()
+        .await
+        .json();
+    assert_eq!(queue.len(), 3);
+
+    app.post(&format!("/admin/moderation/posts/{}/approve", held[0].id))
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "status": "published" }));
+    app.post(&format!("/admin/moderation/posts/{}/reject", held[1].id))
+        .admin()
+        .json(&json!({ "reason": " " }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .assert_json_includes(json!({ "error": "missing_reason" }));
+    app.post("/admin/moderation/posts")
+        .admin()
+        .json(&json!({
+            "ids": [held[1].id.to_string(), held[2].public_id, "999"],
+            "decision": "reject",
+            "reason": "gambling ads",
+        }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({
+            "moderated": [held[1].id.to_string(), held[2].public_id],
+            "not_found": ["999"],
+        }));
+
+    let posts: Vec<Post> = app.get("/posts").send().await.json();
+    assert_eq!(posts.len(), 1);
+    let rejected: Vec<Post> = app
+        .get("/admin/moderation/posts?status=rejected")
+        .admin()
+        .send()
+        .await
+        .json();
+    assert_eq!(rejected.len(), 2);
+
+    let sent = app.notifier.sent();
+    assert_eq!(sent.len(), 3);
+    assert!(sent[2].to_string().ends_with("rejected: gambling ads"));
+}
+
+#[tokio::test]
+async fn reports_take_a_user() {
+    let app = TestApp::in_memory();
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "Hello", "body": "World", "user_id": null }))
+        .send()
+        .await
+        .json();
+
+    app.post(&format!("/posts/{}/report", post.id))
+        .json(&json!({ "reason": "rude" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn post_history_lists_changed_fields() {
+    let app = TestApp::in_memory();
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "first", "body": "same", "user_id": null }))
+        .send()
+        .await
+        .json();
+    app.put(&format!("/posts/{}", post.id))
+        .admin()
+        .json(&json!({ "title": "second", "body": "same", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+
+    let history: Vec<PostRevision> = app
+        .get(&format!("/posts/{}/history", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].action, "post.update");
+    assert_eq!(history[0].actor, "admin token");
+    assert_eq!(
+        history[0].changes,
+        vec![FieldChange {
+            field: String::from("title"),
+            old: json!("first"),
+            new: json!("second"),
+        }]
+    );
+    assert_eq!(history[1].action, "post.create");
+    assert!(history[1]
+        .changes
+        .iter()
+        .any(|change| change.field == "body" && change.old.is_null()));
+
+    app.get("/posts/999/history")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn activity_feed_lists_what_users_posted() {
+    let app = TestApp::in_memory();
+    let user: User = app
+        .post("/users")
+        .json(&json!({ "username": "ann", "email": "ann@example.com" }))
+        .send()
+        .await
+        .json();
+    for title in ["first", "second"] {
+        app.post("/posts")
+            .admin()
+            .json(&json!({ "title": title, "body": "b", "user_id": user.id }))
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+    app.post("/posts")
+        .json(&json!({ "title": "anonymous", "body": "b" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+
+    let response = app
+        .get(&format!("/users/{}/activity?per_page=1", user.id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!([{ "kind": "post", "post_id": 2 }]));
+    assert_eq!(response.header("x-total-count").unwrap(), "2");
+    app.get("/users/42/activity")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn graphql_queries_posts() {
+    let app = TestApp::in_memory();
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "Hello", "body": "world", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+
+    let query = format!(r#"{{ post(id: "{}") {{ title publicId }} }}"#, post.id);
+    let response = app
+        .post("/graphql")
+        .json(&json!({ "query": query }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(
+        response.header("content-type").unwrap(),
+        "application/graphql-response+json"
+    );
+    response.assert_json_includes(json!({
+        "data": { "post": { "title": "Hello", "publicId": post.public_id } }
+    }));
+}
+
+#[tokio::test]
+async fn graphql_subscriptions_see_new_posts() {
+    let app = TestApp::in_memory();
+    let addr = app.serve().await;
+    let mut request = format!("ws://{addr}/graphql")
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "sec-websocket-protocol",
+        "graphql-transport-ws".parse().unwrap(),
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+    let messages = [
+        json!({ "type": "connection_init" }),
+        json!({
+            "id": "1",
+            "type": "subscribe",
+            "payload": { "query": "subscription { postCreated { title } }" },
+        }),
+    ];
+    for message in messages {
+        socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    let ack = socket.next().await.unwrap().unwrap().into_text().unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&ack).unwrap()["type"],
+        "connection_ack"
+    );
+    // the subscription starts listening some time after it is sent, post
+    // until it has
+    let next = loop {
+        app.post("/posts")
+            .json(&json!({ "title": "Live", "body": "news", "user_id": null }))
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+        let wait = Duration::from_millis(100);
+        if let Ok(message) = tokio::time::timeout(wait, socket.next()).await {
+            break message.unwrap().unwrap().into_text().unwrap();
+        }
+    };
+    let next: Value = serde_json::from_str(&next).unwrap();
+    assert_eq!(next["type"], "next");
+    assert_eq!(next["payload"]["data"]["postCreated"]["title"], "Live");
+}
+
+#[tokio::test]
+async fn maintenance_mode_turns_writes_away() {
+    let app = TestApp::in_memory();
+    let new_post = json!({ "title": "t", "body": "b", "user_id": null });
+    app.put("/admin/maintenance")
+        .json(&json!({ "mode": "read_only" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    app.put("/admin/maintenance")
+        .admin()
+        .json(&json!({ "mode": "read_only", "retry_after": 120 }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "mode": "read_only", "retry_after": 120 }));
+
+    app.get("/posts").send().await.assert_status(StatusCode::OK);
+    let response = app
+        .post("/posts")
+        .json(&new_post)
+        .send()
+        .await
+        .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.header("retry-after").unwrap(), "120");
+    response.assert_json_includes(json!({ "error": "maintenance", "mode": "read_only" }));
+    app.get("/readyz")
+        .send()
+        .await
+        .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+    app.put("/admin/maintenance")
+        .admin()
+        .json(&json!({ "mode": "full" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get("/posts")
+        .send()
+        .await
+        .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+    app.put("/admin/maintenance")
+        .admin()
+        .json(&json!({ "mode": "off" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.post("/posts")
+        .json(&new_post)
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+}
+
+#[tokio::test]
+async fn feature_flags_roll_out_and_override() {
+    let app = TestApp::in_memory();
+    app.post("/admin/flags")
+        .admin()
+        .json(&json!({ "key": "New Editor" }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .assert_json_includes(json!({ "error": "invalid_key" }));
+    app.post("/admin/flags")
+        .admin()
+        .json(&json!({ "key": "new-editor", "description": "the new post editor" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "key": "new-editor", "rollout_percent": 0 }));
+    app.get("/me/flags")
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!([{ "key": "new-editor", "enabled": false }]));
+
+    app.put("/admin/flags/new-editor")
+        .admin()
+        .json(&json!({ "rollout_percent": 101 }))
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    app.put("/admin/flags/new-editor")
+        .admin()
+        .json(&json!({ "rollout_percent": 100 }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get("/me/flags")
+        .send()
+        .await
+        .assert_json_includes(json!([{ "key": "new-editor", "enabled": true }]));
+
+    app.put("/admin/flags/new-editor/overrides/7")
+        .admin()
+        .json(&json!({ "enabled": false }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get("/admin/flags/new-editor/overrides")
+        .admin()
+        .send()
+        .await
+        .assert_json_includes(json!([{ "user_id": 7, "enabled": false }]));
+    app.put("/admin/flags/unknown/overrides/7")
+        .admin()
+        .json(&json!({ "enabled": true }))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    app.delete("/admin/flags/new-editor")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get("/admin/flags/new-editor")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn config_reloads_without_a_restart() {
+    let path = std::env::temp_dir().join(format!("{}.toml", common::unique("config")));
+    let app = TestApp::in_memory_with_config(|config| config.config_file = Some(path.clone()));
+    std::fs::write(
+        &path,
+        "check_rate_limit = 1\ncors_origins = [\"https://app.example\"]\n",
+    )
+    .unwrap();
+    app.post("/admin/config/reload")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json_includes(json!({ "check_rate_limit": 1, "log_level": "info" }));
+
+    app.get("/users/check?username=somebody")
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get("/users/check?username=somebody")
+        .send()
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    let allowed = app
+        .get("/posts")
+        .header("origin", "https://app.example")
+        .send()
+        .await;
+    assert_eq!(
+        allowed.header("access-control-allow-origin").unwrap(),
+        "https://app.example"
+    );
+    let other = app
+        .get("/posts")
+        .header("origin", "https://evil.example")
+        .send()
+        .await;
+    assert!(other.header("access-control-allow-origin").is_none());
+
+    // a broken file leaves the settings as they were
+    std::fs::write(&path, "check_rate_limit = \"lots\"\n").unwrap();
+    app.post("/admin/config/reload")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .assert_json_includes(json!({ "error": "invalid_config" }));
+    app.get("/admin/config")
+        .admin()
+        .send()
+        .await
+        .assert_json_includes(json!({ "check_rate_limit": 1 }));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(not(feature = "geoip"))]
+#[tokio::test]
+async fn geoip_databases_need_the_geoip_feature() {
+    let path = std::env::temp_dir().join(format!("{}.toml", common::unique("config")));
+    let app = TestApp::in_memory_with_config(|config| config.config_file = Some(path.clone()));
+    std::fs::write(&path, "geoip_db = \"GeoLite2-City.mmdb\"\n").unwrap();
+    let response = app
+        .post("/admin/config/reload")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(response.text().contains("geoip feature"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+// a throwaway certificate authority, keeping its files in a directory of
+// its own
+struct TestCa {
+    cert: Certificate,
+    key: KeyPair,
+    dir: PathBuf,
+}
+
+impl TestCa {
+    fn new() -> Self {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "test CA");
+        let cert = params.self_signed(&key).unwrap();
+        let dir = std::env::temp_dir().join(common::unique("tls"));
+        std::fs::create_dir_all(&dir).unwrap();
+        TestCa { cert, key, dir }
+    }
+
+    // a certificate for `name` signed by the CA, as PEM: (certificate, key)
+    fn issue(&self, name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![name.to_owned()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn file(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    // the server's certificate and key files
+    fn localhost(&self) -> (PathBuf, PathBuf) {
+        let (cert, key) = self.issue("localhost");
+        (self.file("cert.pem", &cert), self.file("key.pem", &key))
+    }
+
+    // a client trusting the CA
+    fn client(&self) -> reqwest::ClientBuilder {
+        reqwest::Client::builder().add_root_certificate(
+            reqwest::Certificate::from_pem(self.cert.pem().as_bytes()).unwrap(),
+        )
+    }
+}
+
+#[tokio::test]
+async fn serves_https_and_redirects_http() {
+    let app = TestApp::in_memory();
+    let ca = TestCa::new();
+    let (cert, key) = ca.localhost();
+    let addr = app
+        .serve_tls(tls::server_config(&cert, &key, None).unwrap())
+        .await;
+    let client = ca
+        .client()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client
+        .get(format!("https://localhost:{}/", addr.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let plain = listener.local_addr().unwrap();
+    tokio::spawn(tls::redirect_to_https(listener, 8443));
+    let response = client
+        .post(format!("http://localhost:{}/posts?draft=1", plain.port()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers()["location"],
+        "https://localhost:8443/posts?draft=1"
+    );
+
+    assert!(tls::server_config(&key, &cert, None).is_err());
+}
+
+#[tokio::test]
+async fn serves_h2c_only_when_enabled() {
+    let app = TestApp::in_memory();
+    let h2c = Http2 {
+        h2c: true,
+        max_concurrent_streams: Some(8),
+    };
+    let h2 = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+
+    let addr = app.serve_plain(h2c).await;
+    let response = h2.get(format!("http://{addr}/")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    // HTTP/1.1 clients are still served
+    let response = reqwest::get(format!("http://{addr}/")).await.unwrap();
+    assert_eq!(response.version(), reqwest::Version::HTTP_11);
+
+    let addr = app.serve_plain(Http2::default()).await;
+    assert!(h2.get(format!("http://{addr}/")).send().await.is_err());
+    let response = reqwest::get(format!("http://{addr}/")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serves_a_unix_socket() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let app = TestApp::in_memory();
+    let dir = std::env::temp_dir().join(common::unique("unix"));
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = UnixSocket {
+        path: dir.join("api.sock"),
+        mode: Some(0o660),
+    };
+    // a socket left behind by a previous run is replaced
+    drop(socket.bind().unwrap());
+    app.serve_unix(&socket);
+    let mode = std::fs::metadata(&socket.path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o660);
+
+    let mut stream = tokio::net::UnixStream::connect(&socket.path).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn client_certificates_identify_services() {
+    let app = TestApp::in_memory_with_config(|config| {
+        config.admin_identities = vec![String::from("billing.internal")]
+    });
+    let ca = TestCa::new();
+    let (cert, key) = ca.localhost();
+    let client_auth = |required| ClientAuth {
+        ca: ca.file("ca.pem", &ca.cert.pem()),
+        required,
+    };
+    let optional = app
+        .serve_tls(tls::server_config(&cert, &key, Some(&client_auth(false))).unwrap())
+        .await;
+    let required = app
+        .serve_tls(tls::server_config(&cert, &key, Some(&client_auth(true))).unwrap())
+        .await;
+    let as_service = |name: &str| {
+        let (cert, key) = ca.issue(name);
+        let identity = reqwest::Identity::from_pkcs8_pem(cert.as_bytes(), key.as_bytes()).unwrap();
+        ca.client().identity(identity).build().unwrap()
+    };
+    let maintenance =
+        |addr: std::net::SocketAddr| format!("https://localhost:{}/admin/maintenance", addr.port());
+
+    let response = as_service("billing.internal")
+        .get(maintenance(optional))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = as_service("reports.internal")
+        .get(maintenance(optional))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let anonymous = ca.client().build().unwrap();
+    let response = anonymous.get(maintenance(optional)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // required, no certificate gets past the handshake
+    assert!(anonymous.get(maintenance(required)).send().await.is_err());
+    let response = as_service("billing.internal")
+        .get(maintenance(required))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn ip_rules_lock_down_admin_endpoints() {
+    let app = TestApp::in_memory_with_config(|config| {
+        config.admin_ip_rules.allow = vec!["10.0.0.0/8".parse().unwrap()];
+    });
+    let addr = app.serve().await;
+    let client = reqwest::Client::new();
+    let status = |path: &'static str| {
+        let request = client
+            .get(format!("http://{addr}{path}"))
+            .bearer_auth(common::ADMIN_TOKEN);
+        async move { request.send().await.unwrap().status() }
+    };
+    assert_eq!(status("/").await, StatusCode::OK);
+    assert_eq!(status("/admin/maintenance").await, StatusCode::FORBIDDEN);
+    assert_eq!(
+        status("/admin/debug/pprof/profile?seconds=0").await,
+        StatusCode::FORBIDDEN
+    );
+
+    let app = TestApp::in_memory_with_config(|config| {
+        config.ip_rules.deny = vec!["127.0.0.1/32".parse().unwrap()];
+    });
+    let addr = app.serve().await;
+    let response = client.get(format!("http://{addr}/")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "ip_forbidden");
+    let response = client
+        .get(format!("http://{addr}/admin/maintenance"))
+        .bearer_auth(common::ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // the profiler is judged by the admin rules, and only checks its input
+    // here
+    let response = client
+        .get(format!("http://{addr}/admin/debug/pprof/profile?seconds=0"))
+        .bearer_auth(common::ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn admin_listeners_take_admin_endpoints_apart() {
+    let app = TestApp::in_memory();
+    let admin = app.serve_surface(Surface::Admin).await;
+    let api = app.serve_surface(Surface::Api).await;
+    let client = reqwest::Client::new();
+    let status = |addr: SocketAddr, path: &'static str| {
+        let request = client
+            .get(format!("http://{addr}{path}"))
+            .bearer_auth(common::ADMIN_TOKEN);
+        async move { request.send().await.unwrap().status() }
+    };
+    assert_eq!(status(admin, "/admin/jobs").await, StatusCode::OK);
+    assert_eq!(status(admin, "/posts").await, StatusCode::NOT_FOUND);
+    assert_eq!(status(api, "/posts").await, StatusCode::OK);
+    assert_eq!(status(api, "/admin/jobs").await, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn trusted_proxies_name_the_client() {
+    let deny = |config: &mut Config| {
+        config.ip_rules.deny = vec!["203.0.113.7/32".parse().unwrap()];
+    };
+    let app = TestApp::in_memory_with_config(|config| {
+        deny(config);
+        config.trusted_proxies = vec!["127.0.0.1/32".parse().unwrap()];
+    });
+    let addr = app.serve().await;
+    let client = reqwest::Client::new();
+    let status = |addr: SocketAddr, forwarded_for: &'static str| {
+        let request = client
+            .get(format!("http://{addr}/"))
+            .header("x-forwarded-for", forwarded_for);
+        async move { request.send().await.unwrap().status() }
+    };
+    assert_eq!(status(addr, "203.0.113.7").await, StatusCode::FORBIDDEN);
+    assert_eq!(
+        status(addr, "198.51.100.1, 203.0.113.7").await,
+        StatusCode::FORBIDDEN
+    );
+    // what the client put in front of our proxy's entry counts for nothing
+    assert_eq!(
+        status(addr, "203.0.113.7, 198.51.100.1").await,
+        StatusCode::OK
+    );
+
+    // nor does anything from a peer that isn't a trusted proxy
+    let app = TestApp::in_memory_with_config(deny);
+    let addr = app.serve().await;
+    assert_eq!(status(addr, "203.0.113.7").await, StatusCode::OK);
+}
+
+#[test]
+fn client_ip_reads_the_forwarded_header() {
+    let trusted = ["10.0.0.0/8".parse().unwrap()];
+    let peer = Some("10.0.0.1".parse().unwrap());
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "forwarded",
+        r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.2"#
+            .parse()
+            .unwrap(),
+    );
+    headers.insert("x-forwarded-for", "192.0.2.1".parse().unwrap());
+    let ClientIp(client) = ClientIp::resolve(peer, &headers, &trusted);
+    assert_eq!(client, Some("2001:db8::1".parse().unwrap()));
+
+    headers.insert("forwarded", "for=unknown, for=10.0.0.2".parse().unwrap());
+    let ClientIp(client) = ClientIp::resolve(peer, &headers, &trusted);
+    assert_eq!(client, Some("10.0.0.2".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn users_without_an_avatar_get_gravatars() {
+    let app = TestApp::in_memory_with_config(|config| {
+        config.gravatar = Some(Gravatar {
+            default_image: String::from("https://example.com/avatar.png"),
+            rating: String::from("pg"),
+        })
+    });
+    let user: User = app
+        .post("/users")
+        .json(&json!({ "username": "gravatar", "email": "Test@Example.com" }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    let expected = "https://gravatar.com/avatar/973dfe463ec85785f5f95af5ba3906eedb2d931c24e69824a89ea65dba4e813b?d=https%3A%2F%2Fexample.com%2Favatar.png&r=pg";
+    assert_eq!(user.avatar_url.as_deref(), Some(expected));
+    app.get(&format!("/users/{}", user.id))
+        .send()
+        .await
+        .assert_json_includes(json!({ "avatar_url": expected }));
+    app.post("/posts")
+        .admin()
+        .json(&json!({ "title": "Avatar", "body": "body", "user_id": user.id }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get("/posts?include=author")
+        .send()
+        .await
+        .assert_json_includes(json!([{ "author": { "avatar_url": expected } }]));
+
+    let app = TestApp::in_memory();
+    app.post("/users")
+        .json(&json!({ "username": "plain", "email": "plain@example.com" }))
+        .send()
+        .await
+        .assert_json_includes(json!({ "avatar_url": null }));
+}
+
+#[tokio::test]
+async fn post_meta_pages_carry_open_graph_and_twitter_tags() {
+    let app = TestApp::in_memory_with_config(|config| {
+        config.public_url = Some(String::from("https://social.example"))
+    });
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({
+            "title": "Tom & \"Jerry\"",
+            "body": format!("A chase\nacross {}.", "the house ".repeat(30)),
+            "user_id": null,
+        }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK)
+        .json();
+    let response = app
+        .get(&format!("/posts/{}/meta", post.id))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(
+        response.header("content-type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let html = response.text();
+    let url = format!("https://social.example/posts/{}", post.public_id);
+    let description = format!("A chase across {}the…", "the house ".repeat(18));
+    for expected in [
+        String::from(r#"<meta property="og:title" content="Tom &amp; &quot;Jerry&quot;">"#),
+        format!(r#"<meta property="og:description" content="{description}">"#),
+        format!(r#"<meta property="og:url" content="{url}">"#),
+        String::from(r#"<meta name="twitter:card" content="summary">"#),
+        format!(r#"<link rel="canonical" href="{url}">"#),
+        format!(
+            r#"<link rel="alternate" type="application/json+oembed" href="https://social.example/oembed?url={}">"#,
+            url.replace(':', "%3A").replace('/', "%2F")
+        ),
+    ] {
+        assert!(html.contains(&expected), "{expected} missing from {html}");
+    }
+    assert!(!html.contains("og:image"), "{html}");
+
+    let spam: Post = app
+        .post("/posts")
+        .json(&json!({
+            "title": "deals",
+            "body": "see https://a.example https://b.example www.c.example",
+            "user_id": null,
+        }))
+        .send()
+        .await
+        .json();
+    app.get(&format!("/posts/{}/meta", spam.id))
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn posts_have_qr_codes_of_their_urls() {
+    let app = TestApp::in_memory_with_config(|config| {
+        config.public_url = Some(String::from("https://social.example"))
+    });
+    let post: Post = app
+        .post("/posts")
+        .json(&json!({ "title": "Print me", "body": "b", "user_id": null }))
+        .send()
+        .await
+        .json();
+    let path = format!("/posts/{}/qr.png", post.id);
+    let response = app
+        .get(&format!("{path}?size=200"))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert_eq!(response.header("content-type").unwrap(), "image/png");
+    let decoder = png::Decoder::new(response.body.as_ref());
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(info.width, info.height);
+    assert!(info.width <= 200 && info.width > 100, "{}", info.width);
+    // a white border of 4 modules around a code of 21, 25, 29... modules,
+    // which starts with the black corner of a finder pattern
+    let width = info.width as usize;
+    let corner = (0..width).position(|i| pixels[i * width + i] == 0).unwrap();
+    let scale = corner / 4;
+    assert_eq!(corner % 4, 0);
+    assert_eq!(width % scale, 0);
+    assert_eq!((width / scale - 8 - 21) % 4, 0);
+
+    let again = app.get(&format!("{path}?size=200")).send().await;
+    assert_eq!(again.body, response.body);
+    app.get(&format!("{path}?size=10"))
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST)
+        .assert_json_includes(json!({ "error": "invalid_size", "field": "size" }));
+
+    let app = TestApp::in_memory();
+    app.post("/posts")
+        .json(&json!({ "title": "Print me", "body": "b", "user_id": null }))
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    app.get("/posts/1/qr.png")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route() {
+    let app = TestApp::in_memory_with_config(|config| {
+        config.slo.availability = Some(0.999);
+        config.slo.latency = Some(0.99);
+        config.slo.latency_threshold = Duration::from_millis(500);
+    });
+    app.get("/posts/999")
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    app.get("/nowhere").send().await;
+    app.get("/admin/metrics")
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .get("/admin/metrics")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::OK);
+    assert!(response
+        .header("content-type")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let metrics = response.text();
+    for line in [
+        r#"http_request_duration_seconds_count{method="GET",route="/posts/:id"} 1"#,
+        r#"http_request_duration_seconds_bucket{method="GET",route="/posts/:id",le="+Inf"} 1"#,
+        r#"http_requests_total{method="GET",route="/posts/:id",status="4xx"} 1"#,
+        r#"http_requests_total{method="GET",route="unmatched",status="4xx"} 1"#,
+        r#"http_request_errors_total{method="GET",route="/posts/:id"} 0"#,
+        r#"slo_target{slo="availability"} 0.999"#,
+        r#"slo_burn_rate{slo="availability",window="5m"} 0"#,
+        r#"slo_burn_rate{slo="latency",window="1h"} 0"#,
+        // the test's current thread runtime
+        "tokio_workers 1",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == line),
+            "no {line} in\n{metrics}"
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn metrics_show_what_the_runtime_is_up_to() {
+    let app = TestApp::in_memory();
+    let scrape = || async {
+        app.get("/admin/metrics")
+            .admin()
+            .send()
+            .await
+            .assert_status(StatusCode::OK)
+            .text()
+    };
+    let value = |metrics: &str, name: &str| -> f64 {
+        metrics
+            .lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+            .unwrap_or_else(|| panic!("no {name} in\n{metrics}"))
+    };
+
+    // tasks stuck waiting, as they would in a stall
+    let (release, waiting) = tokio::sync::watch::channel(());
+    for _ in 0..5 {
+        let mut waiting = waiting.clone();
+        tokio::spawn(async move { waiting.changed().await.ok() });
+    }
+    let metrics = scrape().await;
+    assert_eq!(value(&metrics, "tokio_workers"), 2.0);
+    let alive = value(&metrics, "tokio_alive_tasks");
+    assert!(alive >= 5.0, "{metrics}");
+    for worker in 0..2 {
+        value(
+            &metrics,
+            &format!(r#"tokio_worker_busy_seconds_total{{worker="{worker}"}}"#),
+        );
+        value(
+            &metrics,
+            &format!(r#"tokio_worker_parks_total{{worker="{worker}"}}"#),
+        );
+    }
+
+    release.send(()).unwrap();
+    for _ in 0..100 {
+        if value(&scrape().await, "tokio_alive_tasks") <= alive - 5.0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("the released tasks are still alive");
+}
+
+#[tokio::test]
+async fn profiles_are_for_admins_and_take_a_while() {
+    let app = TestApp::in_memory();
+    app.get("/admin/debug/pprof/profile?seconds=1")
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    app.get("/admin/debug/pprof/profile?seconds=0")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    app.get("/admin/debug/pprof/profile?seconds=1&format=svg")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[cfg(not(feature = "pprof"))]
+#[tokio::test]
+async fn profiling_needs_the_pprof_feature() {
+    let app = TestApp::in_memory();
+    app.get("/admin/debug/pprof/profile?seconds=1")
+        .admin()
+        .send()
+        .await
+        .assert_status(StatusCode::NOT_IMPLEMENTED);
+}
+
+#[cfg(any(feature = "jemalloc", feature = "mimalloc"))]
+#[tokio::test]
+async fn metrics_show_the_allocator() {
+    let app = TestApp::in_memory();
+    let metrics = app.get("/admin/metrics").admin().send().await.text();
+    assert!(
+        metrics.contains(r#"state="resident"} "#),
+        "no resident bytes in\n{metrics}"
+    );
+}
+
+#[test]
+fn logs_are_redacted() {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use rust_axum_rest_api::redact::{JsonFormat, Redactor};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let redactor = Redactor::new(["ssn"]);
+    assert_eq!(
+        redactor.scrub("mail alice@example.com, or @bob"),
+        "mail [redacted], or @bob"
+    );
+    assert_eq!(
+        redactor.scrub(r#"{"authorization": "Bearer abc.def", "id": 1}"#),
+        r#"{"authorization": "[redacted]", "id": 1}"#
+    );
+    assert_eq!(
+        redactor.scrub("GET /unsubscribe?token=s3cret&list=news"),
+        "GET /unsubscribe?token=[redacted]&list=news"
+    );
+    assert_eq!(redactor.scrub("nothing to hide"), "nothing to hide");
+
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .fmt_fields(redactor)
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info!(
+            user_id = 7,
+            email = "carol@example.com",
+            ssn = "078-05-1120",
+            "signed up as carol@example.com"
+        );
+    });
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        log.contains("signed up as [redacted] user_id=7 email=[redacted] ssn=[redacted]"),
+        "{log}"
+    );
+    assert!(
+        !log.contains("example.com") && !log.contains("078"),
+        "{log}"
+    );
+
+    // and as JSON
+    let buffer = Buffer::default();
+    let writer = buffer.clone();
+    let redactor = Redactor::new(["ssn"]);
+    let subscriber = tracing_subscriber::fmt()
+        .event_format(JsonFormat::new(redactor.clone()))
+        .fmt_fields(redactor)
+        .with_writer(move || writer.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("request", token = "abc");
+        let _entered = span.enter();
+        tracing::warn!(
+            user_id = 7,
+            ssn = "078-05-1120",
+            note = "ask dave@example.com",
+            "signed up as carol@example.com"
+        );
+    });
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let line: Value = serde_json::from_str(log.trim()).unwrap();
+    assert_eq!(line["level"], "WARN");
+    assert_eq!(
+        line["fields"],
+        json!({
+            "message": "signed up as [redacted]",
+            "user_id": 7,
+            "ssn": "[redacted]",
+            "note": "ask [redacted]",
+        })
+    );
+    assert_eq!(
+        line["spans"],
+        json!([{ "name": "request", "fields": "token=[redacted]" }])
+    );
+}
+
+#[test]
+fn profiles_pick_their_dotenv_files_and_defaults() {
+    use rust_axum_rest_api::profile::{LogFormat, Profile};
+
+    assert_eq!("prod".parse(), Ok(Profile::Production));
+    assert_eq!("Development".parse(), Ok(Profile::Development));
+    assert!("staging".parse::<Profile>().is_err());
+    assert_eq!(
+        Profile::Production.dotenv_files(),
+        [
+            ".env.production.local",
+            ".env.production",
+            ".env.local",
+            ".env"
+        ]
+    );
+    assert_eq!(
+        Profile::Test.dotenv_files(),
+        [".env.test.local", ".env.test", ".env"]
+    );
+    assert_eq!(Profile::Development.log_format(), LogFormat::Text);
+    assert_eq!(Profile::Production.log_format(), LogFormat::Json);
+    assert!(Profile::Development.any_origin() && Profile::Development.error_details());
+    assert!(!Profile::Production.any_origin() && !Profile::Production.error_details());
+
+    // APP_ENV from .env, and the more specific files first
+    let dir = std::env::temp_dir().join(common::unique("profile"));
+    std::fs::create_dir_all(&dir).unwrap();
+    let var = common::unique("PROFILE_VAR");
+    let local = format!("{var}_LOCAL");
+    std::fs::write(dir.join(".env"), format!("APP_ENV=test\n{var}=env\n")).unwrap();
+    std::fs::write(dir.join(".env.test"), format!("{var}=test\n")).unwrap();
+    std::fs::write(dir.join(".env.local"), format!("{local}=local\n")).unwrap();
+    assert_eq!(Profile::load(&dir), Ok(Profile::Test));
+    assert_eq!(std::env::var(&var).unwrap(), "test");
+    assert!(std::env::var(&local).is_err());
+}
+
+#[tokio::test]
+async fn development_allows_any_origin_and_explains_errors() {
+    use axum::body::{to_bytes, Body};
+    use axum::routing::get;
+    use rust_axum_rest_api::profile::Profile;
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+
+    let development = TestApp::in_memory_with_config(|config| {
+        config.profile = Profile::Development;
+        config.error_details = true;
+    });
+    let response = development
+        .get("/posts")
+        .header("origin", "http://localhost:3000")
+        .send()
+        .await;
+    assert_eq!(
+        response.header("access-control-allow-origin").unwrap(),
+        "http://localhost:3000"
+    );
+    let strict = TestApp::in_memory();
+    let response = strict
+        .get("/posts")
+        .header("origin", "http://localhost:3000")
+        .send()
+        .await;
+    assert!(response.header("access-control-allow-origin").is_none());
+
+    for (app, detail) in [
+        (&development, json!("panicked: boom")),
+        (&strict, Value::Null),
+    ] {
+        let router: axum::Router = axum::Router::new()
+            .route("/boom", get(|| async { panic!("boom") as &'static str }))
+            .layer(CatchPanicLayer::custom(
+                rust_axum_rest_api::panic::handle_panic,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                app.state.clone(),
+                rust_axum_rest_api::error::scope,
+            ));
+        let response = router
+            .oneshot(
+                axum::http::Request::get("/boom")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "internal");
+        assert_eq!(body["detail"], detail);
+    }
+}
+
+// `router` on a port of its own, standing in for a secrets backend
+async fn serve_fake(router: axum::Router) -> SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    addr
+}
+
+#[tokio::test]
+async fn secrets_are_read_from_vault() {
+    use axum::routing::get;
+    use rust_axum_rest_api::secrets::vault::Vault;
+    use rust_axum_rest_api::secrets::SecretsProvider;
+
+    let router = axum::Router::new().route(
+        "/v1/secret/data/app",
+        get(|headers: HeaderMap| async move {
+            if headers["x-vault-token"] != "s.token" || headers["x-vault-namespace"] != "team" {
+                return (
+                    StatusCode::FORBIDDEN,
+                    axum::Json(json!({ "errors": ["permission denied"] })),
+                );
+            }
+            let data = json!({
+                "data": {
+                    "data": { "DATABASE_URL": "postgres://app@db/app", "DB_MAX_CONNECTIONS": 20 },
+                    "metadata": { "version": 3 },
+                },
+            });
+            (StatusCode::OK, axum::Json(data))
+        }),
+    );
+    let addr = serve_fake(router).await;
+
+    let vault = Vault::new(&format!("http://{addr}/"), "s.token", "secret/data/app");
+    assert!(vault.fetch().await.is_err());
+    let secrets = vault.with_namespace("team").fetch().await.unwrap();
+    assert_eq!(secrets["DATABASE_URL"], "postgres://app@db/app");
+    assert_eq!(secrets["DB_MAX_CONNECTIONS"], "20");
+}
+
+#[tokio::test]
+async fn secrets_are_read_from_aws_secrets_manager() {
+    use axum::routing::post;
+    use rust_axum_rest_api::secrets::aws::{Credentials, SecretsManager};
+    use rust_axum_rest_api::secrets::SecretsProvider;
+
+    let router = axum::Router::new().route(
+        "/",
+        post(|headers: HeaderMap, body: String| async move {
+            let authorization = headers["authorization"].to_str().unwrap().to_owned();
+            assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+            assert!(authorization.contains("/eu-west-1/secretsmanager/aws4_request, "));
+            assert!(authorization.contains(
+                "SignedHeaders=content-type;host;x-amz-date;x-amz-security-token;x-amz-target, "
+            ));
+            assert_eq!(headers["x-amz-target"], "secretsmanager.GetSecretValue");
+            assert_eq!(headers["x-amz-security-token"], "session");
+            let request: Value = serde_json::from_str(&body).unwrap();
+            if request["SecretId"] != "app/prod" {
+                let error = json!({ "__type": "ResourceNotFoundException" });
+                return (StatusCode::BAD_REQUEST, axum::Json(error));
+            }
+            let secret = json!({ "ADMIN_TOKEN": "admin", "EMAIL_LINK_SECRET": "links" });
+            let value = json!({ "Name": "app/prod", "SecretString": secret.to_string() });
+            (StatusCode::OK, axum::Json(value))
+        }),
+    );
+    let addr = serve_fake(router).await;
+
+    let manager = |secret_id: &str| {
+        let credentials = Credentials {
+            access_key_id: String::from("AKIDEXAMPLE"),
+            secret_access_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            session_token: Some(String::from("session")),
+        };
+        SecretsManager::new("eu-west-1", secret_id, credentials)
+            .with_endpoint(&format!("http://{addr}/"))
+            .unwrap()
+    };
+    let secrets = manager("app/prod").fetch().await.unwrap();
+    assert_eq!(secrets.len(), 2);
+    assert_eq!(secrets["ADMIN_TOKEN"], "admin");
+    assert_eq!(secrets["EMAIL_LINK_SECRET"], "links");
+    let error = manager("app/gone").fetch().await.unwrap_err();
+    assert!(error.to_string().contains("ResourceNotFoundException"));
+}
+
+#[tokio::test]
+async fn refreshed_database_url_is_used_for_new_connections() {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use rust_axum_rest_api::secrets::{Secrets, SecretsError, SecretsProvider};
+
+    struct Fixed(Arc<Mutex<HashMap<String, String>>>);
+
+    #[async_trait::async_trait]
+    impl SecretsProvider for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn fetch(&self) -> Result<HashMap<String, String>, SecretsError> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    let values = Arc::new(Mutex::new(HashMap::from([
+        (
+            String::from("DATABASE_URL"),
+            String::from("postgres://app@db/one"),
+        ),
+        (String::from("ADMIN_TOKEN"), String::from("admin")),
+    ])));
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .connect_lazy("postgres://app@db/one")
+        .unwrap();
+    let mut secrets = Secrets::new(Box::new(Fixed(values.clone())), None);
+    assert_eq!(
+        secrets.refresh(&pool).await.unwrap(),
+        ["ADMIN_TOKEN", "DATABASE_URL"]
+    );
+    assert!(secrets.refresh(&pool).await.unwrap().is_empty());
+
+    values.lock().unwrap().insert(
+        String::from("DATABASE_URL"),
+        String::from("postgres://app@db/two"),
+    );
+    assert_eq!(secrets.refresh(&pool).await.unwrap(), ["DATABASE_URL"]);
+    assert_eq!(pool.connect_options().get_database(), Some("two"));
+    values.lock().unwrap().remove("ADMIN_TOKEN");
+    assert_eq!(secrets.refresh(&pool).await.unwrap(), ["ADMIN_TOKEN"]);
+}
+
+#[tokio::test]
+async fn shutdown_drains_then_finishes_the_requests_in_flight() {
+    use axum::routing::get;
+    use rust_axum_rest_api::shutdown::ShutdownConfig;
+
+    let config = ShutdownConfig {
+        drain: Duration::from_millis(300),
+        timeout: Duration::from_secs(5),
+    };
+    let app = TestApp::in_memory_with_config(|c| c.shutdown = config);
+    let shutdown = app.state.shutdown.clone();
+    let addr = app.serve_plain(Http2::default()).await;
+    // a request still going when the listeners stop
+    let slow: axum::Router = axum::Router::new().route(
+        "/slow",
+        get(|| async {
+            tokio::time::sleep(Duration::from_millis(600)).await;
+            "done"
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let slow_addr = listener.local_addr().unwrap();
+    tokio::spawn(rust_axum_rest_api::server::serve(
+        listener,
+        Http2::default(),
+        slow,
+        shutdown.clone(),
+    ));
+    let in_flight = tokio::spawn(reqwest::get(format!("http://{slow_addr}/slow")));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let running = {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move { shutdown.run(config).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // still served while the load balancers catch up, but not ready
+    let response = reqwest::get(format!("http://{addr}/readyz")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "shutting_down");
+    assert!(!running.is_finished());
+
+    let response = in_flight.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "done");
+    tokio::time::timeout(Duration::from_secs(2), running)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    assert!(tokio::net::TcpStream::connect(slow_addr).await.is_err());
+}
@@ -0,0 +1,455 @@
+mod common;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use common::db::TestDb;
+use common::unique;
+use rust_axum_rest_api::error::ApiError;
+use rust_axum_rest_api::jobs;
+use rust_axum_rest_api::locks::{self, Locks};
+use rust_axum_rest_api::models::{OrgRole, Role};
+use rust_axum_rest_api::repo;
+use rust_axum_rest_api::repo::breaker::{Breaker, BreakerConfig};
+use rust_axum_rest_api::repo::retry;
+use rust_axum_rest_api::retention::{self, PurgeReport, Retention};
+
+async fn insert_user(db: &TestDb) -> (i32, String) {
+    let username = unique("user");
+    let id = sqlx::query_scalar!(
+        "INSERT INTO users (username, email) VALUES ($1, $1 || '@example.com') RETURNING id",
+        username
+    )
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    (id, username)
+}
+
+#[tokio::test]
+async fn api_keys_grant_admin_only_to_admins() {
+    let db = TestDb::new().await;
+    let (user_id, username) = insert_user(&db).await;
+    let (api_key, secret) = repo::api_keys::create(&db.pool, user_id, "ci")
+        .await
+        .unwrap();
+    assert!(secret.starts_with(&api_key.prefix));
+
+    assert!(!repo::api_keys::is_admin_key(&db.pool, &secret)
+        .await
+        .unwrap());
+    repo::users::set_role(&db.pool, &username, Role::Admin)
+        .await
+        .unwrap()
+        .expect("user exists");
+    assert!(repo::api_keys::is_admin_key(&db.pool, &secret)
+        .await
+        .unwrap());
+
+    assert!(repo::api_keys::revoke(&db.pool, api_key.id).await.unwrap());
+    assert!(!repo::api_keys::revoke(&db.pool, api_key.id).await.unwrap());
+    assert!(!repo::api_keys::is_admin_key(&db.pool, &secret)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn purge_only_removes_soft_deleted_posts() {
+    let db = TestDb::new().await;
+    let (user_id, _) = insert_user(&db).await;
+    sqlx::query!(
+        "INSERT INTO posts (user_id, title, body, deleted_at) VALUES ($1, 'kept', 'b', NULL), ($1, 'gone', 'b', NOW() - INTERVAL '2 days')",
+        user_id
+    )
+    .execute(&db.pool)
+    .await
+    .unwrap();
+
+    assert_eq!(repo::posts::purge_deleted(&db.pool, 7).await.unwrap(), 0);
+    assert_eq!(repo::posts::purge_deleted(&db.pool, 1).await.unwrap(), 1);
+    let titles = sqlx::query_scalar!("SELECT title FROM posts")
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(titles, vec!["kept"]);
+}
+
+#[tokio::test]
+async fn retention_purges_old_data_unless_dry_running() {
+    let db = TestDb::new().await;
+    let (user_id, _) = insert_user(&db).await;
+    sqlx::query!(
+        "INSERT INTO posts (user_id, title, body, deleted_at) VALUES ($1, 'kept', 'b', NOW() - INTERVAL '2 days'), ($1, 'gone', 'b', NOW() - INTERVAL '40 days')",
+        user_id
+    )
+    .execute(&db.pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO impersonation_tokens (token_hash, user_id, impersonator, expires_at) VALUES ('live', $1, 'admin', NOW() + INTERVAL '1 hour'), ('expired', $1, 'admin', NOW() - INTERVAL '1 hour')",
+        user_id
+    )
+    .execute(&db.pool)
+    .await
+    .unwrap();
+    let org_id =
+        sqlx::query_scalar!("INSERT INTO organizations (name) VALUES ('org') RETURNING id")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+    sqlx::query!(
+        "INSERT INTO org_invitations (org_id, email, role, token_hash, expires_at, accepted_at) VALUES ($1, 'a@example.com', 'member', 'pending', NOW() + INTERVAL '1 day', NULL), ($1, 'b@example.com', 'member', 'stale', NOW() - INTERVAL '40 days', NULL), ($1, 'c@example.com', 'member', 'accepted', NOW() - INTERVAL '40 days', NOW() - INTERVAL '41 days')",
+        org_id
+    )
+    .execute(&db.pool)
+    .await
+    .unwrap();
+
+    let mut retention = Retention {
+        days: 30,
+        dry_run: true,
+    };
+    let expected = PurgeReport {
+        posts: 1,
+        impersonation_tokens: 1,
+        invitations: 1,
+        dry_run: true,
+    };
+    assert_eq!(
+        retention::purge(&db.pool, retention).await.unwrap(),
+        expected
+    );
+    assert_eq!(
+        expected.to_string(),
+        "would have purged 1 posts, 1 impersonation tokens and 1 invitations"
+    );
+    retention.dry_run = false;
+    assert_eq!(
+        retention::purge(&db.pool, retention).await.unwrap(),
+        PurgeReport {
+            dry_run: false,
+            ..expected
+        }
+    );
+    assert_eq!(
+        retention::purge(&db.pool, retention).await.unwrap(),
+        PurgeReport::default()
+    );
+
+    let titles = sqlx::query_scalar!("SELECT title FROM posts")
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(titles, vec!["kept"]);
+    let tokens = sqlx::query_scalar!("SELECT token_hash FROM impersonation_tokens")
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, vec!["live"]);
+    let invitations =
+        sqlx::query_scalar!("SELECT token_hash FROM org_invitations ORDER BY token_hash")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+    assert_eq!(invitations, vec!["accepted", "pending"]);
+}
+
+#[tokio::test]
+async fn scheduled_jobs_run_on_one_instance_at_a_time() {
+    let db = TestDb::new().await;
+    let locks = Locks::Postgres(db.pool.clone());
+    let job = "test-exclusive";
+    let ran = jobs::exclusive(&locks, job, async {
+        // another instance starting the same job meanwhile
+        let nested = jobs::exclusive(&locks, job, async { Ok::<_, sqlx::Error>(()) }).await;
+        assert_eq!(nested.unwrap(), None);
+        Ok::<_, sqlx::Error>("ran")
+    })
+    .await
+    .unwrap();
+    assert_eq!(ran, Some("ran"));
+    // released once done
+    assert_eq!(
+        jobs::exclusive(&locks, job, async { Ok::<_, sqlx::Error>(1) })
+            .await
+            .unwrap(),
+        Some(1)
+    );
+
+    let stats = jobs::stats()
+        .into_iter()
+        .find(|stats| stats.job == job)
+        .unwrap();
+    assert_eq!((stats.runs, stats.contended), (2, 1));
+    assert!(stats.last_contended_at.is_some());
+}
+
+#[tokio::test]
+async fn leases_hold_until_released_or_lost() {
+    let db = TestDb::new().await;
+    let locks = Locks::Postgres(db.pool.clone());
+
+    let mut lease = locks.try_acquire("lease").await.unwrap().unwrap();
+    assert!(locks.try_acquire("lease").await.unwrap().is_none());
+    let other = locks.try_acquire("other").await.unwrap().unwrap();
+    other.release().await.unwrap();
+    assert!(lease.renew().await.unwrap());
+
+    // the session holding it goes away
+    sqlx::query(
+        "SELECT pg_terminate_backend(pid) FROM pg_locks
+         WHERE locktype = 'advisory' AND database = (
+             SELECT oid FROM pg_database WHERE datname = current_database())",
+    )
+    .execute(&db.pool)
+    .await
+    .unwrap();
+    assert!(!matches!(lease.renew().await, Ok(true)));
+    let taken_over = locks.try_acquire("lease").await.unwrap().unwrap();
+    taken_over.release().await.unwrap();
+}
+
+#[tokio::test]
+async fn singletons_run_on_the_leader_only() {
+    let db = TestDb::new().await;
+    let locks = Locks::Postgres(db.pool.clone());
+    let started = Arc::new(AtomicU32::new(0));
+
+    // two instances campaigning for the same service
+    for _ in 0..2 {
+        let started = started.clone();
+        locks::spawn_singleton(locks.clone(), "test-singleton", move || {
+            started.fetch_add(1, Ordering::SeqCst);
+            std::future::pending()
+        });
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(started.load(Ordering::SeqCst), 1);
+    assert!(locks.try_acquire("test-singleton").await.unwrap().is_none());
+
+    // one that is done hands the lock back
+    let (done, finished) = tokio::sync::oneshot::channel();
+    let mut done = Some(done);
+    locks::spawn_singleton(locks.clone(), "test-finished", move || {
+        let done = done.take();
+        async move {
+            if let Some(done) = done {
+                done.send(()).ok();
+            }
+        }
+    });
+    finished.await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let lease = locks.try_acquire("test-finished").await.unwrap().unwrap();
+    lease.release().await.unwrap();
+}
+
+#[cfg(feature = "redis")]
+#[tokio::test]
+async fn redis_leases_are_only_given_up_by_their_holder() {
+    let redis = common::redis::TestRedis::new().await;
+    let locks = Locks::Redis(redis.store.clone());
+    let name = unique("lease");
+
+    let mut lease = locks.try_acquire(&name).await.unwrap().unwrap();
+    assert!(locks.try_acquire(&name).await.unwrap().is_none());
+    assert!(lease.renew().await.unwrap());
+
+    // the key expired and another instance took the lock over
+    let mut conn = redis.store.conn().await.unwrap();
+    let _: i32 = redis::cmd("DEL")
+        .arg(format!("lock:{name}"))
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    let taken_over = locks.try_acquire(&name).await.unwrap().unwrap();
+    assert!(!lease.renew().await.unwrap());
+    lease.release().await.unwrap();
+    assert!(locks.try_acquire(&name).await.unwrap().is_none());
+
+    taken_over.release().await.unwrap();
+    let lease = locks.try_acquire(&name).await.unwrap().unwrap();
+    lease.release().await.unwrap();
+}
+
+#[test]
+fn breaker_fails_fast_while_the_database_is_down() {
+    let breaker = Breaker::new(BreakerConfig {
+        failures: 2,
+        open_for: Duration::from_millis(50),
+        probe_percent: 100,
+    });
+    let down = Err::<(), _>(sqlx::Error::PoolTimedOut);
+    breaker.record(&down);
+    // the query's own fault, the database is fine
+    breaker.record(&Err::<(), _>(sqlx::Error::RowNotFound));
+    breaker.record(&down);
+    assert!(breaker.check().is_ok());
+    breaker.record(&down);
+    assert_eq!(breaker.state(), "open");
+    let open = breaker.check().unwrap_err();
+    assert_eq!(breaker.rejected(), 1);
+
+    let response = ApiError::from(sqlx::Error::from(open)).into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["retry-after"], "1");
+
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(breaker.check().is_ok(), "a probe goes ahead");
+    assert_eq!(breaker.state(), "half_open");
+    breaker.record(&down);
+    assert_eq!(breaker.state(), "open");
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(breaker.check().is_ok());
+    breaker.record(&Ok(()));
+    assert_eq!(breaker.state(), "closed");
+}
+
+#[tokio::test]
+async fn reads_are_retried_after_transient_errors() {
+    let attempts = AtomicU32::new(0);
+    let read = retry::read("tests::flaky", || async {
+        match attempts.fetch_add(1, Ordering::Relaxed) {
+            0 => Err(sqlx::Error::PoolTimedOut),
+            attempt => Ok(attempt),
+        }
+    })
+    .await;
+    assert_eq!(read.unwrap(), 1);
+    assert!(retry::counts().contains(&("tests::flaky", 1)));
+
+    // a missing row stays missing
+    let attempts = AtomicU32::new(0);
+    let read = retry::read("tests::missing", || async {
+        attempts.fetch_add(1, Ordering::Relaxed);
+        Err::<(), _>(sqlx::Error::RowNotFound)
+    })
+    .await;
+    assert!(matches!(read, Err(sqlx::Error::RowNotFound)));
+    assert_eq!(attempts.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn emails_are_sealed_and_found_by_their_hash() {
+    use base64::Engine;
+    use repo::emails::{self, EmailKey};
+    use repo::users::{PgUserRepository, UserRepository};
+    use rust_axum_rest_api::models::CreateUser;
+
+    let db = TestDb::new().await;
+    // written before there was a key
+    let (clear_id, clear_name) = insert_user(&db).await;
+    let clear_email = format!("{clear_name}@example.com");
+
+    // the same key in every test of this binary, the setting being global
+    let key = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+    emails::configure(EmailKey::from_base64(&key).unwrap());
+    let users = PgUserRepository::new(db.pool.clone());
+    let username = unique("sealed");
+    let email = format!("{username}@example.com");
+    let user = users
+        .create(&CreateUser {
+            username: username.clone(),
+            email: email.clone(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(user.email, email);
+
+    let stored = sqlx::query!("SELECT email, email_hash FROM users WHERE id = $1", user.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert!(stored.email.starts_with("enc:v1:"), "{}", stored.email);
+    assert!(!stored.email.contains(&username));
+    assert_eq!(stored.email_hash, emails::hash(&email));
+    assert_eq!(users.get(user.id).await.unwrap().email, email);
+    let found = repo::users::find_by_email(&db.pool, &email).await.unwrap();
+    assert_eq!(found.map(|user| user.id), Some(user.id));
+    assert_eq!(users.taken("", &email).await.unwrap(), (false, true));
+
+    // taken, though the ciphertext would differ
+    let err = users
+        .create(&CreateUser {
+            username: unique("other"),
+            email: email.clone(),
+        })
+        .await
+        .unwrap_err();
+    let response = ApiError::from(err).into_response();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // the old address is found in the clear until it is sealed too, and
+    // can't sign up again meanwhile
+    let found = repo::users::find_by_email(&db.pool, &clear_email)
+        .await
+        .unwrap();
+    assert_eq!(found.map(|user| user.id), Some(clear_id));
+    let err = users
+        .create(&CreateUser {
+            username: unique("again"),
+            email: clear_email.clone(),
+        })
+        .await
+        .unwrap_err();
+    let response = ApiError::from(err).into_response();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // invited addresses are sealed too, old ones by `seal_clear`
+    let org = repo::orgs::create(&db.pool, "Acme", user.id).await.unwrap();
+    let invited = format!("{}@example.com", unique("invited"));
+    let (invitation, token) = repo::invitations::create(
+        &db.pool,
+        org.id,
+        &invited,
+        OrgRole::Member,
+        Some(user.id),
+        chrono::Duration::days(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(invitation.email, invited);
+    let stored = sqlx::query_scalar!(
+        "SELECT email FROM org_invitations WHERE id = $1",
+        invitation.id
+    )
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert!(stored.starts_with("enc:v1:"));
+    let pending = repo::invitations::find_pending(&db.pool, &token)
+        .await
+        .unwrap();
+    assert_eq!(pending.map(|invitation| invitation.email), Some(invited));
+    let clear_invitation = sqlx::query_scalar!(
+        "INSERT INTO org_invitations (org_id, email, role, token_hash, expires_at)
+         VALUES ($1, 'old@example.com', 'member', $2, NOW()) RETURNING id",
+        org.id,
+        unique("hash")
+    )
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+
+    while emails::seal_clear(&db.pool, 100).await.unwrap() > 0 {}
+    let stored = sqlx::query_scalar!(
+        "SELECT email FROM org_invitations WHERE id = $1",
+        clear_invitation
+    )
+    .fetch_one(&db.pool)
+    .await
+    .unwrap();
+    assert!(stored.starts_with("enc:v1:"));
+    let stored = sqlx::query_scalar!("SELECT email FROM users WHERE id = $1", clear_id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+    assert!(stored.starts_with("enc:v1:"));
+    let found = repo::users::find_by_email(&db.pool, &clear_email)
+        .await
+        .unwrap();
+    assert_eq!(found.map(|user| user.email), Some(clear_email));
+}
@@ -0,0 +1,184 @@
+// Wire types of the posts and users API, shared by the server and the client
+// crate so both sides always agree on the JSON shapes. Timestamps are
+// serialized as RFC 3339 strings.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Post {
+    pub id: i32,
+    pub uuid: Uuid,
+    // ULID, usable in place of `id` in URLs
+    pub public_id: String,
+    pub user_id: Option<i32>,
+    // the author's uuid, for links to them under ID_SCHEME=uuid; not part of
+    // responses
+    #[serde(skip)]
+    pub user_uuid: Option<Uuid>,
+    // the organization sharing ownership of the post, if any
+    #[serde(default)]
+    pub org_id: Option<i32>,
+    // everyone who wrote the post, `user_id` first, then the co-authors in
+    // the order they were added
+    #[serde(default)]
+    pub authors: Vec<i32>,
+    pub title: String,
+    pub body: String,
+    // "published", "pending" while it waits for a moderator, "spam" or
+    // "rejected"; missing from older backups, which only held published posts
+    #[serde(default = "published")]
+    pub status: String,
+    // kept up to date by the database rather than counted on every read
+    #[serde(default)]
+    pub comments_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+fn published() -> String {
+    String::from("published")
+}
+
+// A post with its author inlined, returned for `?include=author`: the post's
+// fields plus `author`, which is null for posts without one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostWithAuthor {
+    #[serde(flatten)]
+    pub post: Post,
+    pub author: Option<PublicUser>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatePost {
+    pub title: String,
+    pub body: String,
+    pub user_id: Option<i32>,
+    // post on behalf of an organization the acting user belongs to
+    #[serde(default)]
+    pub org_id: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdatePost {
+    pub title: String,
+    pub body: String,
+    // the new primary author, left out to keep the current one
+    pub user_id: Option<i32>,
+}
+
+// An entry of a post's history, `GET /posts/:id/history`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostRevision {
+    // "post.create", "post.update", "post.approve" and so on
+    pub action: String,
+    // "anonymous", "user 3", "admin token" or "admin user 1"
+    pub actor: String,
+    pub changes: Vec<FieldChange>,
+    pub changed_at: DateTime<Utc>,
+}
+
+// A field that changed, `old` is null for created posts and `new` for
+// deleted ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+// Body of `POST /posts/:id/report` and `POST /comments/:id/report`, made by
+// the reporting user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateReport {
+    pub reason: String,
+}
+
+// A report of a post, or of one of its comments, open until `resolved_at`
+// is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub id: i32,
+    pub post_id: i32,
+    pub comment_id: Option<i32>,
+    pub reporter_id: Option<i32>,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    // "dismissed" or "upheld" once resolved
+    pub outcome: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub message: String,
+}
+
+// The number of items a list endpoint would return, see `GET /posts/count`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Count {
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateUser {
+    pub username: String,
+    pub email: String,
+}
+
+// Whether a username and an email are still free, returned by
+// `GET /users/check` for the ones asked about.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Availability {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: i32,
+    pub uuid: Uuid,
+    pub username: String,
+    pub email: String,
+    // their own avatar, or Gravatar's for the email when the server has it on
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    // the posts out there, neither deleted nor held back by moderation
+    #[serde(default)]
+    pub posts_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// A user as everyone else sees them, without their email: returned by
+// `GET /users/:id` to anyone but the user and admins, and inlined as the
+// author of posts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicUser {
+    pub id: i32,
+    pub uuid: Uuid,
+    pub username: String,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    #[serde(default)]
+    pub posts_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<User> for PublicUser {
+    fn from(user: User) -> Self {
+        PublicUser {
+            id: user.id,
+            uuid: user.uuid,
+            username: user.username,
+            avatar_url: user.avatar_url,
+            posts_count: user.posts_count,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}